@@ -85,9 +85,25 @@ fn compile_and_enforce_bindings_export(target_os: &str, lang_wrapper: &str) -> R
     Ok(())
 }
 
+// Generate JSON schemas of the configs, embedded by `telio_get_config_schema`
+fn write_config_schemas() -> Result<()> {
+    let out_dir = env::var("OUT_DIR")?;
+    std::fs::write(
+        Path::new(&out_dir).join("features_schema.json"),
+        telio_model::schema::features(),
+    )?;
+    std::fs::write(
+        Path::new(&out_dir).join("meshnet_schema.json"),
+        telio_model::schema::meshnet(),
+    )?;
+    Ok(())
+}
+
 fn main() -> Result<()> {
     let target_os = env::var("CARGO_CFG_TARGET_OS")?;
 
+    write_config_schemas()?;
+
     let langs: HashSet<&str> = HashSet::from_iter(["GO", "JAVA", "CS"].iter().copied());
     let ffis = env::var("FFI").unwrap_or_default();
 
@@ -361,6 +361,7 @@ impl Cli {
                 }
             },
             None,
+            None,
         )?;
 
         let nord = if let Some(token) = token {
@@ -2,13 +2,18 @@
 
 use std::{array::TryFromSliceError, convert::TryInto};
 
+use chacha20poly1305::{aead::KeyInit, XChaCha20Poly1305};
 use crypto_box::{aead::Aead, aead::AeadCore, ChaChaBox};
+use curve25519_dalek::montgomery::MontgomeryPoint;
 use rand::{CryptoRng, RngCore};
+use sha2::{Digest, Sha256};
 use telio_utils::telio_err_with_log;
 
 use crate::{PublicKey, SecretKey, KEY_SIZE};
 
 const NONCE_SIZE: usize = 24;
+/// Binds keys of `encrypt_with_context` to the direction of the payload
+const CONTEXT_DIRECTION: &[u8] = b"sender-to-recipient";
 
 /// Error returned when encrypting or decrypting.
 #[derive(Debug, thiserror::Error)]
@@ -175,6 +180,90 @@ pub fn decrypt_response(
     Ok(msg)
 }
 
+/// Encrypt `msg` for `remote_pk` with a key bound to `context`.
+///
+/// X25519 shared secret of `local_sk` and `remote_pk` is hashed together with `context` and the
+/// public keys of the sender and the recipient, in that order, into a XChaCha20-Poly1305 key. So
+/// payloads of different contexts, those of `encrypt_response`, as well as payloads reflected
+/// back to their sender cannot be passed off as each other. Resulting payload contains in order:
+/// random nonce and encrypted message.
+pub fn encrypt_with_context(
+    msg: &[u8],
+    rng: &mut (impl RngCore + CryptoRng),
+    local_sk: &SecretKey,
+    remote_pk: &PublicKey,
+    context: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let cipher = context_cipher(local_sk, remote_pk, context, Direction::Send)?;
+    let mut nonce = [0u8; NONCE_SIZE];
+    rng.fill_bytes(&mut nonce);
+    let encrypted_msg = match cipher.encrypt(&nonce.into(), msg) {
+        Ok(encrypted_msg) => encrypted_msg,
+        Err(e) => telio_err_with_log!(e)?,
+    };
+    let mut complete_payload = Vec::with_capacity(nonce.len() + encrypted_msg.len());
+    complete_payload.extend_from_slice(&nonce);
+    complete_payload.extend(encrypted_msg);
+    Ok(complete_payload)
+}
+
+/// Decrypt message stored in `msg`.
+///
+/// This function should be used with payload created using `encrypt_with_context` with the
+/// same `context`.
+pub fn decrypt_with_context(
+    msg: &[u8],
+    local_sk: &SecretKey,
+    remote_pk: &PublicKey,
+    context: &[u8],
+) -> Result<Vec<u8>, Error> {
+    let nonce: [u8; NONCE_SIZE] = msg
+        .get(..NONCE_SIZE)
+        .ok_or(Error::InvalidLength)?
+        .try_into()?;
+    let message = msg.get(NONCE_SIZE..).ok_or(Error::InvalidLength)?;
+    let cipher = context_cipher(local_sk, remote_pk, context, Direction::Receive)?;
+    let msg = match cipher.decrypt(&nonce.into(), message) {
+        Ok(msg) => msg,
+        Err(e) => telio_err_with_log!(e)?,
+    };
+
+    Ok(msg)
+}
+
+/// Whether the local key sends or receives the payload
+enum Direction {
+    Send,
+    Receive,
+}
+
+fn context_cipher(
+    local_sk: &SecretKey,
+    remote_pk: &PublicKey,
+    context: &[u8],
+    direction: Direction,
+) -> Result<XChaCha20Poly1305, Error> {
+    let shared = MontgomeryPoint(remote_pk.0).mul_clamped(*local_sk.as_bytes());
+    // Low order public keys yield a secret known to everyone
+    if shared.0 == [0u8; KEY_SIZE] {
+        return Err(crypto_box::aead::Error.into());
+    }
+    let local_pk = local_sk.public();
+    let (sender, recipient) = match direction {
+        Direction::Send => (local_pk, *remote_pk),
+        Direction::Receive => (*remote_pk, local_pk),
+    };
+    let key: [u8; KEY_SIZE] = Sha256::new()
+        .chain_update(context)
+        .chain_update(shared.0)
+        .chain_update(CONTEXT_DIRECTION)
+        .chain_update(sender.0)
+        .chain_update(recipient.0)
+        .finalize()
+        .into();
+    Ok(XChaCha20Poly1305::new(&key.into()))
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -302,4 +391,31 @@ mod tests {
         assert_eq!(MSG, decrypted_response);
         Ok(())
     }
+
+    #[test]
+    fn context_roundtrip() -> Result<(), Error> {
+        let mut rng = rand::thread_rng();
+        let local_sk = SecretKey::gen();
+        let remote_sk = SecretKey::gen();
+        let encrypted =
+            encrypt_with_context(MSG, &mut rng, &local_sk, &remote_sk.public(), b"first")?;
+        assert!(!encrypted.contains_str(MSG));
+
+        let decrypted = decrypt_with_context(&encrypted, &remote_sk, &local_sk.public(), b"first")?;
+        assert_eq!(MSG, decrypted);
+
+        // Payload of one context is rejected by other contexts and by `decrypt_response`
+        assert!(
+            decrypt_with_context(&encrypted, &remote_sk, &local_sk.public(), b"second").is_err()
+        );
+        assert!(decrypt_response(&encrypted, &remote_sk, &local_sk.public()).is_err());
+        let response = encrypt_response(MSG, &mut rng, &local_sk, &remote_sk.public())?;
+        assert!(decrypt_with_context(&response, &remote_sk, &local_sk.public(), b"first").is_err());
+
+        // Payload reflected back to its sender is not accepted as coming from the recipient
+        assert!(
+            decrypt_with_context(&encrypted, &local_sk, &remote_sk.public(), b"first").is_err()
+        );
+        Ok(())
+    }
 }
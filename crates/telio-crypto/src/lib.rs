@@ -21,6 +21,8 @@
 //! ```
 
 pub mod encryption;
+pub mod signature;
+pub mod token;
 
 use std::{convert::TryInto, fmt};
 
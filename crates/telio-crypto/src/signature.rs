@@ -0,0 +1,152 @@
+//! This module contains signing of data with WireGuard keys.
+//!
+//! WireGuard keys are X25519 keys, which cannot be used with Ed25519 directly. Signatures are
+//! created with [XEdDSA][xeddsa], which derives the Ed25519 key pair from the X25519 private key,
+//! so that anyone knowing the WireGuard public key of the signer can verify them.
+//!
+//! [xeddsa]: https://signal.org/docs/specifications/xeddsa/
+
+use std::convert::TryInto;
+
+use curve25519_dalek::{
+    edwards::{CompressedEdwardsY, EdwardsPoint},
+    montgomery::MontgomeryPoint,
+    scalar::Scalar,
+};
+use rand::{CryptoRng, RngCore};
+use sha2::{Digest, Sha512};
+
+use crate::{PublicKey, SecretKey};
+
+/// Size of the signature in bytes
+pub const SIGNATURE_SIZE: usize = 64;
+
+/// Sign `data` with the secret key, `rng` provides the randomness required by XEdDSA.
+pub fn sign(
+    secret_key: &SecretKey,
+    data: &[u8],
+    rng: &mut (impl RngCore + CryptoRng),
+) -> [u8; SIGNATURE_SIZE] {
+    let (public_key, private_scalar) = edwards_key_pair(secret_key);
+
+    let mut random = [0u8; 64];
+    rng.fill_bytes(&mut random);
+    // hash_1 of the specification, prefixed with 2^256 - 2 in little endian
+    let mut prefix = [0xFFu8; 32];
+    if let Some(first) = prefix.first_mut() {
+        *first = 0xFE;
+    }
+    let nonce = Scalar::from_hash(
+        Sha512::new()
+            .chain_update(prefix)
+            .chain_update(private_scalar.as_bytes())
+            .chain_update(data)
+            .chain_update(random),
+    );
+
+    let nonce_point = EdwardsPoint::mul_base(&nonce).compress();
+    let challenge = challenge(&nonce_point, &public_key, data);
+    let s = nonce + challenge * private_scalar;
+
+    let mut signature = [0u8; SIGNATURE_SIZE];
+    let (signature_nonce_point, signature_s) = signature.split_at_mut(32);
+    signature_nonce_point.copy_from_slice(nonce_point.as_bytes());
+    signature_s.copy_from_slice(s.as_bytes());
+    signature
+}
+
+/// Check that `signature` of `data` was created with the secret key of `public_key`.
+pub fn verify(public_key: &PublicKey, data: &[u8], signature: &[u8]) -> bool {
+    if signature.len() != SIGNATURE_SIZE {
+        return false;
+    }
+    let (nonce_point, s) = signature.split_at(32);
+    let (nonce_point, s): ([u8; 32], [u8; 32]) = match (nonce_point.try_into(), s.try_into()) {
+        (Ok(nonce_point), Ok(s)) => (nonce_point, s),
+        _ => return false,
+    };
+    let s = match Option::<Scalar>::from(Scalar::from_canonical_bytes(s)) {
+        Some(s) => s,
+        None => return false,
+    };
+    // Sign bit of the Edwards public key is always 0, see `edwards_key_pair`
+    let edwards_public_key = match MontgomeryPoint(public_key.0).to_edwards(0) {
+        Some(point) => point,
+        None => return false,
+    };
+
+    let nonce_point = CompressedEdwardsY(nonce_point);
+    let challenge = challenge(&nonce_point, &edwards_public_key.compress(), data);
+    let expected =
+        EdwardsPoint::vartime_double_scalar_mul_basepoint(&challenge, &-edwards_public_key, &s);
+    expected.compress() == nonce_point
+}
+
+/// Edwards public key and private scalar for the X25519 secret key, the private scalar is
+/// negated if needed, so that the sign bit of the public key is 0
+fn edwards_key_pair(secret_key: &SecretKey) -> (CompressedEdwardsY, Scalar) {
+    let scalar = Scalar::from_bytes_mod_order(*secret_key.as_bytes());
+    let public_key = EdwardsPoint::mul_base(&scalar).compress();
+    if public_key
+        .as_bytes()
+        .last()
+        .map_or(true, |last| last & 0x80 == 0)
+    {
+        (public_key, scalar)
+    } else {
+        (EdwardsPoint::mul_base(&-scalar).compress(), -scalar)
+    }
+}
+
+fn challenge(
+    nonce_point: &CompressedEdwardsY,
+    public_key: &CompressedEdwardsY,
+    data: &[u8],
+) -> Scalar {
+    Scalar::from_hash(
+        Sha512::new()
+            .chain_update(nonce_point.as_bytes())
+            .chain_update(public_key.as_bytes())
+            .chain_update(data),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_is_verified_with_wireguard_public_key() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..16 {
+            let secret_key = SecretKey::gen();
+            let signature = sign(&secret_key, b"attestation", &mut rng);
+            assert!(verify(&secret_key.public(), b"attestation", &signature));
+        }
+    }
+
+    #[test]
+    fn tampered_signature_is_rejected() {
+        let mut rng = rand::thread_rng();
+        let secret_key = SecretKey::gen();
+        let signature = sign(&secret_key, b"attestation", &mut rng);
+
+        assert!(!verify(&secret_key.public(), b"attestatioN", &signature));
+        assert!(!verify(
+            &SecretKey::gen().public(),
+            b"attestation",
+            &signature
+        ));
+        assert!(!verify(
+            &secret_key.public(),
+            b"attestation",
+            signature.split_at(63).0
+        ));
+
+        let mut tampered = signature;
+        if let Some(byte) = tampered.get_mut(40) {
+            *byte ^= 1;
+        }
+        assert!(!verify(&secret_key.public(), b"attestation", &tampered));
+    }
+}
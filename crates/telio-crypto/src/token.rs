@@ -0,0 +1,224 @@
+//! This module contains short lived tokens proving the ownership of a WireGuard key.
+//!
+//! Token has the JWT compact form `header.claims.signature`, every part is base64url encoded
+//! without padding. Signature is created over `header.claims`, prefixed with a context of tokens,
+//! with [`crate::signature`], so the token is verified with the WireGuard public key stored in
+//! the claims and signatures created for other purposes cannot be passed off as tokens.
+
+use rand::{CryptoRng, RngCore};
+use serde::{Deserialize, Serialize};
+
+use crate::{signature, PublicKey, SecretKey};
+
+/// Header of every token, tokens with other headers are rejected
+const HEADER: &str = r#"{"alg":"XEdDSA","typ":"JWT"}"#;
+/// Prefix of the signed part of every token
+const TOKEN_CONTEXT: &[u8] = b"telio-meshnet-token";
+/// Longest validity of the token, in seconds
+pub const MAX_EXPIRY_SECS: u32 = 24 * 60 * 60;
+/// Difference of the clocks of the creator and the verifier tolerated for the token creation time
+const MAX_CLOCK_SKEW_SECS: u64 = 60;
+
+/// Error returned when verifying a token.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Token does not consist of three parts or has an unexpected header
+    #[error("Malformed token")]
+    Malformed,
+    /// Part of the token is not valid base64url
+    #[error(transparent)]
+    Base64(#[from] base64::DecodeError),
+    /// Claims are not valid JSON
+    #[error(transparent)]
+    Json(#[from] serde_json::Error),
+    /// Token belongs to another key
+    #[error("Token was issued for {0:?}")]
+    KeyMismatch(PublicKey),
+    /// Signature does not match the claims
+    #[error("Invalid token signature")]
+    InvalidSignature,
+    /// Token is past its expiry
+    #[error("Token expired at {0}")]
+    Expired(u64),
+    /// Token is created in the future
+    #[error("Token is issued at {0}, in the future")]
+    NotYetValid(u64),
+    /// Token is valid for longer than `MAX_EXPIRY_SECS`
+    #[error("Token is valid for {0} seconds")]
+    ExpiryTooLong(u64),
+}
+
+/// Claims carried by the token
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Claims {
+    /// WireGuard public key of the device
+    pub pk: PublicKey,
+    /// Unix timestamp of the token creation, in seconds
+    pub iat: u64,
+    /// Unix timestamp after which the token is no longer valid, in seconds
+    pub exp: u64,
+}
+
+/// Create token for the public key of `secret_key`, valid for `expiry_secs` from `now`, capped at
+/// `MAX_EXPIRY_SECS`
+pub fn create(
+    secret_key: &SecretKey,
+    now: u64,
+    expiry_secs: u32,
+    rng: &mut (impl RngCore + CryptoRng),
+) -> String {
+    let claims = Claims {
+        pk: secret_key.public(),
+        iat: now,
+        exp: now.saturating_add(expiry_secs.min(MAX_EXPIRY_SECS).into()),
+    };
+    // Serializing a struct of a key and integers cannot fail
+    let claims = serde_json::to_vec(&claims).unwrap_or_default();
+
+    let signed = format!("{}.{}", encode(HEADER.as_bytes()), encode(&claims));
+    let signature = signature::sign(secret_key, &signed_data(&signed), rng);
+    format!("{}.{}", signed, encode(&signature))
+}
+
+/// Verify that `token` was created by the owner of `expected`, no later than `now`, and is not
+/// expired at `now`
+pub fn verify(token: &str, expected: &PublicKey, now: u64) -> Result<Claims, Error> {
+    let (signed, signature) = token.rsplit_once('.').ok_or(Error::Malformed)?;
+    let (header, claims) = signed.split_once('.').ok_or(Error::Malformed)?;
+    if decode(header)? != HEADER.as_bytes() {
+        return Err(Error::Malformed);
+    }
+
+    let claims: Claims = serde_json::from_slice(&decode(claims)?)?;
+    if &claims.pk != expected {
+        return Err(Error::KeyMismatch(claims.pk));
+    }
+    if !signature::verify(expected, &signed_data(signed), &decode(signature)?) {
+        return Err(Error::InvalidSignature);
+    }
+    if claims.exp < now {
+        return Err(Error::Expired(claims.exp));
+    }
+    if claims.iat > now.saturating_add(MAX_CLOCK_SKEW_SECS) {
+        return Err(Error::NotYetValid(claims.iat));
+    }
+    let lifetime = claims.exp.saturating_sub(claims.iat);
+    if lifetime > MAX_EXPIRY_SECS.into() {
+        return Err(Error::ExpiryTooLong(lifetime));
+    }
+    Ok(claims)
+}
+
+fn signed_data(signed: &str) -> Vec<u8> {
+    [TOKEN_CONTEXT, signed.as_bytes()].concat()
+}
+
+fn encode(data: &[u8]) -> String {
+    base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
+
+fn decode(data: &str) -> Result<Vec<u8>, base64::DecodeError> {
+    base64::decode_config(data, base64::URL_SAFE_NO_PAD)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_is_verified_until_expiry() {
+        let secret_key = SecretKey::gen();
+        let token = create(&secret_key, 1000, 60, &mut rand::thread_rng());
+
+        let claims = verify(&token, &secret_key.public(), 1060).unwrap();
+        assert_eq!(
+            claims,
+            Claims {
+                pk: secret_key.public(),
+                iat: 1000,
+                exp: 1060,
+            }
+        );
+        assert!(matches!(
+            verify(&token, &secret_key.public(), 1061),
+            Err(Error::Expired(1060))
+        ));
+    }
+
+    #[test]
+    fn forged_token_is_rejected() {
+        let secret_key = SecretKey::gen();
+        let other_key = SecretKey::gen();
+        let token = create(&secret_key, 1000, 60, &mut rand::thread_rng());
+
+        assert!(matches!(
+            verify(&token, &other_key.public(), 1000),
+            Err(Error::KeyMismatch(_))
+        ));
+
+        // Claims of another key signed with our key
+        let other_token = create(&other_key, 1000, 60, &mut rand::thread_rng());
+        let mut parts: Vec<_> = token.split('.').collect();
+        parts[1] = other_token.split('.').nth(1).unwrap();
+        assert!(matches!(
+            verify(&parts.join("."), &other_key.public(), 1000),
+            Err(Error::InvalidSignature)
+        ));
+
+        assert!(matches!(
+            verify("not a token", &secret_key.public(), 1000),
+            Err(Error::Malformed)
+        ));
+    }
+
+    #[test]
+    fn token_times_are_checked() {
+        let secret_key = SecretKey::gen();
+        let mut rng = rand::thread_rng();
+
+        let token = create(&secret_key, 1000, u32::MAX, &mut rng);
+        let claims = verify(&token, &secret_key.public(), 1000).unwrap();
+        assert_eq!(claims.exp, 1000 + u64::from(MAX_EXPIRY_SECS));
+
+        assert!(matches!(
+            verify(&token, &secret_key.public(), 1000 - MAX_CLOCK_SKEW_SECS - 1),
+            Err(Error::NotYetValid(1000))
+        ));
+        assert!(verify(&token, &secret_key.public(), 1000 - MAX_CLOCK_SKEW_SECS).is_ok());
+
+        // Token signed with a longer validity than allowed
+        let claims = Claims {
+            pk: secret_key.public(),
+            iat: 1000,
+            exp: 1000 + u64::from(MAX_EXPIRY_SECS) + 1,
+        };
+        let signed = format!(
+            "{}.{}",
+            encode(HEADER.as_bytes()),
+            encode(&serde_json::to_vec(&claims).unwrap())
+        );
+        let signature = signature::sign(&secret_key, &signed_data(&signed), &mut rng);
+        let token = format!("{}.{}", signed, encode(&signature));
+        assert!(matches!(
+            verify(&token, &secret_key.public(), 1000),
+            Err(Error::ExpiryTooLong(_))
+        ));
+    }
+
+    #[test]
+    fn signature_of_raw_token_data_is_rejected() {
+        let secret_key = SecretKey::gen();
+        let token = create(&secret_key, 1000, 60, &mut rand::thread_rng());
+        let (signed, _) = token.rsplit_once('.').unwrap();
+
+        let signature = signature::sign(&secret_key, signed.as_bytes(), &mut rand::thread_rng());
+        assert!(matches!(
+            verify(
+                &format!("{}.{}", signed, encode(&signature)),
+                &secret_key.public(),
+                1000
+            ),
+            Err(Error::InvalidSignature)
+        ));
+    }
+}
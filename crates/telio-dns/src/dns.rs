@@ -1,4 +1,4 @@
-use crate::{bind_tun, LocalNameServer, NameServer, Records};
+use crate::{bind_tun, DnsQueryResponse, DohServer, LocalNameServer, NameServer, Records};
 use async_trait::async_trait;
 use boringtun::noise::Tunn;
 use ipnetwork::IpNetwork;
@@ -10,7 +10,10 @@ use tokio::net::UdpSocket;
 use tokio::sync::RwLock;
 use x25519_dalek::{PublicKey as PublicKeyDalek, StaticSecret};
 
-use telio_model::api_config::FeatureExitDns;
+use telio_model::{
+    api_config::FeatureExitDns,
+    config::{DnsFallbackConfig, DnsTtlOverride},
+};
 
 //debug tools
 use telio_utils::{telio_log_debug, telio_log_error};
@@ -27,6 +30,25 @@ pub trait DnsResolver {
     async fn upsert(&self, zone: &str, records: &Records) -> Result<(), String>;
     /// Configure list of forward DNS servers for zone '.'.
     async fn forward(&self, to: &[IpAddr]) -> Result<(), String>;
+    /// Ask the `fallback` servers for names outside of the meshnet when the forward servers
+    /// fail or do not answer in time, `None` disables the fallback.
+    async fn set_fallback(&self, fallback: Option<DnsFallbackConfig>) -> Result<(), String>;
+    /// Clamp TTLs of the cached and returned records to the range of `ttl_override`.
+    async fn set_ttl_override(&self, ttl_override: DnsTtlOverride) -> Result<(), String>;
+    /// Send queries for names outside of the meshnet to the `doh` server over HTTPS instead
+    /// of the forward servers, `None` reverts to the forward servers.
+    async fn set_dns_over_https(&self, doh: Option<DohServer>) -> Result<(), String>;
+    /// Resolve `name` with records of `record_type` as magic DNS would.
+    async fn query(&self, name: &str, record_type: &str) -> Result<DnsQueryResponse, String>;
+    /// Answer queries for `name` with `records`, ahead of meshnet records and forwarding.
+    async fn override_records(
+        &self,
+        name: &str,
+        records: &[IpAddr],
+        ttl: u32,
+    ) -> Result<(), String>;
+    /// Remove records injected with `override_records`.
+    async fn remove_override(&self, name: &str) -> Result<(), String>;
     /// Get public key of this DNS server.
     fn public_key(&self) -> PublicKey;
     /// Get Peer of this DNS server with selected allowed IPs.
@@ -136,6 +158,41 @@ impl DnsResolver for LocalDnsResolver {
         Ok(self.nameserver.forward(to).await?)
     }
 
+    async fn set_fallback(&self, fallback: Option<DnsFallbackConfig>) -> Result<(), String> {
+        telio_log_debug!("Dns - fallback {:?}", fallback);
+        self.nameserver.set_fallback(fallback).await
+    }
+
+    async fn set_ttl_override(&self, ttl_override: DnsTtlOverride) -> Result<(), String> {
+        telio_log_debug!("Dns - ttl override {:?}", ttl_override);
+        self.nameserver.set_ttl_override(ttl_override).await
+    }
+
+    async fn set_dns_over_https(&self, doh: Option<DohServer>) -> Result<(), String> {
+        telio_log_debug!("Dns - dns over https {:?}", doh);
+        self.nameserver.set_dns_over_https(doh).await
+    }
+
+    async fn query(&self, name: &str, record_type: &str) -> Result<DnsQueryResponse, String> {
+        telio_log_debug!("Dns - query {:?} {:?}", name, record_type);
+        self.nameserver.query(name, record_type).await
+    }
+
+    async fn override_records(
+        &self,
+        name: &str,
+        records: &[IpAddr],
+        ttl: u32,
+    ) -> Result<(), String> {
+        telio_log_debug!("Dns - override {:?} {:?} {:?}", name, records, ttl);
+        self.nameserver.override_records(name, records, ttl).await
+    }
+
+    async fn remove_override(&self, name: &str) -> Result<(), String> {
+        telio_log_debug!("Dns - remove override {:?}", name);
+        self.nameserver.remove_override(name).await
+    }
+
     fn public_key(&self) -> PublicKey {
         let static_secret = &StaticSecret::from(self.secret_key.into_bytes());
         telio_log_debug!(
@@ -0,0 +1,159 @@
+//! DNS over HTTPS servers asked by the forward zone instead of the plain upstream servers
+
+use std::net::IpAddr;
+
+use hickory_resolver::{
+    config::{NameServerConfigGroup, Protocol, ResolverConfig, ResolverOpts},
+    name_server::GenericConnector,
+};
+use telio_model::config::ProxySettings;
+use url::{Host, Url};
+
+use crate::forward::{TelioAsyncResolver, TelioRuntimeProvider};
+
+/// Path of the queries, the HTTPS client of the resolver does not support other paths
+const DNS_QUERY_PATH: &str = "/dns-query";
+
+/// DNS over HTTPS server, queries are sent to it in HTTPS POST requests
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DohServer {
+    /// Hostname or IP address of the server, the server certificate is validated against it
+    host: String,
+    port: u16,
+    /// Proxy tunneling the connections to the server
+    pub(crate) proxy: Option<ProxySettings>,
+}
+
+impl DohServer {
+    /// Parse the endpoint `url` of the server, e.g. `https://cloudflare-dns.com/dns-query`
+    pub fn from_url(url: &str) -> Result<Self, String> {
+        let url = Url::parse(url).map_err(|e| format!("Invalid url {}: {}", url, e))?;
+        if url.scheme() != "https" {
+            return Err(format!("Unsupported scheme {}", url.scheme()));
+        }
+        if !matches!(url.path(), "" | "/" | DNS_QUERY_PATH) || url.query().is_some() {
+            return Err(format!(
+                "Unsupported path {}, only {} is supported",
+                url.path(),
+                DNS_QUERY_PATH
+            ));
+        }
+        let host = match url.host() {
+            Some(Host::Domain(domain)) => domain.to_owned(),
+            Some(Host::Ipv4(ip)) => ip.to_string(),
+            Some(Host::Ipv6(ip)) => ip.to_string(),
+            None => return Err("Missing host".to_owned()),
+        };
+
+        Ok(Self {
+            host,
+            port: url.port_or_known_default().unwrap_or(443),
+            proxy: None,
+        })
+    }
+
+    /// Connect to the server through `proxy`
+    pub fn with_proxy(self, proxy: Option<ProxySettings>) -> Self {
+        Self { proxy, ..self }
+    }
+
+    /// Name servers of the resolver, hostname of the server is resolved by the forward servers
+    /// `bootstrap`
+    ///
+    /// The system resolver is not asked, while the VPN is up it is magic DNS itself and the query
+    /// would leave the host in plaintext outside of the proxy.
+    pub(crate) async fn name_servers(
+        &self,
+        bootstrap: &[IpAddr],
+    ) -> Result<NameServerConfigGroup, String> {
+        let ips: Vec<IpAddr> = match self.host.parse() {
+            Ok(ip) => vec![ip],
+            Err(_) => self.resolve(bootstrap).await?,
+        };
+        if ips.is_empty() {
+            return Err(format!("No addresses of {}", self.host));
+        }
+
+        Ok(NameServerConfigGroup::from_ips_https(
+            &ips,
+            self.port,
+            self.host.clone(),
+            true,
+        ))
+    }
+
+    /// Ask the forward servers for the addresses of the server, over TCP through the proxy if set
+    async fn resolve(&self, bootstrap: &[IpAddr]) -> Result<Vec<IpAddr>, String> {
+        if bootstrap.is_empty() {
+            return Err(format!("No forward servers to resolve {}", self.host));
+        }
+        let mut name_servers = NameServerConfigGroup::from_ips_clear(bootstrap, 53, true);
+        if self.proxy.is_some() {
+            // Proxies tunnel only TCP connections
+            name_servers.retain(|ns| ns.protocol == Protocol::Tcp);
+        }
+        let resolver = TelioAsyncResolver::new(
+            ResolverConfig::from_parts(None, vec![], name_servers),
+            ResolverOpts::default(),
+            GenericConnector::new(TelioRuntimeProvider::with_proxy(self.proxy.clone())),
+        );
+        let lookup = resolver
+            .lookup_ip(self.host.as_str())
+            .await
+            .map_err(|e| format!("Failed to resolve {}: {}", self.host, e))?;
+        Ok(lookup.iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::SocketAddr;
+
+    #[test]
+    fn doh_url_is_parsed() {
+        assert_eq!(
+            DohServer::from_url("https://cloudflare-dns.com/dns-query"),
+            Ok(DohServer {
+                host: "cloudflare-dns.com".to_owned(),
+                port: 443,
+                proxy: None,
+            })
+        );
+        assert_eq!(
+            DohServer::from_url("https://[2606:4700:4700::1111]:8443"),
+            Ok(DohServer {
+                host: "2606:4700:4700::1111".to_owned(),
+                port: 8443,
+                proxy: None,
+            })
+        );
+
+        assert!(DohServer::from_url("http://cloudflare-dns.com/dns-query").is_err());
+        assert!(DohServer::from_url("https://dns.google/resolve").is_err());
+        assert!(DohServer::from_url("https://dns.google/dns-query?dns=AAAB").is_err());
+        assert!(DohServer::from_url("cloudflare-dns.com").is_err());
+    }
+
+    #[tokio::test]
+    async fn ip_server_is_not_resolved() {
+        let server = DohServer::from_url("https://1.1.1.1/dns-query").unwrap();
+        let name_servers = server.name_servers(&[]).await.unwrap();
+
+        assert_eq!(name_servers.len(), 1);
+        assert_eq!(
+            name_servers.first().map(|ns| ns.socket_addr),
+            Some(SocketAddr::from(([1, 1, 1, 1], 443)))
+        );
+        assert_eq!(
+            name_servers.first().and_then(|ns| ns.tls_dns_name.clone()),
+            Some("1.1.1.1".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn hostname_needs_forward_servers() {
+        let server = DohServer::from_url("https://cloudflare-dns.com/dns-query").unwrap();
+        assert!(server.name_servers(&[]).await.is_err());
+    }
+}
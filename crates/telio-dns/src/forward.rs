@@ -6,9 +6,11 @@ use std::{
     io,
     net::{Ipv4Addr, Ipv6Addr, SocketAddr},
     pin::Pin,
+    time::Duration,
 };
 
 use async_trait::async_trait;
+use hickory_proto::iocompat::AsyncIoTokioAsStd;
 use hickory_proto::rr::{LowerName, Name, RecordType};
 use hickory_proto::udp::DnsUdpSocket;
 use hickory_proto::{op::ResponseCode, rr::Record};
@@ -19,19 +21,36 @@ use hickory_server::{
     },
     proto::udp::UdpSocket as ProtoUdpSocket,
     resolver::{
-        config::ResolverConfig, error::ResolveErrorKind, lookup::Lookup as ResolverLookup,
+        config::{NameServerConfigGroup, ResolverConfig, ResolverOpts},
+        error::{ResolveError, ResolveErrorKind},
+        lookup::Lookup as ResolverLookup,
         AsyncResolver,
     },
     server::RequestInfo,
     store::forwarder::ForwardConfig,
 };
+use telio_model::config::ProxySettings;
+use telio_sockets::proxy;
 use telio_utils::{telio_log_debug, telio_log_info, telio_log_trace, telio_log_warn};
-use tokio::net::UdpSocket;
+use tokio::net::{TcpStream, UdpSocket};
 
 use crate::bind_tun;
 
 #[derive(Default, Clone)]
-pub struct TelioRuntimeProvider(TokioRuntimeProvider);
+pub struct TelioRuntimeProvider {
+    tokio: TokioRuntimeProvider,
+    /// Proxy tunneling the TCP connections, used by DNS over HTTPS
+    proxy: Option<ProxySettings>,
+}
+
+impl TelioRuntimeProvider {
+    pub fn with_proxy(proxy: Option<ProxySettings>) -> Self {
+        Self {
+            tokio: TokioRuntimeProvider::default(),
+            proxy,
+        }
+    }
+}
 
 impl RuntimeProvider for TelioRuntimeProvider {
     type Handle = <TokioRuntimeProvider as RuntimeProvider>::Handle;
@@ -40,14 +59,22 @@ impl RuntimeProvider for TelioRuntimeProvider {
     type Tcp = <TokioRuntimeProvider as RuntimeProvider>::Tcp;
 
     fn create_handle(&self) -> Self::Handle {
-        self.0.create_handle()
+        self.tokio.create_handle()
     }
 
     fn connect_tcp(
         &self,
         server_addr: SocketAddr,
     ) -> Pin<Box<dyn Send + Future<Output = io::Result<Self::Tcp>>>> {
-        self.0.connect_tcp(server_addr)
+        let proxy = match self.proxy.clone() {
+            Some(proxy) => proxy,
+            None => return self.tokio.connect_tcp(server_addr),
+        };
+        Box::pin(async move {
+            let mut stream = TcpStream::connect(proxy::resolve(&proxy).await?).await?;
+            proxy::connect(&mut stream, &proxy, server_addr).await?;
+            Ok(AsyncIoTokioAsStd(stream))
+        })
     }
 
     fn bind_udp(
@@ -130,6 +157,13 @@ impl ProtoUdpSocket for TelioUdpSocket {
 pub struct ForwardAuthority {
     origin: LowerName,
     resolver: TelioAsyncResolver,
+    fallback: Option<Fallback>,
+}
+
+/// Resolver asked when the forward servers fail or do not answer in time
+struct Fallback {
+    resolver: TelioAsyncResolver,
+    timeout: Duration,
 }
 
 impl ForwardAuthority {
@@ -138,6 +172,7 @@ impl ForwardAuthority {
         origin: Name,
         _zone_type: ZoneType,
         config: ForwardConfig,
+        runtime_provider: TelioRuntimeProvider,
     ) -> Result<Self, String> {
         telio_log_info!("loading forwarder config: {}", origin);
 
@@ -165,7 +200,8 @@ impl ForwardAuthority {
 
         let config = ResolverConfig::from_parts(None, vec![], name_servers);
 
-        let resolver = TelioAsyncResolver::new(config, options, GenericConnector::default());
+        let resolver =
+            TelioAsyncResolver::new(config, options, GenericConnector::new(runtime_provider));
 
         telio_log_info!("forward resolver configured: {}: ", origin);
 
@@ -173,8 +209,65 @@ impl ForwardAuthority {
         Ok(Self {
             origin: origin.into(),
             resolver,
+            fallback: None,
         })
     }
+
+    /// Ask `name_servers` whenever the forward servers fail or do not answer within `timeout`
+    pub fn set_fallback(
+        &mut self,
+        name_servers: NameServerConfigGroup,
+        options: ResolverOpts,
+        timeout: Duration,
+    ) {
+        let config = ResolverConfig::from_parts(None, vec![], name_servers);
+        self.fallback = Some(Fallback {
+            resolver: TelioAsyncResolver::new(config, options, GenericConnector::default()),
+            timeout,
+        });
+    }
+}
+
+/// Result of `primary`, or of the fallback if `primary` fails or does not finish within the
+/// fallback timeout. Negative answers of the forward servers (e.g. NXDOMAIN) are final.
+async fn lookup_with_fallback<T, P, F, Fut>(
+    primary: P,
+    fallback: Option<(Duration, F)>,
+) -> Result<T, ResolveError>
+where
+    P: Future<Output = Result<T, ResolveError>>,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<T, ResolveError>>,
+{
+    let (timeout, fallback) = match fallback {
+        Some(fallback) => fallback,
+        None => return primary.await,
+    };
+
+    match tokio::time::timeout(timeout, primary).await {
+        Ok(Err(err)) if !is_final(&err) => {
+            telio_log_debug!("forward servers failed: {}, asking fallback servers", err);
+            fallback().await
+        }
+        Ok(result) => result,
+        Err(_) => {
+            telio_log_debug!(
+                "forward servers did not answer in {:?}, asking fallback servers",
+                timeout
+            );
+            fallback().await
+        }
+    }
+}
+
+/// Whether the error is an actual answer of the server, which should not be retried elsewhere
+fn is_final(err: &ResolveError) -> bool {
+    match err.kind() {
+        ResolveErrorKind::NoRecordsFound { response_code, .. } => {
+            *response_code != ResponseCode::ServFail
+        }
+        _ => false,
+    }
 }
 
 #[async_trait::async_trait]
@@ -216,7 +309,13 @@ impl Authority for ForwardAuthority {
 
         telio_log_debug!("forwarding lookup: {} {}", name, rtype);
         let name: LowerName = name.clone();
-        let resolve = self.resolver.lookup(name, rtype).await;
+        let fallback = self.fallback.as_ref().map(|fallback| {
+            let name = name.clone();
+            (fallback.timeout, move || {
+                fallback.resolver.lookup(name, rtype)
+            })
+        });
+        let resolve = lookup_with_fallback(self.resolver.lookup(name, rtype), fallback).await;
 
         resolve
             .map(ForwardLookup)
@@ -272,3 +371,47 @@ impl LookupObject for ForwardLookup {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_proto::op::Query;
+    use std::future::{self, Ready};
+
+    type Answer = Result<u32, ResolveError>;
+
+    fn fallback(answer: u32) -> Option<(Duration, impl FnOnce() -> Ready<Answer>)> {
+        Some((Duration::from_millis(50), move || future::ready(Ok(answer))))
+    }
+
+    #[tokio::test]
+    async fn forward_answer_is_preferred() {
+        let primary = future::ready(Ok(1));
+        assert_eq!(lookup_with_fallback(primary, fallback(2)).await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn fallback_is_asked_when_forward_servers_do_not_answer() {
+        let primary = future::pending::<Answer>();
+        assert_eq!(lookup_with_fallback(primary, fallback(2)).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn fallback_is_asked_when_forward_servers_fail() {
+        let primary = future::ready(Err(ResolveErrorKind::NoConnections.into()));
+        assert_eq!(lookup_with_fallback(primary, fallback(2)).await.unwrap(), 2);
+    }
+
+    #[tokio::test]
+    async fn negative_answer_is_final() {
+        let primary = future::ready(Err(ResolveErrorKind::NoRecordsFound {
+            query: Box::new(Query::new()),
+            soa: None,
+            negative_ttl: None,
+            response_code: ResponseCode::NXDomain,
+            trusted: true,
+        }
+        .into()));
+        assert!(lookup_with_fallback(primary, fallback(2)).await.is_err());
+    }
+}
@@ -4,6 +4,7 @@
 //! Easily create and run in process dns resolver.
 
 mod dns;
+mod doh;
 mod nameserver;
 mod resolver;
 mod zone;
@@ -13,7 +14,8 @@ pub mod bind_tun;
 pub(crate) mod forward;
 
 pub use crate::dns::{DnsResolver, LocalDnsResolver};
-pub use nameserver::{LocalNameServer, NameServer};
+pub use doh::DohServer;
+pub use nameserver::{DnsQueryResponse, LocalNameServer, NameServer};
 pub use resolver::Resolver;
 pub use zone::Records;
 
@@ -1,11 +1,13 @@
 use crate::{
+    doh::DohServer,
     resolver::Resolver,
     zone::{AuthoritativeZone, ClonableZones, ForwardZone, Records},
 };
 use async_trait::async_trait;
 use boringtun::noise::{Tunn, TunnResult};
-use hickory_proto::rr::LowerName;
-use hickory_proto::serialize::binary::BinDecodable;
+use hickory_proto::op::{Message, Query, ResponseCode};
+use hickory_proto::rr::{LowerName, Name, Record, RecordType};
+use hickory_proto::serialize::binary::{BinDecodable, BinEncodable};
 use hickory_server::authority::MessageRequest;
 use hickory_server::server::{Protocol, Request};
 use pnet_packet::{
@@ -15,11 +17,14 @@ use pnet_packet::{
     udp::{ipv4_checksum, ipv6_checksum, MutableUdpPacket, UdpPacket},
     Packet,
 };
+use serde::Serialize;
 use std::{
+    collections::HashSet,
     net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
     str::FromStr,
     sync::Arc,
 };
+use telio_model::config::{DnsFallbackConfig, DnsTtlOverride};
 use tokio::net::UdpSocket;
 use tokio::sync::{RwLock, RwLockMappedWriteGuard, RwLockWriteGuard, Semaphore};
 use tokio::task::JoinHandle;
@@ -44,14 +49,72 @@ pub trait NameServer {
     async fn stop(&self);
     /// Configure list of forward DNS servers for zone '.'.
     async fn forward(&self, to: &[IpAddr]) -> Result<(), String>;
+    /// Ask the `fallback` servers when the forward servers fail or do not answer in time,
+    /// `None` disables the fallback. Names of the served zones are never sent to them.
+    async fn set_fallback(&self, fallback: Option<DnsFallbackConfig>) -> Result<(), String>;
+    /// Clamp TTLs of the cached and returned records to the range of `ttl_override`.
+    async fn set_ttl_override(&self, ttl_override: DnsTtlOverride) -> Result<(), String>;
+    /// Forward queries of zone '.' to the `doh` server instead of the forward servers,
+    /// `None` reverts to the forward servers.
+    async fn set_dns_over_https(&self, doh: Option<DohServer>) -> Result<(), String>;
     /// Insert or update zone records used by the server.
     async fn upsert(&self, zone: &str, records: &Records) -> Result<(), String>;
+    /// Resolve `name` using the served zones, forwarding it upstream if needed.
+    async fn query(&self, name: &str, record_type: &str) -> Result<DnsQueryResponse, String>;
+    /// Answer queries for `name` with `records` ahead of the other zones.
+    async fn override_records(
+        &self,
+        name: &str,
+        records: &[IpAddr],
+        ttl: u32,
+    ) -> Result<(), String>;
+    /// Remove records injected with `override_records`.
+    async fn remove_override(&self, name: &str) -> Result<(), String>;
+}
+
+/// Answer to a DNS query made directly through the name server.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DnsQueryResponse {
+    /// Queried domain name.
+    pub name: String,
+    /// Queried record type, e.g. `A` or `AAAA`.
+    #[serde(rename = "type")]
+    pub record_type: String,
+    /// Lowest TTL of the returned records, in seconds.
+    pub ttl: u32,
+    /// Data of the returned records.
+    pub records: Vec<String>,
+}
+
+impl DnsQueryResponse {
+    fn from_message(name: &Name, record_type: RecordType, message: &Message) -> Self {
+        let answers: Vec<_> = message
+            .answers()
+            .iter()
+            .filter(|answer| answer.record_type() == record_type)
+            .collect();
+
+        Self {
+            name: name.to_string(),
+            record_type: record_type.to_string(),
+            ttl: answers.iter().map(|answer| answer.ttl()).min().unwrap_or(0),
+            records: answers
+                .iter()
+                .filter_map(|answer| answer.data().map(|data| data.to_string()))
+                .collect(),
+        }
+    }
 }
 
 /// Local name server.
 #[derive(Default)]
 pub struct LocalNameServer {
     zones: Arc<ClonableZones>,
+    overrides: HashSet<LowerName>,
+    forward_ips: Vec<IpAddr>,
+    doh: Option<DohServer>,
+    fallback: Option<DnsFallbackConfig>,
+    ttl_override: DnsTtlOverride,
     task_handle: Option<JoinHandle<()>>,
 }
 
@@ -61,6 +124,11 @@ impl LocalNameServer {
     pub async fn new(forward_ips: &[IpAddr]) -> Result<Arc<RwLock<Self>>, String> {
         let ns = Arc::new(RwLock::new(LocalNameServer {
             zones: Arc::new(ClonableZones::new()),
+            overrides: HashSet::new(),
+            forward_ips: Vec::new(),
+            doh: None,
+            fallback: None,
+            ttl_override: DnsTtlOverride::default(),
             task_handle: None,
         }));
         ns.forward(forward_ips).await?;
@@ -189,6 +257,7 @@ impl LocalNameServer {
     ) -> Result<Vec<u8>, String> {
         let resolver = Resolver::new();
         let zones = nameserver.zones().await;
+        let ttl_override = nameserver.read().await.ttl_override;
 
         let dns_request = request_info
             .udp
@@ -205,7 +274,12 @@ impl LocalNameServer {
 
         let dns_response = resolver.0.lock().await;
         telio_log_debug!("Nameserver response: {:?}", &dns_response);
-        Ok(dns_response.to_vec())
+        if !ttl_override.is_set() {
+            return Ok(dns_response.to_vec());
+        }
+        let mut dns_response = Message::from_bytes(&dns_response)?;
+        clamp_ttls(&mut dns_response, &ttl_override);
+        Ok(dns_response.to_vec()?)
     }
 
     async fn process_packet(
@@ -515,14 +589,162 @@ impl NameServer for Arc<RwLock<LocalNameServer>> {
         Ok(())
     }
 
+    async fn override_records(
+        &self,
+        name: &str,
+        records: &[IpAddr],
+        ttl: u32,
+    ) -> Result<(), String> {
+        // Catalog answers from the longest matching zone, so a zone holding just the overridden
+        // name takes precedence over meshnet records and forwarding
+        let mut zone_records = Records::new();
+        zone_records.insert(name.to_owned(), records.to_vec());
+        let azone = Arc::new(AuthoritativeZone::with_ttl(name, &zone_records, ttl).await?);
+        let name = LowerName::from_str(name)?;
+
+        let mut this = self.write().await;
+        Arc::make_mut(&mut this.zones).upsert(name.clone(), Box::new(azone));
+        this.overrides.insert(name);
+        Ok(())
+    }
+
+    async fn remove_override(&self, name: &str) -> Result<(), String> {
+        let name = LowerName::from_str(name)?;
+
+        let mut this = self.write().await;
+        if !this.overrides.remove(&name) {
+            return Err(format!("No override for {}", name));
+        }
+        Arc::make_mut(&mut this.zones).remove(&name);
+        Ok(())
+    }
+
     async fn forward(&self, to: &[IpAddr]) -> Result<(), String> {
-        self.zones_mut().await.upsert(
-            LowerName::from_str(".")?,
-            Box::new(Arc::new(ForwardZone::new(".", to).await?)),
-        );
+        let (doh, fallback, ttl_override) = {
+            let this = self.read().await;
+            (this.doh.clone(), this.fallback.clone(), this.ttl_override)
+        };
+        // Build the zone before taking the lock, so queries are not blocked meanwhile
+        let zone = ForwardZone::new(".", to, doh.as_ref(), fallback.as_ref(), ttl_override).await?;
+
+        let mut this = self.write().await;
+        Arc::make_mut(&mut this.zones).upsert(LowerName::from_str(".")?, Box::new(Arc::new(zone)));
+        this.forward_ips = to.to_vec();
+        Ok(())
+    }
+
+    async fn set_fallback(&self, fallback: Option<DnsFallbackConfig>) -> Result<(), String> {
+        // Only zone '.' forwards queries, meshnet zones are authoritative and never fall back
+        let (forward_ips, doh, ttl_override) = {
+            let this = self.read().await;
+            (
+                this.forward_ips.clone(),
+                this.doh.clone(),
+                this.ttl_override,
+            )
+        };
+        let zone = ForwardZone::new(
+            ".",
+            &forward_ips,
+            doh.as_ref(),
+            fallback.as_ref(),
+            ttl_override,
+        )
+        .await?;
+
+        let mut this = self.write().await;
+        Arc::make_mut(&mut this.zones).upsert(LowerName::from_str(".")?, Box::new(Arc::new(zone)));
+        this.fallback = fallback;
+        Ok(())
+    }
+
+    async fn set_ttl_override(&self, ttl_override: DnsTtlOverride) -> Result<(), String> {
+        // Forward zone is rebuilt, so the resolver cache honors the new range
+        let (forward_ips, doh, fallback) = {
+            let this = self.read().await;
+            (
+                this.forward_ips.clone(),
+                this.doh.clone(),
+                this.fallback.clone(),
+            )
+        };
+        let zone = ForwardZone::new(
+            ".",
+            &forward_ips,
+            doh.as_ref(),
+            fallback.as_ref(),
+            ttl_override,
+        )
+        .await?;
+
+        let mut this = self.write().await;
+        Arc::make_mut(&mut this.zones).upsert(LowerName::from_str(".")?, Box::new(Arc::new(zone)));
+        this.ttl_override = ttl_override;
+        Ok(())
+    }
+
+    async fn set_dns_over_https(&self, doh: Option<DohServer>) -> Result<(), String> {
+        // Forward servers are kept, so they are asked again once DNS over HTTPS is disabled
+        let (forward_ips, fallback, ttl_override) = {
+            let this = self.read().await;
+            (
+                this.forward_ips.clone(),
+                this.fallback.clone(),
+                this.ttl_override,
+            )
+        };
+        let zone = ForwardZone::new(
+            ".",
+            &forward_ips,
+            doh.as_ref(),
+            fallback.as_ref(),
+            ttl_override,
+        )
+        .await?;
+
+        let mut this = self.write().await;
+        Arc::make_mut(&mut this.zones).upsert(LowerName::from_str(".")?, Box::new(Arc::new(zone)));
+        this.doh = doh;
         Ok(())
     }
 
+    async fn query(&self, name: &str, record_type: &str) -> Result<DnsQueryResponse, String> {
+        let record_type = RecordType::from_str(&record_type.to_uppercase())
+            .map_err(|_| format!("Invalid record type {}", record_type))?;
+        let mut name = Name::from_str(name)?;
+        name.set_fqdn(true);
+
+        let mut question = Message::new();
+        question
+            .add_query(Query::query(name.clone(), record_type))
+            .set_recursion_desired(true);
+        let message_request = MessageRequest::from_bytes(&question.to_bytes()?)?;
+        let dns_request = Request::new(
+            message_request,
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 0),
+            Protocol::Udp,
+        );
+
+        let resolver = Resolver::new();
+        self.zones()
+            .await
+            .lookup(&dns_request, resolver.clone())
+            .await
+            .map_err(|e| format!("Lookup failed {}", e))?;
+
+        let mut dns_response = Message::from_bytes(&resolver.0.lock().await)?;
+        telio_log_debug!("Nameserver response: {:?}", &dns_response);
+        clamp_ttls(&mut dns_response, &self.read().await.ttl_override);
+        match dns_response.response_code() {
+            ResponseCode::NoError => Ok(DnsQueryResponse::from_message(
+                &name,
+                record_type,
+                &dns_response,
+            )),
+            code => Err(format!("Query failed with {}", code)),
+        }
+    }
+
     // TODO: maybe report or recover in case of thread panic
     async fn stop(&self) {
         if let Some(handle) = &self.read().await.task_handle {
@@ -543,6 +765,23 @@ impl NameServer for Arc<RwLock<LocalNameServer>> {
     }
 }
 
+/// Clamp TTLs of the answers and additional records of `message` to the override range
+fn clamp_ttls(message: &mut Message, ttl_override: &DnsTtlOverride) {
+    if !ttl_override.is_set() {
+        return;
+    }
+    let clamp = |mut records: Vec<Record>| {
+        for record in records.iter_mut() {
+            record.set_ttl(ttl_override.clamp(record.ttl()));
+        }
+        records
+    };
+    let answers = clamp(message.take_answers());
+    message.insert_answers(answers);
+    let additionals = clamp(message.take_additionals());
+    message.insert_additionals(additionals);
+}
+
 #[cfg(test)]
 mod tests {
     use crate::zone::Records;
@@ -603,6 +842,41 @@ mod tests {
         );
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn dns_query() {
+        let mut records = Records::new();
+        records.insert(
+            String::from("pashka.nord."),
+            vec![
+                IpAddr::V4(Ipv4Addr::new(100, 69, 69, 69)),
+                IpAddr::V6(Ipv6Addr::new(0xfd74, 0x656c, 0x696f, 0, 0, 0, 0, 69)),
+            ],
+        );
+        let nameserver = LocalNameServer::new(&[IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8))])
+            .await
+            .unwrap();
+        nameserver.upsert("nord", &records).await.unwrap();
+
+        assert_eq!(
+            nameserver.query("pashka.nord", "A").await.unwrap(),
+            DnsQueryResponse {
+                name: String::from("pashka.nord."),
+                record_type: String::from("A"),
+                ttl: 900,
+                records: vec![String::from("100.69.69.69")],
+            }
+        );
+        assert_eq!(
+            nameserver
+                .query("pashka.nord.", "AAAA")
+                .await
+                .unwrap()
+                .records,
+            vec![String::from("fd74:656c:696f::45")]
+        );
+        assert!(nameserver.query("pashka.nord", "BOGUS").await.is_err());
+    }
+
     #[tokio::test]
     async fn zones_are_lazily_copied_on_write_access() {
         let name1 = "test.nord.".to_owned();
@@ -653,4 +927,104 @@ mod tests {
         assert!(zones.contains(&LowerName::from_str(".").unwrap()));
         assert!(zones.contains(&LowerName::from_str("nord").unwrap()));
     }
+
+    #[tokio::test]
+    async fn overrides_take_precedence_over_zone_records() {
+        let mut records = Records::new();
+        records.insert(
+            "test.nord.".to_owned(),
+            vec![IpAddr::V4(Ipv4Addr::new(100, 69, 69, 69))],
+        );
+        let nameserver = LocalNameServer::new(&[IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8))])
+            .await
+            .unwrap();
+        nameserver.upsert("nord", &records).await.unwrap();
+
+        nameserver
+            .override_records(
+                "test.nord.",
+                &[IpAddr::V4(Ipv4Addr::new(100, 64, 0, 99))],
+                60,
+            )
+            .await
+            .unwrap();
+        let response = nameserver.query("test.nord", "A").await.unwrap();
+        assert_eq!(response.records, vec!["100.64.0.99".to_owned()]);
+        assert_eq!(response.ttl, 60);
+
+        nameserver.remove_override("test.nord.").await.unwrap();
+        let response = nameserver.query("test.nord", "A").await.unwrap();
+        assert_eq!(response.records, vec!["100.69.69.69".to_owned()]);
+        assert!(nameserver.remove_override("test.nord.").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn ttl_is_clamped_to_override() {
+        let nameserver = LocalNameServer::new(&[IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8))])
+            .await
+            .unwrap();
+        nameserver
+            .override_records(
+                "short.nord.",
+                &[IpAddr::V4(Ipv4Addr::new(100, 64, 0, 99))],
+                1,
+            )
+            .await
+            .unwrap();
+        assert_eq!(nameserver.query("short.nord", "A").await.unwrap().ttl, 1);
+
+        nameserver
+            .set_ttl_override(DnsTtlOverride {
+                min_ttl_secs: 30,
+                max_ttl_secs: 0,
+            })
+            .await
+            .unwrap();
+        assert_eq!(nameserver.query("short.nord", "A").await.unwrap().ttl, 30);
+
+        nameserver
+            .set_ttl_override(DnsTtlOverride {
+                min_ttl_secs: 30,
+                max_ttl_secs: 60,
+            })
+            .await
+            .unwrap();
+        nameserver
+            .override_records(
+                "long.nord.",
+                &[IpAddr::V4(Ipv4Addr::new(100, 64, 0, 98))],
+                3600,
+            )
+            .await
+            .unwrap();
+        assert_eq!(nameserver.query("long.nord", "A").await.unwrap().ttl, 60);
+    }
+
+    #[tokio::test]
+    async fn meshnet_names_are_not_sent_to_fallback() {
+        let mut records = Records::new();
+        records.insert(
+            "test.nord.".to_owned(),
+            vec![IpAddr::V4(Ipv4Addr::new(100, 69, 69, 69))],
+        );
+        // Documentation addresses, nothing answers there
+        let nameserver = LocalNameServer::new(&[IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1))])
+            .await
+            .unwrap();
+        nameserver.upsert("nord", &records).await.unwrap();
+        nameserver
+            .set_fallback(Some(DnsFallbackConfig {
+                servers: vec![IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2))],
+                timeout_ms: 10,
+            }))
+            .await
+            .unwrap();
+
+        let response = nameserver.query("test.nord", "A").await.unwrap();
+        assert_eq!(response.records, vec!["100.69.69.69".to_owned()]);
+        assert!(nameserver
+            .zones()
+            .await
+            .contains(&LowerName::from_str(".").unwrap()));
+    }
 }
@@ -14,9 +14,14 @@ use std::{
     collections::{HashMap, HashSet},
     net::IpAddr,
     str::FromStr,
+    time::Duration,
 };
+use telio_model::config::{DnsFallbackConfig, DnsTtlOverride};
 
-use crate::forward::ForwardAuthority;
+use crate::{
+    doh::DohServer,
+    forward::{ForwardAuthority, TelioRuntimeProvider},
+};
 
 /// Zone is a portion of the DNS namespace that is managed by a specific
 /// organization or administrator.
@@ -28,6 +33,9 @@ pub(crate) type Zones = Catalog;
 /// for that domain.
 pub type Records = HashMap<String, Vec<IpAddr>>;
 
+/// TTL of the records served by authoritative zones, in seconds.
+const DEFAULT_RECORD_TTL: u32 = 900;
+
 /// AuthoritativeZone is a zone for which the local server references its
 /// own data when responding to queries.
 pub(crate) struct AuthoritativeZone {
@@ -36,6 +44,10 @@ pub(crate) struct AuthoritativeZone {
 
 impl AuthoritativeZone {
     pub(crate) async fn new(name: &str, records: &Records) -> Result<Self, String> {
+        Self::with_ttl(name, records, DEFAULT_RECORD_TTL).await
+    }
+
+    pub(crate) async fn with_ttl(name: &str, records: &Records, ttl: u32) -> Result<Self, String> {
         // TODO: rewrite code so that this assert is not needed.
         for domain in records.keys() {
             if !domain.contains(name) {
@@ -68,7 +80,7 @@ impl AuthoritativeZone {
         let build_record = |name: Name, ty: RecordType, data: RData| -> Record {
             Record::new()
                 .set_name(name)
-                .set_ttl(900)
+                .set_ttl(ttl)
                 .set_rr_type(ty)
                 .set_dns_class(DNSClass::IN)
                 .set_data(Some(data))
@@ -161,7 +173,45 @@ pub(crate) struct ForwardZone {
 }
 
 impl ForwardZone {
-    pub(crate) async fn new(name: &str, ips: &[IpAddr]) -> Result<Self, String> {
+    pub(crate) async fn new(
+        name: &str,
+        ips: &[IpAddr],
+        doh: Option<&DohServer>,
+        fallback: Option<&DnsFallbackConfig>,
+        ttl_override: DnsTtlOverride,
+    ) -> Result<Self, String> {
+        // DNS over HTTPS server replaces the plain forward servers, fallback stays plain
+        let (name_servers, runtime_provider) = match doh {
+            Some(doh) => (
+                doh.name_servers(ips).await?,
+                TelioRuntimeProvider::with_proxy(doh.proxy.clone()),
+            ),
+            None => (
+                NameServerConfigGroup::from_ips_clear(ips, 53, true),
+                TelioRuntimeProvider::default(),
+            ),
+        };
+        let mut zone = ForwardAuthority::try_from_config(
+            Name::from_str(name)?,
+            ZoneType::Forward,
+            ForwardConfig {
+                options: Some(Self::resolver_options(ttl_override)),
+                name_servers,
+            },
+            runtime_provider,
+        )
+        .await?;
+        if let Some(fallback) = fallback {
+            zone.set_fallback(
+                NameServerConfigGroup::from_ips_clear(&fallback.servers, 53, true),
+                Self::resolver_options(ttl_override),
+                Duration::from_millis(fallback.timeout_ms),
+            );
+        }
+        Ok(ForwardZone { zone })
+    }
+
+    fn resolver_options(ttl_override: DnsTtlOverride) -> ResolverOpts {
         let mut options = ResolverOpts::default();
         // Some tools and browsers do not accept responses without intermediates preserved
         options.preserve_intermediates = true;
@@ -173,16 +223,15 @@ impl ForwardZone {
         // We set the number of retries to 0. The retry should be handled by the OS retry mechanism
         options.attempts = 0;
 
-        let zone = ForwardAuthority::try_from_config(
-            Name::from_str(name)?,
-            ZoneType::Forward,
-            ForwardConfig {
-                options: Some(options),
-                name_servers: NameServerConfigGroup::from_ips_clear(ips, 53, true),
-            },
-        )
-        .await?;
-        Ok(ForwardZone { zone })
+        // Cache answers at least and at most as long as the override allows
+        if ttl_override.min_ttl_secs > 0 {
+            options.positive_min_ttl = Some(Duration::from_secs(ttl_override.min_ttl_secs.into()));
+        }
+        if ttl_override.max_ttl_secs > 0 {
+            options.positive_max_ttl = Some(Duration::from_secs(ttl_override.max_ttl_secs.into()));
+        }
+
+        options
     }
 }
 
@@ -248,6 +297,11 @@ impl ClonableZones {
         self.names.insert(name);
     }
 
+    pub fn remove(&mut self, name: &LowerName) {
+        self.zones.remove(name);
+        self.names.remove(name);
+    }
+
     pub async fn lookup<R: ResponseHandler>(
         &self,
         request: &Request,
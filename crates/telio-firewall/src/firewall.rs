@@ -13,11 +13,15 @@ use pnet_packet::{
     udp::UdpPacket,
     Packet,
 };
+use serde::Serialize;
 use std::{
     convert::TryInto,
     fmt::{Debug, Formatter},
     io,
-    net::{IpAddr as StdIpAddr, Ipv4Addr as StdIpv4Addr, Ipv6Addr as StdIpv6Addr},
+    net::{
+        IpAddr as StdIpAddr, Ipv4Addr as StdIpv4Addr, Ipv6Addr as StdIpv6Addr,
+        SocketAddr as StdSocketAddr,
+    },
     sync::{Mutex, RwLock},
     time::Duration,
 };
@@ -188,6 +192,15 @@ pub trait Firewall {
     /// Returns a whitelist of peers
     fn get_peer_whitelist(&self) -> HashSet<PublicKey>;
 
+    /// Ban peer, all packets from it are dropped regardless of whitelists
+    fn ban_peer(&self, peer: PublicKey);
+
+    /// Lift the ban of the peer
+    fn unban_peer(&self, peer: PublicKey);
+
+    /// Returns banned peers
+    fn get_banned_peers(&self) -> HashSet<PublicKey>;
+
     /// For new connections it opens a pinhole for incoming connection
     /// If connection is already cached, it resets its timer and extends its lifetime
     /// Only returns false for invalid or not ipv4 packets
@@ -239,6 +252,8 @@ pub struct StatefullFirewall {
     icmp: Mutex<LruCache<IcmpConn, ()>>,
     /// Whitelist of networks/peers allowed to connect
     whitelist: RwLock<Whitelist>,
+    /// Peers from which all packets are dropped
+    banned_peers: RwLock<HashSet<PublicKey>>,
     /// Indicates whether the firewall should use IPv6
     allow_ipv6: bool,
     /// Wheter to still keep track of whitelisted TCP/UDP connections.
@@ -246,10 +261,27 @@ pub struct StatefullFirewall {
     record_whitelisted: bool,
 }
 
+/// Connection currently tracked by the firewall
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ActiveConnection {
+    /// Address of the side which initiated the connection
+    pub src: StdSocketAddr,
+    /// Address of the side which accepted the connection
+    pub dst: StdSocketAddr,
+    /// Peer through which the connection goes
+    pub peer_pk: PublicKey,
+    /// Bytes sent from this device over the connection
+    pub bytes_tx: u64,
+    /// Bytes received by this device over the connection
+    pub bytes_rx: u64,
+}
+
 #[derive(Debug)]
 struct UdpConnectionInfo {
     is_remote_initiated: bool,
     last_out_pkg_chunk: Option<Vec<u8>>,
+    tx_bytes: u64,
+    rx_bytes: u64,
 }
 
 impl UdpConnectionInfo {
@@ -265,6 +297,8 @@ struct TcpConnectionInfo {
     rx_alive: bool,
     conn_remote_initiated: bool,
     next_seq: Option<u32>,
+    tx_bytes: u64,
+    rx_bytes: u64,
 }
 
 #[derive(Clone, Copy, Ord, PartialOrd, Eq, PartialEq, Debug, Hash)]
@@ -302,6 +336,19 @@ struct IpConnWithPort {
     local_port: u16,
 }
 
+impl IpConnWithPort {
+    /// Returns (src, dst) socket addresses, as seen by the initiator of the connection
+    fn endpoints(&self, remote_initiated: bool) -> (StdSocketAddr, StdSocketAddr) {
+        let local = StdSocketAddr::new(self.local_addr.into(), self.local_port);
+        let remote = StdSocketAddr::new(self.remote_addr.into(), self.remote_port);
+        if remote_initiated {
+            (remote, local)
+        } else {
+            (local, remote)
+        }
+    }
+}
+
 impl Debug for IpConnWithPort {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("IpConnWithPort")
@@ -400,6 +447,48 @@ impl StatefullFirewall {
         (tcp, udp)
     }
 
+    /// Returns up to `max_entries` most recently active TCP and UDP connections
+    pub fn get_active_connections(&self, max_entries: usize) -> Vec<ActiveConnection> {
+        let mut connections = Vec::new();
+
+        {
+            let tcp = unwrap_lock_or_return!(self.tcp.lock(), Vec::new());
+            connections.extend(tcp.iter_recent().take(max_entries).map(|(k, v, idle)| {
+                let (src, dst) = k.link.endpoints(v.conn_remote_initiated);
+                let connection = ActiveConnection {
+                    src,
+                    dst,
+                    peer_pk: k.pubkey,
+                    bytes_tx: v.tx_bytes,
+                    bytes_rx: v.rx_bytes,
+                };
+                (idle, connection)
+            }));
+        }
+
+        {
+            let udp = unwrap_lock_or_return!(self.udp.lock(), Vec::new());
+            connections.extend(udp.iter_recent().take(max_entries).map(|(k, v, idle)| {
+                let (src, dst) = k.link.endpoints(v.is_remote_initiated);
+                let connection = ActiveConnection {
+                    src,
+                    dst,
+                    peer_pk: k.pubkey,
+                    bytes_tx: v.tx_bytes,
+                    bytes_rx: v.rx_bytes,
+                };
+                (idle, connection)
+            }));
+        }
+
+        connections.sort_by_key(|(idle, _)| *idle);
+        connections
+            .into_iter()
+            .take(max_entries)
+            .map(|(_, connection)| connection)
+            .collect()
+    }
+
     /// Constructs firewall with custom capacity and timeout in ms (for testing only).
     fn new_custom(capacity: usize, ttl: u64, use_ipv6: bool, record_whitelisted: bool) -> Self {
         let ttl = Duration::from_millis(ttl);
@@ -408,6 +497,7 @@ impl StatefullFirewall {
             udp: Mutex::new(LruCache::new(ttl, capacity)),
             icmp: Mutex::new(LruCache::new(ttl, capacity)),
             whitelist: RwLock::new(Whitelist::default()),
+            banned_peers: RwLock::new(HashSet::new()),
             allow_ipv6: use_ipv6,
             record_whitelisted,
         }
@@ -548,6 +638,8 @@ impl StatefullFirewall {
                 let conninfo = UdpConnectionInfo {
                     is_remote_initiated: false,
                     last_out_pkg_chunk: Some(last_chunk),
+                    tx_bytes: ip.packet().len() as u64,
+                    rx_bytes: 0,
                 };
                 telio_log_trace!("Inserting new UDP conntrack entry {:?}", e.key());
                 e.insert(conninfo);
@@ -564,6 +656,7 @@ impl StatefullFirewall {
                 last_chunk.extend_from_slice(pkg_chunk);
 
                 value.last_out_pkg_chunk = Some(last_chunk);
+                value.tx_bytes += ip.packet().len() as u64;
             }
         }
     }
@@ -585,6 +678,8 @@ impl StatefullFirewall {
                     rx_alive: true,
                     conn_remote_initiated: false,
                     next_seq: None,
+                    tx_bytes: ip.packet().len() as u64,
+                    rx_bytes: 0,
                 },
             );
         } else if flags & TcpFlags::RST == TcpFlags::RST {
@@ -593,9 +688,15 @@ impl StatefullFirewall {
         } else if flags & TcpFlags::FIN == TcpFlags::FIN {
             telio_log_trace!("Connection {:?} closing", key);
             if let Entry::Occupied(mut e) = tcp_cache.entry(key) {
-                let TcpConnectionInfo { tx_alive, .. } = e.get_mut();
+                let TcpConnectionInfo {
+                    tx_alive, tx_bytes, ..
+                } = e.get_mut();
                 *tx_alive = false;
+                *tx_bytes += ip.packet().len() as u64;
             }
+        } else if let Some(connection) = tcp_cache.peek_mut(&key) {
+            // Only account the traffic, outbound packets do not extend the entry lifetime
+            connection.tx_bytes += ip.packet().len() as u64;
         }
     }
 
@@ -640,6 +741,8 @@ impl StatefullFirewall {
                     occ.remove();
                     return false;
                 }
+
+                occ.get_mut().rx_bytes += ip.packet().len() as u64;
             }
             Entry::Vacant(vacc) => {
                 let key = vacc.key();
@@ -654,6 +757,8 @@ impl StatefullFirewall {
                 vacc.insert(UdpConnectionInfo {
                     is_remote_initiated: true,
                     last_out_pkg_chunk: None,
+                    tx_bytes: 0,
+                    rx_bytes: ip.packet().len() as u64,
                 });
             }
         }
@@ -714,6 +819,7 @@ impl StatefullFirewall {
                 };
 
                 val.next_seq = Some(next_seq);
+                val.rx_bytes += ip.packet().len() as u64;
             }
             telio_log_trace!("Accepting TCP packet {:?} {:?}", ip, pubkey);
             return true;
@@ -731,6 +837,8 @@ impl StatefullFirewall {
                 rx_alive: true,
                 conn_remote_initiated: true,
                 next_seq: Some(packet.get_sequence() + 1),
+                tx_bytes: 0,
+                rx_bytes: ip.packet().len() as u64,
             };
 
             telio_log_trace!(
@@ -1262,6 +1370,20 @@ impl Firewall for StatefullFirewall {
             .clone()
     }
 
+    fn ban_peer(&self, peer: PublicKey) {
+        telio_log_debug!("Banning {:?} peer in firewall", peer);
+        unwrap_lock_or_return!(self.banned_peers.write()).insert(peer);
+    }
+
+    fn unban_peer(&self, peer: PublicKey) {
+        telio_log_debug!("Unbanning {:?} peer in firewall", peer);
+        unwrap_lock_or_return!(self.banned_peers.write()).remove(&peer);
+    }
+
+    fn get_banned_peers(&self) -> HashSet<PublicKey> {
+        unwrap_lock_or_return!(self.banned_peers.read(), Default::default()).clone()
+    }
+
     fn process_outbound_packet(&self, public_key: &[u8; 32], buffer: &[u8]) -> bool {
         telio_log_debug!("Outbound packet");
 
@@ -1282,6 +1404,12 @@ impl Firewall for StatefullFirewall {
     /// Adds new connection to cache only if ip is whitelisted
     /// Allows all icmp packets except for request types
     fn process_inbound_packet(&self, public_key: &[u8; 32], buffer: &[u8]) -> bool {
+        let peer = PublicKey(*public_key);
+        if unwrap_lock_or_return!(self.banned_peers.read(), false).contains(&peer) {
+            telio_log_trace!("Inbound packet is from banned peer, dropping");
+            return false;
+        }
+
         match unwrap_option_or_return!(buffer.first(), false) >> 4 {
             4 => self.process_inbound_ip_packet::<Ipv4Packet>(public_key, buffer),
             6 if self.allow_ipv6 => {
@@ -1959,6 +2087,7 @@ pub mod tests {
             assert_eq!(fw.process_inbound_packet(&peer, &make_tcp(them, us, TcpFlags::SYN)), false);
 
             let outgoing_init_packet = make_tcp(us, them, TcpFlags::SYN);
+            let pkt_len = outgoing_init_packet.len() as u64;
 
             assert_eq!(fw.process_outbound_packet(&make_peer(), &outgoing_init_packet), true);
             assert_eq!(fw.process_inbound_packet(&make_peer(), &make_tcp(them, us, TcpFlags::SYN | TcpFlags::ACK)), true);
@@ -1973,7 +2102,8 @@ pub mod tests {
             let tcp_key = TcpConn { link , pubkey: PublicKey(peer) };
 
             assert_eq!(fw.tcp.lock().unwrap().get(&tcp_key), Some(&TcpConnectionInfo{
-                tx_alive: true, rx_alive: true, conn_remote_initiated: false, next_seq: Some(1)
+                tx_alive: true, rx_alive: true, conn_remote_initiated: false, next_seq: Some(1),
+                tx_bytes: pkt_len, rx_bytes: 2 * pkt_len
             }));
 
             assert_eq!(fw.process_outbound_packet(&make_peer(), &make_tcp(us, them, TcpFlags::RST)), true);
@@ -2022,6 +2152,7 @@ pub mod tests {
             let peer = make_peer();
 
             let outgoing_init_packet = make_tcp(us, them, TcpFlags::SYN);
+            let pkt_len = outgoing_init_packet.len() as u64;
             assert_eq!(fw.process_outbound_packet(&peer, &outgoing_init_packet), true);
             assert_eq!(fw.tcp.lock().unwrap().len(), 1);
             let link = IpConnWithPort {
@@ -2033,21 +2164,24 @@ pub mod tests {
             let conn_key = TcpConn { link , pubkey: PublicKey(peer) };
 
             assert_eq!(fw.tcp.lock().unwrap().get(&conn_key), Some(&TcpConnectionInfo{
-                tx_alive: true, rx_alive: true, conn_remote_initiated: false, next_seq: None
+                tx_alive: true, rx_alive: true, conn_remote_initiated: false, next_seq: None,
+                tx_bytes: pkt_len, rx_bytes: 0
             }));
 
             assert_eq!(fw.process_inbound_packet(&make_peer(), &make_tcp(them, us, TcpFlags::FIN)), true);
             assert_eq!(fw.tcp.lock().unwrap().len(), 1);
 
             assert_eq!(fw.tcp.lock().unwrap().get(&conn_key), Some(&TcpConnectionInfo{
-                tx_alive: true, rx_alive: false, conn_remote_initiated: false, next_seq: Some(12)
+                tx_alive: true, rx_alive: false, conn_remote_initiated: false, next_seq: Some(12),
+                tx_bytes: pkt_len, rx_bytes: pkt_len
             }));
 
             assert_eq!(fw.process_outbound_packet(&make_peer(), &make_tcp(us, them, TcpFlags::FIN)), true);
             assert_eq!(fw.tcp.lock().unwrap().len(), 1);
 
             assert_eq!(fw.tcp.lock().unwrap().get(&conn_key), Some(&TcpConnectionInfo{
-                tx_alive: false, rx_alive: false, conn_remote_initiated: false, next_seq: Some(12)
+                tx_alive: false, rx_alive: false, conn_remote_initiated: false, next_seq: Some(12),
+                tx_bytes: 2 * pkt_len, rx_bytes: pkt_len
             }));
 
             assert_eq!(fw.process_inbound_packet(&make_peer(), &make_tcp(them, us, TcpFlags::ACK)), true);
@@ -2081,6 +2215,7 @@ pub mod tests {
             let peer = make_peer();
 
             let outgoing_init_packet = make_tcp(us, them, TcpFlags::SYN);
+            let pkt_len = outgoing_init_packet.len() as u64;
             assert_eq!(fw.process_outbound_packet(&make_peer(), &outgoing_init_packet), true);
             assert_eq!(fw.tcp.lock().unwrap().len(), 1);
             let link = IpConnWithPort {
@@ -2092,14 +2227,16 @@ pub mod tests {
             let conn_key = TcpConn { link , pubkey: PublicKey(peer) };
 
             assert_eq!(fw.tcp.lock().unwrap().get(&conn_key), Some(&TcpConnectionInfo{
-                tx_alive: true, rx_alive: true, conn_remote_initiated: false, next_seq: None
+                tx_alive: true, rx_alive: true, conn_remote_initiated: false, next_seq: None,
+                tx_bytes: pkt_len, rx_bytes: 0
             }));
 
             assert_eq!(fw.process_inbound_packet(&make_peer(), &make_tcp(them, us, TcpFlags::FIN)), true);
             assert_eq!(fw.tcp.lock().unwrap().len(), 1);
 
             assert_eq!(fw.tcp.lock().unwrap().get(&conn_key), Some(&TcpConnectionInfo{
-                tx_alive: true, rx_alive: false, conn_remote_initiated: false, next_seq: Some(12)
+                tx_alive: true, rx_alive: false, conn_remote_initiated: false, next_seq: Some(12),
+                tx_bytes: pkt_len, rx_bytes: pkt_len
             }));
 
             assert_eq!(fw.process_outbound_packet(&make_peer(), &make_tcp(us, them, TcpFlags::FIN)), true);
@@ -2107,7 +2244,8 @@ pub mod tests {
 
             // update tcp cache entry timeout
             assert_eq!(fw.tcp.lock().unwrap().get(&conn_key), Some(&TcpConnectionInfo{
-                tx_alive: false, rx_alive: false, conn_remote_initiated: false, next_seq: Some(12)
+                tx_alive: false, rx_alive: false, conn_remote_initiated: false, next_seq: Some(12),
+                tx_bytes: 2 * pkt_len, rx_bytes: pkt_len
             }));
 
             // process inbound packet (should not update ttl, because not ACK, but entry should still exist)
@@ -2611,6 +2749,26 @@ pub mod tests {
         assert!(fw.get_peer_whitelist().is_empty());
     }
 
+    #[test]
+    fn firewall_banned_peer_is_dropped_even_if_whitelisted() {
+        let fw = StatefullFirewall::new(true, false);
+        let peer = make_random_peer();
+        let packet = make_udp("100.100.100.100:1234", "127.0.0.1:1111");
+
+        fw.add_to_peer_whitelist(peer);
+        assert!(fw.process_inbound_packet(&peer.0, &packet));
+
+        fw.ban_peer(peer);
+        assert_eq!(fw.get_banned_peers(), HashSet::from([peer]));
+        assert!(!fw.process_inbound_packet(&peer.0, &packet));
+        let icmp = make_icmp4("100.100.100.100", "127.0.0.1", IcmpTypes::EchoReply.into());
+        assert!(!fw.process_inbound_packet(&peer.0, &icmp));
+
+        fw.unban_peer(peer);
+        assert!(fw.get_banned_peers().is_empty());
+        assert!(fw.process_inbound_packet(&peer.0, &packet));
+    }
+
     #[rustfmt::skip]
     #[test]
     fn firewall_whitelist() {
@@ -3244,4 +3402,47 @@ pub mod tests {
             assert!(fw.process_outbound_packet(&peer_good.0, &outgoing_packet),);
         }
     }
+
+    #[test]
+    fn firewall_reports_most_recent_active_connections() {
+        let us = "127.0.0.1:1111";
+        let them = "8.8.8.8:8888";
+        let remote = "1.1.1.1:443";
+        let local = "127.0.0.1:2222";
+
+        let fw = StatefullFirewall::new_custom(3, LRU_TIMEOUT, false, false);
+        let peer = make_random_peer();
+        fw.add_to_port_whitelist(peer, 2222);
+
+        let udp_outbound = make_udp(us, them);
+        let udp_inbound = make_udp(them, us);
+        let tcp_inbound = make_tcp(remote, local, TcpFlags::SYN);
+
+        assert!(fw.process_outbound_packet(&peer.0, &udp_outbound));
+        advance_time(Duration::from_millis(10));
+        assert!(fw.process_inbound_packet(&peer.0, &tcp_inbound));
+        advance_time(Duration::from_millis(10));
+        assert!(fw.process_inbound_packet(&peer.0, &udp_inbound));
+
+        let udp_connection = ActiveConnection {
+            src: us.parse().unwrap(),
+            dst: them.parse().unwrap(),
+            peer_pk: peer,
+            bytes_tx: udp_outbound.len() as u64,
+            bytes_rx: udp_inbound.len() as u64,
+        };
+        let tcp_connection = ActiveConnection {
+            src: remote.parse().unwrap(),
+            dst: local.parse().unwrap(),
+            peer_pk: peer,
+            bytes_tx: 0,
+            bytes_rx: tcp_inbound.len() as u64,
+        };
+
+        assert_eq!(
+            fw.get_active_connections(10),
+            vec![udp_connection.clone(), tcp_connection]
+        );
+        assert_eq!(fw.get_active_connections(1), vec![udp_connection]);
+    }
 }
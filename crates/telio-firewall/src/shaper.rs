@@ -0,0 +1,198 @@
+//! Per peer bandwidth limits applied on the packet processing path
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use telio_crypto::PublicKey;
+use telio_utils::{telio_log_debug, telio_log_error};
+
+use crate::firewall::HashMap;
+
+/// Amount of traffic which can be sent at once after the peer was idle
+const BURST_DURATION: Duration = Duration::from_millis(250);
+/// Bucket always holds enough tokens for at least one full sized packet
+const MIN_BURST_BYTES: u64 = 1500;
+
+/// Token bucket limiting throughput to a constant rate
+#[derive(Debug)]
+struct TokenBucket {
+    bytes_per_sec: u64,
+    capacity: u64,
+    tokens: u64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(kbps: u32, now: Instant) -> Self {
+        let bytes_per_sec = u64::from(kbps) * 1000 / 8;
+        let capacity =
+            ((bytes_per_sec as f64 * BURST_DURATION.as_secs_f64()) as u64).max(MIN_BURST_BYTES);
+        Self {
+            bytes_per_sec,
+            capacity,
+            tokens: capacity,
+            last_refill: now,
+        }
+    }
+
+    fn take(&mut self, bytes: usize, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last_refill);
+        let refill = (self.bytes_per_sec as f64 * elapsed.as_secs_f64()) as u64;
+        if refill > 0 {
+            self.tokens = self.tokens.saturating_add(refill).min(self.capacity);
+            self.last_refill = now;
+        }
+
+        let bytes = bytes as u64;
+        if self.tokens < bytes {
+            return false;
+        }
+        self.tokens -= bytes;
+        true
+    }
+}
+
+/// Limits of a single peer, `None` means the direction is not limited
+#[derive(Debug)]
+struct PeerShaper {
+    tx: Option<TokenBucket>,
+    rx: Option<TokenBucket>,
+}
+
+/// Rate limiter of traffic exchanged with meshnet peers
+///
+/// Packets exceeding the configured rate are dropped, letting congestion control of the
+/// upper layer protocols slow the connections down.
+#[derive(Debug, Default)]
+pub struct TrafficShaper {
+    peers: Mutex<HashMap<PublicKey, PeerShaper>>,
+}
+
+impl TrafficShaper {
+    /// Create shaper without any limits
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Limit traffic of `peer` to `max_tx_kbps` towards it and `max_rx_kbps` from it,
+    /// zero leaves the direction unlimited. Replaces previous limits of the peer.
+    pub fn set_limits(&self, peer: PublicKey, max_tx_kbps: u32, max_rx_kbps: u32) {
+        telio_log_debug!(
+            "Limiting traffic of {:?} to tx: {} kbps, rx: {} kbps",
+            peer,
+            max_tx_kbps,
+            max_rx_kbps
+        );
+        let now = Instant::now();
+        let bucket = |kbps| {
+            Some(kbps)
+                .filter(|&k| k > 0)
+                .map(|k| TokenBucket::new(k, now))
+        };
+        match self.peers.lock() {
+            Ok(mut peers) => {
+                peers.insert(
+                    peer,
+                    PeerShaper {
+                        tx: bucket(max_tx_kbps),
+                        rx: bucket(max_rx_kbps),
+                    },
+                );
+            }
+            Err(_) => telio_log_error!("Poisoned lock"),
+        }
+    }
+
+    /// Remove limits of `peer`, returns false if it was not limited
+    pub fn remove_limits(&self, peer: &PublicKey) -> bool {
+        telio_log_debug!("Removing traffic limits of {:?}", peer);
+        match self.peers.lock() {
+            Ok(mut peers) => peers.remove(peer).is_some(),
+            Err(_) => {
+                telio_log_error!("Poisoned lock");
+                false
+            }
+        }
+    }
+
+    /// Checks if outbound packet to the peer fits into its limit
+    pub fn process_outbound_packet(&self, public_key: &[u8; 32], buffer: &[u8]) -> bool {
+        self.process_packet(public_key, buffer, Instant::now(), |s| s.tx.as_mut())
+    }
+
+    /// Checks if inbound packet from the peer fits into its limit
+    pub fn process_inbound_packet(&self, public_key: &[u8; 32], buffer: &[u8]) -> bool {
+        self.process_packet(public_key, buffer, Instant::now(), |s| s.rx.as_mut())
+    }
+
+    fn process_packet(
+        &self,
+        public_key: &[u8; 32],
+        buffer: &[u8],
+        now: Instant,
+        direction: impl FnOnce(&mut PeerShaper) -> Option<&mut TokenBucket>,
+    ) -> bool {
+        let mut peers = match self.peers.lock() {
+            Ok(peers) => peers,
+            Err(_) => {
+                telio_log_error!("Poisoned lock");
+                return true;
+            }
+        };
+        match peers.get_mut(&PublicKey(*public_key)).and_then(direction) {
+            Some(bucket) => bucket.take(buffer.len(), now),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_bucket_limits_rate() {
+        let start = Instant::now();
+        // 8000 kbps is 1MB/s, 250KB burst
+        let mut bucket = TokenBucket::new(8000, start);
+
+        assert!(bucket.take(250_000, start));
+        assert!(!bucket.take(1, start));
+        assert!(!bucket.take(2000, start + Duration::from_millis(1)));
+        assert!(bucket.take(2000, start + Duration::from_millis(2)));
+        // Idle time does not accumulate more than the burst
+        assert!(!bucket.take(250_001, start + Duration::from_secs(10)));
+        assert!(bucket.take(250_000, start + Duration::from_secs(10)));
+    }
+
+    #[test]
+    fn token_bucket_fits_full_packet() {
+        let start = Instant::now();
+        let mut bucket = TokenBucket::new(1, start);
+
+        assert!(bucket.take(1500, start));
+        assert!(!bucket.take(1500, start));
+    }
+
+    #[test]
+    fn shaper_limits_only_configured_direction() {
+        let peer = PublicKey([1; 32]);
+        let other = PublicKey([2; 32]);
+        let shaper = TrafficShaper::new();
+        let packet = [0u8; 1500];
+
+        shaper.set_limits(peer, 1, 0);
+        assert!(shaper.process_outbound_packet(&peer.0, &packet));
+        assert!(!shaper.process_outbound_packet(&peer.0, &packet));
+        assert!(shaper.process_inbound_packet(&peer.0, &packet));
+        assert!(shaper.process_inbound_packet(&peer.0, &packet));
+        assert!(shaper.process_outbound_packet(&other.0, &packet));
+        assert!(shaper.process_outbound_packet(&other.0, &packet));
+
+        assert!(shaper.remove_limits(&peer));
+        assert!(!shaper.remove_limits(&peer));
+        assert!(shaper.process_outbound_packet(&peer.0, &packet));
+    }
+}
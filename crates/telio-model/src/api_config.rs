@@ -17,6 +17,7 @@ pub const DEFAULT_DIRECT_PERSISTENT_KEEPALIVE_PERIOD: u32 = 5;
 
 /// Configurable persistent keepalive periods for different types of peers
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct FeaturePersistentKeepalive {
     /// Persistent keepalive period given for VPN peers (in seconds) [default 15s]
     #[serde(default = "FeaturePersistentKeepalive::get_default_keepalive_period")]
@@ -58,15 +59,21 @@ impl Default for FeaturePersistentKeepalive {
 
 /// Configurable features for Wireguard peers
 #[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct FeatureWireguard {
     /// Configurable persistent keepalive periods for wireguard peers
     #[serde(default)]
     pub persistent_keepalive: FeaturePersistentKeepalive,
+    /// Rotate pre-shared keys of meshnet peers every this many seconds. Default value is None,
+    /// meaning meshnet peers don't use pre-shared keys.
+    #[serde(default)]
+    pub psk_rotation_interval_secs: Option<u64>,
 }
 
 #[serde_with::serde_as]
 #[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
 /// QoS configuration options
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct FeatureQoS {
     /// How often to collect rtt data in seconds. Default value is 300.
     pub rtt_interval: Option<u32>,
@@ -80,6 +87,7 @@ pub struct FeatureQoS {
 
 /// Configurable features for Nurse module
 #[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct FeatureNurse {
     /// The unique identifier of the device, used for meshnet ID
     pub fingerprint: String,
@@ -95,6 +103,7 @@ pub struct FeatureNurse {
 
 /// Configurable features for Lana module
 #[derive(Clone, Default, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct FeatureLana {
     /// Path of the file where events will be stored. If such file does not exist, it will be created, otherwise reused
     pub event_path: String,
@@ -112,6 +121,7 @@ impl fmt::Debug for FeatureLana {
 
 /// Configurable features for exit Dns
 #[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct FeatureExitDns {
     /// Controls if it is allowed to reconfigure DNS peer when exit node is
     /// (dis)connected.
@@ -121,6 +131,7 @@ pub struct FeatureExitDns {
 /// Mesh connection path type
 #[derive(Clone, Copy, Debug, Default, EnumCount, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "kebab-case")]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum PathType {
     /// Nodes connected via a middle-man relay
     #[default]
@@ -131,6 +142,7 @@ pub enum PathType {
 
 /// Enable wanted paths for telio
 #[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct FeaturePaths {
     /// Enable paths in increasing priority: 0 is worse then 1 is worse then 2 ...
     /// [PathType::Relay] always assumed as -1
@@ -155,6 +167,7 @@ pub struct FeaturePaths {
 )]
 #[repr(u32)]
 #[serde(rename_all = "kebab-case")]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum EndpointProvider {
     /// Use local interface ips as possible endpoints
     Local = 1,
@@ -169,16 +182,24 @@ pub const DEFAULT_ENDPOINT_POLL_INTERVAL_SECS: u64 = 25;
 
 /// Enable meshent direct connection
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct FeatureDirect {
     /// Endpoint providers [default all]
     #[serde(default)]
     #[serde(deserialize_with = "deserialize_providers")]
+    #[cfg_attr(
+        feature = "schema",
+        schemars(with = "Option<HashSet<EndpointProvider>>")
+    )]
     pub providers: Option<HashSet<EndpointProvider>>,
     /// Polling interval for endpoints [default 10s]
     pub endpoint_interval_secs: Option<u64>,
     /// Configuration options for skipping unresponsive peers
     #[serde(default = "FeatureDirect::default_skip_unresponsive_peers")]
     pub skip_unresponsive_peers: Option<FeatureSkipUnresponsivePeers>,
+    /// Direct paths with higher round trip time are not used, peers stay on the relay
+    /// [default no limit]
+    pub direct_path_latency_threshold_ms: Option<u64>,
 }
 
 impl Default for FeatureDirect {
@@ -187,6 +208,7 @@ impl Default for FeatureDirect {
             skip_unresponsive_peers: Self::default_skip_unresponsive_peers(),
             providers: Default::default(),
             endpoint_interval_secs: Default::default(),
+            direct_path_latency_threshold_ms: Default::default(),
         }
     }
 }
@@ -199,6 +221,7 @@ impl FeatureDirect {
 
 /// Avoid sending periodic messages to peers with no traffic reported by wireguard
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct FeatureSkipUnresponsivePeers {
     /// Time after which peers is considered unresponsive if it didn't receive any handshakes
     #[serde(default = "FeatureSkipUnresponsivePeers::default_no_handshake_threshold_secs")]
@@ -221,6 +244,7 @@ impl Default for FeatureSkipUnresponsivePeers {
 
 /// Configure derp behaviour
 #[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct FeatureDerp {
     /// Tcp keepalive set on derp server's side [default 15s]
     pub tcp_keepalive: Option<u32>,
@@ -235,6 +259,7 @@ pub struct FeatureDerp {
 
 /// Whether to validate keys
 #[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct FeatureValidateKeys(pub bool);
 
 impl Default for FeatureValidateKeys {
@@ -246,10 +271,12 @@ impl Default for FeatureValidateKeys {
 /// Turns on connection resets upon VPN server change
 #[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
 #[serde(transparent)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct FeatureBoringtunResetConns(pub bool);
 
 /// Turns on post quantum VPN tunnel
 #[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct FeaturePostQuantumVPN {
     /// Initial handshake timeout in seconds
     #[serde(default = "FeaturePostQuantumVPN::default_handshake_timeout_s")]
@@ -285,8 +312,63 @@ where
     Ok(Some(eps))
 }
 
+/// Action taken on the meshnet config older than allowed
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum ConfigExpiryAction {
+    /// Apply the config and report `StaleConfig` event
+    #[default]
+    Warn,
+    /// Report `StaleConfig` event and keep the current config
+    Refuse,
+}
+
+/// Handling of stale meshnet configs, configs without `timestamp` are always applied
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ConfigExpiryPolicy {
+    /// Maximum age of the config, in seconds [default 24h]
+    #[serde(default = "ConfigExpiryPolicy::default_max_age_secs")]
+    pub max_age_secs: u64,
+    /// What to do with the stale config [default warn]
+    #[serde(default)]
+    pub action: ConfigExpiryAction,
+}
+
+impl ConfigExpiryPolicy {
+    const fn default_max_age_secs() -> u64 {
+        24 * 60 * 60
+    }
+}
+
+impl Default for ConfigExpiryPolicy {
+    fn default() -> Self {
+        Self {
+            max_age_secs: Self::default_max_age_secs(),
+            action: Default::default(),
+        }
+    }
+}
+
+/// Handling of the IP address assigned to more than one meshnet node
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum IpConflictPolicy {
+    /// Log the conflict and apply the config
+    DetectAndLog,
+    /// Report `DuplicateIpDetected` event and keep the current config
+    DetectAndRefuse,
+    /// Report `DuplicateIpDetected` event and apply the config without the conflicting peers.
+    /// This device keeps its own addresses, among peers the one with the lowest public key
+    /// keeps the address, so every node of the mesh leaves out the same peers.
+    DetectAndResolveViaBackoff,
+}
+
 /// Turns on the no link detection mechanism
 #[derive(Default, Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct FeatureNoLinkDetection {
     /// Configurable rtt in seconds
     pub rtt_seconds: Option<u64>,
@@ -294,6 +376,7 @@ pub struct FeatureNoLinkDetection {
 
 #[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
 /// Encompasses all of the possible features that can be enabled
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Features {
     /// Additional wireguard configuration
     #[serde(default)]
@@ -328,6 +411,24 @@ pub struct Features {
     pub boringtun_reset_connections: FeatureBoringtunResetConns,
     /// If and for how long to flush events when stopping telio. Setting to Some(0) means waiting until all events have been flushed, regardless of how long it takes
     pub flush_events_on_stop_timeout_seconds: Option<u64>,
+    /// Timeout of DNS queries proxied through magic DNS, in milliseconds (5000 if not set)
+    pub proxy_dns_query_timeout_ms: Option<u64>,
+    /// Number of connection events kept for replaying them later (256 if not set, 0 disables)
+    pub event_history_size: Option<usize>,
+    /// Policy for meshnet configs which are too old to be trusted
+    pub config_expiry_policy: Option<ConfigExpiryPolicy>,
+    /// Maximum number of meshnet peers accepted in the config (unlimited if not set)
+    pub max_peers: Option<u32>,
+    /// Number of undelivered events after which a warning is logged (not checked if not set)
+    pub event_queue_warn_threshold: Option<usize>,
+    /// Connected peers which sent nothing for this long are reported as stale, in seconds
+    /// (60 if not set)
+    pub stale_peer_threshold_secs: Option<u64>,
+    /// Handling of meshnet peers assigned the same IP address (not checked if not set)
+    pub ip_conflict_resolution: Option<IpConflictPolicy>,
+    /// Exit node failover switches to the next node after nothing was received from the
+    /// current one for this long, in seconds (30 if not set)
+    pub exit_node_failover_timeout_secs: Option<u64>,
     /// Flag to turn on post quantum VPN tunnel
     #[serde(default)]
     pub post_quantum_vpn: Option<FeaturePostQuantumVPN>,
@@ -451,6 +552,7 @@ mod tests {
                 proxying: Some(25),
                 stun: Some(50),
             },
+            psk_rotation_interval_secs: None,
         },
         nurse: Some(FeatureNurse {
             fingerprint: "fingerprint_test".to_string(),
@@ -473,6 +575,7 @@ mod tests {
             skip_unresponsive_peers: Some(FeatureSkipUnresponsivePeers {
                 no_handshake_threshold_secs: 50,
             }),
+            direct_path_latency_threshold_ms: None,
         }),
         exit_dns: Some(FeatureExitDns {
             auto_switch_dns_ips: Some(true),
@@ -490,6 +593,14 @@ mod tests {
         nicknames: true,
         boringtun_reset_connections: FeatureBoringtunResetConns(true),
         flush_events_on_stop_timeout_seconds: None,
+        proxy_dns_query_timeout_ms: None,
+        event_history_size: None,
+        config_expiry_policy: None,
+        max_peers: None,
+        event_queue_warn_threshold: None,
+        stale_peer_threshold_secs: None,
+        ip_conflict_resolution: None,
+        exit_node_failover_timeout_secs: None,
         post_quantum_vpn: Some(FeaturePostQuantumVPN {
             handshake_timeout_s: 16,
         }),
@@ -504,6 +615,7 @@ mod tests {
                 proxying: Some(25),
                 stun: Some(50),
             },
+            psk_rotation_interval_secs: None,
         },
         nurse: Some(FeatureNurse {
             fingerprint: "fingerprint_test".to_string(),
@@ -524,6 +636,7 @@ mod tests {
             providers: None,
             endpoint_interval_secs: None,
             skip_unresponsive_peers: Some(Default::default()),
+            direct_path_latency_threshold_ms: None,
         }),
         exit_dns: Some(FeatureExitDns {
             auto_switch_dns_ips: None,
@@ -536,6 +649,14 @@ mod tests {
         nicknames: false,
         boringtun_reset_connections: FeatureBoringtunResetConns(false),
         flush_events_on_stop_timeout_seconds: None,
+        proxy_dns_query_timeout_ms: None,
+        event_history_size: None,
+        config_expiry_policy: None,
+        max_peers: None,
+        event_queue_warn_threshold: None,
+        stale_peer_threshold_secs: None,
+        ip_conflict_resolution: None,
+        exit_node_failover_timeout_secs: None,
         post_quantum_vpn: None,
         no_link_detection: None,
     });
@@ -548,7 +669,8 @@ mod tests {
             "endpoint_interval_secs": 30,
             "skip_unresponsive_peers": {
                 "no_handshake_threshold_secs": 42
-            }
+            },
+            "direct_path_latency_threshold_ms": 150
         }"#;
 
         let partial_json = r#"
@@ -566,12 +688,14 @@ mod tests {
             skip_unresponsive_peers: Some(FeatureSkipUnresponsivePeers {
                 no_handshake_threshold_secs: 42,
             }),
+            direct_path_latency_threshold_ms: Some(150),
         };
 
         let partial_features = FeatureDirect {
             providers: Some(vec![EndpointProvider::Local].into_iter().collect()),
             endpoint_interval_secs: None,
             skip_unresponsive_peers: Some(Default::default()),
+            direct_path_latency_threshold_ms: None,
         };
 
         assert_eq!(from_str::<FeatureDirect>(full_json).unwrap(), full_features);
@@ -709,6 +833,14 @@ mod tests {
             nicknames: false,
             boringtun_reset_connections: Default::default(),
             flush_events_on_stop_timeout_seconds: None,
+            proxy_dns_query_timeout_ms: None,
+            event_history_size: None,
+            config_expiry_policy: None,
+            max_peers: None,
+            event_queue_warn_threshold: None,
+            stale_peer_threshold_secs: None,
+            ip_conflict_resolution: None,
+            exit_node_failover_timeout_secs: None,
             post_quantum_vpn: Default::default(),
             no_link_detection: None,
         };
@@ -737,6 +869,14 @@ mod tests {
             nicknames: false,
             boringtun_reset_connections: Default::default(),
             flush_events_on_stop_timeout_seconds: None,
+            proxy_dns_query_timeout_ms: None,
+            event_history_size: None,
+            config_expiry_policy: None,
+            max_peers: None,
+            event_queue_warn_threshold: None,
+            stale_peer_threshold_secs: None,
+            ip_conflict_resolution: None,
+            exit_node_failover_timeout_secs: None,
             post_quantum_vpn: Default::default(),
             no_link_detection: None,
         };
@@ -760,6 +900,14 @@ mod tests {
             nicknames: false,
             boringtun_reset_connections: Default::default(),
             flush_events_on_stop_timeout_seconds: None,
+            proxy_dns_query_timeout_ms: None,
+            event_history_size: None,
+            config_expiry_policy: None,
+            max_peers: None,
+            event_queue_warn_threshold: None,
+            stale_peer_threshold_secs: None,
+            ip_conflict_resolution: None,
+            exit_node_failover_timeout_secs: None,
             post_quantum_vpn: Default::default(),
             no_link_detection: None,
         };
@@ -802,6 +950,14 @@ mod tests {
             nicknames: false,
             boringtun_reset_connections: Default::default(),
             flush_events_on_stop_timeout_seconds: None,
+            proxy_dns_query_timeout_ms: None,
+            event_history_size: None,
+            config_expiry_policy: None,
+            max_peers: None,
+            event_queue_warn_threshold: None,
+            stale_peer_threshold_secs: None,
+            ip_conflict_resolution: None,
+            exit_node_failover_timeout_secs: None,
             post_quantum_vpn: Default::default(),
             no_link_detection: None,
         };
@@ -821,6 +977,14 @@ mod tests {
             nicknames: false,
             boringtun_reset_connections: Default::default(),
             flush_events_on_stop_timeout_seconds: None,
+            proxy_dns_query_timeout_ms: None,
+            event_history_size: None,
+            config_expiry_policy: None,
+            max_peers: None,
+            event_queue_warn_threshold: None,
+            stale_peer_threshold_secs: None,
+            ip_conflict_resolution: None,
+            exit_node_failover_timeout_secs: None,
             post_quantum_vpn: Default::default(),
             no_link_detection: None,
         };
@@ -864,6 +1028,14 @@ mod tests {
             nicknames: false,
             boringtun_reset_connections: Default::default(),
             flush_events_on_stop_timeout_seconds: None,
+            proxy_dns_query_timeout_ms: None,
+            event_history_size: None,
+            config_expiry_policy: None,
+            max_peers: None,
+            event_queue_warn_threshold: None,
+            stale_peer_threshold_secs: None,
+            ip_conflict_resolution: None,
+            exit_node_failover_timeout_secs: None,
             post_quantum_vpn: Default::default(),
             no_link_detection: None,
         };
@@ -871,6 +1043,19 @@ mod tests {
         assert_eq!(Features::default(), expected_defaults);
     }
 
+    #[test]
+    fn test_config_expiry_policy_defaults() {
+        let policy: ConfigExpiryPolicy = from_str("{}").unwrap();
+        assert_eq!(policy, ConfigExpiryPolicy::default());
+        assert_eq!(policy.max_age_secs, 86400);
+        assert_eq!(policy.action, ConfigExpiryAction::Warn);
+
+        let policy: ConfigExpiryPolicy =
+            from_str(r#"{"max_age_secs": 60, "action": "refuse"}"#).unwrap();
+        assert_eq!(policy.max_age_secs, 60);
+        assert_eq!(policy.action, ConfigExpiryAction::Refuse);
+    }
+
     #[test]
     fn get_paths_from_feature_paths() {
         assert_eq!(
@@ -6,7 +6,8 @@ use serde_json::{from_value, Error, Value};
 use telio_utils::Hidden;
 
 use std::{
-    net::{IpAddr, Ipv4Addr},
+    collections::BTreeMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
     ops::Deref,
 };
 
@@ -14,21 +15,27 @@ use telio_crypto::PublicKey;
 
 /// Characterstics descriping a peer
 #[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct PeerBase {
     /// 32-character identifier of the peer
     pub identifier: String,
     /// Public key of the peer
+    #[cfg_attr(feature = "schema", schemars(with = "String"))]
     pub public_key: PublicKey,
     /// Hostname of the peer
+    #[cfg_attr(feature = "schema", schemars(with = "String"))]
     pub hostname: Hidden<String>,
     /// Ip address of peer
     pub ip_addresses: Option<Vec<IpAddr>>,
     /// Nickname for the peer
     pub nickname: Option<String>,
+    /// Operating system of the peer, e.g. `linux`, `macos`, `android`, `ios` or `windows`
+    pub os: Option<String>,
 }
 
 /// Description of a peer
 #[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Peer {
     #[serde(flatten)]
     /// The base object describing a peer
@@ -44,6 +51,7 @@ pub struct Peer {
 
 /// Representation of DNS configuration
 #[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct DnsConfig {
     /// List of DNS servers
     pub dns_servers: Option<Vec<IpAddr>>,
@@ -52,6 +60,7 @@ pub struct DnsConfig {
 /// The currrent state of our connection to derp server
 #[derive(Debug, Default, Clone, PartialEq, Eq, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase")]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum RelayState {
     /// Disconnected from the Derp server
     #[default]
@@ -65,6 +74,7 @@ pub enum RelayState {
 /// Representation of a server, which might be used
 /// both as a Relay server and Stun Server
 #[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Server {
     /// Server region code
     pub region_code: String,
@@ -82,6 +92,7 @@ pub struct Server {
     #[serde(default)]
     pub stun_plaintext_port: u16,
     /// Server public key
+    #[cfg_attr(feature = "schema", schemars(with = "String"))]
     pub public_key: PublicKey,
     /// Determines in which order the client tries to connect to the derp servers
     pub weight: u32,
@@ -95,6 +106,90 @@ pub struct Server {
     pub conn_state: RelayState,
 }
 
+/// Choice of the relay server the client connects to
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "strategy", rename_all = "snake_case")]
+pub enum RelaySelectionPolicy {
+    /// Try servers in the order of their weight, assigned by the API according to expected latency
+    #[default]
+    LowestLatency,
+    /// Connect only to the server with the hostname, other servers are not tried
+    Pinned {
+        /// Hostname of the server
+        hostname: String,
+    },
+}
+
+/// Protocol of the proxy used for control-plane connections
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyType {
+    /// HTTP proxy, tunnels are opened with the `CONNECT` method
+    Http,
+    /// SOCKS5 proxy
+    Socks5,
+}
+
+/// Proxy used for control-plane connections, i.e. connections to relay servers
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ProxySettings {
+    /// Protocol of the proxy
+    #[serde(rename = "type")]
+    pub proxy_type: ProxyType,
+    /// Hostname or IP address of the proxy
+    pub host: String,
+    /// Port of the proxy
+    pub port: u16,
+    /// Username, if the proxy requires authentication
+    #[serde(default)]
+    pub username: Option<String>,
+    /// Password, if the proxy requires authentication
+    #[serde(default)]
+    pub password: Option<Hidden<String>>,
+}
+
+/// WireGuard persistent keepalive intervals of meshnet peers
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeepalivePolicy {
+    /// Interval used for peers without an override, in seconds. If None, intervals from the
+    /// `persistent_keepalive` feature are used
+    #[serde(default)]
+    pub default_secs: Option<u32>,
+    /// Intervals of specific peers
+    #[serde(default)]
+    pub overrides: Vec<KeepaliveOverride>,
+}
+
+/// Persistent keepalive interval of a single peer
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct KeepaliveOverride {
+    /// Public key of the peer
+    pub public_key: PublicKey,
+    /// Interval, in seconds
+    pub secs: u32,
+}
+
+/// Exit node in the failover list of `telio_enable_exit_node_failover`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FailoverExitNode {
+    /// Public key of the exit node
+    pub public_key: PublicKey,
+    /// Endpoint of the VPN server, None for meshnet exit nodes
+    #[serde(default)]
+    pub endpoint: Option<SocketAddr>,
+}
+
+impl KeepalivePolicy {
+    /// Interval of the peer, None if the policy does not set it
+    pub fn secs_for(&self, public_key: &PublicKey) -> Option<u32> {
+        self.overrides
+            .iter()
+            .find(|o| o.public_key == *public_key)
+            .map(|o| o.secs)
+            .or(self.default_secs)
+    }
+}
+
 impl Server {
     /// Returns the full address of the server
     pub fn get_address(&self) -> String {
@@ -106,6 +201,87 @@ impl Server {
     }
 }
 
+/// Status of a server used for STUN based endpoint discovery
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StunServerStatus {
+    /// Hostname of the server
+    pub hostname: String,
+    /// IP address of the server, IPv6 if enabled in the config and known for the server
+    pub ip: IpAddr,
+    /// Port on which server listens for unencrypted stun requests
+    pub port: u16,
+    /// Time it took for the server to respond to the last stun session (in milliseconds),
+    /// None if the server failed to respond or was not queried yet
+    pub last_response_ms: Option<u64>,
+}
+
+/// Role of a network interface seen by the local interfaces endpoint discovery
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InterfaceRole {
+    /// Interface probed for endpoint candidates
+    Primary,
+    /// Interface with an address from the meshnet range, not probed
+    Tunnel,
+}
+
+/// Network interface seen by the local interfaces endpoint discovery
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct NetworkInterface {
+    /// Name of the interface
+    pub name: String,
+    /// IPv4 address assigned to the interface
+    pub ip: IpAddr,
+    /// Whether the interface is probed or belongs to the tunnel
+    pub role: InterfaceRole,
+}
+
+/// Resolvers used by magic DNS when its forward servers do not answer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DnsFallbackConfig {
+    /// Fallback servers, used only for names outside of the meshnet
+    pub servers: Vec<IpAddr>,
+    /// Time to wait for the forward servers before the query is sent to the fallback servers
+    #[serde(default = "DnsFallbackConfig::default_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+impl DnsFallbackConfig {
+    const fn default_timeout_ms() -> u64 {
+        2000
+    }
+}
+
+/// Range magic DNS clamps TTLs of the resolved records to, zero leaves the bound unset
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DnsTtlOverride {
+    /// Records with lower TTL are cached and reported with this TTL, in seconds
+    pub min_ttl_secs: u32,
+    /// Records with higher TTL are cached and reported with this TTL, in seconds
+    pub max_ttl_secs: u32,
+}
+
+impl DnsTtlOverride {
+    /// Whether any of the bounds is set
+    pub fn is_set(&self) -> bool {
+        self.min_ttl_secs > 0 || self.max_ttl_secs > 0
+    }
+
+    /// `ttl` clamped to the set bounds
+    pub fn clamp(&self, ttl: u32) -> u32 {
+        let ttl = if self.min_ttl_secs > 0 {
+            ttl.max(self.min_ttl_secs)
+        } else {
+            ttl
+        };
+        if self.max_ttl_secs > 0 {
+            ttl.min(self.max_ttl_secs)
+        } else {
+            ttl
+        }
+    }
+}
+
 impl PartialEq for Server {
     // Ignore fields used by DerpRelay itself only
     fn eq(&self, other: &Self) -> bool {
@@ -151,6 +327,7 @@ pub struct PartialConfig {
     peers: Option<Vec<Value>>,
     derp_servers: Option<Vec<Server>>,
     dns: Option<DnsConfig>,
+    timestamp: Option<u64>,
 }
 
 impl PartialConfig {
@@ -170,6 +347,7 @@ impl PartialConfig {
                 peers,
                 derp_servers: self.derp_servers,
                 dns: self.dns,
+                timestamp: self.timestamp,
             },
             failures,
         )
@@ -179,6 +357,7 @@ impl PartialConfig {
 /// Rust representation of [meshnet map]
 /// A network map of all the Peers and the servers
 #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Config {
     #[serde(flatten)]
     /// Description of the local peer
@@ -189,6 +368,8 @@ pub struct Config {
     pub derp_servers: Option<Vec<Server>>,
     /// Dns configuration
     pub dns: Option<DnsConfig>,
+    /// Unix timestamp of when the config was issued by the backend, in seconds
+    pub timestamp: Option<u64>,
 }
 
 impl Deref for Peer {
@@ -207,6 +388,45 @@ impl Deref for Config {
     }
 }
 
+/// Public keys of peers which differ between two configs, each list is sorted
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ConfigDiff {
+    /// Peers present only in the new config
+    pub added: Vec<PublicKey>,
+    /// Peers present only in the old config
+    pub removed: Vec<PublicKey>,
+    /// Peers present in both configs with any field changed
+    pub modified: Vec<PublicKey>,
+}
+
+/// Compare peers of two configs, peers are matched by their public keys
+pub fn diff_configs(old: &Config, new: &Config) -> ConfigDiff {
+    let peers = |cfg: &Config| -> BTreeMap<PublicKey, Peer> {
+        cfg.peers
+            .iter()
+            .flatten()
+            .map(|p| (p.public_key, p.clone()))
+            .collect()
+    };
+    let old_peers = peers(old);
+    let new_peers = peers(new);
+
+    let mut diff = ConfigDiff::default();
+    for (public_key, new_peer) in &new_peers {
+        match old_peers.get(public_key) {
+            None => diff.added.push(*public_key),
+            Some(old_peer) if old_peer != new_peer => diff.modified.push(*public_key),
+            Some(_) => (),
+        }
+    }
+    diff.removed = old_peers
+        .keys()
+        .filter(|public_key| !new_peers.contains_key(public_key))
+        .copied()
+        .collect();
+    diff
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -335,6 +555,7 @@ mod tests {
                 hostname: telio_utils::Hidden("everest-alice.nord".to_owned()),
                 ip_addresses: Some(vec!["198.51.100.42".parse().unwrap()]),
                 nickname: Some("bunnyg".to_owned()),
+                os: Some("android".to_owned()),
             },
             peers: Some(vec![
                 Peer {
@@ -346,6 +567,7 @@ mod tests {
                         hostname: telio_utils::Hidden("everest-bob.nord".to_owned()),
                         ip_addresses: Some(vec!["198.51.100.43".parse().unwrap()]),
                         nickname: Some("".to_owned()),
+                        os: Some("android".to_owned()),
                     },
                     is_local: true,
                     allow_incoming_connections: true,
@@ -360,6 +582,7 @@ mod tests {
                         hostname: telio_utils::Hidden("everest-alice.nord".to_owned()),
                         ip_addresses: Some(vec!["198.51.100.43".parse().unwrap()]),
                         nickname: None,
+                        os: Some("android".to_owned()),
                     },
                     is_local: false,
                     allow_incoming_connections: false,
@@ -384,6 +607,7 @@ mod tests {
             dns: Some(DnsConfig {
                 dns_servers: Some(vec!["1.1.1.1".parse().unwrap()]),
             }),
+            timestamp: None,
         };
 
         let partial_config: PartialConfig = from_str(json).unwrap();
@@ -392,4 +616,86 @@ mod tests {
         assert_eq!(peer_deserialization_failures.len(), 3);
         assert_eq!(full_config, expected_config);
     }
+
+    #[test]
+    fn keepalive_policy_overrides_default() {
+        let policy: KeepalivePolicy = from_str(
+            r#"{"default_secs":25,"overrides":[{"public_key":"LRrbraNJXOrVdnpXy6gA/XcpmxymE0oMZlzP5Pqi20I=","secs":120}]}"#,
+        )
+        .unwrap();
+        let overridden: PublicKey = "LRrbraNJXOrVdnpXy6gA/XcpmxymE0oMZlzP5Pqi20I="
+            .parse()
+            .unwrap();
+        let other = PublicKey([1; 32]);
+
+        assert_eq!(policy.secs_for(&overridden), Some(120));
+        assert_eq!(policy.secs_for(&other), Some(25));
+        assert_eq!(
+            from_str::<KeepalivePolicy>("{}").unwrap().secs_for(&other),
+            None
+        );
+    }
+
+    #[test]
+    fn deserialize_proxy_settings() {
+        let proxy: ProxySettings = from_str(
+            r#"{"type":"socks5","host":"proxy.corp.com","port":1080,"username":"user","password":"pass"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            proxy,
+            ProxySettings {
+                proxy_type: ProxyType::Socks5,
+                host: "proxy.corp.com".to_owned(),
+                port: 1080,
+                username: Some("user".to_owned()),
+                password: Some(Hidden("pass".to_owned())),
+            }
+        );
+
+        let proxy: ProxySettings =
+            from_str(r#"{"type":"http","host":"10.0.0.1","port":3128}"#).unwrap();
+        assert_eq!(proxy.proxy_type, ProxyType::Http);
+        assert_eq!(proxy.username, None);
+        assert!(from_str::<ProxySettings>(r#"{"type":"socks4","host":"a","port":1}"#).is_err());
+    }
+
+    #[test]
+    fn diff_peers_of_configs() {
+        let peer = |key: u8, nickname: &str| Peer {
+            base: PeerBase {
+                public_key: PublicKey([key; 32]),
+                nickname: Some(nickname.to_owned()),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let old = Config {
+            peers: Some(vec![peer(1, "a"), peer(2, "b"), peer(3, "c")]),
+            ..Default::default()
+        };
+        let new = Config {
+            peers: Some(vec![peer(4, "d"), peer(3, "c"), peer(2, "renamed")]),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            diff_configs(&old, &new),
+            ConfigDiff {
+                added: vec![PublicKey([4; 32])],
+                removed: vec![PublicKey([1; 32])],
+                modified: vec![PublicKey([2; 32])],
+            }
+        );
+        assert_eq!(diff_configs(&old, &old), ConfigDiff::default());
+        assert_eq!(
+            serde_json::to_string(&diff_configs(&Config::default(), &old)).unwrap(),
+            format!(
+                r#"{{"added":["{}","{}","{}"],"removed":[],"modified":[]}}"#,
+                PublicKey([1; 32]),
+                PublicKey([2; 32]),
+                PublicKey([3; 32])
+            )
+        );
+    }
 }
@@ -58,6 +58,124 @@ pub struct Error {
     msg: EventMsg,
 }
 
+/// Key rotation event. Used to inform the upper layer that it must re-register the device
+/// with the new public key.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct KeyRotated {
+    /// Base64 encoded public key which was used before the rotation
+    pub old_public_key: String,
+    /// Base64 encoded public key which is used after the rotation
+    pub new_public_key: String,
+}
+
+/// Pre-shared key rotation event. Used to inform the upper layer that a new pre-shared key
+/// was agreed with the meshnet peer and applied to its WireGuard configuration.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct PskRotated {
+    /// Base64 encoded public key of the peer
+    pub peer_pk: String,
+}
+
+/// Peer wake request event. Used to inform the upper layer that a meshnet peer asked this
+/// device to wake up, e.g. because it has data to send.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct PeerWakeRequest {
+    /// Base64 encoded public key of the peer which sent the request
+    pub from_pk: String,
+}
+
+/// Stale config event. Used to warn the upper layer that the meshnet config is older than
+/// allowed by `config_expiry_policy` feature, e.g. the device was offline for a long time.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct StaleConfig {
+    /// Unix timestamp of when the config was issued, in seconds
+    pub timestamp: u64,
+    /// Age of the config when it was set, in seconds
+    pub age_secs: u64,
+    /// Whether the config was applied regardless of its age
+    pub applied: bool,
+}
+
+/// Duplicate IP event. Used to report that the meshnet config assigns the same IP address to
+/// more than one node, according to `ip_conflict_resolution` feature.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct DuplicateIpDetected {
+    /// Base64 encoded public key of the conflicting peer
+    pub peer_pk: String,
+    /// IP address assigned to the peer and another node
+    pub ip_address: String,
+    /// Whether the config was applied without the conflicting peer
+    pub applied: bool,
+}
+
+/// IP reassignment event. Used to ask the upper layer to request a new meshnet IP address
+/// from the backend and re-fetch the meshnet config, e.g. because the assigned IP conflicts
+/// with another node.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct IpReassignmentRequested {}
+
+/// Exit node failover event. Used to report that the exit node was switched automatically,
+/// because nothing was received from the previous one for too long.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ExitNodeFailover {
+    /// Base64 encoded public key of the unreachable exit node
+    pub from_pk: String,
+    /// Base64 encoded public key of the exit node connected instead
+    pub to_pk: String,
+}
+
+/// Exit node connection event. Used to report that the exit node connected with
+/// `telio_connect_to_exit_node_with_retry` completed a handshake.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ExitNodeConnected {
+    /// Base64 encoded public key of the exit node
+    pub peer_pk: String,
+    /// Number of connection attempts made
+    pub attempts: u32,
+}
+
+/// Exit node connection failure event. Used to report that no handshake with the exit node
+/// connected with `telio_connect_to_exit_node_with_retry` succeeded after the last attempt.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ExitNodeConnectionFailed {
+    /// Base64 encoded public key of the exit node
+    pub peer_pk: String,
+    /// Number of connection attempts made
+    pub attempts: u32,
+}
+
+/// Clock skew event. Used to report that the local clock differs from the clock of the relay
+/// server so much, that WireGuard handshakes may fail.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ClockSkewWarning {
+    /// Estimated difference of the clocks in milliseconds, positive if the local clock is ahead
+    pub skew_ms: i64,
+}
+
+/// Startup event. Used to report the result of starting the device with `telio_start_async`.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct StartupComplete {
+    /// Whether the device was started
+    pub success: bool,
+    /// Reason of the failure, if the device was not started
+    pub error: Option<String>,
+}
+
+/// Identity verification event. Used to report the result of the challenge sent with
+/// `telio_meshnet_send_auth_challenge`.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct AuthChallengeResult {
+    /// Base64 encoded public key of the challenged peer
+    pub peer_pk: String,
+    /// Whether the peer signed the challenge with the private key of its public key
+    pub verified: bool,
+}
+
+/// Shutdown event. Last event reported before the device is destroyed, the event callback
+/// must not be used by `libtelio` after it.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct ShuttingDown {}
+
 /// Used for the constructing `Event` object.
 /// Adding another `Event` type, that type should implement this trait,
 /// for the ability to be constructed, but not used outside of this module.
@@ -84,6 +202,84 @@ impl MakeEvent for Node {
     }
 }
 
+impl MakeEvent for KeyRotated {
+    fn make() -> Event {
+        Event::KeyRotated { body: None }
+    }
+}
+
+impl MakeEvent for PskRotated {
+    fn make() -> Event {
+        Event::PskRotated { body: None }
+    }
+}
+
+impl MakeEvent for PeerWakeRequest {
+    fn make() -> Event {
+        Event::PeerWakeRequest { body: None }
+    }
+}
+
+impl MakeEvent for StaleConfig {
+    fn make() -> Event {
+        Event::StaleConfig { body: None }
+    }
+}
+
+impl MakeEvent for DuplicateIpDetected {
+    fn make() -> Event {
+        Event::DuplicateIpDetected { body: None }
+    }
+}
+
+impl MakeEvent for IpReassignmentRequested {
+    fn make() -> Event {
+        Event::IpReassignmentRequested { body: None }
+    }
+}
+
+impl MakeEvent for ExitNodeFailover {
+    fn make() -> Event {
+        Event::ExitNodeFailover { body: None }
+    }
+}
+
+impl MakeEvent for ExitNodeConnected {
+    fn make() -> Event {
+        Event::ExitNodeConnected { body: None }
+    }
+}
+
+impl MakeEvent for ExitNodeConnectionFailed {
+    fn make() -> Event {
+        Event::ExitNodeConnectionFailed { body: None }
+    }
+}
+
+impl MakeEvent for ClockSkewWarning {
+    fn make() -> Event {
+        Event::ClockSkewWarning { body: None }
+    }
+}
+
+impl MakeEvent for StartupComplete {
+    fn make() -> Event {
+        Event::StartupComplete { body: None }
+    }
+}
+
+impl MakeEvent for AuthChallengeResult {
+    fn make() -> Event {
+        Event::AuthChallengeResult { body: None }
+    }
+}
+
+impl MakeEvent for ShuttingDown {
+    fn make() -> Event {
+        Event::ShuttingDown { body: None }
+    }
+}
+
 /// Main object of `Event`. See `Event::new()` for init options.
 #[derive(Clone, Debug, Serialize)]
 #[serde(tag = "type")]
@@ -105,6 +301,71 @@ pub enum Event {
         /// Error type event
         body: Option<Error>,
     },
+    /// Used to report the device key rotation
+    KeyRotated {
+        /// Key rotation type event
+        body: Option<KeyRotated>,
+    },
+    /// Used to report the pre-shared key rotation with a meshnet peer
+    PskRotated {
+        /// Pre-shared key rotation type event
+        body: Option<PskRotated>,
+    },
+    /// Used to report that a meshnet peer asked to wake up
+    PeerWakeRequest {
+        /// Peer wake request type event
+        body: Option<PeerWakeRequest>,
+    },
+    /// Used to report that the meshnet config is too old
+    StaleConfig {
+        /// Stale config type event
+        body: Option<StaleConfig>,
+    },
+    /// Used to report that the meshnet config has conflicting IP addresses
+    DuplicateIpDetected {
+        /// Duplicate IP type event
+        body: Option<DuplicateIpDetected>,
+    },
+    /// Used to ask for a new meshnet IP assignment
+    IpReassignmentRequested {
+        /// IP reassignment type event
+        body: Option<IpReassignmentRequested>,
+    },
+    /// Used to report automatic switch of the exit node
+    ExitNodeFailover {
+        /// Exit node failover type event
+        body: Option<ExitNodeFailover>,
+    },
+    /// Used to report that the exit node connected with retries is reachable
+    ExitNodeConnected {
+        /// Exit node connection type event
+        body: Option<ExitNodeConnected>,
+    },
+    /// Used to report that the exit node connected with retries is not reachable
+    ExitNodeConnectionFailed {
+        /// Exit node connection failure type event
+        body: Option<ExitNodeConnectionFailed>,
+    },
+    /// Used to report that the local clock differs too much from the relay server clock
+    ClockSkewWarning {
+        /// Clock skew type event
+        body: Option<ClockSkewWarning>,
+    },
+    /// Used to report that the device started in the background finished starting
+    StartupComplete {
+        /// Startup type event
+        body: Option<StartupComplete>,
+    },
+    /// Used to report the result of the peer identity verification
+    AuthChallengeResult {
+        /// Identity verification type event
+        body: Option<AuthChallengeResult>,
+    },
+    /// Used to report that the device is being destroyed
+    ShuttingDown {
+        /// Shutdown type event
+        body: Option<ShuttingDown>,
+    },
 }
 
 impl Event {
@@ -154,6 +415,110 @@ impl Modifier<Event> for Node {
     }
 }
 
+impl Modifier<Event> for KeyRotated {
+    fn modify(self, res: &mut Event) {
+        if let Event::KeyRotated { body } = res {
+            *body = Some(self);
+        }
+    }
+}
+
+impl Modifier<Event> for PskRotated {
+    fn modify(self, res: &mut Event) {
+        if let Event::PskRotated { body } = res {
+            *body = Some(self);
+        }
+    }
+}
+
+impl Modifier<Event> for PeerWakeRequest {
+    fn modify(self, res: &mut Event) {
+        if let Event::PeerWakeRequest { body } = res {
+            *body = Some(self);
+        }
+    }
+}
+
+impl Modifier<Event> for StaleConfig {
+    fn modify(self, res: &mut Event) {
+        if let Event::StaleConfig { body } = res {
+            *body = Some(self);
+        }
+    }
+}
+
+impl Modifier<Event> for DuplicateIpDetected {
+    fn modify(self, res: &mut Event) {
+        if let Event::DuplicateIpDetected { body } = res {
+            *body = Some(self);
+        }
+    }
+}
+
+impl Modifier<Event> for IpReassignmentRequested {
+    fn modify(self, res: &mut Event) {
+        if let Event::IpReassignmentRequested { body } = res {
+            *body = Some(self);
+        }
+    }
+}
+
+impl Modifier<Event> for ExitNodeFailover {
+    fn modify(self, res: &mut Event) {
+        if let Event::ExitNodeFailover { body } = res {
+            *body = Some(self);
+        }
+    }
+}
+
+impl Modifier<Event> for ExitNodeConnected {
+    fn modify(self, res: &mut Event) {
+        if let Event::ExitNodeConnected { body } = res {
+            *body = Some(self);
+        }
+    }
+}
+
+impl Modifier<Event> for ExitNodeConnectionFailed {
+    fn modify(self, res: &mut Event) {
+        if let Event::ExitNodeConnectionFailed { body } = res {
+            *body = Some(self);
+        }
+    }
+}
+
+impl Modifier<Event> for ClockSkewWarning {
+    fn modify(self, res: &mut Event) {
+        if let Event::ClockSkewWarning { body } = res {
+            *body = Some(self);
+        }
+    }
+}
+
+impl Modifier<Event> for StartupComplete {
+    fn modify(self, res: &mut Event) {
+        if let Event::StartupComplete { body } = res {
+            *body = Some(self);
+        }
+    }
+}
+
+impl Modifier<Event> for AuthChallengeResult {
+    fn modify(self, res: &mut Event) {
+        if let Event::AuthChallengeResult { body } = res {
+            *body = Some(self);
+        }
+    }
+}
+
+impl Modifier<Event> for ShuttingDown {
+    fn modify(self, res: &mut Event) {
+        if let Event::ShuttingDown { body } = res {
+            *body = Some(self);
+        }
+    }
+}
+
 impl Modifier<Event> for ErrorLevel {
     fn modify(self, res: &mut Event) {
         if let Event::Error { body } = res {
@@ -217,8 +582,10 @@ mod tests {
             identifier: "f2b18d10-82ed-49a3-8b50-3356685ec5fa".to_owned(),
             public_key: PublicKey([1_u8; KEY_SIZE]),
             nickname: Some(String::from("alpha")),
+            os: Some(String::from("linux")),
             state: NodeState::Connected,
             link_state: Some(LinkState::Up),
+            is_stale: false,
             is_exit: true,
             is_vpn: true,
             ip_addresses: Vec::from(["127.0.0.1".parse().unwrap()]),
@@ -270,8 +637,8 @@ mod tests {
             r#"{"type":"node","#,
             r#""body":"#,
             r#"{"identifier":"f2b18d10-82ed-49a3-8b50-3356685ec5fa","#,
-            r#""public_key":"AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=","nickname":"alpha","#,
-            r#""state":"connected","link_state":"up","#,
+            r#""public_key":"AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=","nickname":"alpha","os":"linux","#,
+            r#""state":"connected","link_state":"up","is_stale":false,"#,
             r#""is_exit":true,"is_vpn":true,"ip_addresses":["127.0.0.1"],"allowed_ips":["127.0.0.1/32"],"#,
             r#""endpoint":"127.0.0.1:8080","hostname":"example.com","#,
             r#""allow_incoming_connections":false,"#,
@@ -280,6 +647,59 @@ mod tests {
             r#"}}"#
         ));
 
+        let key_rotated_json = String::from(concat!(
+            r#"{"type":"keyrotated","#,
+            r#""body":"#,
+            r#"{"old_public_key":"AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=","#,
+            r#""new_public_key":"AgICAgICAgICAgICAgICAgICAgICAgICAgICAgICAgI=""#,
+            r#"}}"#
+        ));
+
+        let stale_config_json = String::from(
+            r#"{"type":"staleconfig","body":{"timestamp":100,"age_secs":90000,"applied":true}}"#,
+        );
+
+        let duplicate_ip_json = String::from(concat!(
+            r#"{"type":"duplicateipdetected","body":{"#,
+            r#""peer_pk":"AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=","#,
+            r#""ip_address":"100.64.0.2","applied":false"#,
+            r#"}}"#
+        ));
+
+        let ip_reassignment_json = String::from(r#"{"type":"ipreassignmentrequested","body":{}}"#);
+
+        let exit_node_failover_json = String::from(concat!(
+            r#"{"type":"exitnodefailover","body":{"#,
+            r#""from_pk":"AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=","#,
+            r#""to_pk":"AgICAgICAgICAgICAgICAgICAgICAgICAgICAgICAgI=""#,
+            r#"}}"#
+        ));
+
+        let clock_skew_warning_json =
+            String::from(r#"{"type":"clockskewwarning","body":{"skew_ms":-120400}}"#);
+
+        let startup_complete_json = String::from(
+            r#"{"type":"startupcomplete","body":{"success":false,"error":"Adapter error"}}"#,
+        );
+
+        let auth_challenge_result_json = String::from(
+            r#"{"type":"authchallengeresult","body":{"peer_pk":"AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=","verified":true}}"#,
+        );
+
+        let exit_node_connection_failed_json = String::from(
+            r#"{"type":"exitnodeconnectionfailed","body":{"peer_pk":"AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE=","attempts":5}}"#,
+        );
+
+        let shutting_down_json = String::from(r#"{"type":"shuttingdown","body":{}}"#);
+
+        let psk_rotated_json = String::from(
+            r#"{"type":"pskrotated","body":{"peer_pk":"AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE="}}"#,
+        );
+
+        let peer_wake_request_json = String::from(
+            r#"{"type":"peerwakerequest","body":{"from_pk":"AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQE="}}"#,
+        );
+
         let err_event = Event::new::<EventError>()
             .set(EventMsg::from("big_error"))
             .set(ErrorCode::Unknown)
@@ -289,8 +709,115 @@ mod tests {
 
         let node_event = Event::new::<Node>().set(node);
 
+        let key_rotated_event = Event::new::<KeyRotated>().set(KeyRotated {
+            old_public_key: PublicKey([1_u8; KEY_SIZE]).to_string(),
+            new_public_key: PublicKey([2_u8; KEY_SIZE]).to_string(),
+        });
+
         assert_eq!(err_json, err_event.to_json().unwrap());
         assert_eq!(conn_json, conn_event.to_json().unwrap());
         assert_eq!(node_json, node_event.to_json().unwrap());
+        assert_eq!(key_rotated_json, key_rotated_event.to_json().unwrap());
+        assert_eq!(
+            stale_config_json,
+            Event::new::<StaleConfig>()
+                .set(StaleConfig {
+                    timestamp: 100,
+                    age_secs: 90000,
+                    applied: true,
+                })
+                .to_json()
+                .unwrap()
+        );
+        assert_eq!(
+            duplicate_ip_json,
+            Event::new::<DuplicateIpDetected>()
+                .set(DuplicateIpDetected {
+                    peer_pk: PublicKey([1_u8; KEY_SIZE]).to_string(),
+                    ip_address: "100.64.0.2".to_owned(),
+                    applied: false,
+                })
+                .to_json()
+                .unwrap()
+        );
+        assert_eq!(
+            ip_reassignment_json,
+            Event::new::<IpReassignmentRequested>()
+                .set(IpReassignmentRequested {})
+                .to_json()
+                .unwrap()
+        );
+        assert_eq!(
+            exit_node_failover_json,
+            Event::new::<ExitNodeFailover>()
+                .set(ExitNodeFailover {
+                    from_pk: PublicKey([1_u8; KEY_SIZE]).to_string(),
+                    to_pk: PublicKey([2_u8; KEY_SIZE]).to_string(),
+                })
+                .to_json()
+                .unwrap()
+        );
+        assert_eq!(
+            clock_skew_warning_json,
+            Event::new::<ClockSkewWarning>()
+                .set(ClockSkewWarning { skew_ms: -120400 })
+                .to_json()
+                .unwrap()
+        );
+        assert_eq!(
+            startup_complete_json,
+            Event::new::<StartupComplete>()
+                .set(StartupComplete {
+                    success: false,
+                    error: Some("Adapter error".to_owned()),
+                })
+                .to_json()
+                .unwrap()
+        );
+        assert_eq!(
+            auth_challenge_result_json,
+            Event::new::<AuthChallengeResult>()
+                .set(AuthChallengeResult {
+                    peer_pk: PublicKey([1_u8; KEY_SIZE]).to_string(),
+                    verified: true,
+                })
+                .to_json()
+                .unwrap()
+        );
+        assert_eq!(
+            exit_node_connection_failed_json,
+            Event::new::<ExitNodeConnectionFailed>()
+                .set(ExitNodeConnectionFailed {
+                    peer_pk: PublicKey([1_u8; KEY_SIZE]).to_string(),
+                    attempts: 5,
+                })
+                .to_json()
+                .unwrap()
+        );
+        assert_eq!(
+            shutting_down_json,
+            Event::new::<ShuttingDown>()
+                .set(ShuttingDown {})
+                .to_json()
+                .unwrap()
+        );
+        assert_eq!(
+            psk_rotated_json,
+            Event::new::<PskRotated>()
+                .set(PskRotated {
+                    peer_pk: PublicKey([1_u8; KEY_SIZE]).to_string(),
+                })
+                .to_json()
+                .unwrap()
+        );
+        assert_eq!(
+            peer_wake_request_json,
+            Event::new::<PeerWakeRequest>()
+                .set(PeerWakeRequest {
+                    from_pk: PublicKey([1_u8; KEY_SIZE]).to_string(),
+                })
+                .to_json()
+                .unwrap()
+        );
     }
 }
@@ -0,0 +1,89 @@
+//! Liveness report of the libtelio components
+
+use serde::Serialize;
+
+/// Health of a single libtelio component
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ComponentHealth {
+    /// Component responded in time and is operational
+    #[default]
+    Ok,
+    /// Component did not respond in time or is not operational
+    Degraded,
+    /// Component is not running, because it was not enabled
+    Disabled,
+}
+
+/// Health of every component probed by the health check
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct HealthComponents {
+    /// WireGuard adapter
+    pub wireguard: ComponentHealth,
+    /// DERP relay client
+    pub relay: ComponentHealth,
+    /// Magic DNS resolver
+    pub dns: ComponentHealth,
+    /// Direct path discovery (endpoint providers)
+    pub direct_path: ComponentHealth,
+}
+
+/// Liveness report of the running libtelio device
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct HealthReport {
+    /// True when none of the components is degraded
+    pub healthy: bool,
+    /// Health of each component
+    pub components: HealthComponents,
+}
+
+impl From<HealthComponents> for HealthReport {
+    fn from(components: HealthComponents) -> Self {
+        let healthy = [
+            components.wireguard,
+            components.relay,
+            components.dns,
+            components.direct_path,
+        ]
+        .iter()
+        .all(|c| *c != ComponentHealth::Degraded);
+
+        Self {
+            healthy,
+            components,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn health_report_to_json() {
+        let report = HealthReport::from(HealthComponents {
+            wireguard: ComponentHealth::Ok,
+            relay: ComponentHealth::Ok,
+            dns: ComponentHealth::Disabled,
+            direct_path: ComponentHealth::Degraded,
+        });
+
+        assert!(!report.healthy);
+        assert_eq!(
+            serde_json::to_string(&report).unwrap(),
+            r#"{"healthy":false,"components":{"wireguard":"ok","relay":"ok","dns":"disabled","direct_path":"degraded"}}"#
+        );
+    }
+
+    #[test]
+    fn disabled_components_are_healthy() {
+        let report = HealthReport::from(HealthComponents {
+            wireguard: ComponentHealth::Ok,
+            relay: ComponentHealth::Disabled,
+            dns: ComponentHealth::Disabled,
+            direct_path: ComponentHealth::Disabled,
+        });
+
+        assert!(report.healthy);
+    }
+}
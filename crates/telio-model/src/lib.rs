@@ -3,7 +3,10 @@
 pub mod api_config;
 pub mod config;
 pub mod event;
+pub mod health;
 pub mod mesh;
+#[cfg(feature = "schema")]
+pub mod schema;
 pub mod validation;
 
 pub use std::collections::HashMap;
@@ -21,7 +21,7 @@ pub enum Error {
 }
 
 /// Stack in-use by node
-#[derive(Clone, Default, PartialEq, Eq)]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
 pub enum IpStack {
     /// Node can be reached only through IPv4 address
     #[default]
@@ -32,6 +32,17 @@ pub enum IpStack {
     IPv4v6,
 }
 
+impl IpStack {
+    /// Name of the stack as reported to the library users
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IpStack::IPv4 => "ipv4_only",
+            IpStack::IPv6 => "ipv6_only",
+            IpStack::IPv4v6 => "dual_stack",
+        }
+    }
+}
+
 /// Description of a Node
 #[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
 pub struct Node {
@@ -42,10 +53,15 @@ pub struct Node {
     pub public_key: PublicKey,
     /// Nickname for the peer
     pub nickname: Option<String>,
+    /// Operating system of the peer, as reported in the meshnet config
+    pub os: Option<String>,
     /// State of the node (Connecting, connected, or disconnected)
     pub state: NodeState,
     /// Hint of the link state based on last rx timestamp (Up, down)
     pub link_state: Option<LinkState>,
+    /// Node is connected, but nothing was received from it for longer than
+    /// `stale_peer_threshold_secs` feature
+    pub is_stale: bool,
     /// Is the node exit node
     pub is_exit: bool,
     /// Is the node is a vpn server.
@@ -66,6 +82,119 @@ pub struct Node {
     pub path: PathType,
 }
 
+/// Features supported by a meshnet peer, as advertised by the peer itself
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct PeerCapabilities {
+    /// Peer can establish direct connections
+    pub direct_path: bool,
+    /// Peer accepts application messages
+    pub custom_messages: bool,
+    /// Peer rotates WireGuard pre-shared keys
+    pub psk_rotation: bool,
+    /// Peer advertises its telio version
+    pub version_advertisement: bool,
+}
+
+/// Kind of a known endpoint of a meshnet peer
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PeerEndpointType {
+    /// Endpoint found from the addresses of the local network interfaces
+    Local,
+    /// Endpoint found from the address reflected by a STUN server
+    Stun,
+    /// Endpoint found from a port mapped on the router with UPnP
+    Upnp,
+    /// Connection through the relay server
+    Relay,
+}
+
+/// Known endpoint of a meshnet peer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PeerEndpoint {
+    /// Socket address of the peer, `relay` for the relay connection
+    pub endpoint: String,
+    /// How the endpoint was found
+    #[serde(rename = "type")]
+    pub endpoint_type: PeerEndpointType,
+    /// Seconds since anything was last received through the endpoint, `None` if unknown
+    pub last_seen_secs: Option<u64>,
+}
+
+/// Node of the meshnet topology graph
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TopologyNode {
+    /// Public key of the node
+    pub pk: PublicKey,
+    /// Meshnet IP address of the node
+    pub ip: Option<IpAddr>,
+}
+
+/// Connection between two nodes of the meshnet topology graph
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TopologyEdge {
+    /// Public key of the node which reported the connection
+    pub from: PublicKey,
+    /// Public key of the connected node
+    pub to: PublicKey,
+    /// Path of the connection
+    pub path: PathType,
+    /// Latest round trip time in milliseconds, `None` if not measured
+    pub rtt_ms: Option<u64>,
+}
+
+/// Graph of the meshnet nodes and connections between them
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct MeshTopology {
+    /// This node followed by the meshnet peers
+    pub nodes: Vec<TopologyNode>,
+    /// Connections of this node to the connected peers
+    pub edges: Vec<TopologyEdge>,
+}
+
+/// Routing decision for traffic to an IP address reachable through the tunnel
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PeerRoute {
+    /// Destination IP address
+    pub ip: IpAddr,
+    /// Public key of the peer the traffic is sent to
+    pub peer_pk: PublicKey,
+    /// Path used to reach the peer
+    pub path: PathType,
+    /// Traffic is routed through the exit node, as no narrower route matched
+    pub is_exit: bool,
+    /// Name of the tunnel interface, `None` if the adapter picked the default one
+    pub interface: Option<String>,
+}
+
+/// Reason why telio routes the prefix to the tunnel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteSource {
+    /// Traffic sent through the exit node
+    ExitNode,
+    /// Addresses of the meshnet peer
+    Meshnet,
+}
+
+/// Route to the tunnel interface managed by telio
+///
+/// Routes are the allowed IPs of the peers configured on the WireGuard adapter, the adapter sends
+/// the traffic to the peer with the longest matching prefix.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ManagedRoute {
+    /// Destination network
+    pub prefix: IpNetwork,
+    /// Next hop, always `None` as the traffic is sent directly to the interface
+    pub gateway: Option<IpAddr>,
+    /// Name of the tunnel interface, `None` if the adapter picked the default one
+    pub interface: Option<String>,
+    /// Route metric, always `None` as the adapter picks the route by the prefix length
+    pub metric: Option<u32>,
+    /// Reason of the route
+    pub source: RouteSource,
+}
+
 /// Description of the Exit Node
 /// It is the gateway node to the internet
 #[derive(Debug, Default, Clone, Serialize)]
@@ -81,6 +210,27 @@ pub struct ExitNode {
     pub endpoint: Option<SocketAddr>,
 }
 
+/// Group of meshnet peers sharing the same routing policy
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerGroup {
+    /// Identifier of the group, unique within the device
+    pub id: String,
+    /// Public keys of the group members, may include the device itself
+    pub members: Vec<PublicKey>,
+    /// Policy applied to the group members
+    #[serde(default)]
+    pub policy: PeerGroupPolicy,
+}
+
+/// Routing policy of the peer group
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PeerGroupPolicy {
+    /// Meshnet peer used as an exit node by the group members
+    pub exit_node: Option<PublicKey>,
+    /// MTU to be configured on the tunnel interface of the group members
+    pub mtu: Option<u16>,
+}
+
 /// Connection state of the node
 #[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Deserialize, Serialize)]
 #[serde(rename_all = "lowercase")]
@@ -121,6 +271,7 @@ impl From<&PeerBase> for Node {
             identifier: peer.identifier.clone(),
             public_key: peer.public_key,
             nickname: peer.nickname.clone(),
+            os: peer.os.clone(),
             allowed_ips: peer
                 .ip_addresses
                 .as_ref()
@@ -137,6 +288,7 @@ impl From<&Peer> for Node {
         Self {
             public_key: peer.public_key,
             nickname: peer.nickname.clone(),
+            os: peer.os.clone(),
             allowed_ips: peer
                 .ip_addresses
                 .as_ref()
@@ -0,0 +1,33 @@
+//! JSON schemas of the configs accepted by the API, only built with `schema` feature
+
+use schemars::schema_for;
+
+use crate::{api_config::Features, config::Config};
+
+/// JSON schema of [Features]
+pub fn features() -> String {
+    // Serializing a schema cannot fail
+    serde_json::to_string_pretty(&schema_for!(Features)).unwrap_or_default()
+}
+
+/// JSON schema of the meshnet [Config]
+pub fn meshnet() -> String {
+    serde_json::to_string_pretty(&schema_for!(Config)).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::Value;
+
+    #[test]
+    fn schemas_describe_config_fields() {
+        let features: Value = serde_json::from_str(&features()).unwrap();
+        assert!(features["properties"]["ip_conflict_resolution"].is_object());
+        assert!(features["definitions"]["EndpointProvider"].is_object());
+
+        let meshnet: Value = serde_json::from_str(&meshnet()).unwrap();
+        assert_eq!(meshnet["properties"]["public_key"]["type"], "string");
+        assert!(meshnet["properties"]["peers"].is_object());
+    }
+}
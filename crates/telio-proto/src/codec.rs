@@ -15,6 +15,14 @@ pub enum Error {
     /// Encoder received malformed data.
     #[error("Failed to encode message")]
     Encode,
+    /// Encoded message does not fit into a single packet.
+    #[error("Encoded message is too large: expected at most {expected} bytes, got {got}")]
+    PacketTooLarge {
+        /// Maximum allowed packet size
+        expected: usize,
+        /// Size of the encoded packet
+        got: usize,
+    },
     /// Encryption failed
     #[error("Encryption failed: {0}")]
     EncryptionFailed(String),
@@ -5,6 +5,10 @@ use crate::{Codec, CodecError, CodecResult};
 use telio_crypto::PublicKey;
 
 pub use relayed::{
+    application::{ApplicationMsg, MAX_APPLICATION_PAYLOAD_SIZE},
+    auth_challenge::{AuthChallengeMsg, AUTH_NONCE_SIZE},
+    broadcast::BroadcastMsg,
+    capability::CapabilityMsg,
     data::DataMsg,
     generation::Generation,
     natter::CallMeMaybeMsg,
@@ -13,7 +17,9 @@ pub use relayed::{
     pinger::PingerMsg,
     pinger::Timestamp,
     pinger::{PartialPongerMsg, PlaintextPongerMsg},
+    psk_rotate::{PskRotateKind, PskRotateMsg},
     upgrade::UpgradeMsg,
+    wake::WakeMsg,
 };
 
 pub use control::derppoll::{DerpPollRequestMsg, DerpPollResponseMsg, PeersStatesMap};
@@ -78,6 +84,18 @@ pub enum PacketTypeRelayed {
     Upgrade = 0x08,
     /// Ponger packet
     Ponger = 0x09,
+    /// Opaque application-layer message
+    Application = 0x0a,
+    /// Opaque application-layer message sent to all connected peers
+    Broadcast = 0x0b,
+    /// WireGuard pre-shared key negotiation
+    PskRotate = 0x0c,
+    /// Request to wake up a sleeping peer
+    Wake = 0x0d,
+    /// Advertisement of the features supported by the peer
+    Capability = 0x0e,
+    /// Challenge-response verification of the peer identity
+    AuthChallenge = 0x0f,
 
     /// Reserved for future, in case we use all byte values for types.
     Reserved = 0xfe,
@@ -109,6 +127,18 @@ pub enum PacketRelayed {
     Ponger(PartialPongerMsg),
     /// Upgrading connection
     Upgrade(UpgradeMsg),
+    /// Application-layer message
+    Application(ApplicationMsg),
+    /// Application-layer message sent to all connected peers
+    Broadcast(BroadcastMsg),
+    /// WireGuard pre-shared key negotiation
+    PskRotate(PskRotateMsg),
+    /// Request to wake up a sleeping peer
+    Wake(WakeMsg),
+    /// Advertisement of the features supported by the peer
+    Capability(CapabilityMsg),
+    /// Challenge-response verification of the peer identity
+    AuthChallenge(AuthChallengeMsg),
 }
 
 impl PacketRelayed {
@@ -148,6 +178,12 @@ impl PacketRelayed {
                     Self::CallMeMaybeDeprecated(CallMeMaybeMsgDeprecated::decode(bytes)?)
                 }
                 Upgrade => Self::Upgrade(UpgradeMsg::decode(bytes)?),
+                Application => Self::Application(ApplicationMsg::decode(bytes)?),
+                Broadcast => Self::Broadcast(BroadcastMsg::decode(bytes)?),
+                PskRotate => Self::PskRotate(PskRotateMsg::decode(bytes)?),
+                Wake => Self::Wake(WakeMsg::decode(bytes)?),
+                Capability => Self::Capability(CapabilityMsg::decode(bytes)?),
+                AuthChallenge => Self::AuthChallenge(AuthChallengeMsg::decode(bytes)?),
                 // At this point a package already should be decrypted if is not Data
                 Reserved | Invalid | Encrypted => return Err(CodecError::DecodeFailed),
             },
@@ -166,6 +202,12 @@ impl Codec<PacketTypeRelayed> for PacketRelayed {
         PacketTypeRelayed::Pinger,
         PacketTypeRelayed::Upgrade,
         PacketTypeRelayed::Ponger,
+        PacketTypeRelayed::Application,
+        PacketTypeRelayed::Broadcast,
+        PacketTypeRelayed::PskRotate,
+        PacketTypeRelayed::Wake,
+        PacketTypeRelayed::Capability,
+        PacketTypeRelayed::AuthChallenge,
     ];
 
     fn decode(bytes: &[u8]) -> CodecResult<Self>
@@ -188,6 +230,12 @@ impl Codec<PacketTypeRelayed> for PacketRelayed {
                 CallMeMaybeMsgDeprecated::decode(bytes)?,
             )),
             Upgrade => Ok(Self::Upgrade(UpgradeMsg::decode(bytes)?)),
+            Application => Ok(Self::Application(ApplicationMsg::decode(bytes)?)),
+            Broadcast => Ok(Self::Broadcast(BroadcastMsg::decode(bytes)?)),
+            PskRotate => Ok(Self::PskRotate(PskRotateMsg::decode(bytes)?)),
+            Wake => Ok(Self::Wake(WakeMsg::decode(bytes)?)),
+            Capability => Ok(Self::Capability(CapabilityMsg::decode(bytes)?)),
+            AuthChallenge => Ok(Self::AuthChallenge(AuthChallengeMsg::decode(bytes)?)),
             // At this point a package already should be decrypted if is not Data
             Reserved | Invalid | Encrypted => Err(CodecError::DecodeFailed),
         }
@@ -202,6 +250,12 @@ impl Codec<PacketTypeRelayed> for PacketRelayed {
             Self::Ponger(msg) => msg.encode(),
             Self::CallMeMaybeDeprecated(msg) => msg.encode(),
             Self::Upgrade(msg) => msg.encode(),
+            Self::Application(msg) => msg.encode(),
+            Self::Broadcast(msg) => msg.encode(),
+            Self::PskRotate(msg) => msg.encode(),
+            Self::Wake(msg) => msg.encode(),
+            Self::Capability(msg) => msg.encode(),
+            Self::AuthChallenge(msg) => msg.encode(),
         }
     }
 
@@ -215,6 +269,12 @@ impl Codec<PacketTypeRelayed> for PacketRelayed {
             Self::Ponger(msg) => msg.packet_type(),
             Self::CallMeMaybeDeprecated(msg) => msg.packet_type(),
             Self::Upgrade(msg) => msg.packet_type(),
+            Self::Application(msg) => msg.packet_type(),
+            Self::Broadcast(msg) => msg.packet_type(),
+            Self::PskRotate(msg) => msg.packet_type(),
+            Self::Wake(msg) => msg.packet_type(),
+            Self::Capability(msg) => msg.packet_type(),
+            Self::AuthChallenge(msg) => msg.packet_type(),
         }
     }
 }
@@ -352,6 +412,42 @@ impl From<UpgradeMsg> for PacketRelayed {
     }
 }
 
+impl From<ApplicationMsg> for PacketRelayed {
+    fn from(other: ApplicationMsg) -> Self {
+        Self::Application(other)
+    }
+}
+
+impl From<BroadcastMsg> for PacketRelayed {
+    fn from(other: BroadcastMsg) -> Self {
+        Self::Broadcast(other)
+    }
+}
+
+impl From<PskRotateMsg> for PacketRelayed {
+    fn from(other: PskRotateMsg) -> Self {
+        Self::PskRotate(other)
+    }
+}
+
+impl From<WakeMsg> for PacketRelayed {
+    fn from(other: WakeMsg) -> Self {
+        Self::Wake(other)
+    }
+}
+
+impl From<CapabilityMsg> for PacketRelayed {
+    fn from(other: CapabilityMsg) -> Self {
+        Self::Capability(other)
+    }
+}
+
+impl From<AuthChallengeMsg> for PacketRelayed {
+    fn from(other: AuthChallengeMsg) -> Self {
+        Self::AuthChallenge(other)
+    }
+}
+
 impl From<PartialPongerMsg> for PacketRelayed {
     fn from(other: PartialPongerMsg) -> Self {
         Self::Ponger(other)
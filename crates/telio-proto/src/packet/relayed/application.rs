@@ -0,0 +1,155 @@
+use bytes::BufMut;
+
+use crate::{Codec, CodecError, CodecResult, DowncastPacket, PacketRelayed, PacketTypeRelayed};
+
+/// Maximum size of the application message payload
+pub const MAX_APPLICATION_PAYLOAD_SIZE: usize = 4096;
+
+/// Packet encapsulating opaque application-layer messages exchanged between meshnet peers
+/// Application: [ type: 0x0au8, payload: [u8]]
+/// # Examples
+/// ```rust
+/// # use crate::telio_proto::{ApplicationMsg, Codec, PacketTypeRelayed};
+/// let bytes = &[10, 1, 2, 3];
+/// let msg = ApplicationMsg::decode(bytes).expect("Failed to parse packet");
+/// assert_eq!(msg.packet_type(), PacketTypeRelayed::Application);
+/// assert_eq!(msg.get_payload(), &[1, 2, 3]);
+///
+/// assert_eq!(bytes, msg.encode().unwrap().as_slice());
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ApplicationMsg {
+    payload: Vec<u8>,
+}
+
+impl ApplicationMsg {
+    /// Creates new application message, fails if payload exceeds [`MAX_APPLICATION_PAYLOAD_SIZE`].
+    pub fn new(payload: &[u8]) -> CodecResult<Self> {
+        check_payload_size(payload.len())?;
+        Ok(Self {
+            payload: payload.to_vec(),
+        })
+    }
+
+    /// Returns payload data.
+    pub fn get_payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// Consumes the message, returning payload data.
+    pub fn into_payload(self) -> Vec<u8> {
+        self.payload
+    }
+}
+
+fn check_payload_size(size: usize) -> CodecResult<()> {
+    if size > MAX_APPLICATION_PAYLOAD_SIZE {
+        return Err(CodecError::PacketTooLarge {
+            expected: MAX_APPLICATION_PAYLOAD_SIZE,
+            got: size,
+        });
+    }
+    Ok(())
+}
+
+impl Codec<PacketTypeRelayed> for ApplicationMsg {
+    const TYPES: &'static [PacketTypeRelayed] = &[PacketTypeRelayed::Application];
+
+    fn decode(bytes: &[u8]) -> CodecResult<Self>
+    where
+        Self: Sized,
+    {
+        if bytes.is_empty() {
+            return Err(CodecError::InvalidLength);
+        }
+
+        match PacketTypeRelayed::from(*bytes.first().unwrap_or(&(PacketTypeRelayed::Invalid as u8)))
+        {
+            PacketTypeRelayed::Application => {
+                let payload = bytes.get(1..).ok_or(CodecError::DecodeFailed)?;
+                Self::new(payload)
+            }
+            _ => Err(CodecError::DecodeFailed),
+        }
+    }
+
+    fn encode(self) -> CodecResult<Vec<u8>> {
+        check_payload_size(self.payload.len())?;
+
+        let mut bytes = Vec::with_capacity(1 + self.payload.len());
+        bytes.put_u8(PacketTypeRelayed::Application as u8);
+        bytes.put(self.payload.as_slice());
+
+        Ok(bytes)
+    }
+
+    fn packet_type(&self) -> PacketTypeRelayed {
+        PacketTypeRelayed::Application
+    }
+}
+
+impl DowncastPacket<PacketRelayed> for ApplicationMsg {
+    fn downcast(packet: PacketRelayed) -> Result<Self, PacketRelayed>
+    where
+        Self: Sized,
+    {
+        match packet {
+            PacketRelayed::Application(msg) => Ok(msg),
+            packet => Err(packet),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_empty_payload() {
+        let msg = ApplicationMsg::decode(&[PacketTypeRelayed::Application as u8])
+            .expect("Failed to parse application msg");
+        assert!(msg.get_payload().is_empty());
+    }
+
+    #[test]
+    fn fail_to_decode_packet_of_wrong_type() {
+        let bytes = &[PacketTypeRelayed::Upgrade as u8, 1, 2, 3];
+        assert_eq!(ApplicationMsg::decode(bytes), Err(CodecError::DecodeFailed));
+    }
+
+    #[test]
+    fn fail_to_decode_too_large_payload() {
+        let mut bytes = vec![PacketTypeRelayed::Application as u8];
+        bytes.extend_from_slice(&[0; MAX_APPLICATION_PAYLOAD_SIZE + 1]);
+        assert_eq!(
+            ApplicationMsg::decode(&bytes),
+            Err(CodecError::PacketTooLarge {
+                expected: MAX_APPLICATION_PAYLOAD_SIZE,
+                got: MAX_APPLICATION_PAYLOAD_SIZE + 1,
+            })
+        );
+    }
+
+    #[test]
+    fn create_max_size_message() {
+        let payload = [42; MAX_APPLICATION_PAYLOAD_SIZE];
+        let msg = ApplicationMsg::new(&payload).expect("Failed to create application msg");
+        let bytes = msg.encode().expect("Failed to encode application msg");
+        assert_eq!(bytes.len(), MAX_APPLICATION_PAYLOAD_SIZE + 1);
+        assert_eq!(
+            ApplicationMsg::decode(&bytes).unwrap().get_payload(),
+            &payload[..]
+        );
+    }
+
+    #[test]
+    fn fail_to_create_too_large_message() {
+        assert_eq!(
+            ApplicationMsg::new(&[0; MAX_APPLICATION_PAYLOAD_SIZE + 1]),
+            Err(CodecError::PacketTooLarge {
+                expected: MAX_APPLICATION_PAYLOAD_SIZE,
+                got: MAX_APPLICATION_PAYLOAD_SIZE + 1,
+            })
+        );
+    }
+}
@@ -0,0 +1,176 @@
+use std::convert::TryInto;
+
+use bytes::BufMut;
+use telio_crypto::signature::SIGNATURE_SIZE;
+
+use crate::{Codec, CodecError, CodecResult, DowncastPacket, PacketRelayed, PacketTypeRelayed};
+
+/// Size of the random nonce signed by the challenged peer
+pub const AUTH_NONCE_SIZE: usize = 32;
+
+/// Packet used to verify that a meshnet peer owns the private key of its public key
+/// AuthChallenge: [ type: 0x0fu8, kind: u8, nonce: [u8; 32], signature: [u8; 64]? ]
+///
+/// The challenged peer answers with the same nonce and its signature of the nonce, created with
+/// [`telio_crypto::signature`]. `signature` is present only in responses.
+/// # Examples
+/// ```rust
+/// # use crate::telio_proto::{AuthChallengeMsg, Codec, PacketTypeRelayed};
+/// let mut bytes = vec![15, 0];
+/// bytes.extend_from_slice(&[7; 32]);
+/// let msg = AuthChallengeMsg::decode(&bytes).expect("Failed to parse packet");
+/// assert_eq!(msg.packet_type(), PacketTypeRelayed::AuthChallenge);
+/// assert_eq!(msg.get_nonce(), &[7; 32]);
+/// assert_eq!(msg.get_signature(), None);
+///
+/// assert_eq!(bytes, msg.encode().unwrap());
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct AuthChallengeMsg {
+    nonce: [u8; AUTH_NONCE_SIZE],
+    signature: Option<[u8; SIGNATURE_SIZE]>,
+}
+
+impl AuthChallengeMsg {
+    /// Creates challenge asking the peer to sign `nonce`.
+    pub fn challenge(nonce: [u8; AUTH_NONCE_SIZE]) -> Self {
+        Self {
+            nonce,
+            signature: None,
+        }
+    }
+
+    /// Creates response to the challenge with `nonce`.
+    pub fn response(nonce: [u8; AUTH_NONCE_SIZE], signature: [u8; SIGNATURE_SIZE]) -> Self {
+        Self {
+            nonce,
+            signature: Some(signature),
+        }
+    }
+
+    /// Returns nonce of the challenge.
+    pub fn get_nonce(&self) -> &[u8; AUTH_NONCE_SIZE] {
+        &self.nonce
+    }
+
+    /// Returns signature of the nonce, `None` for challenges.
+    pub fn get_signature(&self) -> Option<&[u8; SIGNATURE_SIZE]> {
+        self.signature.as_ref()
+    }
+}
+
+impl Codec<PacketTypeRelayed> for AuthChallengeMsg {
+    const TYPES: &'static [PacketTypeRelayed] = &[PacketTypeRelayed::AuthChallenge];
+
+    fn decode(bytes: &[u8]) -> CodecResult<Self>
+    where
+        Self: Sized,
+    {
+        if bytes.is_empty() {
+            return Err(CodecError::InvalidLength);
+        }
+
+        match PacketTypeRelayed::from(*bytes.first().unwrap_or(&(PacketTypeRelayed::Invalid as u8)))
+        {
+            PacketTypeRelayed::AuthChallenge => {
+                let kind = *bytes.get(1).ok_or(CodecError::InvalidLength)?;
+                let nonce = bytes
+                    .get(2..2 + AUTH_NONCE_SIZE)
+                    .and_then(|b| b.try_into().ok())
+                    .ok_or(CodecError::InvalidLength)?;
+                let rest = bytes.get(2 + AUTH_NONCE_SIZE..).unwrap_or_default();
+
+                match kind {
+                    0 if rest.is_empty() => Ok(Self::challenge(nonce)),
+                    1 => {
+                        let signature = rest.try_into().map_err(|_| CodecError::InvalidLength)?;
+                        Ok(Self::response(nonce, signature))
+                    }
+                    0 => Err(CodecError::InvalidLength),
+                    _ => Err(CodecError::DecodeFailed),
+                }
+            }
+            _ => Err(CodecError::DecodeFailed),
+        }
+    }
+
+    fn encode(self) -> CodecResult<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(2 + AUTH_NONCE_SIZE + SIGNATURE_SIZE);
+        bytes.put_u8(PacketTypeRelayed::AuthChallenge as u8);
+        bytes.put_u8(self.signature.is_some() as u8);
+        bytes.put_slice(&self.nonce);
+        if let Some(signature) = self.signature {
+            bytes.put_slice(&signature);
+        }
+
+        Ok(bytes)
+    }
+
+    fn packet_type(&self) -> PacketTypeRelayed {
+        PacketTypeRelayed::AuthChallenge
+    }
+}
+
+impl DowncastPacket<PacketRelayed> for AuthChallengeMsg {
+    fn downcast(packet: PacketRelayed) -> Result<Self, PacketRelayed>
+    where
+        Self: Sized,
+    {
+        match packet {
+            PacketRelayed::AuthChallenge(msg) => Ok(msg),
+            packet => Err(packet),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn response_roundtrip() {
+        let msg = AuthChallengeMsg::response([1; AUTH_NONCE_SIZE], [2; SIGNATURE_SIZE]);
+        let bytes = msg.clone().encode().unwrap();
+        assert_eq!(bytes.len(), 2 + AUTH_NONCE_SIZE + SIGNATURE_SIZE);
+        assert_eq!(bytes.get(..3), Some([15, 1, 1].as_slice()));
+        assert_eq!(AuthChallengeMsg::decode(&bytes), Ok(msg));
+    }
+
+    #[test]
+    fn fail_to_decode_malformed_packets() {
+        let mut challenge = vec![15, 0];
+        challenge.extend_from_slice(&[1; AUTH_NONCE_SIZE]);
+
+        // Truncated nonce
+        assert_eq!(
+            AuthChallengeMsg::decode(&challenge[..20]),
+            Err(CodecError::InvalidLength)
+        );
+        // Challenge with trailing data
+        let mut longer = challenge.clone();
+        longer.push(0);
+        assert_eq!(
+            AuthChallengeMsg::decode(&longer),
+            Err(CodecError::InvalidLength)
+        );
+        // Response with truncated signature
+        let mut response = challenge.clone();
+        response[1] = 1;
+        response.extend_from_slice(&[2; SIGNATURE_SIZE - 1]);
+        assert_eq!(
+            AuthChallengeMsg::decode(&response),
+            Err(CodecError::InvalidLength)
+        );
+        // Unknown kind
+        challenge[1] = 2;
+        assert_eq!(
+            AuthChallengeMsg::decode(&challenge),
+            Err(CodecError::DecodeFailed)
+        );
+        // Other packet type
+        assert_eq!(
+            AuthChallengeMsg::decode(&[14, 0]),
+            Err(CodecError::DecodeFailed)
+        );
+    }
+}
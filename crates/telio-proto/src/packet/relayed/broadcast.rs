@@ -0,0 +1,124 @@
+use bytes::BufMut;
+
+use crate::{
+    Codec, CodecError, CodecResult, DowncastPacket, PacketRelayed, PacketTypeRelayed,
+    MAX_APPLICATION_PAYLOAD_SIZE,
+};
+
+/// Packet encapsulating opaque application-layer message sent to all connected meshnet peers
+/// Broadcast: [ type: 0x0bu8, payload: [u8]]
+/// # Examples
+/// ```rust
+/// # use crate::telio_proto::{BroadcastMsg, Codec, PacketTypeRelayed};
+/// let bytes = &[11, 1, 2, 3];
+/// let msg = BroadcastMsg::decode(bytes).expect("Failed to parse packet");
+/// assert_eq!(msg.packet_type(), PacketTypeRelayed::Broadcast);
+/// assert_eq!(msg.get_payload(), &[1, 2, 3]);
+///
+/// assert_eq!(bytes, msg.encode().unwrap().as_slice());
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct BroadcastMsg {
+    payload: Vec<u8>,
+}
+
+impl BroadcastMsg {
+    /// Creates new broadcast message, fails if payload exceeds [`MAX_APPLICATION_PAYLOAD_SIZE`].
+    pub fn new(payload: &[u8]) -> CodecResult<Self> {
+        check_payload_size(payload.len())?;
+        Ok(Self {
+            payload: payload.to_vec(),
+        })
+    }
+
+    /// Returns payload data.
+    pub fn get_payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    /// Consumes the message, returning payload data.
+    pub fn into_payload(self) -> Vec<u8> {
+        self.payload
+    }
+}
+
+fn check_payload_size(size: usize) -> CodecResult<()> {
+    if size > MAX_APPLICATION_PAYLOAD_SIZE {
+        return Err(CodecError::PacketTooLarge {
+            expected: MAX_APPLICATION_PAYLOAD_SIZE,
+            got: size,
+        });
+    }
+    Ok(())
+}
+
+impl Codec<PacketTypeRelayed> for BroadcastMsg {
+    const TYPES: &'static [PacketTypeRelayed] = &[PacketTypeRelayed::Broadcast];
+
+    fn decode(bytes: &[u8]) -> CodecResult<Self>
+    where
+        Self: Sized,
+    {
+        if bytes.is_empty() {
+            return Err(CodecError::InvalidLength);
+        }
+
+        match PacketTypeRelayed::from(*bytes.first().unwrap_or(&(PacketTypeRelayed::Invalid as u8)))
+        {
+            PacketTypeRelayed::Broadcast => {
+                let payload = bytes.get(1..).ok_or(CodecError::DecodeFailed)?;
+                Self::new(payload)
+            }
+            _ => Err(CodecError::DecodeFailed),
+        }
+    }
+
+    fn encode(self) -> CodecResult<Vec<u8>> {
+        check_payload_size(self.payload.len())?;
+
+        let mut bytes = Vec::with_capacity(1 + self.payload.len());
+        bytes.put_u8(PacketTypeRelayed::Broadcast as u8);
+        bytes.put(self.payload.as_slice());
+
+        Ok(bytes)
+    }
+
+    fn packet_type(&self) -> PacketTypeRelayed {
+        PacketTypeRelayed::Broadcast
+    }
+}
+
+impl DowncastPacket<PacketRelayed> for BroadcastMsg {
+    fn downcast(packet: PacketRelayed) -> Result<Self, PacketRelayed>
+    where
+        Self: Sized,
+    {
+        match packet {
+            PacketRelayed::Broadcast(msg) => Ok(msg),
+            packet => Err(packet),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ApplicationMsg;
+
+    #[test]
+    fn fail_to_decode_application_msg() {
+        let bytes = ApplicationMsg::new(&[1, 2, 3]).unwrap().encode().unwrap();
+        assert_eq!(BroadcastMsg::decode(&bytes), Err(CodecError::DecodeFailed));
+    }
+
+    #[test]
+    fn fail_to_create_too_large_message() {
+        assert_eq!(
+            BroadcastMsg::new(&[0; MAX_APPLICATION_PAYLOAD_SIZE + 1]),
+            Err(CodecError::PacketTooLarge {
+                expected: MAX_APPLICATION_PAYLOAD_SIZE,
+                got: MAX_APPLICATION_PAYLOAD_SIZE + 1,
+            })
+        );
+    }
+}
@@ -0,0 +1,222 @@
+use std::convert::TryInto;
+
+use bytes::BufMut;
+
+use crate::{Codec, CodecError, CodecResult, DowncastPacket, PacketRelayed, PacketTypeRelayed};
+
+/// Size of the message following the packet type
+const BODY_SIZE: usize = 1 + 4;
+
+/// Flag asking the receiver to answer with its own capabilities
+const FLAG_REPLY_REQUESTED: u8 = 0x01;
+/// Flag marking the version of the sender appended to the message
+const FLAG_HAS_VERSION: u8 = 0x02;
+
+/// Packet advertising features supported by the sending meshnet peer
+/// Capability: [ type: 0x0eu8, flags: u8, capabilities: u32, (version_len: u8, version: utf8)?]
+///
+/// Bits of the capabilities not known to the receiver are kept, so newer peers can advertise
+/// features older ones don't understand. Older peers reject messages with the version, so it is
+/// only sent to peers advertising `VERSION`.
+/// # Examples
+/// ```rust
+/// # use crate::telio_proto::{CapabilityMsg, Codec, PacketTypeRelayed};
+/// let bytes = &[14, 1, 0, 0, 0, 3];
+/// let msg = CapabilityMsg::decode(bytes).expect("Failed to parse packet");
+/// assert_eq!(msg.packet_type(), PacketTypeRelayed::Capability);
+/// assert!(msg.is_reply_requested());
+/// assert!(msg.has(CapabilityMsg::DIRECT_PATH));
+/// assert!(msg.has(CapabilityMsg::CUSTOM_MESSAGES));
+/// assert!(!msg.has(CapabilityMsg::PSK_ROTATION));
+///
+/// assert_eq!(bytes, msg.encode().unwrap().as_slice());
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CapabilityMsg {
+    capabilities: u32,
+    reply_requested: bool,
+    version: Option<String>,
+}
+
+impl CapabilityMsg {
+    /// Peer can establish direct connections
+    pub const DIRECT_PATH: u32 = 1 << 0;
+    /// Peer accepts application messages
+    pub const CUSTOM_MESSAGES: u32 = 1 << 1;
+    /// Peer rotates WireGuard pre-shared keys
+    pub const PSK_ROTATION: u32 = 1 << 2;
+    /// Peer understands messages carrying the version of the sender
+    pub const VERSION: u32 = 1 << 3;
+
+    /// Creates message advertising `capabilities`, a bitmask of the constants above.
+    pub fn new(capabilities: u32, reply_requested: bool) -> Self {
+        Self {
+            capabilities,
+            reply_requested,
+            version: None,
+        }
+    }
+
+    /// Appends telio version of the sender to the message.
+    pub fn with_version(self, version: &str) -> Self {
+        Self {
+            version: Some(version.to_owned()),
+            ..self
+        }
+    }
+
+    /// Returns telio version of the sender, if included.
+    pub fn get_version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    /// Returns bitmask of the advertised capabilities.
+    pub fn get_capabilities(&self) -> u32 {
+        self.capabilities
+    }
+
+    /// Returns whether all bits of `capability` are advertised.
+    pub fn has(&self, capability: u32) -> bool {
+        self.capabilities & capability == capability
+    }
+
+    /// Returns whether the sender expects capabilities of the receiver in response.
+    pub fn is_reply_requested(&self) -> bool {
+        self.reply_requested
+    }
+}
+
+impl Codec<PacketTypeRelayed> for CapabilityMsg {
+    const TYPES: &'static [PacketTypeRelayed] = &[PacketTypeRelayed::Capability];
+
+    fn decode(bytes: &[u8]) -> CodecResult<Self>
+    where
+        Self: Sized,
+    {
+        if bytes.is_empty() {
+            return Err(CodecError::InvalidLength);
+        }
+
+        match PacketTypeRelayed::from(*bytes.first().unwrap_or(&(PacketTypeRelayed::Invalid as u8)))
+        {
+            PacketTypeRelayed::Capability => {
+                let flags = *bytes.get(1).ok_or(CodecError::InvalidLength)?;
+                let capabilities = bytes
+                    .get(2..1 + BODY_SIZE)
+                    .and_then(|b| b.try_into().ok())
+                    .map(u32::from_be_bytes)
+                    .ok_or(CodecError::InvalidLength)?;
+                let rest = bytes.get(1 + BODY_SIZE..).unwrap_or_default();
+                let msg = Self::new(capabilities, flags & FLAG_REPLY_REQUESTED != 0);
+
+                if flags & FLAG_HAS_VERSION == 0 {
+                    if !rest.is_empty() {
+                        return Err(CodecError::InvalidLength);
+                    }
+                    return Ok(msg);
+                }
+                let (len, version) = rest.split_first().ok_or(CodecError::InvalidLength)?;
+                if version.len() != *len as usize {
+                    return Err(CodecError::InvalidLength);
+                }
+                let version = std::str::from_utf8(version).map_err(|_| CodecError::DecodeFailed)?;
+                Ok(msg.with_version(version))
+            }
+            _ => Err(CodecError::DecodeFailed),
+        }
+    }
+
+    fn encode(self) -> CodecResult<Vec<u8>> {
+        let mut flags = 0;
+        if self.reply_requested {
+            flags |= FLAG_REPLY_REQUESTED;
+        }
+        if self.version.is_some() {
+            flags |= FLAG_HAS_VERSION;
+        }
+
+        let mut bytes = Vec::with_capacity(1 + BODY_SIZE);
+        bytes.put_u8(PacketTypeRelayed::Capability as u8);
+        bytes.put_u8(flags);
+        bytes.put_u32(self.capabilities);
+        if let Some(version) = self.version {
+            let len: u8 = version.len().try_into().map_err(|_| CodecError::Encode)?;
+            bytes.put_u8(len);
+            bytes.put_slice(version.as_bytes());
+        }
+
+        Ok(bytes)
+    }
+
+    fn packet_type(&self) -> PacketTypeRelayed {
+        PacketTypeRelayed::Capability
+    }
+}
+
+impl DowncastPacket<PacketRelayed> for CapabilityMsg {
+    fn downcast(packet: PacketRelayed) -> Result<Self, PacketRelayed>
+    where
+        Self: Sized,
+    {
+        match packet {
+            PacketRelayed::Capability(msg) => Ok(msg),
+            packet => Err(packet),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keep_unknown_capabilities() {
+        let bytes = [14, 0, 0x80, 0, 0, 4];
+        let msg = CapabilityMsg::decode(&bytes).unwrap();
+        assert!(!msg.is_reply_requested());
+        assert!(msg.has(CapabilityMsg::PSK_ROTATION));
+        assert_eq!(msg.get_capabilities(), 0x8000_0004);
+        assert_eq!(msg.encode().unwrap(), bytes);
+    }
+
+    #[test]
+    fn version_is_appended() {
+        let msg = CapabilityMsg::new(CapabilityMsg::VERSION, false).with_version("v4.2.1");
+        let bytes = msg.clone().encode().unwrap();
+        assert_eq!(
+            bytes,
+            [14, 2, 0, 0, 0, 8, 6, b'v', b'4', b'.', b'2', b'.', b'1']
+        );
+
+        let decoded = CapabilityMsg::decode(&bytes).unwrap();
+        assert_eq!(decoded.get_version(), Some("v4.2.1"));
+        assert_eq!(decoded, msg);
+
+        assert_eq!(
+            CapabilityMsg::decode(&[14, 2, 0, 0, 0, 8, 3, b'v', b'4']),
+            Err(CodecError::InvalidLength)
+        );
+        assert_eq!(
+            CapabilityMsg::new(0, false)
+                .with_version(&"1".repeat(256))
+                .encode(),
+            Err(CodecError::Encode)
+        );
+    }
+
+    #[test]
+    fn fail_to_decode_malformed_packets() {
+        assert_eq!(
+            CapabilityMsg::decode(&[14, 1, 0, 0, 0]),
+            Err(CodecError::InvalidLength)
+        );
+        assert_eq!(
+            CapabilityMsg::decode(&[14, 1, 0, 0, 0, 3, 0]),
+            Err(CodecError::InvalidLength)
+        );
+        assert_eq!(
+            CapabilityMsg::decode(&[13, 1, 0, 0, 0, 3]),
+            Err(CodecError::DecodeFailed)
+        );
+    }
+}
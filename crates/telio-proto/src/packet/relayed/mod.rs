@@ -1,7 +1,13 @@
 //! Implementation for Node <-> Node packets
+pub mod application;
+pub mod auth_challenge;
+pub mod broadcast;
+pub mod capability;
 pub mod data;
 pub mod generation;
 pub mod natter;
 pub mod nurse;
 pub mod pinger;
+pub mod psk_rotate;
 pub mod upgrade;
+pub mod wake;
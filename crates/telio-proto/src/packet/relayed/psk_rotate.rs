@@ -0,0 +1,251 @@
+use std::convert::TryInto;
+
+use bytes::BufMut;
+
+use crate::{Codec, CodecError, CodecResult, DowncastPacket, PacketRelayed, PacketTypeRelayed};
+
+/// Size of the fixed part of the message following the packet type
+const HEADER_SIZE: usize = 1 + 8;
+
+/// Upper bound of the encrypted pre-shared key size
+const MAX_ENCRYPTED_PSK_SIZE: usize = 256;
+
+/// Step of the pre-shared key negotiation
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PskRotateKind {
+    /// New pre-shared key proposed by the initiator
+    Offer = 0,
+    /// Acknowledgement that the responder received the offered key
+    Ack = 1,
+    /// Confirmation that the initiator applied the acknowledged key, so the responder applies it
+    Confirm = 2,
+    /// Sender has no negotiated key, e.g. after a restart, so no key is used with it
+    Reset = 3,
+}
+
+/// Packet used to negotiate a new WireGuard pre-shared key with a meshnet peer
+/// PskRotate: [ type: 0x0cu8, kind: u8, sequence: u64, encrypted_psk: [u8]]
+///
+/// `encrypted_psk` is empty for all kinds except [`PskRotateKind::Offer`].
+/// # Examples
+/// ```rust
+/// # use crate::telio_proto::{PskRotateMsg, PskRotateKind, Codec, PacketTypeRelayed};
+/// let bytes = &[12, 1, 0, 0, 0, 0, 0, 0, 0, 42];
+/// let msg = PskRotateMsg::decode(bytes).expect("Failed to parse packet");
+/// assert_eq!(msg.packet_type(), PacketTypeRelayed::PskRotate);
+/// assert_eq!(msg.get_kind(), PskRotateKind::Ack);
+/// assert_eq!(msg.get_sequence(), 42);
+///
+/// assert_eq!(bytes, msg.encode().unwrap().as_slice());
+/// ```
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct PskRotateMsg {
+    kind: PskRotateKind,
+    sequence: u64,
+    encrypted_psk: Vec<u8>,
+}
+
+impl PskRotateMsg {
+    /// Creates offer of the pre-shared key, already encrypted for the peer.
+    pub fn offer(sequence: u64, encrypted_psk: &[u8]) -> CodecResult<Self> {
+        check_encrypted_psk_size(encrypted_psk.len())?;
+        Ok(Self {
+            kind: PskRotateKind::Offer,
+            sequence,
+            encrypted_psk: encrypted_psk.to_vec(),
+        })
+    }
+
+    /// Creates acknowledgement of the offer with the given `sequence`.
+    pub fn ack(sequence: u64) -> Self {
+        Self {
+            kind: PskRotateKind::Ack,
+            sequence,
+            encrypted_psk: Vec::new(),
+        }
+    }
+
+    /// Creates confirmation of the acknowledged offer with the given `sequence`.
+    pub fn confirm(sequence: u64) -> Self {
+        Self {
+            kind: PskRotateKind::Confirm,
+            sequence,
+            encrypted_psk: Vec::new(),
+        }
+    }
+
+    /// Creates reset of the negotiated keys, `sequence` increases with each reset of the sender.
+    pub fn reset(sequence: u64) -> Self {
+        Self {
+            kind: PskRotateKind::Reset,
+            sequence,
+            encrypted_psk: Vec::new(),
+        }
+    }
+
+    /// Returns negotiation step of the message.
+    pub fn get_kind(&self) -> PskRotateKind {
+        self.kind
+    }
+
+    /// Returns sequence number, increasing with each offer or reset of the sender.
+    pub fn get_sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Returns encrypted pre-shared key.
+    pub fn get_encrypted_psk(&self) -> &[u8] {
+        &self.encrypted_psk
+    }
+}
+
+fn check_encrypted_psk_size(size: usize) -> CodecResult<()> {
+    if size > MAX_ENCRYPTED_PSK_SIZE {
+        return Err(CodecError::PacketTooLarge {
+            expected: MAX_ENCRYPTED_PSK_SIZE,
+            got: size,
+        });
+    }
+    Ok(())
+}
+
+impl Codec<PacketTypeRelayed> for PskRotateMsg {
+    const TYPES: &'static [PacketTypeRelayed] = &[PacketTypeRelayed::PskRotate];
+
+    fn decode(bytes: &[u8]) -> CodecResult<Self>
+    where
+        Self: Sized,
+    {
+        if bytes.is_empty() {
+            return Err(CodecError::InvalidLength);
+        }
+
+        match PacketTypeRelayed::from(*bytes.first().unwrap_or(&(PacketTypeRelayed::Invalid as u8)))
+        {
+            PacketTypeRelayed::PskRotate => {
+                let kind = match bytes.get(1) {
+                    Some(0) => PskRotateKind::Offer,
+                    Some(1) => PskRotateKind::Ack,
+                    Some(2) => PskRotateKind::Confirm,
+                    Some(3) => PskRotateKind::Reset,
+                    _ => return Err(CodecError::DecodeFailed),
+                };
+                let sequence = bytes
+                    .get(2..1 + HEADER_SIZE)
+                    .and_then(|b| b.try_into().ok())
+                    .map(u64::from_be_bytes)
+                    .ok_or(CodecError::InvalidLength)?;
+                let encrypted_psk = bytes.get(1 + HEADER_SIZE..).unwrap_or_default();
+
+                match kind {
+                    PskRotateKind::Offer if encrypted_psk.is_empty() => {
+                        Err(CodecError::DecodeFailed)
+                    }
+                    PskRotateKind::Offer => Self::offer(sequence, encrypted_psk),
+                    _ if !encrypted_psk.is_empty() => Err(CodecError::DecodeFailed),
+                    kind => Ok(Self {
+                        kind,
+                        sequence,
+                        encrypted_psk: Vec::new(),
+                    }),
+                }
+            }
+            _ => Err(CodecError::DecodeFailed),
+        }
+    }
+
+    fn encode(self) -> CodecResult<Vec<u8>> {
+        check_encrypted_psk_size(self.encrypted_psk.len())?;
+
+        let mut bytes = Vec::with_capacity(1 + HEADER_SIZE + self.encrypted_psk.len());
+        bytes.put_u8(PacketTypeRelayed::PskRotate as u8);
+        bytes.put_u8(self.kind as u8);
+        bytes.put_u64(self.sequence);
+        bytes.put(self.encrypted_psk.as_slice());
+
+        Ok(bytes)
+    }
+
+    fn packet_type(&self) -> PacketTypeRelayed {
+        PacketTypeRelayed::PskRotate
+    }
+}
+
+impl DowncastPacket<PacketRelayed> for PskRotateMsg {
+    fn downcast(packet: PacketRelayed) -> Result<Self, PacketRelayed>
+    where
+        Self: Sized,
+    {
+        match packet {
+            PacketRelayed::PskRotate(msg) => Ok(msg),
+            packet => Err(packet),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offer_roundtrip() {
+        let msg = PskRotateMsg::offer(7, &[1, 2, 3]).unwrap();
+        let bytes = msg.clone().encode().unwrap();
+        assert_eq!(bytes, [12, 0, 0, 0, 0, 0, 0, 0, 0, 7, 1, 2, 3]);
+        assert_eq!(PskRotateMsg::decode(&bytes), Ok(msg));
+    }
+
+    #[test]
+    fn confirm_and_reset_roundtrip() {
+        for msg in [PskRotateMsg::confirm(7), PskRotateMsg::reset(8)] {
+            let bytes = msg.clone().encode().unwrap();
+            assert_eq!(bytes.len(), 1 + HEADER_SIZE);
+            assert_eq!(PskRotateMsg::decode(&bytes), Ok(msg));
+        }
+    }
+
+    #[test]
+    fn fail_to_decode_malformed_packets() {
+        // Offer without key
+        assert_eq!(
+            PskRotateMsg::decode(&[12, 0, 0, 0, 0, 0, 0, 0, 0, 7]),
+            Err(CodecError::DecodeFailed)
+        );
+        // Ack with key
+        assert_eq!(
+            PskRotateMsg::decode(&[12, 1, 0, 0, 0, 0, 0, 0, 0, 7, 1]),
+            Err(CodecError::DecodeFailed)
+        );
+        // Confirm with key
+        assert_eq!(
+            PskRotateMsg::decode(&[12, 2, 0, 0, 0, 0, 0, 0, 0, 7, 1]),
+            Err(CodecError::DecodeFailed)
+        );
+        // Unknown kind
+        assert_eq!(
+            PskRotateMsg::decode(&[12, 4, 0, 0, 0, 0, 0, 0, 0, 7]),
+            Err(CodecError::DecodeFailed)
+        );
+        // Truncated sequence
+        assert_eq!(
+            PskRotateMsg::decode(&[12, 1, 0, 0]),
+            Err(CodecError::InvalidLength)
+        );
+        // Other packet type
+        assert_eq!(
+            PskRotateMsg::decode(&[11, 1, 2, 3]),
+            Err(CodecError::DecodeFailed)
+        );
+    }
+
+    #[test]
+    fn fail_to_create_too_large_offer() {
+        assert_eq!(
+            PskRotateMsg::offer(1, &[0; MAX_ENCRYPTED_PSK_SIZE + 1]),
+            Err(CodecError::PacketTooLarge {
+                expected: MAX_ENCRYPTED_PSK_SIZE,
+                got: MAX_ENCRYPTED_PSK_SIZE + 1,
+            })
+        );
+    }
+}
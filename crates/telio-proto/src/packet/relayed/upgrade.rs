@@ -13,6 +13,9 @@ use protobuf::Message;
 pub struct UpgradeMsg {
     /// Endpoint which message sender is requesting to upgrade to
     pub endpoint: SocketAddr,
+    /// Monotonically increasing message number used by receiver to reject replayed messages,
+    /// zero in messages of older peers which do not number them
+    pub sequence: u64,
 }
 
 impl Codec<PacketTypeRelayed> for UpgradeMsg {
@@ -36,22 +39,17 @@ impl Codec<PacketTypeRelayed> for UpgradeMsg {
                     .get_endpoint()
                     .parse()
                     .map_err(|_| CodecError::DecodeFailed)?;
-                Ok(Self { endpoint })
+                Ok(Self {
+                    endpoint,
+                    sequence: proto_upgrade.get_sequence(),
+                })
             }
             _ => Err(CodecError::DecodeFailed),
         }
     }
 
     fn encode(self) -> CodecResult<Vec<u8>> {
-        let mut bytes = Vec::with_capacity(MAX_PACKET_SIZE);
-        let mut msg = Upgrade::new();
-        msg.set_endpoint(self.endpoint.to_string());
-
-        bytes.put_u8(PacketTypeRelayed::Upgrade as u8);
-        msg.write_to_vec(&mut bytes)
-            .map_err(|_| CodecError::Encode)?;
-
-        Ok(bytes)
+        encode_upgrade(self.endpoint.to_string(), self.sequence)
     }
 
     fn packet_type(&self) -> PacketTypeRelayed {
@@ -59,6 +57,26 @@ impl Codec<PacketTypeRelayed> for UpgradeMsg {
     }
 }
 
+fn encode_upgrade(endpoint: String, sequence: u64) -> CodecResult<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(MAX_PACKET_SIZE);
+    let mut msg = Upgrade::new();
+    msg.set_endpoint(endpoint);
+    msg.set_sequence(sequence);
+
+    bytes.put_u8(PacketTypeRelayed::Upgrade as u8);
+    msg.write_to_vec(&mut bytes)
+        .map_err(|_| CodecError::Encode)?;
+
+    if bytes.len() > MAX_PACKET_SIZE {
+        return Err(CodecError::PacketTooLarge {
+            expected: MAX_PACKET_SIZE,
+            got: bytes.len(),
+        });
+    }
+
+    Ok(bytes)
+}
+
 impl DowncastPacket<PacketRelayed> for UpgradeMsg {
     fn downcast(packet: PacketRelayed) -> Result<Self, PacketRelayed>
     where
@@ -82,6 +100,17 @@ mod tests {
         ];
         let upgrade_msg = UpgradeMsg::decode(upgrade_bytes).expect("Failed to parse upgrade msg");
         assert_eq!(upgrade_msg.endpoint, "127.0.0.1:1234".parse().unwrap());
+        assert_eq!(upgrade_msg.sequence, 0);
+    }
+
+    #[test]
+    fn decode_packet_with_sequence() {
+        let upgrade_bytes = &[
+            8, 10, 14, 49, 50, 55, 46, 48, 46, 48, 46, 49, 58, 49, 50, 51, 52, 16, 42,
+        ];
+        let upgrade_msg = UpgradeMsg::decode(upgrade_bytes).expect("Failed to parse upgrade msg");
+        assert_eq!(upgrade_msg.endpoint, "127.0.0.1:1234".parse().unwrap());
+        assert_eq!(upgrade_msg.sequence, 42);
     }
 
     #[test]
@@ -102,11 +131,24 @@ mod tests {
     fn encode_packet() {
         let upgrade_msg = UpgradeMsg {
             endpoint: "127.0.0.1:1234".parse().unwrap(),
+            sequence: 42,
         };
         let expected_upgrade_bytes: &[u8] = &[
-            8, 10, 14, 49, 50, 55, 46, 48, 46, 48, 46, 49, 58, 49, 50, 51, 52,
+            8, 10, 14, 49, 50, 55, 46, 48, 46, 48, 46, 49, 58, 49, 50, 51, 52, 16, 42,
         ];
         let actual_upgrade_bytes = upgrade_msg.encode().unwrap();
         assert_eq!(expected_upgrade_bytes, actual_upgrade_bytes);
     }
+
+    #[test]
+    fn fail_to_encode_too_large_packet() {
+        let endpoint = "1".repeat(MAX_PACKET_SIZE);
+        assert!(matches!(
+            encode_upgrade(endpoint, 0),
+            Err(CodecError::PacketTooLarge {
+                expected: MAX_PACKET_SIZE,
+                got,
+            }) if got > MAX_PACKET_SIZE
+        ));
+    }
 }
@@ -0,0 +1,69 @@
+use bytes::BufMut;
+
+use crate::{Codec, CodecError, CodecResult, DowncastPacket, PacketRelayed, PacketTypeRelayed};
+
+/// Packet asking a sleeping meshnet peer to wake up and reestablish the connection
+/// Wake: [ type: 0x0du8 ]
+/// # Examples
+/// ```rust
+/// # use crate::telio_proto::{WakeMsg, Codec, PacketTypeRelayed};
+/// let bytes = &[13];
+/// let msg = WakeMsg::decode(bytes).expect("Failed to parse packet");
+/// assert_eq!(msg.packet_type(), PacketTypeRelayed::Wake);
+///
+/// assert_eq!(bytes, msg.encode().unwrap().as_slice());
+/// ```
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct WakeMsg;
+
+impl Codec<PacketTypeRelayed> for WakeMsg {
+    const TYPES: &'static [PacketTypeRelayed] = &[PacketTypeRelayed::Wake];
+
+    fn decode(bytes: &[u8]) -> CodecResult<Self>
+    where
+        Self: Sized,
+    {
+        match bytes {
+            [] => Err(CodecError::InvalidLength),
+            [ty] if PacketTypeRelayed::from(*ty) == PacketTypeRelayed::Wake => Ok(Self),
+            [ty, ..] if PacketTypeRelayed::from(*ty) == PacketTypeRelayed::Wake => {
+                Err(CodecError::InvalidLength)
+            }
+            _ => Err(CodecError::DecodeFailed),
+        }
+    }
+
+    fn encode(self) -> CodecResult<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(1);
+        bytes.put_u8(PacketTypeRelayed::Wake as u8);
+        Ok(bytes)
+    }
+
+    fn packet_type(&self) -> PacketTypeRelayed {
+        PacketTypeRelayed::Wake
+    }
+}
+
+impl DowncastPacket<PacketRelayed> for WakeMsg {
+    fn downcast(packet: PacketRelayed) -> Result<Self, PacketRelayed>
+    where
+        Self: Sized,
+    {
+        match packet {
+            PacketRelayed::Wake(msg) => Ok(msg),
+            packet => Err(packet),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fail_to_decode_malformed_packets() {
+        assert_eq!(WakeMsg::decode(&[]), Err(CodecError::InvalidLength));
+        assert_eq!(WakeMsg::decode(&[13, 1]), Err(CodecError::InvalidLength));
+        assert_eq!(WakeMsg::decode(&[12]), Err(CodecError::DecodeFailed));
+    }
+}
@@ -10,9 +10,9 @@ use std::{
     io::{Cursor, Error as IoError, ErrorKind},
     net::SocketAddr,
     sync::Arc,
-    time::Duration,
+    time::{Duration, SystemTime},
 };
-use telio_sockets::{SocketBufSizes, SocketPool, TcpParams};
+use telio_sockets::{proxy, SocketBufSizes, SocketPool, TcpParams};
 use telio_task::io::Chan;
 use webpki_roots::TLS_SERVER_ROOTS;
 
@@ -49,6 +49,10 @@ pub struct DerpConnection {
 
     /// For polling derp about remote peers states
     pub poll_timer: Interval,
+
+    /// Difference of the local clock from the server clock in milliseconds, positive if the
+    /// local clock is ahead, estimated from the `Date` header of the server response
+    pub clock_skew: Option<i64>,
 }
 
 impl DerpConnection {
@@ -106,7 +110,17 @@ pub async fn connect_http_and_start(
         },
     }))?;
 
-    let stream = timeout(derp_config.timeout, socket.connect(ip)).await??;
+    let stream = match derp_config.proxy.as_ref() {
+        Some(proxy) => {
+            timeout(derp_config.timeout, async {
+                let mut stream = socket.connect(proxy::resolve(proxy).await?).await?;
+                proxy::connect(&mut stream, proxy, ip).await?;
+                Ok::<_, Error>(stream)
+            })
+            .await??
+        }
+        None => timeout(derp_config.timeout, socket.connect(ip)).await??,
+    };
 
     let addr = PairAddr {
         local: stream.local_addr()?,
@@ -170,7 +184,7 @@ async fn connect_and_start<RW: AsyncRead + AsyncWrite + Send + 'static>(
 ) -> Result<DerpConnection, Error> {
     let (mut reader, mut writer) = split(stream);
 
-    let leftovers = Box::pin(connect_http(
+    let (leftovers, clock_skew) = Box::pin(connect_http(
         &mut reader,
         &mut writer,
         &server_keepalives,
@@ -213,17 +227,19 @@ async fn connect_and_start<RW: AsyncRead + AsyncWrite + Send + 'static>(
             timer.set_missed_tick_behavior(MissedTickBehavior::Delay);
             timer
         },
+        clock_skew,
     })
 }
 
 /// Sends the initial HTTP packet and parses the response in order to initiate the
-/// connection
+/// connection. Returns data following the response and the estimated clock skew.
 async fn connect_http<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
     reader: &mut R,
     writer: &mut W,
     server_keepalives: &DerpKeepaliveConfig,
     host: &str,
-) -> Result<Vec<u8>, Error> {
+) -> Result<(Vec<u8>, Option<i64>), Error> {
+    let sent_at = SystemTime::now();
     writer
         .write_all(
             format!(
@@ -244,6 +260,7 @@ async fn connect_http<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
 
     let mut data = [0_u8; MAX_TCP_PACKET_SIZE];
     let data_len = reader.read(&mut data).await?;
+    let received_at = SystemTime::now();
 
     let mut headers = [httparse::EMPTY_HEADER; 16];
     let mut res = httparse::Response::new(&mut headers);
@@ -256,7 +273,15 @@ async fn connect_http<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
         }
         Status::Complete(len) => len,
     };
-    Ok(data
+    let clock_skew = res
+        .headers
+        .iter()
+        .find(|header| header.name.eq_ignore_ascii_case("date"))
+        .and_then(|header| std::str::from_utf8(header.value).ok())
+        .and_then(|date| httpdate::parse_http_date(date).ok())
+        .map(|server_time| estimate_clock_skew(sent_at, received_at, server_time));
+
+    let leftovers = data
         .get(res_len..data_len)
         .ok_or_else(|| {
             Box::new(IoError::new(
@@ -264,7 +289,26 @@ async fn connect_http<R: AsyncRead + Unpin, W: AsyncWrite + Unpin>(
                 "Out of bounds index for data buffer",
             ))
         })?
-        .to_vec())
+        .to_vec();
+    Ok((leftovers, clock_skew))
+}
+
+/// Difference of the local clock from `server_time` in milliseconds, positive if the local
+/// clock is ahead. Server time is assumed to be taken halfway between sending the request and
+/// receiving the response.
+fn estimate_clock_skew(
+    sent_at: SystemTime,
+    received_at: SystemTime,
+    server_time: SystemTime,
+) -> i64 {
+    let round_trip = received_at.duration_since(sent_at).unwrap_or_default();
+    let local_time = sent_at + round_trip / 2;
+    // `Date` header is truncated to whole seconds
+    let server_time = server_time + Duration::from_millis(500);
+    match local_time.duration_since(server_time) {
+        Ok(ahead) => ahead.as_millis() as i64,
+        Err(behind) => -(behind.duration().as_millis() as i64),
+    }
 }
 
 #[cfg(test)]
@@ -325,12 +369,28 @@ mod tests {
             tcp_keepalive: 1,
             derp_keepalive: 2,
         };
+        let (leftovers, clock_skew) = connect_http(&mut r, &mut w, &derp_config, HOST)
+            .await
+            .unwrap();
+        assert_eq!(RESPONSE_BODY.as_bytes(), leftovers.as_slice());
+        // Server adds the `Date` header with the same clock
+        assert!(clock_skew.map_or(false, |skew| skew.abs() <= 1000));
+    }
+
+    #[test]
+    fn clock_skew_is_estimated_from_server_time() {
+        let sent_at = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let received_at = sent_at + Duration::from_millis(200);
+
+        // Server time of 1000.5s minus the local midpoint of 1000.1s
+        assert_eq!(estimate_clock_skew(sent_at, received_at, sent_at), -400);
         assert_eq!(
-            RESPONSE_BODY.as_bytes(),
-            connect_http(&mut r, &mut w, &derp_config, HOST)
-                .await
-                .unwrap()
-                .as_slice()
+            estimate_clock_skew(sent_at, received_at, sent_at - Duration::from_secs(120)),
+            119_600
+        );
+        assert_eq!(
+            estimate_clock_skew(sent_at, received_at, sent_at + Duration::from_secs(120)),
+            -120_400
         );
     }
 }
@@ -0,0 +1,202 @@
+//! Round trip time measurements of the relay
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use telio_crypto::PublicKey;
+use telio_proto::{PacketRelayed, Session};
+
+/// Number of the latest samples kept for the relay server and for each peer
+pub const RTT_HISTORY_SIZE: usize = 64;
+
+/// WireGuard handshake initiation message type
+const WG_HANDSHAKE_INITIATION: u8 = 1;
+/// WireGuard handshake response message type
+const WG_HANDSHAKE_RESPONSE: u8 = 2;
+
+/// Single round trip time measurement
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct RttSample {
+    /// Unix timestamp of the measurement, in seconds
+    pub ts_secs: u64,
+    /// Measured round trip time, in milliseconds
+    pub rtt_ms: u64,
+}
+
+/// Fixed size ring buffer of the latest samples
+#[derive(Debug, Default)]
+struct RttHistory {
+    samples: VecDeque<RttSample>,
+}
+
+impl RttHistory {
+    fn record(&mut self, sent: Instant) {
+        if self.samples.len() >= RTT_HISTORY_SIZE {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(RttSample {
+            ts_secs: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            rtt_ms: sent.elapsed().as_millis() as u64,
+        });
+    }
+
+    fn last(&self, n: usize) -> Vec<RttSample> {
+        self.samples
+            .iter()
+            .skip(self.samples.len().saturating_sub(n))
+            .copied()
+            .collect()
+    }
+}
+
+/// Keeps track of round trip times to the relay server and to peers over the relay
+///
+/// Relay server is measured with DERP poll requests, peers are measured with WireGuard
+/// handshakes which go through the relay.
+#[derive(Debug, Default)]
+pub struct RelayLatency {
+    server: RttHistory,
+    peers: HashMap<PublicKey, RttHistory>,
+    pending_poll: Option<(Session, Instant)>,
+    pending_handshakes: HashMap<PublicKey, Instant>,
+}
+
+impl RelayLatency {
+    /// Note a DERP poll request sent to the server
+    pub fn on_poll_request(&mut self, session: Session) {
+        self.pending_poll = Some((session, Instant::now()));
+    }
+
+    /// Note a valid DERP poll response received from the server
+    pub fn on_poll_response(&mut self, session: Session) {
+        match self.pending_poll {
+            Some((pending, sent)) if pending == session => {
+                self.pending_poll = None;
+                self.server.record(sent);
+            }
+            _ => (),
+        }
+    }
+
+    /// Note a packet sent to `pk` through the relay
+    pub fn on_outgoing(&mut self, pk: &PublicKey, msg: &PacketRelayed) {
+        if wg_message_type(msg) == Some(WG_HANDSHAKE_INITIATION) {
+            self.pending_handshakes.insert(*pk, Instant::now());
+        }
+    }
+
+    /// Note a packet received from `pk` through the relay
+    pub fn on_incoming(&mut self, pk: &PublicKey, msg: &PacketRelayed) {
+        if wg_message_type(msg) == Some(WG_HANDSHAKE_RESPONSE) {
+            if let Some(sent) = self.pending_handshakes.remove(pk) {
+                self.peers.entry(*pk).or_default().record(sent);
+            }
+        }
+    }
+
+    /// Forget measurements of peers which are no longer present
+    pub fn retain_peers(&mut self, peers: &HashSet<PublicKey>) {
+        self.peers.retain(|pk, _| peers.contains(pk));
+        self.pending_handshakes.retain(|pk, _| peers.contains(pk));
+    }
+
+    /// Get at most `n` latest samples, oldest first, for the given peer or for the relay server
+    /// if `peer` is None
+    pub fn history(&self, peer: Option<&PublicKey>, n: usize) -> Vec<RttSample> {
+        match peer {
+            Some(pk) => self
+                .peers
+                .get(pk)
+                .map(|history| history.last(n))
+                .unwrap_or_default(),
+            None => self.server.last(n),
+        }
+    }
+
+    /// Approximate number of heap bytes used by the measurements
+    pub fn memory_usage(&self) -> usize {
+        let sample = std::mem::size_of::<RttSample>();
+        let pk = std::mem::size_of::<PublicKey>();
+        self.server.samples.capacity() * sample
+            + self
+                .peers
+                .values()
+                .map(|history| {
+                    pk + std::mem::size_of::<RttHistory>() + history.samples.capacity() * sample
+                })
+                .sum::<usize>()
+            + self.pending_handshakes.capacity() * (pk + std::mem::size_of::<Instant>())
+    }
+}
+
+fn wg_message_type(msg: &PacketRelayed) -> Option<u8> {
+    match msg {
+        PacketRelayed::Data(data) => data.get_payload().first().copied(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use telio_proto::DataMsg;
+
+    #[test]
+    fn measure_handshakes_of_peers() {
+        let pk = PublicKey([1; 32]);
+        let mut latency = RelayLatency::default();
+
+        latency.on_incoming(&pk, &PacketRelayed::Data(DataMsg::new(&[2, 0, 0, 0])));
+        assert!(latency.history(Some(&pk), 10).is_empty());
+
+        latency.on_outgoing(&pk, &PacketRelayed::Data(DataMsg::new(&[1, 0, 0, 0])));
+        latency.on_incoming(&pk, &PacketRelayed::Data(DataMsg::new(&[4, 0, 0, 0])));
+        latency.on_incoming(&pk, &PacketRelayed::Data(DataMsg::new(&[2, 0, 0, 0])));
+        assert_eq!(latency.history(Some(&pk), 10).len(), 1);
+        assert!(latency.history(None, 10).is_empty());
+
+        latency.retain_peers(&HashSet::new());
+        assert!(latency.history(Some(&pk), 10).is_empty());
+    }
+
+    #[test]
+    fn memory_usage_grows_with_samples() {
+        let pk = PublicKey([1; 32]);
+        let mut latency = RelayLatency::default();
+        assert_eq!(latency.memory_usage(), 0);
+
+        latency.on_outgoing(&pk, &PacketRelayed::Data(DataMsg::new(&[1, 0, 0, 0])));
+        latency.on_incoming(&pk, &PacketRelayed::Data(DataMsg::new(&[2, 0, 0, 0])));
+        assert!(latency.memory_usage() >= std::mem::size_of::<RttSample>());
+    }
+
+    #[test]
+    fn measure_poll_responses_of_server() {
+        let mut latency = RelayLatency::default();
+
+        latency.on_poll_request(1);
+        latency.on_poll_response(2);
+        assert!(latency.history(None, 10).is_empty());
+        latency.on_poll_response(1);
+        latency.on_poll_response(1);
+        assert_eq!(latency.history(None, 10).len(), 1);
+    }
+
+    #[test]
+    fn keep_latest_samples() {
+        let mut history = RttHistory::default();
+        for _ in 0..RTT_HISTORY_SIZE + 10 {
+            history.record(Instant::now());
+        }
+
+        assert_eq!(history.samples.len(), RTT_HISTORY_SIZE);
+        assert_eq!(history.last(5).len(), 5);
+        assert_eq!(history.last(RTT_HISTORY_SIZE * 2).len(), RTT_HISTORY_SIZE);
+    }
+}
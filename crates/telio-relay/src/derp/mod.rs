@@ -8,7 +8,11 @@
 //! until first connection is made. For other configuration values, see `Config` description
 
 pub mod http;
+mod latency;
+mod packet_trace;
 pub mod proto;
+mod server_status;
+mod sessions;
 
 use async_trait::async_trait;
 use futures::{future::select_all, Future};
@@ -16,11 +20,11 @@ use generic_array::typenum::Unsigned;
 use std::collections::{HashMap, HashSet};
 use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use telio_crypto::{PublicKey, SecretKey};
 use telio_model::{
     api_config::FeatureDerp,
-    config::{RelayState, Server},
+    config::{ProxySettings, RelaySelectionPolicy, RelayState, Server},
 };
 use telio_proto::{
     Codec, DerpPollRequestMsg, PacketControl, PacketRelayed, PacketTypeRelayed, PeersStatesMap,
@@ -35,6 +39,7 @@ use telio_utils::{
 };
 use tokio::sync::mpsc::OwnedPermit;
 use tokio::{task::JoinHandle, time::sleep};
+use uuid::Uuid;
 
 use crypto_box::{
     aead::{Aead, AeadCore, Error, Nonce, Payload},
@@ -45,9 +50,22 @@ use core::result::Result;
 use generic_array::GenericArray;
 use rand::{rngs::StdRng, SeedableRng};
 
-use self::{http::connect_http_and_start, http::DerpConnection};
+use self::{
+    http::connect_http_and_start,
+    http::DerpConnection,
+    latency::RelayLatency,
+    packet_trace::PacketTrace,
+    server_status::ServerChecks,
+    sessions::{RelaySessions, SessionIds, RELAY_SESSION_IDLE_TIMEOUT},
+};
 
-pub use self::{proto::Error as DerpError, proto::FrameChannel};
+pub use self::{
+    latency::{RttSample, RTT_HISTORY_SIZE},
+    packet_trace::{PacketTraceDirection, PacketTraceEntry},
+    proto::Error as DerpError,
+    proto::FrameChannel,
+    server_status::RelayServerStatus,
+};
 
 /// Helper container structure for specific server ordering
 #[derive(Clone, Debug, Default)]
@@ -66,6 +84,23 @@ impl SortedServers {
         }
     }
 
+    /// Create SortedServers with the servers allowed by `policy`
+    pub fn with_policy(servers: Vec<Server>, policy: &RelaySelectionPolicy) -> Self {
+        match policy {
+            RelaySelectionPolicy::LowestLatency => Self::new(servers),
+            RelaySelectionPolicy::Pinned { hostname } => {
+                let servers: Vec<Server> = servers
+                    .into_iter()
+                    .filter(|server| &server.hostname == hostname)
+                    .collect();
+                if servers.is_empty() {
+                    telio_log_warn!("Pinned relay server {} is not in the config", hostname);
+                }
+                Self::new(servers)
+            }
+        }
+    }
+
     fn get_next(&mut self) -> Option<Server> {
         if self.current_server_num < self.servers.len() {
             let result = self.servers.get(self.current_server_num).cloned();
@@ -115,6 +150,16 @@ struct State {
     derp_poll_session: Session,
     /// Cache the result of derp polling
     remote_peers_states: PeersStatesMap,
+    /// Peers exchanging traffic through the relay, when their number is limited
+    relay_sessions: Option<RelaySessions>,
+    /// Identifiers of sessions with peers over the current connection
+    session_ids: SessionIds,
+    /// Round trip times to the server and to peers over the relay
+    latency: RelayLatency,
+    /// First bytes of the latest packets exchanged with peers, when enabled
+    packet_trace: PacketTrace,
+    /// Latest connection attempts to the configured servers
+    server_checks: ServerChecks,
 
     connecting: Option<JoinHandle<(Server, DerpConnection)>>,
 }
@@ -167,6 +212,10 @@ pub struct Config {
     pub meshnet_peers: Vec<PublicKey>,
     /// Use Mozilla's root certificates instead of OS ones [default false]
     pub use_built_in_root_certificates: bool,
+    /// Maximum number of peers relayed at the same time [default unlimited]
+    pub max_relay_connections: Option<usize>,
+    /// Proxy used to connect to Derp servers [default none]
+    pub proxy: Option<ProxySettings>,
 }
 
 impl Default for Config {
@@ -183,6 +232,8 @@ impl Default for Config {
             enable_polling: false,
             meshnet_peers: Default::default(),
             use_built_in_root_certificates: false,
+            max_relay_connections: None,
+            proxy: None,
         }
     }
 }
@@ -197,6 +248,7 @@ impl State {
         if let Some(c) = self.conn.take() {
             c.stop();
         }
+        self.session_ids.end_all();
         // kill server
         if let Some(mut server) = self.server.take() {
             telio_log_debug!("({}) Disconnected from DERP server!", Self::NAME);
@@ -209,6 +261,7 @@ impl State {
     fn start_connecting(&self, mut config: Config) -> JoinHandle<(Server, DerpConnection)> {
         let event = self.event.clone();
         let socket_pool = self.socket_pool.clone();
+        let server_checks = self.server_checks.clone();
 
         let connection = async move {
             let mut sleep_time = 1f64;
@@ -239,6 +292,7 @@ impl State {
                 let _ = event.send(Box::new(server.clone()));
 
                 // Try to establish connection
+                let started = Instant::now();
                 match Box::pin(connect_http_and_start(
                     socket_pool.clone(),
                     &server.get_address(),
@@ -249,11 +303,13 @@ impl State {
                 {
                     Ok(conn) => {
                         telio_log_info!("({}) Connected to {}", Self::NAME, server.get_address());
+                        server_checks.record(&server, Some(started.elapsed()));
                         server.conn_state = RelayState::Connected;
                         break (server, conn);
                     }
                     Err(err) => {
                         telio_log_warn!("({}) Failed to connect: {}", Self::NAME, err);
+                        server_checks.record(&server, None);
                         continue;
                     }
                 }
@@ -284,6 +340,11 @@ impl DerpRelay {
                 socket_pool,
                 derp_poll_session: 0,
                 remote_peers_states: HashMap::new(),
+                relay_sessions: None,
+                session_ids: SessionIds::default(),
+                latency: RelayLatency::default(),
+                packet_trace: PacketTrace::default(),
+                server_checks: ServerChecks::default(),
                 connecting: None,
             }),
         }
@@ -297,13 +358,32 @@ impl DerpRelay {
                 return Ok(());
             }
 
+            let proxy_changed = s.config.as_ref().and_then(|c| c.proxy.as_ref())
+                != config.as_ref().and_then(|c| c.proxy.as_ref());
             s.config = config;
 
+            let max_relay_connections = s.config.as_ref().and_then(|c| c.max_relay_connections);
+            if s.relay_sessions.as_ref().map(RelaySessions::max) != max_relay_connections {
+                telio_log_info!(
+                    "Limiting relayed peers to: {:?}",
+                    max_relay_connections
+                );
+                s.relay_sessions = max_relay_connections
+                    .map(|max| RelaySessions::new(max, RELAY_SESSION_IDLE_TIMEOUT));
+            }
+            if let Some(config) = s.config.as_ref() {
+                s.latency.retain_peers(&config.allowed_pk);
+            }
+
             // Prepare new config
             if let Some(config) = s.config.as_mut() {
                 // TODO: This logic should most likely linked with wg_stun_controll
                 // Restart connection
                 match s.server.as_ref() {
+                    Some(_) if proxy_changed => {
+                        telio_log_info!("Relay proxy changed - reconnecting.");
+                        s.disconnect().await;
+                    }
                     Some(server) => {
                         // Current server not found in new config or server no config
                         if !config.servers.contains(server) {
@@ -349,6 +429,18 @@ impl DerpRelay {
             .unwrap_or(None)
     }
 
+    /// Get difference of the local clock from the clock of the connected server in milliseconds,
+    /// positive if the local clock is ahead. Returns None if not connected or unknown
+    pub async fn get_clock_skew(&self) -> Option<i64> {
+        task_exec!(&self.task, async move |s| Ok(s
+            .conn
+            .as_ref()
+            .and_then(|conn| conn.clock_skew)))
+        .await
+        .ok()
+        .flatten()
+    }
+
     /// Get newest information about remote peer states
     pub async fn get_remote_peer_states(&self) -> PeersStatesMap {
         task_exec!(&self.task, async move |s| Ok(s.remote_peers_states.clone()))
@@ -357,6 +449,79 @@ impl DerpRelay {
             .unwrap_or_default()
     }
 
+    /// Approximate number of heap bytes used by the relay state
+    ///
+    /// Counts configured servers and peers, cached remote peer states and latency samples.
+    pub async fn get_memory_usage(&self) -> usize {
+        task_exec!(&self.task, async move |s| {
+            let pk = std::mem::size_of::<PublicKey>();
+            let config = s.config.as_ref().map_or(0, |c| {
+                c.servers.servers.capacity() * std::mem::size_of::<Server>()
+                    + c.allowed_pk.capacity() * pk
+                    + c.meshnet_peers.capacity() * pk
+            });
+            Ok(config
+                + s.remote_peers_states.capacity() * (pk + std::mem::size_of::<bool>())
+                + s.latency.memory_usage())
+        })
+        .await
+        .unwrap_or_default()
+    }
+
+    /// Get at most `n` latest round trip time samples, oldest first
+    ///
+    /// Samples of `peer` are measured with WireGuard handshakes going through the relay.
+    /// If `peer` is None, samples of the relay server itself are returned, those are
+    /// measured with DERP poll requests and are available only when polling is enabled.
+    pub async fn get_latency_history(&self, peer: Option<PublicKey>, n: usize) -> Vec<RttSample> {
+        task_exec!(&self.task, async move |s| Ok(s
+            .latency
+            .history(peer.as_ref(), n)))
+        .await
+        .unwrap_or_default()
+    }
+
+    /// Capture up to `max_bytes_per_packet` of the latest `max_packets` packets exchanged with
+    /// peers, zero `max_packets` disables the capture
+    pub async fn enable_packet_trace(&self, max_bytes_per_packet: usize, max_packets: usize) {
+        let _ = task_exec!(&self.task, async move |s| {
+            s.packet_trace.enable(max_bytes_per_packet, max_packets);
+            Ok(())
+        })
+        .await;
+    }
+
+    /// Take packets captured since the previous call, oldest first
+    pub async fn take_packet_trace(&self) -> Vec<PacketTraceEntry> {
+        task_exec!(&self.task, async move |s| Ok(s.packet_trace.take()))
+            .await
+            .unwrap_or_default()
+    }
+
+    /// Get identifier of the ongoing relay session with `peer`
+    ///
+    /// Identifier changes whenever the session is re-established, `None` if no traffic was
+    /// exchanged with the peer over the current server connection.
+    pub async fn get_session_id(&self, peer: PublicKey) -> Option<Uuid> {
+        task_exec!(&self.task, async move |s| Ok(s.session_ids.get(&peer)))
+            .await
+            .ok()
+            .flatten()
+    }
+
+    /// Get all configured servers, in the order they are tried, with results of the latest
+    /// connection attempts. Servers not tried yet are included as well.
+    pub async fn get_server_list(&self) -> Vec<RelayServerStatus> {
+        task_exec!(&self.task, async move |s| {
+            Ok(s.config.as_ref().map_or_else(Vec::new, |config| {
+                s.server_checks
+                    .statuses(&config.servers.servers, s.server.as_ref())
+            }))
+        })
+        .await
+        .unwrap_or_default()
+    }
+
     /// Try reconnect
     pub async fn reconnect(&self) {
         let _ = task_exec!(&self.task, async move |s| {
@@ -517,6 +682,7 @@ impl State {
         msg: PacketRelayed,
         config: &Config,
         rng: &mut StdRng,
+        packet_trace: &mut PacketTrace,
     ) {
         // TODO add custom task's log format macro
         telio_log_trace!(
@@ -526,14 +692,17 @@ impl State {
             msg.packet_type()
         );
         match msg.encode() {
-            Ok(buf) => match DerpRelay::encrypt_if_needed(config.secret_key, pk, rng, &buf) {
-                Ok(cipher_text) => {
-                    let _ = permit.send((pk, cipher_text));
-                }
-                Err(error) => {
-                    telio_log_debug!("({}) Encryption failed: {}", Self::NAME, error);
+            Ok(buf) => {
+                packet_trace.record(PacketTraceDirection::Tx, &pk, &buf);
+                match DerpRelay::encrypt_if_needed(config.secret_key, pk, rng, &buf) {
+                    Ok(cipher_text) => {
+                        let _ = permit.send((pk, cipher_text));
+                    }
+                    Err(error) => {
+                        telio_log_debug!("({}) Encryption failed: {}", Self::NAME, error);
+                    }
                 }
-            },
+            }
             Err(e) => {
                 telio_log_debug!("({}) Failed to encode packet: {}", Self::NAME, e);
             }
@@ -563,28 +732,34 @@ impl State {
         pk: PublicKey,
         buf: Vec<u8>,
         config: &Config,
+        latency: &mut RelayLatency,
+        packet_trace: &mut PacketTrace,
     ) {
         if config.allowed_pk.contains(&pk) {
             match DerpRelay::decrypt_if_needed(config.secret_key, pk, &buf) {
-                Ok(plain_text) => match PacketRelayed::decode(&plain_text) {
-                    Ok(msg) => {
-                        telio_log_trace!(
-                            "({}) DERP --> Rx, pubkey: {:?}, len: {}, packet type: {:?}",
-                            Self::NAME,
-                            pk,
-                            buf.len(),
-                            msg.packet_type()
-                        );
-                        permit.send((pk, msg));
-                    }
-                    Err(e) => {
-                        telio_log_debug!(
-                            "({}) DERP --> Rx, failed to parse packet: ({})",
-                            Self::NAME,
-                            e
-                        );
+                Ok(plain_text) => {
+                    packet_trace.record(PacketTraceDirection::Rx, &pk, &plain_text);
+                    match PacketRelayed::decode(&plain_text) {
+                        Ok(msg) => {
+                            telio_log_trace!(
+                                "({}) DERP --> Rx, pubkey: {:?}, len: {}, packet type: {:?}",
+                                Self::NAME,
+                                pk,
+                                buf.len(),
+                                msg.packet_type()
+                            );
+                            latency.on_incoming(&pk, &msg);
+                            permit.send((pk, msg));
+                        }
+                        Err(e) => {
+                            telio_log_debug!(
+                                "({}) DERP --> Rx, failed to parse packet: ({})",
+                                Self::NAME,
+                                e
+                            );
+                        }
                     }
-                },
+                }
                 Err(error) => {
                     telio_log_debug!("Decryption failed: {}", error);
                 }
@@ -686,7 +861,13 @@ impl Runtime for State {
                     // Received payload from upper relay, forward it to DERP stream
                     res = wait_for_tx(&c.comms_relayed.tx, upper_read) => match res {
                         Some((permit, Some((pk, msg)))) => {
-                            Self::handle_outcoming_payload_relayed(permit, pk, msg, config, &mut self.rng).await;
+                            if self.relay_sessions.as_mut().map_or(true, |s| s.admit(&pk)) {
+                                self.session_ids.get_or_start(&pk);
+                                self.latency.on_outgoing(&pk, &msg);
+                                Self::handle_outcoming_payload_relayed(permit, pk, msg, config, &mut self.rng, &mut self.packet_trace).await;
+                            } else {
+                                telio_log_debug!("({}) Relay sessions limit reached, dropping packet to: {:?}, session: {:?}", Self::NAME, pk, self.session_ids.get(&pk));
+                            }
                         },
                         Some((_, None)) => {
                             telio_log_debug!("Disconnecting from DERP server due to closed rx channel");
@@ -703,6 +884,7 @@ impl Runtime for State {
                     Some((permit, _)) = wait_for_tx(&c.comms_direct.tx, poll_timer_tick) => {
                         if config.enable_polling {
                             self.derp_poll_session = self.derp_poll_session.wrapping_add(1);
+                            self.latency.on_poll_request(self.derp_poll_session);
                             Self::handle_outcoming_payload_direct(permit, PacketControl::DerpPollRequest(DerpPollRequestMsg::new(
                                 self.derp_poll_session, &config.meshnet_peers
                             ))).await;
@@ -710,10 +892,21 @@ impl Runtime for State {
                     }
                     // Received payload from DERP stream, forward it to upper relay
                     Some((permit, Some((pk, buf)))) = wait_for_tx(chan_tx, derp_relayed_read) => {
-                        Self::handle_incoming_payload_relayed(permit, pk, buf, config).await;
+                        if self.relay_sessions.as_mut().map_or(true, |s| s.admit(&pk)) {
+                            self.session_ids.get_or_start(&pk);
+                            Self::handle_incoming_payload_relayed(permit, pk, buf, config, &mut self.latency, &mut self.packet_trace).await;
+                        } else {
+                            telio_log_debug!("({}) Relay sessions limit reached, dropping packet from: {:?}, session: {:?}", Self::NAME, pk, self.session_ids.get(&pk));
+                        }
                     },
                     Some((_, Some(buf))) = wait_for_tx(chan_tx, derp_direct_read) => {
-                        self.remote_peers_states = Self::handle_incoming_payload_direct(self.derp_poll_session, buf).await.unwrap_or_default();
+                        self.remote_peers_states = match Self::handle_incoming_payload_direct(self.derp_poll_session, buf).await {
+                            Some(states) => {
+                                self.latency.on_poll_response(self.derp_poll_session);
+                                states
+                            }
+                            None => Default::default(),
+                        };
                         telio_log_debug!("Remote peers statuses: {:?}", self.remote_peers_states);
                     }
 
@@ -844,6 +1037,37 @@ mod tests {
         assert_eq!(None, config.servers.get_next());
     }
 
+    #[test]
+    fn test_server_selection_policy() {
+        let servers = vec![
+            Server {
+                hostname: "derp1.example.com".into(),
+                weight: 2,
+                ..Default::default()
+            },
+            Server {
+                hostname: "derp2.example.com".into(),
+                weight: 1,
+                ..Default::default()
+            },
+        ];
+
+        let mut sorted =
+            SortedServers::with_policy(servers.clone(), &RelaySelectionPolicy::LowestLatency);
+        assert_eq!("derp2.example.com", sorted.get_next().unwrap().hostname);
+        assert_eq!("derp1.example.com", sorted.get_next().unwrap().hostname);
+
+        let pinned = |hostname: &str| RelaySelectionPolicy::Pinned {
+            hostname: hostname.to_owned(),
+        };
+        let mut sorted = SortedServers::with_policy(servers.clone(), &pinned("derp1.example.com"));
+        assert_eq!("derp1.example.com", sorted.get_next().unwrap().hostname);
+        assert_eq!(None, sorted.get_next());
+
+        let mut sorted = SortedServers::with_policy(servers, &pinned("derp3.example.com"));
+        assert_eq!(None, sorted.get_next());
+    }
+
     #[tokio::test(flavor = "multi_thread")]
     #[ignore = "derp cannot connect to real host"]
     async fn test_derp_fallback() {
@@ -0,0 +1,109 @@
+//! Capture of the first bytes of packets exchanged with peers over the relay
+//!
+//! Packets are captured before encryption of the outgoing and after decryption of the incoming
+//! control messages, so the trace shows the telio protocol as seen by the peers.
+
+use std::{
+    collections::VecDeque,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use telio_crypto::PublicKey;
+
+/// Direction of the captured packet, as seen from this device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PacketTraceDirection {
+    /// Packet received from the peer
+    Rx,
+    /// Packet sent to the peer
+    Tx,
+}
+
+/// Single captured packet
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PacketTraceEntry {
+    /// Direction of the packet
+    pub direction: PacketTraceDirection,
+    /// Public key of the peer exchanging the packet
+    pub peer_pk: PublicKey,
+    /// Time the packet was captured, in milliseconds since UNIX epoch
+    pub timestamp_ms: u64,
+    /// Hex encoded first bytes of the packet
+    pub hex: String,
+}
+
+/// Ring buffer of the latest captured packets, nothing is captured until enabled
+#[derive(Debug, Default)]
+pub struct PacketTrace {
+    max_bytes_per_packet: usize,
+    max_packets: usize,
+    entries: VecDeque<PacketTraceEntry>,
+}
+
+impl PacketTrace {
+    /// Capture up to `max_bytes_per_packet` of the latest `max_packets` packets, zero
+    /// `max_packets` disables the capture
+    pub fn enable(&mut self, max_bytes_per_packet: usize, max_packets: usize) {
+        self.max_bytes_per_packet = max_bytes_per_packet;
+        self.max_packets = max_packets;
+        let excess = self.entries.len().saturating_sub(max_packets);
+        self.entries.drain(..excess);
+    }
+
+    /// Capture the packet, if enabled
+    pub fn record(&mut self, direction: PacketTraceDirection, pk: &PublicKey, packet: &[u8]) {
+        if self.max_packets == 0 {
+            return;
+        }
+        if self.entries.len() >= self.max_packets {
+            self.entries.pop_front();
+        }
+        let captured = packet.get(..self.max_bytes_per_packet).unwrap_or(packet);
+        self.entries.push_back(PacketTraceEntry {
+            direction,
+            peer_pk: *pk,
+            timestamp_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as u64),
+            hex: hex::encode(captured),
+        });
+    }
+
+    /// Take captured packets, oldest first, leaving the buffer empty
+    pub fn take(&mut self) -> Vec<PacketTraceEntry> {
+        self.entries.drain(..).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keep_first_bytes_of_latest_packets() {
+        let pk = PublicKey([1; 32]);
+        let mut trace = PacketTrace::default();
+        trace.record(PacketTraceDirection::Rx, &pk, &[0xaa]);
+        assert!(trace.take().is_empty());
+
+        trace.enable(2, 2);
+        trace.record(PacketTraceDirection::Rx, &pk, &[0xaa, 0xbb, 0xcc]);
+        trace.record(PacketTraceDirection::Tx, &pk, &[0x01]);
+        trace.record(PacketTraceDirection::Tx, &pk, &[0x02, 0x03, 0x04]);
+
+        let entries = trace.take();
+        assert_eq!(
+            entries
+                .iter()
+                .map(|e| (e.direction, e.hex.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                (PacketTraceDirection::Tx, "01"),
+                (PacketTraceDirection::Tx, "0203")
+            ]
+        );
+        assert!(trace.take().is_empty());
+    }
+}
@@ -0,0 +1,137 @@
+//! Results of connection attempts to the configured relay servers
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use telio_model::config::{RelayState, Server};
+
+/// Health of a single configured relay server
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RelayServerStatus {
+    /// Hostname of the server
+    pub hostname: String,
+    /// Port on which server listens to relay requests
+    pub port: u16,
+    /// Time the latest successful connection took to establish, in milliseconds,
+    /// `None` if the server was not tried yet or the latest attempt failed
+    pub latency_ms: Option<u64>,
+    /// Whether the relay is currently connected to the server
+    pub connected: bool,
+    /// Seconds since the latest connection attempt, `None` if the server was not tried yet
+    pub last_checked_secs: Option<u64>,
+}
+
+/// Outcome of the latest connection attempt
+#[derive(Debug, Clone, Copy)]
+struct ServerCheck {
+    checked_at: Instant,
+    connect_time: Option<Duration>,
+}
+
+/// Latest connection attempts to the servers, shared with the connecting task
+#[derive(Debug, Clone, Default)]
+pub struct ServerChecks {
+    checks: Arc<Mutex<HashMap<String, ServerCheck>>>,
+}
+
+impl ServerChecks {
+    /// Note an attempt to connect to the server, `connect_time` is `None` if it failed
+    pub fn record(&self, server: &Server, connect_time: Option<Duration>) {
+        if let Ok(mut checks) = self.checks.lock() {
+            checks.insert(
+                server.hostname.clone(),
+                ServerCheck {
+                    checked_at: Instant::now(),
+                    connect_time,
+                },
+            );
+        }
+    }
+
+    /// Status of every server in `servers`, including those which were not tried yet
+    pub fn statuses(
+        &self,
+        servers: &[Server],
+        connected: Option<&Server>,
+    ) -> Vec<RelayServerStatus> {
+        let checks = match self.checks.lock() {
+            Ok(checks) => checks,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        servers
+            .iter()
+            .map(|server| {
+                let check = checks.get(&server.hostname);
+                RelayServerStatus {
+                    hostname: server.hostname.clone(),
+                    port: server.relay_port,
+                    latency_ms: check
+                        .and_then(|check| check.connect_time)
+                        .map(|time| time.as_millis() as u64),
+                    connected: connected.map_or(false, |connected| {
+                        connected.hostname == server.hostname
+                            && connected.conn_state == RelayState::Connected
+                    }),
+                    last_checked_secs: check.map(|check| check.checked_at.elapsed().as_secs()),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server(hostname: &str) -> Server {
+        Server {
+            hostname: hostname.to_owned(),
+            relay_port: 8765,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn statuses_include_untried_servers() {
+        let servers = vec![server("derp1"), server("derp2"), server("derp3")];
+        let checks = ServerChecks::default();
+        checks.record(&servers[0], None);
+        checks.record(&servers[1], Some(Duration::from_millis(15)));
+        let connected = Server {
+            conn_state: RelayState::Connected,
+            ..servers[1].clone()
+        };
+
+        let statuses = checks.statuses(&servers, Some(&connected));
+        assert_eq!(
+            statuses,
+            vec![
+                RelayServerStatus {
+                    hostname: "derp1".to_owned(),
+                    port: 8765,
+                    latency_ms: None,
+                    connected: false,
+                    last_checked_secs: Some(0),
+                },
+                RelayServerStatus {
+                    hostname: "derp2".to_owned(),
+                    port: 8765,
+                    latency_ms: Some(15),
+                    connected: true,
+                    last_checked_secs: Some(0),
+                },
+                RelayServerStatus {
+                    hostname: "derp3".to_owned(),
+                    port: 8765,
+                    latency_ms: None,
+                    connected: false,
+                    last_checked_secs: None,
+                },
+            ]
+        );
+    }
+}
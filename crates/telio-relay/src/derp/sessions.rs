@@ -0,0 +1,128 @@
+//! Bookkeeping of peers which exchange traffic through the DERP relay
+
+use std::{collections::HashMap, time::Duration};
+
+use telio_crypto::PublicKey;
+use telio_utils::{telio_log_info, LruCache};
+use uuid::Uuid;
+
+/// Relay session is considered idle and is evicted after not seeing traffic for this long
+pub const RELAY_SESSION_IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Limits the number of peers which can use the relay at the same time
+///
+/// Sessions of the most recently active peers are kept, idle sessions are evicted
+/// starting from the least recently used one.
+#[derive(Debug)]
+pub struct RelaySessions {
+    sessions: LruCache<PublicKey, ()>,
+    max: usize,
+}
+
+impl RelaySessions {
+    /// Create bookkeeping for at most `max` simultaneous sessions
+    pub fn new(max: usize, idle_timeout: Duration) -> Self {
+        Self {
+            sessions: LruCache::new(idle_timeout, max),
+            max,
+        }
+    }
+
+    /// Maximum number of simultaneous sessions
+    pub fn max(&self) -> usize {
+        self.max
+    }
+
+    /// Record traffic of `pk`, returns false if the limit is reached and
+    /// no idle session could be evicted to make room for a new one
+    pub fn admit(&mut self, pk: &PublicKey) -> bool {
+        // Refreshes the session and evicts idle ones
+        if self.sessions.get(pk).is_some() {
+            return true;
+        }
+        if self.sessions.len() >= self.max {
+            return false;
+        }
+        self.sessions.insert(*pk, ());
+        true
+    }
+}
+
+/// Identifiers of relay sessions with peers, logged to group log lines by session
+///
+/// Session with a peer starts with the first packet exchanged with it over the current server
+/// connection and ends when the connection is closed, so reconnecting starts new sessions.
+#[derive(Debug, Default)]
+pub struct SessionIds {
+    ids: HashMap<PublicKey, Uuid>,
+}
+
+impl SessionIds {
+    /// Identifier of the ongoing session with `pk`, starting a new one if there is none
+    pub fn get_or_start(&mut self, pk: &PublicKey) -> Uuid {
+        *self.ids.entry(*pk).or_insert_with(|| {
+            let id = Uuid::new_v4();
+            telio_log_info!("Relay session {} with {:?} started", id, pk);
+            id
+        })
+    }
+
+    /// Identifier of the ongoing session with `pk`
+    pub fn get(&self, pk: &PublicKey) -> Option<Uuid> {
+        self.ids.get(pk).copied()
+    }
+
+    /// End sessions with all peers, called when the server connection is closed
+    pub fn end_all(&mut self) {
+        for (pk, id) in self.ids.drain() {
+            telio_log_info!("Relay session {} with {:?} ended", id, pk);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuse_new_sessions_over_limit() {
+        let (a, b, c) = (PublicKey([1; 32]), PublicKey([2; 32]), PublicKey([3; 32]));
+        let mut sessions = RelaySessions::new(2, RELAY_SESSION_IDLE_TIMEOUT);
+
+        assert!(sessions.admit(&a));
+        assert!(sessions.admit(&b));
+        assert!(!sessions.admit(&c));
+        // Existing sessions are not affected
+        assert!(sessions.admit(&a));
+        assert!(sessions.admit(&b));
+    }
+
+    #[test]
+    fn evict_idle_sessions() {
+        let (a, b, c) = (PublicKey([1; 32]), PublicKey([2; 32]), PublicKey([3; 32]));
+        let mut sessions = RelaySessions::new(2, Duration::from_millis(50));
+
+        assert!(sessions.admit(&a));
+        assert!(sessions.admit(&b));
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(sessions.admit(&c));
+        assert!(sessions.admit(&a));
+        assert!(!sessions.admit(&b));
+    }
+
+    #[test]
+    fn session_ids_change_after_reconnect() {
+        let (a, b) = (PublicKey([1; 32]), PublicKey([2; 32]));
+        let mut ids = SessionIds::default();
+
+        assert_eq!(ids.get(&a), None);
+        let first = ids.get_or_start(&a);
+        assert_eq!(ids.get_or_start(&a), first);
+        assert_eq!(ids.get(&a), Some(first));
+        assert_ne!(ids.get_or_start(&b), first);
+
+        ids.end_all();
+        assert_eq!(ids.get(&a), None);
+        assert_ne!(ids.get_or_start(&a), first);
+    }
+}
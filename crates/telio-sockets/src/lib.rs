@@ -2,8 +2,9 @@ mod socket_pool;
 
 pub mod native;
 pub mod protector;
+pub mod proxy;
 pub mod socket_params;
 
-pub use protector::{NativeProtector, Protect, Protector};
+pub use protector::{BindNetwork, NativeProtector, NetworkConstraints, Protect, Protector};
 pub use socket_params::{SocketBufSizes, TcpParams, UdpParams};
 pub use socket_pool::{External, SocketPool};
@@ -0,0 +1,121 @@
+use std::io;
+
+use parking_lot::Mutex;
+use telio_utils::telio_log_debug;
+
+use crate::native::NativeSocket;
+
+use super::{BindNetwork, NetworkConstraints, Protect, Protector};
+
+pub struct NativeProtector {
+    /// Application callback used to exclude sockets from the VPN (VpnService::protect())
+    protect: Option<Protect>,
+    /// Application callback binding sockets to a network (Network::bindSocket()), apps lack
+    /// the privileges to bind sockets to interfaces themselves
+    bind_network: Option<BindNetwork>,
+    constraints: Mutex<NetworkConstraints>,
+}
+
+impl NativeProtector {
+    pub fn new() -> io::Result<Self> {
+        Ok(Self {
+            protect: None,
+            bind_network: None,
+            constraints: Mutex::new(NetworkConstraints::default()),
+        })
+    }
+
+    pub fn with_protect(protect: Protect, bind_network: Option<BindNetwork>) -> Self {
+        Self {
+            protect: Some(protect),
+            bind_network,
+            constraints: Mutex::new(NetworkConstraints::default()),
+        }
+    }
+}
+
+impl Protector for NativeProtector {
+    fn make_external(&self, socket: NativeSocket) -> io::Result<()> {
+        if let Some(protect) = self.protect.as_ref() {
+            protect(socket);
+        }
+
+        let constraints = *self.constraints.lock();
+        if constraints == NetworkConstraints::default() {
+            return Ok(());
+        }
+
+        // Constraints are best effort, socket stays on the default network if they cannot be met
+        match self.bind_network.as_ref() {
+            Some(bind_network) => {
+                if !bind_network(socket, constraints) {
+                    telio_log_debug!(
+                        "No network satisfies {:?}, using default network for socket {}",
+                        constraints,
+                        socket
+                    );
+                }
+            }
+            None => telio_log_debug!(
+                "No bind callback for {:?}, using default network for socket {}",
+                constraints,
+                socket
+            ),
+        }
+
+        Ok(())
+    }
+
+    fn clean(&self, _socket: NativeSocket) {
+        // Skip, socket will be removed
+    }
+
+    fn set_network_constraints(&self, constraints: NetworkConstraints) {
+        *self.constraints.lock() = constraints;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicI32, Ordering},
+        Arc,
+    };
+
+    use super::*;
+
+    #[test]
+    fn protect_is_called_without_constraints() {
+        let protected = Arc::new(AtomicI32::new(0));
+        let protector = NativeProtector::with_protect(
+            {
+                let protected = protected.clone();
+                Arc::new(move |fd| protected.store(fd, Ordering::Relaxed))
+            },
+            Some(Arc::new(|_, _| panic!("Bound without constraints"))),
+        );
+
+        assert!(protector.make_external(42).is_ok());
+        assert_eq!(protected.load(Ordering::Relaxed), 42);
+    }
+
+    #[test]
+    fn socket_is_bound_by_the_host() {
+        let bound = Arc::new(Mutex::new(None));
+        let protector = NativeProtector::with_protect(Arc::new(|_| ()), {
+            let bound = bound.clone();
+            Some(Arc::new(move |fd, constraints| {
+                *bound.lock() = Some((fd, constraints));
+                true
+            }))
+        });
+        let constraints = NetworkConstraints {
+            require_unmetered: true,
+            require_wifi: false,
+        };
+        protector.set_network_constraints(constraints);
+
+        assert!(protector.make_external(42).is_ok());
+        assert_eq!(*bound.lock(), Some((42, constraints)));
+    }
+}
@@ -1,3 +1,4 @@
+use serde::Deserialize;
 use std::{io, panic::RefUnwindSafe, sync::Arc};
 
 use crate::native::NativeSocket;
@@ -15,13 +16,28 @@ pub mod platform;
 pub mod platform;
 
 #[cfg(target_os = "android")]
-#[path = "unsupported.rs"]
+#[path = "android.rs"]
 pub mod platform;
 
 pub use platform::NativeProtector;
 
 pub type Protect = Arc<dyn Fn(NativeSocket) + Send + Sync + RefUnwindSafe + 'static>;
 
+/// Host callback binding the socket to a network satisfying the constraints, e.g. with
+/// `Network.bindSocket()` on Android. Returns `false` if no network satisfies them.
+pub type BindNetwork =
+    Arc<dyn Fn(NativeSocket, NetworkConstraints) -> bool + Send + Sync + RefUnwindSafe + 'static>;
+
+/// Restrictions on the networks which external sockets may use
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct NetworkConstraints {
+    /// Use only networks which are not metered
+    pub require_unmetered: bool,
+    /// Use only WiFi networks
+    pub require_wifi: bool,
+}
+
 pub trait Protector: Send + Sync {
     fn make_external(&self, socket: NativeSocket) -> io::Result<()>;
 
@@ -35,6 +51,9 @@ pub trait Protector: Send + Sync {
 
     #[cfg(any(target_os = "macos", target_os = "ios", target_os = "tvos", windows))]
     fn set_tunnel_interface(&self, interface: u64);
+
+    #[cfg(target_os = "android")]
+    fn set_network_constraints(&self, constraints: NetworkConstraints);
 }
 
 impl Protector for Protect {
@@ -55,4 +74,7 @@ impl Protector for Protect {
 
     #[cfg(any(target_os = "macos", target_os = "ios", target_os = "tvos", windows))]
     fn set_tunnel_interface(&self, _: u64) {}
+
+    #[cfg(target_os = "android")]
+    fn set_network_constraints(&self, _: NetworkConstraints) {}
 }
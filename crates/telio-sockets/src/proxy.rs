@@ -0,0 +1,309 @@
+//! Tunneling of TCP connections through HTTP and SOCKS5 proxies
+
+use std::{
+    io::{Error, ErrorKind},
+    net::SocketAddr,
+};
+
+use httparse::Status;
+use telio_model::config::{ProxySettings, ProxyType};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::lookup_host,
+};
+
+/// Max size of the HTTP proxy response headers
+const MAX_HTTP_RESPONSE_SIZE: usize = 8192;
+
+const SOCKS_VERSION: u8 = 5;
+const SOCKS_AUTH_NONE: u8 = 0x00;
+const SOCKS_AUTH_PASSWORD: u8 = 0x02;
+const SOCKS_AUTH_NO_ACCEPTABLE: u8 = 0xff;
+const SOCKS_PASSWORD_AUTH_VERSION: u8 = 1;
+const SOCKS_CMD_CONNECT: u8 = 1;
+const SOCKS_ATYP_IPV4: u8 = 1;
+const SOCKS_ATYP_DOMAIN: u8 = 3;
+const SOCKS_ATYP_IPV6: u8 = 4;
+const SOCKS_REPLY_SUCCEEDED: u8 = 0;
+
+fn proxy_error(msg: &str) -> Error {
+    Error::new(ErrorKind::Other, format!("Proxy: {}", msg))
+}
+
+/// Resolve IPv4 address of the proxy
+pub async fn resolve(proxy: &ProxySettings) -> Result<SocketAddr, Error> {
+    lookup_host((proxy.host.as_str(), proxy.port))
+        .await?
+        .find(SocketAddr::is_ipv4)
+        .ok_or_else(|| proxy_error("no IPv4 address"))
+}
+
+/// Open a tunnel to `target` over the `stream` connected to the proxy
+pub async fn connect<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    proxy: &ProxySettings,
+    target: SocketAddr,
+) -> Result<(), Error> {
+    match proxy.proxy_type {
+        ProxyType::Http => connect_http(stream, proxy, target).await,
+        ProxyType::Socks5 => connect_socks5(stream, proxy, target).await,
+    }
+}
+
+async fn connect_http<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    proxy: &ProxySettings,
+    target: SocketAddr,
+) -> Result<(), Error> {
+    let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", target);
+    if let Some(username) = &proxy.username {
+        let password = proxy.password.as_ref().map_or("", |p| p.as_str());
+        request.push_str(&format!(
+            "Proxy-Authorization: Basic {}\r\n",
+            base64::encode(format!("{}:{}", username, password))
+        ));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    // Read byte by byte, anything after the headers belongs to the tunneled connection
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() >= MAX_HTTP_RESPONSE_SIZE {
+            return Err(proxy_error("response too large"));
+        }
+        response.push(stream.read_u8().await?);
+    }
+
+    let mut headers = [httparse::EMPTY_HEADER; 32];
+    let mut res = httparse::Response::new(&mut headers);
+    match res
+        .parse(&response)
+        .map_err(|e| proxy_error(&e.to_string()))?
+    {
+        Status::Complete(_) if res.code == Some(200) => Ok(()),
+        Status::Complete(_) => Err(proxy_error(&format!(
+            "CONNECT failed with status {:?}",
+            res.code
+        ))),
+        Status::Partial => Err(proxy_error("incomplete response")),
+    }
+}
+
+async fn connect_socks5<S: AsyncRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    proxy: &ProxySettings,
+    target: SocketAddr,
+) -> Result<(), Error> {
+    let credentials = proxy.username.as_ref().map(|username| {
+        (
+            username.as_str(),
+            proxy.password.as_ref().map_or("", |p| p.as_str()),
+        )
+    });
+
+    // Method selection
+    if credentials.is_some() {
+        stream
+            .write_all(&[SOCKS_VERSION, 2, SOCKS_AUTH_NONE, SOCKS_AUTH_PASSWORD])
+            .await?;
+    } else {
+        stream
+            .write_all(&[SOCKS_VERSION, 1, SOCKS_AUTH_NONE])
+            .await?;
+    }
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    match (reply, credentials) {
+        ([SOCKS_VERSION, SOCKS_AUTH_NONE], _) => (),
+        ([SOCKS_VERSION, SOCKS_AUTH_PASSWORD], Some((username, password))) => {
+            // Username/password authentication, RFC 1929
+            if username.len() > u8::MAX as usize || password.len() > u8::MAX as usize {
+                return Err(proxy_error("credentials too long"));
+            }
+            let mut request = vec![SOCKS_PASSWORD_AUTH_VERSION, username.len() as u8];
+            request.extend_from_slice(username.as_bytes());
+            request.push(password.len() as u8);
+            request.extend_from_slice(password.as_bytes());
+            stream.write_all(&request).await?;
+
+            stream.read_exact(&mut reply).await?;
+            if reply[1] != 0 {
+                return Err(proxy_error("authentication failed"));
+            }
+        }
+        ([SOCKS_VERSION, SOCKS_AUTH_NO_ACCEPTABLE], _) => {
+            return Err(proxy_error("no acceptable authentication method"))
+        }
+        _ => return Err(proxy_error("unexpected method selection reply")),
+    }
+
+    // Connect request
+    let mut request = vec![SOCKS_VERSION, SOCKS_CMD_CONNECT, 0];
+    match target {
+        SocketAddr::V4(addr) => {
+            request.push(SOCKS_ATYP_IPV4);
+            request.extend_from_slice(&addr.ip().octets());
+        }
+        SocketAddr::V6(addr) => {
+            request.push(SOCKS_ATYP_IPV6);
+            request.extend_from_slice(&addr.ip().octets());
+        }
+    }
+    request.extend_from_slice(&target.port().to_be_bytes());
+    stream.write_all(&request).await?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != SOCKS_VERSION {
+        return Err(proxy_error("unexpected connect reply"));
+    }
+    if reply[1] != SOCKS_REPLY_SUCCEEDED {
+        return Err(proxy_error(&format!(
+            "connect failed with reply {}",
+            reply[1]
+        )));
+    }
+    // Skip the bound address and port
+    let addr_len = match reply[3] {
+        SOCKS_ATYP_IPV4 => 4,
+        SOCKS_ATYP_IPV6 => 16,
+        SOCKS_ATYP_DOMAIN => stream.read_u8().await? as usize,
+        _ => return Err(proxy_error("unexpected address type")),
+    };
+    let mut bound = vec![0u8; addr_len + 2];
+    stream.read_exact(&mut bound).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use telio_utils::Hidden;
+    use tokio::io::duplex;
+
+    fn settings(proxy_type: ProxyType, username: Option<&str>) -> ProxySettings {
+        ProxySettings {
+            proxy_type,
+            host: "proxy.example.com".to_owned(),
+            port: 1080,
+            username: username.map(String::from),
+            password: username.map(|_| Hidden("secret".to_owned())),
+        }
+    }
+
+    fn target() -> SocketAddr {
+        "10.0.0.1:8765".parse().unwrap()
+    }
+
+    #[tokio::test]
+    async fn http_connect() {
+        let (mut client, mut server) = duplex(1024);
+        let server = tokio::spawn(async move {
+            let mut buf = vec![0u8; 1024];
+            let len = server.read(&mut buf).await.unwrap();
+            server
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\nDERP")
+                .await
+                .unwrap();
+            String::from_utf8(buf[..len].to_vec()).unwrap()
+        });
+
+        connect(
+            &mut client,
+            &settings(ProxyType::Http, Some("user")),
+            target(),
+        )
+        .await
+        .unwrap();
+        let request = server.await.unwrap();
+        assert!(request.starts_with("CONNECT 10.0.0.1:8765 HTTP/1.1\r\n"));
+        assert!(request.contains(&format!(
+            "Proxy-Authorization: Basic {}\r\n",
+            base64::encode("user:secret")
+        )));
+
+        // Data following the response is left for the tunneled connection
+        let mut tunneled = [0u8; 4];
+        client.read_exact(&mut tunneled).await.unwrap();
+        assert_eq!(&tunneled, b"DERP");
+    }
+
+    #[tokio::test]
+    async fn http_connect_refused() {
+        let (mut client, mut server) = duplex(1024);
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 1024];
+            let _ = server.read(&mut buf).await.unwrap();
+            server
+                .write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n")
+                .await
+                .unwrap();
+        });
+
+        assert!(
+            connect(&mut client, &settings(ProxyType::Http, None), target())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn socks5_connect_with_password() {
+        let (mut client, mut server) = duplex(1024);
+        let server = tokio::spawn(async move {
+            let mut greeting = [0u8; 4];
+            server.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(greeting, [5, 2, 0, 2]);
+            server.write_all(&[5, 2]).await.unwrap();
+
+            let mut auth = [0u8; 1 + 1 + 4 + 1 + 6];
+            server.read_exact(&mut auth).await.unwrap();
+            assert_eq!(&auth, b"\x01\x04user\x06secret");
+            server.write_all(&[1, 0]).await.unwrap();
+
+            let mut request = [0u8; 10];
+            server.read_exact(&mut request).await.unwrap();
+            assert_eq!(request, [5, 1, 0, 1, 10, 0, 0, 1, 0x22, 0x3d]);
+            server
+                .write_all(&[5, 0, 0, 1, 192, 168, 0, 1, 0, 80])
+                .await
+                .unwrap();
+        });
+
+        connect(
+            &mut client,
+            &settings(ProxyType::Socks5, Some("user")),
+            target(),
+        )
+        .await
+        .unwrap();
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn socks5_connect_failure() {
+        let (mut client, mut server) = duplex(1024);
+        tokio::spawn(async move {
+            let mut greeting = [0u8; 3];
+            server.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(greeting, [5, 1, 0]);
+            server.write_all(&[5, 0]).await.unwrap();
+
+            let mut request = [0u8; 10];
+            server.read_exact(&mut request).await.unwrap();
+            // Connection refused
+            server
+                .write_all(&[5, 5, 0, 1, 0, 0, 0, 0, 0, 0])
+                .await
+                .unwrap();
+        });
+
+        assert!(
+            connect(&mut client, &settings(ProxyType::Socks5, None), target())
+                .await
+                .is_err()
+        );
+    }
+}
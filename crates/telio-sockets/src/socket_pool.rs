@@ -18,6 +18,8 @@ use tokio::{
 use boringtun::device::MakeExternalBoringtun;
 use telio_utils::{telio_log_debug, telio_log_warn};
 
+#[cfg(target_os = "android")]
+use crate::NetworkConstraints;
 use crate::{
     native::{AsNativeSocket, NativeSocket},
     Protector, TcpParams, UdpParams,
@@ -125,6 +127,11 @@ impl SocketPool {
         self.protect.set_tunnel_interface(interface);
     }
 
+    #[cfg(target_os = "android")]
+    pub fn set_network_constraints(&self, constraints: NetworkConstraints) {
+        self.protect.set_network_constraints(constraints);
+    }
+
     pub fn new_external_tcp_v4(
         &self,
         params: Option<TcpParams>,
@@ -255,6 +262,8 @@ mod tests {
             fn set_tunnel_interface(&self, interface: u64);
             #[cfg(any(target_os = "macos", target_os = "ios", target_os = "tvos"))]
             fn make_internal(&self, interface: i32) -> Result<(), std::io::Error>;
+            #[cfg(target_os = "android")]
+            fn set_network_constraints(&self, constraints: NetworkConstraints);
         }
     }
 
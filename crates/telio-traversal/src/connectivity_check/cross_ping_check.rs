@@ -81,6 +81,18 @@ macro_rules! do_state_transition {
     }};
 }
 
+/// Endpoint of a peer which passed the connectivity check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidatedEndpoint {
+    /// WireGuard endpoint of the peer
+    pub remote_endpoint: SocketAddr,
+    /// Provider of the local endpoint the check was performed from, `None` if the provider no
+    /// longer reports it
+    pub provider: Option<EndpointProviderType>,
+    /// Time since the endpoint was validated
+    pub since_validated: Duration,
+}
+
 #[cfg_attr(any(test, feature = "mockall"), mockall::automock)]
 #[async_trait]
 pub trait CrossPingCheckTrait {
@@ -88,6 +100,10 @@ pub trait CrossPingCheckTrait {
     async fn get_validated_endpoints(
         &self,
     ) -> Result<HashMap<PublicKey, WireGuardEndpointCandidateChangeEvent>, Error>;
+    async fn get_peer_endpoints(
+        &self,
+        public_key: PublicKey,
+    ) -> Result<Vec<ValidatedEndpoint>, Error>;
     async fn notify_failed_wg_connection(&self, public_key: PublicKey) -> Result<(), Error>;
     async fn notify_successfull_wg_connection_upgrade(
         &self,
@@ -169,6 +185,12 @@ pub struct State<E: Backoff> {
     ///
     /// A closure which produces exponential backoff helpers for the cross ping check sessions.
     exponential_backoff_helper_provider: ExponentialBackoffProvider<E>,
+
+    /// Round trip time above which endpoints are not validated
+    ///
+    /// Peers stay on the relay when the direct path is slower, e.g. over satellite links. The
+    /// session times out and is retried later, so the endpoint is used once the RTT drops.
+    max_rtt: Option<Duration>,
 }
 
 impl<E: Backoff> CrossPingCheck<E> {
@@ -179,6 +201,7 @@ impl<E: Backoff> CrossPingCheck<E> {
         poll_period: Duration,
         ping_pong_handler: Arc<Mutex<PingPongHandler>>,
         exponential_backoff_helper_provider: ExponentialBackoffProvider<E>,
+        max_rtt: Option<Duration>,
     ) -> Self {
         Self {
             task: Task::start(State {
@@ -191,6 +214,7 @@ impl<E: Backoff> CrossPingCheck<E> {
                 poll_timer: interval_at(tokio::time::Instant::now(), poll_period),
                 ping_pong_handler,
                 exponential_backoff_helper_provider,
+                max_rtt,
             }),
         }
     }
@@ -204,6 +228,7 @@ impl CrossPingCheck {
         poll_period: Duration,
         ping_pong_handler: Arc<Mutex<PingPongHandler>>,
         exponential_backoff_bounds: ExponentialBackoffBounds,
+        max_rtt: Option<Duration>,
     ) -> Self {
         telio_log_info!("Starting cross ping check");
 
@@ -216,6 +241,7 @@ impl CrossPingCheck {
             Box::new(move || {
                 ExponentialBackoff::new(exponential_backoff_bounds).map_err(Error::from)
             }),
+            max_rtt,
         )
     }
 
@@ -252,6 +278,35 @@ impl CrossPingCheckTrait for CrossPingCheck {
         res
     }
 
+    async fn get_peer_endpoints(
+        &self,
+        public_key: PublicKey,
+    ) -> Result<Vec<ValidatedEndpoint>, Error> {
+        let res: Result<Vec<ValidatedEndpoint>, Error> = task_exec!(&self.task, async move |s| {
+            Ok(s.endpoint_connectivity_check_state
+                .values()
+                .filter(|v| v.public_key == public_key)
+                .filter_map(|v| match (v.state.clone(), v.last_validated_enpoint) {
+                    (PublishedByPublish(_), Some(ep)) => Some(ValidatedEndpoint {
+                        remote_endpoint: ep,
+                        provider: s
+                            .local_endpoint_cache
+                            .iter()
+                            .find(|(_, candidates)| {
+                                candidates.contains(&v.local_endpoint_candidate)
+                            })
+                            .map(|(provider, _)| provider),
+                        since_validated: v.last_state_transition.elapsed(),
+                    }),
+                    _ => None,
+                })
+                .collect())
+        })
+        .await
+        .map_err(|e| e.into());
+        res
+    }
+
     async fn notify_failed_wg_connection(&self, public_key: PublicKey) -> Result<(), Error> {
         let res: Result<(), Error> = task_exec!(&self.task, async move |s| {
             let sessions = s
@@ -471,6 +526,18 @@ impl<E: Backoff> State<E> {
 
     async fn handle_pong_rx_event(&mut self, event: PongEvent) -> Result<(), Error> {
         let session_id = event.msg.get_session();
+        if let Some(max_rtt) = self.max_rtt {
+            if event.rtt > max_rtt {
+                telio_log_debug!(
+                    "Pong for session {:?} from {:?} took {:?}, more than {:?}. Staying on relay",
+                    session_id,
+                    event.addr,
+                    event.rtt,
+                    max_rtt
+                );
+                return Ok(());
+            }
+        }
         let session = State::get_connectivty_check_state(
             &mut self.endpoint_connectivity_check_state,
             &session_id,
@@ -955,6 +1022,12 @@ mod tests {
     const SESSION_ID: u64 = 0;
 
     fn prepare_checker_test() -> Result<(CrossPingCheck, TestChannels), Error> {
+        prepare_checker_test_with_max_rtt(None)
+    }
+
+    fn prepare_checker_test_with_max_rtt(
+        max_rtt: Option<Duration>,
+    ) -> Result<(CrossPingCheck, TestChannels), Error> {
         let mut endpoint_provider_mock = MockEndpointProvider::new();
         endpoint_provider_mock
             .expect_send_ping()
@@ -982,6 +1055,7 @@ mod tests {
             Duration::from_secs(2),
             Arc::new(Mutex::new(PingPongHandler::new(SecretKey::gen()))),
             ExponentialBackoffBounds::default(),
+            max_rtt,
         );
 
         let channels = TestChannels {
@@ -999,6 +1073,16 @@ mod tests {
         endpoint: SocketAddr,
         original_pub_key: PublicKey,
     ) {
+        let session = start_endpoint_check(channels, endpoint, original_pub_key).await;
+        send_pong(channels, session, Duration::from_millis(100)).await;
+        channels.wg_endpoint_publish_events.recv().await.unwrap();
+    }
+
+    async fn start_endpoint_check(
+        channels: &mut TestChannels,
+        endpoint: SocketAddr,
+        original_pub_key: PublicKey,
+    ) -> Session {
         channels
             .endpoint_change_subscriber
             .send((
@@ -1021,8 +1105,12 @@ mod tests {
             .await
             .unwrap();
         wait_for_tick().await;
+        cmm_init.get_session()
+    }
+
+    async fn send_pong(channels: &mut TestChannels, session: Session, rtt: Duration) {
         let endpoint = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)), 8080);
-        let msg = PingerMsg::ping(WGPort(2), cmm_init.get_session(), 10_u64)
+        let msg = PingerMsg::ping(WGPort(2), session, 10_u64)
             .pong(
                 WGPort(2),
                 &endpoint.ip(),
@@ -1033,12 +1121,11 @@ mod tests {
             .pong_rx_events
             .send(PongEvent {
                 addr: endpoint,
-                rtt: Duration::from_millis(100),
+                rtt,
                 msg,
             })
             .await
             .unwrap();
-        channels.wg_endpoint_publish_events.recv().await.unwrap();
     }
 
     fn prepare_test_session_in_state(
@@ -1078,6 +1165,7 @@ mod tests {
                 peers: Some(vec![peer]),
                 derp_servers: None,
                 dns: None,
+                timestamp: None,
             }))
             .await
             .unwrap();
@@ -1090,6 +1178,36 @@ mod tests {
         assert!(change_event.local_endpoint == endpoint);
     }
 
+    #[tokio::test]
+    async fn slow_endpoints_are_not_validated() {
+        let (checker, mut channels) =
+            prepare_checker_test_with_max_rtt(Some(Duration::from_millis(50))).unwrap();
+        let endpoint = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1)), 8080);
+        let mut peer = Peer::default();
+        let original_pub_key = PublicKey(*b"ABBBBBBBBBBBBBBBBBBBAAAAAAAAAAAA");
+        peer.base.public_key = original_pub_key;
+
+        checker
+            .configure(Some(Config {
+                this: PeerBase::default(),
+                peers: Some(vec![peer]),
+                derp_servers: None,
+                dns: None,
+                timestamp: None,
+            }))
+            .await
+            .unwrap();
+
+        let session = start_endpoint_check(&mut channels, endpoint, original_pub_key).await;
+        send_pong(&mut channels, session, Duration::from_millis(100)).await;
+        wait_for_tick().await;
+        assert!(checker.get_validated_endpoints().await.unwrap().is_empty());
+
+        send_pong(&mut channels, session, Duration::from_millis(10)).await;
+        channels.wg_endpoint_publish_events.recv().await.unwrap();
+        assert!(!checker.get_validated_endpoints().await.unwrap().is_empty());
+    }
+
     #[tokio::test]
     async fn notify_failed_wg_connection() {
         let (checker, mut channels) = prepare_checker_test().unwrap();
@@ -1104,6 +1222,7 @@ mod tests {
                 peers: Some(vec![peer]),
                 derp_servers: None,
                 dns: None,
+                timestamp: None,
             }))
             .await
             .unwrap();
@@ -11,6 +11,7 @@ use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::Arc;
 use std::time::Duration;
 use telio_crypto::PublicKey;
+use telio_model::config::{InterfaceRole, NetworkInterface};
 use telio_proto::{Session, WGPort};
 use telio_sockets::External;
 use telio_task::{io::chan, task_exec, BoxAction, Runtime, Task};
@@ -154,6 +155,11 @@ impl<T: WireGuard, G: GetIfAddrs> LocalInterfacesEndpointProvider<T, G> {
     pub async fn stop(self) {
         let _ = self.task.stop().await.resume_unwind();
     }
+
+    /// Get IPv4 interfaces of the system along with their role in endpoint discovery
+    pub async fn get_interfaces(&self) -> Result<Vec<NetworkInterface>, Error> {
+        task_exec!(&self.task, async move |s| Ok(s.list_interfaces())).await?
+    }
 }
 
 impl<T: WireGuard, G: GetIfAddrs> State<T, G> {
@@ -173,7 +179,7 @@ impl<T: WireGuard, G: GetIfAddrs> State<T, G> {
     }
 
     fn gather_local_interfaces(&self) -> Result<Vec<if_addrs::Interface>, Error> {
-        let shared_range: Ipv4Net = Ipv4Net::new(Ipv4Addr::new(100, 64, 0, 0), 10)?;
+        let shared_range = meshnet_range()?;
         Ok(self
             .get_if_addr
             .get()?
@@ -188,6 +194,28 @@ impl<T: WireGuard, G: GetIfAddrs> State<T, G> {
             .collect())
     }
 
+    fn list_interfaces(&self) -> Result<Vec<NetworkInterface>, Error> {
+        let shared_range = meshnet_range()?;
+        Ok(self
+            .get_if_addr
+            .get()?
+            .into_iter()
+            .filter(|x| !x.addr.is_loopback())
+            .filter_map(|x| match x.addr.ip() {
+                IpAddr::V4(v4) => Some(NetworkInterface {
+                    name: x.name,
+                    ip: IpAddr::V4(v4),
+                    role: if shared_range.contains(&v4) {
+                        InterfaceRole::Tunnel
+                    } else {
+                        InterfaceRole::Primary
+                    },
+                }),
+                IpAddr::V6(_) => None,
+            })
+            .collect())
+    }
+
     async fn poll_local_endpoints(&mut self) -> Result<(), Error> {
         if let Some(candidates_publisher) = self.endpoint_candidates_change_publisher.as_ref() {
             let wg_port = self.get_wg_port().await?;
@@ -257,6 +285,11 @@ impl<T: WireGuard, G: GetIfAddrs> State<T, G> {
     }
 }
 
+/// Shared address range used by libtelio's meshnet network
+fn meshnet_range() -> Result<Ipv4Net, Error> {
+    Ok(Ipv4Net::new(Ipv4Addr::new(100, 64, 0, 0), 10)?)
+}
+
 #[async_trait]
 impl<T: WireGuard, G: GetIfAddrs> Runtime for State<T, G> {
     const NAME: &'static str = "LocalInterfacesEndpointProvider";
@@ -467,6 +500,58 @@ mod tests {
         assert!(interfaces[0].name == "correct");
     }
 
+    #[tokio::test]
+    async fn list_interfaces_with_roles() {
+        let wg_mock = MockWireGuard::new();
+        let mut get_if_addrs_mock = MockGetIfAddrs::new();
+        get_if_addrs_mock.expect_get().return_once(|| {
+            Ok(vec![
+                if_addrs::Interface {
+                    name: "lo".to_owned(),
+                    addr: if_addrs::IfAddr::V4(if_addrs::Ifv4Addr {
+                        ip: Ipv4Addr::new(127, 0, 0, 1),
+                        netmask: Ipv4Addr::new(255, 0, 0, 0),
+                        broadcast: None,
+                    }),
+                },
+                if_addrs::Interface {
+                    name: "en0".to_owned(),
+                    addr: if_addrs::IfAddr::V4(if_addrs::Ifv4Addr {
+                        ip: Ipv4Addr::new(192, 168, 1, 5),
+                        netmask: Ipv4Addr::new(255, 255, 255, 0),
+                        broadcast: None,
+                    }),
+                },
+                if_addrs::Interface {
+                    name: "utun0".to_owned(),
+                    addr: if_addrs::IfAddr::V4(if_addrs::Ifv4Addr {
+                        ip: Ipv4Addr::new(100, 64, 0, 1),
+                        netmask: Ipv4Addr::new(255, 192, 0, 0),
+                        broadcast: None,
+                    }),
+                },
+            ])
+        });
+
+        let state = prepare_state_test(wg_mock, get_if_addrs_mock).await;
+
+        assert_eq!(
+            state.0.list_interfaces().unwrap(),
+            vec![
+                NetworkInterface {
+                    name: "en0".to_owned(),
+                    ip: Ipv4Addr::new(192, 168, 1, 5).into(),
+                    role: InterfaceRole::Primary,
+                },
+                NetworkInterface {
+                    name: "utun0".to_owned(),
+                    ip: Ipv4Addr::new(100, 64, 0, 1).into(),
+                    role: InterfaceRole::Tunnel,
+                },
+            ]
+        );
+    }
+
     fn generate_fake_local_interface(addr_suffix: u8) -> std::io::Result<Vec<if_addrs::Interface>> {
         Ok(vec![if_addrs::Interface {
             name: "random_name".to_owned(),
@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::{net::SocketAddr, sync::Arc, time::Duration};
 
@@ -7,7 +8,7 @@ use async_trait::async_trait;
 use futures::{future::pending, Future};
 use stun_codec::TransactionId;
 use telio_crypto::PublicKey;
-use telio_model::config::Server;
+use telio_model::config::{Server, StunServerStatus};
 use telio_proto::{Session, WGPort};
 use telio_sockets::SocketPool;
 use telio_sockets::{native::AsNativeSocket, External};
@@ -17,7 +18,7 @@ use telio_utils::{
     telio_log_debug, telio_log_error, telio_log_info, telio_log_warn, PinnedSleep,
 };
 use telio_wg::{DynamicWg, WireGuard};
-use tokio::{net::UdpSocket, pin, sync::Mutex};
+use tokio::{net::UdpSocket, pin, sync::Mutex, time::Instant};
 
 use crate::{endpoint_providers::EndpointProviderType, ping_pong_handler::PingPongHandler};
 
@@ -77,6 +78,17 @@ impl<Wg: WireGuard> StunEndpointProvider<Wg> {
         })
         .await;
     }
+
+    /// Forget the known endpoints and search for them right away, regardless of the state
+    ///
+    /// Endpoints are announced again even if they did not change.
+    pub async fn redetect(&self) {
+        let _ = task_exec!(&self.task, async move |s| {
+            s.redetect().await;
+            Ok(())
+        })
+        .await;
+    }
 }
 
 impl<Wg: WireGuard, E: Backoff> StunEndpointProvider<Wg, E> {
@@ -103,6 +115,7 @@ impl<Wg: WireGuard, E: Backoff> StunEndpointProvider<Wg, E> {
                 stun_peer_publisher,
                 stun_state: StunState::WaitingForWg,
                 sockets: None,
+                response_times: HashMap::new(),
             }),
         }
     }
@@ -218,6 +231,9 @@ impl<Wg: WireGuard, E: Backoff> StunEndpointProvider<Wg, E> {
                 });
                 s.servers = servers;
                 s.current_server_index = 0;
+                let servers = &s.servers;
+                s.response_times
+                    .retain(|pk, _| servers.iter().any(|server| server.public_key == *pk));
 
                 if s.stun_peer_publisher
                     .send(s.servers.get(s.current_server_index).cloned())
@@ -242,6 +258,39 @@ impl<Wg: WireGuard, E: Backoff> StunEndpointProvider<Wg, E> {
         let _ = self.task.stop().await.resume_unwind();
     }
 
+    /// Get configured STUN servers along with their last response time
+    ///
+    /// With IPv6 enabled in the config, the IPv6 address of the server peer is reported, if it
+    /// has one.
+    pub async fn get_servers(&self) -> Vec<StunServerStatus> {
+        task_exec!(&self.task, async move |s| {
+            let peers = if s.ipv6_is_enabled() {
+                s.wg.get_interface().await.ok().map(|wg| wg.peers)
+            } else {
+                None
+            };
+            Ok(s.servers
+                .iter()
+                .map(|server| StunServerStatus {
+                    hostname: server.hostname.clone(),
+                    ip: peers
+                        .as_ref()
+                        .and_then(|peers| peers.get(&server.public_key))
+                        .and_then(|peer| peer.allowed_ips.iter().find(|ip| ip.is_ipv6()))
+                        .map(|ip| ip.ip())
+                        .unwrap_or(IpAddr::V4(server.ipv4)),
+                    port: server.stun_plaintext_port,
+                    last_response_ms: s
+                        .response_times
+                        .get(&server.public_key)
+                        .map(|rtt| rtt.as_millis() as u64),
+                })
+                .collect())
+        })
+        .await
+        .unwrap_or_default()
+    }
+
     #[cfg(test)]
     pub async fn get_ext_socket_addr(&self) -> Option<SocketAddr> {
         task_exec!(&self.task, async move |s| {
@@ -397,6 +446,9 @@ struct State<Wg: WireGuard, E: Backoff> {
     stun_state: StunState,
 
     stun_peer_publisher: chan::Tx<Option<StunServer>>,
+
+    /// Time it took for each server (by public key) to resolve the last stun session
+    response_times: HashMap<PublicKey, Duration>,
 }
 
 impl<Wg: WireGuard, E: Backoff> State<Wg, E> {
@@ -447,6 +499,14 @@ impl<Wg: WireGuard, E: Backoff> State<Wg, E> {
         }
     }
 
+    async fn redetect(&mut self) {
+        self.last_candidates.clear();
+        self.stun_session = None;
+        self.exponential_backoff.reset();
+        self.transition_to_wait_for_wg();
+        self.try_transition_to_searching_for_server().await;
+    }
+
     /// Get wg port identified by stun
     fn get_wg_port(&self) -> WGPort {
         if let Some(wg_port) = self.last_candidates.first().map(|c| c.wg.port()) {
@@ -542,6 +602,10 @@ impl<Wg: WireGuard, E: Backoff> State<Wg, E> {
             match session.try_consume(payload, src_addr)? {
                 // Candidate resolved, session is consumed.
                 StunResult::Final(candidate) => {
+                    if let Some(server) = self.servers.get(self.current_server_index) {
+                        self.response_times
+                            .insert(server.public_key, session.started_at.elapsed());
+                    }
                     self.transition_to_has_endpoints_state(candidate).await;
                     return Ok(true);
                 }
@@ -630,6 +694,9 @@ impl<Wg: WireGuard, E: Backoff> State<Wg, E> {
 
         // Clear the session so we can start the next search after backing off
         self.stun_session = None;
+        if let Some(server) = self.servers.get(self.current_server_index) {
+            self.response_times.remove(&server.public_key);
+        }
         // If we have IPv6 and we failed to get IPv6 endpoint we should fallback to IPv4
         if self.is_in_ipv6_mode() {
             telio_log_warn!("Fallback to IPv4");
@@ -796,6 +863,7 @@ impl<Wg: WireGuard, E: Backoff> Runtime for State<Wg, E> {
 struct StunSession {
     wg: StunRequest,
     udp: StunRequest,
+    started_at: Instant,
 }
 
 #[derive(Debug)]
@@ -844,6 +912,7 @@ impl StunSession {
         Ok(Self {
             wg: StunRequest::Waiting(wg, wg_stun.0),
             udp: StunRequest::Waiting(udp, udp_stun.0),
+            started_at: Instant::now(),
         })
     }
 
@@ -1229,6 +1298,39 @@ mod tests {
         env.stun_provider.stop().await;
     }
 
+    #[tokio::test]
+    async fn redetection_announces_same_endpoints() {
+        let mut env = prepare_test_env(None, false).await;
+        env.configure_env().await;
+
+        let udp_endpoint = SocketAddr::new([1, 1, 1, 1].into(), 11111);
+        let wg_endpoint = SocketAddr::new([2, 2, 2, 2].into(), 22222);
+        let expected = vec![EndpointCandidate {
+            udp: udp_endpoint,
+            wg: wg_endpoint,
+        }];
+
+        for _ in 0..2 {
+            await_timeout!(stun_reply(
+                &env.peers[0].stun_sock,
+                XorMappedAddress::new(udp_endpoint)
+            ));
+            await_timeout!(stun_reply(
+                &env.peers[0].peer_sock_v4,
+                MappedAddress::new(wg_endpoint)
+            ));
+
+            let event = await_timeout!(env.change_event.recv());
+            let (provider, candidates) = event.expect("got event");
+            assert_eq!(provider, EndpointProviderType::Stun);
+            assert_eq!(candidates, expected);
+
+            env.stun_provider.redetect().await;
+        }
+
+        env.stun_provider.stop().await;
+    }
+
     #[tokio::test]
     async fn report_empty_candidate_list_on_stun_failure() {
         let ipv6 = false;
@@ -1646,6 +1748,52 @@ mod tests {
         env.stun_provider.stop().await;
     }
 
+    #[tokio::test(start_paused = true)]
+    async fn servers_report_address_of_configured_family() {
+        for ipv6 in [false, true] {
+            let mut env = prepare_test_env_with_server_weights(None, vec![100, 200], ipv6).await;
+
+            env.configure_env().await;
+
+            let servers = env.stun_provider.get_servers().await;
+            assert_eq!(servers.len(), 2);
+            assert!(servers.iter().all(|s| s.ip.is_ipv6() == ipv6));
+
+            env.stun_provider.stop().await;
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn servers_report_last_response_time() {
+        let mut env = prepare_test_env_with_server_weights(None, vec![100, 200], false).await;
+
+        env.configure_env().await;
+
+        tokio::task::yield_now().await;
+
+        let servers = env.stun_provider.get_servers().await;
+        assert_eq!(servers.len(), 2);
+        assert!(servers.iter().all(|s| s.last_response_ms.is_none()));
+
+        env.expect_server_after_session_timeout(1).await;
+        assert!(env
+            .stun_provider
+            .get_servers()
+            .await
+            .iter()
+            .all(|s| s.last_response_ms.is_none()));
+
+        jump_to_next_session_start(Duration::from_millis(10000)).await;
+        env.reply_on_both_sockets(1, IpProto::IPv4).await;
+
+        let servers = env.stun_provider.get_servers().await;
+        assert_eq!(servers[0].last_response_ms, None);
+        assert!(servers[1].last_response_ms.is_some());
+        assert_eq!(servers[1].port, env.stun_servers[1].stun_plaintext_port);
+
+        env.stun_provider.stop().await;
+    }
+
     #[tokio::test(start_paused = true)]
     async fn exponential_backoff() {
         // We need to prepare some more complex mock to test if it is used properly
@@ -1877,9 +2025,12 @@ mod tests {
             async fn get_wg_socket(&self, ipv6: bool) -> Result<Option<i32>, Error>;
             async fn set_secret_key(&self, key: SecretKey) -> Result<(), Error>;
             async fn set_fwmark(&self, fwmark: u32) -> Result<(), Error>;
+            async fn set_listen_port(&self, port: u16) -> Result<(), Error>;
             async fn add_peer(&self, peer: Peer) -> Result<(), Error>;
             async fn del_peer(&self, key: PublicKey) -> Result<(), Error>;
+            async fn reset_statistics(&self, public_key: Option<PublicKey>) -> Result<(), Error>;
             async fn drop_connected_sockets(&self) -> Result<(), Error>;
+            async fn send_keepalive(&self, public_key: PublicKey) -> Result<(), Error>;
             async fn time_since_last_rx(&self, public_key: PublicKey) -> Result<Option<Duration>, Error>;
             async fn time_since_last_endpoint_change(&self, public_key: PublicKey) -> Result<Option<Duration>, Error>;
             async fn stop(self);
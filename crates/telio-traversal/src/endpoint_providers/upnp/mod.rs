@@ -613,9 +613,12 @@ mod tests {
             async fn get_wg_socket(&self, ipv6: bool) -> Result1<Option<i32>>;
             async fn set_secret_key(&self, key: SecretKey) -> Result1<()>;
             async fn set_fwmark(&self, fwmark: u32) -> Result1<()>;
+            async fn set_listen_port(&self, port: u16) -> Result1<()>;
             async fn add_peer(&self, peer: Peer) -> Result1<()>;
             async fn del_peer(&self, key: PublicKey) -> Result1<()>;
+            async fn reset_statistics(&self, public_key: Option<PublicKey>) -> Result1<()>;
             async fn drop_connected_sockets(&self) -> Result1<()>;
+            async fn send_keepalive(&self, public_key: PublicKey) -> Result1<()>;
             async fn time_since_last_rx(&self, public_key: PublicKey) -> Result1<Option<Duration>>;
             async fn time_since_last_endpoint_change(&self, public_key: PublicKey) -> Result1<Option<Duration>>;
             async fn stop(self);
@@ -2,7 +2,7 @@ use async_trait::async_trait;
 use futures::Future;
 use std::collections::HashMap;
 use std::net::SocketAddr;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use telio_crypto::PublicKey;
 use telio_proto::UpgradeMsg;
 use telio_task::{io::chan, io::Chan, task_exec, BoxAction, Runtime, Task};
@@ -26,10 +26,19 @@ pub enum Error {
     Task(#[from] telio_task::ExecError),
     #[error("Upgrade request not found or expired")]
     NotFound,
+    /// Received upgrade message is not newer than the last accepted one
+    #[error("Upgrade message sequence {got} is not newer than {last}")]
+    Replayed { got: u64, last: u64 },
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// How far past `u64::MAX` a sequence number may wrap and still be accepted as newer
+const SEQUENCE_WRAP_WINDOW: u64 = 64;
+
+/// Sequence decoded from messages of peers which do not number them
+const UNSEQUENCED: u64 = 0;
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct UpgradeRequest {
     pub endpoint: SocketAddr,
@@ -65,6 +74,10 @@ pub struct State {
     upgrade_requests: HashMap<PublicKey, UpgradeRequest>,
     expiration_period: Duration,
     poll_timer: Interval,
+    /// Sequence number of the next upgrade message we send
+    next_sequence: u64,
+    /// Sequence number of the last accepted upgrade message from each peer
+    last_sequences: HashMap<PublicKey, u64>,
 }
 
 impl UpgradeSync {
@@ -81,6 +94,8 @@ impl UpgradeSync {
                 upgrade_requests: Default::default(),
                 expiration_period,
                 poll_timer: interval_at(Instant::now(), expiration_period / 2),
+                next_sequence: initial_sequence(),
+                last_sequences: Default::default(),
             }),
         })
     }
@@ -158,10 +173,12 @@ impl State {
                 *public_key,
                 UpgradeMsg {
                     endpoint: local_endpoint,
+                    sequence: self.next_sequence,
                 },
             ))
             .await
             .map_err(Error::SendUpgradeMsgErr)?;
+        self.next_sequence = next_sequence(self.next_sequence);
 
         // Insert endpoint to local end to force our side to keep the endpoint too
         self.upgrade_requests.insert(
@@ -186,6 +203,19 @@ impl State {
             upgrade_msg.endpoint
         );
 
+        match (upgrade_msg.sequence, self.last_sequences.get(public_key)) {
+            // Older peers do not number their messages, so they cannot be checked for replays
+            (UNSEQUENCED, None) => (),
+            // Peer numbering its messages does not send unsequenced ones, those are replays of
+            // older messages or have the sequence stripped
+            (got, Some(&last)) if got == UNSEQUENCED || !is_newer_sequence(got, last) => {
+                return Err(Error::Replayed { got, last });
+            }
+            (got, _) => {
+                self.last_sequences.insert(*public_key, got);
+            }
+        }
+
         let new_request = UpgradeRequest {
             endpoint: upgrade_msg.endpoint,
             requested_at: Instant::now(),
@@ -240,6 +270,28 @@ impl State {
     }
 }
 
+/// Start numbering from current time, so that sequence keeps increasing across restarts
+fn initial_sequence() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(UNSEQUENCED, |since_epoch| since_epoch.as_millis() as u64)
+        .max(UNSEQUENCED + 1)
+}
+
+/// Sequence following `sequence`, skipping the one of unsequenced messages when wrapping
+fn next_sequence(sequence: u64) -> u64 {
+    match sequence.wrapping_add(1) {
+        UNSEQUENCED => UNSEQUENCED + 1,
+        next => next,
+    }
+}
+
+/// Check whether `sequence` follows `last`, allowing it to wrap around `u64::MAX`
+fn is_newer_sequence(sequence: u64, last: u64) -> bool {
+    let ahead = sequence.wrapping_sub(last);
+    sequence > last || (ahead > 0 && ahead <= SEQUENCE_WRAP_WINDOW)
+}
+
 #[async_trait]
 impl Runtime for State {
     const NAME: &'static str = "UpgradeSync";
@@ -309,6 +361,7 @@ mod tests {
 
         let upg_msg = UpgradeMsg {
             endpoint: "127.0.0.1:6666".parse().unwrap(),
+            sequence: 1,
         };
 
         let pk = "REjdn4zY2TFx2AMujoNGPffo9vDiRDXpGG4jHPtx2AY="
@@ -364,6 +417,7 @@ mod tests {
 
         let upg_msg = UpgradeMsg {
             endpoint: "127.0.0.1:6666".parse().unwrap(),
+            sequence: 1,
         };
 
         let pk = "REjdn4zY2TFx2AMujoNGPffo9vDiRDXpGG4jHPtx2AY="
@@ -407,4 +461,141 @@ mod tests {
         .await
         .unwrap();
     }
+
+    #[test]
+    fn accept_in_order_sequences() {
+        assert!(is_newer_sequence(1, 0));
+        assert!(is_newer_sequence(2, 1));
+        assert!(is_newer_sequence(100, 2));
+    }
+
+    #[test]
+    fn reject_out_of_order_and_replayed_sequences() {
+        assert!(!is_newer_sequence(1, 2));
+        assert!(!is_newer_sequence(2, 2));
+        assert!(!is_newer_sequence(0, u64::MAX / 2));
+    }
+
+    #[test]
+    fn accept_wrapped_sequences_within_window() {
+        assert!(is_newer_sequence(0, u64::MAX));
+        assert!(is_newer_sequence(SEQUENCE_WRAP_WINDOW - 1, u64::MAX));
+        assert!(!is_newer_sequence(SEQUENCE_WRAP_WINDOW, u64::MAX));
+        assert!(!is_newer_sequence(u64::MAX, 0));
+    }
+
+    #[test]
+    fn own_sequence_is_never_unsequenced() {
+        assert_ne!(initial_sequence(), UNSEQUENCED);
+        assert_eq!(next_sequence(u64::MAX), UNSEQUENCED + 1);
+        assert!(is_newer_sequence(next_sequence(u64::MAX), u64::MAX));
+    }
+
+    #[tokio::test]
+    async fn accept_consecutive_unsequenced_requests() {
+        const EXPIRY: Duration = Duration::from_secs(10);
+        let (upg_sync, mut upg_rq_rx, intercoms_them) = setup(EXPIRY);
+
+        let pk = "REjdn4zY2TFx2AMujoNGPffo9vDiRDXpGG4jHPtx2AY="
+            .parse::<PublicKey>()
+            .unwrap();
+        let upg_msg = |port: u16| UpgradeMsg {
+            endpoint: SocketAddr::from(([127, 0, 0, 1], port)),
+            sequence: UNSEQUENCED,
+        };
+
+        for msg in [upg_msg(1000), upg_msg(1001)] {
+            intercoms_them.tx.send((pk, msg)).await.unwrap();
+        }
+
+        let first = time::timeout(Duration::from_secs(1), upg_rq_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(first.new_request.unwrap().endpoint.port(), 1000);
+
+        let second = time::timeout(Duration::from_secs(1), upg_rq_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(second.old_request.unwrap().endpoint.port(), 1000);
+        assert_eq!(second.new_request.unwrap().endpoint.port(), 1001);
+
+        upg_sync.stop().await;
+    }
+
+    #[tokio::test]
+    async fn drop_replayed_and_out_of_order_requests() {
+        const EXPIRY: Duration = Duration::from_secs(10);
+        let (upg_sync, mut upg_rq_rx, intercoms_them) = setup(EXPIRY);
+
+        let pk = "REjdn4zY2TFx2AMujoNGPffo9vDiRDXpGG4jHPtx2AY="
+            .parse::<PublicKey>()
+            .unwrap();
+        let upg_msg = |port: u16, sequence: u64| UpgradeMsg {
+            endpoint: SocketAddr::from(([127, 0, 0, 1], port)),
+            sequence,
+        };
+
+        for msg in [
+            upg_msg(1000, 5),
+            upg_msg(1001, 3),
+            upg_msg(1002, 5),
+            upg_msg(1003, 6),
+        ] {
+            intercoms_them.tx.send((pk, msg)).await.unwrap();
+        }
+
+        let first = time::timeout(Duration::from_secs(1), upg_rq_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(first.new_request.unwrap().endpoint.port(), 1000);
+
+        let second = time::timeout(Duration::from_secs(1), upg_rq_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(second.old_request.unwrap().endpoint.port(), 1000);
+        assert_eq!(second.new_request.unwrap().endpoint.port(), 1003);
+
+        upg_sync.stop().await;
+    }
+
+    #[tokio::test]
+    async fn drop_unsequenced_requests_after_sequenced_ones() {
+        const EXPIRY: Duration = Duration::from_secs(10);
+        let (upg_sync, mut upg_rq_rx, intercoms_them) = setup(EXPIRY);
+
+        let pk = "REjdn4zY2TFx2AMujoNGPffo9vDiRDXpGG4jHPtx2AY="
+            .parse::<PublicKey>()
+            .unwrap();
+        let upg_msg = |port: u16, sequence: u64| UpgradeMsg {
+            endpoint: SocketAddr::from(([127, 0, 0, 1], port)),
+            sequence,
+        };
+
+        for msg in [
+            upg_msg(1000, 5),
+            upg_msg(1001, UNSEQUENCED),
+            upg_msg(1002, 6),
+        ] {
+            intercoms_them.tx.send((pk, msg)).await.unwrap();
+        }
+
+        let first = time::timeout(Duration::from_secs(1), upg_rq_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(first.new_request.unwrap().endpoint.port(), 1000);
+
+        let second = time::timeout(Duration::from_secs(1), upg_rq_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(second.old_request.unwrap().endpoint.port(), 1000);
+        assert_eq!(second.new_request.unwrap().endpoint.port(), 1002);
+
+        upg_sync.stop().await;
+    }
 }
@@ -282,11 +282,39 @@ impl<Key: Clone + Eq + Hash, Value> LruCache<Key, Value> {
         None
     }
 
+    /// Returns a mutable reference to the value with the given `key`, if present and not expired,
+    /// without updating the timestamp.
+    pub fn peek_mut<Q: ?Sized>(&mut self, key: &Q) -> Option<&mut Value>
+    where
+        Key: Borrow<Q>,
+        Q: Hash + Eq,
+    {
+        let ttl = self.ttl;
+        match self.map.get_mut(key) {
+            Some(timed_value) if !timed_value.is_expired(ttl, Instant::now()) => {
+                Some(&mut timed_value.data)
+            }
+            _ => None,
+        }
+    }
+
     /// Returns an iterator over all (key, value) pairs
     pub fn iter(&self) -> impl Iterator<Item = (&Key, &Value)> {
         self.map.iter().map(|(key, val)| (key, &val.data))
     }
 
+    /// Returns an iterator over all non-expired (key, value) pairs, starting from the most
+    /// recently accessed one, together with the time elapsed since their last access
+    pub fn iter_recent(&self) -> impl Iterator<Item = (&Key, &Value, Duration)> {
+        let now = Instant::now();
+        let ttl = self.ttl;
+        self.map
+            .iter()
+            .rev()
+            .take_while(move |(_, timed_value)| !timed_value.is_expired(ttl, now))
+            .map(move |(key, timed_value)| (key, &timed_value.data, now - timed_value.last_access))
+    }
+
     /// Removes expired items from the cache and returns all removed keys.
     fn remove_expired(&mut self) -> (Instant, Vec<Key>) {
         let now = Instant::now();
@@ -505,6 +533,47 @@ mod tests {
         assert_eq!(None, lru_cache.peek(&0));
     }
 
+    #[test]
+    fn peek_mut_does_not_update_time() {
+        let time_to_live = Duration::from_millis(500);
+        let mut lru_cache = LruCache::<usize, usize>::new(time_to_live, usize::MAX);
+
+        let _ = lru_cache.insert(0, 0);
+
+        advance_time_by_ms(300);
+        *lru_cache.peek_mut(&0).unwrap() += 1;
+        assert_eq!(Some(&1), lru_cache.peek(&0));
+        advance_time_by_ms(300);
+        assert_eq!(None, lru_cache.peek_mut(&0));
+    }
+
+    #[test]
+    fn iter_recent_starts_from_most_recently_accessed() {
+        let time_to_live = Duration::from_millis(500);
+        let mut lru_cache = LruCache::<usize, usize>::new(time_to_live, usize::MAX);
+
+        let _ = lru_cache.insert(0, 0);
+        advance_time_by_ms(100);
+        let _ = lru_cache.insert(1, 1);
+        advance_time_by_ms(100);
+        let _ = lru_cache.insert(2, 2);
+        advance_time_by_ms(100);
+        lru_cache.get(&0);
+        advance_time_by_ms(350);
+
+        let recent: Vec<_> = lru_cache
+            .iter_recent()
+            .map(|(k, _, idle)| (*k, idle))
+            .collect();
+        assert_eq!(
+            recent,
+            vec![
+                (0, Duration::from_millis(350)),
+                (2, Duration::from_millis(450))
+            ]
+        );
+    }
+
     mod remove_expired {
         use super::*;
 
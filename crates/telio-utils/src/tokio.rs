@@ -41,6 +41,14 @@ impl ThreadTracker {
         self.statuses.values().all(|s| *s == ThreadStatus::Parked)
     }
 
+    /// Number of tokio threads which were started and have not stopped yet
+    pub fn live_threads(&self) -> usize {
+        self.statuses
+            .values()
+            .filter(|s| **s != ThreadStatus::Stopped)
+            .count()
+    }
+
     /// Tokio runtime callback
     pub fn on_thread_start(&mut self) {
         self.set_status(ThreadStatus::Started);
@@ -93,3 +101,29 @@ impl Monitor for Arc<parking_lot::Mutex<ThreadTracker>> {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn count_live_threads() {
+        let mut tracker = ThreadTracker::default();
+        assert_eq!(tracker.live_threads(), 0);
+
+        tracker.on_thread_start();
+        tracker.on_thread_park();
+        assert_eq!(tracker.live_threads(), 1);
+
+        let mut tracker = std::thread::spawn(move || {
+            tracker.on_thread_start();
+            tracker
+        })
+        .join()
+        .unwrap();
+        assert_eq!(tracker.live_threads(), 2);
+
+        tracker.on_thread_stop();
+        assert_eq!(tracker.live_threads(), 1);
+    }
+}
@@ -7,6 +7,10 @@ mod boring;
 #[cfg_attr(docsrs, doc(cfg(target_os = "linux")))]
 mod linux_native_wg;
 
+#[cfg(any(not(windows), doc))]
+#[cfg_attr(docsrs, doc(cfg(not(windows))))]
+mod packet;
+
 #[cfg(any(windows, doc))]
 #[cfg_attr(docsrs, doc(cfg(windows)))]
 mod wireguard_go;
@@ -19,10 +23,10 @@ use async_trait::async_trait;
 #[cfg(any(test, feature = "test-adapter"))]
 pub use mockall::automock;
 use std::{
-    io,
+    fmt, io,
     net::{IpAddr, Ipv4Addr},
     str::FromStr,
-    sync::Arc,
+    sync::{Arc, Mutex},
 };
 use telio_crypto::PublicKey;
 use telio_sockets::{Protect, SocketPool};
@@ -37,6 +41,36 @@ pub type FirewallCb = Option<Arc<dyn Fn(&[u8; 32], &[u8]) -> bool + Send + Sync>
 pub type FirewallResetConnsCb =
     Option<Arc<dyn Fn(&PublicKey, Ipv4Addr, &mut dyn io::Write, &mut dyn io::Write) + Send + Sync>>;
 
+#[cfg(any(not(windows), doc))]
+pub use packet::{PacketAdapter, Transport, Tunnel};
+
+/// Factory of an adapter implemented outside of libtelio, called on every adapter start with
+/// the name of the tunnel interface, its file descriptor, if given, and the inbound and outbound
+/// firewall callbacks the adapter must pass plaintext packets through
+pub type AdapterFactory = Arc<
+    dyn Fn(&str, Option<Tun>, FirewallCb, FirewallCb) -> Result<Box<dyn Adapter>, Error>
+        + Send
+        + Sync,
+>;
+
+/// Last failure of the adapter subsystem, kept apart from errors of other components
+#[derive(Clone, Debug, Default)]
+pub struct AdapterErrorBuffer(Arc<Mutex<Option<String>>>);
+
+impl AdapterErrorBuffer {
+    /// Replace the last failure
+    pub fn set(&self, err: impl fmt::Display) {
+        if let Ok(mut last) = self.0.lock() {
+            *last = Some(err.to_string());
+        }
+    }
+
+    /// Last failure, `None` if no adapter operation has failed yet
+    pub fn get(&self) -> Option<String> {
+        self.0.lock().ok().and_then(|last| last.clone())
+    }
+}
+
 /// Tunnel file descriptor
 #[cfg(not(target_os = "windows"))]
 #[cfg_attr(docsrs, doc(cfg(not(windows))))]
@@ -184,6 +218,26 @@ impl Default for AdapterType {
     }
 }
 
+impl AdapterType {
+    /// Adapter types which can be started on this platform
+    ///
+    /// Linux native adapter is reported only if the kernel supports WireGuard.
+    pub fn available() -> Vec<AdapterType> {
+        let mut adapters = Vec::new();
+        if cfg!(unix) {
+            adapters.push(AdapterType::BoringTun);
+        }
+        if cfg!(target_os = "linux") && std::path::Path::new("/sys/module/wireguard").exists() {
+            adapters.push(AdapterType::LinuxNativeWg);
+        }
+        if cfg!(windows) {
+            adapters.push(AdapterType::WireguardGo);
+            adapters.push(AdapterType::WindowsNativeWg);
+        }
+        adapters
+    }
+}
+
 impl FromStr for AdapterType {
     type Err = Error;
 
@@ -0,0 +1,817 @@
+//! WireGuard adapter exchanging encrypted packets through a transport given by the application
+//!
+//! WireGuard protocol runs in a boringtun `Tunn` per peer. Plaintext packets are read from and
+//! written to the tunnel interface, encrypted ones are handed to the [`Transport`], which decides
+//! how they reach the peer, e.g. over a transport other than UDP. Plaintext packets pass the
+//! firewall callbacks the same way they do in the boringtun adapter.
+
+use async_trait::async_trait;
+use boringtun::{
+    device::tun::TunSocket,
+    noise::{errors::WireGuardError, Tunn, TunnResult},
+};
+use ipnetwork::IpNetwork;
+use std::{
+    collections::{BTreeMap, HashSet},
+    convert::{TryFrom, TryInto},
+    io, mem,
+    net::{IpAddr, SocketAddr},
+    os::unix::io::AsRawFd,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+use telio_crypto::{PresharedKey, PublicKey, SecretKey};
+use telio_utils::{telio_log_debug, telio_log_warn};
+use wireguard_uapi::xplatform::set;
+use x25519_dalek::{PublicKey as PublicKeyDalek, StaticSecret};
+
+use super::{Adapter, Error as AdapterError, FirewallCb, Tun as NativeTun};
+use crate::uapi::{Cmd, Interface, Peer, Response};
+
+/// Largest plaintext packet read from the tunnel
+const MAX_PACKET: usize = 65535 - WG_OVERHEAD;
+/// Bytes added by WireGuard to every packet
+const WG_OVERHEAD: usize = 32;
+/// Size of the buffers encrypted packets are written to
+const BUFFER_SIZE: usize = 65536;
+/// How often WireGuard timers, e.g. handshake retries and keepalives, are checked
+const TIMER_INTERVAL: Duration = Duration::from_millis(250);
+/// Longest time threads block on I/O before checking whether the adapter was stopped
+const POLL_TIMEOUT: Duration = Duration::from_millis(100);
+/// Session indices of boringtun are 24 bits long
+const INDEX_MASK: u32 = 0x00ff_ffff;
+
+/// Carries encrypted WireGuard packets between the peers
+pub trait Transport: Send + Sync {
+    /// Send the packet to the peer
+    fn send_packet(&self, peer: &PublicKey, packet: &[u8]) -> io::Result<()>;
+
+    /// Wait up to `timeout` for a packet, returns its sender and length, `None` on timeout
+    fn recv_packet(
+        &self,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> io::Result<Option<(PublicKey, usize)>>;
+
+    /// Add or update the peer with its endpoint, or remove it
+    fn set_peer(
+        &self,
+        peer: &PublicKey,
+        endpoint: Option<SocketAddr>,
+        remove: bool,
+    ) -> io::Result<()>;
+}
+
+/// Exchanges plaintext packets with the system
+pub trait Tunnel: Send + Sync {
+    /// Wait up to `timeout` for a packet, returns its length, `None` on timeout
+    fn read_packet(&self, buf: &mut [u8], timeout: Duration) -> io::Result<Option<usize>>;
+
+    /// Deliver the packet to the system
+    fn write_packet(&self, packet: &[u8]) -> io::Result<()>;
+}
+
+impl Tunnel for TunSocket {
+    fn read_packet(&self, buf: &mut [u8], timeout: Duration) -> io::Result<Option<usize>> {
+        let mut fd = libc::pollfd {
+            fd: self.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout = timeout.as_millis().try_into().unwrap_or(libc::c_int::MAX);
+        // Safety: fd is a single valid pollfd
+        match unsafe { libc::poll(&mut fd, 1, timeout) } {
+            0 => Ok(None),
+            res if res < 0 => Err(io::Error::last_os_error()),
+            _ => self
+                .read(buf)
+                .map(|packet| Some(packet.len()))
+                .map_err(|err| io::Error::new(io::ErrorKind::Other, format!("{:?}", err))),
+        }
+    }
+
+    fn write_packet(&self, packet: &[u8]) -> io::Result<()> {
+        let written = match packet.first().map(|byte| byte >> 4) {
+            Some(4) => self.write4(packet),
+            Some(6) => self.write6(packet),
+            _ => return Err(io::ErrorKind::InvalidInput.into()),
+        };
+        if written == packet.len() {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+}
+
+/// WireGuard session with the peer and its configuration
+struct PeerTunnel {
+    config: Peer,
+    tunn: Arc<Tunn>,
+}
+
+/// Configuration of the adapter
+#[derive(Default)]
+struct State {
+    private_key: Option<SecretKey>,
+    peers: BTreeMap<PublicKey, PeerTunnel>,
+    next_index: u32,
+}
+
+/// Parts of the adapter shared with its threads
+struct Shared {
+    transport: Arc<dyn Transport>,
+    tunnel: Arc<dyn Tunnel>,
+    state: Mutex<State>,
+    stopped: AtomicBool,
+    firewall_process_inbound_callback: FirewallCb,
+    firewall_process_outbound_callback: FirewallCb,
+}
+
+/// Userspace WireGuard sending encrypted packets through a [`Transport`]
+pub struct PacketAdapter {
+    shared: Arc<Shared>,
+    threads: Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl PacketAdapter {
+    /// Start the adapter on the tunnel interface `name`, or on `tun` if given
+    pub fn start(
+        name: &str,
+        tun: Option<NativeTun>,
+        transport: Arc<dyn Transport>,
+        firewall_process_inbound_callback: FirewallCb,
+        firewall_process_outbound_callback: FirewallCb,
+    ) -> Result<Self, AdapterError> {
+        let tunnel = match tun {
+            Some(tun) => TunSocket::new_from_fd(tun)?,
+            None => TunSocket::new(name)?,
+        };
+        Self::with_tunnel(
+            transport,
+            Arc::new(tunnel),
+            firewall_process_inbound_callback,
+            firewall_process_outbound_callback,
+        )
+    }
+
+    /// Start the adapter exchanging plaintext packets with `tunnel`
+    pub fn with_tunnel(
+        transport: Arc<dyn Transport>,
+        tunnel: Arc<dyn Tunnel>,
+        firewall_process_inbound_callback: FirewallCb,
+        firewall_process_outbound_callback: FirewallCb,
+    ) -> Result<Self, AdapterError> {
+        let shared = Arc::new(Shared {
+            transport,
+            tunnel,
+            state: Mutex::new(State::default()),
+            stopped: AtomicBool::new(false),
+            firewall_process_inbound_callback,
+            firewall_process_outbound_callback,
+        });
+
+        let loops: [(&str, fn(&Shared)); 3] = [
+            ("telio-wg-outbound", Shared::run_outbound),
+            ("telio-wg-inbound", Shared::run_inbound),
+            ("telio-wg-timers", Shared::run_timers),
+        ];
+        let mut threads = Vec::with_capacity(loops.len());
+        for (name, run) in loops {
+            let thread_shared = shared.clone();
+            match thread::Builder::new()
+                .name(name.to_owned())
+                .spawn(move || run(&thread_shared))
+            {
+                Ok(thread) => threads.push(thread),
+                Err(err) => {
+                    shared.stopped.store(true, Ordering::Relaxed);
+                    join(threads);
+                    return Err(err.into());
+                }
+            }
+        }
+
+        Ok(Self {
+            shared,
+            threads: Mutex::new(threads),
+        })
+    }
+}
+
+impl Drop for PacketAdapter {
+    fn drop(&mut self) {
+        // Threads exit on their own within the poll timeout
+        self.shared.stopped.store(true, Ordering::Relaxed);
+    }
+}
+
+#[async_trait]
+impl Adapter for PacketAdapter {
+    async fn stop(&self) {
+        self.shared.stopped.store(true, Ordering::Relaxed);
+        let threads = match self.threads.lock() {
+            Ok(mut threads) => mem::take(&mut *threads),
+            Err(_) => return,
+        };
+        join(threads);
+    }
+
+    fn get_adapter_luid(&self) -> u64 {
+        0
+    }
+
+    async fn send_uapi_cmd(&self, cmd: &Cmd) -> Result<Response, AdapterError> {
+        let mut state = self.shared.lock()?;
+        match cmd {
+            Cmd::Get => Ok(Response {
+                errno: 0,
+                interface: Some(state.interface()),
+            }),
+            Cmd::Set(device) => {
+                state.apply(device, self.shared.transport.as_ref())?;
+                Ok(Response {
+                    errno: 0,
+                    interface: None,
+                })
+            }
+        }
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, State>, AdapterError> {
+        self.state
+            .lock()
+            .map_err(|_| AdapterError::InternalError("Packet adapter state is poisoned"))
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+
+    fn send(&self, peer: &PublicKey, packet: &[u8]) {
+        if let Err(err) = self.transport.send_packet(peer, packet) {
+            telio_log_debug!("Failed to send packet to {:?}: {}", peer, err);
+        }
+    }
+
+    /// Encrypt packets read from the tunnel and send them to the peers routing their destination
+    fn run_outbound(&self) {
+        let mut buf = vec![0u8; MAX_PACKET];
+        let mut out = vec![0u8; BUFFER_SIZE];
+        while !self.is_stopped() {
+            let len = match self.tunnel.read_packet(&mut buf, POLL_TIMEOUT) {
+                Ok(Some(len)) => len,
+                Ok(None) => continue,
+                Err(err) => {
+                    telio_log_warn!("Failed to read from tunnel: {}", err);
+                    thread::sleep(POLL_TIMEOUT);
+                    continue;
+                }
+            };
+            let packet = buf.get(..len).unwrap_or_default();
+            let route = destination(packet)
+                .and_then(|dst| self.state.lock().ok().and_then(|state| state.route(dst)));
+            let Some((peer, tunn)) = route else {
+                continue;
+            };
+            if !accepts(&self.firewall_process_outbound_callback, &peer, packet) {
+                continue;
+            }
+            match tunn.encapsulate(packet, &mut out) {
+                TunnResult::WriteToNetwork(packet) => self.send(&peer, packet),
+                TunnResult::Err(err) => {
+                    telio_log_debug!("Failed to encapsulate packet for {:?}: {:?}", peer, err)
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Decrypt packets received from the transport and write them to the tunnel
+    fn run_inbound(&self) {
+        let mut buf = vec![0u8; BUFFER_SIZE];
+        let mut out = vec![0u8; BUFFER_SIZE];
+        while !self.is_stopped() {
+            let (peer, len) = match self.transport.recv_packet(&mut buf, POLL_TIMEOUT) {
+                Ok(Some(received)) => received,
+                Ok(None) => continue,
+                Err(err) => {
+                    telio_log_warn!("Failed to receive from transport: {}", err);
+                    thread::sleep(POLL_TIMEOUT);
+                    continue;
+                }
+            };
+            let session = self.state.lock().ok().and_then(|state| {
+                state
+                    .peers
+                    .get(&peer)
+                    .map(|p| (p.tunn.clone(), p.config.allowed_ips.clone()))
+            });
+            let Some((tunn, allowed_ips)) = session else {
+                telio_log_debug!("Dropping packet from unknown peer {:?}", peer);
+                continue;
+            };
+
+            let mut datagram = buf.get(..len).unwrap_or_default();
+            loop {
+                match tunn.decapsulate(None, datagram, &mut out) {
+                    // Handshake responses and packets queued until the handshake completed
+                    TunnResult::WriteToNetwork(packet) => {
+                        self.send(&peer, packet);
+                        datagram = &[];
+                        continue;
+                    }
+                    TunnResult::WriteToTunnelV4(packet, src) => {
+                        self.deliver(&peer, &allowed_ips, packet, src.into())
+                    }
+                    TunnResult::WriteToTunnelV6(packet, src) => {
+                        self.deliver(&peer, &allowed_ips, packet, src.into())
+                    }
+                    TunnResult::Err(err) => {
+                        telio_log_debug!("Failed to decapsulate packet from {:?}: {:?}", peer, err)
+                    }
+                    TunnResult::Done => (),
+                }
+                break;
+            }
+        }
+    }
+
+    /// Write the packet to the tunnel if its source is allowed for the peer and the firewall
+    /// accepts it
+    fn deliver(&self, peer: &PublicKey, allowed_ips: &[IpNetwork], packet: &[u8], src: IpAddr) {
+        if !allowed_ips.iter().any(|network| network.contains(src)) {
+            telio_log_debug!("Dropping packet from disallowed source {}", src);
+            return;
+        }
+        if !accepts(&self.firewall_process_inbound_callback, peer, packet) {
+            return;
+        }
+        if let Err(err) = self.tunnel.write_packet(packet) {
+            telio_log_warn!("Failed to write to tunnel: {}", err);
+        }
+    }
+
+    /// Drive handshakes and keepalives of every peer
+    fn run_timers(&self) {
+        let mut out = vec![0u8; BUFFER_SIZE];
+        while !self.is_stopped() {
+            thread::sleep(TIMER_INTERVAL);
+            let sessions: Vec<_> = match self.state.lock() {
+                Ok(state) => state
+                    .peers
+                    .iter()
+                    .map(|(public_key, peer)| (*public_key, peer.tunn.clone()))
+                    .collect(),
+                Err(_) => return,
+            };
+            for (peer, tunn) in sessions {
+                match tunn.update_timers(&mut out) {
+                    TunnResult::WriteToNetwork(packet) => self.send(&peer, packet),
+                    TunnResult::Err(WireGuardError::ConnectionExpired) => (),
+                    TunnResult::Err(err) => {
+                        telio_log_debug!("Failed to update timers of {:?}: {:?}", peer, err)
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+}
+
+impl State {
+    fn interface(&self) -> Interface {
+        Interface {
+            private_key: self.private_key,
+            listen_port: None,
+            fwmark: 0,
+            peers: self
+                .peers
+                .iter()
+                .map(|(public_key, peer)| {
+                    let (since_handshake, tx_bytes, rx_bytes, ..) = peer.tunn.stats();
+                    let stats = Peer {
+                        rx_bytes: Some(rx_bytes as u64),
+                        tx_bytes: Some(tx_bytes as u64),
+                        time_since_last_handshake: since_handshake,
+                        ..peer.config.clone()
+                    };
+                    (*public_key, stats)
+                })
+                .collect(),
+        }
+    }
+
+    /// Peer whose allowed IPs contain `dst` with the longest prefix
+    fn route(&self, dst: IpAddr) -> Option<(PublicKey, Arc<Tunn>)> {
+        self.peers
+            .iter()
+            .flat_map(|(public_key, peer)| {
+                peer.config
+                    .allowed_ips
+                    .iter()
+                    .filter(move |network| network.contains(dst))
+                    .map(move |network| (network.prefix(), public_key, peer))
+            })
+            .max_by_key(|(prefix, ..)| *prefix)
+            .map(|(_, public_key, peer)| (*public_key, peer.tunn.clone()))
+    }
+
+    fn apply(
+        &mut self,
+        device: &set::Device,
+        transport: &dyn Transport,
+    ) -> Result<(), AdapterError> {
+        if let Some(private_key) = device.private_key.map(SecretKey::new) {
+            if self.private_key != Some(private_key) {
+                self.private_key = Some(private_key);
+                // Sessions are bound to the static key, so all of them start over
+                let public_keys: Vec<_> = self.peers.keys().copied().collect();
+                for public_key in public_keys {
+                    let config = match self.peers.get(&public_key) {
+                        Some(peer) => peer.config.clone(),
+                        None => continue,
+                    };
+                    let tunn = self.new_tunn(&config)?;
+                    self.peers.insert(public_key, PeerTunnel { config, tunn });
+                }
+            }
+        }
+
+        if device.replace_peers == Some(true) {
+            let listed: HashSet<_> = device
+                .peers
+                .iter()
+                .map(|peer| PublicKey(peer.public_key))
+                .collect();
+            let removed: Vec<_> = self
+                .peers
+                .keys()
+                .filter(|public_key| !listed.contains(public_key))
+                .copied()
+                .collect();
+            for public_key in removed {
+                self.remove_peer(&public_key, transport)?;
+            }
+        }
+
+        for peer in &device.peers {
+            self.apply_peer(peer, transport)?;
+        }
+        Ok(())
+    }
+
+    fn apply_peer(
+        &mut self,
+        peer: &set::Peer,
+        transport: &dyn Transport,
+    ) -> Result<(), AdapterError> {
+        let public_key = PublicKey(peer.public_key);
+        if peer.remove == Some(true) {
+            return self.remove_peer(&public_key, transport);
+        }
+
+        let existing = self
+            .peers
+            .get(&public_key)
+            .map(|existing| (existing.config.clone(), existing.tunn.clone()));
+        if existing.is_none() && peer.update_only == Some(true) {
+            return Ok(());
+        }
+
+        let mut config = existing
+            .as_ref()
+            .map(|(config, _)| config.clone())
+            .unwrap_or_else(|| Peer {
+                public_key,
+                ..Default::default()
+            });
+        if let Some(endpoint) = peer.endpoint {
+            config.endpoint = Some(endpoint);
+        }
+        if let Some(keepalive) = peer.persistent_keepalive_interval {
+            config.persistent_keepalive_interval = Some(keepalive.into());
+        }
+        if let Some(psk) = peer.preshared_key {
+            // All-zero key removes the pre-shared key
+            config.preshared_key = (psk != [0; 32]).then_some(PresharedKey(psk));
+        }
+        if peer.replace_allowed_ips == Some(true) {
+            config.allowed_ips.clear();
+        }
+        for ip in &peer.allowed_ips {
+            if let Ok(network) = IpNetwork::new(ip.ipaddr, ip.cidr_mask) {
+                if !config.allowed_ips.contains(&network) {
+                    config.allowed_ips.push(network);
+                }
+            }
+        }
+
+        // Session keeps running unless the parameters it was created with change
+        let tunn = match existing {
+            Some((old, tunn))
+                if old.preshared_key == config.preshared_key
+                    && old.persistent_keepalive_interval
+                        == config.persistent_keepalive_interval =>
+            {
+                tunn
+            }
+            _ => self.new_tunn(&config)?,
+        };
+        transport.set_peer(&public_key, config.endpoint, false)?;
+        self.peers.insert(public_key, PeerTunnel { config, tunn });
+        Ok(())
+    }
+
+    fn remove_peer(
+        &mut self,
+        public_key: &PublicKey,
+        transport: &dyn Transport,
+    ) -> Result<(), AdapterError> {
+        if self.peers.remove(public_key).is_some() {
+            transport.set_peer(public_key, None, true)?;
+        }
+        Ok(())
+    }
+
+    fn new_tunn(&mut self, config: &Peer) -> Result<Arc<Tunn>, AdapterError> {
+        let private_key = self
+            .private_key
+            .ok_or(AdapterError::InternalError("Private key is not set"))?;
+        self.next_index = self.next_index.wrapping_add(1) & INDEX_MASK;
+        let keepalive = config
+            .persistent_keepalive_interval
+            .and_then(|keepalive| u16::try_from(keepalive).ok())
+            .filter(|keepalive| *keepalive > 0);
+        let tunn = Tunn::new(
+            StaticSecret::from(private_key.into_bytes()),
+            PublicKeyDalek::from(config.public_key.0),
+            config.preshared_key.map(|psk| psk.0),
+            keepalive,
+            self.next_index,
+            None,
+        )
+        .map_err(AdapterError::InternalError)?;
+        Ok(Arc::from(tunn))
+    }
+}
+
+/// Destination address of the IP packet
+fn destination(packet: &[u8]) -> Option<IpAddr> {
+    match packet.first()? >> 4 {
+        4 => {
+            let dst: [u8; 4] = packet.get(16..20)?.try_into().ok()?;
+            Some(dst.into())
+        }
+        6 => {
+            let dst: [u8; 16] = packet.get(24..40)?.try_into().ok()?;
+            Some(dst.into())
+        }
+        _ => None,
+    }
+}
+
+/// Whether the firewall callback, if set, lets the plaintext packet of the peer through
+fn accepts(callback: &FirewallCb, peer: &PublicKey, packet: &[u8]) -> bool {
+    callback
+        .as_ref()
+        .map_or(true, |callback| callback(&peer.0, packet))
+}
+
+fn join(threads: Vec<JoinHandle<()>>) {
+    for thread in threads {
+        if thread.join().is_err() {
+            telio_log_warn!("Packet adapter thread panicked");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pnet_packet::{ip::IpNextHeaderProtocols, ipv4::MutableIpv4Packet};
+    use std::{
+        net::Ipv4Addr,
+        sync::mpsc::{channel, Receiver, Sender},
+    };
+
+    /// Channel end carrying packets of one side
+    struct Pipe<T> {
+        tx: Mutex<Sender<T>>,
+        rx: Mutex<Receiver<T>>,
+    }
+
+    impl<T> Pipe<T> {
+        fn pair() -> (Self, Self) {
+            let (a_tx, a_rx) = channel();
+            let (b_tx, b_rx) = channel();
+            (
+                Self {
+                    tx: Mutex::new(a_tx),
+                    rx: Mutex::new(b_rx),
+                },
+                Self {
+                    tx: Mutex::new(b_tx),
+                    rx: Mutex::new(a_rx),
+                },
+            )
+        }
+
+        fn recv(&self, timeout: Duration) -> Option<T> {
+            self.rx.lock().unwrap().recv_timeout(timeout).ok()
+        }
+
+        fn send(&self, value: T) {
+            let _ = self.tx.lock().unwrap().send(value);
+        }
+    }
+
+    struct TestTransport {
+        local: PublicKey,
+        pipe: Pipe<(PublicKey, Vec<u8>)>,
+    }
+
+    impl Transport for TestTransport {
+        fn send_packet(&self, _: &PublicKey, packet: &[u8]) -> io::Result<()> {
+            self.pipe.send((self.local, packet.to_vec()));
+            Ok(())
+        }
+
+        fn recv_packet(
+            &self,
+            buf: &mut [u8],
+            timeout: Duration,
+        ) -> io::Result<Option<(PublicKey, usize)>> {
+            Ok(self.pipe.recv(timeout).map(|(peer, packet)| {
+                buf[..packet.len()].copy_from_slice(&packet);
+                (peer, packet.len())
+            }))
+        }
+
+        fn set_peer(&self, _: &PublicKey, _: Option<SocketAddr>, _: bool) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Tunnel for Pipe<Vec<u8>> {
+        fn read_packet(&self, buf: &mut [u8], timeout: Duration) -> io::Result<Option<usize>> {
+            Ok(self.recv(timeout).map(|packet| {
+                buf[..packet.len()].copy_from_slice(&packet);
+                packet.len()
+            }))
+        }
+
+        fn write_packet(&self, packet: &[u8]) -> io::Result<()> {
+            self.send(packet.to_vec());
+            Ok(())
+        }
+    }
+
+    fn set_device(private_key: &SecretKey, peer: &PublicKey, allowed_ip: Ipv4Addr) -> Cmd {
+        Cmd::Set(set::Device {
+            private_key: Some(private_key.into_bytes()),
+            peers: vec![set::Peer {
+                public_key: peer.0,
+                allowed_ips: vec![set::AllowedIp {
+                    ipaddr: allowed_ip.into(),
+                    cidr_mask: 32,
+                }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        })
+    }
+
+    fn start(
+        local: PublicKey,
+        pipe: Pipe<(PublicKey, Vec<u8>)>,
+        tun: Pipe<Vec<u8>>,
+        firewall_process_inbound_callback: FirewallCb,
+        firewall_process_outbound_callback: FirewallCb,
+    ) -> PacketAdapter {
+        PacketAdapter::with_tunnel(
+            Arc::new(TestTransport { local, pipe }),
+            Arc::new(tun),
+            firewall_process_inbound_callback,
+            firewall_process_outbound_callback,
+        )
+        .unwrap()
+    }
+
+    fn ipv4_packet(src: Ipv4Addr, dst: Ipv4Addr) -> Vec<u8> {
+        let mut buf = vec![0u8; 28];
+        let mut packet = MutableIpv4Packet::new(&mut buf).unwrap();
+        packet.set_version(4);
+        packet.set_header_length(5);
+        packet.set_total_length(28);
+        packet.set_ttl(64);
+        packet.set_next_level_protocol(IpNextHeaderProtocols::Udp);
+        packet.set_source(src);
+        packet.set_destination(dst);
+        buf
+    }
+
+    #[tokio::test]
+    async fn packets_cross_the_transport_after_handshake() {
+        let (alice_sk, bob_sk) = (SecretKey::gen(), SecretKey::gen());
+        let (alice_pk, bob_pk) = (alice_sk.public(), bob_sk.public());
+        let (alice_ip, bob_ip) = (Ipv4Addr::new(100, 64, 0, 1), Ipv4Addr::new(100, 64, 0, 2));
+
+        let (alice_pipe, bob_pipe) = Pipe::pair();
+        let (alice_tun, alice_app) = Pipe::pair();
+        let (bob_tun, bob_app) = Pipe::pair();
+
+        let alice = start(alice_pk, alice_pipe, alice_tun, None, None);
+        let bob = start(bob_pk, bob_pipe, bob_tun, None, None);
+        alice
+            .send_uapi_cmd(&set_device(&alice_sk, &bob_pk, bob_ip))
+            .await
+            .unwrap();
+        bob.send_uapi_cmd(&set_device(&bob_sk, &alice_pk, alice_ip))
+            .await
+            .unwrap();
+
+        // First packet triggers the handshake and is delivered once it completes
+        let packet = ipv4_packet(alice_ip, bob_ip);
+        alice_app.send(packet.clone());
+        assert_eq!(bob_app.recv(Duration::from_secs(5)), Some(packet));
+
+        let reply = ipv4_packet(bob_ip, alice_ip);
+        bob_app.send(reply.clone());
+        assert_eq!(alice_app.recv(Duration::from_secs(5)), Some(reply));
+
+        // Packets from sources outside of the allowed IPs are dropped
+        bob_app.send(ipv4_packet(Ipv4Addr::new(100, 64, 0, 3), alice_ip));
+        assert_eq!(alice_app.recv(Duration::from_millis(500)), None);
+
+        let interface = alice.send_uapi_cmd(&Cmd::Get).await.unwrap().interface;
+        let peer = interface.unwrap().peers.remove(&bob_pk).unwrap();
+        assert!(peer.time_since_last_handshake.is_some());
+        assert!(peer.tx_bytes.unwrap() > 0);
+
+        alice.stop().await;
+        bob.stop().await;
+    }
+
+    #[tokio::test]
+    async fn packets_pass_the_firewall() {
+        let (alice_sk, bob_sk) = (SecretKey::gen(), SecretKey::gen());
+        let (alice_pk, bob_pk) = (alice_sk.public(), bob_sk.public());
+        let (alice_ip, bob_ip) = (Ipv4Addr::new(100, 64, 0, 1), Ipv4Addr::new(100, 64, 0, 2));
+
+        let (alice_pipe, bob_pipe) = Pipe::pair();
+        let (alice_tun, alice_app) = Pipe::pair();
+        let (bob_tun, bob_app) = Pipe::pair();
+
+        let outbound = Arc::new(Mutex::new(Vec::new()));
+        let inbound = Arc::new(Mutex::new(Vec::new()));
+        let alice = start(
+            alice_pk,
+            alice_pipe,
+            alice_tun,
+            None,
+            Some(Arc::new({
+                let outbound = outbound.clone();
+                move |peer: &[u8; 32], _: &[u8]| {
+                    outbound.lock().unwrap().push(PublicKey(*peer));
+                    true
+                }
+            })),
+        );
+        // Bob rejects every packet coming from his peers
+        let bob = start(
+            bob_pk,
+            bob_pipe,
+            bob_tun,
+            Some(Arc::new({
+                let inbound = inbound.clone();
+                move |peer: &[u8; 32], _: &[u8]| {
+                    inbound.lock().unwrap().push(PublicKey(*peer));
+                    false
+                }
+            })),
+            None,
+        );
+        alice
+            .send_uapi_cmd(&set_device(&alice_sk, &bob_pk, bob_ip))
+            .await
+            .unwrap();
+        bob.send_uapi_cmd(&set_device(&bob_sk, &alice_pk, alice_ip))
+            .await
+            .unwrap();
+
+        alice_app.send(ipv4_packet(alice_ip, bob_ip));
+        assert_eq!(bob_app.recv(Duration::from_secs(2)), None);
+        assert_eq!(*outbound.lock().unwrap(), vec![bob_pk]);
+        assert_eq!(*inbound.lock().unwrap(), vec![alice_pk]);
+
+        alice.stop().await;
+        bob.stop().await;
+    }
+}
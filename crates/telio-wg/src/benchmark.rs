@@ -0,0 +1,184 @@
+//! Throughput measurements of the WireGuard data path
+
+use std::{
+    net::Ipv4Addr,
+    time::{Duration, Instant},
+};
+
+use boringtun::noise::{Tunn, TunnResult};
+use pnet_packet::{
+    ip::IpNextHeaderProtocols,
+    ipv4::{checksum, MutableIpv4Packet},
+};
+use serde::Serialize;
+use telio_crypto::SecretKey;
+
+use crate::{AdapterType, Error};
+
+/// Size of IP packets pushed through the tunnel, matches the default WireGuard MTU
+const PACKET_SIZE: usize = 1420;
+/// Buffer large enough to hold an encapsulated packet of `PACKET_SIZE`
+const BUFFER_SIZE: usize = 2048;
+/// Longest measurement allowed, longer requested durations are cut down to it
+pub const MAX_DURATION: Duration = Duration::from_secs(10);
+
+/// Result of the adapter benchmark
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AdapterBenchmark {
+    /// Name of the benchmarked adapter
+    pub adapter: String,
+    /// Amount of data which went through the tunnel, in megabits per second
+    pub throughput_mbps: f64,
+    /// Average time it took a single packet to go through the tunnel, in microseconds
+    pub latency_us: u64,
+}
+
+/// Push packets through a loopback pair of tunnels for the given `duration`
+///
+/// The measurement runs on the calling thread and keeps it busy for the whole `duration`, which
+/// is capped at `MAX_DURATION`.
+/// Only the userspace `BoringTun` adapter can be benchmarked, `Error::UnsupportedAdapter` is
+/// returned for the others.
+pub fn benchmark_adapter(
+    adapter: AdapterType,
+    duration: Duration,
+) -> Result<AdapterBenchmark, Error> {
+    match adapter {
+        AdapterType::BoringTun => benchmark_boringtun(duration.min(MAX_DURATION)),
+        _ => Err(Error::UnsupportedAdapter),
+    }
+}
+
+fn benchmark_boringtun(duration: Duration) -> Result<AdapterBenchmark, Error> {
+    let (initiator, responder) = establish_session()?;
+
+    let packet = ipv4_packet(PACKET_SIZE)?;
+    let mut encrypted = [0u8; BUFFER_SIZE];
+    let mut decrypted = [0u8; BUFFER_SIZE];
+
+    let mut packets: u64 = 0;
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        let datagram = match initiator.encapsulate(&packet, &mut encrypted) {
+            TunnResult::WriteToNetwork(datagram) => datagram,
+            _ => return Err(Error::InternalError("Failed to encapsulate packet")),
+        };
+        match responder.decapsulate(None, datagram, &mut decrypted) {
+            TunnResult::WriteToTunnelV4(..) => packets += 1,
+            _ => return Err(Error::InternalError("Failed to decapsulate packet")),
+        }
+    }
+    let elapsed = start.elapsed();
+
+    Ok(AdapterBenchmark {
+        adapter: "boringtun".to_owned(),
+        throughput_mbps: throughput_mbps(packets, elapsed),
+        latency_us: (elapsed.as_micros() / u128::from(packets.max(1))) as u64,
+    })
+}
+
+/// Create a pair of tunnels with completed handshake
+fn establish_session() -> Result<(Box<Tunn>, Box<Tunn>), Error> {
+    let initiator_secret = SecretKey::gen();
+    let responder_secret = SecretKey::gen();
+
+    let initiator = Tunn::new(
+        initiator_secret.into_bytes().into(),
+        responder_secret.public().0.into(),
+        None,
+        None,
+        0,
+        None,
+    )
+    .map_err(Error::InternalError)?;
+    let responder = Tunn::new(
+        responder_secret.into_bytes().into(),
+        initiator_secret.public().0.into(),
+        None,
+        None,
+        1,
+        None,
+    )
+    .map_err(Error::InternalError)?;
+
+    let mut initiation = [0u8; BUFFER_SIZE];
+    let mut response = [0u8; BUFFER_SIZE];
+    let mut keepalive = [0u8; BUFFER_SIZE];
+    let mut done = [0u8; BUFFER_SIZE];
+
+    // Encapsulating without a session starts the handshake
+    let initiation = match initiator.encapsulate(&[], &mut initiation) {
+        TunnResult::WriteToNetwork(initiation) => initiation,
+        _ => {
+            return Err(Error::InternalError(
+                "Failed to create handshake initiation",
+            ))
+        }
+    };
+    let response = match responder.decapsulate(None, initiation, &mut response) {
+        TunnResult::WriteToNetwork(response) => response,
+        _ => return Err(Error::InternalError("Failed to create handshake response")),
+    };
+    // Initiator confirms the session with a keepalive
+    let keepalive = match initiator.decapsulate(None, response, &mut keepalive) {
+        TunnResult::WriteToNetwork(keepalive) => keepalive,
+        _ => return Err(Error::InternalError("Failed to complete handshake")),
+    };
+    match responder.decapsulate(None, keepalive, &mut done) {
+        TunnResult::Done => Ok((initiator, responder)),
+        _ => Err(Error::InternalError("Failed to confirm session")),
+    }
+}
+
+fn ipv4_packet(size: usize) -> Result<Vec<u8>, Error> {
+    let mut buf = vec![0u8; size];
+    let mut packet = MutableIpv4Packet::new(&mut buf)
+        .ok_or(Error::InternalError("Packet buffer is too small"))?;
+    packet.set_version(4);
+    packet.set_header_length(5);
+    packet.set_total_length(size as u16);
+    packet.set_ttl(64);
+    packet.set_next_level_protocol(IpNextHeaderProtocols::Udp);
+    packet.set_source(Ipv4Addr::new(100, 64, 0, 1));
+    packet.set_destination(Ipv4Addr::new(100, 64, 0, 2));
+    packet.set_checksum(checksum(&packet.to_immutable()));
+    Ok(buf)
+}
+
+fn throughput_mbps(packets: u64, elapsed: Duration) -> f64 {
+    let secs = elapsed.as_secs_f64();
+    if secs == 0.0 {
+        return 0.0;
+    }
+    (packets * PACKET_SIZE as u64 * 8) as f64 / secs / 1_000_000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn benchmark_boringtun_adapter() {
+        let result = benchmark_adapter(AdapterType::BoringTun, Duration::from_millis(50))
+            .expect("Failed to benchmark adapter");
+        assert_eq!(result.adapter, "boringtun");
+        assert!(result.throughput_mbps > 0.0);
+    }
+
+    #[test]
+    fn benchmark_is_unsupported_for_kernel_adapters() {
+        assert!(matches!(
+            benchmark_adapter(AdapterType::LinuxNativeWg, Duration::from_millis(1)),
+            Err(Error::UnsupportedAdapter)
+        ));
+    }
+
+    #[test]
+    fn throughput_is_reported_in_megabits() {
+        assert_eq!(throughput_mbps(0, Duration::ZERO), 0.0);
+        assert_eq!(
+            throughput_mbps(1000, Duration::from_secs(1)),
+            (1000 * PACKET_SIZE * 8) as f64 / 1_000_000.0
+        );
+    }
+}
@@ -8,11 +8,17 @@ pub(crate) mod adapter;
 pub(crate) mod wg;
 pub(crate) mod windows;
 
+#[cfg(feature = "benchmarking")]
+pub mod benchmark;
+
 /// Post quantum primitives
 pub mod pq;
 pub mod uapi;
 
 pub use crate::{
-    adapter::{Adapter, AdapterType, Error, FirewallCb, Tun},
+    adapter::{Adapter, AdapterErrorBuffer, AdapterFactory, AdapterType, Error, FirewallCb, Tun},
     wg::*,
 };
+
+#[cfg(not(windows))]
+pub use crate::adapter::{PacketAdapter, Transport, Tunnel};
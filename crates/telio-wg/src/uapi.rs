@@ -380,7 +380,8 @@ impl Display for Response {
     }
 }
 
-pub(super) fn response_from_str(string: &str) -> Result<Response, Error> {
+/// Parse response to the UAPI command from its text form
+pub fn response_from_str(string: &str) -> Result<Response, Error> {
     response_from_read(string.as_bytes())
 }
 
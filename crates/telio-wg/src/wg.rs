@@ -24,7 +24,10 @@ use telio_task::{
 };
 
 use crate::{
-    adapter::{self, Adapter, AdapterType, Error, FirewallResetConnsCb, Tun},
+    adapter::{
+        self, Adapter, AdapterErrorBuffer, AdapterFactory, AdapterType, Error,
+        FirewallResetConnsCb, Tun,
+    },
     uapi::{self, AnalyticsEvent, Cmd, Event, Interface, Peer, PeerState, Response},
     FirewallCb,
 };
@@ -52,12 +55,18 @@ pub trait WireGuard: Send + Sync + 'static {
     async fn set_secret_key(&self, key: SecretKey) -> Result<(), Error>;
     /// Set adapter fwmark, unix only
     async fn set_fwmark(&self, fwmark: u32) -> Result<(), Error>;
+    /// Set adapter listen port
+    async fn set_listen_port(&self, port: u16) -> Result<(), Error>;
     /// Add Peer to adapter
     async fn add_peer(&self, peer: Peer) -> Result<(), Error>;
     /// Remove Peer from adapter
     async fn del_peer(&self, key: PublicKey) -> Result<(), Error>;
+    /// Zero rx/tx counters of the peer reported by `get_interface`, all peers if `None`
+    async fn reset_statistics(&self, public_key: Option<PublicKey>) -> Result<(), Error>;
     /// Disconnect from all peers, implemented only in Boringtun
     async fn drop_connected_sockets(&self) -> Result<(), Error>;
+    /// Send keepalive to the peer now, regardless of its persistent keepalive timer
+    async fn send_keepalive(&self, public_key: PublicKey) -> Result<(), Error>;
     /// Retrieve time since last RXed (and accepted) packet
     async fn time_since_last_rx(&self, public_key: PublicKey) -> Result<Option<Duration>, Error>;
     /// Retrieve time since last endpoint (either roamed or manual) change
@@ -98,6 +107,10 @@ pub struct Config {
     /// Callback of firewall to create connection reset packets
     /// for all active connections
     pub firewall_reset_connections: FirewallResetConnsCb,
+    /// Factory of a custom adapter, used instead of `adapter` if set
+    pub custom_adapter: Option<AdapterFactory>,
+    /// Failures of the adapter start and of UAPI requests are recorded here
+    pub error_buffer: AdapterErrorBuffer,
 }
 
 /// Events and analytics transmission channels
@@ -163,10 +176,14 @@ struct State {
     #[cfg(unix)]
     cfg: Config,
     adapter: Box<dyn Adapter>,
+    error_buffer: AdapterErrorBuffer,
     interval: Interval,
     interface: Interface,
     event: Tx<Box<Event>>,
     last_endpoint_change: HashMap<PublicKey, Instant>,
+    // Adapter counters (rx, tx) of peers at the last statistics reset, adapter counters
+    // themselves cannot be reset on every platform
+    stats_baseline: HashMap<PublicKey, (u64, u64)>,
     analytics_tx: Option<mc_chan::Tx<Box<AnalyticsEvent>>>,
 
     // Detecting unexpected driver failures, such as a malicious removal
@@ -262,6 +279,8 @@ impl DynamicWg {
     ///             firewall_process_outbound_callback:
     ///                 Some(Arc::new(firewall_filter_outbound_packets)),
     ///             firewall_reset_connections: None,
+    ///             custom_adapter: None,
+    ///             error_buffer: Default::default(),
     ///         },
     ///         NoLinkDetection::Disabled,
     ///     );
@@ -271,17 +290,33 @@ impl DynamicWg {
     where
         Self: Sized,
     {
-        let adapter = Self::start_adapter(cfg.try_clone()?)?;
+        let adapter = Self::start_adapter(cfg.try_clone()?).map_err(|err| {
+            cfg.error_buffer.set(&err);
+            err
+        })?;
+        let error_buffer = cfg.error_buffer.clone();
         #[cfg(unix)]
-        return Ok(Self::start_with(io, adapter, no_link_detection, cfg));
+        return Ok(Self::start_with(
+            io,
+            adapter,
+            no_link_detection,
+            error_buffer,
+            cfg,
+        ));
         #[cfg(windows)]
-        return Ok(Self::start_with(io, adapter, no_link_detection));
+        return Ok(Self::start_with(
+            io,
+            adapter,
+            no_link_detection,
+            error_buffer,
+        ));
     }
 
     fn start_with(
         io: Io,
         adapter: Box<dyn Adapter>,
         no_link_detection: NoLinkDetection,
+        error_buffer: AdapterErrorBuffer,
         #[cfg(unix)] cfg: Config,
     ) -> Self {
         let interval = time::interval(Duration::from_millis(POLL_MILLIS));
@@ -291,10 +326,12 @@ impl DynamicWg {
                 #[cfg(unix)]
                 cfg,
                 adapter,
+                error_buffer,
                 interval,
                 interface: Default::default(),
                 event: io.events,
                 last_endpoint_change: Default::default(),
+                stats_baseline: Default::default(),
                 analytics_tx: io.analytics_tx,
                 uapi_failed_last_call: false,
                 uapi_fail_counter: 0,
@@ -306,6 +343,14 @@ impl DynamicWg {
 
     #[cfg(not(any(test, feature = "test-adapter")))]
     fn start_adapter(cfg: Config) -> Result<Box<dyn Adapter>, Error> {
+        if let Some(factory) = cfg.custom_adapter {
+            return factory(
+                cfg.name.as_deref().unwrap_or(DEFAULT_NAME),
+                cfg.tun,
+                cfg.firewall_process_inbound_callback,
+                cfg.firewall_process_outbound_callback,
+            );
+        }
         adapter::start(
             cfg.adapter,
             &cfg.name.unwrap_or_else(|| DEFAULT_NAME.to_owned()),
@@ -332,7 +377,7 @@ impl DynamicWg {
 #[async_trait]
 impl WireGuard for DynamicWg {
     async fn get_interface(&self) -> Result<Interface, Error> {
-        Ok(task_exec!(&self.task, async move |s| Ok(s.interface.clone())).await?)
+        Ok(task_exec!(&self.task, async move |s| Ok(s.interface_with_statistics())).await?)
     }
 
     async fn get_adapter_luid(&self) -> Result<u64, Error> {
@@ -383,6 +428,16 @@ impl WireGuard for DynamicWg {
         .await?)
     }
 
+    async fn set_listen_port(&self, port: u16) -> Result<(), Error> {
+        Ok(task_exec!(&self.task, async move |s| {
+            let mut to = s.interface.clone();
+            to.listen_port = Some(port);
+            let _ = s.update(&to, true).await;
+            Ok(())
+        })
+        .await?)
+    }
+
     async fn add_peer(&self, mut new_peer: Peer) -> Result<(), Error> {
         Ok(task_exec!(&self.task, async move |s| {
             let mut to = s.interface.clone();
@@ -434,6 +489,31 @@ impl WireGuard for DynamicWg {
         .await?)
     }
 
+    async fn reset_statistics(&self, public_key: Option<PublicKey>) -> Result<(), Error> {
+        Ok(task_exec!(&self.task, async move |s| {
+            let State {
+                interface,
+                stats_baseline,
+                ..
+            } = s;
+            for (pk, peer) in interface
+                .peers
+                .iter()
+                .filter(|(pk, _)| public_key.map_or(true, |key| key == **pk))
+            {
+                stats_baseline.insert(
+                    *pk,
+                    (
+                        peer.rx_bytes.unwrap_or_default(),
+                        peer.tx_bytes.unwrap_or_default(),
+                    ),
+                );
+            }
+            Ok(())
+        })
+        .await?)
+    }
+
     async fn drop_connected_sockets(&self) -> Result<(), Error> {
         Ok(task_exec!(&self.task, async move |s| {
             s.adapter.drop_connected_sockets().await;
@@ -442,6 +522,13 @@ impl WireGuard for DynamicWg {
         .await?)
     }
 
+    async fn send_keepalive(&self, public_key: PublicKey) -> Result<(), Error> {
+        task_exec!(&self.task, async move |s| Ok(s
+            .send_keepalive(public_key)
+            .await))
+        .await?
+    }
+
     async fn time_since_last_rx(&self, public_key: PublicKey) -> Result<Option<Duration>, Error> {
         Ok(task_exec!(&self.task, async move |s| Ok(
             s.time_since_last_rx(public_key)
@@ -503,6 +590,8 @@ impl Config {
             firewall_process_inbound_callback: self.firewall_process_inbound_callback.clone(),
             firewall_process_outbound_callback: self.firewall_process_outbound_callback.clone(),
             firewall_reset_connections: self.firewall_reset_connections.clone(),
+            custom_adapter: self.custom_adapter.clone(),
+            error_buffer: self.error_buffer.clone(),
         })
     }
 }
@@ -524,7 +613,13 @@ impl State {
     }
 
     async fn uapi_request(&mut self, cmd: &Cmd) -> Result<Response, Error> {
-        let ret = self.adapter.send_uapi_cmd(cmd).await?;
+        let ret = match self.adapter.send_uapi_cmd(cmd).await {
+            Ok(ret) => ret,
+            Err(err) => {
+                self.error_buffer.set(&err);
+                return Err(err);
+            }
+        };
         telio_log_debug!("UAPI request: {}, response: {}", &cmd.to_string(), &ret);
 
         // Count continuous adapter failures.
@@ -538,12 +633,56 @@ impl State {
         } else {
             self.uapi_failed_last_call = true;
             self.uapi_fail_counter += 1;
+            self.error_buffer
+                .set(format!("UAPI request failed with errno {}", ret.errno));
             // TODO: check failure count threshold, cleanup network config, destroy adapter, notify app
         }
 
         Ok(ret)
     }
 
+    /// WireGuard sends a keepalive right away when persistent keepalive of the peer is turned on,
+    /// so it is turned off and on again. Peers without persistent keepalive are left without it.
+    async fn send_keepalive(&mut self, public_key: PublicKey) -> Result<(), Error> {
+        let interval = self
+            .interface
+            .peers
+            .get(&public_key)
+            .ok_or(Error::InternalError(
+                "Keepalive peer missing from interface",
+            ))?
+            .persistent_keepalive_interval
+            .unwrap_or_default() as u16;
+        let probe_interval = match interval {
+            0 => WG_KEEPALIVE.as_secs() as u16,
+            interval => interval,
+        };
+
+        let mut intervals = vec![0, probe_interval];
+        if interval != probe_interval {
+            intervals.push(interval);
+        }
+        for interval in intervals {
+            let cmd = Cmd::Set(set::Device {
+                private_key: None,
+                listen_port: None,
+                fwmark: None,
+                replace_peers: None,
+                peers: vec![set::Peer {
+                    persistent_keepalive_interval: Some(interval),
+                    ..set::Peer::from_public_key(public_key.0).update_only(true)
+                }],
+            });
+            let response = self.uapi_request(&cmd).await?;
+            if response.errno != 0 {
+                return Err(Error::InternalError(
+                    "Failed to update persistent keepalive",
+                ));
+            }
+        }
+        Ok(())
+    }
+
     fn update_calculate_changes(&self, to: &uapi::Interface) -> DiffKeys {
         // Create key sets
         let f_keys: HashSet<&PublicKey> = self.interface.peers.keys().collect();
@@ -786,6 +925,18 @@ impl State {
         }
     }
 
+    /// Interface with peer counters relative to the last statistics reset
+    fn interface_with_statistics(&self) -> uapi::Interface {
+        let mut interface = self.interface.clone();
+        for (pk, (rx_base, tx_base)) in &self.stats_baseline {
+            if let Some(peer) = interface.peers.get_mut(pk) {
+                peer.rx_bytes = peer.rx_bytes.map(|rx| counter_delta(rx, *rx_base));
+                peer.tx_bytes = peer.tx_bytes.map(|tx| counter_delta(tx, *tx_base));
+            }
+        }
+        interface
+    }
+
     #[allow(mpsc_blocking_send)]
     async fn update(&mut self, to: &uapi::Interface, push: bool) -> Result<bool, Error> {
         // Diff and report events
@@ -810,6 +961,9 @@ impl State {
         let diff_keys = self.update_calculate_changes(to);
 
         self.update_endpoint_change_timestamps(&diff_keys, to);
+        for key in diff_keys.delete_keys.iter() {
+            let _ = self.stats_baseline.remove(key);
+        }
 
         self.update_send_notification_events(to, &diff_keys).await?;
 
@@ -945,6 +1099,12 @@ impl Runtime for State {
     }
 }
 
+/// Traffic counted since the reset, adapter counter lower than the baseline means the adapter
+/// started counting from zero again
+fn counter_delta(current: u64, baseline: u64) -> u64 {
+    current.checked_sub(baseline).unwrap_or(current)
+}
+
 #[cfg(any(test, feature = "test-adapter"))]
 #[allow(missing_docs)]
 pub mod tests {
@@ -969,18 +1129,6 @@ pub mod tests {
             StdMutex::new(None);
     }
 
-    impl DynamicWg {
-        pub async fn set_listen_port(&self, port: u16) -> Result<(), Error> {
-            Ok(task_exec!(&self.task, async move |s| {
-                let mut ifc = s.interface.clone();
-                ifc.listen_port = Some(port);
-                let _ = s.update(&ifc, true).await;
-                Ok(())
-            })
-            .await?)
-        }
-    }
-
     #[cfg(all(unix, test))]
     impl Config {
         fn new() -> std::io::Result<Self> {
@@ -995,6 +1143,8 @@ pub mod tests {
                 firewall_process_inbound_callback: Default::default(),
                 firewall_process_outbound_callback: Default::default(),
                 firewall_reset_connections: None,
+                custom_adapter: None,
+                error_buffer: Default::default(),
             })
         }
     }
@@ -1079,6 +1229,7 @@ pub mod tests {
             },
             Box::new(adapter.clone()),
             NoLinkDetection::Disabled,
+            Default::default(),
             #[cfg(all(unix, test))]
             Config::new().unwrap(),
             #[cfg(all(unix, not(test)))]
@@ -1246,6 +1397,127 @@ pub mod tests {
         wg.stop().await;
     }
 
+    #[tokio::test(start_paused = true)]
+    async fn wg_resets_statistics() {
+        let Env {
+            adapter,
+            wg,
+            mut event,
+            ..
+        } = setup().await;
+        let mut ifa = Interface::default();
+
+        let pkc = SecretKey::gen().public();
+        let mut peer = Peer {
+            public_key: pkc,
+            endpoint: Some(([1, 1, 1, 1], 123).into()),
+            persistent_keepalive_interval: Some(25),
+            rx_bytes: Some(100),
+            tx_bytes: Some(50),
+            ..Default::default()
+        };
+        adapter.expect_send_uapi_cmd_generic_call(1).await;
+        wg.add_peer(peer.clone()).await.unwrap();
+        let _ = event.recv().await;
+        adapter.lock().await.checkpoint();
+
+        // Other peers are not affected
+        wg.reset_statistics(Some(SecretKey::gen().public()))
+            .await
+            .unwrap();
+        let wgi = wg.get_interface().await.unwrap();
+        assert_eq!(wgi.peers[&pkc].rx_bytes, Some(100));
+
+        wg.reset_statistics(None).await.unwrap();
+        let wgi = wg.get_interface().await.unwrap();
+        assert_eq!(wgi.peers[&pkc].rx_bytes, Some(0));
+        assert_eq!(wgi.peers[&pkc].tx_bytes, Some(0));
+
+        // Adapter keeps counting from the old values
+        peer.time_since_last_handshake = Some(Duration::from_secs(15));
+        peer.rx_bytes = Some(150);
+        peer.tx_bytes = Some(80);
+        ifa.peers.insert(pkc, peer);
+        adapter
+            .lock()
+            .await
+            .expect_send_uapi_cmd()
+            .with(predicate::eq(Cmd::Get))
+            .times(1)
+            .returning(move |_| {
+                Ok(Response {
+                    errno: 0,
+                    interface: Some(ifa.clone()),
+                })
+            });
+        let _ = event.recv().await;
+        let wgi = wg.get_interface().await.unwrap();
+        assert_eq!(wgi.peers[&pkc].rx_bytes, Some(50));
+        assert_eq!(wgi.peers[&pkc].tx_bytes, Some(30));
+
+        adapter.lock().await.expect_stop().return_once(|| ());
+        wg.stop().await;
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn wg_sends_keepalive() {
+        let Env {
+            adapter,
+            wg,
+            mut event,
+            ..
+        } = setup().await;
+
+        let pkc = SecretKey::gen().public();
+        adapter.expect_send_uapi_cmd_generic_call(1).await;
+        wg.add_peer(Peer {
+            public_key: pkc,
+            persistent_keepalive_interval: Some(25),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let _ = event.recv().await;
+        adapter.lock().await.checkpoint();
+
+        // Persistent keepalive is turned off and back on
+        let intervals = Arc::new(std::sync::Mutex::new(Vec::new()));
+        adapter
+            .lock()
+            .await
+            .expect_send_uapi_cmd()
+            .times(2)
+            .returning({
+                let intervals = intervals.clone();
+                move |cmd| {
+                    if let Cmd::Set(dev) = cmd {
+                        if let Ok(mut intervals) = intervals.lock() {
+                            intervals
+                                .extend(dev.peers.iter().map(|p| p.persistent_keepalive_interval));
+                        }
+                    }
+                    Ok(Response {
+                        errno: 0,
+                        interface: Some(Interface::default()),
+                    })
+                }
+            });
+        wg.send_keepalive(pkc).await.unwrap();
+        adapter.lock().await.checkpoint();
+        assert_eq!(*intervals.lock().unwrap(), vec![Some(0), Some(25)]);
+
+        assert!(wg.send_keepalive(SecretKey::gen().public()).await.is_err());
+
+        adapter.lock().await.expect_stop().return_once(|| ());
+        wg.stop().await;
+    }
+
+    #[test]
+    fn counter_delta_after_adapter_restart() {
+        assert_eq!(counter_delta(150, 100), 50);
+        assert_eq!(counter_delta(30, 100), 30);
+    }
+
     #[tokio::test(start_paused = true)]
     async fn wg_peer_disconnects() {
         let Env {
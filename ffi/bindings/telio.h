@@ -10,6 +10,13 @@
 #include <stdint.h>
 #include <stdlib.h>
 
+/**
+ * Version of the C API described by this header, incremented whenever a function or a type of
+ * the API changes. Wrappers should check that `telio_get_api_version` returns at least this
+ * version before calling anything else.
+ */
+#define TELIO_MIN_API_VERSION 1
+
 /**
  * Possible adapters.
  */
@@ -44,6 +51,20 @@ typedef enum telio_log_level {
   TELIO_LOG_TRACE = 6,
 } telio_log_level;
 
+/**
+ * Config formats described by `telio_get_config_schema`.
+ */
+typedef enum telio_schema_type {
+  /**
+   * Features JSON passed to `telio_new`.
+   */
+  TELIO_SCHEMA_FEATURES,
+  /**
+   * Meshnet config JSON passed to `telio_set_meshnet`.
+   */
+  TELIO_SCHEMA_MESHNET,
+} telio_schema_type;
+
 typedef enum telio_result {
   /**
    * Operation was successful.
@@ -73,6 +94,10 @@ typedef enum telio_result {
    * The device is already started.
    */
   TELIO_RES_ALREADY_STARTED = 6,
+  /**
+   * Operation is not supported on this platform.
+   */
+  TELIO_RES_UNSUPPORTED_PLATFORM = 7,
 } telio_result;
 
 typedef struct telio telio;
@@ -111,6 +136,135 @@ typedef struct telio_logger_cb {
   telio_logger_fn cb;
 } telio_logger_cb;
 
+typedef void (*telio_logger_v2_fn)(void*, enum telio_log_level, const char*, const char*);
+
+/**
+ * Logging callback receiving structured fields of the log message
+ */
+typedef struct telio_logger_v2_cb {
+  /**
+   * Context to pass to callback.
+   * User must ensure safe access of this var from multithreaded context.
+   */
+  void *ctx;
+  /**
+   * Function to be called with the level, the message and a JSON object of fields of the
+   * message and the spans it was logged in
+   */
+  telio_logger_v2_fn cb;
+} telio_logger_v2_cb;
+
+typedef void (*telio_custom_message_fn)(void*, const char*, const uint8_t*, uintptr_t);
+
+/**
+ * Custom message callback, receives base64 encoded sender public key and message payload
+ */
+typedef struct telio_custom_message_cb {
+  /**
+   * Context to pass to callback.
+   * User must ensure safe access of this var from multithreaded context.
+   */
+  void *ctx;
+  /**
+   * Function to be called
+   */
+  telio_custom_message_fn cb;
+} telio_custom_message_cb;
+
+typedef uint64_t (*telio_link_state_provider_fn)(void*);
+
+/**
+ * Link state provider callback, returns an identifier of the current network interfaces state
+ */
+typedef struct telio_link_state_provider_cb {
+  /**
+   * Context to pass to callback.
+   * User must ensure safe access of this var from multithreaded context.
+   */
+  void *ctx;
+  /**
+   * Function to be called
+   */
+  telio_link_state_provider_fn cb;
+} telio_link_state_provider_cb;
+
+#if defined(TELIO_PACKET_STATS)
+typedef void (*telio_packet_stats_fn)(void*, uint8_t, const uint8_t*, uint32_t);
+#endif
+
+#if defined(TELIO_PACKET_STATS)
+/**
+ * Packet statistics callback, receives direction (0 inbound, 1 outbound), 32 byte public key
+ * of the peer and size of the packet
+ */
+typedef struct telio_packet_stats_cb {
+  /**
+   * Context to pass to callback.
+   * User must ensure safe access of this var from multithreaded context.
+   */
+  void *ctx;
+  /**
+   * Function to be called
+   */
+  telio_packet_stats_fn cb;
+} telio_packet_stats_cb;
+#endif
+
+typedef enum telio_result (*telio_adapter_send_packet_fn)(void*, const uint8_t*, const uint8_t*, uintptr_t);
+
+typedef int32_t (*telio_adapter_recv_packet_fn)(void*, uint8_t*, uintptr_t, uint32_t, uint8_t*);
+
+typedef enum telio_result (*telio_adapter_set_peer_fn)(void*, const uint8_t*, const char*, bool);
+
+typedef void (*telio_adapter_stop_fn)(void*);
+
+/**
+ * Transport of encrypted WireGuard packets implemented by the application
+ */
+typedef struct telio_custom_adapter {
+  /**
+   * Handle of the adapter passed to its functions.
+   */
+  void *handle;
+  /**
+   * Send the encrypted packet `(data, len)` to the peer with the 32 byte public key,
+   * returns `TELIO_RES_OK` on success.
+   */
+  telio_adapter_send_packet_fn send_packet;
+  /**
+   * Wait up to `timeout_ms` for an encrypted packet, copy it to `(buf, capacity)` and the
+   * 32 byte public key of its sender to `peer`. Returns the length of the packet, 0 on timeout
+   * and a negative value on failure.
+   */
+  telio_adapter_recv_packet_fn recv_packet;
+  /**
+   * Add or update the peer with the 32 byte public key and its endpoint, e.g. `1.2.3.4:51820`,
+   * NULL if unknown, or remove the peer if `remove` is set. Returns `TELIO_RES_OK` on success.
+   */
+  telio_adapter_set_peer_fn set_peer;
+  /**
+   * Stop the adapter, the handle is not used afterwards. Called exactly once.
+   */
+  telio_adapter_stop_fn stop;
+} telio_custom_adapter;
+
+typedef enum telio_result (*telio_adapter_factory_fn)(void*, struct telio_custom_adapter*);
+
+/**
+ * Factory of custom adapters, fills in the adapter and returns `TELIO_RES_OK` on success
+ */
+typedef struct telio_adapter_factory_cb {
+  /**
+   * Context to pass to callback.
+   * User must ensure safe access of this var from multithreaded context.
+   */
+  void *ctx;
+  /**
+   * Function to be called
+   */
+  telio_adapter_factory_fn cb;
+} telio_adapter_factory_cb;
+
 #if defined(__ANDROID__)
 typedef void (*telio_protect_fn)(void*, int32_t);
 #endif
@@ -132,6 +286,29 @@ typedef struct telio_protect_cb {
 } telio_protect_cb;
 #endif
 
+#if defined(__ANDROID__)
+typedef bool (*telio_bind_network_fn)(void*, int32_t, bool, bool);
+#endif
+
+#if defined(__ANDROID__)
+/**
+ * Android callback binding the socket to a network, e.g. with `Network.bindSocket()`.
+ * Receives the socket, `require_unmetered` and `require_wifi`, returns false if no network
+ * satisfies the constraints.
+ */
+typedef struct telio_bind_network_cb {
+  /**
+   * Context to pass to callback.
+   * User must ensure safe access of this var from multithreaded context.
+   */
+  void *ctx;
+  /**
+   * Function to be called
+   */
+  telio_bind_network_fn cb;
+} telio_bind_network_cb;
+#endif
+
 #ifdef __cplusplus
 extern "C" {
 #endif // __cplusplus
@@ -152,6 +329,23 @@ enum telio_result telio_new(struct telio **dev,
                             enum telio_log_level log_level,
                             struct telio_logger_cb logger);
 
+/**
+ * Create new telio library instance with structured logging callback
+ *
+ * Same as `telio_new`, except that along with the message, the logger receives a JSON object
+ * of fields of the log message and of the spans it was logged in, e.g. `{"peer":"..."}`.
+ * # Parameters
+ * - `events`:     Events callback
+ * - `features`:   JSON string of enabled features
+ * - `log_level`:  Log level
+ * - `logger_v2`:  Structured logging callback
+ */
+enum telio_result telio_new_with_logger_v2(struct telio **dev,
+                                           const char *features,
+                                           struct telio_event_cb events,
+                                           enum telio_log_level log_level,
+                                           struct telio_logger_v2_cb logger_v2);
+
 #if defined(__ANDROID__)
 /**
  * Initialize OS certificate store, should be called only once. Without call to telio_init_cert_store
@@ -182,8 +376,37 @@ enum telio_result telio_new_with_protect(struct telio **dev,
                                          struct telio_protect_cb protect);
 #endif
 
+#if defined(__ANDROID__)
+/**
+ * Create new telio library instance which binds its sockets to networks through the host
+ *
+ * Same as `telio_new_with_protect`, except that sockets are bound with `bind_network` to a
+ * network satisfying the constraints set with `telio_set_network_constraints`. Apps cannot
+ * bind sockets to interfaces themselves, so without it the constraints are not applied.
+ * # Parameters
+ * - `events`:       Events callback
+ * - `features`:     JSON string of enabled features
+ * - `log_level`:    Log level
+ * - `logger`:       Logging callback
+ * - `protect`:      Callback executed after exit-node connect (for VpnService::protectFromVpn())
+ * - `bind_network`: Callback binding sockets to a network (for Network.bindSocket())
+ */
+enum telio_result telio_new_with_network_binding(struct telio **dev,
+                                                 const char *features,
+                                                 struct telio_event_cb events,
+                                                 enum telio_log_level log_level,
+                                                 struct telio_logger_cb logger,
+                                                 struct telio_protect_cb protect,
+                                                 struct telio_bind_network_cb bind_network);
+#endif
+
 /**
  * Completely stop and uninit telio lib.
+ *
+ * `shuttingdown` event is reported before anything is stopped, after the events reported
+ * earlier. It is the last event passed to the event callback, events reported while stopping
+ * are dropped. Waits for the callback to receive it for up to
+ * `flush_events_on_stop_timeout_seconds`, or 5 seconds if the feature is not set.
  */
 void telio_destroy(struct telio *dev);
 
@@ -192,11 +415,89 @@ void telio_destroy(struct telio *dev);
  */
 enum telio_result telio_destroy_hard(struct telio *dev);
 
+/**
+ * Register additional events callback.
+ *
+ * Every event is passed to all registered callbacks, in the order of registration.
+ *
+ * # Parameters
+ * - `id`: Caller chosen ID of the callback, used to remove it. ID 0 is taken by the callback
+ *         passed to `telio_new`.
+ * - `cb`: Events callback.
+ *
+ * # Returns
+ * `TELIO_RES_ERROR` if a callback with the same ID is already registered.
+ *
+ */
+enum telio_result telio_add_event_listener(const struct telio *dev,
+                                           uint64_t id,
+                                           struct telio_event_cb cb);
+
+/**
+ * Unregister events callback added with `telio_add_event_listener`.
+ *
+ * # Parameters
+ * - `id`: ID the callback was registered with.
+ *
+ * # Returns
+ * `TELIO_RES_ERROR` if no callback with the ID is registered or the ID is 0, callback passed
+ * to `telio_new` cannot be removed.
+ *
+ */
+enum telio_result telio_remove_event_listener(const struct telio *dev, uint64_t id);
+
+/**
+ * Group events passed to the event callbacks into batches.
+ *
+ * Instead of a single event, callbacks receive a JSON array of events. A batch is passed once
+ * it holds `max_batch_size` events or `flush_interval_ms` passes since batching was enabled or
+ * the last timer flush, whichever comes first. Calling it again changes the limits.
+ *
+ * Batches are passed by the same task as the single events, so callbacks are never called
+ * concurrently.
+ *
+ * # Parameters
+ * - `max_batch_size`: Maximal number of events in a batch, must not be 0.
+ * - `flush_interval_ms`: Longest time an event waits in a batch, must not be 0.
+ *
+ */
+enum telio_result telio_enable_event_batching(const struct telio *dev,
+                                              uint32_t max_batch_size,
+                                              uint32_t flush_interval_ms);
+
+/**
+ * Pass every event to the event callbacks on its own again, events waiting in a batch are
+ * passed as the last batch right after the events already reported.
+ */
+enum telio_result telio_disable_event_batching(const struct telio *dev);
+
 /**
  * Get default recommended adapter type for platform.
  */
 enum telio_adapter_type telio_get_default_adapter(void);
 
+/**
+ * Get adapter types available on this platform.
+ *
+ * # Returns
+ * JSON array of adapter type names, e.g. `["TELIO_ADAPTER_BORING_TUN"]`.
+ *
+ */
+char *telio_get_supported_adapters(void);
+
+/**
+ * Get information about the platform for diagnostics and bug reports.
+ *
+ * Does not require a telio instance.
+ *
+ * # Returns
+ * JSON string
+ * `{"os":"linux","os_version":"6.1.0","arch":"aarch64","has_kernel_wireguard":true,"tun_available":true}`,
+ * `os_version` is the kernel release reported by `uname` and is null where unavailable.
+ *
+ */
+char *telio_get_platform_info(void);
+
 /**
  * Start telio with specified adapter.
  *
@@ -206,6 +507,21 @@ enum telio_result telio_start(const struct telio *dev,
                               const char *private_key,
                               enum telio_adapter_type adapter);
 
+/**
+ * Start telio with specified adapter in the background.
+ *
+ * Returns without waiting for the adapter to be initialized, which takes 500ms or more on some
+ * platforms. `StartupComplete` event is reported when starting finishes, successfully or not.
+ * Other calls, including `telio_start` and `telio_stop`, wait for the startup to finish.
+ *
+ * # Returns
+ * `TELIO_RES_ALREADY_STARTED` if the device is started or still starting.
+ *
+ */
+enum telio_result telio_start_async(const struct telio *dev,
+                                    const char *private_key,
+                                    enum telio_adapter_type adapter);
+
 /**
  * Start telio with specified adapter and name.
  *
@@ -216,7 +532,26 @@ enum telio_result telio_start_named(const struct telio *dev,
                                     enum telio_adapter_type adapter,
                                     const char *name);
 
-#if !defined(_WIN32)
+/**
+ * Start telio with specified adapter and name, marking encapsulated packets with fwmark.
+ *
+ * Unlike `telio_start_named` followed by `telio_set_fwmark`, no packet leaves without the mark.
+ * Adapter will attempt to open its own tunnel.
+ *
+ * # Parameters
+ * - `private_key`: base64 encoded private_key.
+ * - `adapter`: Adapter type.
+ * - `name`: Name of the tunnel interface.
+ * - `fwmark`: unsigned 32-bit integer
+ *
+ * Returns `TELIO_RES_UNSUPPORTED_PLATFORM` on platforms other than Linux.
+ */
+enum telio_result telio_start_named_with_fwmark(const struct telio *dev,
+                                                const char *private_key,
+                                                enum telio_adapter_type adapter,
+                                                const char *name,
+                                                unsigned int fwmark);
+
 /**
  * Start telio device with specified adapter and already open tunnel.
  *
@@ -227,12 +562,31 @@ enum telio_result telio_start_named(const struct telio *dev,
  * - `adapter`: Adapter type.
  * - `tun`: A valid filedescriptor to tun device.
  *
+ * Returns `TELIO_RES_UNSUPPORTED_PLATFORM` on Windows.
  */
 enum telio_result telio_start_with_tun(const struct telio *dev,
                                        const char *private_key,
                                        enum telio_adapter_type adapter,
                                        int tun);
-#endif
+
+/**
+ * Start telio device with a transport of encrypted packets implemented by the application.
+ *
+ * libtelio runs WireGuard in userspace on the default tunnel interface and hands encrypted
+ * packets to the adapter created by `adapter_factory`, which carries them to the peers, e.g.
+ * over a transport other than UDP. The factory is called on every adapter start, the adapter is
+ * stopped with its `stop` function when the device stops or the adapter is dropped. Plaintext
+ * packets pass the meshnet firewall as with the built-in adapters.
+ *
+ * # Parameters
+ * - `private_key`: base64 encoded private_key.
+ * - `adapter_factory`: Callback filling in `telio_custom_adapter` and returning `TELIO_RES_OK`.
+ *
+ * Returns `TELIO_RES_UNSUPPORTED_PLATFORM` on Windows.
+ */
+enum telio_result telio_start_with_custom_adapter(const struct telio *dev,
+                                                 const char *private_key,
+                                                 struct telio_adapter_factory_cb adapter_factory);
 
 /**
  * Stop telio device.
@@ -244,6 +598,18 @@ enum telio_result telio_stop(const struct telio *dev);
  */
 uint64_t telio_get_adapter_luid(const struct telio *dev);
 
+/**
+ * Get the tunnel file descriptor passed to `telio_start_with_tun`.
+ *
+ * The descriptor is borrowed, it stays owned by telio and must not be closed
+ * by the caller.
+ *
+ * # Returns
+ * The file descriptor, or -1 if the device is not started or did not use an
+ * already open tunnel. Always -1 on Windows.
+ */
+int telio_get_current_tun_fd(const struct telio *dev);
+
 /**
  * Sets private key for started device.
  *
@@ -255,18 +621,124 @@ uint64_t telio_get_adapter_luid(const struct telio *dev);
  */
 enum telio_result telio_set_private_key(const struct telio *dev, const char *private_key);
 
+/**
+ * Rotates private key of started device.
+ *
+ * On success `KeyRotated` event with old and new public keys is reported through the event
+ * callback, so the new public key can be registered with the backend. As with
+ * `telio_set_private_key`, `TELIO_RES_INVALID_KEY` is returned if meshnet is on and the key
+ * differs from the one in meshnet config while `validate_keys` feature is enabled.
+ *
+ * # Parameters
+ * - `new_private_key`: Base64 encoded WireGuard private key, must not be NULL.
+ *
+ */
+enum telio_result telio_rotate_secret_key(const struct telio *dev, const char *new_private_key);
+
 char *telio_get_private_key(const struct telio *dev);
 
-#if defined(__linux__)
+/**
+ * Get a stable identifier of the device.
+ *
+ * # Returns
+ * Base64 encoded SHA-256 hash of the device public key. Stays the same across restarts
+ * as long as the same private key is used. NULL is returned on failure.
+ */
+char *telio_get_device_fingerprint(const struct telio *dev);
+
+/**
+ * Get a stable UUID of the device for correlation with backend logs.
+ *
+ * # Returns
+ * UUID v5 string derived from the device public key, e.g.
+ * `"3b7e8a52-1c4d-5f60-9a2b-7c8d9e0f1a2b"`. Stays the same across restarts as long as the same
+ * private key is used, while the key itself is not revealed. NULL is returned on failure.
+ */
+char *telio_get_device_id(const struct telio *dev);
+
+/**
+ * Export current WireGuard interface and peer configuration.
+ *
+ * Configuration is read from the adapter and formatted as a `wg(8)` config file, e.g.
+ * ```text
+ * [Interface]
+ * # PrivateKey is hidden, public key fingerprint: <fingerprint>
+ * ListenPort = 51820
+ *
+ * [Peer]
+ * PublicKey = <public key>
+ * AllowedIPs = 100.64.0.2/32
+ * Endpoint = 1.2.3.4:51820
+ * PersistentKeepalive = 25
+ * ```
+ * Secrets are not exported: the private key is replaced by the fingerprint returned by
+ * `telio_get_device_fingerprint` and preshared keys are omitted.
+ *
+ * # Returns
+ * Config file contents, NULL is returned on failure.
+ */
+char *telio_export_wireguard_config(const struct telio *dev);
+
+/**
+ * Zero rx/tx byte counters of all peers.
+ *
+ * Counters reported afterwards start from zero, e.g. to count traffic of a single VPN session.
+ * Counters of the WireGuard adapter itself are not changed.
+ *
+ */
+enum telio_result telio_reset_statistics(const struct telio *dev);
+
+/**
+ * Zero rx/tx byte counters of a single peer.
+ *
+ * # Parameters
+ * - `public_key`: Base64 encoded WireGuard public key of the peer.
+ *
+ * # Returns
+ * `TELIO_RES_ERROR` if the peer is not configured.
+ *
+ */
+enum telio_result telio_reset_statistics_for(const struct telio *dev, const char *public_key);
+
+/**
+ * Sets UDP port the WireGuard socket listens on.
+ *
+ * Equivalent to `wg set <interface> listen-port <port>`. The port is applied on the next
+ * `telio_start*` call, so the device must be stopped. By default the adapter picks a random port.
+ *
+ * # Parameters
+ * - `port`: UDP port number, 0 lets the adapter pick a random port.
+ *
+ * # Returns
+ * `TELIO_RES_ALREADY_STARTED` if the device is running.
+ *
+ */
+enum telio_result telio_listen_on_port(const struct telio *dev, uint16_t port);
+
 /**
  * Sets fmark for started device.
  *
  * # Parameters
  * - `fwmark`: unsigned 32-bit integer
  *
+ * Returns `TELIO_RES_UNSUPPORTED_PLATFORM` on platforms other than Linux.
  */
 enum telio_result telio_set_fwmark(const struct telio *dev, unsigned int fwmark);
-#endif
+
+/**
+ * Restrict traffic of the started device to networks matching the constraints.
+ *
+ * Sockets are bound to a matching network by the `bind_network` callback given to
+ * `telio_new_with_network_binding`. When no network matches, they keep using the default network.
+ * Returns `TELIO_RES_BAD_CONFIG` if the instance was created without the callback, as the
+ * constraints would have no effect.
+ *
+ * # Parameters
+ * - `constraints`: JSON object, e.g. `{"require_unmetered":true,"require_wifi":false}`
+ *
+ * Returns `TELIO_RES_UNSUPPORTED_PLATFORM` on platforms other than Android.
+ */
+enum telio_result telio_set_network_constraints(const struct telio *dev, const char *constraints);
 
 /**
  * Notify telio with network state changes.
@@ -277,6 +749,16 @@ enum telio_result telio_set_fwmark(const struct telio *dev, unsigned int fwmark)
  */
 enum telio_result telio_notify_network_change(const struct telio *dev, const char *network_info);
 
+/**
+ * Searches for direct path endpoints again and pings the meshnet peers with them.
+ *
+ * Use it after network changes telio cannot notice, e.g. manual NAT or firewall changes.
+ * Unlike `telio_notify_network_change`, the relay connection is kept. Known STUN endpoints are
+ * forgotten, so all the peers are probed even if the endpoints did not change. Does nothing if
+ * direct connections are not enabled.
+ */
+enum telio_result telio_trigger_network_redetection(const struct telio *dev);
+
 /**
  * Wrapper for `telio_connect_to_exit_node_with_id` that doesn't take an identifier
  */
@@ -332,6 +814,32 @@ enum telio_result telio_connect_to_exit_node_with_id(const struct telio *dev,
                                                      const char *allowed_ips,
                                                      const char *endpoint);
 
+/**
+ * Connects to an exit node like `telio_connect_to_exit_node`, retrying until the exit node
+ * is reachable.
+ *
+ * Returns after the first attempt. When no handshake with the exit node succeeds within the
+ * delay, the exit node is connected again and the delay is multiplied by the backoff factor.
+ * `ExitNodeConnected` event is reported on the first handshake, `ExitNodeConnectionFailed`
+ * after the last attempt. Retrying stops when another exit node is connected or the exit
+ * node is disconnected.
+ *
+ * # Parameters
+ * - `public_key`: Base64 encoded WireGuard public key for an exit node.
+ * - `allowed_ips`: Semicolon separated list of subnets which will be routed to the exit node.
+ *                  Can be NULL, same as "0.0.0.0/0".
+ * - `endpoint`: An endpoint to an exit node. Can be NULL, must contain a port.
+ * - `policy_json`: JSON object, e.g.
+ *                  `{"max_attempts":5,"initial_delay_ms":1000,"backoff_factor":2.0}`.
+ *                  Missing fields take the values from the example.
+ *
+ */
+enum telio_result telio_connect_to_exit_node_with_retry(const struct telio *dev,
+                                                        const char *public_key,
+                                                        const char *allowed_ips,
+                                                        const char *endpoint,
+                                                        const char *policy_json);
+
 /**
  * Enables magic DNS if it was not enabled yet,
  *
@@ -352,11 +860,149 @@ enum telio_result telio_connect_to_exit_node_with_id(const struct telio *dev,
  */
 enum telio_result telio_enable_magic_dns(const struct telio *dev, const char *forward_servers);
 
+/**
+ * Replaces upstream servers of the running magic DNS.
+ *
+ * Use it when the DNS servers of the system change, e.g. after switching networks. Queries
+ * keep being answered while the servers are swapped, unlike with `telio_disable_magic_dns`
+ * followed by `telio_enable_magic_dns`.
+ *
+ * # Parameters
+ * - `new_servers`: JSON array of DNS servers to route the requests trough.
+ *
+ * # Returns
+ * `TELIO_RES_BAD_CONFIG` if magic DNS is not enabled.
+ *
+ */
+enum telio_result telio_notify_dns_servers_changed(const struct telio *dev,
+                                                   const char *new_servers);
+
+/**
+ * Configures servers asked by magic DNS when its forward servers fail or do not answer in time.
+ *
+ * Only names outside of the meshnet are sent to the fallback servers, so meshnet hostnames
+ * never leak to them. Can be called before `telio_enable_magic_dns`, the setting is kept until
+ * replaced.
+ *
+ * # Parameters
+ * - `servers_json`: JSON object `{"servers":["1.1.1.1"],"timeout_ms":2000}`, `timeout_ms` is
+ *                   optional and defaults to 2000. Empty `servers` disable the fallback.
+ *
+ */
+enum telio_result telio_set_dns_fallback_servers(const struct telio *dev,
+                                                 const char *servers_json);
+
+/**
+ * Clamps TTLs of the records resolved by magic DNS to the given range.
+ *
+ * Records with shorter TTL are cached and reported with `min_ttl_secs`, records with longer
+ * TTL with `max_ttl_secs`. Applies to forwarded and meshnet records. Can be called before
+ * `telio_enable_magic_dns`, the setting is kept until replaced.
+ *
+ * # Parameters
+ * - `min_ttl_secs`: Minimum TTL in seconds, 0 leaves it unset (default).
+ * - `max_ttl_secs`: Maximum TTL in seconds, 0 leaves it unset (default).
+ *
+ * # Returns
+ * `TELIO_RES_BAD_CONFIG` if both bounds are set and `min_ttl_secs` exceeds `max_ttl_secs`.
+ *
+ */
+enum telio_result telio_set_dns_ttl_override(const struct telio *dev,
+                                             uint32_t min_ttl_secs,
+                                             uint32_t max_ttl_secs);
+
+/**
+ * Resolves names outside of the meshnet with a DNS over HTTPS server.
+ *
+ * Magic DNS sends the queries in HTTPS POST requests to the server instead of sending them
+ * over UDP to the servers given to `telio_enable_magic_dns`. Connections to the server go
+ * through the proxy set with `telio_set_proxy_settings`, if any. Hostname of the server is
+ * resolved by the servers given to `telio_enable_magic_dns`, over TCP through the proxy if set.
+ * Can be called before `telio_enable_magic_dns`, the setting is kept until
+ * `telio_disable_dns_over_https`.
+ *
+ * # Parameters
+ * - `doh_url`: URL of the server, e.g. `https://cloudflare-dns.com/dns-query`.
+ *
+ * # Returns
+ * `TELIO_RES_BAD_CONFIG` if the URL is not an `https` URL with `/dns-query` path.
+ *
+ */
+enum telio_result telio_enable_dns_over_https(const struct telio *dev, const char *doh_url);
+
+/**
+ * Resolves names outside of the meshnet over UDP with the servers given to
+ * `telio_enable_magic_dns` again.
+ */
+enum telio_result telio_disable_dns_over_https(const struct telio *dev);
+
 /**
  * Disables magic DNS if it was enabled.
  */
 enum telio_result telio_disable_magic_dns(const struct telio *dev);
 
+/**
+ * Resolves a name through magic DNS without routing system DNS through the tunnel.
+ *
+ * Magic DNS has to be enabled. Names outside of the meshnet are forwarded to the servers given to
+ * `telio_enable_magic_dns`. Blocks until the answer arrives or `proxy_dns_query_timeout_ms`
+ * feature (5 seconds by default) elapses.
+ *
+ * # Parameters
+ * - `query_name`: Domain name to resolve, e.g. `peer.nord`.
+ * - `record_type`: Type of the requested records, e.g. `A` or `AAAA`.
+ *
+ * # Returns
+ * JSON string `{"name":"peer.nord.","type":"A","ttl":300,"records":["100.64.0.1"]}`.
+ * NULL is returned if the query fails or times out.
+ *
+ */
+char *telio_proxy_dns_query(const struct telio *dev,
+                            const char *query_name,
+                            const char *record_type);
+
+/**
+ * Resolves a meshnet hostname to its addresses through magic DNS.
+ *
+ * Magic DNS has to be enabled. Both A and AAAA records are queried, each query is limited just
+ * like in `telio_proxy_dns_query`.
+ *
+ * # Parameters
+ * - `hostname`: Hostname of the meshnet node, e.g. `peer.nord`.
+ *
+ * # Returns
+ * JSON array of addresses, e.g. `["100.64.0.1","fd74:656c:696f::1"]`, empty if the name has
+ * no records. NULL is returned if a query fails or times out.
+ *
+ */
+char *telio_resolve_mesh_hostname(const struct telio *dev, const char *hostname);
+
+/**
+ * Answers magic DNS queries for a name with the given addresses.
+ *
+ * Injected records take precedence over meshnet records and forwarding, an existing override of
+ * the name is replaced. Magic DNS has to be enabled, overrides are dropped when it is disabled.
+ *
+ * # Parameters
+ * - `name`:           Domain name, e.g. `test.mesh`.
+ * - `addresses_json`: JSON array of IPv4 and IPv6 addresses, served as A and AAAA records.
+ * - `ttl`:            TTL of the records, in seconds.
+ *
+ */
+enum telio_result telio_dns_override(const struct telio *dev,
+                                     const char *name,
+                                     const char *addresses_json,
+                                     uint32_t ttl);
+
+/**
+ * Removes records injected with `telio_dns_override`.
+ *
+ * # Parameters
+ * - `name`: Domain name passed to `telio_dns_override`.
+ *
+ */
+enum telio_result telio_dns_remove_override(const struct telio *dev, const char *name);
+
 /**
  * Disconnects from specified exit node.
  *
@@ -371,6 +1017,41 @@ enum telio_result telio_disconnect_from_exit_node(const struct telio *dev, const
  */
 enum telio_result telio_disconnect_from_exit_nodes(const struct telio *dev);
 
+/**
+ * Switches between exit nodes automatically.
+ *
+ * If the connected exit node is not in the list, the first node is connected. When nothing
+ * is received from the connected exit node for longer than `exit_node_failover_timeout_secs`
+ * feature (30 seconds by default), the next node in the list is connected and
+ * `ExitNodeFailover` event is reported. After the last node, the list starts over from the
+ * first one. Failover pauses while another exit node is connected manually.
+ *
+ * # Parameters
+ * - `failover_config_json`: JSON array of exit nodes in priority order, e.g.
+ *   `[{"public_key":"...","endpoint":"1.2.3.4:51820"},{"public_key":"..."}]`.
+ *   `endpoint` is set for VPN servers and omitted for meshnet exit nodes.
+ *
+ */
+enum telio_result telio_enable_exit_node_failover(const struct telio *dev,
+                                                  const char *failover_config_json);
+
+/**
+ * Stops switching exit nodes automatically, the connected exit node is kept.
+ */
+enum telio_result telio_disable_exit_node_failover(const struct telio *dev);
+
+/**
+ * Get exit nodes the device is currently connected to.
+ *
+ * # Returns
+ * JSON array
+ * `[{"identifier":"...","public_key":"...","allowed_ips":["0.0.0.0/0"],"endpoint":"1.2.3.4:51820"}]`,
+ * empty if no exit node is connected. `endpoint` is null for meshnet exit nodes.
+ * NULL is returned on failure.
+ *
+ */
+char *telio_get_connected_exit_nodes(const struct telio *dev);
+
 /**
  * Enables meshnet if it is not enabled yet.
  * In case meshnet is enabled, this updates the peer map with the specified one.
@@ -378,28 +1059,1129 @@ enum telio_result telio_disconnect_from_exit_nodes(const struct telio *dev);
  * # Parameters
  * - `cfg`: Output of GET /v1/meshnet/machines/{machineIdentifier}/map
  *
+ * # Returns
+ * `TELIO_RES_BAD_CONFIG` if `timestamp` of the config is older than allowed by
+ * `config_expiry_policy` feature with `refuse` action. `StaleConfig` event is reported for
+ * stale configs regardless of the action.
+ *
+ * `TELIO_RES_BAD_CONFIG` if the config has more peers than allowed by `max_peers` feature.
+ *
+ * `TELIO_RES_BAD_CONFIG` if the config assigns the same IP address to more than one node and
+ * the ip conflict policy is `detect_and_refuse`, see `telio_set_ip_conflict_resolution_policy`.
+ *
  */
 enum telio_result telio_set_meshnet(const struct telio *dev, const char *cfg);
 
+/**
+ * Compares peers of two meshnet configs.
+ *
+ * Peers are matched by their public keys. Device state is not used or changed.
+ *
+ * # Parameters
+ * - `old_cfg`: JSON string of the old config, in the format of `telio_set_meshnet`.
+ * - `new_cfg`: JSON string of the new config.
+ *
+ * # Returns
+ * JSON object with base64 encoded public keys of the peers, e.g.
+ * `{"added":["pk1"],"removed":["pk2"],"modified":["pk3"]}`, or NULL if a config is invalid.
+ *
+ */
+char *telio_get_config_diff(const struct telio *_dev, const char *old_cfg, const char *new_cfg);
+
 /**
  * Disables the meshnet functionality by closing all the connections.
  */
 enum telio_result telio_set_meshnet_off(const struct telio *dev);
 
-char *telio_generate_secret_key(const struct telio *_dev);
+/**
+ * Get a hash of the last successfully applied meshnet config.
+ *
+ * Can be compared with the previously returned one to skip pushing configs which did not change.
+ *
+ * # Returns
+ * Hex encoded SHA-256 hash of the canonical JSON form of the config. String of 64 zeroes is
+ * returned if meshnet is not configured, NULL is returned on failure.
+ */
+char *telio_get_meshnet_config_hash(const struct telio *dev);
 
-char *telio_generate_public_key(const struct telio *_dev, const char *secret);
+/**
+ * Get the hostname of this device in the meshnet.
+ *
+ * # Returns
+ * Hostname from the meshnet config, e.g. `mydevice.nord`. NULL is returned if meshnet is not
+ * configured or on failure.
+ */
+char *telio_get_mesh_hostname(const struct telio *dev);
 
-char *telio_get_version_tag(void);
+/**
+ * Get the time the meshnet config was last applied.
+ *
+ * Set when `telio_set_meshnet` or `telio_set_meshnet_off` completes successfully.
+ *
+ * # Returns
+ * Unix timestamp in seconds, 0 if no config was applied or on failure.
+ */
+uint64_t telio_get_last_config_apply_time(const struct telio *dev);
 
-char *telio_get_commit_sha(void);
+/**
+ * Get IP stack of the device, derived from the addresses in the meshnet config.
+ *
+ * # Returns
+ * One of "ipv4_only", "ipv6_only" or "dual_stack". NULL is returned if meshnet is not
+ * configured or on failure.
+ */
+char *telio_get_ip_stack(const struct telio *dev);
 
-char *telio_get_status_map(const struct telio *dev);
+/**
+ * Get the routing decision for traffic to the given IP address.
+ *
+ * Performs longest prefix match against allowed IPs of the WireGuard peers, similar to
+ * `ip route get` for the tunnel interface.
+ *
+ * # Parameters
+ * - `ip`: IPv4 or IPv6 address, e.g. `100.64.1.5`.
+ *
+ * # Returns
+ * JSON string `{"ip":"100.64.1.5","peer_pk":"...","path":"direct","is_exit":false,"interface":"nlx0"}`,
+ * `is_exit` is true when traffic goes through the exit node. NULL is returned if no route
+ * matches or on failure.
+ */
+char *telio_get_peer_route(const struct telio *dev, const char *ip);
 
 /**
- * Get last error's message length, including trailing null
+ * Get the allowed IPs of the peer as currently configured on the WireGuard adapter.
+ *
+ * Reflects what is actually routed to the peer, including changes made at runtime, e.g. by
+ * connecting to an exit node, rather than the meshnet config given to `telio_set_meshnet`.
+ *
+ * # Parameters
+ * - `public_key`: Base64 encoded WireGuard public key of the peer.
+ *
+ * # Returns
+ * JSON array of CIDR strings, e.g. `["100.64.1.5/32"]`, empty if the peer has no routes
+ * configured. NULL is returned on failure.
  */
-char *telio_get_last_error(const struct telio *_dev);
+char *telio_get_peer_allowed_ips(const struct telio *dev, const char *public_key);
+
+/**
+ * Get the routes to the tunnel interface managed by telio, for debugging routing conflicts.
+ *
+ * Routes are the allowed IPs of all peers configured on the WireGuard adapter. After
+ * `telio_stop` no routes are managed and an empty array is returned.
+ *
+ * # Returns
+ * JSON array, e.g.
+ * `[{"prefix":"0.0.0.0/0","gateway":null,"interface":"nlx0","metric":null,"source":"exit_node"}]`,
+ * `source` is either `exit_node` or `meshnet`. NULL is returned on failure.
+ */
+char *telio_get_route_table(const struct telio *dev);
+
+/**
+ * Asks a sleeping meshnet peer to wake up.
+ *
+ * The request is sent over the relay, so no direct connection to the peer is
+ * needed. The peer reports it with a `PeerWakeRequest` event and probes its
+ * endpoints again. Delivery is not confirmed.
+ *
+ * # Parameters
+ * - `public_key`: Base64 encoded WireGuard public key of the meshnet peer.
+ *
+ */
+enum telio_result telio_meshnet_wake_peer(const struct telio *dev, const char *public_key);
+
+/**
+ * Verifies that a meshnet peer owns the private key of its public key.
+ *
+ * A random 32 byte nonce is sent to the peer over the relay, and the peer answers
+ * with its signature of the nonce. The signature is created with XEdDSA, the Ed25519
+ * key pair derived from the WireGuard key of the peer. The result is reported with an
+ * `AuthChallengeResult` event, nothing is reported if the peer doesn't answer.
+ *
+ * # Parameters
+ * - `peer_pk`: Base64 encoded WireGuard public key of the meshnet peer.
+ *
+ */
+enum telio_result telio_meshnet_send_auth_challenge(const struct telio *dev, const char *peer_pk);
+
+/**
+ * Sends a WireGuard keepalive to the meshnet peer right away.
+ *
+ * Keepalives are otherwise sent by the persistent keepalive timer of each peer, which may lag
+ * behind after the system was asleep. The keepalive is sent regardless of the timer state.
+ *
+ * # Parameters
+ * - `public_key`: Base64 encoded WireGuard public key of the meshnet peer.
+ *
+ */
+enum telio_result telio_meshnet_peer_keepalive_probe(const struct telio *dev,
+                                                     const char *public_key);
+
+/**
+ * Asks the application to request a new meshnet IP address for this device.
+ *
+ * Reports `IpReassignmentRequested` event, the application should then ask the backend for a
+ * new assignment and pass the updated config to `telio_set_meshnet`. libtelio does not
+ * contact the backend itself.
+ *
+ * # Returns
+ * `TELIO_RES_ERROR` if meshnet is not configured.
+ *
+ */
+enum telio_result telio_meshnet_request_ip_reassignment(const struct telio *dev);
+
+/**
+ * Bans a meshnet peer locally, all traffic from it is dropped.
+ *
+ * The peer is kept in the WireGuard configuration, so the ban can be lifted with
+ * `telio_meshnet_unban_peer` without a meshnet config change. Bans are cleared on
+ * `telio_stop`.
+ *
+ * # Parameters
+ * - `public_key`: Base64 encoded WireGuard public key of the peer.
+ *
+ */
+enum telio_result telio_meshnet_ban_peer(const struct telio *dev, const char *public_key);
+
+/**
+ * Lifts the ban set by `telio_meshnet_ban_peer`.
+ *
+ * # Parameters
+ * - `public_key`: Base64 encoded WireGuard public key of the peer.
+ *
+ */
+enum telio_result telio_meshnet_unban_peer(const struct telio *dev, const char *public_key);
+
+/**
+ * Get peers banned with `telio_meshnet_ban_peer`.
+ *
+ * # Returns
+ * JSON array of base64 encoded public keys, e.g. `["5Mzuv..."]`. NULL is returned on failure.
+ */
+char *telio_meshnet_list_banned_peers(const struct telio *dev);
+
+/**
+ * Sends an application message to a meshnet peer over the relay.
+ *
+ * # Parameters
+ * - `recipient_public_key`: Base64 encoded WireGuard public key of the meshnet peer.
+ * - `payload`:              Message data, at most 4096 bytes.
+ * - `len`:                  Length of the message data.
+ *
+ */
+enum telio_result telio_meshnet_send_custom_message(const struct telio *dev,
+                                                    const char *recipient_public_key,
+                                                    const uint8_t *payload,
+                                                    uintptr_t len);
+
+/**
+ * Sends an application message to all connected meshnet peers over the relay.
+ *
+ * Peers receive it through the callback registered with `telio_set_custom_message_handler`.
+ *
+ * # Parameters
+ * - `payload`:  Message data, at most 4096 bytes.
+ * - `len`:      Length of the message data.
+ * - `enqueued`: Set to the number of peers the message was enqueued for, may be NULL.
+ *               Delivery to the peers is not confirmed.
+ *
+ */
+enum telio_result telio_meshnet_broadcast(const struct telio *dev,
+                                          const uint8_t *payload,
+                                          uintptr_t len,
+                                          uintptr_t *enqueued);
+
+/**
+ * Registers a callback for application messages received from meshnet peers.
+ *
+ * Replaces previously registered callback.
+ *
+ * # Parameters
+ * - `handler`: Callback receiving base64 encoded sender public key and message data.
+ *
+ */
+enum telio_result telio_set_custom_message_handler(const struct telio *dev,
+                                                   struct telio_custom_message_cb handler);
+
+/**
+ * Registers a callback detecting network changes, for hosts which don't call
+ * `telio_notify_network_change` themselves.
+ *
+ * The callback is polled every 5 seconds from the telio runtime, so it must return quickly.
+ * It returns an identifier of the current state of the network interfaces, e.g. a hash of
+ * the interface addresses and the default route. A change of the identifier is handled like
+ * `telio_notify_network_change`. Replaces previously registered callback,
+ * `telio_unregister_link_state_provider` removes it.
+ *
+ * # Parameters
+ * - `provider`: Callback returning the identifier of the network interfaces state.
+ *
+ */
+enum telio_result telio_register_link_state_provider(const struct telio *dev,
+                                                     struct telio_link_state_provider_cb provider);
+
+/**
+ * Unregisters the callback registered with `telio_register_link_state_provider`.
+ *
+ * A poll already in progress may still be running the callback when this returns.
+ *
+ */
+enum telio_result telio_unregister_link_state_provider(const struct telio *dev);
+
+/**
+ * Encrypts application payload so that only the given meshnet peer can read it.
+ *
+ * Key is derived from X25519 agreement of the device private key and `recipient_pk` and the
+ * direction from this device to the recipient, and is used only for application payloads,
+ * payload is sealed with XChaCha20-Poly1305. Peer decrypts it with `telio_decrypt_payload`.
+ *
+ * # Parameters
+ * - `recipient_pk`: Base64 encoded WireGuard public key of the recipient.
+ * - `plaintext`:    Payload to encrypt.
+ * - `len`:          Length of the payload.
+ * - `out`:          Set to the encrypted payload, must be freed with `telio_free_bytes`.
+ * - `out_len`:      Set to the length of the encrypted payload.
+ *
+ */
+enum telio_result telio_encrypt_payload(const struct telio *dev,
+                                        const char *recipient_pk,
+                                        const uint8_t *plaintext,
+                                        uintptr_t len,
+                                        uint8_t **out,
+                                        uintptr_t *out_len);
+
+/**
+ * Decrypts application payload encrypted by a meshnet peer with `telio_encrypt_payload`.
+ *
+ * # Parameters
+ * - `sender_pk`:  Base64 encoded WireGuard public key of the sender.
+ * - `ciphertext`: Encrypted payload.
+ * - `len`:        Length of the encrypted payload.
+ * - `out`:        Set to the decrypted payload, must be freed with `telio_free_bytes`.
+ * - `out_len`:    Set to the length of the decrypted payload.
+ *
+ */
+enum telio_result telio_decrypt_payload(const struct telio *dev,
+                                        const char *sender_pk,
+                                        const uint8_t *ciphertext,
+                                        uintptr_t len,
+                                        uint8_t **out,
+                                        uintptr_t *out_len);
+
+/**
+ * Signs data with the device WireGuard private key, e.g. to attest identity to a peer.
+ *
+ * Signature is created with XEdDSA over `data` prefixed with a context of its own, so the peer
+ * verifies it with the WireGuard public key of the device using `telio_verify_signature`, and
+ * it cannot be used in place of signatures libtelio creates for its own protocols.
+ *
+ * # Parameters
+ * - `data`:          Data to sign.
+ * - `len`:           Length of the data.
+ * - `out_signature`: Buffer the signature is written to, at least 64 bytes.
+ * - `out_len`:       Set to the size of `out_signature` by the caller, set to the length of
+ *                    the signature on return. `TELIO_RES_BAD_CONFIG` is returned and the
+ *                    required size is set if the buffer is too small.
+ *
+ */
+enum telio_result telio_sign_data(const struct telio *dev,
+                                  const uint8_t *data,
+                                  uintptr_t len,
+                                  uint8_t *out_signature,
+                                  uintptr_t *out_len);
+
+/**
+ * Verifies a signature created by a meshnet peer with `telio_sign_data`.
+ *
+ * Does not require a device instance.
+ *
+ * # Parameters
+ * - `public_key`: Base64 encoded WireGuard public key of the signer.
+ * - `data`:       Signed data.
+ * - `len`:        Length of the data.
+ * - `signature`:  Signature to verify.
+ * - `sig_len`:    Length of the signature.
+ *
+ * # Returns
+ * `TELIO_RES_OK` if the signature is valid, `TELIO_RES_ERROR` if it is not.
+ *
+ */
+enum telio_result telio_verify_signature(const char *public_key,
+                                         const uint8_t *data,
+                                         uintptr_t len,
+                                         const uint8_t *signature,
+                                         uintptr_t sig_len);
+
+/**
+ * Creates a short-lived token proving that the device owns its WireGuard private key.
+ *
+ * Token has the JWT compact form `header.claims.signature` with base64url encoded parts. Claims
+ * are `{"pk":"<public key>","iat":<unix secs>,"exp":<unix secs>}`, the signature is created with
+ * XEdDSA over `header.claims` prefixed with a context of tokens, so signatures of
+ * `telio_sign_data` are not valid tokens.
+ *
+ * # Parameters
+ * - `expiry_secs`: Validity of the token, in seconds, capped at one day.
+ *
+ * # Returns
+ * Token string, NULL is returned on failure.
+ *
+ */
+char *telio_create_meshnet_token(const struct telio *dev, uint32_t expiry_secs);
+
+/**
+ * Verifies a token created with `telio_create_meshnet_token`.
+ *
+ * Does not require a device instance.
+ *
+ * # Parameters
+ * - `token`:       Token to verify.
+ * - `expected_pk`: Base64 encoded WireGuard public key the token has to be issued for.
+ *
+ * # Returns
+ * `TELIO_RES_OK` if the token is valid, not expired and not issued in the future,
+ * `TELIO_RES_ERROR` if it is not.
+ *
+ */
+enum telio_result telio_verify_meshnet_token(const char *token, const char *expected_pk);
+
+/**
+ * Frees a buffer returned by `telio_encrypt_payload` or `telio_decrypt_payload`.
+ *
+ * Passing NULL is a no-op.
+ */
+void telio_free_bytes(uint8_t *bytes);
+
+/**
+ * Disables direct connections between meshnet peers.
+ *
+ * Stops all direct path probing (including STUN) and routes meshnet traffic through the relay.
+ * The setting is kept across `telio_set_meshnet` calls.
+ */
+enum telio_result telio_disable_direct_path(const struct telio *dev);
+
+/**
+ * Re-enables direct connections disabled by `telio_disable_direct_path`.
+ */
+enum telio_result telio_enable_direct_path(const struct telio *dev);
+
+/**
+ * Limits the number of meshnet peers which exchange traffic through the relay at the same time.
+ *
+ * The number is unlimited by default. When the limit is reached, sessions of the most recently
+ * active peers are kept and new peers are refused until the least recently used sessions become
+ * idle. The setting is kept across `telio_set_meshnet` calls.
+ *
+ * # Parameters
+ * - `max`: Maximum number of simultaneous relay sessions, the minimum allowed value is 1.
+ *
+ */
+enum telio_result telio_set_max_relay_connections(const struct telio *dev, uint32_t max);
+
+/**
+ * Chooses the relay servers the device connects to.
+ *
+ * Servers are taken from the meshnet config, the policy is kept across `telio_set_meshnet`
+ * calls. If the connected server is not allowed by the new policy, the relay reconnects.
+ *
+ * # Parameters
+ * - `policy_json`: One of:
+ *     - `{"strategy":"lowest_latency"}`: try servers in the order of their weight, assigned by
+ *       the API according to expected latency. This is the default.
+ *     - `{"strategy":"pinned","hostname":"derp1.example.com"}`: connect only to the server with
+ *       the hostname, other servers are not tried.
+ *
+ */
+enum telio_result telio_set_relay_server_selection_policy(const struct telio *dev,
+                                                          const char *policy_json);
+
+/**
+ * Sets WireGuard persistent keepalive intervals of meshnet peers.
+ *
+ * Peers without an override use `default_secs`, or the intervals from the
+ * `persistent_keepalive` feature if it is not set. The policy is kept across
+ * `telio_set_meshnet` calls and private key changes.
+ *
+ * # Parameters
+ * - `policy_json`: JSON object, e.g.
+ *   `{"default_secs":25,"overrides":[{"public_key":"...","secs":120}]}`.
+ *   Intervals are in seconds, at most 65535.
+ *
+ */
+enum telio_result telio_set_keepalive_policy(const struct telio *dev, const char *policy_json);
+
+/**
+ * Sets proxy used for control-plane connections of libtelio.
+ *
+ * HTTPS and WebSocket connections to DERP relay servers and connections to the
+ * DNS over HTTPS server of magic DNS are tunneled through the proxy. Data-plane WireGuard UDP traffic, including WireGuard packets to
+ * peers and STUN, always bypasses the proxy. The setting is kept across
+ * `telio_set_meshnet` calls. Changing it reconnects to the relay server.
+ *
+ * # Parameters
+ * - `settings_json`: JSON object, e.g.
+ *   `{"type":"socks5","host":"proxy.corp.com","port":1080,"username":"...","password":"..."}`.
+ *   `type` is `http` (tunnel opened with `CONNECT`) or `socks5`, `username`
+ *   and `password` are optional. NULL connects directly.
+ *
+ */
+enum telio_result telio_set_proxy_settings(const struct telio *dev, const char *settings_json);
+
+/**
+ * Sets handling of IP addresses assigned by the backend to more than one meshnet node.
+ *
+ * Overrides `ip_conflict_resolution` feature, the policy is checked on the next
+ * `telio_set_meshnet` call.
+ *
+ * # Parameters
+ * - `policy`: One of:
+ *     - `detect_and_log`: log the conflict and apply the config.
+ *     - `detect_and_refuse`: report `DuplicateIpDetected` event and fail `telio_set_meshnet`
+ *       with `TELIO_RES_BAD_CONFIG`.
+ *     - `detect_and_resolve_via_backoff`: report `DuplicateIpDetected` event and apply the
+ *       config without the conflicting peers. This device keeps its own addresses, among
+ *       peers the one with the lowest public key keeps the address.
+ *
+ *   NULL disables the check.
+ *
+ */
+enum telio_result telio_set_ip_conflict_resolution_policy(const struct telio *dev,
+                                                          const char *policy);
+
+/**
+ * Creates or replaces a group of meshnet peers sharing the same routing policy.
+ *
+ * If this device is a member of the group and the policy specifies an exit node, traffic is
+ * routed through that exit node. Groups are kept across `telio_set_meshnet` calls.
+ *
+ * # Parameters
+ * - `group_id`: Identifier of the group.
+ * - `member_keys_json`: JSON array of base64 encoded public keys of the meshnet nodes,
+ *                       may include the key of this device.
+ * - `policy_json`: JSON object `{"exit_node":"<public key>","mtu":1280}`, both fields are
+ *                  optional. NULL means no policy.
+ *
+ */
+enum telio_result telio_create_peer_group(const struct telio *dev,
+                                          const char *group_id,
+                                          const char *member_keys_json,
+                                          const char *policy_json);
+
+/**
+ * Deletes a peer group created by `telio_create_peer_group`.
+ *
+ * If traffic was routed through the exit node of the group, it is disconnected.
+ *
+ * # Parameters
+ * - `group_id`: Identifier of the group.
+ *
+ */
+enum telio_result telio_delete_peer_group(const struct telio *dev, const char *group_id);
+
+/**
+ * Lists peer groups created by `telio_create_peer_group`.
+ *
+ * # Returns
+ * JSON array `[{"id":"office","members":["<public key>"],"policy":{"exit_node":"<public key>","mtu":1280}}]`
+ * ordered by group identifiers. NULL is returned on failure.
+ *
+ */
+char *telio_list_peer_groups(const struct telio *dev);
+
+/**
+ * Limits bandwidth of traffic exchanged with the peer.
+ *
+ * Installs a token bucket rate limiter on the packet processing path, packets exceeding the limit
+ * are dropped. Replaces previous limits of the peer.
+ *
+ * Only the boringtun adapter runs packets through the limiter. With other adapters, or an adapter
+ * given to `telio_start_with_custom_adapter`, the call fails with `TELIO_RES_BAD_CONFIG`.
+ *
+ * # Parameters
+ * - `public_key`: Base64 encoded WireGuard public key of the peer.
+ * - `max_tx_kbps`: Limit of traffic sent to the peer in kilobits per second, 0 means unlimited.
+ * - `max_rx_kbps`: Limit of traffic received from the peer in kilobits per second, 0 means unlimited.
+ *
+ */
+enum telio_result telio_enable_traffic_shaping(const struct telio *dev,
+                                               const char *public_key,
+                                               uint32_t max_tx_kbps,
+                                               uint32_t max_rx_kbps);
+
+/**
+ * Removes bandwidth limits set by `telio_enable_traffic_shaping`.
+ *
+ * # Parameters
+ * - `public_key`: Base64 encoded WireGuard public key of the peer.
+ *
+ */
+enum telio_result telio_disable_traffic_shaping(const struct telio *dev, const char *public_key);
+
+/**
+ * Sets a nickname reported for the meshnet peer in the status map.
+ *
+ * Nickname is kept until meshnet config is replaced and does not affect the connection.
+ * `TELIO_RES_BAD_CONFIG` is returned if the nickname is not valid, e.g. contains spaces or
+ * upper case letters.
+ *
+ * # Parameters
+ * - `public_key`: Base64 encoded WireGuard public key of the meshnet peer.
+ * - `nickname`:   Nickname of the peer, NULL removes previously set nickname.
+ *
+ */
+enum telio_result telio_set_peer_nickname(const struct telio *dev,
+                                          const char *public_key,
+                                          const char *nickname);
+
+/**
+ * Gets a nickname reported for the meshnet peer.
+ *
+ * # Parameters
+ * - `public_key`: Base64 encoded WireGuard public key of the meshnet peer.
+ *
+ * # Returns
+ * Nickname set by `telio_set_peer_nickname` or the one from meshnet config. NULL is returned
+ * if the peer has no nickname or is not part of the meshnet.
+ *
+ */
+char *telio_get_peer_nickname(const struct telio *dev, const char *public_key);
+
+/**
+ * Gets the operating system of the meshnet peer.
+ *
+ * # Parameters
+ * - `public_key`: Base64 encoded WireGuard public key of the meshnet peer.
+ *
+ * # Returns
+ * Operating system as reported in the meshnet config, e.g. `linux` or `android`. NULL is
+ * returned if the config does not specify it or the peer is not part of the meshnet.
+ *
+ */
+char *telio_get_peer_os(const struct telio *dev, const char *public_key);
+
+/**
+ * Gets the telio version running on the meshnet peer.
+ *
+ * Peers advertise it along with their capabilities over the relay.
+ *
+ * # Parameters
+ * - `public_key`: Base64 encoded WireGuard public key of the meshnet peer.
+ *
+ * # Returns
+ * Version in the format of `telio_get_version_tag`, e.g. `4.2.1`. NULL is returned if the
+ * version is not known yet, the peer runs a version which does not advertise it or the peer
+ * is not part of the meshnet.
+ *
+ */
+char *telio_get_peer_version(const struct telio *dev, const char *public_key);
+
+/**
+ * Gets the features supported by the meshnet peer.
+ *
+ * Peers advertise them over the relay after connecting to it, all features are
+ * reported as unsupported until the advertisement is received.
+ *
+ * # Parameters
+ * - `public_key`: Base64 encoded WireGuard public key of the meshnet peer.
+ *
+ * # Returns
+ * JSON object
+ * `{"direct_path":true,"custom_messages":true,"psk_rotation":false,"version_advertisement":true}`.
+ * NULL is returned if the peer is not part of the meshnet or on failure.
+ *
+ */
+char *telio_meshnet_get_peer_capabilities(const struct telio *dev, const char *public_key);
+
+/**
+ * Gets all known endpoints of the meshnet peer.
+ *
+ * Unlike the endpoint in the node status, which is the one currently used, this lists the relay
+ * connection and every direct endpoint validated by the connectivity check.
+ *
+ * # Parameters
+ * - `public_key`: Base64 encoded WireGuard public key of the meshnet peer.
+ *
+ * # Returns
+ * JSON array
+ * `[{"endpoint":"1.2.3.4:51820","type":"stun","last_seen_secs":5},{"endpoint":"relay","type":"relay","last_seen_secs":1}]`,
+ * `type` is one of `local`, `stun`, `upnp` or `relay`, `last_seen_secs` is null if nothing
+ * was received through the endpoint yet. NULL is returned if the peer is not part of the
+ * meshnet or on failure.
+ *
+ */
+char *telio_meshnet_get_peer_endpoints(const struct telio *dev, const char *public_key);
+
+/**
+ * Gets the graph of meshnet nodes and connections of this node to the peers.
+ *
+ * Built from the node status and the relay latency measurements, so clients can render a
+ * network map. Every configured peer is a node, connected peers also have an edge from this
+ * node, telling if the connection is `direct` or `relay`.
+ *
+ * # Returns
+ * JSON string
+ * `{"nodes":[{"pk":"...","ip":"100.64.0.1"}],"edges":[{"from":"...","to":"...","path":"relay","rtt_ms":5}]}`,
+ * the first node is this device. `rtt_ms` is measured only for relayed connections and is null
+ * otherwise. NULL is returned on failure.
+ *
+ */
+char *telio_meshnet_get_topology(const struct telio *dev);
+
+char *telio_generate_secret_key(const struct telio *_dev);
+
+char *telio_generate_public_key(const struct telio *_dev, const char *secret);
+
+/**
+ * Checks whether a string is a valid WireGuard public key.
+ *
+ * Can be called without a device instance, e.g. to validate user input.
+ *
+ * # Returns
+ * `TELIO_RES_OK` if `key_b64` is a base64 encoded 32 byte key,
+ * `TELIO_RES_INVALID_STRING` otherwise.
+ */
+enum telio_result telio_validate_public_key(const char *key_b64);
+
+/**
+ * Checks whether a string is a valid WireGuard secret key.
+ *
+ * Can be called without a device instance, e.g. to validate user input.
+ *
+ * # Returns
+ * `TELIO_RES_OK` if `key_b64` is a base64 encoded 32 byte key,
+ * `TELIO_RES_INVALID_STRING` otherwise.
+ */
+enum telio_result telio_validate_secret_key(const char *key_b64);
+
+char *telio_get_version_tag(void);
+
+char *telio_get_commit_sha(void);
+
+/**
+ * Get version of the C API implemented by the library.
+ *
+ * Can be called before `telio_new`. Compare it with `TELIO_MIN_API_VERSION` of the header the
+ * wrapper was built with to detect mismatched library and wrapper versions.
+ *
+ */
+uint32_t telio_get_api_version(void);
+
+/**
+ * Get the JSON schema of a config format accepted by the library.
+ *
+ * Schemas are generated from the types used to parse the configs when the library is built, so
+ * they always match the running version. Can be called before `telio_new`.
+ *
+ * # Parameters
+ * - `schema_type`: Config format to describe.
+ *
+ * # Returns
+ * JSON schema (draft 7) of the config.
+ *
+ */
+char *telio_get_config_schema(enum telio_schema_type schema_type);
+
+#if defined(TELIO_BENCHMARKING)
+/**
+ * Measure throughput of the WireGuard implementation of the adapter used by the device.
+ *
+ * Blocks for at least `duration_ms` milliseconds, capped at 10 seconds. Only available when built
+ * with `benchmarking` feature, returns NULL if the adapter cannot be benchmarked. The device is not
+ * locked while the measurement runs.
+ *
+ * The live adapter is not measured: packets go through a pair of in-memory boringtun tunnels,
+ * without the TUN device and the network sockets. Only the `boringtun` adapter is supported,
+ * NULL is returned for the other adapters.
+ *
+ * # Returns
+ * JSON string `{"adapter":"boringtun","throughput_mbps":120.5,"latency_us":50}`.
+ *
+ */
+char *telio_benchmark_adapter(const struct telio *dev, uint32_t duration_ms);
+#endif
+
+#if defined(TELIO_DIAGNOSTICS)
+/**
+ * Trace the path to the meshnet peer with ICMP probes of increasing TTL.
+ *
+ * Blocks for up to `max_hops * timeout_ms` milliseconds. Only available when built with
+ * `diagnostics` feature and requires privileges to open a raw socket.
+ *
+ * # Parameters
+ * - `target_public_key`: Base64 encoded WireGuard public key of the meshnet peer.
+ * - `max_hops`: Maximum number of probed hops.
+ * - `timeout_ms`: Time to wait for the reply of a single hop, capped at 5000 milliseconds.
+ *
+ * # Returns
+ * JSON string `{"hops":[{"hop":1,"via":"relay","rtt_ms":10}]}`, `rtt_ms` is null for hops
+ * which did not reply in time. NULL is returned if the peer is not part of the meshnet or on
+ * failure.
+ *
+ */
+char *telio_meshnet_traceroute(const struct telio *dev,
+                               const char *target_public_key,
+                               uint8_t max_hops,
+                               uint32_t timeout_ms);
+#endif
+
+#if defined(TELIO_DIAGNOSTICS)
+/**
+ * Measure round trip time and packet loss to the meshnet peer with ICMP echo requests.
+ *
+ * Blocks for up to `samples * max(interval_ms, 1000)` milliseconds. Only available when built
+ * with `diagnostics` feature and requires privileges to open a raw socket.
+ *
+ * # Parameters
+ * - `public_key`: Base64 encoded WireGuard public key of the meshnet peer.
+ * - `samples`: Number of probes to send, between 1 and 65535.
+ * - `interval_ms`: Time between the probes.
+ *
+ * # Returns
+ * JSON string `{"avg_rtt_ms":15.2,"min_rtt_ms":12.0,"max_rtt_ms":18.0,"packet_loss_pct":0.5}`,
+ * RTT fields are null if no probe was answered. NULL is returned if the peer is not part of
+ * the meshnet or on failure.
+ *
+ */
+char *telio_measure_path_quality(const struct telio *dev,
+                                 const char *public_key,
+                                 uint32_t samples,
+                                 uint32_t interval_ms);
+#endif
+
+#if defined(TELIO_DIAGNOSTICS)
+/**
+ * Diagnose connectivity to the meshnet peer in several stages.
+ *
+ * Stages are run one after another even if some of them fail: `dns` resolves the hostname of the
+ * peer through magic DNS, `relay` checks the relay server connection and the peer's presence on
+ * it, `stun` checks that STUN servers respond so endpoints for the direct path can be discovered
+ * and `ping` sends an ICMP echo request to the peer through the tunnel. Blocks for up to the magic
+ * DNS query timeout plus one second. Only available when built with `diagnostics` feature, the
+ * `ping` stage requires privileges to open a raw socket.
+ *
+ * # Parameters
+ * - `target_public_key`: Base64 encoded WireGuard public key of the meshnet peer.
+ *
+ * # Returns
+ * JSON string `{"tests":[{"test":"relay","passed":true,"latency_ms":12,"error":null}]}`, with
+ * the stages in the order they were run. `latency_ms` is null when not known. NULL is returned
+ * if the peer is not part of the meshnet or on failure.
+ *
+ */
+char *telio_check_connectivity(const struct telio *dev, const char *target_public_key);
+#endif
+
+#if defined(TELIO_MEMORY_DIAGNOSTICS)
+/**
+ * Get approximate memory used by long-lived structures of the started device.
+ *
+ * Estimated from sizes of the collections kept by each subsystem. Only available when built
+ * with `memory_diagnostics` feature.
+ *
+ * # Returns
+ * JSON string
+ * `{"peer_map_bytes":4096,"log_buffer_bytes":102400,"relay_state_bytes":8192,"total_bytes":114688}`
+ * or NULL on failure. `log_buffer_bytes` is the history of events reported to the app.
+ *
+ */
+char *telio_get_memory_stats(const struct telio *dev);
+#endif
+
+#if defined(TELIO_AUDIT_LOG)
+/**
+ * Write peer changes, exit node connections and key rotations to the audit log.
+ *
+ * Every record is chained with HMAC keyed by the audit key, so removed or modified records are
+ * detected by `telio_verify_audit_log`. The audit key is kept by the application and is
+ * independent of the device private key, so key rotations do not break the log. Only available
+ * when built with `audit_log` feature.
+ *
+ * # Parameters
+ * - `path`: Path of the log file, records are appended to an existing log. NULL stops logging.
+ * - `audit_key`: Secret of the application keying the log, e.g. 32 random bytes encoded as
+ *   base64. Ignored when `path` is NULL.
+ *
+ * # Returns
+ * `TELIO_RES_INVALID_KEY` if the audit key is empty, `TELIO_RES_ERROR` if the file cannot be
+ * opened or the existing log does not verify with the audit key.
+ *
+ */
+enum telio_result telio_set_audit_log_path(const struct telio *dev,
+                                           const char *path,
+                                           const char *audit_key);
+#endif
+
+#if defined(TELIO_AUDIT_LOG)
+/**
+ * Verify the chain of the audit log written with `audit_key`.
+ *
+ * # Parameters
+ * - `path`: Path of the log file.
+ * - `audit_key`: Audit key given to `telio_set_audit_log_path`.
+ *
+ * # Returns
+ * `TELIO_RES_OK` if the log is intact, `TELIO_RES_ERROR` if it was tampered with or cannot be
+ * read.
+ *
+ */
+enum telio_result telio_verify_audit_log(const char *path, const char *audit_key);
+#endif
+
+#if defined(TELIO_TRAFFIC_LOGGING)
+/**
+ * Start logging metadata of a random sample of packets exchanged with peers.
+ *
+ * Logged are direction, size, public key of the peer and the path (relay or direct) of packets
+ * accepted by the firewall. Calling it again replaces the config. Only available when built with
+ * `traffic_logging` feature.
+ *
+ * # Parameters
+ * - `config_json`: JSON object, e.g.
+ *   `{"sample_rate":0.01,"max_entries":1000,"include_payload_bytes":false}`.
+ *   `sample_rate` is the probability of logging a packet, `max_entries` latest entries are kept.
+ *   First 64 bytes of the packets are logged only if `include_payload_bytes` is true. Missing
+ *   fields take the values from the example.
+ *
+ */
+enum telio_result telio_enable_traffic_logging(const struct telio *dev,
+                                               const char *config_json);
+#endif
+
+#if defined(TELIO_TRAFFIC_LOGGING)
+/**
+ * Get packets logged since `telio_enable_traffic_logging`.
+ *
+ * Only available when built with `traffic_logging` feature.
+ *
+ * # Returns
+ * JSON array of entries ordered from the oldest, e.g.
+ * `[{"ts_ms":1700000000000,"direction":"inbound","size":84,"public_key":"...","path":"relay"}]`.
+ * Entries carry base64 encoded `payload` only if `include_payload_bytes` was set. NULL is returned
+ * on failure.
+ *
+ */
+char *telio_get_traffic_log(const struct telio *dev);
+#endif
+
+#if defined(TELIO_PACKET_STATS)
+/**
+ * Registers a callback called for every packet exchanged with peers.
+ *
+ * Only packets accepted by the firewall are reported. The callback is called from the packet
+ * processing threads without locks or allocations, so it must be thread safe and return
+ * quickly. Replaces previously registered callback, which may still be called by packets
+ * already in flight. Only available when built with `packet_stats` feature.
+ *
+ * # Parameters
+ * - `cb`: Callback receiving direction (0 inbound, 1 outbound), pointer to the 32 byte public
+ *         key of the peer, valid only during the call, and size of the IP packet.
+ *
+ */
+enum telio_result telio_enable_traffic_stats_hook(const struct telio *dev,
+                                                  struct telio_packet_stats_cb cb);
+#endif
+
+#if defined(TELIO_PACKET_TRACE)
+/**
+ * Start capturing the first bytes of packets exchanged with meshnet peers over the relay.
+ *
+ * Packets are captured as exchanged with the peers, before encryption of outgoing and after
+ * decryption of incoming control messages. Calling it again replaces the limits, which are kept
+ * when meshnet is restarted. Only available when built with `packet_trace` feature.
+ *
+ * # Parameters
+ * - `max_bytes_per_packet`: Number of bytes captured from the start of each packet.
+ * - `max_packets`: Number of the latest packets kept, 0 disables the capture.
+ *
+ */
+enum telio_result telio_enable_packet_trace(const struct telio *dev,
+                                            uint32_t max_bytes_per_packet,
+                                            uint32_t max_packets);
+#endif
+
+#if defined(TELIO_PACKET_TRACE)
+/**
+ * Get packets captured since the previous call, the capture buffer is cleared.
+ *
+ * Only available when built with `packet_trace` feature.
+ *
+ * # Returns
+ * JSON array of packets ordered from the oldest, e.g.
+ * `[{"direction":"rx","peer_pk":"...","timestamp_ms":1700000000000,"hex":"aabbcc"}]`.
+ * NULL is returned on failure.
+ *
+ */
+char *telio_get_packet_trace(const struct telio *dev);
+#endif
+
+/**
+ * Get the number of meshnet peers which are currently connected.
+ *
+ * Returns -1 on error.
+ */
+int64_t telio_get_connected_peers_count(const struct telio *dev);
+
+/**
+ * Get the estimated difference of the local clock from the clock of the relay server.
+ *
+ * The skew is estimated from the time reported by the server when connecting to it.
+ * `ClockSkewWarning` event is reported when it exceeds 90 seconds, as WireGuard handshakes
+ * fail when the clocks differ by more than 3 minutes.
+ *
+ * # Returns
+ * Skew in milliseconds, positive if the local clock is ahead. Returns 0 if not connected to
+ * a relay server or on error.
+ */
+int64_t telio_get_clock_skew(const struct telio *dev);
+
+/**
+ * Get the number of events waiting to be delivered to the event callback.
+ *
+ * Growing depth means the callback does not keep up with the events, e.g. it is blocked.
+ * Returns 0 on error.
+ */
+uint32_t telio_get_event_queue_depth(const struct telio *dev);
+
+/**
+ * Get the number of threads spawned for this instance.
+ *
+ * Counts threads of the async runtime and the thread monitoring them, threads spawned
+ * internally by the WireGuard adapter are not included. Returns -1 on error.
+ */
+int32_t telio_get_thread_count(const struct telio *dev);
+
+char *telio_get_status_map(const struct telio *dev);
+
+/**
+ * Get a liveness report of the running device.
+ *
+ * Each component is probed with a bounded timeout, components that do not respond in time are
+ * reported as `degraded`, components that are not enabled are reported as `disabled`.
+ *
+ * # Returns
+ * JSON encoded report, e.g.
+ * `{"healthy":true,"components":{"wireguard":"ok","relay":"ok","dns":"disabled","direct_path":"ok"}}`
+ * or NULL if the runtime itself is unresponsive, the reason is available via `telio_get_last_error`.
+ */
+char *telio_health_check(const struct telio *dev);
+
+/**
+ * Get STUN servers used for direct path discovery.
+ *
+ * # Returns
+ * JSON array of servers, e.g.
+ * `[{"hostname":"derp-01","ip":"1.2.3.4","port":3478,"last_response_ms":12}]`.
+ * `last_response_ms` is null for servers which failed to respond or were not queried yet.
+ * `ip` is the IPv6 address of the server when IPv6 is enabled in the config and the server
+ * has one. Array is empty when direct connections are not enabled. NULL is returned on failure,
+ * the reason is available via `telio_get_last_error`.
+ */
+char *telio_get_stun_servers(const struct telio *dev);
+
+/**
+ * Get network interfaces seen by direct path discovery.
+ *
+ * # Returns
+ * JSON array of IPv4 interfaces other than loopback, e.g.
+ * `[{"name":"en0","ip":"192.168.1.5","role":"primary"},{"name":"utun0","ip":"100.64.0.1","role":"tunnel"}]`.
+ * Interfaces with `primary` role are probed for endpoint candidates, interfaces with `tunnel` role
+ * have an address from the meshnet range and are skipped. Array is empty when local interfaces
+ * endpoint provider is not enabled. NULL is returned on failure.
+ */
+char *telio_list_interfaces(const struct telio *dev);
+
+/**
+ * Get the latest round trip time samples over the relay.
+ *
+ * Samples of a peer are taken from WireGuard handshakes going through the relay. Samples of the
+ * relay server itself are taken from DERP poll requests, which are sent only when derp polling
+ * feature is enabled. At most 64 latest samples are kept for the server and for each peer.
+ *
+ * # Parameters
+ * - `peer_pk`: Base64 encoded public key of the meshnet peer, NULL for the relay server.
+ * - `n_samples`: Maximum number of samples to return.
+ *
+ * # Returns
+ * JSON array of samples ordered from the oldest, e.g.
+ * `[{"ts_secs":1700000010,"rtt_ms":15},{"ts_secs":1700000020,"rtt_ms":17}]`.
+ * Array is empty when meshnet is not configured. NULL is returned on failure.
+ */
+char *telio_get_relay_latency_history(const struct telio *dev,
+                                      const char *peer_pk,
+                                      uint32_t n_samples);
+
+/**
+ * Get all configured relay servers and their health.
+ *
+ * Servers are listed in the order they are tried, including servers which were not tried yet.
+ * `latency_ms` is the time the latest successful connection to the server took to establish,
+ * `last_checked_secs` is the number of seconds since the latest connection attempt.
+ *
+ * # Returns
+ * JSON array of servers, e.g.
+ * `[{"hostname":"derp1.example.com","port":8765,"latency_ms":15,"connected":true,"last_checked_secs":5}]`.
+ * `latency_ms` and `last_checked_secs` are null for servers which were not tried yet. Array is
+ * empty when meshnet is not configured. NULL is returned on failure.
+ */
+char *telio_get_relay_server_list(const struct telio *dev);
+
+/**
+ * Get identifier of the ongoing relay session with a meshnet peer.
+ *
+ * Session starts with the first packet relayed to or from the peer and ends when the connection
+ * to the relay server is closed, so the identifier changes every time the session is
+ * re-established. It is logged when the session starts and ends, to group log lines by session.
+ *
+ * # Parameters
+ * - `peer_pk`: Base64 encoded public key of the meshnet peer.
+ *
+ * # Returns
+ * UUID string, e.g. `67e55044-10b1-426f-9247-bb680e5fe0c8`. NULL is returned if there is no
+ * relay session with the peer or on failure.
+ */
+char *telio_get_relay_session_id(const struct telio *dev, const char *peer_pk);
+
+/**
+ * Get connection events reported after the given time.
+ *
+ * Allows replaying events missed while the app was not active. Peer, relay and key rotation
+ * events are kept, only the latest `event_history_size` (256 by default) of them are available.
+ *
+ * # Parameters
+ * - `since_unix_secs`: Unix timestamp in seconds, only newer events are returned.
+ *
+ * # Returns
+ * JSON array of events ordered from the oldest, in the same form as passed to the event
+ * callback with additional `ts_secs` field, e.g.
+ * `[{"ts_secs":1700000010,"type":"node","body":{...}}]`. NULL is returned on failure.
+ */
+char *telio_get_connection_events_since(const struct telio *dev, uint64_t since_unix_secs);
+
+/**
+ * Get connections currently tracked by the firewall.
+ *
+ * # Returns
+ * JSON array of at most 256 most recently active connections, e.g.
+ * `[{"src":"192.168.100.1:1234","dst":"1.1.1.1:443","peer_pk":"...","bytes_tx":100,"bytes_rx":200}]`.
+ * `src` is the side which initiated the connection. NULL is returned on failure.
+ */
+char *telio_list_active_connections(const struct telio *dev);
+
+/**
+ * Get last error's message length, including trailing null
+ */
+char *telio_get_last_error(const struct telio *_dev);
+
+/**
+ * Get the last error of the WireGuard adapter.
+ *
+ * Unlike `telio_get_last_error`, only failures of the adapter itself are reported, e.g. failed
+ * creation of the tunnel interface or a failed UAPI request. The error is kept until another
+ * adapter operation fails.
+ *
+ * # Returns
+ * Error message, or NULL if no adapter operation has failed yet.
+ */
+char *telio_get_adapter_error(const struct telio *dev);
 
 /**
  * For testing only.
@@ -413,9 +2195,14 @@ enum telio_result __telio_generate_thread_panic(const struct telio *dev);
 
 void __telio_force_export(enum telio_result,
                           enum telio_adapter_type,
+                          enum telio_schema_type,
                           struct telio_event_cb,
                           struct telio_logger_cb,
-                          struct telio_protect_cb);
+                          struct telio_logger_v2_cb,
+                          struct telio_custom_message_cb,
+                          struct telio_adapter_factory_cb,
+                          struct telio_protect_cb,
+                          struct telio_bind_network_cb);
 
 #ifdef __cplusplus
 } // extern "C"
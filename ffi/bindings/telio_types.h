@@ -10,6 +10,13 @@
 #include <stdint.h>
 #include <stdlib.h>
 
+/**
+ * Version of the C API described by this header, incremented whenever a function or a type of
+ * the API changes. Wrappers should check that `telio_get_api_version` returns at least this
+ * version before calling anything else.
+ */
+#define TELIO_MIN_API_VERSION 1
+
 /**
  * Possible adapters.
  */
@@ -44,6 +51,20 @@ typedef enum telio_log_level {
   TELIO_LOG_TRACE = 6,
 } telio_log_level;
 
+/**
+ * Config formats described by `telio_get_config_schema`.
+ */
+typedef enum telio_schema_type {
+  /**
+   * Features JSON passed to `telio_new`.
+   */
+  TELIO_SCHEMA_FEATURES,
+  /**
+   * Meshnet config JSON passed to `telio_set_meshnet`.
+   */
+  TELIO_SCHEMA_MESHNET,
+} telio_schema_type;
+
 typedef enum telio_result {
   /**
    * Operation was successful.
@@ -73,6 +94,10 @@ typedef enum telio_result {
    * The device is already started.
    */
   TELIO_RES_ALREADY_STARTED = 6,
+  /**
+   * Operation is not supported on this platform.
+   */
+  TELIO_RES_UNSUPPORTED_PLATFORM = 7,
 } telio_result;
 
 typedef void (*telio_event_fn)(void*, const char*);
@@ -136,6 +161,7 @@ extern "C" {
 
 void __telio_force_export(enum telio_result,
                           enum telio_adapter_type,
+                          enum telio_schema_type,
                           struct telio_event_cb,
                           struct telio_logger_cb,
                           struct telio_protect_cb);
@@ -45,6 +45,12 @@ struct telio {};
 %extend telio {
     static enum telio_adapter_type get_default_adapter();
 
+    %newobject get_supported_adapters;
+    static char* get_supported_adapters();
+
+    %newobject get_platform_info;
+    static char* get_platform_info();
+
 
 #if defined(__ANDROID__)
     telio(const char* features, telio_event_cb events, enum telio_log_level level, telio_logger_cb logger, telio_protect_cb protect, jobject ctx) {
@@ -68,38 +74,91 @@ struct telio {};
         telio_destroy($self);
     }
 
+    enum telio_result enable_event_batching(unsigned int max_batch_size,
+                                            unsigned int flush_interval_ms);
+
+    enum telio_result disable_event_batching();
+
     enum telio_result start(const char *private_key,
                             enum telio_adapter_type adapter);
 
+    enum telio_result start_async(const char *private_key,
+                                  enum telio_adapter_type adapter);
+
     enum telio_result start_named(const char *private_key,
                             enum telio_adapter_type adapter,
                             const char *name);
 
+    enum telio_result start_named_with_fwmark(const char *private_key,
+                                              enum telio_adapter_type adapter,
+                                              const char *name,
+                                              unsigned int fwmark);
 
-#if !defined(_WIN32)
     enum telio_result start_with_tun(const char *private_key,
                                            enum telio_adapter_type adapter,
                                            int tun);
-#endif
 
     enum telio_result enable_magic_dns(const char *forward_servers);
 
+    enum telio_result notify_dns_servers_changed(const char *new_servers);
+
+    enum telio_result set_dns_fallback_servers(const char *servers_json);
+
+    enum telio_result set_dns_ttl_override(unsigned int min_ttl_secs, unsigned int max_ttl_secs);
+
+    enum telio_result enable_dns_over_https(const char *doh_url);
+
+    enum telio_result disable_dns_over_https();
+
     enum telio_result disable_magic_dns();
 
+    %newobject proxy_dns_query;
+    const char* proxy_dns_query(const char *query_name, const char *record_type);
+
+    %newobject resolve_mesh_hostname;
+    const char* resolve_mesh_hostname(const char *hostname);
+
+    enum telio_result dns_override(const char *name,
+                                   const char *addresses_json,
+                                   unsigned int ttl);
+
+    enum telio_result dns_remove_override(const char *name);
+
     enum telio_result stop();
 
     unsigned long long get_adapter_luid();
 
+    int get_current_tun_fd();
+
     enum telio_result set_private_key(const char *private_key);
 
+    enum telio_result rotate_secret_key(const char *new_private_key);
+
     const char* get_private_key();
 
-#if defined(__linux__)
+    %newobject get_device_fingerprint;
+    const char* get_device_fingerprint();
+
+    %newobject get_device_id;
+    const char* get_device_id();
+
+    %newobject export_wireguard_config;
+    const char* export_wireguard_config();
+
+    enum telio_result reset_statistics();
+
+    enum telio_result reset_statistics_for(const char *public_key);
+
+    enum telio_result listen_on_port(unsigned short port);
+
     enum telio_result set_fwmark(unsigned int fwmark);
-#endif
+
+    enum telio_result set_network_constraints(const char *constraints);
 
     enum telio_result notify_network_change(const char *notify_info);
 
+    enum telio_result trigger_network_redetection();
+
     enum telio_result connect_to_exit_node(const char *public_key,
                                            const char *allowed_ips,
                                            const char *endpoint);
@@ -109,30 +168,178 @@ struct telio {};
                                                    const char *allowed_ips,
                                                    const char *endpoint);
 
+    enum telio_result connect_to_exit_node_with_retry(const char *public_key,
+                                                      const char *allowed_ips,
+                                                      const char *endpoint,
+                                                      const char *policy_json);
+
     enum telio_result disconnect_from_exit_node(const char *public_key);
 
     enum telio_result disconnect_from_exit_nodes();
 
+    enum telio_result enable_exit_node_failover(const char *failover_config_json);
+
+    enum telio_result disable_exit_node_failover();
+
+    %newobject get_connected_exit_nodes;
+    const char* get_connected_exit_nodes();
+
     enum telio_result set_meshnet(const char *cfg);
 
+    %newobject get_config_diff;
+    const char* get_config_diff(const char *old_cfg, const char *new_cfg);
+
     enum telio_result set_meshnet_off();
 
+    enum telio_result meshnet_wake_peer(const char *public_key);
+
+    enum telio_result meshnet_send_auth_challenge(const char *peer_pk);
+
+    enum telio_result meshnet_peer_keepalive_probe(const char *public_key);
+
+    enum telio_result meshnet_request_ip_reassignment();
+
+    enum telio_result meshnet_ban_peer(const char *public_key);
+
+    enum telio_result meshnet_unban_peer(const char *public_key);
+
+    %newobject meshnet_list_banned_peers;
+    const char* meshnet_list_banned_peers();
+
+    %newobject get_meshnet_config_hash;
+    const char* get_meshnet_config_hash();
+
+    %newobject get_mesh_hostname;
+    const char* get_mesh_hostname();
+
+    unsigned long long get_last_config_apply_time();
+
+    %newobject get_ip_stack;
+    const char* get_ip_stack();
+
+    %newobject get_peer_route;
+    const char* get_peer_route(const char *ip);
+
+    %newobject get_peer_allowed_ips;
+    const char* get_peer_allowed_ips(const char *public_key);
+
+    %newobject get_route_table;
+    const char* get_route_table();
+
+    enum telio_result disable_direct_path();
+
+    enum telio_result enable_direct_path();
+
+    enum telio_result set_max_relay_connections(unsigned int max);
+
+    enum telio_result set_relay_server_selection_policy(const char *policy_json);
+
+    enum telio_result set_keepalive_policy(const char *policy_json);
+
+    enum telio_result set_proxy_settings(const char *settings_json);
+
+    enum telio_result set_ip_conflict_resolution_policy(const char *policy);
+
+    enum telio_result create_peer_group(const char *group_id,
+                                        const char *member_keys_json,
+                                        const char *policy_json);
+
+    enum telio_result delete_peer_group(const char *group_id);
+
+    %newobject list_peer_groups;
+    const char* list_peer_groups();
+
+    enum telio_result enable_traffic_shaping(const char *public_key,
+                                             unsigned int max_tx_kbps,
+                                             unsigned int max_rx_kbps);
+
+    enum telio_result disable_traffic_shaping(const char *public_key);
+
+    enum telio_result set_peer_nickname(const char *public_key, const char *nickname);
+
+    %newobject get_peer_nickname;
+    const char* get_peer_nickname(const char *public_key);
+
+    %newobject get_peer_os;
+    const char* get_peer_os(const char *public_key);
+
+    %newobject get_peer_version;
+    const char* get_peer_version(const char *public_key);
+
+    %newobject meshnet_get_peer_capabilities;
+    const char* meshnet_get_peer_capabilities(const char *public_key);
+
+    %newobject meshnet_get_peer_endpoints;
+    const char* meshnet_get_peer_endpoints(const char *public_key);
+
+    %newobject meshnet_get_topology;
+    const char* meshnet_get_topology();
+
     %newobject generate_secret_key;
     const char* generate_secret_key();
 
     %newobject generate_public_key;
     const char* generate_public_key(const char *secret_key);
 
+    static enum telio_result validate_public_key(const char *key_b64);
+
+    static enum telio_result validate_secret_key(const char *key_b64);
+
+    %newobject create_meshnet_token;
+    const char* create_meshnet_token(unsigned int expiry_secs);
+
+    static enum telio_result verify_meshnet_token(const char *token, const char *expected_pk);
+
+    long long get_connected_peers_count();
+
+    long long get_clock_skew();
+
+    unsigned int get_event_queue_depth();
+
+    int get_thread_count();
+
     %newobject get_status_map;
     const char* get_status_map();
 
+    %newobject health_check;
+    const char* health_check();
+
+    %newobject get_stun_servers;
+    const char* get_stun_servers();
+
+    %newobject list_interfaces;
+    const char* list_interfaces();
+
+    %newobject get_relay_latency_history;
+    const char* get_relay_latency_history(const char *peer_pk, unsigned int n_samples);
+
+    %newobject get_relay_server_list;
+    const char* get_relay_server_list();
+
+    %newobject get_relay_session_id;
+    const char* get_relay_session_id(const char *peer_pk);
+
+    %newobject get_connection_events_since;
+    const char* get_connection_events_since(unsigned long long since_unix_secs);
+
+    %newobject list_active_connections;
+    const char* list_active_connections();
+
     %newobject get_last_error;
     const char* get_last_error();
 
+    %newobject get_adapter_error;
+    const char* get_adapter_error();
+
     %newobject get_version_tag;
     static char* get_version_tag();
 
     %newobject get_commit_sha;
     static char* get_commit_sha();
+
+    static unsigned int get_api_version();
+
+    %newobject get_config_schema;
+    static char* get_config_schema(enum telio_schema_type schema_type);
 };
 
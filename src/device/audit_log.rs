@@ -0,0 +1,239 @@
+//! Tamper evident log of security relevant operations, only built with `audit_log` feature
+//!
+//! Every line of the log is a JSON object carrying HMAC-SHA256 of its content and of the hash
+//! of the previous line, so removed, reordered or modified lines break the chain. HMAC key is
+//! derived from the audit key provided by the application, which is independent of the device
+//! private key, so the log stays verifiable and can be reopened after key rotations.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, ErrorKind, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use telio_crypto::PublicKey;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Domain separation of the HMAC key from other uses of the audit key
+const AUDIT_KEY_CONTEXT: &[u8] = b"libtelio audit log";
+
+/// Security relevant operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditEvent {
+    /// Private key of the device was rotated, `pk` is the new public key
+    KeyRotated,
+    /// Meshnet peer was added to the config
+    PeerAdded,
+    /// Meshnet peer was removed from the config
+    PeerRemoved,
+    /// Traffic is routed through the exit node
+    ExitNodeConnected,
+    /// Traffic is no longer routed through the exit node
+    ExitNodeDisconnected,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AuditEntry {
+    ts: u64,
+    event: AuditEvent,
+    pk: PublicKey,
+    prev_hash: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AuditRecord {
+    #[serde(flatten)]
+    entry: AuditEntry,
+    hash: String,
+}
+
+/// Appends chained records to the log file
+#[derive(Debug)]
+pub struct AuditLog {
+    file: File,
+    key: [u8; 32],
+    prev_hash: String,
+}
+
+impl AuditLog {
+    /// Open the log for appending, creating it if needed
+    ///
+    /// Fails with `ErrorKind::InvalidData` if the existing log does not verify with `audit_key`,
+    /// records are not appended to a broken chain.
+    pub fn open(path: &Path, audit_key: &[u8]) -> io::Result<Self> {
+        let key = hmac_key(audit_key)?;
+        let prev_hash = match File::open(path) {
+            Ok(file) => verify_chain(file, &key)?,
+            Err(err) if err.kind() == ErrorKind::NotFound => genesis_hash(),
+            Err(err) => return Err(err),
+        };
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file,
+            key,
+            prev_hash,
+        })
+    }
+
+    /// Append the operation to the log
+    pub fn record(&mut self, event: AuditEvent, pk: PublicKey) -> io::Result<()> {
+        let entry = AuditEntry {
+            ts: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+            event,
+            pk,
+            prev_hash: self.prev_hash.clone(),
+        };
+        let record = AuditRecord {
+            hash: entry_hash(&self.key, &entry)?,
+            entry,
+        };
+        writeln!(self.file, "{}", serde_json::to_string(&record)?)?;
+        self.file.flush()?;
+        self.prev_hash = record.hash;
+        Ok(())
+    }
+}
+
+/// Check the chain of the log written with `audit_key`
+///
+/// Fails with `ErrorKind::InvalidData` naming the first line which does not verify.
+pub fn verify(path: &Path, audit_key: &[u8]) -> io::Result<()> {
+    verify_chain(File::open(path)?, &hmac_key(audit_key)?).map(|_| ())
+}
+
+/// Returns the hash of the last record
+fn verify_chain(file: File, key: &[u8; 32]) -> io::Result<String> {
+    let mut prev_hash = genesis_hash();
+    for (n, line) in BufReader::new(file).lines().enumerate() {
+        let invalid = |reason: &str| {
+            io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Audit log line {}: {}", n + 1, reason),
+            )
+        };
+        let record: AuditRecord =
+            serde_json::from_str(&line?).map_err(|_| invalid("malformed record"))?;
+        if record.entry.prev_hash != prev_hash {
+            return Err(invalid("broken chain"));
+        }
+        if record.hash != entry_hash(key, &record.entry)? {
+            return Err(invalid("hash mismatch"));
+        }
+        prev_hash = record.hash;
+    }
+    Ok(prev_hash)
+}
+
+fn hmac_key(audit_key: &[u8]) -> io::Result<[u8; 32]> {
+    if audit_key.is_empty() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "Empty audit log key",
+        ));
+    }
+    Ok(Sha256::new()
+        .chain_update(AUDIT_KEY_CONTEXT)
+        .chain_update(audit_key)
+        .finalize()
+        .into())
+}
+
+fn entry_hash(key: &[u8; 32], entry: &AuditEntry) -> io::Result<String> {
+    let mut mac = HmacSha256::new_from_slice(key)
+        .map_err(|_| io::Error::new(ErrorKind::InvalidInput, "Invalid audit log key"))?;
+    mac.update(serde_json::to_string(entry)?.as_bytes());
+    Ok(format!("{:x}", mac.finalize().into_bytes()))
+}
+
+fn genesis_hash() -> String {
+    "0".repeat(64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, path::PathBuf};
+    use telio_crypto::SecretKey;
+
+    fn log_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!(
+            "telio-audit-{}-{}.jsonl",
+            name,
+            rand::random::<u64>()
+        ));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn verify_appended_records() {
+        let path = log_path("append");
+        let audit_key = rand::random::<[u8; 32]>();
+        let pk = SecretKey::gen().public();
+
+        let mut log = AuditLog::open(&path, &audit_key).unwrap();
+        log.record(AuditEvent::PeerAdded, pk).unwrap();
+        log.record(AuditEvent::ExitNodeConnected, pk).unwrap();
+        drop(log);
+
+        // Chain continues after reopening
+        let mut log = AuditLog::open(&path, &audit_key).unwrap();
+        log.record(AuditEvent::PeerRemoved, pk).unwrap();
+        drop(log);
+
+        verify(&path, &audit_key).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 3);
+        assert!(verify(&path, b"other key").is_err());
+        assert_eq!(
+            AuditLog::open(&path, &[]).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn detect_tampering() {
+        let path = log_path("tamper");
+        let audit_key = rand::random::<[u8; 32]>();
+
+        let mut log = AuditLog::open(&path, &audit_key).unwrap();
+        for event in [
+            AuditEvent::PeerAdded,
+            AuditEvent::KeyRotated,
+            AuditEvent::PeerRemoved,
+        ] {
+            log.record(event, SecretKey::gen().public()).unwrap();
+        }
+        drop(log);
+
+        let lines: Vec<String> = fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect();
+
+        // Removed line
+        fs::write(&path, format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+        assert_eq!(
+            verify(&path, &audit_key).unwrap_err().kind(),
+            ErrorKind::InvalidData
+        );
+
+        // Modified event
+        let modified = lines[1].replace("key_rotated", "peer_added");
+        fs::write(&path, format!("{}\n{}\n{}\n", lines[0], modified, lines[2])).unwrap();
+        assert!(verify(&path, &audit_key).is_err());
+        assert!(AuditLog::open(&path, &audit_key).is_err());
+
+        let _ = fs::remove_file(&path);
+    }
+}
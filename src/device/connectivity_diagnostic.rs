@@ -0,0 +1,83 @@
+//! Multi-stage connectivity diagnostic of a meshnet peer, only built with `diagnostics` feature
+
+use std::{fmt::Display, time::Duration};
+
+use serde::Serialize;
+
+/// Stage of the connectivity diagnostic
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectivityTest {
+    /// Hostname of the peer resolves through magic DNS
+    Dns,
+    /// Relay server is connected and the peer is reachable through it
+    Relay,
+    /// STUN servers respond, so endpoints for the direct path can be discovered
+    Stun,
+    /// Peer answers ICMP echo request sent through the tunnel
+    Ping,
+}
+
+/// Outcome of a single stage of the connectivity diagnostic
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ConnectivityTestResult {
+    /// Stage of the diagnostic
+    pub test: ConnectivityTest,
+    /// Whether the stage succeeded
+    pub passed: bool,
+    /// Time the stage took or the measured round trip time, if known
+    pub latency_ms: Option<u64>,
+    /// Reason of the failure
+    pub error: Option<String>,
+}
+
+impl ConnectivityTestResult {
+    /// Successful stage
+    pub fn passed(test: ConnectivityTest, latency: Option<Duration>) -> Self {
+        Self {
+            test,
+            passed: true,
+            latency_ms: latency.map(|latency| latency.as_millis() as u64),
+            error: None,
+        }
+    }
+
+    /// Failed stage
+    pub fn failed(test: ConnectivityTest, error: impl Display) -> Self {
+        Self {
+            test,
+            passed: false,
+            latency_ms: None,
+            error: Some(error.to_string()),
+        }
+    }
+}
+
+/// Result of the connectivity diagnostic, stages are in the order they were run
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct ConnectivityReport {
+    /// Outcomes of the stages
+    pub tests: Vec<ConnectivityTestResult>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serialize_report() {
+        let report = ConnectivityReport {
+            tests: vec![
+                ConnectivityTestResult::passed(
+                    ConnectivityTest::Relay,
+                    Some(Duration::from_millis(12)),
+                ),
+                ConnectivityTestResult::failed(ConnectivityTest::Stun, "No STUN server responded"),
+            ],
+        };
+        assert_eq!(
+            serde_json::to_string(&report).unwrap(),
+            r#"{"tests":[{"test":"relay","passed":true,"latency_ms":12,"error":null},{"test":"stun","passed":false,"latency_ms":null,"error":"No STUN server responded"}]}"#
+        );
+    }
+}
@@ -0,0 +1,129 @@
+//! Bounded history of connection events reported to the integrators
+
+use std::{
+    collections::VecDeque,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use serde::Serialize;
+use telio_model::event::Event;
+
+/// Number of events kept if `event_history_size` feature is not set
+pub const DEFAULT_EVENT_HISTORY_SIZE: usize = 256;
+
+/// Event together with the time it was reported
+#[derive(Clone, Debug, Serialize)]
+pub struct HistoryEvent {
+    /// Unix timestamp of the event, in seconds
+    pub ts_secs: u64,
+    /// Reported event, serialized in the same form as passed to the event callback
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+/// Keeps the latest peer, relay and key rotation events, oldest are dropped first
+#[derive(Debug)]
+pub struct EventHistory {
+    events: VecDeque<HistoryEvent>,
+    size: usize,
+}
+
+impl EventHistory {
+    /// Create history of at most `size` events, zero disables the history
+    pub fn new(size: usize) -> Self {
+        Self {
+            events: VecDeque::with_capacity(size),
+            size,
+        }
+    }
+
+    /// Record the event if it is related to connections
+    pub fn record(&mut self, event: &Event) {
+        let ts_secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.record_at(event, ts_secs);
+    }
+
+    fn record_at(&mut self, event: &Event, ts_secs: u64) {
+        if self.size == 0
+            || !matches!(
+                event,
+                Event::Node { .. } | Event::Relay { .. } | Event::KeyRotated { .. }
+            )
+        {
+            return;
+        }
+        if self.events.len() >= self.size {
+            self.events.pop_front();
+        }
+        self.events.push_back(HistoryEvent {
+            ts_secs,
+            event: event.clone(),
+        });
+    }
+
+    /// Approximate number of heap bytes used by the kept events
+    #[cfg(feature = "memory_diagnostics")]
+    pub fn memory_usage(&self) -> usize {
+        self.events.capacity() * std::mem::size_of::<HistoryEvent>()
+    }
+
+    /// Events which occurred after `since_secs` Unix timestamp, oldest first
+    pub fn since(&self, since_secs: u64) -> Vec<HistoryEvent> {
+        self.events
+            .iter()
+            .filter(|e| e.ts_secs > since_secs)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use telio_model::event::{KeyRotated, Set, ShuttingDown};
+
+    fn key_rotated(old: &str) -> Event {
+        Event::new::<KeyRotated>().set(KeyRotated {
+            old_public_key: old.to_owned(),
+            new_public_key: String::new(),
+        })
+    }
+
+    #[test]
+    fn keep_latest_connection_events() {
+        let mut history = EventHistory::new(2);
+        history.record_at(&key_rotated("a"), 10);
+        history.record_at(&Event::new::<ShuttingDown>(), 11);
+        history.record_at(&key_rotated("b"), 12);
+        history.record_at(&key_rotated("c"), 13);
+
+        let events = history.since(0);
+        assert_eq!(
+            events.iter().map(|e| e.ts_secs).collect::<Vec<_>>(),
+            vec![12, 13]
+        );
+        assert_eq!(history.since(12).len(), 1);
+        assert!(history.since(13).is_empty());
+    }
+
+    #[test]
+    fn serialize_with_timestamp() {
+        let mut history = EventHistory::new(DEFAULT_EVENT_HISTORY_SIZE);
+        history.record_at(&key_rotated("a"), 10);
+
+        assert_eq!(
+            serde_json::to_string(&history.since(0)).unwrap(),
+            r#"[{"ts_secs":10,"type":"keyrotated","body":{"old_public_key":"a","new_public_key":""}}]"#
+        );
+    }
+
+    #[test]
+    fn zero_size_disables_history() {
+        let mut history = EventHistory::new(0);
+        history.record_at(&key_rotated("a"), 10);
+        assert!(history.since(0).is_empty());
+    }
+}
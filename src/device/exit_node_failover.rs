@@ -0,0 +1,99 @@
+//! Automatic switching between exit nodes listed in priority order
+
+use std::time::{Duration, Instant};
+
+use telio_model::config::FailoverExitNode;
+
+/// Exit nodes used by the failover and the one currently connected
+#[derive(Debug)]
+pub struct FailoverList {
+    nodes: Vec<FailoverExitNode>,
+    timeout: Duration,
+    current: usize,
+    connected_at: Instant,
+}
+
+impl FailoverList {
+    /// Failover starting from the node at `current` index, connected at `now`
+    pub fn new(
+        nodes: Vec<FailoverExitNode>,
+        timeout: Duration,
+        current: usize,
+        now: Instant,
+    ) -> Self {
+        Self {
+            nodes,
+            timeout,
+            current,
+            connected_at: now,
+        }
+    }
+
+    /// Exit node which should be connected
+    pub fn current(&self) -> Option<&FailoverExitNode> {
+        self.nodes.get(self.current)
+    }
+
+    /// Move to the next node if nothing was received from the current one for longer than the
+    /// timeout, after the last node the list starts over from the primary one
+    pub fn next_if_unreachable(
+        &mut self,
+        since_last_rx: Option<Duration>,
+        now: Instant,
+    ) -> Option<&FailoverExitNode> {
+        let connected_for = now.saturating_duration_since(self.connected_at);
+        let unreachable = connected_for > self.timeout
+            && since_last_rx.map_or(true, |since| since > self.timeout);
+        if !unreachable || self.nodes.len() < 2 {
+            return None;
+        }
+
+        self.current = (self.current + 1) % self.nodes.len();
+        self.connected_at = now;
+        self.current()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use telio_crypto::PublicKey;
+
+    #[test]
+    fn switches_to_next_node_after_timeout() {
+        let node = |key: u8| FailoverExitNode {
+            public_key: PublicKey([key; 32]),
+            endpoint: None,
+        };
+        let start = Instant::now();
+        let timeout = Duration::from_secs(30);
+        let mut failover = FailoverList::new(vec![node(1), node(2)], timeout, 0, start);
+
+        // Nothing received yet, but the node is still within the timeout
+        assert_eq!(
+            failover.next_if_unreachable(None, start + Duration::from_secs(10)),
+            None
+        );
+        assert_eq!(
+            failover.next_if_unreachable(Some(Duration::from_secs(5)), start + timeout * 2),
+            None
+        );
+
+        let now = start + timeout * 3;
+        assert_eq!(
+            failover.next_if_unreachable(Some(timeout * 2), now),
+            Some(&node(2))
+        );
+        assert_eq!(failover.current(), Some(&node(2)));
+
+        // Stale rx of the previous connection is ignored within the timeout after switching
+        assert_eq!(
+            failover.next_if_unreachable(Some(timeout * 4), now + timeout),
+            None
+        );
+        assert_eq!(
+            failover.next_if_unreachable(None, now + timeout * 2),
+            Some(&node(1))
+        );
+    }
+}
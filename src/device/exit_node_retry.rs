@@ -0,0 +1,134 @@
+//! Reconnecting an exit node until a handshake with it succeeds
+
+use std::time::{Duration, Instant};
+
+use serde::Deserialize;
+
+/// Longest time waited for the handshake, whatever the policy
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(60 * 60);
+
+/// How many times and how often the exit node is connected again
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct ExitNodeRetryPolicy {
+    /// Number of connection attempts, including the first one
+    pub max_attempts: u32,
+    /// Time to wait for the handshake after the first attempt, in milliseconds
+    pub initial_delay_ms: u64,
+    /// Multiplier of the delay after every attempt
+    pub backoff_factor: f64,
+}
+
+impl ExitNodeRetryPolicy {
+    /// Policy makes at least one attempt and does not shrink the delay
+    pub fn is_valid(&self) -> bool {
+        self.max_attempts > 0 && self.backoff_factor.is_finite() && self.backoff_factor >= 1.0
+    }
+}
+
+impl Default for ExitNodeRetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_delay_ms: 1000,
+            backoff_factor: 2.0,
+        }
+    }
+}
+
+/// Attempts made so far and the time the current one gives up
+#[derive(Debug)]
+pub struct ExitNodeRetry {
+    policy: ExitNodeRetryPolicy,
+    attempts: u32,
+    deadline: Instant,
+}
+
+impl ExitNodeRetry {
+    /// Retry with the first attempt made at `now`
+    pub fn new(policy: ExitNodeRetryPolicy, now: Instant) -> Self {
+        let deadline = now + delay(&policy, 1);
+        Self {
+            policy,
+            attempts: 1,
+            deadline,
+        }
+    }
+
+    /// Number of attempts made so far
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// Time the current attempt gives up
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+
+    /// Start the next attempt at `now`, `false` if all the attempts were made
+    pub fn next_attempt(&mut self, now: Instant) -> bool {
+        if self.attempts >= self.policy.max_attempts {
+            return false;
+        }
+        self.attempts += 1;
+        self.deadline = now + delay(&self.policy, self.attempts);
+        true
+    }
+}
+
+/// Time to wait for the handshake after `attempt`, counted from one
+fn delay(policy: &ExitNodeRetryPolicy, attempt: u32) -> Duration {
+    let factor = policy
+        .backoff_factor
+        .powi(attempt.saturating_sub(1).min(i32::MAX as u32) as i32);
+    Duration::try_from_secs_f64(policy.initial_delay_ms as f64 * factor / 1000.0)
+        .map_or(MAX_RETRY_DELAY, |delay| delay.min(MAX_RETRY_DELAY))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delay_grows_until_attempts_are_exhausted() {
+        let policy = ExitNodeRetryPolicy {
+            max_attempts: 3,
+            initial_delay_ms: 500,
+            backoff_factor: 2.0,
+        };
+        let start = Instant::now();
+        let mut retry = ExitNodeRetry::new(policy, start);
+        assert_eq!(retry.attempts(), 1);
+        assert_eq!(retry.deadline(), start + Duration::from_millis(500));
+
+        let now = retry.deadline();
+        assert!(retry.next_attempt(now));
+        assert_eq!(retry.attempts(), 2);
+        assert_eq!(retry.deadline(), now + Duration::from_secs(1));
+
+        let now = retry.deadline();
+        assert!(retry.next_attempt(now));
+        assert_eq!(retry.deadline(), now + Duration::from_secs(2));
+
+        assert!(!retry.next_attempt(retry.deadline()));
+        assert_eq!(retry.attempts(), 3);
+    }
+
+    #[test]
+    fn missing_policy_fields_are_defaults() {
+        let policy: ExitNodeRetryPolicy = serde_json::from_str(r#"{"max_attempts":2}"#).unwrap();
+        assert_eq!(
+            policy,
+            ExitNodeRetryPolicy {
+                max_attempts: 2,
+                ..Default::default()
+            }
+        );
+        assert!(policy.is_valid());
+        assert!(!ExitNodeRetryPolicy {
+            backoff_factor: 0.5,
+            ..Default::default()
+        }
+        .is_valid());
+    }
+}
@@ -0,0 +1,79 @@
+//! Approximate memory usage of long-lived structures, only built with `memory_diagnostics` feature
+//!
+//! Sizes are estimated from lengths and capacities of the collections kept by each subsystem,
+//! heap data owned by the elements themselves (e.g. strings) is not followed.
+
+use std::mem::size_of;
+
+use ipnetwork::IpNetwork;
+use serde::Serialize;
+use telio_crypto::PublicKey;
+use telio_model::config::{Config, Peer};
+use telio_wg::uapi::{self, Interface};
+
+/// Memory used by the device, in bytes
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct MemoryStats {
+    /// WireGuard peers and meshnet peers from the config
+    pub peer_map_bytes: usize,
+    /// History of events reported to the integrators
+    pub log_buffer_bytes: usize,
+    /// Relay servers, cached peer states and latency samples
+    pub relay_state_bytes: usize,
+    /// Sum of all the above
+    pub total_bytes: usize,
+}
+
+impl MemoryStats {
+    pub fn new(peer_map_bytes: usize, log_buffer_bytes: usize, relay_state_bytes: usize) -> Self {
+        Self {
+            peer_map_bytes,
+            log_buffer_bytes,
+            relay_state_bytes,
+            total_bytes: peer_map_bytes + log_buffer_bytes + relay_state_bytes,
+        }
+    }
+}
+
+/// Estimate memory used by the peers of the WireGuard interface and of the meshnet config
+pub fn peer_map_usage(interface: &Interface, meshnet_config: Option<&Config>) -> usize {
+    let wg_peers: usize = interface
+        .peers
+        .values()
+        .map(|peer| {
+            size_of::<PublicKey>()
+                + size_of::<uapi::Peer>()
+                + peer.allowed_ips.capacity() * size_of::<IpNetwork>()
+        })
+        .sum();
+    let meshnet_peers = meshnet_config
+        .and_then(|config| config.peers.as_ref())
+        .map_or(0, |peers| peers.capacity() * size_of::<Peer>());
+    wg_peers + meshnet_peers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn count_peers_of_interface() {
+        let mut interface = Interface::default();
+        assert_eq!(peer_map_usage(&interface, None), 0);
+
+        let public_key = PublicKey([1; 32]);
+        interface.peers.insert(
+            public_key,
+            uapi::Peer {
+                public_key,
+                allowed_ips: vec!["100.64.0.1/32".parse().unwrap()],
+                ..Default::default()
+            },
+        );
+        let usage = peer_map_usage(&interface, None);
+        assert!(usage >= size_of::<uapi::Peer>() + size_of::<IpNetwork>());
+
+        let stats = MemoryStats::new(usage, 10, 20);
+        assert_eq!(stats.total_bytes, usage + 30);
+    }
+}
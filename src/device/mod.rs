@@ -1,14 +1,41 @@
+#[cfg(feature = "audit_log")]
+mod audit_log;
+#[cfg(feature = "diagnostics")]
+mod connectivity_diagnostic;
+mod event_history;
+mod exit_node_failover;
+mod exit_node_retry;
+#[cfg(feature = "memory_diagnostics")]
+mod memory_stats;
+#[cfg(feature = "packet_stats")]
+mod packet_stats;
+#[cfg(feature = "diagnostics")]
+mod path_quality;
+mod platform_info;
+mod psk_rotation;
+#[cfg(feature = "diagnostics")]
+mod traceroute;
+#[cfg(feature = "traffic_logging")]
+mod traffic_log;
 mod wg_controller;
 
 use async_trait::async_trait;
-use telio_crypto::{PublicKey, SecretKey};
-use telio_firewall::firewall::{Firewall, StatefullFirewall};
+use sha2::{Digest, Sha256};
+use telio_crypto::{encryption, signature, token, PresharedKey, PublicKey, SecretKey};
+use telio_firewall::{
+    firewall::{ActiveConnection, Firewall, StatefullFirewall},
+    shaper::TrafficShaper,
+};
 use telio_lana::init_lana;
 use telio_nat_detect::nat_detection::{retrieve_single_nat, NatData};
+use telio_proto::{
+    ApplicationMsg, AuthChallengeMsg, BroadcastMsg, CapabilityMsg, CodecError, PskRotateMsg,
+    WakeMsg, AUTH_NONCE_SIZE,
+};
 use telio_proxy::{Config as ProxyConfig, Io as ProxyIo, Proxy, UdpProxy};
 use telio_relay::{
     derp::Config as DerpConfig, multiplexer::Multiplexer, DerpKeepaliveConfig, DerpRelay,
-    SortedServers,
+    RelayServerStatus, RttSample, SortedServers,
 };
 use telio_sockets::{NativeProtector, Protect, SocketPool};
 use telio_task::{
@@ -20,7 +47,7 @@ use telio_traversal::{
     cross_ping_check::{CrossPingCheck, CrossPingCheckTrait, Io as CpcIo},
     endpoint_providers::{
         self, local::LocalInterfacesEndpointProvider, stun::StunEndpointProvider, stun::StunServer,
-        upnp::UpnpEndpointProvider, EndpointProvider,
+        upnp::UpnpEndpointProvider, EndpointProvider, EndpointProviderType,
     },
     last_handshake_time_provider::{LastHandshakeTimeProvider, WireGuardLastHandshakeTimeProvider},
     ping_pong_handler::PingPongHandler,
@@ -30,6 +57,13 @@ use telio_traversal::{
 #[cfg(any(target_os = "macos", target_os = "ios", target_os = "tvos"))]
 use telio_sockets::native;
 
+#[cfg(target_os = "android")]
+use telio_sockets::{BindNetwork, NetworkConstraints};
+
+#[cfg(feature = "diagnostics")]
+pub use connectivity_diagnostic::{ConnectivityReport, ConnectivityTest, ConnectivityTestResult};
+#[cfg(feature = "diagnostics")]
+pub use path_quality::PathQuality;
 use telio_nurse::{
     config::Config as NurseConfig, data::MeshConfigUpdateEvent,
     MeshnetEntities as NurseMeshnetEntities, Nurse, NurseIo,
@@ -41,8 +75,34 @@ use tokio::{
     sync::Mutex,
     time::{interval_at, Interval},
 };
-
-use telio_dns::{DnsResolver, LocalDnsResolver, Records};
+#[cfg(feature = "diagnostics")]
+pub use traceroute::{TracerouteHop, TracerouteReport};
+
+#[cfg(feature = "audit_log")]
+pub use audit_log::verify as verify_audit_log;
+#[cfg(feature = "audit_log")]
+use audit_log::{AuditEvent, AuditLog};
+pub use event_history::HistoryEvent;
+use event_history::{EventHistory, DEFAULT_EVENT_HISTORY_SIZE};
+use exit_node_failover::FailoverList;
+use exit_node_retry::ExitNodeRetry;
+pub use exit_node_retry::ExitNodeRetryPolicy;
+#[cfg(feature = "memory_diagnostics")]
+pub use memory_stats::MemoryStats;
+#[cfg(feature = "packet_stats")]
+use packet_stats::PacketStatsHook;
+#[cfg(feature = "packet_stats")]
+pub use packet_stats::{PacketDirection, PacketStatsHandler};
+pub use platform_info::PlatformInfo;
+use psk_rotation::{KeyChange, PskRotation};
+#[cfg(feature = "packet_trace")]
+pub use telio_relay::PacketTraceEntry;
+#[cfg(feature = "traffic_logging")]
+use traffic_log::{TrafficDirection, TrafficLog};
+#[cfg(feature = "traffic_logging")]
+pub use traffic_log::{TrafficLogConfig, TrafficLogEntry};
+
+use telio_dns::{DnsQueryResponse, DnsResolver, DohServer, LocalDnsResolver, Records};
 
 use telio_dns::bind_tun;
 use wg::{
@@ -50,9 +110,10 @@ use wg::{
     NoLinkDetection,
 };
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::{
     collections::{hash_map::Entry, HashSet},
+    fmt::Write as _,
     future::Future,
     io::{self, Error as IoError, ErrorKind},
     net::{IpAddr, Ipv4Addr, SocketAddr},
@@ -61,6 +122,7 @@ use std::{
 };
 
 use cfg_if::cfg_if;
+use uuid::Uuid;
 
 use telio_utils::{
     commit_sha,
@@ -72,17 +134,31 @@ use telio_utils::{
 
 use telio_model::{
     api_config::{
-        FeaturePersistentKeepalive, Features, PathType, DEFAULT_ENDPOINT_POLL_INTERVAL_SECS,
+        ConfigExpiryAction, FeaturePersistentKeepalive, Features, IpConflictPolicy, PathType,
+        DEFAULT_ENDPOINT_POLL_INTERVAL_SECS,
+    },
+    config::{
+        Config, DnsFallbackConfig, DnsTtlOverride, FailoverExitNode, KeepalivePolicy,
+        NetworkInterface, Peer, PeerBase, ProxySettings, RelaySelectionPolicy, RelayState,
+        Server as DerpServer, StunServerStatus,
+    },
+    event::{
+        AuthChallengeResult, ClockSkewWarning, DuplicateIpDetected, Event, ExitNodeConnected,
+        ExitNodeConnectionFailed, ExitNodeFailover, IpReassignmentRequested, KeyRotated,
+        PeerWakeRequest, PskRotated, Set, ShuttingDown, StaleConfig,
+    },
+    health::{ComponentHealth, HealthComponents, HealthReport},
+    mesh::{
+        get_ip_stack, ExitNode, IpStack, LinkState, ManagedRoute, MeshTopology, Node, NodeState,
+        PeerCapabilities, PeerEndpoint, PeerEndpointType, PeerGroup, PeerRoute, RouteSource,
+        TopologyEdge, TopologyNode,
     },
-    config::{Config, Peer, PeerBase, Server as DerpServer},
-    event::{Event, Set},
-    mesh::{ExitNode, LinkState, Node},
     validation::validate_nickname,
 };
 
 pub use wg::{
-    uapi::Event as WGEvent, uapi::Interface, AdapterType, DynamicWg, Error as AdapterError,
-    FirewallCb, Tun, WireGuard,
+    uapi::Event as WGEvent, uapi::Interface, Adapter, AdapterErrorBuffer, AdapterFactory,
+    AdapterType, DynamicWg, Error as AdapterError, FirewallCb, Tun, WireGuard,
 };
 
 #[cfg(test)]
@@ -120,6 +196,10 @@ pub enum Error {
     DnsResolverError(String),
     #[error("DNS module should be disabled when executing this operation")]
     DnsNotDisabled,
+    #[error("DNS module should be enabled when executing this operation")]
+    DnsNotEnabled,
+    #[error("DNS query timed out")]
+    DnsQueryTimeout,
     #[error("Failed to reconnect to DERP server")]
     FailedToReconnect,
     #[error("Failed to recover information about NAT")]
@@ -153,13 +233,114 @@ pub enum Error {
     PostQuantum(#[from] telio_wg::pq::Error),
     #[error("Cannot setup meshnet when the post quantum VPN is set up")]
     MeshnetUnavailableWithPQ,
+    #[error("Meshnet config is older than allowed by the config expiry policy")]
+    StaleConfig,
+    #[error("Meshnet config has {count} peers, at most {max} are allowed")]
+    TooManyPeers { count: usize, max: u32 },
+    #[error("Exit node failover list is empty")]
+    EmptyFailoverList,
+    #[error("Exit node retry policy must make an attempt and must not shrink the delay")]
+    InvalidRetryPolicy,
+    #[error("Meshnet peer {public_key:?} is assigned {ip} which belongs to another node")]
+    DuplicateIp { public_key: PublicKey, ip: IpAddr },
+    #[error("Async runtime is unresponsive")]
+    RuntimeUnresponsive,
+    #[error("Failed to encode message: {0}")]
+    CodecError(#[from] CodecError),
+    #[error("Failed to send message to the relay")]
+    RelaySendFailed,
+    #[error("Failed to serialize: {0}")]
+    SerializationError(#[from] serde_json::Error),
+    #[error(transparent)]
+    NodeError(#[from] telio_model::mesh::Error),
+    #[error("Invalid peer group: {0}")]
+    InvalidPeerGroup(String),
+    #[error("Deleting non-existent peer group")]
+    PeerGroupNotFound,
+    #[error("At least one relay connection must be allowed")]
+    InvalidMaxRelayConnections,
+    #[error("Minimum DNS TTL must not exceed the maximum")]
+    InvalidDnsTtlOverride,
+    #[error("Invalid DNS over HTTPS url: {0}")]
+    InvalidDnsOverHttpsUrl(String),
+    #[error("Keepalive interval must not exceed 65535 seconds")]
+    InvalidKeepalivePolicy,
+    #[error("Traffic shaping is not enabled for the peer")]
+    TrafficShapingNotEnabled,
+    #[error("Traffic shaping is supported only by the boringtun adapter")]
+    TrafficShapingUnsupported,
+    #[error("No peer route matches the IP address")]
+    NoPeerRoute,
+    #[error("Device was created without a network binding callback")]
+    NetworkBindingUnavailable,
+    #[error("Invalid nickname")]
+    InvalidNickname,
+    #[error("Payload encryption error: {0}")]
+    PayloadEncryption(#[from] telio_crypto::encryption::Error),
+    #[cfg(feature = "audit_log")]
+    #[error("Audit log error: {0}")]
+    AuditLog(std::io::Error),
+    #[cfg(feature = "diagnostics")]
+    #[error("Traceroute failed: {0}")]
+    TracerouteFailed(std::io::Error),
+    #[cfg(feature = "diagnostics")]
+    #[error("Path quality measurement failed: {0}")]
+    PathQualityFailed(std::io::Error),
 }
 
 pub type Result<T = ()> = std::result::Result<T, Error>;
 
+/// Time given to each component to respond to the health check
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_millis(500);
+/// Time given to a DNS query proxied through magic DNS, unless configured by features
+const DEFAULT_PROXY_DNS_QUERY_TIMEOUT: Duration = Duration::from_secs(5);
+/// Silence after which a connected peer is reported as stale, unless configured by features.
+/// Longer than the default keepalive periods, so idle peers are not reported.
+const DEFAULT_STALE_PEER_THRESHOLD: Duration = Duration::from_secs(60);
+
+/// Exit node failover timeout if `exit_node_failover_timeout_secs` feature is not set
+const DEFAULT_EXIT_NODE_FAILOVER_TIMEOUT: Duration = Duration::from_secs(30);
+/// Time given to the event callback to receive `ShuttingDown` if
+/// `flush_events_on_stop_timeout_seconds` feature does not bound it
+const DEFAULT_SHUTTING_DOWN_DELIVERY_TIMEOUT: Duration = Duration::from_secs(5);
+/// Time given to the peer to answer the ping of the connectivity diagnostic
+#[cfg(feature = "diagnostics")]
+const CONNECTIVITY_PING_TIMEOUT: Duration = Duration::from_secs(1);
+/// Clock skew to the relay server above which `ClockSkewWarning` is reported, WireGuard rejects
+/// handshakes from clocks differing by more than 3 minutes
+const CLOCK_SKEW_WARNING_THRESHOLD: Duration = Duration::from_secs(90);
+/// Prefix of the data signed in response to the identity challenge, so that peers cannot use
+/// the challenge to get signatures of arbitrary data
+const AUTH_CHALLENGE_CONTEXT: &[u8] = b"telio-auth-challenge";
+/// Prefix of the data the application signs with the device key, so that its signatures cannot
+/// be passed off as those of identity challenges or tokens, which use prefixes of their own
+const SIGN_DATA_CONTEXT: &[u8] = b"telio-sign-data";
+/// Label of the key encrypting application payloads, so that the application cannot decrypt or
+/// forge messages of the meshnet protocols encrypted with the device keys
+const PAYLOAD_ENCRYPTION_CONTEXT: &[u8] = b"telio-app-payload";
+/// Lowest MTU accepted in peer group policy, minimum required by IPv4
+const MIN_PEER_GROUP_MTU: u16 = 576;
+/// Prefix of the exit node identifier used when routing through the peer group exit node
+const PEER_GROUP_EXIT_PREFIX: &str = "peer-group:";
+/// Namespace of the UUIDs identifying devices, see `device_id`
+const DEVICE_ID_NAMESPACE: Uuid = Uuid::from_u128(0x5c9b_2a41_8e6f_4d3a_b7c0_1f2e_9d84_6a37);
+/// Meshnet config hash reported when there is no meshnet config applied
+pub const EMPTY_MESHNET_CONFIG_HASH: &str =
+    "0000000000000000000000000000000000000000000000000000000000000000";
+
 pub trait EventCb: Fn(Box<Event>) + Send + 'static {}
 impl<T> EventCb for T where T: Fn(Box<Event>) + Send + 'static {}
 
+/// Work run by the task passing events to the event callback, in between the events
+pub type EventTaskJob = Box<dyn FnOnce() + Send + 'static>;
+
+/// Receiver of application messages sent by meshnet peers
+pub type CustomMessageHandler = Arc<dyn Fn(PublicKey, Vec<u8>) + Send + Sync + 'static>;
+
+/// Source of an identifier of the current network interfaces state, e.g. a hash of the interface
+/// addresses and the default route
+pub type LinkStateProvider = Arc<dyn Fn() -> u64 + Send + Sync + 'static>;
+
 #[derive(Clone, Default)]
 pub struct DeviceConfig {
     pub private_key: SecretKey,
@@ -167,6 +348,10 @@ pub struct DeviceConfig {
     pub fwmark: Option<u32>,
     pub name: Option<String>,
     pub tun: Option<Tun>,
+    // UDP port of the WireGuard socket, picked by the adapter if None
+    pub listen_port: Option<u16>,
+    // Adapter implemented by the application, used instead of `adapter` if set
+    pub custom_adapter: Option<AdapterFactory>,
 }
 
 pub struct Device {
@@ -174,7 +359,18 @@ pub struct Device {
     event: Tx<Box<Event>>,
     rt: Option<Task<Runtime>>,
     protect: Option<Protect>,
+    bind_network: Option<BindNetwork>,
     features: Features,
+    custom_message_handler: Arc<parking_lot::RwLock<Option<CustomMessageHandler>>>,
+    link_state_provider: Arc<parking_lot::RwLock<Option<LinkStateProvider>>>,
+    listen_port: Option<u16>,
+    event_history: Arc<parking_lot::Mutex<EventHistory>>,
+    thread_tracker: Arc<parking_lot::Mutex<ThreadTracker>>,
+    adapter_error: AdapterErrorBuffer,
+    /// Notified once `ShuttingDown` was passed to the event callback
+    shutting_down_delivered: Option<std::sync::mpsc::Receiver<()>>,
+    /// Jobs run by the task passing events to the event callback
+    event_task_jobs: tokio::sync::mpsc::UnboundedSender<EventTaskJob>,
 }
 
 #[derive(Default)]
@@ -182,7 +378,8 @@ pub struct RequestedState {
     // WireGuard interface configuration
     pub device_config: DeviceConfig,
 
-    // A configuration as requested by libtelio.set_config(...) call, no modifications
+    // A configuration as requested by libtelio.set_config(...) call, no modifications except
+    // peers left out by the ip conflict policy
     pub meshnet_config: Option<Config>,
 
     // An old meshnet configuration
@@ -200,6 +397,18 @@ pub struct RequestedState {
     // case of disconnecting from non-vpn exit peer
     pub upstream_servers: Option<Vec<IpAddr>>,
 
+    // Local DNS resolver fallback, passed by libtelio.set_dns_fallback_servers(...),
+    // kept across disabling and enabling magic DNS
+    pub dns_fallback: Option<DnsFallbackConfig>,
+
+    // Range of TTLs returned by local DNS resolver, passed by libtelio.set_dns_ttl_override(...),
+    // kept across disabling and enabling magic DNS
+    pub dns_ttl_override: DnsTtlOverride,
+
+    // DNS over HTTPS server replacing upstream servers of local DNS resolver, passed by
+    // libtelio.enable_dns_over_https(...), kept across disabling and enabling magic DNS
+    pub dns_over_https: Option<DohServer>,
+
     // Wireguard stun server that should be currently used
     pub wg_stun_server: Option<StunServer>,
 
@@ -207,6 +416,41 @@ pub struct RequestedState {
     pub(crate) keepalive_periods: FeaturePersistentKeepalive,
 
     pub postquantum_wg: Option<wg::pq::PqKeys>,
+
+    // Nicknames set by libtelio.set_peer_nickname(...), dropped when meshnet config is replaced
+    pub peer_nicknames: HashMap<PublicKey, String>,
+
+    // Direct path was disabled by libtelio.disable_direct_path(), all traffic goes through relay
+    pub direct_path_disabled: bool,
+
+    // Hash of the meshnet config which was last applied successfully by libtelio.set_config(...)
+    pub meshnet_config_hash: Option<String>,
+
+    // Unix timestamp of the last successful libtelio.set_config(...) completion, in seconds
+    pub config_applied_at: Option<u64>,
+
+    // Peer groups created by libtelio.create_peer_group(...), applied on top of meshnet config
+    pub peer_groups: BTreeMap<String, PeerGroup>,
+
+    // Limit of peers relayed at the same time, set by libtelio.set_max_relay_connections(...)
+    pub max_relay_connections: Option<usize>,
+
+    // Relay servers allowed by libtelio.set_relay_server_selection_policy(...)
+    pub relay_selection_policy: RelaySelectionPolicy,
+
+    // Keepalive intervals of meshnet peers set by libtelio.set_keepalive_policy(...)
+    pub keepalive_policy: KeepalivePolicy,
+
+    // Proxy for the connections to relay and DNS over HTTPS servers set by
+    // libtelio.set_proxy_settings(...)
+    pub proxy_settings: Option<ProxySettings>,
+
+    // Pre-shared keys negotiated with meshnet peers, if psk_rotation_interval_secs feature is set
+    pub meshnet_psks: HashMap<PublicKey, PresharedKey>,
+
+    // Handling of duplicate IP addresses in meshnet config, from ip_conflict_resolution feature
+    // or libtelio.set_ip_conflict_resolution_policy(...)
+    pub ip_conflict_policy: Option<IpConflictPolicy>,
 }
 
 pub struct MeshnetEntites {
@@ -219,6 +463,24 @@ pub struct MeshnetEntites {
     // UDP proxy for supporting relayed WireGuard connections
     proxy: Arc<UdpProxy>,
 
+    // Application messages exchanged with peers over the relay
+    application: Chan<(PublicKey, ApplicationMsg)>,
+
+    // Application messages broadcast to all connected peers over the relay
+    broadcast: Chan<(PublicKey, BroadcastMsg)>,
+
+    // Pre-shared key negotiation with peers over the relay
+    psk_rotate: Chan<(PublicKey, PskRotateMsg)>,
+
+    // Wake requests exchanged with sleeping peers over the relay
+    wake: Chan<(PublicKey, WakeMsg)>,
+
+    // Capabilities advertised to and by peers over the relay
+    capability: Chan<(PublicKey, CapabilityMsg)>,
+
+    // Identity challenges exchanged with peers over the relay
+    auth_challenge: Chan<(PublicKey, AuthChallengeMsg)>,
+
     // Entities for direct wireguard connections
     direct: Option<DirectEntities>,
 }
@@ -233,6 +495,17 @@ pub struct Entities {
     // Internal firewall
     firewall: Arc<StatefullFirewall>,
 
+    // Per peer bandwidth limits
+    shaper: Arc<TrafficShaper>,
+
+    // Sampled log of packets exchanged with peers
+    #[cfg(feature = "traffic_logging")]
+    traffic_log: Arc<TrafficLog>,
+
+    // Hook called for every packet exchanged with peers
+    #[cfg(feature = "packet_stats")]
+    packet_stats: Arc<PacketStatsHook>,
+
     // Entities for meshnet connections
     meshnet: Option<MeshnetEntites>,
 
@@ -330,6 +603,15 @@ pub struct DNS<D: DnsResolver = LocalDnsResolver> {
     virtual_host_tun_fd: Option<i32>,
 }
 
+/// Exit node connected again until a handshake with it succeeds
+struct RetriedExitNode {
+    node: ExitNode,
+    /// Exit node requested after the last attempt, retrying stops once it is changed otherwise,
+    /// e.g. by connecting another exit node
+    requested: Option<PublicKey>,
+    retry: ExitNodeRetry,
+}
+
 struct Runtime {
     features: Features,
 
@@ -367,6 +649,46 @@ struct Runtime {
     /// Some of the events are time based, so just poll the whole state from time to time
     polling_interval: Interval,
 
+    /// Receiver of application messages sent by meshnet peers, shared with the device
+    custom_message_handler: Arc<parking_lot::RwLock<Option<CustomMessageHandler>>>,
+
+    /// Provider of the network interfaces state polled for changes, shared with the device
+    link_state_provider: Arc<parking_lot::RwLock<Option<LinkStateProvider>>>,
+
+    /// State returned by the link state provider at the previous poll
+    link_state: Option<u64>,
+
+    /// Pre-shared key rotation interval, if psk_rotation_interval_secs feature is set
+    psk_rotation_interval: Option<Interval>,
+
+    /// Negotiation state of the pre-shared keys with meshnet peers
+    psk_rotation: PskRotation,
+
+    /// Capabilities advertised by meshnet peers
+    peer_capabilities: HashMap<PublicKey, PeerCapabilities>,
+
+    /// Telio versions advertised by meshnet peers along with their capabilities
+    peer_versions: HashMap<PublicKey, String>,
+
+    /// Nonces of the identity challenges sent to meshnet peers and not answered yet
+    auth_challenges: HashMap<PublicKey, [u8; AUTH_NONCE_SIZE]>,
+
+    /// Exit nodes switched automatically, set by libtelio.enable_exit_node_failover(...)
+    exit_node_failover: Option<FailoverList>,
+
+    /// Exit node reconnected until a handshake succeeds, set by
+    /// libtelio.connect_exit_node_with_retry(...)
+    exit_node_retry: Option<RetriedExitNode>,
+
+    /// Log of security relevant operations, written only while the runtime is running
+    #[cfg(feature = "audit_log")]
+    audit_log: Option<AuditLog>,
+
+    /// Bytes per packet and number of packets captured by the relay, kept across meshnet
+    /// restarts, set by libtelio.enable_packet_trace(...)
+    #[cfg(feature = "packet_trace")]
+    packet_trace: Option<(usize, usize)>,
+
     #[cfg(test)]
     /// MockedAdapter (tests)
     test_env: telio_wg::tests::Env,
@@ -379,6 +701,7 @@ impl Device {
         features: Features,
         event_cb: F,
         protect: Option<Protect>,
+        bind_network: Option<BindNetwork>,
     ) -> Result<Self> {
         let version_tag = version_tag();
         let commit_sha = commit_sha();
@@ -416,12 +739,52 @@ impl Device {
             })
             .build()?;
 
-        thread_tracker.start();
+        thread_tracker.clone().start();
+
+        let event_history = Arc::new(parking_lot::Mutex::new(EventHistory::new(
+            features
+                .event_history_size
+                .unwrap_or(DEFAULT_EVENT_HISTORY_SIZE),
+        )));
 
         let (event_tx, mut event_rx) = tokio::sync::broadcast::channel(256);
-        art.spawn(async move {
-            while let Ok(event) = event_rx.recv().await {
-                event_cb(event);
+        let (shutting_down_tx, shutting_down_rx) = std::sync::mpsc::sync_channel(1);
+        let (event_task_jobs, mut job_rx) = tokio::sync::mpsc::unbounded_channel::<EventTaskJob>();
+        art.spawn({
+            let event_history = event_history.clone();
+            let warn_threshold = features.event_queue_warn_threshold;
+            async move {
+                let mut over_threshold = false;
+                loop {
+                    let event = tokio::select! {
+                        event = event_rx.recv() => match event {
+                            Ok(event) => event,
+                            Err(_) => break,
+                        },
+                        Some(job) = job_rx.recv() => {
+                            job();
+                            continue;
+                        }
+                    };
+                    if let Some(threshold) = warn_threshold {
+                        let depth = event_rx.len();
+                        if depth > threshold && !over_threshold {
+                            telio_log_warn!(
+                                "{} events are waiting to be delivered, event callback may be blocked",
+                                depth
+                            );
+                        }
+                        over_threshold = depth > threshold;
+                    }
+                    // Nothing is passed to the callback after the instance is shutting down
+                    let last = matches!(*event, Event::ShuttingDown { .. });
+                    event_history.lock().record(&event);
+                    event_cb(event);
+                    if last {
+                        let _ = shutting_down_tx.send(());
+                        break;
+                    }
+                }
             }
         });
 
@@ -431,6 +794,15 @@ impl Device {
             event: event_tx,
             rt: None,
             protect,
+            bind_network,
+            custom_message_handler: Default::default(),
+            link_state_provider: Default::default(),
+            listen_port: None,
+            event_history,
+            thread_tracker,
+            adapter_error: Default::default(),
+            shutting_down_delivered: Some(shutting_down_rx),
+            event_task_jobs,
         })
     }
 
@@ -444,448 +816,1674 @@ impl Device {
         })
     }
 
-    pub fn start(&mut self, config: &DeviceConfig) -> Result {
-        if self.is_running() {
-            return Err(Error::AlreadyStarted);
-        }
-
-        self.rt = Some(self.art()?.block_on(async {
-            let t = Task::start(
-                Box::pin(Runtime::start(
-                    self.event.clone(),
-                    config,
-                    self.features.clone(),
-                    self.protect.clone(),
-                ))
-                .await?,
-            );
-            Ok::<Task<Runtime>, Error>(t)
-        })?);
-
-        Ok(())
+    /// Find peer which traffic to `ip` is sent to, similar to `ip route get` for the tunnel
+    pub fn get_peer_route(&self, ip: IpAddr) -> Result<PeerRoute> {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt.get_peer_route(ip).await)).await?
+        })
     }
 
-    pub fn stop(&mut self) {
-        if let Some(rt) = self.rt.take() {
-            if let Some(art) = &self.art {
-                let _ = art.block_on(rt.stop());
-                self.flush_events();
-            }
-        }
+    /// Routes to the tunnel managed by telio, from the allowed IPs of all peers on the adapter
+    pub fn get_route_table(&self) -> Result<Vec<ManagedRoute>> {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt.get_route_table().await)).await?
+        })
     }
 
-    fn flush_events(&self) {
-        if let Some(timeout) = self.features.flush_events_on_stop_timeout_seconds {
-            let start_time = Instant::now();
-            while !self.event.is_empty() {
-                std::thread::sleep(Duration::from_millis(100));
-                if timeout > 0 && start_time.elapsed().as_secs() >= timeout {
-                    break;
-                }
-            }
-        }
+    /// Allowed IPs of the peer as currently configured on the WireGuard adapter
+    ///
+    /// Empty if the peer is not configured on the adapter.
+    pub fn get_peer_allowed_ips(&self, public_key: PublicKey) -> Result<Vec<ipnetwork::IpNetwork>> {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .get_peer_allowed_ips(&public_key)
+                .await))
+            .await?
+        })
     }
 
-    /// [Windows only] Retrieve the UUID of the interface
+    /// Append security relevant operations of the running device to the log at `path`
     ///
-    /// This methods retrieves the UUID of the virtual interface created by device::start() call
-    pub fn get_adapter_luid(&mut self) -> u64 {
-        if let Some(art) = &self.art {
-            let res: Result<u64> = art.block_on(async {
-                task_exec!(self.rt()?, async move |rt| Ok(rt.get_adapter_luid().await)).await?
-            });
-            res.unwrap_or(0)
-        } else {
-            0
-        }
+    /// Records are chained with HMAC keyed by `audit_key`, which is independent of the private
+    /// key, see `audit_log::verify`. `None` stops logging, the log is also closed when the device
+    /// stops.
+    #[cfg(feature = "audit_log")]
+    pub fn set_audit_log_path(
+        &self,
+        path: Option<std::path::PathBuf>,
+        audit_key: Vec<u8>,
+    ) -> Result {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .set_audit_log_path(path, &audit_key)
+                .await))
+            .await?
+        })
     }
 
-    pub fn shutdown_art(&mut self) {
-        if let Some(art) = self.art.take() {
-            if let Ok(art) = Arc::try_unwrap(art) {
-                art.shutdown_background();
-            }
-        }
+    /// Peer, relay and key rotation events reported after `since_unix_secs`, oldest first
+    ///
+    /// Events are kept even when the device is stopped, so they can be replayed after the app
+    /// wakes up. Only the latest `event_history_size` events are available.
+    pub fn get_connection_events_since(&self, since_unix_secs: u64) -> Vec<HistoryEvent> {
+        self.event_history.lock().since(since_unix_secs)
     }
 
-    pub fn try_shutdown(mut self, timeout: Duration) -> Result {
-        let art = self.art.take().ok_or(Error::NotStarted)?;
-        let art = Arc::try_unwrap(art);
-        match art {
-            Ok(art) => {
-                art.shutdown_timeout(timeout);
-                Ok(())
-            }
-            _ => Err(Error::AsyncRuntime(IoError::new(
-                ErrorKind::Other,
-                "cannot aquire async runtime",
-            ))),
-        }
+    /// Approximate memory used by peer maps, event history and relay state of the running device
+    #[cfg(feature = "memory_diagnostics")]
+    pub fn get_memory_stats(&self) -> Result<MemoryStats> {
+        let log_buffer_bytes = self.event_history.lock().memory_usage();
+        let (peer_map_bytes, relay_state_bytes) = self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt.get_memory_usage().await)).await?
+        })?;
+        Ok(MemoryStats::new(
+            peer_map_bytes,
+            log_buffer_bytes,
+            relay_state_bytes,
+        ))
     }
 
-    /// Configure the private key of the WireGuard interface
-    ///
-    /// This methods sets the private key of the WireGuard interface. Note that it
-    /// is only valid to call this method only on device instance which is currently
-    /// not running. E.g. either before start()'ing or after stop()'ing it.
-    pub fn set_private_key(&self, private_key: &SecretKey) -> Result {
-        let private_key = *private_key; //Going into async context, therefore just copy for lifetimes
+    /// Count meshnet peers which currently have an active WireGuard session
+    pub fn get_connected_peers_count(&self) -> Result<usize> {
         self.art()?.block_on(async {
-            task_exec!(self.rt()?, async move |rt| {
-                Ok(rt.set_private_key(&private_key).await)
-            })
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .get_connected_peers_count()
+                .await))
             .await?
         })
     }
 
-    /// Retrieves currently configured private key for the interface
-    pub fn get_private_key(&self) -> Result<SecretKey> {
+    /// Estimated difference of the local clock from the relay server clock in milliseconds
+    ///
+    /// Positive if the local clock is ahead, `None` if not connected to a relay server.
+    pub fn get_clock_skew(&self) -> Result<Option<i64>> {
         self.art()?.block_on(async {
-            task_exec!(self.rt()?, async move |rt| Ok(rt.get_private_key().await)).await?
+            task_exec!(self.rt()?, async move |rt| Ok(rt.get_clock_skew().await)).await?
         })
     }
 
-    /// [Linux only] Configure the fwmark used for encapsulated packets
-    #[cfg(any(target_os = "linux", doc))]
-    #[cfg_attr(docsrs, doc(cfg(target_os = "linux")))]
-    pub fn set_fwmark(&self, fwmark: u32) -> Result {
+    /// Get the type of the adapter the device was started with
+    pub fn adapter_type(&self) -> Result<AdapterType> {
         self.art()?.block_on(async {
-            task_exec!(self.rt()?, async move |rt| Ok(rt.set_fwmark(fwmark).await)).await?
+            Ok(task_exec!(self.rt()?, async move |rt| Ok(rt
+                .requested_state
+                .device_config
+                .adapter))
+            .await?)
         })
     }
 
-    #[cfg(not(windows))]
-    async fn protect_from_vpn(&self, adapter: &impl WireGuard) -> Result {
-        if let Some(protect) = self.protect.as_ref() {
-            if let Some(fd) = adapter.get_wg_socket(false).await? {
-                protect(fd);
-            }
-            if let Some(fd) = adapter.get_wg_socket(true).await? {
-                protect(fd);
-            }
-        }
-        Ok(())
+    /// Collect a liveness report of the running device
+    ///
+    /// Every component is probed with a bounded timeout and reported as degraded if it does not
+    /// respond in time. Fails with `Error::RuntimeUnresponsive` if the runtime itself is stuck.
+    pub fn health_check(&self) -> Result<HealthReport> {
+        self.art()?.block_on(async {
+            tokio::time::timeout(
+                HEALTH_CHECK_TIMEOUT * 8,
+                task_exec!(self.rt()?, async move |rt| Ok(rt.health_check().await)),
+            )
+            .await
+            .map_err(|_| Error::RuntimeUnresponsive)??
+        })
     }
 
-    /// Configure meshnet
+    /// Get hex encoded SHA-256 hash of the last successfully applied meshnet config
     ///
-    /// This method sets the desired meshnet configuration
-    pub fn set_config(&self, config: &Option<Config>) -> Result {
-        let config = config.clone();
+    /// Hash is computed over the canonical JSON form of the config, so it changes only when the
+    /// config does. `EMPTY_MESHNET_CONFIG_HASH` is returned if meshnet is not configured.
+    pub fn get_meshnet_config_hash(&self) -> Result<String> {
         self.art()?.block_on(async {
-            task_exec!(self.rt()?, async move |rt| Ok(Box::pin(
-                rt.set_config(&config)
-            )
-            .await))
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .get_meshnet_config_hash()
+                .await))
             .await?
         })
     }
 
-    /// Notify device about network change event
+    /// Hostname of this device in the meshnet config, `None` if meshnet is not configured
+    pub fn get_mesh_hostname(&self) -> Result<Option<String>> {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt.get_mesh_hostname().await)).await?
+        })
+    }
+
+    /// Get Unix timestamp, in seconds, of the last successful `set_config` completion
     ///
-    /// In some cases integrators may have better knowledge of the network state or state changes,
-    /// therefore this method assumes, that a device has migrated from one network to the other,
-    /// and adapts whatever is needed
-    pub fn notify_network_change(&self) -> Result {
+    /// Turning meshnet off counts as applying the config too. `None` if no config was applied.
+    pub fn get_last_config_apply_time(&self) -> Result<Option<u64>> {
         self.art()?.block_on(async {
-            task_exec!(self.rt()?, async move |rt| {
-                Ok(rt.notify_network_change().await)
-            })
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .get_last_config_apply_time()
+                .await))
             .await?
         })
     }
 
-    /// Connect to exit node
-    ///
-    /// Exit node in this case may be the VPN server or another meshnet node. In the former case,
-    /// new node is created and WireGuard tunnel is established to that node. In the latter case
-    /// the specified (matched by public key) meshnet node is "promoted" to be the exit node
-    pub fn connect_exit_node(&self, node: &ExitNode) -> Result {
+    /// Retrieve STUN servers used for direct path discovery along with their last response times
+    pub fn get_stun_servers(&self) -> Result<Vec<StunServerStatus>> {
         self.art()?.block_on(async {
-            let node = node.clone();
-            let _wireguard_interface: Arc<DynamicWg> = task_exec!(self.rt()?, async move |rt| {
-                rt.connect_exit_node(&node).await?;
-                Ok(rt.entities.wireguard_interface.clone())
-            })
-            .await?;
+            task_exec!(self.rt()?, async move |rt| Ok(rt.get_stun_servers().await)).await?
+        })
+    }
 
-            // TODO: delete this as sockets are protected from within boringtun itself
-            #[cfg(not(windows))]
-            self.protect_from_vpn(&*_wireguard_interface).await?;
+    /// Network interfaces seen by the local interfaces endpoint provider
+    pub fn list_interfaces(&self) -> Result<Vec<NetworkInterface>> {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt.list_interfaces().await)).await?
+        })
+    }
 
-            Ok(())
+    /// Get at most `n_samples` latest round trip times over the relay, oldest first
+    ///
+    /// Samples of `peer` are taken from WireGuard handshakes going through the relay, with `None`
+    /// samples of the relay server itself are returned. Empty list is returned if meshnet is
+    /// not configured.
+    pub fn get_relay_latency_history(
+        &self,
+        peer: Option<PublicKey>,
+        n_samples: usize,
+    ) -> Result<Vec<RttSample>> {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .get_relay_latency_history(peer, n_samples)
+                .await))
+            .await?
         })
     }
 
-    /// Disconnect from exit node
+    /// Get all configured relay servers with results of the latest connection attempts
     ///
-    /// Undoes the effects of calling device::connect_exit_node(), matching the node by public key
-    pub fn disconnect_exit_node(&self, node_key: &PublicKey) -> Result {
+    /// Servers are in the order they are tried, those not tried yet are included as well.
+    /// Empty list is returned if meshnet is not configured.
+    pub fn get_relay_server_list(&self) -> Result<Vec<RelayServerStatus>> {
         self.art()?.block_on(async {
-            let node_key = *node_key;
-            task_exec!(self.rt()?, async move |rt| {
-                Ok(rt.disconnect_exit_node(&node_key).await)
-            })
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .get_relay_server_list()
+                .await))
             .await?
-            .map_err(Error::from)
         })
     }
 
-    /// Disconnects from any VPN and/or demotes any meshnet node to be a regular meshnet node
-    /// instead of exit node
-    pub fn disconnect_exit_nodes(&self) -> Result {
+    /// Get identifier of the ongoing relay session with the meshnet peer
+    ///
+    /// Identifier changes whenever the session is re-established and is logged along relay
+    /// events. `None` if no traffic was relayed to or from the peer over the current relay
+    /// connection or meshnet is not configured.
+    pub fn get_relay_session_id(&self, peer: PublicKey) -> Result<Option<uuid::Uuid>> {
         self.art()?.block_on(async {
-            task_exec!(self.rt()?, async move |rt| {
-                Ok(rt.disconnect_exit_nodes().await)
-            })
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .get_relay_session_id(peer)
+                .await))
             .await?
-            .map_err(Error::from)
         })
     }
 
-    fn rt(&self) -> Result<&Task<Runtime>> {
-        self.rt.as_ref().ok_or(Error::NotStarted)
+    /// Register a handler for application messages received from meshnet peers
+    ///
+    /// Replaces previously registered handler, `None` unregisters it.
+    pub fn set_custom_message_handler(&self, handler: Option<CustomMessageHandler>) {
+        *self.custom_message_handler.write() = handler;
     }
 
-    fn art(&self) -> Result<&Arc<AsyncRuntime>> {
-        self.art.as_ref().ok_or(Error::NotStarted)
+    /// Register a provider of the network interfaces state, polled for changes every few seconds
+    ///
+    /// Change of the returned state is handled like `notify_network_change`, so that the host
+    /// doesn't have to report network changes itself. Replaces previously registered provider,
+    /// `None` unregisters it.
+    pub fn set_link_state_provider(&self, provider: Option<LinkStateProvider>) {
+        *self.link_state_provider.write() = provider;
     }
 
-    /// Enables DNS server
+    /// Send an application message to a meshnet peer over the relay
     ///
-    /// DNS server hosted on a virtual host within device can be used to resolve the domain names
-    /// of the meshnet nodes. If the DNS query sent to this server does not fall under .nord
-    /// top-level-domain, the query is forwarded to one of the `upstream_servers`.
-    pub fn enable_magic_dns(&self, upstream_servers: &[IpAddr]) -> Result {
+    /// Payload can be at most `telio_proto::MAX_APPLICATION_PAYLOAD_SIZE` bytes long.
+    pub fn send_custom_message(&self, recipient: PublicKey, payload: &[u8]) -> Result {
+        let msg = ApplicationMsg::new(payload)?;
         self.art()?.block_on(async {
-            let upstream_servers = upstream_servers.to_vec();
             task_exec!(self.rt()?, async move |rt| {
-                Ok(rt.start_dns(&upstream_servers).await)
+                Ok(rt.send_custom_message(recipient, msg).await)
             })
             .await?
         })
     }
 
-    /// Disables DNS server
+    /// Send an application message to all connected meshnet peers over the relay
     ///
-    /// Undoes the effects of `device::enable_magic_dns()` call
-    pub fn disable_magic_dns(&self) -> Result {
+    /// Returns the number of peers the message was enqueued for, delivery is not confirmed.
+    /// Peers receive it through the same handler as messages sent with `send_custom_message`.
+    pub fn broadcast_custom_message(&self, payload: &[u8]) -> Result<usize> {
+        let msg = BroadcastMsg::new(payload)?;
         self.art()?.block_on(async {
-            task_exec!(self.rt()?, async move |rt| Ok(rt.stop_dns().await)).await?
+            task_exec!(self.rt()?, async move |rt| {
+                Ok(rt.broadcast_custom_message(msg).await)
+            })
+            .await?
         })
     }
 
-    /// A artificial method causing panics
+    /// Ask a sleeping meshnet peer to wake up
     ///
-    /// Used only for testing purposes
-    pub fn _panic(&self) -> Result {
+    /// Wake request is sent over the relay, so it doesn't need a direct connection to the peer.
+    /// Delivery is not confirmed.
+    pub fn wake_peer(&self, public_key: PublicKey) -> Result {
         self.art()?.block_on(async {
-            task_exec!(self.rt()?, async move |rt| Ok(rt._panic().await)).await?
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .wake_peer(public_key)
+                .await))
+            .await?
         })
     }
 
-    /// Retrieves a reference to SocketPool. Use this instead of SocketPool::default() when possible
-    pub fn get_socket_pool(&self) -> Result<Arc<SocketPool>> {
+    /// Verify that a meshnet peer owns the private key of its public key
+    ///
+    /// Random nonce is sent to the peer over the relay and the peer answers with its signature of
+    /// the nonce. Result is reported with `AuthChallengeResult` event, nothing is reported if the
+    /// peer doesn't answer.
+    pub fn send_auth_challenge(&self, public_key: PublicKey) -> Result {
         self.art()?.block_on(async {
-            task_exec!(self.rt()?, async move |rt| Ok(rt.get_socket_pool().await)).await?
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .send_auth_challenge(public_key)
+                .await))
+            .await?
         })
     }
 
-    pub fn get_nat(&self, skt: SocketAddr) -> Result<NatData> {
-        match self.art()?.block_on(retrieve_single_nat(skt)) {
-            Ok(data) => Ok(data),
-            Err(no_data) => Err(Error::FailedNatInfoRecover(no_data)),
-        }
+    /// Send WireGuard keepalive to the meshnet peer now, regardless of its keepalive timer
+    pub fn send_keepalive(&self, public_key: PublicKey) -> Result {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .send_keepalive(public_key)
+                .await))
+            .await?
+        })
     }
 
-    pub fn trigger_analytics_event(&self) -> Result<()> {
+    /// Ask the application to get a new meshnet IP address from the backend
+    ///
+    /// Only `IpReassignmentRequested` event is reported, the backend is not contacted.
+    pub fn request_ip_reassignment(&self) -> Result {
         self.art()?.block_on(async {
             task_exec!(self.rt()?, async move |rt| Ok(rt
-                .trigger_analytics_event()
+                .request_ip_reassignment()
                 .await))
             .await?
         })
     }
-}
 
-impl Drop for Device {
-    fn drop(&mut self) {
-        if self.features.lana.is_some() {
-            let _ = telio_lana::deinit_lana();
-        }
+    /// Drop all traffic from the peer, without removing it from the WireGuard interface
+    ///
+    /// Ban is local to this device and is kept until `unban_peer` or device stop.
+    pub fn ban_peer(&self, public_key: PublicKey) -> Result {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .ban_peer(public_key)
+                .await))
+            .await?
+        })
     }
-}
 
-impl RequestedState {
-    // A Convenience function to build a DNS records list from the requested meshnet config
-    // This function does not take into account whether DNS is enabled or not. It simply builds a
-    // list of hostname<->IP pairs out of currently requested meshnet nodes. If meshnet is disabled,
-    // empty list is returned.
-    pub fn collect_dns_records(&self) -> Records {
-        self.meshnet_config
-            .clone()
-            .map_or(Vec::new(), |cfg| {
-                [
-                    cfg.peers.map_or(Vec::new(), |peers| peers),
-                    vec![Peer {
-                        base: cfg.this,
-                        ..Default::default()
-                    }],
-                ]
-                .concat()
-            })
-            .iter()
-            .filter_map(|v| {
-                v.ip_addresses
-                    .as_ref()
-                    .map(|ips| (v.hostname.0.to_owned().to_string(), ips.clone()))
-            })
-            .collect()
+    /// Lift the ban set by `ban_peer`
+    pub fn unban_peer(&self, public_key: PublicKey) -> Result {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .unban_peer(public_key)
+                .await))
+            .await?
+        })
     }
 
-    // Same as collect_dns_records() fn but for peers with defined nicknames.
-    pub fn collect_dns_nickname_records(&self) -> Records {
-        let to_record = |p: &PeerBase| {
-            p.nickname
-                .as_ref()
-                .cloned()
-                .and_then(|nick| p.ip_addresses.as_ref().cloned().map(|ips| (nick, ips)))
-        };
+    /// Peers banned with `ban_peer`
+    pub fn get_banned_peers(&self) -> Result<Vec<PublicKey>> {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt.get_banned_peers().await)).await?
+        })
+    }
 
-        self.meshnet_config
-            .iter()
-            .flat_map(|cfg| {
-                to_record(&cfg.this).into_iter().chain(
-                    cfg.peers
-                        .iter()
-                        .flat_map(|peers| peers.iter())
-                        .filter_map(|p| to_record(p)),
-                )
-            })
-            .filter(|(nick, _)| validate_nickname(nick))
-            .fold(Records::new(), |mut records, (mut nick, ip)| {
-                nick += ".nord";
+    /// Current WireGuard interface and peer configuration in the `wg(8)` config file format
+    ///
+    /// Secrets are not exported, the private key is replaced with the fingerprint of the
+    /// public key and preshared keys are omitted.
+    pub fn export_wireguard_config(&self) -> Result<String> {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .export_wireguard_config()
+                .await))
+            .await?
+        })
+    }
 
-                if let Entry::Vacant(e) = records.entry(nick.to_owned()) {
-                    e.insert(ip);
-                } else {
-                    telio_log_warn!("Nickname is already assigned: {nick:?}. Ignore");
-                }
+    /// Zero rx/tx byte counters of the peer, or of all peers if `public_key` is `None`
+    ///
+    /// Counters reported afterwards, e.g. in the exported WireGuard config, start from zero.
+    pub fn reset_statistics(&self, public_key: Option<PublicKey>) -> Result {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .reset_statistics(public_key)
+                .await))
+            .await?
+        })
+    }
 
-                records
-            })
+    /// Stable identifier of the device, derived from its public key
+    ///
+    /// Same key always results in the same fingerprint, while the key itself is not revealed.
+    pub fn get_device_fingerprint(&self) -> Result<String> {
+        Ok(device_fingerprint(&self.get_private_key()?.public()))
     }
-}
 
-impl MeshnetEntites {
-    async fn stop(mut self) {
-        macro_rules! stop_entity {
-            ($entity: expr, $name: expr) => {{
-                if let Ok(task) = $entity
-                    .map(|m| m.stop())
-                    .map_err(|_| {
-                        telio_log_warn!(
-                            "Oops, smething went wrong! Something is holding a strong reference to the {:?} instance.",
-                            $name
-                        );
-                    }) {
-                    task.await;
-                } else {
-                    telio_log_warn!("Oops, something went wrong while stopping {:?} tasks.", $name);
-                }
-            }}
-        }
-        macro_rules! stop_arc_entity {
-            ($entity: expr, $name: expr) => {{
-                stop_entity!(Arc::try_unwrap($entity), $name)
-            }};
-        }
+    /// Stable UUID of the device, derived from its public key
+    ///
+    /// Meant for correlating logs of the device with backend logs, without revealing the key.
+    pub fn get_device_id(&self) -> Result<Uuid> {
+        Ok(device_id(&self.get_private_key()?.public()))
+    }
 
-        if let Some(direct) = self.direct.take() {
-            // Arc dependency on endpoint providers
-            stop_arc_entity!(direct.cross_ping_check, "CrossPingCheck");
-            drop(direct.endpoint_providers);
+    /// Last failure of the WireGuard adapter, e.g. failed creation of the tunnel interface
+    ///
+    /// Kept across restarts of the device, `None` if no adapter operation has failed yet.
+    pub fn get_adapter_error(&self) -> Option<String> {
+        self.adapter_error.get()
+    }
 
-            // Arc dependency on wireguard
-            if let Some(local) = direct.local_interfaces_endpoint_provider {
-                stop_arc_entity!(local, "LocalInterfacesEndpointProvider");
-            }
-            if let Some(stun) = direct.stun_endpoint_provider {
-                stop_arc_entity!(stun, "StunEndpointProvider");
-            }
-            if let Some(upnp) = direct.upnp_endpoint_provider {
-                stop_arc_entity!(upnp, "UpnpEndpointProvider");
-            }
+    /// Encrypt application payload for the owner of `recipient`
+    ///
+    /// Key is derived from X25519 agreement of the device private key and `recipient`, bound to
+    /// the application payload context and the direction from this device to `recipient`.
+    /// Payload is sealed with XChaCha20-Poly1305 under a random nonce, which is prepended to the
+    /// result.
+    pub fn encrypt_payload(&self, recipient: &PublicKey, plaintext: &[u8]) -> Result<Vec<u8>> {
+        Ok(encryption::encrypt_with_context(
+            plaintext,
+            &mut rand::thread_rng(),
+            &self.get_private_key()?,
+            recipient,
+            PAYLOAD_ENCRYPTION_CONTEXT,
+        )?)
+    }
 
-            stop_arc_entity!(direct.session_keeper, "SessionKeeper");
-            stop_arc_entity!(direct.upgrade_sync, "UpgradeSync");
-        }
+    /// Decrypt application payload created by `sender` with `encrypt_payload`
+    pub fn decrypt_payload(&self, sender: &PublicKey, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        Ok(encryption::decrypt_with_context(
+            ciphertext,
+            &self.get_private_key()?,
+            sender,
+            PAYLOAD_ENCRYPTION_CONTEXT,
+        )?)
+    }
 
-        stop_arc_entity!(self.multiplexer, "Multiplexer");
-        stop_arc_entity!(self.derp, "Derp");
-        stop_arc_entity!(self.proxy, "UdpProxy");
+    /// Sign `data` with the device private key
+    ///
+    /// Signature is created with XEdDSA over `data` prefixed with the application signing
+    /// context, anyone knowing the device public key can verify it with `verify_signed_data`.
+    pub fn sign_data(&self, data: &[u8]) -> Result<[u8; signature::SIGNATURE_SIZE]> {
+        Ok(signature::sign(
+            &self.get_private_key()?,
+            &[SIGN_DATA_CONTEXT, data].concat(),
+            &mut rand::thread_rng(),
+        ))
     }
-}
 
-impl Runtime {
-    async fn start(
-        libtelio_wide_event_publisher: Tx<Box<Event>>,
-        config: &DeviceConfig,
-        features: Features,
-        protect: Option<Protect>,
-    ) -> Result<Self> {
-        let firewall = Arc::new(StatefullFirewall::new(
-            features.ipv6,
-            features.boringtun_reset_connections.0,
-        ));
+    /// Create a token proving the ownership of the device private key, valid for `expiry_secs`,
+    /// capped at `token::MAX_EXPIRY_SECS`
+    ///
+    /// See `telio_crypto::token` for the format.
+    pub fn create_meshnet_token(&self, expiry_secs: u32) -> Result<String> {
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        Ok(token::create(
+            &self.get_private_key()?,
+            now,
+            expiry_secs,
+            &mut rand::thread_rng(),
+        ))
+    }
 
-        let firewall_filter_inbound_packets = {
-            let fw = firewall.clone();
-            move |peer: &[u8; 32], packet: &[u8]| fw.process_inbound_packet(peer, packet)
-        };
-        let firewall_filter_outbound_packets = {
-            let fw = firewall.clone();
-            move |peer: &[u8; 32], packet: &[u8]| fw.process_outbound_packet(peer, packet)
-        };
-        let firewall_reset_connections = if features.boringtun_reset_connections.0 {
-            let fw = firewall.clone();
-            let cb = move |exit_pubkey: &PublicKey,
-                           exit_ipv4: Ipv4Addr,
-                           sink4: &mut dyn io::Write,
-                           sink6: &mut dyn io::Write| {
-                if let Err(err) = fw.reset_connections(exit_pubkey, exit_ipv4, sink4, sink6) {
-                    telio_log_warn!("Failed to reset all connections: {err:?}");
-                }
-            };
-            Some(Arc::new(cb) as Arc<_>)
-        } else {
-            None
-        };
+    /// Limit bandwidth of traffic exchanged with the peer
+    ///
+    /// Packets exceeding `max_tx_kbps` towards the peer or `max_rx_kbps` from it are dropped,
+    /// zero leaves the direction unlimited. Replaces previous limits of the peer.
+    /// Only boringtun passes packets through the shaper, other adapters are rejected.
+    pub fn enable_traffic_shaping(
+        &self,
+        public_key: PublicKey,
+        max_tx_kbps: u32,
+        max_rx_kbps: u32,
+    ) -> Result {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .enable_traffic_shaping(public_key, max_tx_kbps, max_rx_kbps)
+                .await))
+            .await?
+        })
+    }
 
-        let socket_pool = Arc::new({
-            if let Some(protect) = protect.clone() {
-                SocketPool::new(protect)
-            } else {
-                SocketPool::new(NativeProtector::new(
-                    #[cfg(target_os = "macos")]
-                    features.is_test_env.unwrap_or(false),
-                )?)
-            }
-        });
+    /// Remove bandwidth limits set by `enable_traffic_shaping`
+    pub fn disable_traffic_shaping(&self, public_key: PublicKey) -> Result {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .disable_traffic_shaping(public_key)
+                .await))
+            .await?
+        })
+    }
 
-        let derp_events = McChan::default();
+    /// Start logging metadata of a random sample of packets exchanged with peers
+    ///
+    /// Replaces the previous config, entries logged so far are kept up to `max_entries`.
+    #[cfg(feature = "traffic_logging")]
+    pub fn enable_traffic_logging(&self, config: TrafficLogConfig) -> Result {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .enable_traffic_logging(config)
+                .await))
+            .await?
+        })
+    }
 
-        let (config_update_ch, collection_trigger_ch) = if features.nurse.is_some() {
-            (
-                Some(McChan::<Box<MeshConfigUpdateEvent>>::default().tx),
-                Some(McChan::<Box<()>>::default().tx),
-            )
-        } else {
-            (None, None)
+    /// Packets logged since `enable_traffic_logging`, ordered from the oldest
+    #[cfg(feature = "traffic_logging")]
+    pub fn get_traffic_log(&self) -> Result<Vec<TrafficLogEntry>> {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt.get_traffic_log().await)).await?
+        })
+    }
+
+    /// Call `handler` for every packet exchanged with peers, `None` disables it
+    ///
+    /// Handler runs on the packet processing threads, so it must return quickly.
+    #[cfg(feature = "packet_stats")]
+    pub fn set_packet_stats_handler(&self, handler: Option<PacketStatsHandler>) -> Result {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .set_packet_stats_handler(handler)
+                .await))
+            .await?
+        })
+    }
+
+    /// Capture first `max_bytes_per_packet` bytes of the latest `max_packets` packets exchanged
+    /// with meshnet peers over the relay, zero `max_packets` disables the capture
+    #[cfg(feature = "packet_trace")]
+    pub fn enable_packet_trace(&self, max_bytes_per_packet: usize, max_packets: usize) -> Result {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .enable_packet_trace(max_bytes_per_packet, max_packets)
+                .await))
+            .await?
+        })
+    }
+
+    /// Packets captured since the previous call, ordered from the oldest
+    #[cfg(feature = "packet_trace")]
+    pub fn get_packet_trace(&self) -> Result<Vec<PacketTraceEntry>> {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt.get_packet_trace().await)).await?
+        })
+    }
+
+    /// Retrieve up to `max_entries` most recently active connections tracked by the firewall
+    pub fn get_active_connections(&self, max_entries: usize) -> Result<Vec<ActiveConnection>> {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| {
+                Ok(rt.get_active_connections(max_entries).await)
+            })
+            .await?
+        })
+    }
+
+    pub fn start(&mut self, config: &DeviceConfig) -> Result {
+        if self.is_running() {
+            return Err(Error::AlreadyStarted);
+        }
+
+        let config = DeviceConfig {
+            listen_port: config.listen_port.or(self.listen_port),
+            ..config.clone()
         };
 
-        // tests runtime use wg::MockedAdapter
-        cfg_if! {
+        self.rt = Some(self.art()?.block_on(async {
+            let t = Task::start(
+                Box::pin(Runtime::start(
+                    self.event.clone(),
+                    &config,
+                    self.features.clone(),
+                    self.protect.clone(),
+                    self.bind_network.clone(),
+                    self.custom_message_handler.clone(),
+                    self.link_state_provider.clone(),
+                    self.adapter_error.clone(),
+                ))
+                .await?,
+            );
+            Ok::<Task<Runtime>, Error>(t)
+        })?);
+
+        Ok(())
+    }
+
+    /// Set UDP port of the WireGuard socket used by the next `start`
+    ///
+    /// Port can only be changed while the device is stopped, zero lets the adapter pick one.
+    /// Port given in `DeviceConfig::listen_port` takes precedence.
+    pub fn set_listen_port(&mut self, port: u16) -> Result {
+        if self.is_running() {
+            return Err(Error::AlreadyStarted);
+        }
+        self.listen_port = Some(port).filter(|&port| port != 0);
+        Ok(())
+    }
+
+    pub fn stop(&mut self) {
+        if let Some(rt) = self.rt.take() {
+            if let Some(art) = &self.art {
+                let _ = art.block_on(rt.stop());
+                self.flush_events();
+            }
+        }
+    }
+
+    /// Queue `event` for the event callback, after the events already reported by the device
+    pub fn publish_event(&self, event: Box<Event>) {
+        let _ = self.event.send(event);
+    }
+
+    /// Runner of jobs on the task passing events to the event callback
+    ///
+    /// Jobs never run concurrently with the event callback. They are dropped once `ShuttingDown`
+    /// was delivered.
+    pub fn event_task_runner(&self) -> Arc<dyn Fn(EventTaskJob) + Send + Sync> {
+        let jobs = self.event_task_jobs.clone();
+        Arc::new(move |job| {
+            let _ = jobs.send(job);
+        })
+    }
+
+    /// Queue `ShuttingDown` as the last event for the event callback
+    ///
+    /// It is passed to the callback after the events already reported, events reported later
+    /// are dropped. Returned receiver is notified once the callback returned and disconnected
+    /// if the event cannot be delivered, `None` if the event was already queued.
+    pub fn publish_shutting_down(&mut self) -> Option<std::sync::mpsc::Receiver<()>> {
+        let delivered = self.shutting_down_delivered.take()?;
+        self.publish_event(Box::new(Event::new::<ShuttingDown>().set(ShuttingDown {})));
+        Some(delivered)
+    }
+
+    /// Time to wait for `ShuttingDown` to be passed to the event callback
+    ///
+    /// `flush_events_on_stop_timeout_seconds` feature if set to a non-zero value, fixed
+    /// default otherwise.
+    pub fn shutting_down_delivery_timeout(&self) -> Duration {
+        self.features
+            .flush_events_on_stop_timeout_seconds
+            .filter(|timeout| *timeout > 0)
+            .map_or(DEFAULT_SHUTTING_DOWN_DELIVERY_TIMEOUT, Duration::from_secs)
+    }
+
+    /// Number of events waiting to be passed to the event callback
+    pub fn get_event_queue_depth(&self) -> usize {
+        self.event.len()
+    }
+
+    /// Number of threads spawned for this instance
+    ///
+    /// Counts threads of the async runtime, including blocking ones, and the thread monitoring
+    /// them. Threads spawned internally by the WireGuard adapter are not included.
+    pub fn get_thread_count(&self) -> usize {
+        // Monitoring thread is running for the whole lifetime of the instance
+        self.thread_tracker.lock().live_threads() + 1
+    }
+
+    fn flush_events(&self) {
+        if let Some(timeout) = self.features.flush_events_on_stop_timeout_seconds {
+            let start_time = Instant::now();
+            while !self.event.is_empty() {
+                std::thread::sleep(Duration::from_millis(100));
+                if timeout > 0 && start_time.elapsed().as_secs() >= timeout {
+                    break;
+                }
+            }
+        }
+    }
+
+    /// [Windows only] Retrieve the UUID of the interface
+    ///
+    /// This methods retrieves the UUID of the virtual interface created by device::start() call
+    pub fn get_adapter_luid(&mut self) -> u64 {
+        if let Some(art) = &self.art {
+            let res: Result<u64> = art.block_on(async {
+                task_exec!(self.rt()?, async move |rt| Ok(rt.get_adapter_luid().await)).await?
+            });
+            res.unwrap_or(0)
+        } else {
+            0
+        }
+    }
+
+    pub fn shutdown_art(&mut self) {
+        if let Some(art) = self.art.take() {
+            if let Ok(art) = Arc::try_unwrap(art) {
+                art.shutdown_background();
+            }
+        }
+    }
+
+    pub fn try_shutdown(mut self, timeout: Duration) -> Result {
+        let art = self.art.take().ok_or(Error::NotStarted)?;
+        let art = Arc::try_unwrap(art);
+        match art {
+            Ok(art) => {
+                art.shutdown_timeout(timeout);
+                Ok(())
+            }
+            _ => Err(Error::AsyncRuntime(IoError::new(
+                ErrorKind::Other,
+                "cannot aquire async runtime",
+            ))),
+        }
+    }
+
+    /// Configure the private key of the WireGuard interface
+    ///
+    /// This methods sets the private key of the WireGuard interface. Note that it
+    /// is only valid to call this method only on device instance which is currently
+    /// not running. E.g. either before start()'ing or after stop()'ing it.
+    pub fn set_private_key(&self, private_key: &SecretKey) -> Result {
+        let private_key = *private_key; //Going into async context, therefore just copy for lifetimes
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| {
+                Ok(rt.set_private_key(&private_key).await)
+            })
+            .await?
+        })
+    }
+
+    /// Replaces the private key of a started device and reports `KeyRotated` event
+    ///
+    /// The new key is validated the same way as in `set_private_key`, so with meshnet on it has
+    /// to be rotated by the backend first.
+    pub fn rotate_secret_key(&self, private_key: &SecretKey) -> Result {
+        let private_key = *private_key;
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| {
+                Ok(rt.rotate_secret_key(&private_key).await)
+            })
+            .await?
+        })
+    }
+
+    /// Set a nickname reported for the meshnet peer, `None` removes it
+    ///
+    /// Overrides the nickname from meshnet config until the config is replaced. Fails with
+    /// `Error::InvalidNickname` if the nickname is rejected by `validate_nickname`.
+    pub fn set_peer_nickname(&self, public_key: PublicKey, nickname: Option<String>) -> Result {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| {
+                Ok(rt.set_peer_nickname(public_key, nickname).await)
+            })
+            .await?
+        })
+    }
+
+    /// Get a nickname reported for the meshnet peer
+    pub fn get_peer_nickname(&self, public_key: PublicKey) -> Result<Option<String>> {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| {
+                Ok(rt.get_peer_nickname(&public_key).await)
+            })
+            .await?
+        })
+    }
+
+    /// Get the operating system of the meshnet peer, as reported in the meshnet config
+    pub fn get_peer_os(&self, public_key: PublicKey) -> Result<Option<String>> {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .get_peer_os(&public_key)
+                .await))
+            .await?
+        })
+    }
+
+    /// Get the telio version of the meshnet peer, in the format of `version_tag`
+    ///
+    /// Peers advertise it along with their capabilities, `None` until the advertisement is
+    /// received or if the peer runs a version which does not advertise it.
+    pub fn get_peer_version(&self, public_key: PublicKey) -> Result<Option<String>> {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .get_peer_version(&public_key)
+                .await))
+            .await?
+        })
+    }
+
+    /// Get all known endpoints of the meshnet peer
+    ///
+    /// Lists the relay connection and every direct endpoint validated by the connectivity check,
+    /// not only the one currently used by WireGuard.
+    pub fn get_peer_endpoints(&self, public_key: PublicKey) -> Result<Vec<PeerEndpoint>> {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .get_peer_endpoints(&public_key)
+                .await))
+            .await?
+        })
+    }
+
+    /// Get the graph of meshnet nodes and connections of this node to the peers
+    ///
+    /// Every configured peer is a node, only connected peers have an edge, which tells if the
+    /// connection is direct or relayed.
+    pub fn get_mesh_topology(&self) -> Result<MeshTopology> {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt.get_mesh_topology().await)).await?
+        })
+    }
+
+    /// Get the features supported by the meshnet peer
+    ///
+    /// Peers advertise them over the relay after connecting to it. All features are reported as
+    /// unsupported until the advertisement is received.
+    pub fn get_peer_capabilities(&self, public_key: PublicKey) -> Result<PeerCapabilities> {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .get_peer_capabilities(&public_key)
+                .await))
+            .await?
+        })
+    }
+
+    /// IP stack of this device, derived from addresses assigned to it in the meshnet config
+    pub fn get_ip_stack(&self) -> Result<IpStack> {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt.get_ip_stack().await)).await?
+        })
+    }
+
+    /// Trace the path to the meshnet peer, see `traceroute::traceroute`
+    ///
+    /// Blocks for up to `max_hops * timeout`.
+    #[cfg(feature = "diagnostics")]
+    pub fn meshnet_traceroute(
+        &self,
+        target: PublicKey,
+        max_hops: u8,
+        timeout: Duration,
+    ) -> Result<TracerouteReport> {
+        self.art()?.block_on(async {
+            let (ip, via) = task_exec!(self.rt()?, async move |rt| Ok(rt
+                .traceroute_target(&target)
+                .await))
+            .await??;
+            tokio::task::spawn_blocking(move || traceroute::traceroute(ip, via, max_hops, timeout))
+                .await
+                .map_err(|_| {
+                    Error::TracerouteFailed(IoError::new(
+                        ErrorKind::Other,
+                        "Traceroute task failed",
+                    ))
+                })?
+                .map_err(Error::TracerouteFailed)
+        })
+    }
+
+    /// Measure round trip time and packet loss to the meshnet peer, see `path_quality::measure`
+    ///
+    /// Blocks for up to `samples * max(interval, 1s)`.
+    #[cfg(feature = "diagnostics")]
+    pub fn measure_path_quality(
+        &self,
+        target: PublicKey,
+        samples: u32,
+        interval: Duration,
+    ) -> Result<PathQuality> {
+        self.art()?.block_on(async {
+            let (ip, _) = task_exec!(self.rt()?, async move |rt| Ok(rt
+                .traceroute_target(&target)
+                .await))
+            .await??;
+            tokio::task::spawn_blocking(move || path_quality::measure(ip, samples, interval))
+                .await
+                .map_err(|_| {
+                    Error::PathQualityFailed(IoError::new(
+                        ErrorKind::Other,
+                        "Path quality task failed",
+                    ))
+                })?
+                .map_err(Error::PathQualityFailed)
+        })
+    }
+
+    /// Diagnose connectivity to the meshnet peer in stages, see `ConnectivityTest`
+    ///
+    /// Stages are run one after another even if some fail. Blocks for up to the magic DNS
+    /// query timeout plus one second for the ping.
+    #[cfg(feature = "diagnostics")]
+    pub fn check_connectivity(&self, target: PublicKey) -> Result<ConnectivityReport> {
+        let dns_timeout = self
+            .features
+            .proxy_dns_query_timeout_ms
+            .map_or(DEFAULT_PROXY_DNS_QUERY_TIMEOUT, Duration::from_millis);
+        self.art()?.block_on(async {
+            let (mut report, ping_target) = task_exec!(self.rt()?, async move |rt| Ok(rt
+                .check_connectivity(&target, dns_timeout)
+                .await))
+            .await??;
+
+            let ping = match ping_target {
+                Some(ip) => tokio::task::spawn_blocking(move || {
+                    path_quality::measure(ip, 1, CONNECTIVITY_PING_TIMEOUT)
+                })
+                .await
+                .map_err(|_| IoError::new(ErrorKind::Other, "Ping task failed"))
+                .and_then(|quality| quality),
+                None => Err(IoError::new(
+                    ErrorKind::NotFound,
+                    Error::NoMeshnetIP.to_string(),
+                )),
+            };
+            report.tests.push(match ping {
+                Ok(PathQuality {
+                    avg_rtt_ms: Some(rtt_ms),
+                    ..
+                }) => ConnectivityTestResult::passed(
+                    ConnectivityTest::Ping,
+                    Some(Duration::from_secs_f64(rtt_ms / 1000.0)),
+                ),
+                Ok(_) => {
+                    ConnectivityTestResult::failed(ConnectivityTest::Ping, "Peer did not answer")
+                }
+                Err(err) => ConnectivityTestResult::failed(ConnectivityTest::Ping, err),
+            });
+            Ok(report)
+        })
+    }
+
+    /// Create or replace a group of meshnet peers sharing the same routing policy
+    ///
+    /// If this device is a member of the group and the policy has an exit node, traffic is
+    /// routed through it, replacing the currently connected exit node.
+    pub fn create_peer_group(&self, group: PeerGroup) -> Result {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .create_peer_group(group)
+                .await))
+            .await?
+        })
+    }
+
+    /// Delete a peer group, disconnecting from its exit node if it is in use
+    pub fn delete_peer_group(&self, id: String) -> Result {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .delete_peer_group(&id)
+                .await))
+            .await?
+        })
+    }
+
+    /// List peer groups ordered by their identifiers
+    pub fn list_peer_groups(&self) -> Result<Vec<PeerGroup>> {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt.list_peer_groups().await)).await?
+        })
+    }
+
+    /// Limit the number of peers which can exchange traffic through the relay at the same time
+    ///
+    /// Unlimited by default. When the limit is reached, new peers are refused until sessions of
+    /// the least recently active peers become idle. Setting is kept across meshnet config changes.
+    pub fn set_max_relay_connections(&self, max: u32) -> Result {
+        if max < 1 {
+            return Err(Error::InvalidMaxRelayConnections);
+        }
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .set_max_relay_connections(max as usize)
+                .await))
+            .await?
+        })
+    }
+
+    /// Choose the relay servers the device connects to
+    ///
+    /// Servers are taken from the meshnet config, setting is kept across meshnet config changes.
+    /// If the connected server is not allowed by the new policy, the relay reconnects.
+    pub fn set_relay_server_selection_policy(&self, policy: RelaySelectionPolicy) -> Result {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .set_relay_server_selection_policy(policy)
+                .await))
+            .await?
+        })
+    }
+
+    /// Set persistent keepalive intervals of meshnet peers
+    ///
+    /// Peers without an override use the policy default, or the intervals from features if the
+    /// default is not set. Policy is kept across meshnet config and private key changes.
+    pub fn set_keepalive_policy(&self, policy: KeepalivePolicy) -> Result {
+        let max = u16::MAX as u32;
+        if policy.default_secs.map_or(false, |secs| secs > max)
+            || policy.overrides.iter().any(|o| o.secs > max)
+        {
+            return Err(Error::InvalidKeepalivePolicy);
+        }
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .set_keepalive_policy(policy)
+                .await))
+            .await?
+        })
+    }
+
+    /// Set proxy used for the connections to relay and DNS over HTTPS servers, `None` connects
+    /// directly
+    ///
+    /// Only the control-plane TCP connections are proxied, WireGuard UDP traffic always goes
+    /// directly to the peers and relays.
+    pub fn set_proxy_settings(&self, proxy: Option<ProxySettings>) -> Result {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .set_proxy_settings(proxy)
+                .await))
+            .await?
+        })
+    }
+
+    /// Set handling of IP addresses assigned to more than one meshnet node, `None` disables the
+    /// check
+    ///
+    /// Overrides `ip_conflict_resolution` feature, checked on the next meshnet config change.
+    pub fn set_ip_conflict_resolution_policy(&self, policy: Option<IpConflictPolicy>) -> Result {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .set_ip_conflict_resolution_policy(policy)
+                .await))
+            .await?
+        })
+    }
+
+    /// Stop direct path discovery and route all meshnet traffic through the relay
+    ///
+    /// Setting is kept across meshnet config changes until `enable_direct_path` is called.
+    pub fn disable_direct_path(&self) -> Result {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .set_direct_path(false)
+                .await))
+            .await?
+        })
+    }
+
+    /// Resume direct path discovery disabled by `disable_direct_path`
+    pub fn enable_direct_path(&self) -> Result {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .set_direct_path(true)
+                .await))
+            .await?
+        })
+    }
+
+    /// Retrieves currently configured private key for the interface
+    pub fn get_private_key(&self) -> Result<SecretKey> {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt.get_private_key().await)).await?
+        })
+    }
+
+    /// [Non-Windows only] Retrieve the tunnel file descriptor passed to `start`
+    ///
+    /// `None` is returned if the adapter opened its own tunnel. The descriptor stays owned by
+    /// the device.
+    #[cfg(not(windows))]
+    pub fn get_current_tun_fd(&self) -> Result<Option<Tun>> {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .get_current_tun_fd()
+                .await))
+            .await?
+        })
+    }
+
+    /// [Linux only] Configure the fwmark used for encapsulated packets
+    #[cfg(any(target_os = "linux", doc))]
+    #[cfg_attr(docsrs, doc(cfg(target_os = "linux")))]
+    pub fn set_fwmark(&self, fwmark: u32) -> Result {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt.set_fwmark(fwmark).await)).await?
+        })
+    }
+
+    /// [Android only] Restrict external sockets to networks satisfying the constraints
+    ///
+    /// Constraints are best effort, sockets fall back to the default network when
+    /// no network satisfies them. Fails with `Error::NetworkBindingUnavailable` if the device was
+    /// created without a network binding callback, as the constraints would have no effect.
+    #[cfg(any(target_os = "android", doc))]
+    #[cfg_attr(docsrs, doc(cfg(target_os = "android")))]
+    pub fn set_network_constraints(&self, constraints: NetworkConstraints) -> Result {
+        if self.bind_network.is_none() {
+            return Err(Error::NetworkBindingUnavailable);
+        }
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| {
+                Ok(rt.set_network_constraints(constraints).await)
+            })
+            .await?
+        })
+    }
+
+    #[cfg(not(windows))]
+    async fn protect_from_vpn(&self, adapter: &impl WireGuard) -> Result {
+        if let Some(protect) = self.protect.as_ref() {
+            if let Some(fd) = adapter.get_wg_socket(false).await? {
+                protect(fd);
+            }
+            if let Some(fd) = adapter.get_wg_socket(true).await? {
+                protect(fd);
+            }
+        }
+        Ok(())
+    }
+
+    /// Configure meshnet
+    ///
+    /// This method sets the desired meshnet configuration
+    pub fn set_config(&self, config: &Option<Config>) -> Result {
+        let config = config.clone();
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(Box::pin(
+                rt.set_config(&config)
+            )
+            .await))
+            .await?
+        })
+    }
+
+    /// Notify device about network change event
+    ///
+    /// In some cases integrators may have better knowledge of the network state or state changes,
+    /// therefore this method assumes, that a device has migrated from one network to the other,
+    /// and adapts whatever is needed
+    pub fn notify_network_change(&self) -> Result {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| {
+                Ok(rt.notify_network_change().await)
+            })
+            .await?
+        })
+    }
+
+    /// Search for direct path endpoints of this device again and ping the peers with them
+    ///
+    /// Unlike `notify_network_change`, the relay connection is kept. Known STUN endpoints are
+    /// forgotten, so the peers are pinged even if the endpoints did not change.
+    pub fn trigger_network_redetection(&self) -> Result {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| {
+                Ok(rt.trigger_network_redetection().await)
+            })
+            .await?
+        })
+    }
+
+    /// Connect to exit node
+    ///
+    /// Exit node in this case may be the VPN server or another meshnet node. In the former case,
+    /// new node is created and WireGuard tunnel is established to that node. In the latter case
+    /// the specified (matched by public key) meshnet node is "promoted" to be the exit node
+    pub fn connect_exit_node(&self, node: &ExitNode) -> Result {
+        self.art()?.block_on(async {
+            let node = node.clone();
+            let _wireguard_interface: Arc<DynamicWg> = task_exec!(self.rt()?, async move |rt| {
+                rt.connect_exit_node(&node).await?;
+                Ok(rt.entities.wireguard_interface.clone())
+            })
+            .await?;
+
+            // TODO: delete this as sockets are protected from within boringtun itself
+            #[cfg(not(windows))]
+            self.protect_from_vpn(&*_wireguard_interface).await?;
+
+            Ok(())
+        })
+    }
+
+    /// Connect to exit node, connecting it again until a handshake with it succeeds
+    ///
+    /// Returns after the first attempt, the next attempts are made in the background.
+    pub fn connect_exit_node_with_retry(
+        &self,
+        node: &ExitNode,
+        policy: ExitNodeRetryPolicy,
+    ) -> Result {
+        if !policy.is_valid() {
+            return Err(Error::InvalidRetryPolicy);
+        }
+        self.art()?.block_on(async {
+            let node = node.clone();
+            let _wireguard_interface: Arc<DynamicWg> = task_exec!(self.rt()?, async move |rt| {
+                rt.connect_exit_node_with_retry(&node, policy).await?;
+                Ok(rt.entities.wireguard_interface.clone())
+            })
+            .await?;
+
+            #[cfg(not(windows))]
+            self.protect_from_vpn(&*_wireguard_interface).await?;
+
+            Ok(())
+        })
+    }
+
+    /// Switch between the exit nodes automatically, `nodes` are in priority order
+    ///
+    /// If the connected exit node is not in the list, the first node is connected. When nothing
+    /// is received from the connected node for longer than the failover timeout, the next node
+    /// is connected.
+    pub fn enable_exit_node_failover(&self, nodes: Vec<FailoverExitNode>) -> Result {
+        if nodes.is_empty() {
+            return Err(Error::EmptyFailoverList);
+        }
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .enable_exit_node_failover(nodes)
+                .await))
+            .await?
+        })
+    }
+
+    /// Stop switching exit nodes, the connected exit node is kept
+    pub fn disable_exit_node_failover(&self) -> Result {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .disable_exit_node_failover()
+                .await))
+            .await?
+        })
+    }
+
+    /// Disconnect from exit node
+    ///
+    /// Exit nodes the device is currently connected to
+    ///
+    /// Allowed ips are reported as applied to the WireGuard peer, i.e. with the defaults filled
+    /// in when the node was connected without them.
+    pub fn get_connected_exit_nodes(&self) -> Result<Vec<ExitNode>> {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .get_connected_exit_nodes()
+                .await))
+            .await?
+        })
+    }
+
+    /// Undoes the effects of calling device::connect_exit_node(), matching the node by public key
+    pub fn disconnect_exit_node(&self, node_key: &PublicKey) -> Result {
+        self.art()?.block_on(async {
+            let node_key = *node_key;
+            task_exec!(self.rt()?, async move |rt| {
+                Ok(rt.disconnect_exit_node(&node_key).await)
+            })
+            .await?
+            .map_err(Error::from)
+        })
+    }
+
+    /// Disconnects from any VPN and/or demotes any meshnet node to be a regular meshnet node
+    /// instead of exit node
+    pub fn disconnect_exit_nodes(&self) -> Result {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| {
+                Ok(rt.disconnect_exit_nodes().await)
+            })
+            .await?
+            .map_err(Error::from)
+        })
+    }
+
+    fn rt(&self) -> Result<&Task<Runtime>> {
+        self.rt.as_ref().ok_or(Error::NotStarted)
+    }
+
+    fn art(&self) -> Result<&Arc<AsyncRuntime>> {
+        self.art.as_ref().ok_or(Error::NotStarted)
+    }
+
+    /// Enables DNS server
+    ///
+    /// DNS server hosted on a virtual host within device can be used to resolve the domain names
+    /// of the meshnet nodes. If the DNS query sent to this server does not fall under .nord
+    /// top-level-domain, the query is forwarded to one of the `upstream_servers`.
+    pub fn enable_magic_dns(&self, upstream_servers: &[IpAddr]) -> Result {
+        self.art()?.block_on(async {
+            let upstream_servers = upstream_servers.to_vec();
+            task_exec!(self.rt()?, async move |rt| {
+                Ok(rt.start_dns(&upstream_servers).await)
+            })
+            .await?
+        })
+    }
+
+    /// Replace upstream servers of the running DNS server
+    ///
+    /// Queries keep being answered while the servers are swapped. Fails with
+    /// `Error::DnsNotEnabled` if magic DNS is not enabled.
+    pub fn notify_dns_servers_changed(&self, upstream_servers: &[IpAddr]) -> Result {
+        self.art()?.block_on(async {
+            let upstream_servers = upstream_servers.to_vec();
+            task_exec!(self.rt()?, async move |rt| {
+                Ok(rt.notify_dns_servers_changed(&upstream_servers).await)
+            })
+            .await?
+        })
+    }
+
+    /// Ask `fallback` servers when the upstream servers of magic DNS fail or do not answer
+    ///
+    /// Only names outside of the meshnet are sent to the fallback servers. The setting is
+    /// remembered if magic DNS is not enabled yet, `None` disables the fallback.
+    pub fn set_dns_fallback_servers(&self, fallback: Option<DnsFallbackConfig>) -> Result {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| {
+                Ok(rt.set_dns_fallback_servers(fallback).await)
+            })
+            .await?
+        })
+    }
+
+    /// Clamp TTLs of the records resolved by magic DNS, zero leaves the bound unset
+    ///
+    /// Applies to both the forward resolver cache and the answers. The setting is remembered
+    /// if magic DNS is not enabled yet.
+    pub fn set_dns_ttl_override(&self, ttl_override: DnsTtlOverride) -> Result {
+        if ttl_override.min_ttl_secs > 0
+            && ttl_override.max_ttl_secs > 0
+            && ttl_override.min_ttl_secs > ttl_override.max_ttl_secs
+        {
+            return Err(Error::InvalidDnsTtlOverride);
+        }
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| {
+                Ok(rt.set_dns_ttl_override(ttl_override).await)
+            })
+            .await?
+        })
+    }
+
+    /// Resolve names outside of the meshnet with the DNS over HTTPS server at `url`
+    ///
+    /// The server replaces the upstream servers of magic DNS and is reached through the proxy
+    /// from `set_proxy_settings`, if any. The setting is remembered if magic DNS is not enabled
+    /// yet.
+    pub fn enable_dns_over_https(&self, url: &str) -> Result {
+        let doh = DohServer::from_url(url).map_err(Error::InvalidDnsOverHttpsUrl)?;
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| {
+                Ok(rt.set_dns_over_https(Some(doh)).await)
+            })
+            .await?
+        })
+    }
+
+    /// Resolve names outside of the meshnet with the upstream servers over UDP again
+    pub fn disable_dns_over_https(&self) -> Result {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| {
+                Ok(rt.set_dns_over_https(None).await)
+            })
+            .await?
+        })
+    }
+
+    /// Disables DNS server
+    ///
+    /// Undoes the effects of `device::enable_magic_dns()` call
+    pub fn disable_magic_dns(&self) -> Result {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt.stop_dns().await)).await?
+        })
+    }
+
+    /// Resolve `name` through magic DNS, without routing system DNS through the tunnel
+    ///
+    /// Queries for names outside of the meshnet are forwarded to the upstream servers given to
+    /// `enable_magic_dns`. Fails with `Error::DnsQueryTimeout` if there is no answer in time.
+    pub fn proxy_dns_query(&self, name: &str, record_type: &str) -> Result<DnsQueryResponse> {
+        let timeout = self
+            .features
+            .proxy_dns_query_timeout_ms
+            .map_or(DEFAULT_PROXY_DNS_QUERY_TIMEOUT, Duration::from_millis);
+        let name = name.to_owned();
+        let record_type = record_type.to_owned();
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| {
+                Ok(rt.proxy_dns_query(&name, &record_type, timeout).await)
+            })
+            .await?
+        })
+    }
+
+    /// Resolve A and AAAA records of `hostname` through magic DNS
+    ///
+    /// Every query is limited by `proxy_dns_query_timeout_ms` feature, just like
+    /// `proxy_dns_query`. Empty if the name has no records.
+    pub fn resolve_mesh_hostname(&self, hostname: &str) -> Result<Vec<IpAddr>> {
+        let timeout = self
+            .features
+            .proxy_dns_query_timeout_ms
+            .map_or(DEFAULT_PROXY_DNS_QUERY_TIMEOUT, Duration::from_millis);
+        let hostname = hostname.to_owned();
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| {
+                Ok(rt.resolve_mesh_hostname(&hostname, timeout).await)
+            })
+            .await?
+        })
+    }
+
+    /// Answer magic DNS queries for `name` with `addresses`, ahead of meshnet records and
+    /// forwarding
+    ///
+    /// Magic DNS has to be enabled, overrides are dropped when it is disabled.
+    pub fn dns_override(&self, name: &str, addresses: Vec<IpAddr>, ttl: u32) -> Result {
+        let name = name.to_owned();
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| {
+                Ok(rt.dns_override(&name, &addresses, ttl).await)
+            })
+            .await?
+        })
+    }
+
+    /// Remove override added with `dns_override`
+    pub fn dns_remove_override(&self, name: &str) -> Result {
+        let name = name.to_owned();
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| {
+                Ok(rt.dns_remove_override(&name).await)
+            })
+            .await?
+        })
+    }
+
+    /// A artificial method causing panics
+    ///
+    /// Used only for testing purposes
+    pub fn _panic(&self) -> Result {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt._panic().await)).await?
+        })
+    }
+
+    /// Retrieves a reference to SocketPool. Use this instead of SocketPool::default() when possible
+    pub fn get_socket_pool(&self) -> Result<Arc<SocketPool>> {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt.get_socket_pool().await)).await?
+        })
+    }
+
+    pub fn get_nat(&self, skt: SocketAddr) -> Result<NatData> {
+        match self.art()?.block_on(retrieve_single_nat(skt)) {
+            Ok(data) => Ok(data),
+            Err(no_data) => Err(Error::FailedNatInfoRecover(no_data)),
+        }
+    }
+
+    pub fn trigger_analytics_event(&self) -> Result<()> {
+        self.art()?.block_on(async {
+            task_exec!(self.rt()?, async move |rt| Ok(rt
+                .trigger_analytics_event()
+                .await))
+            .await?
+        })
+    }
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        if self.features.lana.is_some() {
+            let _ = telio_lana::deinit_lana();
+        }
+    }
+}
+
+impl RequestedState {
+    // A Convenience function to build a DNS records list from the requested meshnet config
+    // This function does not take into account whether DNS is enabled or not. It simply builds a
+    // list of hostname<->IP pairs out of currently requested meshnet nodes. If meshnet is disabled,
+    // empty list is returned.
+    pub fn collect_dns_records(&self) -> Records {
+        self.meshnet_config
+            .clone()
+            .map_or(Vec::new(), |cfg| {
+                [
+                    cfg.peers.map_or(Vec::new(), |peers| peers),
+                    vec![Peer {
+                        base: cfg.this,
+                        ..Default::default()
+                    }],
+                ]
+                .concat()
+            })
+            .iter()
+            .filter_map(|v| {
+                v.ip_addresses
+                    .as_ref()
+                    .map(|ips| (v.hostname.0.to_owned().to_string(), ips.clone()))
+            })
+            .collect()
+    }
+
+    // Same as collect_dns_records() fn but for peers with defined nicknames.
+    pub fn collect_dns_nickname_records(&self) -> Records {
+        let to_record = |p: &PeerBase| {
+            p.nickname
+                .as_ref()
+                .cloned()
+                .and_then(|nick| p.ip_addresses.as_ref().cloned().map(|ips| (nick, ips)))
+        };
+
+        self.meshnet_config
+            .iter()
+            .flat_map(|cfg| {
+                to_record(&cfg.this).into_iter().chain(
+                    cfg.peers
+                        .iter()
+                        .flat_map(|peers| peers.iter())
+                        .filter_map(|p| to_record(p)),
+                )
+            })
+            .filter(|(nick, _)| validate_nickname(nick))
+            .fold(Records::new(), |mut records, (mut nick, ip)| {
+                nick += ".nord";
+
+                if let Entry::Vacant(e) = records.entry(nick.to_owned()) {
+                    e.insert(ip);
+                } else {
+                    telio_log_warn!("Nickname is already assigned: {nick:?}. Ignore");
+                }
+
+                records
+            })
+    }
+}
+
+macro_rules! stop_entity {
+    ($entity: expr, $name: expr) => {{
+        if let Ok(task) = $entity
+            .map(|m| m.stop())
+            .map_err(|_| {
+                telio_log_warn!(
+                    "Oops, smething went wrong! Something is holding a strong reference to the {:?} instance.",
+                    $name
+                );
+            }) {
+            task.await;
+        } else {
+            telio_log_warn!("Oops, something went wrong while stopping {:?} tasks.", $name);
+        }
+    }}
+}
+macro_rules! stop_arc_entity {
+    ($entity: expr, $name: expr) => {{
+        stop_entity!(Arc::try_unwrap($entity), $name)
+    }};
+}
+
+impl MeshnetEntites {
+    async fn stop(mut self) {
+        if let Some(direct) = self.direct.take() {
+            direct.stop().await;
+        }
+
+        stop_arc_entity!(self.multiplexer, "Multiplexer");
+        stop_arc_entity!(self.derp, "Derp");
+        stop_arc_entity!(self.proxy, "UdpProxy");
+    }
+}
+
+impl DirectEntities {
+    async fn stop(self) {
+        // Arc dependency on endpoint providers
+        stop_arc_entity!(self.cross_ping_check, "CrossPingCheck");
+        drop(self.endpoint_providers);
+
+        // Arc dependency on wireguard
+        if let Some(local) = self.local_interfaces_endpoint_provider {
+            stop_arc_entity!(local, "LocalInterfacesEndpointProvider");
+        }
+        if let Some(stun) = self.stun_endpoint_provider {
+            stop_arc_entity!(stun, "StunEndpointProvider");
+        }
+        if let Some(upnp) = self.upnp_endpoint_provider {
+            stop_arc_entity!(upnp, "UpnpEndpointProvider");
+        }
+
+        stop_arc_entity!(self.session_keeper, "SessionKeeper");
+        stop_arc_entity!(self.upgrade_sync, "UpgradeSync");
+    }
+}
+
+impl Runtime {
+    // The network binding callback is used only on Android
+    #[cfg_attr(not(target_os = "android"), allow(unused_variables))]
+    async fn start(
+        libtelio_wide_event_publisher: Tx<Box<Event>>,
+        config: &DeviceConfig,
+        features: Features,
+        protect: Option<Protect>,
+        bind_network: Option<BindNetwork>,
+        custom_message_handler: Arc<parking_lot::RwLock<Option<CustomMessageHandler>>>,
+        link_state_provider: Arc<parking_lot::RwLock<Option<LinkStateProvider>>>,
+        adapter_error: AdapterErrorBuffer,
+    ) -> Result<Self> {
+        let firewall = Arc::new(StatefullFirewall::new(
+            features.ipv6,
+            features.boringtun_reset_connections.0,
+        ));
+
+        let shaper = Arc::new(TrafficShaper::new());
+        #[cfg(feature = "traffic_logging")]
+        let traffic_log = Arc::new(TrafficLog::default());
+        #[cfg(feature = "packet_stats")]
+        let packet_stats = Arc::new(PacketStatsHook::default());
+
+        let firewall_filter_inbound_packets = {
+            let fw = firewall.clone();
+            let shaper = shaper.clone();
+            #[cfg(feature = "traffic_logging")]
+            let traffic_log = traffic_log.clone();
+            #[cfg(feature = "packet_stats")]
+            let packet_stats = packet_stats.clone();
+            move |peer: &[u8; 32], packet: &[u8]| {
+                let accepted = fw.process_inbound_packet(peer, packet)
+                    && shaper.process_inbound_packet(peer, packet);
+                #[cfg(feature = "traffic_logging")]
+                if accepted {
+                    traffic_log.record(TrafficDirection::Inbound, peer, packet);
+                }
+                #[cfg(feature = "packet_stats")]
+                if accepted {
+                    packet_stats.record(PacketDirection::Inbound, peer, packet);
+                }
+                accepted
+            }
+        };
+        let firewall_filter_outbound_packets = {
+            let fw = firewall.clone();
+            let shaper = shaper.clone();
+            #[cfg(feature = "traffic_logging")]
+            let traffic_log = traffic_log.clone();
+            #[cfg(feature = "packet_stats")]
+            let packet_stats = packet_stats.clone();
+            // Shaper goes first, so dropped packets do not open pinholes
+            move |peer: &[u8; 32], packet: &[u8]| {
+                let accepted = shaper.process_outbound_packet(peer, packet)
+                    && fw.process_outbound_packet(peer, packet);
+                #[cfg(feature = "traffic_logging")]
+                if accepted {
+                    traffic_log.record(TrafficDirection::Outbound, peer, packet);
+                }
+                #[cfg(feature = "packet_stats")]
+                if accepted {
+                    packet_stats.record(PacketDirection::Outbound, peer, packet);
+                }
+                accepted
+            }
+        };
+        let firewall_reset_connections = if features.boringtun_reset_connections.0 {
+            let fw = firewall.clone();
+            let cb = move |exit_pubkey: &PublicKey,
+                           exit_ipv4: Ipv4Addr,
+                           sink4: &mut dyn io::Write,
+                           sink6: &mut dyn io::Write| {
+                if let Err(err) = fw.reset_connections(exit_pubkey, exit_ipv4, sink4, sink6) {
+                    telio_log_warn!("Failed to reset all connections: {err:?}");
+                }
+            };
+            Some(Arc::new(cb) as Arc<_>)
+        } else {
+            None
+        };
+
+        let socket_pool = Arc::new({
+            if let Some(protect) = protect.clone() {
+                #[cfg(target_os = "android")]
+                let protect = NativeProtector::with_protect(protect, bind_network);
+                SocketPool::new(protect)
+            } else {
+                SocketPool::new(NativeProtector::new(
+                    #[cfg(target_os = "macos")]
+                    features.is_test_env.unwrap_or(false),
+                )?)
+            }
+        });
+
+        // Mark the sockets before the adapter opens any of them
+        #[cfg(target_os = "linux")]
+        if let Some(fwmark) = config.fwmark {
+            socket_pool.set_fwmark(fwmark);
+        }
+
+        let derp_events = McChan::default();
+
+        let (config_update_ch, collection_trigger_ch) = if features.nurse.is_some() {
+            (
+                Some(McChan::<Box<MeshConfigUpdateEvent>>::default().tx),
+                Some(McChan::<Box<()>>::default().tx),
+            )
+        } else {
+            (None, None)
+        };
+
+        // tests runtime use wg::MockedAdapter
+        cfg_if! {
             if #[cfg(not(test))] {
                 let analytics_ch = match features.nurse.is_some() {
                     true => Some(McChan::default().tx),
@@ -907,393 +2505,1867 @@ impl Runtime {
                             firewall_filter_outbound_packets,
                         )),
                         firewall_reset_connections,
+                        custom_adapter: config.custom_adapter.clone(),
+                        error_buffer: adapter_error.clone(),
                     },
                     NoLinkDetection::from(features.no_link_detection),
                 )?);
                 let wg_events = wg_events.rx;
             } else {
-                let wg::tests::Env {
-                        event: wg_events,
-                        analytics: analytics_ch,
-                        wg: wireguard_interface,
-                        adapter,
-                } = wg::tests::setup(
-                        wg::Config {
-                            adapter: config.adapter,
-                            name: config.name.clone(),
-                            tun: config.tun,
-                            socket_pool: socket_pool.clone(),
-                            firewall_process_inbound_callback: Some(Arc::new(firewall_filter_inbound_packets)),
-                            firewall_process_outbound_callback: Some(Arc::new(
-                                firewall_filter_outbound_packets,
-                            )),
-                            firewall_reset_connections,
+                let wg::tests::Env {
+                        event: wg_events,
+                        analytics: analytics_ch,
+                        wg: wireguard_interface,
+                        adapter,
+                } = wg::tests::setup(
+                        wg::Config {
+                            adapter: config.adapter,
+                            name: config.name.clone(),
+                            tun: config.tun,
+                            socket_pool: socket_pool.clone(),
+                            firewall_process_inbound_callback: Some(Arc::new(firewall_filter_inbound_packets)),
+                            firewall_process_outbound_callback: Some(Arc::new(
+                                firewall_filter_outbound_packets,
+                            )),
+                            firewall_reset_connections,
+                            custom_adapter: config.custom_adapter.clone(),
+                            error_buffer: adapter_error.clone(),
+                        }
+                    ).await;
+
+                    adapter.expect_send_uapi_cmd_generic_call(1).await;
+            }
+        }
+
+        wireguard_interface
+            .set_secret_key(config.private_key)
+            .await?;
+
+        if let Some(listen_port) = config.listen_port {
+            wireguard_interface.set_listen_port(listen_port).await?;
+        }
+
+        // No peers are configured yet, so nothing was sent without the mark
+        #[cfg(target_os = "linux")]
+        if let Some(fwmark) = config.fwmark {
+            wireguard_interface.set_fwmark(fwmark).await?;
+        }
+
+        #[cfg(test)]
+        adapter.lock().await.checkpoint();
+
+        let nurse = if telio_lana::is_lana_initialized() {
+            if let Some(nurse_features) = &features.nurse {
+                let nurse_io = NurseIo {
+                    wg_event_channel: &libtelio_wide_event_publisher,
+                    wg_analytics_channel: analytics_ch.clone(),
+                    config_update_channel: config_update_ch.clone(),
+                    collection_trigger_channel: collection_trigger_ch.clone(),
+                };
+
+                Some(Arc::new(
+                    Nurse::start_with(
+                        config.private_key.public(),
+                        NurseConfig::new(nurse_features),
+                        nurse_io,
+                    )
+                    .await,
+                ))
+            } else {
+                telio_log_debug!("nurse not configured");
+                None
+            }
+        } else {
+            telio_log_debug!("lana not initialized");
+            None
+        };
+
+        #[cfg(windows)]
+        {
+            let adapter_luid = wireguard_interface.get_adapter_luid().await?;
+            socket_pool.set_tunnel_interface(adapter_luid);
+        }
+        #[cfg(any(target_os = "macos", target_os = "ios", target_os = "tvos"))]
+        set_tunnel_interface(&socket_pool, config);
+
+        let requested_state = RequestedState {
+            device_config: config.clone(),
+            keepalive_periods: features.wireguard.persistent_keepalive.clone(),
+            ip_conflict_policy: features.ip_conflict_resolution,
+            ..Default::default()
+        };
+
+        let dns = Arc::new(Mutex::new(DNS {
+            resolver: None,
+            #[cfg(unix)]
+            virtual_host_tun_fd: config.tun,
+            #[cfg(windows)]
+            virtual_host_tun_fd: None,
+        }));
+
+        let wg_endpoint_publish_events = Chan::default();
+        let wg_upgrade_sync = Chan::default();
+        let stun_server_events = Chan::default();
+
+        // First rotation happens after a full interval, until then peers use no pre-shared keys
+        let psk_rotation_interval = features
+            .wireguard
+            .psk_rotation_interval_secs
+            .filter(|secs| *secs > 0)
+            .map(|secs| {
+                let period = Duration::from_secs(secs);
+                interval_at(tokio::time::Instant::now() + period, period)
+            });
+
+        Ok(Runtime {
+            features,
+            requested_state,
+            entities: Entities {
+                wireguard_interface: wireguard_interface.clone(),
+                dns,
+                firewall,
+                shaper,
+                #[cfg(feature = "traffic_logging")]
+                traffic_log,
+                #[cfg(feature = "packet_stats")]
+                packet_stats,
+                meshnet: None,
+                socket_pool,
+                nurse,
+            },
+            event_listeners: EventListeners {
+                wg_endpoint_publish_event_subscriber: wg_endpoint_publish_events.rx,
+                wg_event_subscriber: wg_events,
+                derp_event_subscriber: derp_events.rx,
+                endpoint_upgrade_event_subscriber: wg_upgrade_sync.rx,
+                stun_server_subscriber: stun_server_events.rx,
+            },
+            event_publishers: EventPublishers {
+                libtelio_event_publisher: libtelio_wide_event_publisher,
+                nurse_config_update_publisher: config_update_ch,
+                nurse_collection_trigger_publisher: collection_trigger_ch,
+                wg_endpoint_publish_event_publisher: wg_endpoint_publish_events.tx,
+                endpoint_upgrade_event_subscriber: wg_upgrade_sync.tx,
+                stun_server_publisher: stun_server_events.tx,
+                derp_events_publisher: derp_events.tx,
+            },
+            polling_interval: interval_at(tokio::time::Instant::now(), Duration::from_secs(5)),
+            custom_message_handler,
+            link_state_provider,
+            link_state: None,
+            psk_rotation_interval,
+            psk_rotation: PskRotation::default(),
+            peer_capabilities: HashMap::new(),
+            peer_versions: HashMap::new(),
+            auth_challenges: HashMap::new(),
+            exit_node_failover: None,
+            exit_node_retry: None,
+            #[cfg(feature = "audit_log")]
+            audit_log: None,
+            #[cfg(feature = "packet_trace")]
+            packet_trace: None,
+            #[cfg(test)]
+            test_env: wg::tests::Env {
+                analytics: analytics_ch,
+                event: Chan::default().rx,
+                wg: wireguard_interface,
+                adapter,
+            },
+        })
+    }
+
+    async fn start_meshnet_entities(&mut self) -> Result<MeshnetEntites> {
+        // Start multiplexer
+        //
+        let (multiplexer_derp_chan, derp_multiplexer_chan) = Chan::pipe();
+        let multiplexer = Arc::new(Multiplexer::start(multiplexer_derp_chan));
+
+        // Start UDP proxy
+        let proxy = Arc::new(UdpProxy::start(ProxyIo {
+            relay: multiplexer.get_channel().await?,
+        }));
+
+        let application = multiplexer.get_channel().await?;
+        let broadcast = multiplexer.get_channel().await?;
+        let psk_rotate = multiplexer.get_channel().await?;
+        let wake = multiplexer.get_channel().await?;
+        let capability = multiplexer.get_channel().await?;
+        let auth_challenge = multiplexer.get_channel().await?;
+
+        // Start Derp client
+        let derp = Arc::new(DerpRelay::start_with(
+            derp_multiplexer_chan,
+            self.entities.socket_pool.clone(),
+            self.event_publishers.derp_events_publisher.clone(),
+        ));
+        #[cfg(feature = "packet_trace")]
+        if let Some((max_bytes_per_packet, max_packets)) = self.packet_trace {
+            derp.enable_packet_trace(max_bytes_per_packet, max_packets)
+                .await;
+        }
+
+        if let Some(nurse) = self.entities.nurse.as_ref() {
+            nurse
+                .configure_meshnet(Some(NurseMeshnetEntities {
+                    derp: derp.clone(),
+                    multiplexer: multiplexer.clone(),
+                    derp_event_channel: self.event_publishers.derp_events_publisher.clone(),
+                }))
+                .await;
+        }
+
+        let direct = self.start_direct_entities(&multiplexer).await?;
+
+        Ok(MeshnetEntites {
+            multiplexer,
+            derp,
+            proxy,
+            application,
+            broadcast,
+            psk_rotate,
+            wake,
+            capability,
+            auth_challenge,
+            direct,
+        })
+    }
+
+    async fn start_direct_entities(
+        &self,
+        multiplexer: &Multiplexer,
+    ) -> Result<Option<DirectEntities>> {
+        // Start Direct entities if "direct" feature is on and direct path is not disabled
+        let direct = match &self.features.direct {
+            Some(direct) if !self.requested_state.direct_path_disabled => direct,
+            _ => return Ok(None),
+        };
+
+        let endpoint_publish_events = Chan::default();
+        let pong_rxed_events = Chan::default();
+
+        // Create endpoint providers
+        let has_provider = |provider| {
+            // Default is all providers
+            match direct.providers.as_ref().map(|p| p.contains(&provider)) {
+                Some(prov) => prov,
+                None => provider != Upnp,
+            }
+        };
+
+        use telio_model::api_config::EndpointProvider::*;
+
+        let ping_pong_tracker = Arc::new(Mutex::new(PingPongHandler::new(
+            self.requested_state.device_config.private_key,
+        )));
+        let mut endpoint_providers: Vec<Arc<dyn EndpointProvider>> = Vec::new();
+
+        // Create Local Interface Endpoint Provider
+        let local_interfaces_endpoint_provider = if has_provider(Local) {
+            let ep = Arc::new(LocalInterfacesEndpointProvider::new(
+                self.entities
+                    .socket_pool
+                    .new_external_udp((Ipv4Addr::UNSPECIFIED, 0), None)
+                    .await?,
+                self.entities.wireguard_interface.clone(),
+                Duration::from_secs(
+                    direct
+                        .endpoint_interval_secs
+                        .unwrap_or(DEFAULT_ENDPOINT_POLL_INTERVAL_SECS),
+                ),
+                ping_pong_tracker.clone(),
+            ));
+            endpoint_providers.push(ep.clone());
+            Some(ep)
+        } else {
+            None
+        };
+
+        // Create Stun Endpoint Provider
+        let stun_endpoint_provider = if has_provider(Stun) {
+            let ep = Arc::new(StunEndpointProvider::start(
+                self.entities.wireguard_interface.clone(),
+                ExponentialBackoffBounds {
+                    initial: Duration::from_secs(
+                        direct
+                            .endpoint_interval_secs
+                            .unwrap_or(DEFAULT_ENDPOINT_POLL_INTERVAL_SECS),
+                    ),
+                    maximal: Some(Duration::from_secs(120)),
+                },
+                ping_pong_tracker.clone(),
+                self.event_publishers.stun_server_publisher.clone(),
+            )?);
+            endpoint_providers.push(ep.clone());
+            Some(ep)
+        } else {
+            None
+        };
+
+        // Create Upnp Endpoint Provider
+        let upnp_endpoint_provider = if has_provider(Upnp) {
+            let ep = Arc::new(UpnpEndpointProvider::start(
+                self.entities
+                    .socket_pool
+                    .new_external_udp((Ipv4Addr::UNSPECIFIED, 0), None)
+                    .await?,
+                self.entities.wireguard_interface.clone(),
+                ExponentialBackoffBounds {
+                    initial: Duration::from_secs(
+                        direct
+                            .endpoint_interval_secs
+                            .unwrap_or(DEFAULT_ENDPOINT_POLL_INTERVAL_SECS),
+                    ),
+                    maximal: Some(Duration::from_secs(120)),
+                },
+                ping_pong_tracker.clone(),
+            )?);
+            endpoint_providers.push(ep.clone());
+            Some(ep)
+        } else {
+            None
+        };
+
+        // Subscribe to endpoint providers' events
+        for endpoint_provider in &endpoint_providers {
+            endpoint_provider
+                .subscribe_for_endpoint_candidates_change_events(endpoint_publish_events.tx.clone())
+                .await;
+            endpoint_provider
+                .subscribe_for_pong_events(pong_rxed_events.tx.clone())
+                .await;
+        }
+
+        let last_handshake_time_provider: Option<Arc<dyn LastHandshakeTimeProvider>> =
+            if let Some(skip_unresponsive_peers) = &direct.skip_unresponsive_peers {
+                Some(Arc::new(WireGuardLastHandshakeTimeProvider {
+                    wg: self.entities.wireguard_interface.clone(),
+                    threshold: Duration::from_secs(
+                        skip_unresponsive_peers.no_handshake_threshold_secs,
+                    ),
+                }))
+            } else {
+                None
+            };
+        // Create Cross Ping Check
+        let cross_ping_check = Arc::new(CrossPingCheck::start(
+            CpcIo {
+                endpoint_change_subscriber: endpoint_publish_events.rx,
+                pong_rx_subscriber: pong_rxed_events.rx,
+                wg_endpoint_publisher: self
+                    .event_publishers
+                    .wg_endpoint_publish_event_publisher
+                    .clone(),
+                intercoms: multiplexer.get_channel().await?,
+            },
+            endpoint_providers.clone(),
+            last_handshake_time_provider.clone(),
+            Duration::from_secs(2),
+            ping_pong_tracker,
+            Default::default(),
+            direct
+                .direct_path_latency_threshold_ms
+                .map(Duration::from_millis),
+        ));
+
+        // Create WireGuard connection upgrade synchronizer
+        let upgrade_sync = Arc::new(UpgradeSync::new(
+            self.event_publishers
+                .endpoint_upgrade_event_subscriber
+                .clone(),
+            multiplexer.get_channel().await?,
+            Duration::from_secs(5),
+        )?);
+
+        let session_keeper = Arc::new(SessionKeeper::start(self.entities.socket_pool.clone())?);
+
+        Ok(Some(DirectEntities {
+            local_interfaces_endpoint_provider,
+            stun_endpoint_provider,
+            upnp_endpoint_provider,
+            endpoint_providers,
+            cross_ping_check,
+            upgrade_sync,
+            session_keeper,
+        }))
+    }
+
+    #[cfg(feature = "audit_log")]
+    async fn set_audit_log_path(
+        &mut self,
+        path: Option<std::path::PathBuf>,
+        audit_key: &[u8],
+    ) -> Result {
+        self.audit_log = path
+            .map(|path| AuditLog::open(&path, audit_key))
+            .transpose()
+            .map_err(Error::AuditLog)?;
+        Ok(())
+    }
+
+    #[cfg(feature = "audit_log")]
+    fn audit(&mut self, event: AuditEvent, public_key: PublicKey) {
+        if let Some(log) = self.audit_log.as_mut() {
+            if let Err(err) = log.record(event, public_key) {
+                telio_log_warn!("Failed to write audit log: {}", err);
+            }
+        }
+    }
+
+    async fn get_peer_route(&self, ip: IpAddr) -> Result<PeerRoute> {
+        let wgi = self.entities.wireguard_interface.get_interface().await?;
+        let (network, peer) = lookup_route(&wgi, ip).ok_or(Error::NoPeerRoute)?;
+        let path = self
+            .peer_to_node(peer, None, None)
+            .await
+            .map(|node| node.path)
+            .unwrap_or_default();
+        Ok(PeerRoute {
+            ip,
+            peer_pk: peer.public_key,
+            path,
+            is_exit: network.prefix() == 0,
+            interface: self.requested_state.device_config.name.clone(),
+        })
+    }
+
+    async fn get_route_table(&self) -> Result<Vec<ManagedRoute>> {
+        let wgi = self.entities.wireguard_interface.get_interface().await?;
+        let exit_pk = self
+            .requested_state
+            .exit_node
+            .as_ref()
+            .map(|exit_node| exit_node.public_key);
+        Ok(wgi
+            .peers
+            .values()
+            .flat_map(|peer| {
+                // Meshnet address of the meshnet exit node is still a meshnet route
+                let is_exit = Some(peer.public_key) == exit_pk;
+                let is_meshnet_peer = self.find_meshnet_peer(&peer.public_key).is_some();
+                peer.allowed_ips.iter().map(move |prefix| ManagedRoute {
+                    prefix: *prefix,
+                    gateway: None,
+                    interface: self.requested_state.device_config.name.clone(),
+                    metric: None,
+                    source: if is_exit && (prefix.prefix() == 0 || !is_meshnet_peer) {
+                        RouteSource::ExitNode
+                    } else {
+                        RouteSource::Meshnet
+                    },
+                })
+            })
+            .collect())
+    }
+
+    async fn get_peer_allowed_ips(
+        &self,
+        public_key: &PublicKey,
+    ) -> Result<Vec<ipnetwork::IpNetwork>> {
+        let wgi = self.entities.wireguard_interface.get_interface().await?;
+        Ok(wgi
+            .peers
+            .get(public_key)
+            .map(|peer| peer.allowed_ips.clone())
+            .unwrap_or_default())
+    }
+
+    async fn external_nodes(&self) -> Result<Vec<Node>> {
+        let wgi = self.entities.wireguard_interface.get_interface().await?;
+        let mut nodes = Vec::new();
+        for peer in wgi.peers.values() {
+            if let Some(node) = self.peer_to_node(peer, None, None).await {
+                nodes.push(node);
+            }
+        }
+        Ok(nodes)
+    }
+
+    /// Returns memory used by the peer maps and by the relay state
+    #[cfg(feature = "memory_diagnostics")]
+    async fn get_memory_usage(&self) -> Result<(usize, usize)> {
+        let wgi = self.entities.wireguard_interface.get_interface().await?;
+        let peer_map_bytes =
+            memory_stats::peer_map_usage(&wgi, self.requested_state.meshnet_config.as_ref());
+        let relay_state_bytes = match self.entities.meshnet.as_ref() {
+            Some(meshnet) => meshnet.derp.get_memory_usage().await,
+            None => 0,
+        };
+        Ok((peer_map_bytes, relay_state_bytes))
+    }
+
+    async fn get_connected_peers_count(&self) -> Result<usize> {
+        Ok(self.connected_meshnet_peers().await?.len())
+    }
+
+    async fn get_clock_skew(&self) -> Result<Option<i64>> {
+        Ok(match self.entities.meshnet.as_ref() {
+            Some(meshnet) => meshnet.derp.get_clock_skew().await,
+            None => None,
+        })
+    }
+
+    /// Meshnet peers which currently have an active WireGuard session
+    async fn connected_meshnet_peers(&self) -> Result<Vec<PublicKey>> {
+        let meshnet_peers = match self
+            .requested_state
+            .meshnet_config
+            .as_ref()
+            .and_then(|config| config.peers.as_ref())
+        {
+            Some(peers) => peers,
+            None => return Ok(Vec::new()),
+        };
+
+        let wgi = self.entities.wireguard_interface.get_interface().await?;
+        Ok(wgi
+            .peers
+            .values()
+            .filter(|peer| peer.is_connected())
+            .filter(|peer| {
+                meshnet_peers
+                    .iter()
+                    .any(|p| p.public_key == peer.public_key)
+            })
+            .map(|peer| peer.public_key)
+            .collect())
+    }
+
+    async fn health_check(&self) -> Result<HealthReport> {
+        async fn probe(check: impl Future<Output = bool>) -> ComponentHealth {
+            match tokio::time::timeout(HEALTH_CHECK_TIMEOUT, check).await {
+                Ok(true) => ComponentHealth::Ok,
+                _ => ComponentHealth::Degraded,
+            }
+        }
+
+        let wireguard = probe(async {
+            self.entities
+                .wireguard_interface
+                .get_interface()
+                .await
+                .is_ok()
+        })
+        .await;
+
+        let relay = match self.entities.meshnet.as_ref() {
+            Some(meshnet) => probe(meshnet.derp.get_conn_state()).await,
+            None => ComponentHealth::Disabled,
+        };
+
+        let dns = match tokio::time::timeout(HEALTH_CHECK_TIMEOUT, self.entities.dns.lock()).await {
+            Ok(dns) if dns.resolver.is_some() => ComponentHealth::Ok,
+            Ok(_) => ComponentHealth::Disabled,
+            Err(_) => ComponentHealth::Degraded,
+        };
+
+        let endpoint_providers = self.entities.endpoint_providers();
+        let direct_path = if endpoint_providers.is_empty() {
+            ComponentHealth::Disabled
+        } else {
+            probe(async {
+                for ep in endpoint_providers {
+                    if let Some(endpoints) = ep.get_current_endpoints().await {
+                        if !endpoints.is_empty() {
+                            return true;
                         }
-                    ).await;
+                    }
+                }
+                false
+            })
+            .await
+        };
+
+        Ok(HealthReport::from(HealthComponents {
+            wireguard,
+            relay,
+            dns,
+            direct_path,
+        }))
+    }
+
+    async fn get_stun_servers(&self) -> Result<Vec<StunServerStatus>> {
+        let stun = self
+            .entities
+            .meshnet
+            .as_ref()
+            .and_then(|m| m.direct.as_ref())
+            .and_then(|d| d.stun_endpoint_provider.as_ref());
+
+        Ok(match stun {
+            Some(stun) => stun.get_servers().await,
+            None => Vec::new(),
+        })
+    }
+
+    async fn list_interfaces(&self) -> Result<Vec<NetworkInterface>> {
+        let local = self
+            .entities
+            .meshnet
+            .as_ref()
+            .and_then(|m| m.direct.as_ref())
+            .and_then(|d| d.local_interfaces_endpoint_provider.as_ref());
+
+        Ok(match local {
+            Some(local) => local.get_interfaces().await?,
+            None => Vec::new(),
+        })
+    }
+
+    async fn get_relay_latency_history(
+        &self,
+        peer: Option<PublicKey>,
+        n_samples: usize,
+    ) -> Result<Vec<RttSample>> {
+        Ok(match self.entities.meshnet.as_ref() {
+            Some(meshnet) => meshnet.derp.get_latency_history(peer, n_samples).await,
+            None => Vec::new(),
+        })
+    }
+
+    async fn get_relay_server_list(&self) -> Result<Vec<RelayServerStatus>> {
+        Ok(match self.entities.meshnet.as_ref() {
+            Some(meshnet) => meshnet.derp.get_server_list().await,
+            None => Vec::new(),
+        })
+    }
+
+    async fn get_relay_session_id(&self, peer: PublicKey) -> Result<Option<uuid::Uuid>> {
+        Ok(match self.entities.meshnet.as_ref() {
+            Some(meshnet) => meshnet.derp.get_session_id(peer).await,
+            None => None,
+        })
+    }
+
+    async fn send_custom_message(&self, recipient: PublicKey, msg: ApplicationMsg) -> Result {
+        let meshnet = self
+            .entities
+            .meshnet
+            .as_ref()
+            .ok_or(Error::MeshnetNotConfigured)?;
+
+        let is_peer = self
+            .requested_state
+            .meshnet_config
+            .as_ref()
+            .and_then(|config| config.peers.as_ref())
+            .map_or(false, |peers| {
+                peers.iter().any(|peer| peer.public_key == recipient)
+            });
+        if !is_peer {
+            return Err(Error::InvalidNode);
+        }
+
+        meshnet
+            .application
+            .tx
+            .send((recipient, msg))
+            .await
+            .map_err(|_| Error::RelaySendFailed)
+    }
+
+    async fn broadcast_custom_message(&self, msg: BroadcastMsg) -> Result<usize> {
+        let meshnet = self
+            .entities
+            .meshnet
+            .as_ref()
+            .ok_or(Error::MeshnetNotConfigured)?;
+
+        let mut enqueued = 0;
+        for recipient in self.connected_meshnet_peers().await? {
+            match meshnet.broadcast.tx.send((recipient, msg.clone())).await {
+                Ok(()) => enqueued += 1,
+                Err(_) => telio_log_warn!("Failed to enqueue broadcast to {:?}", recipient),
+            }
+        }
+        Ok(enqueued)
+    }
+
+    async fn request_ip_reassignment(&self) -> Result {
+        if self.requested_state.meshnet_config.is_none() {
+            return Err(Error::MeshnetNotConfigured);
+        }
 
-                    adapter.expect_send_uapi_cmd_generic_call(1).await;
+        telio_log_info!("Requesting meshnet IP reassignment");
+        let _ = self
+            .event_publishers
+            .libtelio_event_publisher
+            .send(Box::new(
+                Event::new::<IpReassignmentRequested>().set(IpReassignmentRequested {}),
+            ));
+        Ok(())
+    }
+
+    async fn wake_peer(&self, public_key: PublicKey) -> Result {
+        let meshnet = self
+            .entities
+            .meshnet
+            .as_ref()
+            .ok_or(Error::MeshnetNotConfigured)?;
+
+        let is_peer = self
+            .requested_state
+            .meshnet_config
+            .as_ref()
+            .and_then(|config| config.peers.as_ref())
+            .map_or(false, |peers| {
+                peers.iter().any(|peer| peer.public_key == public_key)
+            });
+        if !is_peer {
+            return Err(Error::InvalidNode);
+        }
+
+        meshnet
+            .wake
+            .tx
+            .send((public_key, WakeMsg))
+            .await
+            .map_err(|_| Error::RelaySendFailed)
+    }
+
+    async fn send_auth_challenge(&mut self, public_key: PublicKey) -> Result {
+        let meshnet = self
+            .entities
+            .meshnet
+            .as_ref()
+            .ok_or(Error::MeshnetNotConfigured)?;
+        self.find_meshnet_peer(&public_key)
+            .ok_or(Error::InvalidNode)?;
+
+        let nonce: [u8; AUTH_NONCE_SIZE] = rand::random();
+        meshnet
+            .auth_challenge
+            .tx
+            .send((public_key, AuthChallengeMsg::challenge(nonce)))
+            .await
+            .map_err(|_| Error::RelaySendFailed)?;
+        // Only the latest challenge is accepted, answers to the previous ones are ignored
+        self.auth_challenges.insert(public_key, nonce);
+        Ok(())
+    }
+
+    async fn handle_auth_challenge_message(&mut self, sender: PublicKey, msg: AuthChallengeMsg) {
+        if self.find_meshnet_peer(&sender).is_none() {
+            telio_log_debug!("Ignoring identity challenge from {:?}", sender);
+            return;
+        }
+
+        let signed = [AUTH_CHALLENGE_CONTEXT, msg.get_nonce()].concat();
+        let peer_signature = match msg.get_signature() {
+            Some(peer_signature) => peer_signature,
+            None => {
+                let signature = signature::sign(
+                    &self.requested_state.device_config.private_key,
+                    &signed,
+                    &mut rand::thread_rng(),
+                );
+                let reply = AuthChallengeMsg::response(*msg.get_nonce(), signature);
+                if let Some(meshnet) = self.entities.meshnet.as_ref() {
+                    if meshnet
+                        .auth_challenge
+                        .tx
+                        .send((sender, reply))
+                        .await
+                        .is_err()
+                    {
+                        telio_log_warn!("Failed to answer identity challenge of {:?}", sender);
+                    }
+                }
+                return;
+            }
+        };
+
+        if self.auth_challenges.get(&sender) != Some(msg.get_nonce()) {
+            telio_log_debug!(
+                "Ignoring unexpected identity challenge answer from {:?}",
+                sender
+            );
+            return;
+        }
+        self.auth_challenges.remove(&sender);
+
+        let verified = signature::verify(&sender, &signed, peer_signature);
+        if verified {
+            telio_log_info!("Identity of {:?} verified", sender);
+        } else {
+            telio_log_warn!("Identity of {:?} failed verification", sender);
+        }
+        let _ = self
+            .event_publishers
+            .libtelio_event_publisher
+            .send(Box::new(Event::new::<AuthChallengeResult>().set(
+                AuthChallengeResult {
+                    peer_pk: sender.to_string(),
+                    verified,
+                },
+            )));
+    }
+
+    async fn send_keepalive(&self, public_key: PublicKey) -> Result {
+        self.find_meshnet_peer(&public_key)
+            .ok_or(Error::InvalidNode)?;
+        Ok(self
+            .entities
+            .wireguard_interface
+            .send_keepalive(public_key)
+            .await?)
+    }
+
+    async fn ban_peer(&self, public_key: PublicKey) -> Result {
+        self.entities.firewall.ban_peer(public_key);
+        Ok(())
+    }
+
+    async fn unban_peer(&self, public_key: PublicKey) -> Result {
+        self.entities.firewall.unban_peer(public_key);
+        Ok(())
+    }
+
+    async fn get_banned_peers(&self) -> Result<Vec<PublicKey>> {
+        let mut banned_peers: Vec<_> = self
+            .entities
+            .firewall
+            .get_banned_peers()
+            .into_iter()
+            .collect();
+        banned_peers.sort();
+        Ok(banned_peers)
+    }
+
+    async fn handle_wake_message(&mut self, sender: PublicKey) {
+        let is_peer = self
+            .requested_state
+            .meshnet_config
+            .as_ref()
+            .and_then(|cfg| cfg.peers.as_ref())
+            .map_or(false, |peers| peers.iter().any(|p| p.public_key == sender));
+        if !is_peer {
+            telio_log_debug!("Ignoring wake request from {:?}", sender);
+            return;
+        }
+
+        telio_log_info!("Wake request from {:?}", sender);
+        let _ = self
+            .event_publishers
+            .libtelio_event_publisher
+            .send(Box::new(Event::new::<PeerWakeRequest>().set(
+                PeerWakeRequest {
+                    from_pk: sender.to_string(),
+                },
+            )));
+
+        // Probe endpoints right away instead of waiting for the next cycle
+        if let Err(err) = self.trigger_network_redetection().await {
+            telio_log_warn!("Failed to redetect endpoints on wake request: {}", err);
+        }
+    }
+
+    fn handle_custom_message(&self, sender: PublicKey, payload: Vec<u8>) {
+        match self.custom_message_handler.read().as_ref() {
+            Some(handler) => handler(sender, payload),
+            None => telio_log_debug!(
+                "Dropping application message from {:?}, no handler registered",
+                sender
+            ),
+        }
+    }
+
+    async fn rotate_psks(&mut self) {
+        let meshnet = match self.entities.meshnet.as_ref() {
+            Some(meshnet) => meshnet,
+            None => return,
+        };
+        let peers: Vec<PublicKey> = self
+            .requested_state
+            .meshnet_config
+            .as_ref()
+            .and_then(|cfg| cfg.peers.as_ref())
+            .map(|peers| peers.iter().map(|p| p.public_key).collect())
+            .unwrap_or_default();
+        self.requested_state
+            .meshnet_psks
+            .retain(|public_key, _| peers.contains(public_key));
+
+        let offers = match self.psk_rotation.rotate(
+            &self.requested_state.device_config.private_key,
+            peers,
+            Instant::now(),
+        ) {
+            Ok(offers) => offers,
+            Err(err) => {
+                telio_log_warn!("Failed to rotate pre-shared keys: {}", err);
+                return;
+            }
+        };
+        for (peer, offer) in offers {
+            if meshnet.psk_rotate.tx.send((peer, offer)).await.is_err() {
+                telio_log_warn!("Failed to offer pre-shared key to {:?}", peer);
+            }
+        }
+    }
+
+    async fn handle_psk_rotate_message(&mut self, sender: PublicKey, msg: PskRotateMsg) {
+        let is_peer = self
+            .requested_state
+            .meshnet_config
+            .as_ref()
+            .and_then(|cfg| cfg.peers.as_ref())
+            .map_or(false, |peers| peers.iter().any(|p| p.public_key == sender));
+        if self.psk_rotation_interval.is_none() || !is_peer {
+            telio_log_debug!("Ignoring pre-shared key message from {:?}", sender);
+            return;
+        }
+
+        let (change, reply) = match self.psk_rotation.handle(
+            &self.requested_state.device_config.private_key,
+            sender,
+            self.requested_state.meshnet_psks.get(&sender).copied(),
+            msg,
+            Instant::now(),
+        ) {
+            Ok(result) => result,
+            Err(err) => {
+                telio_log_warn!("Pre-shared key message from {:?}: {}", sender, err);
+                return;
+            }
+        };
+
+        if let Some(change) = change {
+            self.apply_psk_change(sender, change).await;
+        }
+        self.send_psk_rotate_message(sender, reply).await;
+    }
+
+    /// Send again unanswered pre-shared key messages and finish commits of the applied keys
+    async fn retry_psk_rotation(&mut self) {
+        let handshakes: HashMap<PublicKey, Instant> =
+            match self.entities.wireguard_interface.get_interface().await {
+                Ok(wgi) => {
+                    let now = Instant::now();
+                    wgi.peers
+                        .iter()
+                        .filter_map(|(public_key, peer)| {
+                            let since = peer.time_since_last_handshake?;
+                            Some((*public_key, now.checked_sub(since)?))
+                        })
+                        .collect()
+                }
+                Err(err) => {
+                    telio_log_warn!("Failed to get WireGuard interface: {}", err);
+                    HashMap::new()
+                }
+            };
+
+        let steps = self.psk_rotation.retry(Instant::now(), |peer, applied| {
+            handshakes.get(peer).map_or(false, |at| *at > applied)
+        });
+        for (peer, change, msg) in steps {
+            if let Some(change) = change {
+                self.apply_psk_change(peer, change).await;
+            }
+            self.send_psk_rotate_message(peer, msg).await;
+        }
+    }
+
+    /// Send reset of the pre-shared keys to the peers which did not get one since the start
+    async fn reset_psks(&mut self, peers: impl IntoIterator<Item = PublicKey>) {
+        if self.psk_rotation_interval.is_none() {
+            return;
+        }
+        for (peer, reset) in self.psk_rotation.reset(peers) {
+            self.send_psk_rotate_message(peer, Some(reset)).await;
+        }
+    }
+
+    async fn send_psk_rotate_message(&self, peer: PublicKey, msg: Option<PskRotateMsg>) {
+        if let (Some(msg), Some(meshnet)) = (msg, self.entities.meshnet.as_ref()) {
+            if meshnet.psk_rotate.tx.send((peer, msg)).await.is_err() {
+                telio_log_warn!("Failed to send pre-shared key message to {:?}", peer);
+            }
+        }
+    }
+
+    async fn apply_psk_change(&mut self, peer: PublicKey, change: KeyChange) {
+        match change {
+            KeyChange::Rotated(psk) | KeyChange::Reverted(Some(psk)) => {
+                self.requested_state.meshnet_psks.insert(peer, psk);
+            }
+            KeyChange::Reverted(None) | KeyChange::Reset => {
+                self.requested_state.meshnet_psks.remove(&peer);
+            }
+        }
+        if let Err(err) = wg_controller::consolidate_wg_state(
+            &self.requested_state,
+            &self.entities,
+            &self.features,
+        )
+        .await
+        {
+            telio_log_warn!("WireGuard controller failure: {:?}. Ignoring", err);
+        }
+
+        match change {
+            KeyChange::Rotated(_) => {
+                telio_log_info!("Pre-shared key rotated for {:?}", peer);
+                let _ = self
+                    .event_publishers
+                    .libtelio_event_publisher
+                    .send(Box::new(Event::new::<PskRotated>().set(PskRotated {
+                        peer_pk: peer.to_string(),
+                    })));
+            }
+            KeyChange::Reverted(_) => {
+                telio_log_warn!(
+                    "No handshake with the new pre-shared key of {:?}, previous key restored",
+                    peer
+                );
+            }
+            KeyChange::Reset => {
+                telio_log_info!("Pre-shared key of {:?} reset", peer);
+            }
+        }
+    }
+
+    async fn export_wireguard_config(&self) -> Result<String> {
+        let wgi = self.entities.wireguard_interface.get_interface().await?;
+        Ok(wireguard_config(&wgi))
+    }
+
+    async fn reset_statistics(&self, public_key: Option<PublicKey>) -> Result {
+        let wg = &self.entities.wireguard_interface;
+        if let Some(public_key) = public_key.as_ref() {
+            if !wg.get_interface().await?.peers.contains_key(public_key) {
+                return Err(Error::InvalidNode);
+            }
+        }
+        Ok(wg.reset_statistics(public_key).await?)
+    }
+
+    async fn enable_traffic_shaping(
+        &self,
+        public_key: PublicKey,
+        max_tx_kbps: u32,
+        max_rx_kbps: u32,
+    ) -> Result {
+        let device_config = &self.requested_state.device_config;
+        if device_config.custom_adapter.is_some()
+            || !matches!(device_config.adapter, AdapterType::BoringTun)
+        {
+            return Err(Error::TrafficShapingUnsupported);
+        }
+        self.entities
+            .shaper
+            .set_limits(public_key, max_tx_kbps, max_rx_kbps);
+        Ok(())
+    }
+
+    async fn disable_traffic_shaping(&self, public_key: PublicKey) -> Result {
+        if !self.entities.shaper.remove_limits(&public_key) {
+            return Err(Error::TrafficShapingNotEnabled);
+        }
+        Ok(())
+    }
+
+    async fn get_active_connections(&self, max_entries: usize) -> Result<Vec<ActiveConnection>> {
+        Ok(self.entities.firewall.get_active_connections(max_entries))
+    }
+
+    #[cfg(feature = "traffic_logging")]
+    async fn enable_traffic_logging(&self, config: TrafficLogConfig) -> Result {
+        self.entities.traffic_log.enable(config);
+        Ok(())
+    }
+
+    #[cfg(feature = "traffic_logging")]
+    async fn get_traffic_log(&self) -> Result<Vec<TrafficLogEntry>> {
+        Ok(self.entities.traffic_log.entries())
+    }
+
+    #[cfg(feature = "packet_stats")]
+    async fn set_packet_stats_handler(&self, handler: Option<PacketStatsHandler>) -> Result {
+        self.entities.packet_stats.set(handler);
+        Ok(())
+    }
+
+    #[cfg(feature = "packet_trace")]
+    async fn enable_packet_trace(
+        &mut self,
+        max_bytes_per_packet: usize,
+        max_packets: usize,
+    ) -> Result {
+        self.packet_trace = Some((max_bytes_per_packet, max_packets));
+        if let Some(meshnet) = self.entities.meshnet.as_ref() {
+            meshnet
+                .derp
+                .enable_packet_trace(max_bytes_per_packet, max_packets)
+                .await;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "packet_trace")]
+    async fn get_packet_trace(&self) -> Result<Vec<PacketTraceEntry>> {
+        match self.entities.meshnet.as_ref() {
+            Some(meshnet) => Ok(meshnet.derp.take_packet_trace().await),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    async fn upsert_dns_peers(&self) -> Result {
+        if let Some(dns) = &self.entities.dns.lock().await.resolver {
+            let mut peers: Records = HashMap::new();
+            // Hostnames have priority over nicknames (override if there's any conflict)
+            if self.features.nicknames {
+                peers = self.requested_state.collect_dns_nickname_records();
+            }
+            peers.extend(self.requested_state.collect_dns_records());
+
+            // Insert wildcard for subdomains
+            let wildcarded_peers: Records = peers
+                .iter()
+                .map(|(name, ip)| (format!("*.{}", name), ip.clone()))
+                .collect();
+            peers.extend(wildcarded_peers);
+
+            dns.upsert("nord", &peers)
+                .await
+                .map_err(Error::DnsResolverError)?;
+        }
+
+        Ok(())
+    }
+
+    async fn set_private_key(&mut self, private_key: &SecretKey) -> Result {
+        // TODO: create a global controll state to consolidate all entities
+
+        self.validate_private_key(private_key).await?;
+        self.apply_private_key(private_key).await
+    }
+
+    async fn rotate_secret_key(&mut self, private_key: &SecretKey) -> Result {
+        let old_public_key = self.requested_state.device_config.private_key.public();
+
+        self.validate_private_key(private_key).await?;
+        self.apply_private_key(private_key).await?;
+
+        let new_public_key = private_key.public();
+        if old_public_key != new_public_key {
+            let _ = self
+                .event_publishers
+                .libtelio_event_publisher
+                .send(Box::new(Event::new::<KeyRotated>().set(KeyRotated {
+                    old_public_key: old_public_key.to_string(),
+                    new_public_key: new_public_key.to_string(),
+                })));
+            #[cfg(feature = "audit_log")]
+            self.audit(AuditEvent::KeyRotated, new_public_key);
+        }
+        Ok(())
+    }
+
+    /// Key can't be changed with meshnet on, unless it is still in the meshnet config
+    async fn validate_private_key(&self, private_key: &SecretKey) -> Result {
+        let should_validate_keys = self.features.validate_keys.0;
+        let meshnet_is_on = self.requested_state.meshnet_config.is_some();
+        let key_is_the_different = self.get_private_key().await? != *private_key;
+        if should_validate_keys && meshnet_is_on && key_is_the_different {
+            return Err(Error::BadPublicKey);
+        }
+        Ok(())
+    }
+
+    async fn apply_private_key(&mut self, private_key: &SecretKey) -> Result {
+        if self.entities.dns.lock().await.resolver.is_some() {
+            return Err(Error::DnsNotDisabled);
+        }
+
+        if let Some(m_entities) = self.entities.meshnet.as_ref() {
+            m_entities
+                .derp
+                .configure(m_entities.derp.get_config().await.map(|c| DerpConfig {
+                    secret_key: *private_key,
+                    ..c
+                }))
+                .await;
+        }
+
+        self.requested_state.device_config.private_key = *private_key;
+
+        if let Some(nurse) = &self.entities.nurse {
+            nurse.set_private_key(*private_key).await;
+        }
+
+        wg_controller::consolidate_wg_state(&self.requested_state, &self.entities, &self.features)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_max_relay_connections(&mut self, max: usize) -> Result {
+        self.requested_state.max_relay_connections = Some(max);
+
+        if let Some(m_entities) = self.entities.meshnet.as_ref() {
+            m_entities
+                .derp
+                .configure(m_entities.derp.get_config().await.map(|c| DerpConfig {
+                    max_relay_connections: Some(max),
+                    ..c
+                }))
+                .await;
+        }
+        Ok(())
+    }
+
+    async fn set_keepalive_policy(&mut self, policy: KeepalivePolicy) -> Result {
+        telio_log_info!("Keepalive policy: {:?}", policy);
+        self.requested_state.keepalive_policy = policy;
+
+        wg_controller::consolidate_wg_state(&self.requested_state, &self.entities, &self.features)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_proxy_settings(&mut self, proxy: Option<ProxySettings>) -> Result {
+        telio_log_info!("Relay proxy: {:?}", proxy);
+        self.requested_state.proxy_settings = proxy.clone();
+
+        if self.requested_state.dns_over_https.is_some() {
+            if let Some(resolver) = &self.entities.dns.lock().await.resolver {
+                resolver
+                    .set_dns_over_https(self.dns_over_https_server())
+                    .await
+                    .map_err(Error::DnsResolverError)?;
             }
         }
 
-        wireguard_interface
-            .set_secret_key(config.private_key)
-            .await?;
+        if let Some(m_entities) = self.entities.meshnet.as_ref() {
+            m_entities
+                .derp
+                .configure(
+                    m_entities
+                        .derp
+                        .get_config()
+                        .await
+                        .map(|c| DerpConfig { proxy, ..c }),
+                )
+                .await;
+        }
+        Ok(())
+    }
 
-        #[cfg(test)]
-        adapter.lock().await.checkpoint();
+    async fn set_ip_conflict_resolution_policy(
+        &mut self,
+        policy: Option<IpConflictPolicy>,
+    ) -> Result {
+        telio_log_info!("Ip conflict resolution policy: {:?}", policy);
+        self.requested_state.ip_conflict_policy = policy;
+        Ok(())
+    }
 
-        let nurse = if telio_lana::is_lana_initialized() {
-            if let Some(nurse_features) = &features.nurse {
-                let nurse_io = NurseIo {
-                    wg_event_channel: &libtelio_wide_event_publisher,
-                    wg_analytics_channel: analytics_ch.clone(),
-                    config_update_channel: config_update_ch.clone(),
-                    collection_trigger_channel: collection_trigger_ch.clone(),
-                };
+    async fn set_relay_server_selection_policy(&mut self, policy: RelaySelectionPolicy) -> Result {
+        telio_log_info!("Relay server selection policy: {:?}", policy);
+        self.requested_state.relay_selection_policy = policy;
+
+        if let (Some(m_entities), Some(config)) = (
+            self.entities.meshnet.as_ref(),
+            self.requested_state.meshnet_config.as_ref(),
+        ) {
+            let servers = SortedServers::with_policy(
+                config.derp_servers.clone().unwrap_or_default(),
+                &self.requested_state.relay_selection_policy,
+            );
+            m_entities
+                .derp
+                .configure(
+                    m_entities
+                        .derp
+                        .get_config()
+                        .await
+                        .map(|c| DerpConfig { servers, ..c }),
+                )
+                .await;
+        }
+        Ok(())
+    }
 
-                Some(Arc::new(
-                    Nurse::start_with(
-                        config.private_key.public(),
-                        NurseConfig::new(nurse_features),
-                        nurse_io,
-                    )
-                    .await,
-                ))
-            } else {
-                telio_log_debug!("nurse not configured");
-                None
+    /// Pass the meshnet config to the direct path entities, if they are running
+    async fn configure_direct_entities(&self, config: &Option<Config>) -> Result {
+        let stun_ep = self
+            .entities
+            .meshnet
+            .as_ref()
+            .and_then(|m| m.direct.as_ref())
+            .and_then(|direct| direct.stun_endpoint_provider.as_ref());
+
+        // Refresh the lists of servers for STUN endpoint provider
+        if let (Some(stun_ep), Some(config)) = (stun_ep, config) {
+            let use_ipv6 = self.features.ipv6 && {
+                config
+                    .this
+                    .ip_addresses
+                    .as_ref()
+                    .map(|vec| vec.iter().any(|addr| addr.is_ipv6()))
+                    .unwrap_or(false)
+            };
+
+            stun_ep
+                .configure(
+                    config.derp_servers.clone().unwrap_or_default(),
+                    use_ipv6,
+                    self.get_socket_pool().await?,
+                )
+                .await;
+        }
+
+        if let Some(cpc) = self.entities.cross_ping_check() {
+            cpc.configure(config.clone()).await?;
+        }
+        Ok(())
+    }
+
+    async fn set_direct_path(&mut self, enabled: bool) -> Result {
+        if self.requested_state.direct_path_disabled != enabled {
+            return Ok(());
+        }
+        self.requested_state.direct_path_disabled = !enabled;
+
+        if enabled {
+            telio_log_info!("Direct path enabled");
+            let multiplexer = self
+                .entities
+                .meshnet
+                .as_ref()
+                .map(|m| m.multiplexer.clone());
+            if let Some(multiplexer) = multiplexer {
+                let direct = self.start_direct_entities(&multiplexer).await?;
+                if let Some(meshnet) = self.entities.meshnet.as_mut() {
+                    meshnet.direct = direct;
+                }
+                self.configure_direct_entities(&self.requested_state.meshnet_config)
+                    .await?;
             }
         } else {
-            telio_log_debug!("lana not initialized");
-            None
-        };
+            telio_log_warn!("Direct path disabled, meshnet traffic will go through the relay only");
+            let direct = self.entities.meshnet.as_mut().and_then(|m| m.direct.take());
+            if let Some(direct) = direct {
+                direct.stop().await;
+            }
+        }
 
-        #[cfg(windows)]
-        {
-            let adapter_luid = wireguard_interface.get_adapter_luid().await?;
-            socket_pool.set_tunnel_interface(adapter_luid);
+        wg_controller::consolidate_wg_state(&self.requested_state, &self.entities, &self.features)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_peer_nickname(
+        &mut self,
+        public_key: PublicKey,
+        nickname: Option<String>,
+    ) -> Result {
+        self.find_meshnet_peer(&public_key)
+            .ok_or(Error::InvalidNode)?;
+        if !nickname.as_deref().map_or(true, validate_nickname) {
+            return Err(Error::InvalidNickname);
         }
-        #[cfg(any(target_os = "macos", target_os = "ios", target_os = "tvos"))]
-        set_tunnel_interface(&socket_pool, config);
 
-        let requested_state = RequestedState {
-            device_config: config.clone(),
-            keepalive_periods: features.wireguard.persistent_keepalive.clone(),
-            ..Default::default()
+        match nickname {
+            Some(nickname) => self
+                .requested_state
+                .peer_nicknames
+                .insert(public_key, nickname),
+            None => self.requested_state.peer_nicknames.remove(&public_key),
         };
+        Ok(())
+    }
 
-        let dns = Arc::new(Mutex::new(DNS {
-            resolver: None,
-            #[cfg(unix)]
-            virtual_host_tun_fd: config.tun,
-            #[cfg(windows)]
-            virtual_host_tun_fd: None,
-        }));
+    async fn get_peer_nickname(&self, public_key: &PublicKey) -> Result<Option<String>> {
+        let peer = self
+            .find_meshnet_peer(public_key)
+            .ok_or(Error::InvalidNode)?;
+        Ok(self.peer_nickname(peer))
+    }
 
-        let wg_endpoint_publish_events = Chan::default();
-        let wg_upgrade_sync = Chan::default();
-        let stun_server_events = Chan::default();
+    async fn create_peer_group(&mut self, group: PeerGroup) -> Result {
+        self.validate_peer_group(&group)?;
+        self.requested_state
+            .peer_groups
+            .insert(group.id.clone(), group);
+        self.apply_peer_groups().await
+    }
 
-        Ok(Runtime {
-            features,
-            requested_state,
-            entities: Entities {
-                wireguard_interface: wireguard_interface.clone(),
-                dns,
-                firewall,
-                meshnet: None,
-                socket_pool,
-                nurse,
-            },
-            event_listeners: EventListeners {
-                wg_endpoint_publish_event_subscriber: wg_endpoint_publish_events.rx,
-                wg_event_subscriber: wg_events,
-                derp_event_subscriber: derp_events.rx,
-                endpoint_upgrade_event_subscriber: wg_upgrade_sync.rx,
-                stun_server_subscriber: stun_server_events.rx,
-            },
-            event_publishers: EventPublishers {
-                libtelio_event_publisher: libtelio_wide_event_publisher,
-                nurse_config_update_publisher: config_update_ch,
-                nurse_collection_trigger_publisher: collection_trigger_ch,
-                wg_endpoint_publish_event_publisher: wg_endpoint_publish_events.tx,
-                endpoint_upgrade_event_subscriber: wg_upgrade_sync.tx,
-                stun_server_publisher: stun_server_events.tx,
-                derp_events_publisher: derp_events.tx,
-            },
-            polling_interval: interval_at(tokio::time::Instant::now(), Duration::from_secs(5)),
-            #[cfg(test)]
-            test_env: wg::tests::Env {
-                analytics: analytics_ch,
-                event: Chan::default().rx,
-                wg: wireguard_interface,
-                adapter,
-            },
-        })
+    async fn delete_peer_group(&mut self, id: &str) -> Result {
+        self.requested_state
+            .peer_groups
+            .remove(id)
+            .ok_or(Error::PeerGroupNotFound)?;
+        self.apply_peer_groups().await
     }
 
-    async fn start_meshnet_entities(&mut self) -> Result<MeshnetEntites> {
-        let endpoint_publish_events = Chan::default();
-        let pong_rxed_events = Chan::default();
+    async fn list_peer_groups(&self) -> Result<Vec<PeerGroup>> {
+        Ok(self.requested_state.peer_groups.values().cloned().collect())
+    }
 
-        // Start multiplexer
-        //
-        let (multiplexer_derp_chan, derp_multiplexer_chan) = Chan::pipe();
-        let multiplexer = Arc::new(Multiplexer::start(multiplexer_derp_chan));
+    fn validate_peer_group(&self, group: &PeerGroup) -> Result {
+        let config = self
+            .requested_state
+            .meshnet_config
+            .as_ref()
+            .ok_or(Error::MeshnetNotConfigured)?;
 
-        // Start UDP proxy
-        let proxy = Arc::new(UdpProxy::start(ProxyIo {
-            relay: multiplexer.get_channel().await?,
-        }));
+        if group.id.is_empty() {
+            return Err(Error::InvalidPeerGroup("group id is empty".to_owned()));
+        }
+        if let Some(member) = group.members.iter().find(|member| {
+            **member != config.this.public_key && self.find_meshnet_peer(member).is_none()
+        }) {
+            return Err(Error::InvalidPeerGroup(format!(
+                "member {} is not a meshnet node",
+                member
+            )));
+        }
+        if let Some(exit_node) = group.policy.exit_node {
+            if self.find_meshnet_peer(&exit_node).is_none() {
+                return Err(Error::InvalidPeerGroup(format!(
+                    "exit node {} is not a meshnet peer",
+                    exit_node
+                )));
+            }
+        }
+        if let Some(mtu) = group.policy.mtu.filter(|mtu| *mtu < MIN_PEER_GROUP_MTU) {
+            return Err(Error::InvalidPeerGroup(format!(
+                "MTU {} is lower than {}",
+                mtu, MIN_PEER_GROUP_MTU
+            )));
+        }
+        Ok(())
+    }
 
-        // Start Derp client
-        let derp = Arc::new(DerpRelay::start_with(
-            derp_multiplexer_chan,
-            self.entities.socket_pool.clone(),
-            self.event_publishers.derp_events_publisher.clone(),
-        ));
+    /// Route through the exit node of the first group (by id) this device is a member of
+    ///
+    /// Groups whose exit node is not part of the current meshnet config are skipped.
+    async fn apply_peer_groups(&mut self) -> Result {
+        let this = self
+            .requested_state
+            .meshnet_config
+            .as_ref()
+            .map(|config| config.this.public_key);
+        let group_exit_node = self.requested_state.peer_groups.values().find_map(|group| {
+            group
+                .policy
+                .exit_node
+                .filter(|_| this.map_or(false, |this| group.members.contains(&this)))
+                .filter(|exit_node| self.find_meshnet_peer(exit_node).is_some())
+                .map(|public_key| ExitNode {
+                    identifier: format!("{}{}", PEER_GROUP_EXIT_PREFIX, group.id),
+                    public_key,
+                    allowed_ips: None,
+                    endpoint: None,
+                })
+        });
 
-        if let Some(nurse) = self.entities.nurse.as_ref() {
-            nurse
-                .configure_meshnet(Some(NurseMeshnetEntities {
-                    derp: derp.clone(),
-                    multiplexer: multiplexer.clone(),
-                    derp_event_channel: self.event_publishers.derp_events_publisher.clone(),
-                }))
-                .await;
-        }
+        let exit_node = self.requested_state.exit_node.as_ref();
+        let is_connected_to = |node: &ExitNode| {
+            exit_node.map_or(false, |exit_node| {
+                exit_node.identifier == node.identifier && exit_node.public_key == node.public_key
+            })
+        };
+        let uses_group_exit_node = exit_node.map_or(false, |exit_node| {
+            exit_node.identifier.starts_with(PEER_GROUP_EXIT_PREFIX)
+        });
 
-        // Start Direct entities if "direct" feature is on
-        let direct = if let Some(direct) = &self.features.direct {
-            // Create endpoint providers
-            let has_provider = |provider| {
-                // Default is all providers
-                match direct.providers.as_ref().map(|p| p.contains(&provider)) {
-                    Some(prov) => prov,
-                    None => provider != Upnp,
-                }
-            };
+        match group_exit_node {
+            Some(node) if !is_connected_to(&node) => self.connect_exit_node(&node).await,
+            None if uses_group_exit_node => self.disconnect_exit_nodes().await,
+            _ => Ok(()),
+        }
+    }
 
-            use telio_model::api_config::EndpointProvider::*;
+    async fn get_peer_os(&self, public_key: &PublicKey) -> Result<Option<String>> {
+        let peer = self
+            .find_meshnet_peer(public_key)
+            .ok_or(Error::InvalidNode)?;
+        Ok(peer.os.clone())
+    }
 
-            let ping_pong_tracker = Arc::new(Mutex::new(PingPongHandler::new(
-                self.requested_state.device_config.private_key,
-            )));
-            let mut endpoint_providers: Vec<Arc<dyn EndpointProvider>> = Vec::new();
+    async fn get_peer_capabilities(&self, public_key: &PublicKey) -> Result<PeerCapabilities> {
+        self.find_meshnet_peer(public_key)
+            .ok_or(Error::InvalidNode)?;
+        Ok(self
+            .peer_capabilities
+            .get(public_key)
+            .copied()
+            .unwrap_or_default())
+    }
 
-            // Create Local Interface Endpoint Provider
-            let local_interfaces_endpoint_provider = if has_provider(Local) {
-                let ep = Arc::new(LocalInterfacesEndpointProvider::new(
-                    self.entities
-                        .socket_pool
-                        .new_external_udp((Ipv4Addr::UNSPECIFIED, 0), None)
-                        .await?,
-                    self.entities.wireguard_interface.clone(),
-                    Duration::from_secs(
-                        direct
-                            .endpoint_interval_secs
-                            .unwrap_or(DEFAULT_ENDPOINT_POLL_INTERVAL_SECS),
-                    ),
-                    ping_pong_tracker.clone(),
-                ));
-                endpoint_providers.push(ep.clone());
-                Some(ep)
-            } else {
-                None
-            };
+    async fn get_peer_version(&self, public_key: &PublicKey) -> Result<Option<String>> {
+        self.find_meshnet_peer(public_key)
+            .ok_or(Error::InvalidNode)?;
+        Ok(self.peer_versions.get(public_key).cloned())
+    }
 
-            // Create Stun Endpoint Provider
-            let stun_endpoint_provider = if has_provider(Stun) {
-                let ep = Arc::new(StunEndpointProvider::start(
-                    self.entities.wireguard_interface.clone(),
-                    ExponentialBackoffBounds {
-                        initial: Duration::from_secs(
-                            direct
-                                .endpoint_interval_secs
-                                .unwrap_or(DEFAULT_ENDPOINT_POLL_INTERVAL_SECS),
-                        ),
-                        maximal: Some(Duration::from_secs(120)),
-                    },
-                    ping_pong_tracker.clone(),
-                    self.event_publishers.stun_server_publisher.clone(),
-                )?);
-                endpoint_providers.push(ep.clone());
-                Some(ep)
-            } else {
-                None
-            };
+    async fn get_peer_endpoints(&self, public_key: &PublicKey) -> Result<Vec<PeerEndpoint>> {
+        self.find_meshnet_peer(public_key)
+            .ok_or(Error::InvalidNode)?;
+        let meshnet = match self.entities.meshnet.as_ref() {
+            Some(meshnet) => meshnet,
+            None => return Ok(Vec::new()),
+        };
 
-            // Create Upnp Endpoint Provider
-            let upnp_endpoint_provider = if has_provider(Upnp) {
-                let ep = Arc::new(UpnpEndpointProvider::start(
-                    self.entities
-                        .socket_pool
-                        .new_external_udp((Ipv4Addr::UNSPECIFIED, 0), None)
-                        .await?,
-                    self.entities.wireguard_interface.clone(),
-                    ExponentialBackoffBounds {
-                        initial: Duration::from_secs(
-                            direct
-                                .endpoint_interval_secs
-                                .unwrap_or(DEFAULT_ENDPOINT_POLL_INTERVAL_SECS),
-                        ),
-                        maximal: Some(Duration::from_secs(120)),
-                    },
-                    ping_pong_tracker.clone(),
-                )?);
-                endpoint_providers.push(ep.clone());
-                Some(ep)
-            } else {
-                None
-            };
+        let wgi = self.entities.wireguard_interface.get_interface().await?;
+        let current_endpoint = wgi.peers.get(public_key).and_then(|peer| peer.endpoint);
+        let since_last_rx = self
+            .entities
+            .wireguard_interface
+            .time_since_last_rx(*public_key)
+            .await?;
+        // Only the endpoint used by WireGuard has a record of the last received packet
+        let last_seen = |endpoint: SocketAddr, fallback: Option<Duration>| {
+            match current_endpoint {
+                Some(current) if current == endpoint => since_last_rx,
+                _ => fallback,
+            }
+            .map(|since| since.as_secs())
+        };
 
-            // Subscribe to endpoint providers' events
-            for endpoint_provider in &endpoint_providers {
-                endpoint_provider
-                    .subscribe_for_endpoint_candidates_change_events(
-                        endpoint_publish_events.tx.clone(),
-                    )
-                    .await;
-                endpoint_provider
-                    .subscribe_for_pong_events(pong_rxed_events.tx.clone())
-                    .await;
-            }
-
-            let last_handshake_time_provider: Option<Arc<dyn LastHandshakeTimeProvider>> =
-                if let Some(skip_unresponsive_peers) = &direct.skip_unresponsive_peers {
-                    Some(Arc::new(WireGuardLastHandshakeTimeProvider {
-                        wg: self.entities.wireguard_interface.clone(),
-                        threshold: Duration::from_secs(
-                            skip_unresponsive_peers.no_handshake_threshold_secs,
-                        ),
-                    }))
-                } else {
-                    None
+        let mut endpoints = Vec::new();
+        if let Some(proxy) = meshnet.proxy.get_endpoint_map().await?.get(public_key) {
+            endpoints.push(PeerEndpoint {
+                endpoint: "relay".to_owned(),
+                endpoint_type: PeerEndpointType::Relay,
+                last_seen_secs: last_seen(*proxy, None),
+            });
+        }
+        if let Some(direct) = meshnet.direct.as_ref() {
+            for validated in direct
+                .cross_ping_check
+                .get_peer_endpoints(*public_key)
+                .await?
+            {
+                let endpoint_type = match validated.provider {
+                    Some(EndpointProviderType::LocalInterfaces) => PeerEndpointType::Local,
+                    Some(EndpointProviderType::Stun) => PeerEndpointType::Stun,
+                    Some(EndpointProviderType::Upnp) => PeerEndpointType::Upnp,
+                    // Local endpoint is gone, the session is about to be dropped
+                    None => continue,
                 };
-            // Create Cross Ping Check
-            let cross_ping_check = Arc::new(CrossPingCheck::start(
-                CpcIo {
-                    endpoint_change_subscriber: endpoint_publish_events.rx,
-                    pong_rx_subscriber: pong_rxed_events.rx,
-                    wg_endpoint_publisher: self
-                        .event_publishers
-                        .wg_endpoint_publish_event_publisher
-                        .clone(),
-                    intercoms: multiplexer.get_channel().await?,
-                },
-                endpoint_providers.clone(),
-                last_handshake_time_provider.clone(),
-                Duration::from_secs(2),
-                ping_pong_tracker,
-                Default::default(),
-            ));
+                endpoints.push(PeerEndpoint {
+                    endpoint: validated.remote_endpoint.to_string(),
+                    endpoint_type,
+                    last_seen_secs: last_seen(
+                        validated.remote_endpoint,
+                        Some(validated.since_validated),
+                    ),
+                });
+            }
+        }
+        Ok(endpoints)
+    }
 
-            // Create WireGuard connection upgrade synchronizer
-            let upgrade_sync = Arc::new(UpgradeSync::new(
-                self.event_publishers
-                    .endpoint_upgrade_event_subscriber
-                    .clone(),
-                multiplexer.get_channel().await?,
-                Duration::from_secs(5),
-            )?);
+    async fn get_mesh_topology(&self) -> Result<MeshTopology> {
+        let config = match self.requested_state.meshnet_config.as_ref() {
+            Some(config) => config,
+            None => return Ok(MeshTopology::default()),
+        };
+        let first_ip =
+            |ips: &Option<Vec<IpAddr>>| ips.as_ref().and_then(|ips| ips.first().copied());
 
-            let session_keeper = Arc::new(SessionKeeper::start(self.entities.socket_pool.clone())?);
+        let mut topology = MeshTopology::default();
+        topology.nodes.push(TopologyNode {
+            pk: config.this.public_key,
+            ip: first_ip(&config.this.ip_addresses),
+        });
+        for peer in config.peers.iter().flatten() {
+            topology.nodes.push(TopologyNode {
+                pk: peer.public_key,
+                ip: first_ip(&peer.ip_addresses),
+            });
+        }
 
-            Some(DirectEntities {
-                local_interfaces_endpoint_provider,
-                stun_endpoint_provider,
-                upnp_endpoint_provider,
-                endpoint_providers,
-                cross_ping_check,
-                upgrade_sync,
-                session_keeper,
-            })
-        } else {
-            None
-        };
+        for node in self.external_nodes().await? {
+            if node.state != NodeState::Connected
+                || self.find_meshnet_peer(&node.public_key).is_none()
+            {
+                continue;
+            }
+            // Only the relayed path is measured, by the relay latency probes
+            let rtt_ms = match (node.path, self.entities.meshnet.as_ref()) {
+                (PathType::Relay, Some(meshnet)) => meshnet
+                    .derp
+                    .get_latency_history(Some(node.public_key), 1)
+                    .await
+                    .pop()
+                    .map(|sample| sample.rtt_ms),
+                _ => None,
+            };
+            topology.edges.push(TopologyEdge {
+                from: config.this.public_key,
+                to: node.public_key,
+                path: node.path,
+                rtt_ms,
+            });
+        }
+        Ok(topology)
+    }
 
-        Ok(MeshnetEntites {
-            multiplexer,
-            derp,
-            proxy,
-            direct,
-        })
+    fn local_capabilities(&self) -> u32 {
+        let mut capabilities = CapabilityMsg::CUSTOM_MESSAGES | CapabilityMsg::VERSION;
+        if self.features.direct.is_some() && !self.requested_state.direct_path_disabled {
+            capabilities |= CapabilityMsg::DIRECT_PATH;
+        }
+        if self.psk_rotation_interval.is_some() {
+            capabilities |= CapabilityMsg::PSK_ROTATION;
+        }
+        capabilities
     }
 
-    async fn external_nodes(&self) -> Result<Vec<Node>> {
-        let wgi = self.entities.wireguard_interface.get_interface().await?;
-        let mut nodes = Vec::new();
-        for peer in wgi.peers.values() {
-            if let Some(node) = self.peer_to_node(peer, None, None).await {
-                nodes.push(node);
+    /// Capabilities of this device for `peer`, the version is included only if the peer
+    /// advertised that it understands it
+    fn capability_msg(&self, peer: &PublicKey, reply_requested: bool) -> CapabilityMsg {
+        let msg = CapabilityMsg::new(self.local_capabilities(), reply_requested);
+        match self.peer_capabilities.get(peer) {
+            Some(capabilities) if capabilities.version_advertisement => {
+                msg.with_version(version_tag())
             }
+            _ => msg,
         }
-        Ok(nodes)
     }
 
-    async fn upsert_dns_peers(&self) -> Result {
-        if let Some(dns) = &self.entities.dns.lock().await.resolver {
-            let mut peers: Records = HashMap::new();
-            // Hostnames have priority over nicknames (override if there's any conflict)
-            if self.features.nicknames {
-                peers = self.requested_state.collect_dns_nickname_records();
+    /// Report `ClockSkewWarning` if the local clock differs too much from the relay server clock
+    async fn check_clock_skew(&self) {
+        let skew_ms = match self.get_clock_skew().await {
+            Ok(Some(skew_ms)) => skew_ms,
+            _ => return,
+        };
+        if u128::from(skew_ms.unsigned_abs()) > CLOCK_SKEW_WARNING_THRESHOLD.as_millis() {
+            telio_log_warn!(
+                "Local clock differs from the relay server by {} ms",
+                skew_ms
+            );
+            let _ = self
+                .event_publishers
+                .libtelio_event_publisher
+                .send(Box::new(
+                    Event::new::<ClockSkewWarning>().set(ClockSkewWarning { skew_ms }),
+                ));
+        }
+    }
+
+    async fn advertise_capabilities(&self, peers: impl IntoIterator<Item = PublicKey>) {
+        let meshnet = match self.entities.meshnet.as_ref() {
+            Some(meshnet) => meshnet,
+            None => return,
+        };
+        for peer in peers {
+            let msg = self.capability_msg(&peer, true);
+            if meshnet.capability.tx.send((peer, msg)).await.is_err() {
+                telio_log_warn!("Failed to advertise capabilities to {:?}", peer);
             }
-            peers.extend(self.requested_state.collect_dns_records());
+        }
+    }
 
-            // Insert wildcard for subdomains
-            let wildcarded_peers: Records = peers
-                .iter()
-                .map(|(name, ip)| (format!("*.{}", name), ip.clone()))
-                .collect();
-            peers.extend(wildcarded_peers);
+    async fn handle_capability_message(&mut self, sender: PublicKey, msg: CapabilityMsg) {
+        if self.find_meshnet_peer(&sender).is_none() {
+            telio_log_debug!("Ignoring capabilities of {:?}", sender);
+            return;
+        }
 
-            dns.upsert("nord", &peers)
-                .await
-                .map_err(Error::DnsResolverError)?;
+        let capabilities = PeerCapabilities {
+            direct_path: msg.has(CapabilityMsg::DIRECT_PATH),
+            custom_messages: msg.has(CapabilityMsg::CUSTOM_MESSAGES),
+            psk_rotation: msg.has(CapabilityMsg::PSK_ROTATION),
+            version_advertisement: msg.has(CapabilityMsg::VERSION),
+        };
+        telio_log_debug!(
+            "Capabilities of {:?}: {:?}, version: {:?}",
+            sender,
+            capabilities,
+            msg.get_version()
+        );
+        let previous = self.peer_capabilities.insert(sender, capabilities);
+        if let Some(version) = msg.get_version() {
+            self.peer_versions.insert(sender, version.to_owned());
         }
 
-        Ok(())
+        // Our previous advertisement could not carry the version, as the peer was not known to
+        // understand it yet
+        let version_not_sent = capabilities.version_advertisement
+            && !previous.map_or(false, |previous| previous.version_advertisement);
+        if !msg.is_reply_requested() && !version_not_sent {
+            return;
+        }
+        if let Some(meshnet) = self.entities.meshnet.as_ref() {
+            let reply = self.capability_msg(&sender, false);
+            if meshnet.capability.tx.send((sender, reply)).await.is_err() {
+                telio_log_warn!("Failed to advertise capabilities to {:?}", sender);
+            }
+        }
     }
 
-    async fn set_private_key(&mut self, private_key: &SecretKey) -> Result {
-        // TODO: create a global controll state to consolidate all entities
+    async fn get_ip_stack(&self) -> Result<IpStack> {
+        let config = self
+            .requested_state
+            .meshnet_config
+            .as_ref()
+            .ok_or(Error::MeshnetNotConfigured)?;
+        Ok(get_ip_stack(
+            config.this.ip_addresses.as_deref().unwrap_or_default(),
+        )?)
+    }
 
-        let should_validate_keys = self.features.validate_keys.0;
-        let meshnet_is_on = self.requested_state.meshnet_config.is_some();
-        let key_is_the_different = self.get_private_key().await? != *private_key;
-        if should_validate_keys && meshnet_is_on && key_is_the_different {
-            return Err(Error::BadPublicKey);
-        }
+    /// Meshnet IPv4 address of the peer and the path currently used to reach it
+    #[cfg(feature = "diagnostics")]
+    async fn traceroute_target(&self, public_key: &PublicKey) -> Result<(Ipv4Addr, PathType)> {
+        let peer = self
+            .find_meshnet_peer(public_key)
+            .ok_or(Error::InvalidNode)?;
+        let ip = peer
+            .ip_addresses
+            .iter()
+            .flatten()
+            .find_map(|ip| match ip {
+                IpAddr::V4(ip) => Some(*ip),
+                IpAddr::V6(_) => None,
+            })
+            .ok_or(Error::NoMeshnetIP)?;
 
-        if self.entities.dns.lock().await.resolver.is_some() {
-            return Err(Error::DnsNotDisabled);
-        }
+        let wgi = self.entities.wireguard_interface.get_interface().await?;
+        let wg_peer = wgi.peers.get(public_key).ok_or(Error::InvalidNode)?;
+        let path = self
+            .peer_to_node(wg_peer, None, None)
+            .await
+            .map(|node| node.path)
+            .unwrap_or_default();
+        Ok((ip, path))
+    }
 
-        if let Some(m_entities) = self.entities.meshnet.as_ref() {
-            m_entities
-                .derp
-                .configure(m_entities.derp.get_config().await.map(|c| DerpConfig {
-                    secret_key: *private_key,
-                    ..c
-                }))
-                .await;
-        }
+    /// Run the stages of the connectivity diagnostic which do not need a raw socket
+    ///
+    /// Returns the report along with the meshnet IPv4 address of the peer to ping, if it has one.
+    #[cfg(feature = "diagnostics")]
+    async fn check_connectivity(
+        &self,
+        public_key: &PublicKey,
+        dns_timeout: Duration,
+    ) -> Result<(ConnectivityReport, Option<Ipv4Addr>)> {
+        let peer = self
+            .find_meshnet_peer(public_key)
+            .ok_or(Error::InvalidNode)?;
+        let ping_target = peer.ip_addresses.iter().flatten().find_map(|ip| match ip {
+            IpAddr::V4(ip) => Some(*ip),
+            IpAddr::V6(_) => None,
+        });
+        let mut report = ConnectivityReport::default();
 
-        self.requested_state.device_config.private_key = *private_key;
+        let started = Instant::now();
+        report.tests.push(
+            match self
+                .proxy_dns_query(peer.hostname.as_str(), "A", dns_timeout)
+                .await
+            {
+                Ok(response) if !response.records.is_empty() => {
+                    ConnectivityTestResult::passed(ConnectivityTest::Dns, Some(started.elapsed()))
+                }
+                Ok(_) => ConnectivityTestResult::failed(
+                    ConnectivityTest::Dns,
+                    "Hostname did not resolve",
+                ),
+                Err(err) => ConnectivityTestResult::failed(ConnectivityTest::Dns, err),
+            },
+        );
 
-        if let Some(nurse) = &self.entities.nurse {
-            nurse.set_private_key(*private_key).await;
-        }
+        report.tests.push(match self.entities.meshnet.as_ref() {
+            None => {
+                ConnectivityTestResult::failed(ConnectivityTest::Relay, Error::MeshnetNotConfigured)
+            }
+            Some(meshnet) => {
+                let connected = meshnet
+                    .derp
+                    .get_connected_server()
+                    .await
+                    .map_or(false, |server| server.conn_state == RelayState::Connected);
+                // Peer states are known only with derp polling, unknown peers are not failed
+                let peer_offline =
+                    meshnet.derp.get_remote_peer_states().await.get(public_key) == Some(&false);
+                if !connected {
+                    ConnectivityTestResult::failed(
+                        ConnectivityTest::Relay,
+                        "Not connected to a relay server",
+                    )
+                } else if peer_offline {
+                    ConnectivityTestResult::failed(
+                        ConnectivityTest::Relay,
+                        "Peer is not connected to the relay server",
+                    )
+                } else {
+                    let rtt = meshnet
+                        .derp
+                        .get_latency_history(Some(*public_key), 1)
+                        .await
+                        .pop()
+                        .map(|sample| Duration::from_millis(sample.rtt_ms));
+                    ConnectivityTestResult::passed(ConnectivityTest::Relay, rtt)
+                }
+            }
+        });
 
-        wg_controller::consolidate_wg_state(&self.requested_state, &self.entities, &self.features)
-            .await?;
-        Ok(())
+        let stun = self
+            .entities
+            .meshnet
+            .as_ref()
+            .and_then(|m| m.direct.as_ref())
+            .and_then(|d| d.stun_endpoint_provider.as_ref());
+        report.tests.push(match stun {
+            None => ConnectivityTestResult::failed(
+                ConnectivityTest::Stun,
+                "STUN endpoint provider is not enabled",
+            ),
+            Some(stun) => match stun
+                .get_servers()
+                .await
+                .iter()
+                .filter_map(|server| server.last_response_ms)
+                .min()
+            {
+                Some(rtt_ms) => ConnectivityTestResult::passed(
+                    ConnectivityTest::Stun,
+                    Some(Duration::from_millis(rtt_ms)),
+                ),
+                None => ConnectivityTestResult::failed(
+                    ConnectivityTest::Stun,
+                    "No STUN server responded",
+                ),
+            },
+        });
+
+        Ok((report, ping_target))
+    }
+
+    fn find_meshnet_peer(&self, public_key: &PublicKey) -> Option<&Peer> {
+        self.requested_state
+            .meshnet_config
+            .as_ref()?
+            .peers
+            .as_ref()?
+            .iter()
+            .find(|peer| peer.public_key == *public_key)
+    }
+
+    /// Nickname set by the application takes precedence over the one from meshnet config
+    fn peer_nickname(&self, peer: &Peer) -> Option<String> {
+        self.requested_state
+            .peer_nicknames
+            .get(&peer.public_key)
+            .or(peer.nickname.as_ref())
+            .cloned()
     }
 
     async fn get_private_key(&self) -> Result<SecretKey> {
         Ok(self.requested_state.device_config.private_key)
     }
 
+    #[cfg(not(windows))]
+    async fn get_current_tun_fd(&self) -> Result<Option<Tun>> {
+        Ok(self.requested_state.device_config.tun)
+    }
+
     async fn get_adapter_luid(&mut self) -> Result<u64> {
         Ok(self.entities.wireguard_interface.get_adapter_luid().await?)
     }
@@ -1308,6 +4380,16 @@ impl Runtime {
         Ok(())
     }
 
+    #[cfg(target_os = "android")]
+    async fn set_network_constraints(&mut self, constraints: NetworkConstraints) -> Result {
+        self.entities
+            .socket_pool
+            .set_network_constraints(constraints);
+
+        // Re-create existing sockets, so they would be bound according to new constraints
+        self.notify_network_change().await
+    }
+
     async fn notify_network_change(&mut self) -> Result {
         self.entities
             .wireguard_interface
@@ -1328,6 +4410,51 @@ impl Runtime {
         Ok(())
     }
 
+    /// Handle a change of the state returned by the link state provider as a network change
+    async fn check_link_state(&mut self) {
+        // Host callback is called without holding the lock, so it may register another provider
+        let provider = self.link_state_provider.read().clone();
+        let state = match provider {
+            Some(provider) => provider(),
+            None => {
+                self.link_state = None;
+                return;
+            }
+        };
+        let previous = self.link_state.replace(state);
+        if previous.map_or(false, |previous| previous != state) {
+            telio_log_info!("Link state changed, handling network change");
+            if let Err(err) = self.notify_network_change().await {
+                telio_log_warn!("Failed to handle link state change: {}", err);
+            }
+        }
+    }
+
+    async fn trigger_network_redetection(&mut self) -> Result {
+        let direct = match self
+            .entities
+            .meshnet
+            .as_ref()
+            .and_then(|m| m.direct.as_ref())
+        {
+            Some(direct) => direct,
+            None => {
+                telio_log_debug!("Direct connections are not running, nothing to redetect");
+                return Ok(());
+            }
+        };
+
+        if let Some(stun) = &direct.stun_endpoint_provider {
+            stun.redetect().await;
+        }
+        for ep in direct.endpoint_providers.iter() {
+            if let Err(err) = ep.trigger_endpoint_candidates_discovery(true).await {
+                telio_log_debug!("Failed to trigger {}: {}", ep.name(), err);
+            }
+        }
+        Ok(())
+    }
+
     async fn start_dns(&mut self, upstream_dns_servers: &[IpAddr]) -> Result {
         self.requested_state.upstream_servers = Some(Vec::from(upstream_dns_servers));
         {
@@ -1364,6 +4491,21 @@ impl Runtime {
                 )
                 .await
                 .map_err(Error::DnsResolverError)?;
+                if self.requested_state.dns_fallback.is_some() {
+                    dns.set_fallback(self.requested_state.dns_fallback.clone())
+                        .await
+                        .map_err(Error::DnsResolverError)?;
+                }
+                if self.requested_state.dns_ttl_override.is_set() {
+                    dns.set_ttl_override(self.requested_state.dns_ttl_override)
+                        .await
+                        .map_err(Error::DnsResolverError)?;
+                }
+                if self.requested_state.dns_over_https.is_some() {
+                    dns.set_dns_over_https(self.dns_over_https_server())
+                        .await
+                        .map_err(Error::DnsResolverError)?;
+                }
                 dns.start().await;
                 dns_entity.resolver = Some(dns);
             }
@@ -1388,6 +4530,79 @@ impl Runtime {
         Ok(())
     }
 
+    async fn notify_dns_servers_changed(&mut self, upstream_dns_servers: &[IpAddr]) -> Result {
+        let dns = self.entities.dns.lock().await;
+        let resolver = dns.resolver.as_ref().ok_or(Error::DnsNotEnabled)?;
+        self.requested_state.upstream_servers = Some(Vec::from(upstream_dns_servers));
+
+        // Meshnet exit node resolves through the default servers, new upstream servers are
+        // applied when disconnecting from it
+        let is_meshnet_exit_node = self
+            .requested_state
+            .exit_node
+            .as_ref()
+            .map_or(false, |node| {
+                self.requested_state
+                    .meshnet_config
+                    .as_ref()
+                    .and_then(|config| config.peers.as_deref())
+                    .map_or(false, |peers| {
+                        peers.iter().any(|p| p.public_key == node.public_key)
+                    })
+            });
+        if is_meshnet_exit_node && resolver.auto_switch_ips {
+            telio_log_debug!("Upstream DNS servers stored until exit node is disconnected");
+            return Ok(());
+        }
+
+        telio_log_debug!("forwarding to dns {:?}", upstream_dns_servers);
+        resolver
+            .forward(upstream_dns_servers)
+            .await
+            .map_err(Error::DnsResolverError)
+    }
+
+    async fn set_dns_fallback_servers(&mut self, fallback: Option<DnsFallbackConfig>) -> Result {
+        self.requested_state.dns_fallback = fallback.clone();
+        if let Some(resolver) = &self.entities.dns.lock().await.resolver {
+            resolver
+                .set_fallback(fallback)
+                .await
+                .map_err(Error::DnsResolverError)?;
+        }
+        Ok(())
+    }
+
+    async fn set_dns_ttl_override(&mut self, ttl_override: DnsTtlOverride) -> Result {
+        self.requested_state.dns_ttl_override = ttl_override;
+        if let Some(resolver) = &self.entities.dns.lock().await.resolver {
+            resolver
+                .set_ttl_override(ttl_override)
+                .await
+                .map_err(Error::DnsResolverError)?;
+        }
+        Ok(())
+    }
+
+    async fn set_dns_over_https(&mut self, doh: Option<DohServer>) -> Result {
+        self.requested_state.dns_over_https = doh;
+        if let Some(resolver) = &self.entities.dns.lock().await.resolver {
+            resolver
+                .set_dns_over_https(self.dns_over_https_server())
+                .await
+                .map_err(Error::DnsResolverError)?;
+        }
+        Ok(())
+    }
+
+    /// Requested DNS over HTTPS server, reached through the requested proxy
+    fn dns_over_https_server(&self) -> Option<DohServer> {
+        self.requested_state
+            .dns_over_https
+            .clone()
+            .map(|doh| doh.with_proxy(self.requested_state.proxy_settings.clone()))
+    }
+
     async fn stop_dns(&mut self) -> Result {
         self.requested_state.upstream_servers = None;
         if let Some(dns) = self.entities.dns.lock().await.resolver.take() {
@@ -1399,12 +4614,179 @@ impl Runtime {
         Ok(())
     }
 
+    async fn get_meshnet_config_hash(&self) -> Result<String> {
+        Ok(self
+            .requested_state
+            .meshnet_config_hash
+            .clone()
+            .unwrap_or_else(|| EMPTY_MESHNET_CONFIG_HASH.to_owned()))
+    }
+
+    async fn get_last_config_apply_time(&self) -> Result<Option<u64>> {
+        Ok(self.requested_state.config_applied_at)
+    }
+
+    async fn get_mesh_hostname(&self) -> Result<Option<String>> {
+        Ok(self
+            .requested_state
+            .meshnet_config
+            .as_ref()
+            .map(|config| config.this.hostname.0.clone()))
+    }
+
+    async fn proxy_dns_query(
+        &self,
+        name: &str,
+        record_type: &str,
+        timeout: Duration,
+    ) -> Result<DnsQueryResponse> {
+        let dns = self.entities.dns.lock().await;
+        let resolver = dns.resolver.as_ref().ok_or(Error::DnsNotEnabled)?;
+        tokio::time::timeout(timeout, resolver.query(name, record_type))
+            .await
+            .map_err(|_| Error::DnsQueryTimeout)?
+            .map_err(Error::DnsResolverError)
+    }
+
+    async fn resolve_mesh_hostname(
+        &self,
+        hostname: &str,
+        timeout: Duration,
+    ) -> Result<Vec<IpAddr>> {
+        let mut addresses = Vec::new();
+        for record_type in ["A", "AAAA"] {
+            let response = self.proxy_dns_query(hostname, record_type, timeout).await?;
+            addresses.extend(
+                response
+                    .records
+                    .iter()
+                    .filter_map(|record| record.parse::<IpAddr>().ok()),
+            );
+        }
+        Ok(addresses)
+    }
+
+    async fn dns_override(&self, name: &str, addresses: &[IpAddr], ttl: u32) -> Result {
+        let dns = self.entities.dns.lock().await;
+        let resolver = dns.resolver.as_ref().ok_or(Error::DnsNotEnabled)?;
+        resolver
+            .override_records(name, addresses, ttl)
+            .await
+            .map_err(Error::DnsResolverError)
+    }
+
+    async fn dns_remove_override(&self, name: &str) -> Result {
+        let dns = self.entities.dns.lock().await;
+        let resolver = dns.resolver.as_ref().ok_or(Error::DnsNotEnabled)?;
+        resolver
+            .remove_override(name)
+            .await
+            .map_err(Error::DnsResolverError)
+    }
+
+    /// Report the config older than allowed by `config_expiry_policy`, fails if the policy
+    /// refuses stale configs
+    fn check_config_expiry(&self, config: &Config) -> Result {
+        let (policy, timestamp) = match (self.features.config_expiry_policy, config.timestamp) {
+            (Some(policy), Some(timestamp)) => (policy, timestamp),
+            _ => return Ok(()),
+        };
+        let age_secs = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+            .saturating_sub(timestamp);
+        if age_secs <= policy.max_age_secs {
+            return Ok(());
+        }
+
+        let applied = policy.action == ConfigExpiryAction::Warn;
+        telio_log_warn!(
+            "Meshnet config is {}s old, max age is {}s, applied: {}",
+            age_secs,
+            policy.max_age_secs,
+            applied
+        );
+        let _ = self
+            .event_publishers
+            .libtelio_event_publisher
+            .send(Box::new(Event::new::<StaleConfig>().set(StaleConfig {
+                timestamp,
+                age_secs,
+                applied,
+            })));
+
+        if applied {
+            Ok(())
+        } else {
+            Err(Error::StaleConfig)
+        }
+    }
+
+    /// Report IP addresses assigned to more than one node according to the ip conflict policy,
+    /// returns the config to apply
+    fn resolve_ip_conflicts(&self, config: &Config) -> Result<Config> {
+        let policy = match self.requested_state.ip_conflict_policy {
+            Some(policy) => policy,
+            None => return Ok(config.clone()),
+        };
+        let conflicts = find_ip_conflicts(config);
+
+        let applied = policy == IpConflictPolicy::DetectAndResolveViaBackoff;
+        for (public_key, ip) in &conflicts {
+            telio_log_warn!(
+                "Meshnet peer {:?} is assigned {} which belongs to another node, policy: {:?}",
+                public_key,
+                ip,
+                policy
+            );
+            if policy != IpConflictPolicy::DetectAndLog {
+                let _ = self
+                    .event_publishers
+                    .libtelio_event_publisher
+                    .send(Box::new(Event::new::<DuplicateIpDetected>().set(
+                        DuplicateIpDetected {
+                            peer_pk: public_key.to_string(),
+                            ip_address: ip.to_string(),
+                            applied,
+                        },
+                    )));
+            }
+        }
+
+        match (policy, conflicts.first()) {
+            (IpConflictPolicy::DetectAndRefuse, Some(&(public_key, ip))) => {
+                Err(Error::DuplicateIp { public_key, ip })
+            }
+            (IpConflictPolicy::DetectAndResolveViaBackoff, Some(_)) => {
+                let mut config = config.clone();
+                if let Some(peers) = config.peers.as_mut() {
+                    peers.retain(|peer| !conflicts.iter().any(|(pk, _)| *pk == peer.public_key));
+                }
+                Ok(config)
+            }
+            _ => Ok(config.clone()),
+        }
+    }
+
     async fn set_config(&mut self, config: &Option<Config>) -> Result {
         if self.features.post_quantum_vpn.is_some() && config.is_some() {
             // Post quantum VPN is enabled and we're trying to set up the meshnet
             return Err(Error::MeshnetUnavailableWithPQ);
         }
 
+        if let Some(cfg) = config {
+            self.check_config_expiry(cfg)?;
+            check_max_peers(cfg, self.features.max_peers).map_err(|err| {
+                telio_log_error!("{}", err);
+                err
+            })?;
+        }
+        let config = &config
+            .as_ref()
+            .map(|cfg| self.resolve_ip_conflicts(cfg))
+            .transpose()?;
+
         if let Some(cfg) = config {
             let should_validate_keys = self.features.validate_keys.0;
             let keys_match =
@@ -1414,8 +4796,50 @@ impl Runtime {
             }
         }
 
+        if self.requested_state.meshnet_config != *config {
+            self.requested_state.peer_nicknames.clear();
+        }
+
+        if self.requested_state.direct_path_disabled && self.features.direct.is_some() {
+            let peers_count = config
+                .as_ref()
+                .and_then(|c| c.peers.as_ref())
+                .map_or(0, |peers| peers.len());
+            if peers_count > 0 {
+                telio_log_warn!(
+                    "Direct path is disabled, {} meshnet peers will be reachable only through the relay",
+                    peers_count
+                );
+            }
+        }
+        #[cfg(feature = "audit_log")]
+        {
+            let peer_keys = |config: Option<&Config>| -> HashSet<PublicKey> {
+                config
+                    .and_then(|c| c.peers.as_ref())
+                    .map(|peers| peers.iter().map(|p| p.public_key).collect())
+                    .unwrap_or_default()
+            };
+            let old_peers = peer_keys(self.requested_state.meshnet_config.as_ref());
+            let new_peers = peer_keys(config.as_ref());
+            for pk in new_peers.difference(&old_peers) {
+                self.audit(AuditEvent::PeerAdded, *pk);
+            }
+            for pk in old_peers.difference(&new_peers) {
+                self.audit(AuditEvent::PeerRemoved, *pk);
+            }
+        }
+
         self.requested_state.old_meshnet_config = self.requested_state.meshnet_config.clone();
         self.requested_state.meshnet_config = config.clone();
+        let peers = config
+            .as_ref()
+            .and_then(|c| c.peers.as_deref())
+            .unwrap_or_default();
+        self.peer_capabilities
+            .retain(|pk, _| peers.iter().any(|p| p.public_key == *pk));
+        self.peer_versions
+            .retain(|pk, _| peers.iter().any(|p| p.public_key == *pk));
 
         let wg_itf = self.entities.wireguard_interface.get_interface().await?;
         let secret_key = if let Some(secret_key) = wg_itf.private_key {
@@ -1454,7 +4878,10 @@ impl Runtime {
 
             let derp_config = DerpConfig {
                 secret_key,
-                servers: SortedServers::new(config.derp_servers.clone().unwrap_or_default()),
+                servers: SortedServers::with_policy(
+                    config.derp_servers.clone().unwrap_or_default(),
+                    &self.requested_state.relay_selection_policy,
+                ),
                 allowed_pk: peers,
                 timeout: Duration::from_secs(10), //TODO: make configurable
                 server_keepalives: DerpKeepaliveConfig::from(&self.features.derp),
@@ -1476,35 +4903,37 @@ impl Runtime {
                     .clone()
                     .unwrap_or_default()
                     .use_built_in_root_certificates,
+                max_relay_connections: self.requested_state.max_relay_connections,
+                proxy: self.requested_state.proxy_settings.clone(),
             };
 
             // Update configuration for DERP client
             meshnet_entities.derp.configure(Some(derp_config)).await;
 
-            // Refresh the lists of servers for STUN endpoint provider
-            if let Some(direct) = meshnet_entities.direct.as_ref() {
-                if let Some(stun_ep) = direct.stun_endpoint_provider.as_ref() {
-                    let use_ipv6 = self.features.ipv6 && {
-                        config
-                            .this
-                            .ip_addresses
-                            .as_ref()
-                            .map(|vec| vec.iter().any(|addr| addr.is_ipv6()))
-                            .unwrap_or(false)
-                    };
-
-                    stun_ep
-                        .configure(
-                            config.derp_servers.clone().unwrap_or_default(),
-                            use_ipv6,
-                            self.get_socket_pool().await?,
-                        )
-                        .await;
-                }
-            }
-
             self.entities.meshnet = Some(meshnet_entities);
 
+            // Peers already in the config got the capabilities when the relay connected
+            let old_peers: HashSet<PublicKey> = self
+                .requested_state
+                .old_meshnet_config
+                .as_ref()
+                .and_then(|c| c.peers.as_ref())
+                .map(|peers| peers.iter().map(|p| p.public_key).collect())
+                .unwrap_or_default();
+            let new_peers: Vec<PublicKey> = config
+                .peers
+                .as_ref()
+                .map(|peers| {
+                    peers
+                        .iter()
+                        .map(|p| p.public_key)
+                        .filter(|pk| !old_peers.contains(pk))
+                        .collect()
+                })
+                .unwrap_or_default();
+            self.advertise_capabilities(new_peers.iter().copied()).await;
+            self.reset_psks(new_peers).await;
+
             self.upsert_dns_peers().await?;
         } else {
             // Nurse is keeping Arc to Derp, so we need to get rid of it before stopping Derp
@@ -1519,9 +4948,7 @@ impl Runtime {
             self.upsert_dns_peers().await?;
         }
 
-        if let Some(cpc) = self.entities.cross_ping_check() {
-            cpc.configure(config.clone()).await?;
-        }
+        self.configure_direct_entities(config).await?;
 
         // If Disabling meshnet (by calling `set_config()` with `None` as the argument) need to clear exit node
         // so that the controller does not mistake it for a VPN node. See LLT-4266 for more details.
@@ -1559,6 +4986,19 @@ impl Runtime {
             }
         }
 
+        if !self.requested_state.peer_groups.is_empty() {
+            self.apply_peer_groups().await?;
+        }
+
+        self.requested_state.meshnet_config_hash =
+            config.as_ref().map(meshnet_config_hash).transpose()?;
+        self.requested_state.config_applied_at = Some(
+            std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        );
+
         Ok(())
     }
 
@@ -1630,9 +5070,13 @@ impl Runtime {
             return Err(Error::EndpointNotProvided);
         }
 
+        #[cfg(feature = "audit_log")]
+        let exit_public_key = exit_node.public_key;
         let old_exit_node = self.requested_state.exit_node.replace(exit_node);
         wg_controller::consolidate_wg_state(&self.requested_state, &self.entities, &self.features)
             .await?;
+        #[cfg(feature = "audit_log")]
+        self.audit(AuditEvent::ExitNodeConnected, exit_public_key);
 
         if let Some(last_exit) = old_exit_node
             .as_ref()
@@ -1675,6 +5119,248 @@ impl Runtime {
         Ok(())
     }
 
+    async fn connect_exit_node_with_retry(
+        &mut self,
+        exit_node: &ExitNode,
+        policy: ExitNodeRetryPolicy,
+    ) -> Result {
+        telio_log_info!(
+            "Connecting exit node {:?} with retry: {:?}",
+            exit_node.public_key,
+            policy
+        );
+        self.exit_node_retry = None;
+        match self.connect_exit_node(exit_node).await {
+            Ok(()) => (),
+            // Retrying does not bring the endpoint back
+            Err(Error::EndpointNotProvided) => return Err(Error::EndpointNotProvided),
+            Err(err) => telio_log_warn!(
+                "Failed to connect exit node {:?}: {}, retrying",
+                exit_node.public_key,
+                err
+            ),
+        }
+        self.exit_node_retry = Some(RetriedExitNode {
+            node: exit_node.clone(),
+            requested: self.requested_exit_node(),
+            retry: ExitNodeRetry::new(policy, Instant::now()),
+        });
+        Ok(())
+    }
+
+    fn requested_exit_node(&self) -> Option<PublicKey> {
+        self.requested_state
+            .exit_node
+            .as_ref()
+            .map(|exit_node| exit_node.public_key)
+    }
+
+    /// Connect the exit node again when no handshake succeeded within the delay of the attempt
+    ///
+    /// Retrying stops when the requested exit node changed since the last attempt, e.g. after
+    /// another exit node was connected or the exit node was disconnected. Attempts which failed
+    /// before the exit node was requested, e.g. on fetching the post-quantum keys, are retried.
+    async fn check_exit_node_retry(&mut self) {
+        let requested = self.requested_exit_node();
+        let exit_node = match &self.exit_node_retry {
+            Some(retried) if retried.requested == requested => retried.node.clone(),
+            _ => {
+                self.exit_node_retry = None;
+                return;
+            }
+        };
+
+        // State events are not published when the exit node was connected before retrying
+        let state = match self.entities.wireguard_interface.get_interface().await {
+            Ok(wgi) => wgi
+                .peers
+                .get(&exit_node.public_key)
+                .map(|peer| peer.state()),
+            Err(err) => {
+                telio_log_warn!("Failed to get WireGuard interface: {}", err);
+                None
+            }
+        };
+        if let Some(state) = state {
+            self.check_exit_node_retry_handshake(&exit_node.public_key, state);
+        }
+
+        let retry = match self.exit_node_retry.as_mut() {
+            Some(retried) => &mut retried.retry,
+            None => return,
+        };
+
+        if !retry.next_attempt(Instant::now()) {
+            let attempts = retry.attempts();
+            self.exit_node_retry = None;
+            telio_log_warn!(
+                "Exit node {:?} is unreachable after {} attempts",
+                exit_node.public_key,
+                attempts
+            );
+            let _ = self
+                .event_publishers
+                .libtelio_event_publisher
+                .send(Box::new(Event::new::<ExitNodeConnectionFailed>().set(
+                    ExitNodeConnectionFailed {
+                        peer_pk: exit_node.public_key.to_string(),
+                        attempts,
+                    },
+                )));
+            return;
+        }
+
+        telio_log_info!(
+            "Exit node {:?} is unreachable, attempt {}",
+            exit_node.public_key,
+            retry.attempts()
+        );
+        if let Err(err) = self.connect_exit_node(&exit_node).await {
+            telio_log_warn!(
+                "Failed to connect exit node {:?}: {}",
+                exit_node.public_key,
+                err
+            );
+        }
+        let requested = self.requested_exit_node();
+        if let Some(retried) = self.exit_node_retry.as_mut() {
+            retried.requested = requested;
+        }
+    }
+
+    /// Report the exit node being retried as connected on its first handshake
+    fn check_exit_node_retry_handshake(&mut self, public_key: &PublicKey, state: NodeState) {
+        let attempts = match &self.exit_node_retry {
+            Some(retried)
+                if retried.node.public_key == *public_key && state == NodeState::Connected =>
+            {
+                retried.retry.attempts()
+            }
+            _ => return,
+        };
+        self.exit_node_retry = None;
+        let _ = self
+            .event_publishers
+            .libtelio_event_publisher
+            .send(Box::new(Event::new::<ExitNodeConnected>().set(
+                ExitNodeConnected {
+                    peer_pk: public_key.to_string(),
+                    attempts,
+                },
+            )));
+    }
+
+    async fn enable_exit_node_failover(&mut self, nodes: Vec<FailoverExitNode>) -> Result {
+        telio_log_info!("Exit node failover: {:?}", nodes);
+        let connected = self
+            .requested_state
+            .exit_node
+            .as_ref()
+            .and_then(|exit_node| {
+                nodes
+                    .iter()
+                    .position(|node| node.public_key == exit_node.public_key)
+            });
+        let timeout = self
+            .features
+            .exit_node_failover_timeout_secs
+            .map_or(DEFAULT_EXIT_NODE_FAILOVER_TIMEOUT, Duration::from_secs);
+        let failover = FailoverList::new(
+            nodes,
+            timeout,
+            connected.unwrap_or_default(),
+            Instant::now(),
+        );
+
+        if connected.is_none() {
+            if let Some(node) = failover.current() {
+                self.connect_exit_node(&failover_exit_node(node)).await?;
+            }
+        }
+        self.exit_node_failover = Some(failover);
+        Ok(())
+    }
+
+    async fn disable_exit_node_failover(&mut self) -> Result {
+        telio_log_info!("Exit node failover disabled");
+        self.exit_node_failover = None;
+        Ok(())
+    }
+
+    /// Connect the next exit node of the failover list if the current one is unreachable
+    ///
+    /// Nothing is done while the connected exit node is not the one chosen by the failover,
+    /// e.g. after it was changed manually.
+    async fn check_exit_node_failover(&mut self) {
+        let current = match (
+            self.exit_node_failover
+                .as_ref()
+                .and_then(|failover| failover.current()),
+            self.requested_state.exit_node.as_ref(),
+        ) {
+            (Some(current), Some(exit_node)) if current.public_key == exit_node.public_key => {
+                current.public_key
+            }
+            _ => return,
+        };
+
+        let since_last_rx = match self
+            .entities
+            .wireguard_interface
+            .time_since_last_rx(current)
+            .await
+        {
+            Ok(since_last_rx) => since_last_rx,
+            Err(err) => {
+                telio_log_warn!("Failed to get time since last rx: {}", err);
+                return;
+            }
+        };
+        let next = match self.exit_node_failover.as_mut().and_then(|failover| {
+            failover
+                .next_if_unreachable(since_last_rx, Instant::now())
+                .map(failover_exit_node)
+        }) {
+            Some(next) => next,
+            None => return,
+        };
+
+        telio_log_warn!(
+            "Exit node {:?} is unreachable, failing over to {:?}",
+            current,
+            next.public_key
+        );
+        if let Err(err) = self.connect_exit_node(&next).await {
+            telio_log_warn!("Failed to connect exit node {:?}: {}", next.public_key, err);
+            return;
+        }
+        let _ = self
+            .event_publishers
+            .libtelio_event_publisher
+            .send(Box::new(Event::new::<ExitNodeFailover>().set(
+                ExitNodeFailover {
+                    from_pk: current.to_string(),
+                    to_pk: next.public_key.to_string(),
+                },
+            )));
+    }
+
+    async fn get_connected_exit_nodes(&self) -> Result<Vec<ExitNode>> {
+        self.requested_state
+            .exit_node
+            .iter()
+            .map(|exit_node| {
+                Ok(ExitNode {
+                    allowed_ips: Some(wg_controller::exit_node_allowed_ips(
+                        exit_node,
+                        &self.features,
+                    )?),
+                    ..exit_node.clone()
+                })
+            })
+            .collect()
+    }
+
     async fn disconnect_exit_node(&mut self, node_key: &PublicKey) -> Result {
         match self.requested_state.exit_node.as_ref() {
             Some(exit_node) if &exit_node.public_key == node_key => {
@@ -1689,6 +5375,8 @@ impl Runtime {
 
     async fn disconnect_exit_nodes(&mut self) -> Result {
         if let Some(exit_node) = self.requested_state.exit_node.take() {
+            #[cfg(feature = "audit_log")]
+            self.audit(AuditEvent::ExitNodeDisconnected, exit_node.public_key);
             self.requested_state.last_exit_node = Some(exit_node);
 
             // for macos dns
@@ -1775,6 +5463,9 @@ impl Runtime {
             PathType::Direct
         };
 
+        let state = state.unwrap_or_else(|| peer.state());
+        let is_stale = state == PeerState::Connected && self.is_peer_stale(peer).await;
+
         // Build a node to report event about, we need to report about either meshnet peers
         // or VPN peers. Others (like DNS, or anycast) are considered to be "internal" ones
         // and will not be reported via libtelio events.
@@ -1784,9 +5475,11 @@ impl Runtime {
                 Some(Node {
                     identifier: meshnet_peer.base.identifier.clone(),
                     public_key: meshnet_peer.base.public_key,
-                    nickname: meshnet_peer.base.nickname.clone(),
-                    state: state.unwrap_or_else(|| peer.state()),
+                    nickname: self.peer_nickname(meshnet_peer),
+                    os: meshnet_peer.base.os.clone(),
+                    state,
                     link_state,
+                    is_stale,
                     is_exit: peer
                         .allowed_ips
                         .iter()
@@ -1807,8 +5500,10 @@ impl Runtime {
                     identifier: exit_node.identifier.clone(),
                     public_key: exit_node.public_key,
                     nickname: None,
-                    state: state.unwrap_or_else(|| peer.state()),
+                    os: None,
+                    state,
                     link_state,
+                    is_stale,
                     is_exit: true,
                     is_vpn: exit_node.endpoint.is_some(),
                     ip_addresses: vec![
@@ -1827,6 +5522,26 @@ impl Runtime {
         }
     }
 
+    /// Whether nothing was received from the peer for longer than the stale peer threshold
+    async fn is_peer_stale(&self, peer: &uapi::Peer) -> bool {
+        let threshold = self
+            .features
+            .stale_peer_threshold_secs
+            .map_or(DEFAULT_STALE_PEER_THRESHOLD, Duration::from_secs);
+        match self
+            .entities
+            .wireguard_interface
+            .time_since_last_rx(peer.public_key)
+            .await
+        {
+            Ok(time_since_last_rx) => time_since_last_rx.map_or(false, |rx| rx > threshold),
+            Err(err) => {
+                telio_log_warn!("Failed to get time since last rx: {}", err);
+                false
+            }
+        }
+    }
+
     async fn trigger_analytics_event(&self) -> Result<()> {
         if let Some(ch) = &self.event_publishers.nurse_collection_trigger_publisher {
             let _ = ch.send(Box::new(()));
@@ -1847,6 +5562,31 @@ impl TaskRuntime for Runtime {
     where
         F: Future<Output = BoxAction<Self, std::result::Result<(), Self::Err>>> + Send,
     {
+        let (
+            application_rx,
+            broadcast_rx,
+            psk_rotate_rx,
+            wake_rx,
+            capability_rx,
+            auth_challenge_rx,
+        ) = match self.entities.meshnet.as_mut() {
+            Some(meshnet) => (
+                Some(&mut meshnet.application.rx),
+                Some(&mut meshnet.broadcast.rx),
+                Some(&mut meshnet.psk_rotate.rx),
+                Some(&mut meshnet.wake.rx),
+                Some(&mut meshnet.capability.rx),
+                Some(&mut meshnet.auth_challenge.rx),
+            ),
+            None => (None, None, None, None, None, None),
+        };
+        let psk_rotation_interval = self.psk_rotation_interval.as_mut();
+        let psk_retry_deadline = self.psk_rotation.next_retry();
+        let exit_node_retry_deadline = self
+            .exit_node_retry
+            .as_ref()
+            .map(|retried| retried.retry.deadline());
+
         tokio::select! {
             Some(_) = self.event_listeners.wg_endpoint_publish_event_subscriber.recv() => {
                 telio_log_debug!("WG consolidation triggered by endpoint publish event");
@@ -1860,9 +5600,13 @@ impl TaskRuntime for Runtime {
             },
 
             Some(mesh_event) = self.event_listeners.wg_event_subscriber.recv() => {
+                self.check_exit_node_retry_handshake(&mesh_event.peer.public_key, mesh_event.state);
                 let node = self.peer_to_node(&mesh_event.peer, Some(mesh_event.state), mesh_event.link_state).await;
 
                 if let Some(node) = node {
+                    #[cfg(feature = "traffic_logging")]
+                    self.entities.traffic_log.set_path(node.public_key, node.path);
+
                     // Publish WG event to app
                     let _ = self.event_publishers.libtelio_event_publisher.send(
                         Box::new(Event::new::<Node>().set(node))
@@ -1873,9 +5617,22 @@ impl TaskRuntime for Runtime {
             },
 
             Ok(derp_event) = self.event_listeners.derp_event_subscriber.recv() => {
+                let connected = derp_event.conn_state == RelayState::Connected;
                 let _ = self.event_publishers.libtelio_event_publisher.send(
                     Box::new(Event::new::<DerpServer>().set(*derp_event))
                 );
+                if connected {
+                    self.check_clock_skew().await;
+                    let peers: Vec<PublicKey> = self
+                        .requested_state
+                        .meshnet_config
+                        .as_ref()
+                        .and_then(|c| c.peers.as_ref())
+                        .map(|peers| peers.iter().map(|p| p.public_key).collect())
+                        .unwrap_or_default();
+                    self.advertise_capabilities(peers.iter().copied()).await;
+                    self.reset_psks(peers).await;
+                }
                 Ok(())
             },
 
@@ -1890,6 +5647,96 @@ impl TaskRuntime for Runtime {
                 Ok(())
             },
 
+            Some((sender, msg)) = async move {
+                match application_rx {
+                    Some(rx) => rx.recv().await,
+                    None => futures::future::pending().await,
+                }
+            } => {
+                self.handle_custom_message(sender, msg.into_payload());
+                Ok(())
+            },
+
+            Some((sender, msg)) = async move {
+                match broadcast_rx {
+                    Some(rx) => rx.recv().await,
+                    None => futures::future::pending().await,
+                }
+            } => {
+                self.handle_custom_message(sender, msg.into_payload());
+                Ok(())
+            },
+
+            Some((sender, msg)) = async move {
+                match psk_rotate_rx {
+                    Some(rx) => rx.recv().await,
+                    None => futures::future::pending().await,
+                }
+            } => {
+                self.handle_psk_rotate_message(sender, msg).await;
+                Ok(())
+            },
+
+            Some((sender, _)) = async move {
+                match wake_rx {
+                    Some(rx) => rx.recv().await,
+                    None => futures::future::pending().await,
+                }
+            } => {
+                self.handle_wake_message(sender).await;
+                Ok(())
+            },
+
+            Some((sender, msg)) = async move {
+                match capability_rx {
+                    Some(rx) => rx.recv().await,
+                    None => futures::future::pending().await,
+                }
+            } => {
+                self.handle_capability_message(sender, msg).await;
+                Ok(())
+            },
+
+            Some((sender, msg)) = async move {
+                match auth_challenge_rx {
+                    Some(rx) => rx.recv().await,
+                    None => futures::future::pending().await,
+                }
+            } => {
+                self.handle_auth_challenge_message(sender, msg).await;
+                Ok(())
+            },
+
+            _ = async move {
+                match psk_rotation_interval {
+                    Some(interval) => interval.tick().await,
+                    None => futures::future::pending().await,
+                }
+            } => {
+                self.rotate_psks().await;
+                Ok(())
+            },
+
+            _ = async move {
+                match psk_retry_deadline {
+                    Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+                    None => futures::future::pending().await,
+                }
+            } => {
+                self.retry_psk_rotation().await;
+                Ok(())
+            },
+
+            _ = async move {
+                match exit_node_retry_deadline {
+                    Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+                    None => futures::future::pending().await,
+                }
+            } => {
+                self.check_exit_node_retry().await;
+                Ok(())
+            },
+
             Some(wg_stun_server) = self.event_listeners.stun_server_subscriber.recv() => {
                 telio_log_debug!("WG consolidation triggered by STUN server event");
 
@@ -1912,6 +5759,8 @@ impl TaskRuntime for Runtime {
                         |e| {
                             telio_log_warn!("WireGuard controller failure: {:?}. Ignoring", e);
                         });
+                self.check_exit_node_failover().await;
+                self.check_link_state().await;
                 Ok(())
             },
 
@@ -1925,28 +5774,6 @@ impl TaskRuntime for Runtime {
     }
 
     async fn stop(#[allow(unused_mut)] mut self) {
-        macro_rules! stop_entity {
-            ($entity: expr, $name: expr) => {{
-                if let Ok(task) = $entity
-                    .map(|m| m.stop())
-                    .map_err(|_| {
-                        telio_log_warn!(
-                            "Oops, smething went wrong! Something is holding a strong reference to the {:?} instance.",
-                            $name
-                        );
-                    }) {
-                    task.await;
-                } else {
-                    telio_log_warn!("Oops, something went wrong while stopping {:?} tasks.", $name);
-                }
-            }}
-        }
-        macro_rules! stop_arc_entity {
-            ($entity: expr, $name: expr) => {{
-                stop_entity!(Arc::try_unwrap($entity), $name)
-            }};
-        }
-
         let _ = self.stop_dns().await;
 
         // Nurse is keeping Arc to Derp, so we need to get rid of it before stopping Derp
@@ -1968,6 +5795,11 @@ impl TaskRuntime for Runtime {
     }
 }
 
+/// Check that `signature` of `data` was created by `Device::sign_data` of the owner of `public_key`
+pub fn verify_signed_data(public_key: &PublicKey, data: &[u8], signature: &[u8]) -> bool {
+    signature::verify(public_key, &[SIGN_DATA_CONTEXT, data].concat(), signature)
+}
+
 #[cfg(any(target_os = "macos", target_os = "ios", target_os = "tvos"))]
 fn set_tunnel_interface(socket_pool: &Arc<SocketPool>, config: &DeviceConfig) {
     let mut tunnel_if_index = None;
@@ -1999,45 +5831,354 @@ fn set_tunnel_interface(socket_pool: &Arc<SocketPool>, config: &DeviceConfig) {
     if let Some(tunnel_if_index) = tunnel_if_index {
         socket_pool.set_tunnel_interface(tunnel_if_index)
     }
-}
+}
+
+/// Base64 encoded SHA-256 hash of the public key
+fn device_fingerprint(public_key: &PublicKey) -> String {
+    base64::encode(Sha256::digest(public_key.0))
+}
+
+/// UUID v5 of the public key within the libtelio device namespace
+fn device_id(public_key: &PublicKey) -> Uuid {
+    Uuid::new_v5(&DEVICE_ID_NAMESPACE, &public_key.0)
+}
+
+/// Format interface in the `wg(8)` config file format, without secrets
+fn wireguard_config(interface: &Interface) -> String {
+    let mut conf = String::from("[Interface]\n");
+    if let Some(private_key) = interface.private_key.as_ref() {
+        let _ = writeln!(
+            conf,
+            "# PrivateKey is hidden, public key fingerprint: {}",
+            device_fingerprint(&private_key.public())
+        );
+    }
+    if let Some(listen_port) = interface.listen_port {
+        let _ = writeln!(conf, "ListenPort = {}", listen_port);
+    }
+    if interface.fwmark != 0 {
+        let _ = writeln!(conf, "FwMark = {:#x}", interface.fwmark);
+    }
+
+    for peer in interface.peers.values() {
+        let _ = writeln!(conf, "\n[Peer]\nPublicKey = {}", peer.public_key);
+        if peer.preshared_key.is_some() {
+            conf.push_str("# PresharedKey is hidden\n");
+        }
+        if !peer.allowed_ips.is_empty() {
+            let allowed_ips: Vec<String> =
+                peer.allowed_ips.iter().map(|ip| ip.to_string()).collect();
+            let _ = writeln!(conf, "AllowedIPs = {}", allowed_ips.join(", "));
+        }
+        if let Some(endpoint) = peer.endpoint {
+            let _ = writeln!(conf, "Endpoint = {}", endpoint);
+        }
+        if let Some(keepalive) = peer.persistent_keepalive_interval.filter(|&k| k > 0) {
+            let _ = writeln!(conf, "PersistentKeepalive = {}", keepalive);
+        }
+        if let Some(handshake) = peer.time_since_last_handshake {
+            let _ = writeln!(
+                conf,
+                "# Latest handshake: {} seconds ago",
+                handshake.as_secs()
+            );
+        }
+        if let (Some(rx), Some(tx)) = (peer.rx_bytes, peer.tx_bytes) {
+            let _ = writeln!(conf, "# Transfer: {} B received, {} B sent", rx, tx);
+        }
+    }
+
+    conf
+}
+
+/// Longest prefix match of `ip` against allowed IPs of the interface peers
+fn lookup_route(interface: &Interface, ip: IpAddr) -> Option<(&ipnetwork::IpNetwork, &uapi::Peer)> {
+    interface
+        .peers
+        .values()
+        .flat_map(|peer| {
+            peer.allowed_ips
+                .iter()
+                .filter(move |network| network.contains(ip))
+                .map(move |network| (network, peer))
+        })
+        .max_by_key(|(network, _)| network.prefix())
+}
+
+/// Hex encoded SHA-256 hash of the config serialized to JSON with sorted object keys
+fn meshnet_config_hash(config: &Config) -> Result<String> {
+    let canonical = serde_json::to_value(config)?.to_string();
+    Ok(format!("{:x}", Sha256::digest(canonical.as_bytes())))
+}
+
+/// Exit node connected by the failover, allowed ips are the defaults
+fn failover_exit_node(node: &FailoverExitNode) -> ExitNode {
+    ExitNode {
+        identifier: Uuid::new_v4().to_string(),
+        public_key: node.public_key,
+        allowed_ips: None,
+        endpoint: node.endpoint,
+    }
+}
+
+/// Fails if the config has more peers than allowed by `max_peers` feature
+fn check_max_peers(config: &Config, max_peers: Option<u32>) -> Result {
+    let count = config.peers.as_ref().map_or(0, |peers| peers.len());
+    match max_peers {
+        Some(max) if count > max as usize => Err(Error::TooManyPeers { count, max }),
+        _ => Ok(()),
+    }
+}
+
+/// Peers assigned an IP address of another node. This device keeps its own addresses, among
+/// peers the one with the lowest public key keeps the address.
+fn find_ip_conflicts(config: &Config) -> Vec<(PublicKey, IpAddr)> {
+    let mut owners: HashMap<IpAddr, PublicKey> = config
+        .this
+        .ip_addresses
+        .iter()
+        .flatten()
+        .map(|ip| (*ip, config.this.public_key))
+        .collect();
+    let mut peers: Vec<&Peer> = config.peers.iter().flatten().collect();
+    peers.sort_by_key(|peer| peer.public_key);
+
+    let mut conflicts = Vec::new();
+    for peer in peers {
+        for ip in peer.ip_addresses.iter().flatten() {
+            match owners.get(ip) {
+                Some(owner) if *owner != peer.public_key => conflicts.push((peer.public_key, *ip)),
+                Some(_) => (),
+                None => {
+                    owners.insert(*ip, peer.public_key);
+                }
+            }
+        }
+    }
+    conflicts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::*;
+    use std::net::Ipv6Addr;
+    use telio_model::api_config::{FeatureDirect, FeaturePostQuantumVPN};
+    use telio_model::config::{Peer, PeerBase};
+    use telio_model::mesh::PeerGroupPolicy;
+
+    fn build_peer_base(
+        hostname: String,
+        ip_addresses: Option<Vec<IpAddr>>,
+        nickname: Option<String>,
+    ) -> PeerBase {
+        PeerBase {
+            hostname: telio_utils::Hidden(hostname),
+            ip_addresses,
+            nickname,
+            ..Default::default()
+        }
+    }
+
+    fn build_peer(
+        hostname: String,
+        ip_addresses: Option<Vec<IpAddr>>,
+        nickname: Option<String>,
+    ) -> Peer {
+        Peer {
+            base: build_peer_base(hostname, ip_addresses, nickname),
+            ..Default::default()
+        }
+    }
+
+    fn build_mesh_config(peers: Option<Vec<Peer>>) -> Config {
+        Config {
+            peers,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_device_fingerprint() {
+        assert_eq!(
+            device_fingerprint(&PublicKey([0; 32])),
+            "Zmh6rfhivXdsj8GLjp+OIAiXFIVu4jOzkCpZHQ1fKSU="
+        );
+
+        let public_key = SecretKey::gen().public();
+        assert_eq!(
+            device_fingerprint(&public_key),
+            device_fingerprint(&public_key)
+        );
+        assert_ne!(device_fingerprint(&public_key), public_key.to_string());
+    }
+
+    #[test]
+    fn test_device_id() {
+        let public_key = SecretKey::gen().public();
+        let id = device_id(&public_key);
+        assert_eq!(id.get_version_num(), 5);
+        assert_eq!(id, device_id(&public_key));
+        assert_ne!(id, device_id(&SecretKey::gen().public()));
+    }
+
+    #[test]
+    fn test_verify_signed_data() {
+        let mut rng = rand::thread_rng();
+        let secret_key = SecretKey::gen();
+        let nonce = [7u8; 16];
+
+        let signature =
+            signature::sign(&secret_key, &[SIGN_DATA_CONTEXT, &nonce].concat(), &mut rng);
+        assert!(verify_signed_data(&secret_key.public(), &nonce, &signature));
+
+        // Signatures of libtelio protocols are not accepted as signed data and vice versa
+        let challenge_data = [AUTH_CHALLENGE_CONTEXT, &nonce].concat();
+        let challenge_answer = signature::sign(&secret_key, &challenge_data, &mut rng);
+        assert!(!verify_signed_data(
+            &secret_key.public(),
+            &nonce,
+            &challenge_answer
+        ));
+        assert!(!signature::verify(
+            &secret_key.public(),
+            &challenge_data,
+            &signature
+        ));
+        assert!(!verify_signed_data(
+            &secret_key.public(),
+            &challenge_data,
+            &challenge_answer
+        ));
+    }
+
+    #[test]
+    fn test_meshnet_config_hash() {
+        let config = build_mesh_config(None);
+        let hash = meshnet_config_hash(&config).unwrap();
+        assert_eq!(hash.len(), EMPTY_MESHNET_CONFIG_HASH.len());
+        assert_ne!(hash, EMPTY_MESHNET_CONFIG_HASH);
+        assert_eq!(hash, meshnet_config_hash(&build_mesh_config(None)).unwrap());
+
+        let other = build_mesh_config(Some(vec![Peer::default()]));
+        assert_ne!(hash, meshnet_config_hash(&other).unwrap());
+    }
+
+    #[test]
+    fn test_max_peers() {
+        let config = build_mesh_config(Some(vec![Peer::default(); 5]));
+
+        assert!(matches!(
+            check_max_peers(&config, Some(3)),
+            Err(Error::TooManyPeers { count: 5, max: 3 })
+        ));
+        assert!(check_max_peers(&config, Some(5)).is_ok());
+        assert!(check_max_peers(&config, None).is_ok());
+        assert!(check_max_peers(&build_mesh_config(None), Some(0)).is_ok());
+    }
+
+    #[test]
+    fn test_find_ip_conflicts() {
+        let peer = |key: u8, ip: &str| Peer {
+            base: PeerBase {
+                public_key: PublicKey([key; 32]),
+                ip_addresses: Some(vec![ip.parse().unwrap()]),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut config = build_mesh_config(Some(vec![
+            peer(3, "100.64.0.2"),
+            peer(2, "100.64.0.2"),
+            peer(4, "100.64.0.1"),
+            peer(5, "100.64.0.5"),
+        ]));
+        config.this.public_key = PublicKey([9; 32]);
+        config.this.ip_addresses = Some(vec!["100.64.0.1".parse().unwrap()]);
+
+        assert_eq!(
+            find_ip_conflicts(&config),
+            vec![
+                (PublicKey([3; 32]), "100.64.0.2".parse().unwrap()),
+                (PublicKey([4; 32]), "100.64.0.1".parse().unwrap()),
+            ]
+        );
+        assert!(
+            find_ip_conflicts(&build_mesh_config(Some(vec![peer(1, "100.64.0.1")]))).is_empty()
+        );
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use rstest::*;
-    use std::net::Ipv6Addr;
-    use telio_model::api_config::FeatureDirect;
-    use telio_model::config::{Peer, PeerBase};
+    #[test]
+    fn test_wireguard_config_hides_secrets() {
+        let secret_key = SecretKey::gen();
+        let preshared_key = telio_crypto::PresharedKey([7; 32]);
+        let peer_key = SecretKey::gen().public();
+        let interface = Interface {
+            private_key: Some(secret_key),
+            listen_port: Some(51820),
+            fwmark: 0,
+            peers: BTreeMap::from([(
+                peer_key,
+                uapi::Peer {
+                    public_key: peer_key,
+                    endpoint: Some(SocketAddr::from(([1, 2, 3, 4], 5678))),
+                    persistent_keepalive_interval: Some(25),
+                    allowed_ips: vec![ipnetwork::IpNetwork::from(IpAddr::from(Ipv4Addr::new(
+                        100, 64, 0, 2,
+                    )))],
+                    preshared_key: Some(preshared_key),
+                    ..Default::default()
+                },
+            )]),
+        };
 
-    fn build_peer_base(
-        hostname: String,
-        ip_addresses: Option<Vec<IpAddr>>,
-        nickname: Option<String>,
-    ) -> PeerBase {
-        PeerBase {
-            hostname: telio_utils::Hidden(hostname),
-            ip_addresses,
-            nickname,
-            ..Default::default()
-        }
+        let conf = wireguard_config(&interface);
+        assert_eq!(
+            conf,
+            format!(
+                "[Interface]\n\
+                 # PrivateKey is hidden, public key fingerprint: {}\n\
+                 ListenPort = 51820\n\
+                 \n\
+                 [Peer]\n\
+                 PublicKey = {}\n\
+                 # PresharedKey is hidden\n\
+                 AllowedIPs = 100.64.0.2/32\n\
+                 Endpoint = 1.2.3.4:5678\n\
+                 PersistentKeepalive = 25\n",
+                device_fingerprint(&secret_key.public()),
+                peer_key
+            )
+        );
+        assert!(!conf.contains(&secret_key.to_string()));
+        assert!(!conf.contains(&preshared_key.to_string()));
     }
 
-    fn build_peer(
-        hostname: String,
-        ip_addresses: Option<Vec<IpAddr>>,
-        nickname: Option<String>,
-    ) -> Peer {
-        Peer {
-            base: build_peer_base(hostname, ip_addresses, nickname),
+    #[test]
+    fn test_lookup_route_prefers_longest_prefix() {
+        let exit_key = SecretKey::gen().public();
+        let peer_key = SecretKey::gen().public();
+        let peer = |public_key, allowed_ips: &[&str]| uapi::Peer {
+            public_key,
+            allowed_ips: allowed_ips.iter().map(|ip| ip.parse().unwrap()).collect(),
             ..Default::default()
-        }
-    }
-
-    fn build_mesh_config(peers: Option<Vec<Peer>>) -> Config {
-        Config {
-            peers,
+        };
+        let interface = Interface {
+            peers: BTreeMap::from([
+                (exit_key, peer(exit_key, &["0.0.0.0/0", "100.64.0.1/32"])),
+                (peer_key, peer(peer_key, &["100.64.1.5/32"])),
+            ]),
             ..Default::default()
-        }
+        };
+
+        let route = |ip: &str| {
+            lookup_route(&interface, ip.parse().unwrap())
+                .map(|(network, peer)| (network.prefix(), peer.public_key))
+        };
+        assert_eq!(route("100.64.1.5"), Some((32, peer_key)));
+        assert_eq!(route("100.64.0.1"), Some((32, exit_key)));
+        assert_eq!(route("8.8.8.8"), Some((0, exit_key)));
+        assert_eq!(route("fd74:656c:696f::1"), None);
     }
 
     #[test]
@@ -2268,6 +6409,10 @@ mod tests {
             },
             features,
             None,
+            None,
+            Default::default(),
+            Default::default(),
+            Default::default(),
         )
         .await
         .unwrap();
@@ -2297,6 +6442,111 @@ mod tests {
         );
     }
 
+    #[cfg(not(windows))]
+    #[tokio::test(start_paused = true)]
+    async fn test_link_state_change_is_handled_as_network_change() {
+        let (sender, _receiver) = tokio::sync::broadcast::channel(1);
+        let mut rt = Runtime::start(
+            sender,
+            &DeviceConfig {
+                private_key: SecretKey::gen(),
+                ..Default::default()
+            },
+            Features::default(),
+            None,
+            None,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+
+        let link_state = Arc::new(std::sync::atomic::AtomicU64::new(1));
+        *rt.link_state_provider.write() = Some(Arc::new({
+            let link_state = link_state.clone();
+            move || link_state.load(std::sync::atomic::Ordering::Relaxed)
+        }));
+
+        // First poll only records the state
+        rt.check_link_state().await;
+        rt.check_link_state().await;
+        rt.test_env.adapter.lock().await.checkpoint();
+
+        link_state.store(2, std::sync::atomic::Ordering::Relaxed);
+        rt.test_env
+            .adapter
+            .lock()
+            .await
+            .expect_drop_connected_sockets()
+            .times(1)
+            .return_const(());
+        rt.check_link_state().await;
+        rt.check_link_state().await;
+        rt.test_env.adapter.lock().await.checkpoint();
+
+        // Unregistering the provider forgets the recorded state
+        *rt.link_state_provider.write() = None;
+        rt.check_link_state().await;
+        assert!(rt.link_state.is_none());
+    }
+
+    #[cfg(not(windows))]
+    #[tokio::test(start_paused = true)]
+    async fn test_notify_dns_servers_changed_requires_dns() {
+        let (sender, _receiver) = tokio::sync::broadcast::channel(1);
+        let mut rt = Runtime::start(
+            sender,
+            &DeviceConfig {
+                private_key: SecretKey::gen(),
+                ..Default::default()
+            },
+            Features::default(),
+            None,
+            None,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(
+            rt.notify_dns_servers_changed(&[IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1))])
+                .await,
+            Err(Error::DnsNotEnabled)
+        ));
+        assert!(rt.requested_state.upstream_servers.is_none());
+    }
+
+    #[cfg(not(windows))]
+    #[tokio::test(start_paused = true)]
+    async fn test_traffic_shaping_requires_boringtun() {
+        let (sender, _receiver) = tokio::sync::broadcast::channel(1);
+        let rt = Runtime::start(
+            sender,
+            &DeviceConfig {
+                private_key: SecretKey::gen(),
+                adapter: AdapterType::WireguardGo,
+                ..Default::default()
+            },
+            Features::default(),
+            None,
+            None,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+
+        assert!(matches!(
+            rt.enable_traffic_shaping(SecretKey::gen().public(), 100, 100)
+                .await,
+            Err(Error::TrafficShapingUnsupported)
+        ));
+    }
+
     #[cfg(not(windows))]
     #[tokio::test(start_paused = true)]
     async fn test_disconnect_exit_nodes() {
@@ -2313,6 +6563,10 @@ mod tests {
             },
             features,
             None,
+            None,
+            Default::default(),
+            Default::default(),
+            Default::default(),
         )
         .await
         .unwrap();
@@ -2328,6 +6582,7 @@ mod tests {
             hostname: telio_utils::Hidden("hostname".to_owned()),
             ip_addresses: Some(vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))]),
             nickname: Some("nickname".to_owned()),
+            os: None,
         };
         let get_config = Config {
             this: peer_base.clone(),
@@ -2337,6 +6592,7 @@ mod tests {
             }]),
             derp_servers: None,
             dns: None,
+            timestamp: None,
         };
 
         rt.test_env
@@ -2366,15 +6622,92 @@ mod tests {
         assert!(rt.requested_state.exit_node.is_some());
         rt.test_env.adapter.lock().await.checkpoint();
 
+        let connected = rt.get_connected_exit_nodes().await.unwrap();
+        assert_eq!(connected.len(), 1);
+        assert_eq!(connected[0].public_key, pubkey);
+        assert_eq!(
+            connected[0].allowed_ips,
+            Some(vec!["0.0.0.0/0".parse().unwrap()])
+        );
+
         rt.test_env
             .adapter
             .expect_send_uapi_cmd_generic_call(1)
             .await;
         assert!(rt.disconnect_exit_nodes().await.is_ok());
         assert!(rt.requested_state.exit_node.is_none());
+        assert!(rt.get_connected_exit_nodes().await.unwrap().is_empty());
         rt.test_env.adapter.lock().await.checkpoint();
     }
 
+    #[cfg(not(windows))]
+    #[tokio::test(start_paused = true)]
+    async fn test_exit_node_retry_after_failed_first_attempt() {
+        let (sender, mut receiver) = tokio::sync::broadcast::channel(16);
+        let features = Features {
+            // Nothing answers the post-quantum handshake, so every attempt fails before the
+            // exit node is requested
+            post_quantum_vpn: Some(FeaturePostQuantumVPN {
+                handshake_timeout_s: 1,
+            }),
+            ..Default::default()
+        };
+
+        let mut rt = Runtime::start(
+            sender,
+            &DeviceConfig {
+                private_key: SecretKey::gen(),
+                ..Default::default()
+            },
+            features,
+            None,
+            None,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+
+        let exit_node = ExitNode {
+            public_key: SecretKey::gen().public(),
+            endpoint: Some(([127, 0, 0, 1], 9).into()),
+            ..Default::default()
+        };
+        let policy = ExitNodeRetryPolicy {
+            max_attempts: 2,
+            initial_delay_ms: 1000,
+            backoff_factor: 1.0,
+        };
+
+        assert!(rt
+            .connect_exit_node_with_retry(&exit_node, policy)
+            .await
+            .is_ok());
+        assert!(rt.requested_state.exit_node.is_none());
+
+        rt.check_exit_node_retry().await;
+        assert_eq!(
+            rt.exit_node_retry
+                .as_ref()
+                .map(|retried| retried.retry.attempts()),
+            Some(2)
+        );
+
+        rt.check_exit_node_retry().await;
+        assert!(rt.exit_node_retry.is_none());
+
+        let mut failed = None;
+        while let Ok(event) = receiver.try_recv() {
+            if let Event::ExitNodeConnectionFailed { body: Some(body) } = *event {
+                failed = Some(body);
+            }
+        }
+        let failed = failed.unwrap();
+        assert_eq!(failed.peer_pk, exit_node.public_key.to_string());
+        assert_eq!(failed.attempts, 2);
+    }
+
     #[cfg(not(windows))]
     #[tokio::test(start_paused = true)]
     async fn test_duplicate_allowed_ips() {
@@ -2390,6 +6723,10 @@ mod tests {
             },
             features,
             None,
+            None,
+            Default::default(),
+            Default::default(),
+            Default::default(),
         )
         .await
         .unwrap();
@@ -2404,6 +6741,7 @@ mod tests {
                 hostname: telio_utils::Hidden("hostname".to_owned()),
                 ip_addresses: Some(vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))]),
                 nickname: Some("nickname".to_owned()),
+                os: None,
             },
             peers: Some(vec![
                 Peer {
@@ -2425,6 +6763,7 @@ mod tests {
             ]),
             derp_servers: None,
             dns: None,
+            timestamp: None,
         };
 
         let vpn_node = ExitNode {
@@ -2478,6 +6817,10 @@ mod tests {
             },
             features,
             None,
+            None,
+            Default::default(),
+            Default::default(),
+            Default::default(),
         )
         .await
         .unwrap();
@@ -2493,6 +6836,7 @@ mod tests {
             hostname: telio_utils::Hidden("hostname".to_owned()),
             ip_addresses: Some(vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))]),
             nickname: Some("nickname".to_owned()),
+            os: None,
         };
         let config = Some(Config {
             this: peer_base.clone(),
@@ -2502,6 +6846,7 @@ mod tests {
             }]),
             derp_servers: None,
             dns: None,
+            timestamp: None,
         });
 
         rt.test_env
@@ -2536,22 +6881,140 @@ mod tests {
 
         rt.test_env
             .adapter
-            .expect_send_uapi_cmd_generic_call(0)
+            .expect_send_uapi_cmd_generic_call(0)
+            .await;
+        assert!(rt.set_config(&config).await.is_ok());
+        assert_eq!(
+            rt.requested_state.exit_node.as_ref().unwrap().public_key,
+            pubkey
+        );
+        rt.test_env.adapter.lock().await.checkpoint();
+
+        rt.test_env
+            .adapter
+            .expect_send_uapi_cmd_generic_call(1)
+            .await;
+        assert!(rt.disconnect_exit_node(&pubkey).await.is_ok());
+        assert!(rt.requested_state.exit_node.is_none());
+        rt.test_env.adapter.lock().await.checkpoint();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_peer_group_exit_node() {
+        let (sender, _receiver) = tokio::sync::broadcast::channel(1);
+        let private_key = SecretKey::gen();
+
+        let mut rt = Runtime::start(
+            sender,
+            &DeviceConfig {
+                private_key,
+                ..Default::default()
+            },
+            Default::default(),
+            None,
+            None,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+
+        let pubkey = private_key.public();
+        let exit_pubkey = SecretKey::gen().public();
+        let config = Some(Config {
+            this: PeerBase {
+                public_key: pubkey,
+                ..Default::default()
+            },
+            peers: Some(vec![Peer {
+                base: PeerBase {
+                    public_key: exit_pubkey,
+                    ip_addresses: Some(vec![IpAddr::V4(Ipv4Addr::new(100, 64, 0, 2))]),
+                    ..Default::default()
+                },
+                ..Default::default()
+            }]),
+            derp_servers: None,
+            dns: None,
+            timestamp: None,
+        });
+        let group = PeerGroup {
+            id: "office".to_owned(),
+            members: vec![pubkey],
+            policy: PeerGroupPolicy {
+                exit_node: Some(exit_pubkey),
+                mtu: Some(1280),
+            },
+        };
+
+        assert!(matches!(
+            rt.create_peer_group(group.clone()).await,
+            Err(Error::MeshnetNotConfigured)
+        ));
+
+        rt.test_env
+            .adapter
+            .expect_send_uapi_cmd_generic_call(1)
+            .await;
+        rt.entities
+            .wireguard_interface
+            .set_listen_port(1234)
+            .await
+            .unwrap();
+        rt.test_env.adapter.lock().await.checkpoint();
+
+        rt.test_env
+            .adapter
+            .expect_send_uapi_cmd_generic_call(1)
+            .await;
+        assert!(rt.set_config(&config).await.is_ok());
+        rt.test_env.adapter.lock().await.checkpoint();
+
+        assert!(matches!(
+            rt.create_peer_group(PeerGroup {
+                members: vec![SecretKey::gen().public()],
+                ..group.clone()
+            })
+            .await,
+            Err(Error::InvalidPeerGroup(_))
+        ));
+        assert!(matches!(
+            rt.create_peer_group(PeerGroup {
+                policy: PeerGroupPolicy {
+                    mtu: Some(100),
+                    ..group.policy.clone()
+                },
+                ..group.clone()
+            })
+            .await,
+            Err(Error::InvalidPeerGroup(_))
+        ));
+        assert!(rt.list_peer_groups().await.unwrap().is_empty());
+
+        rt.test_env
+            .adapter
+            .expect_send_uapi_cmd_generic_call(1)
             .await;
-        assert!(rt.set_config(&config).await.is_ok());
-        assert_eq!(
-            rt.requested_state.exit_node.as_ref().unwrap().public_key,
-            pubkey
-        );
+        assert!(rt.create_peer_group(group.clone()).await.is_ok());
+        let exit_node = rt.requested_state.exit_node.as_ref().unwrap();
+        assert_eq!(exit_node.public_key, exit_pubkey);
+        assert_eq!(exit_node.identifier, "peer-group:office");
+        assert_eq!(rt.list_peer_groups().await.unwrap(), vec![group]);
         rt.test_env.adapter.lock().await.checkpoint();
 
         rt.test_env
             .adapter
             .expect_send_uapi_cmd_generic_call(1)
             .await;
-        assert!(rt.disconnect_exit_node(&pubkey).await.is_ok());
+        assert!(rt.delete_peer_group("office").await.is_ok());
         assert!(rt.requested_state.exit_node.is_none());
         rt.test_env.adapter.lock().await.checkpoint();
+
+        assert!(matches!(
+            rt.delete_peer_group("office").await,
+            Err(Error::PeerGroupNotFound)
+        ));
     }
 
     #[cfg(not(windows))]
@@ -2563,6 +7026,7 @@ mod tests {
                 providers: None,
                 endpoint_interval_secs: None,
                 skip_unresponsive_peers: Default::default(),
+                direct_path_latency_threshold_ms: None,
             }),
             ..Default::default()
         };
@@ -2577,6 +7041,10 @@ mod tests {
             },
             features,
             None,
+            None,
+            Default::default(),
+            Default::default(),
+            Default::default(),
         )
         .await
         .unwrap();
@@ -2593,6 +7061,7 @@ mod tests {
             hostname: telio_utils::Hidden("hostname".to_owned()),
             ip_addresses: Some(vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))]),
             nickname: Some("nickname".to_owned()),
+            os: None,
         };
         let config = Some(Config {
             this: peer_base.clone(),
@@ -2602,6 +7071,7 @@ mod tests {
             }]),
             derp_servers: None,
             dns: None,
+            timestamp: None,
         });
 
         rt.test_env
@@ -2643,6 +7113,7 @@ mod tests {
                 providers: Some(HashSet::<telio_model::api_config::EndpointProvider>::new()),
                 endpoint_interval_secs: None,
                 skip_unresponsive_peers: Default::default(),
+                direct_path_latency_threshold_ms: None,
             }),
             ..Default::default()
         };
@@ -2657,6 +7128,10 @@ mod tests {
             },
             features,
             None,
+            None,
+            Default::default(),
+            Default::default(),
+            Default::default(),
         )
         .await
         .unwrap();
@@ -2673,6 +7148,7 @@ mod tests {
             hostname: telio_utils::Hidden("hostname".to_owned()),
             ip_addresses: Some(vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))]),
             nickname: Some("nickname".to_owned()),
+            os: None,
         };
         let config = Some(Config {
             this: peer_base.clone(),
@@ -2682,6 +7158,7 @@ mod tests {
             }]),
             derp_servers: None,
             dns: None,
+            timestamp: None,
         });
 
         rt.test_env
@@ -2733,6 +7210,7 @@ mod tests {
                 skip_unresponsive_peers: Some(FeatureSkipUnresponsivePeers {
                     no_handshake_threshold_secs: 42,
                 }),
+                direct_path_latency_threshold_ms: None,
             }),
             ..Default::default()
         };
@@ -2747,6 +7225,10 @@ mod tests {
             },
             features,
             None,
+            None,
+            Default::default(),
+            Default::default(),
+            Default::default(),
         )
         .await
         .unwrap();
@@ -2763,6 +7245,7 @@ mod tests {
             hostname: telio_utils::Hidden("hostname".to_owned()),
             ip_addresses: Some(vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))]),
             nickname: Some("nickname".to_owned()),
+            os: None,
         };
         let config = Some(Config {
             this: peer_base.clone(),
@@ -2772,6 +7255,7 @@ mod tests {
             }]),
             derp_servers: None,
             dns: None,
+            timestamp: None,
         });
 
         rt.test_env
@@ -2804,6 +7288,113 @@ mod tests {
         assert!(entities.stun_endpoint_provider.is_some());
     }
 
+    #[cfg(not(windows))]
+    #[tokio::test(start_paused = true)]
+    async fn test_reenabled_direct_path_is_configured() {
+        let (sender, _receiver) = tokio::sync::broadcast::channel(1);
+
+        let mut providers = HashSet::<telio_model::api_config::EndpointProvider>::new();
+        providers.insert(telio_model::api_config::EndpointProvider::Stun);
+
+        let features = Features {
+            direct: Some(FeatureDirect {
+                providers: Some(providers),
+                endpoint_interval_secs: None,
+                skip_unresponsive_peers: None,
+                direct_path_latency_threshold_ms: None,
+            }),
+            ..Default::default()
+        };
+
+        let private_key = SecretKey::gen();
+
+        let mut rt = Runtime::start(
+            sender,
+            &DeviceConfig {
+                private_key,
+                ..Default::default()
+            },
+            features,
+            None,
+            None,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+
+        let peer_base = PeerBase {
+            identifier: "identifier".to_owned(),
+            public_key: private_key.public(),
+            hostname: telio_utils::Hidden("hostname".to_owned()),
+            ip_addresses: Some(vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))]),
+            nickname: None,
+            os: None,
+        };
+        let config = Some(Config {
+            this: peer_base.clone(),
+            peers: Some(vec![Peer {
+                base: peer_base,
+                ..Default::default()
+            }]),
+            derp_servers: Some(vec![DerpServer {
+                region_code: "lt".to_owned(),
+                name: "lt1".to_owned(),
+                hostname: "lt1.example.com".to_owned(),
+                ipv4: Ipv4Addr::new(127, 0, 0, 1),
+                relay_port: 8765,
+                stun_port: 3478,
+                stun_plaintext_port: 3479,
+                public_key: SecretKey::gen().public(),
+                weight: 1,
+                use_plain_text: true,
+                conn_state: RelayState::Disconnected,
+            }]),
+            dns: None,
+            timestamp: None,
+        });
+
+        rt.test_env
+            .adapter
+            .expect_send_uapi_cmd_generic_call(1)
+            .await;
+        rt.entities
+            .wireguard_interface
+            .set_listen_port(1234)
+            .await
+            .unwrap();
+        rt.test_env.adapter.lock().await.checkpoint();
+
+        rt.test_env
+            .adapter
+            .expect_send_uapi_cmd_generic_call(1)
+            .await;
+        rt.set_config(&config).await.unwrap();
+        rt.test_env.adapter.lock().await.checkpoint();
+
+        rt.test_env
+            .adapter
+            .expect_send_uapi_cmd_generic_call(2)
+            .await;
+        let stun_ep = |rt: &Runtime| {
+            rt.entities
+                .meshnet
+                .as_ref()
+                .and_then(|m| m.direct.as_ref())
+                .and_then(|direct| direct.stun_endpoint_provider.clone())
+        };
+        rt.set_direct_path(false).await.unwrap();
+        assert!(stun_ep(&rt).is_none());
+
+        rt.set_direct_path(true).await.unwrap();
+        rt.test_env.adapter.lock().await.checkpoint();
+
+        let stun_ep =
+            stun_ep(&rt).expect("STUN endpoint provider should be restarted with the direct path");
+        assert_eq!(stun_ep.get_servers().await.len(), 1);
+    }
+
     #[cfg(not(windows))]
     #[tokio::test(start_paused = true)]
     async fn test_set_config_with_wrong_public_key() {
@@ -2819,6 +7410,10 @@ mod tests {
             },
             features,
             None,
+            None,
+            Default::default(),
+            Default::default(),
+            Default::default(),
         )
         .await
         .unwrap();
@@ -2830,6 +7425,7 @@ mod tests {
                 hostname: telio_utils::Hidden("hostname".to_owned()),
                 ip_addresses: Some(vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))]),
                 nickname: Some("nickname".to_owned()),
+                os: None,
             };
             Config {
                 this: peer_base.clone(),
@@ -2839,6 +7435,7 @@ mod tests {
                 }]),
                 derp_servers: None,
                 dns: None,
+                timestamp: None,
             }
         }
 
@@ -2884,6 +7481,10 @@ mod tests {
             },
             features,
             None,
+            None,
+            Default::default(),
+            Default::default(),
+            Default::default(),
         )
         .await
         .unwrap();
@@ -2894,6 +7495,7 @@ mod tests {
             hostname: telio_utils::Hidden("hostname".to_owned()),
             ip_addresses: Some(vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))]),
             nickname: Some("nickname".to_owned()),
+            os: None,
         };
         let config = Config {
             this: peer_base.clone(),
@@ -2903,6 +7505,7 @@ mod tests {
             }]),
             derp_servers: None,
             dns: None,
+            timestamp: None,
         };
         let config = Some(config);
 
@@ -2935,6 +7538,198 @@ mod tests {
         ));
     }
 
+    #[cfg(not(windows))]
+    #[tokio::test(start_paused = true)]
+    async fn test_set_peer_nickname_is_validated() {
+        let (sender, _receiver) = tokio::sync::broadcast::channel(1);
+
+        let private_key = SecretKey::gen();
+        let peer_key = SecretKey::gen().public();
+        let mut rt = Runtime::start(
+            sender,
+            &DeviceConfig {
+                private_key,
+                ..Default::default()
+            },
+            Features::default(),
+            None,
+            None,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+
+        let config = Config {
+            this: PeerBase {
+                identifier: "this".to_owned(),
+                public_key: private_key.public(),
+                hostname: telio_utils::Hidden("this.nord".to_owned()),
+                ip_addresses: Some(vec![IpAddr::V4(Ipv4Addr::new(100, 64, 0, 1))]),
+                nickname: None,
+                os: None,
+            },
+            peers: Some(vec![Peer {
+                base: PeerBase {
+                    identifier: "peer".to_owned(),
+                    public_key: peer_key,
+                    hostname: telio_utils::Hidden("peer.nord".to_owned()),
+                    ip_addresses: Some(vec![IpAddr::V4(Ipv4Addr::new(100, 64, 0, 2))]),
+                    nickname: None,
+                    os: None,
+                },
+                ..Default::default()
+            }]),
+            derp_servers: None,
+            dns: None,
+            timestamp: None,
+        };
+        rt.test_env
+            .adapter
+            .expect_send_uapi_cmd_generic_call(1)
+            .await;
+        rt.set_config(&Some(config)).await.unwrap();
+        rt.test_env.adapter.lock().await.checkpoint();
+
+        for nickname in ["", "with space", "UpperCase", "ends-", "double--hyphen"] {
+            assert!(matches!(
+                rt.set_peer_nickname(peer_key, Some(nickname.to_owned()))
+                    .await,
+                Err(Error::InvalidNickname)
+            ));
+        }
+        assert_eq!(rt.get_peer_nickname(&peer_key).await.unwrap(), None);
+
+        rt.set_peer_nickname(peer_key, Some("johnny".to_owned()))
+            .await
+            .unwrap();
+        assert_eq!(
+            rt.get_peer_nickname(&peer_key).await.unwrap(),
+            Some("johnny".to_owned())
+        );
+        rt.set_peer_nickname(peer_key, None).await.unwrap();
+        assert_eq!(rt.get_peer_nickname(&peer_key).await.unwrap(), None);
+    }
+
+    #[cfg(not(windows))]
+    #[tokio::test(start_paused = true)]
+    async fn test_rotate_secret_key() {
+        let (sender, mut receiver) = tokio::sync::broadcast::channel(16);
+
+        let old_private_key = SecretKey::gen();
+        let new_private_key = SecretKey::gen();
+        let mut rt = Runtime::start(
+            sender,
+            &DeviceConfig {
+                private_key: old_private_key,
+                ..Default::default()
+            },
+            Features::default(),
+            None,
+            None,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+
+        rt.test_env
+            .adapter
+            .expect_send_uapi_cmd_generic_call(1)
+            .await;
+        rt.rotate_secret_key(&new_private_key).await.unwrap();
+        rt.test_env.adapter.lock().await.checkpoint();
+
+        match *receiver.try_recv().unwrap() {
+            Event::KeyRotated { body: Some(body) } => {
+                assert_eq!(body.old_public_key, old_private_key.public().to_string());
+                assert_eq!(body.new_public_key, new_private_key.public().to_string());
+            }
+            event => panic!("Unexpected event: {event:?}"),
+        }
+
+        let peer_base = PeerBase {
+            identifier: "identifier".to_owned(),
+            public_key: new_private_key.public(),
+            hostname: telio_utils::Hidden("hostname".to_owned()),
+            ip_addresses: Some(vec![IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))]),
+            nickname: None,
+            os: None,
+        };
+        let config = Config {
+            this: peer_base.clone(),
+            peers: Some(vec![Peer {
+                base: peer_base,
+                ..Default::default()
+            }]),
+            derp_servers: None,
+            dns: None,
+            timestamp: None,
+        };
+        rt.test_env
+            .adapter
+            .expect_send_uapi_cmd_generic_call(1)
+            .await;
+        rt.set_config(&Some(config)).await.unwrap();
+        rt.test_env.adapter.lock().await.checkpoint();
+
+        assert!(matches!(
+            rt.rotate_secret_key(&SecretKey::gen()).await.unwrap_err(),
+            Error::BadPublicKey
+        ));
+        assert_eq!(rt.get_private_key().await.unwrap(), new_private_key);
+        while let Ok(event) = receiver.try_recv() {
+            assert!(!matches!(*event, Event::KeyRotated { .. }));
+        }
+    }
+
+    #[cfg(all(not(windows), feature = "audit_log"))]
+    #[tokio::test(start_paused = true)]
+    async fn test_audit_log_reopens_after_key_rotation() {
+        let (sender, _receiver) = tokio::sync::broadcast::channel(1);
+        let mut rt = Runtime::start(
+            sender,
+            &DeviceConfig {
+                private_key: SecretKey::gen(),
+                ..Default::default()
+            },
+            Features::default(),
+            None,
+            None,
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+
+        let path = std::env::temp_dir().join(format!(
+            "telio-audit-rotation-{}.jsonl",
+            rand::random::<u64>()
+        ));
+        let audit_key = b"application audit key";
+        rt.set_audit_log_path(Some(path.clone()), audit_key)
+            .await
+            .unwrap();
+
+        rt.test_env
+            .adapter
+            .expect_send_uapi_cmd_generic_call(1)
+            .await;
+        rt.rotate_secret_key(&SecretKey::gen()).await.unwrap();
+        rt.test_env.adapter.lock().await.checkpoint();
+
+        rt.set_audit_log_path(None, &[]).await.unwrap();
+        rt.set_audit_log_path(Some(path.clone()), audit_key)
+            .await
+            .unwrap();
+        verify_audit_log(&path, audit_key).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap().lines().count(), 1);
+        let _ = std::fs::remove_file(&path);
+    }
+
     #[cfg(not(windows))]
     #[tokio::test(start_paused = true)]
     async fn test_disabling_meshnet_will_not_fail_if_wg_has_not_listen_port() {
@@ -2949,6 +7744,10 @@ mod tests {
             },
             Default::default(),
             None,
+            None,
+            Default::default(),
+            Default::default(),
+            Default::default(),
         )
         .await
         .unwrap();
@@ -2994,6 +7793,10 @@ mod tests {
             },
             features,
             None,
+            None,
+            Default::default(),
+            Default::default(),
+            Default::default(),
         )
         .await
         .unwrap();
@@ -3007,6 +7810,7 @@ mod tests {
                 IpAddr::V6(Ipv6Addr::new(0, 0, 0, 0, 0, 0xffff, 0xc00a, 0x2ff)),
             ]),
             nickname: Some("nickname".to_owned()),
+            os: None,
         };
         let config = Config {
             this: peer_base.clone(),
@@ -3016,6 +7820,7 @@ mod tests {
             }]),
             derp_servers: None,
             dns: None,
+            timestamp: None,
         };
 
         rt.test_env
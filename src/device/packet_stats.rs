@@ -0,0 +1,110 @@
+//! Per packet statistics hook, only built with `packet_stats` feature
+//!
+//! Hook is called from the packet processing threads of the WireGuard adapter for every packet
+//! accepted by the firewall and the traffic shaper, so it is read without locks or allocations.
+
+use std::{
+    ptr,
+    sync::atomic::{AtomicPtr, Ordering},
+};
+
+use parking_lot::Mutex;
+
+/// Direction of the packet, as seen from this device
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketDirection {
+    /// Packet received from the peer
+    Inbound = 0,
+    /// Packet sent to the peer
+    Outbound = 1,
+}
+
+/// Handler receiving direction, public key of the peer and size of every packet
+pub type PacketStatsHandler = Box<dyn Fn(PacketDirection, &[u8; 32], u32) + Send + Sync>;
+
+/// Hook shared between the runtime and the packet callbacks
+#[derive(Default)]
+pub struct PacketStatsHook {
+    handler: AtomicPtr<PacketStatsHandler>,
+    // Replaced handlers may still be running on packet threads, so they are freed with the hook
+    retired: Mutex<Vec<Box<PacketStatsHandler>>>,
+}
+
+impl PacketStatsHook {
+    /// Replace the handler, `None` disables the hook
+    pub fn set(&self, handler: Option<PacketStatsHandler>) {
+        let new = handler.map_or(ptr::null_mut(), |handler| Box::into_raw(Box::new(handler)));
+        let old = self.handler.swap(new, Ordering::AcqRel);
+        if !old.is_null() {
+            // SAFETY: non null handlers are created with Box::into_raw above
+            self.retired.lock().push(unsafe { Box::from_raw(old) });
+        }
+    }
+
+    /// Pass the packet to the handler, if one is set
+    pub fn record(&self, direction: PacketDirection, peer: &[u8; 32], packet: &[u8]) {
+        let handler = self.handler.load(Ordering::Acquire);
+        // SAFETY: handlers are freed only when the hook is dropped
+        if let Some(handler) = unsafe { handler.as_ref() } {
+            handler(direction, peer, packet.len() as u32);
+        }
+    }
+}
+
+impl Drop for PacketStatsHook {
+    fn drop(&mut self) {
+        let handler = *self.handler.get_mut();
+        if !handler.is_null() {
+            // SAFETY: non null handlers are created with Box::into_raw in `set`
+            drop(unsafe { Box::from_raw(handler) });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicU32, AtomicUsize},
+        Arc,
+    };
+
+    #[test]
+    fn packets_are_passed_to_the_latest_handler() {
+        let hook = PacketStatsHook::default();
+        hook.record(PacketDirection::Inbound, &[1; 32], &[0; 100]);
+
+        let first = Arc::new(AtomicUsize::new(0));
+        let bytes = Arc::new(AtomicU32::new(0));
+        hook.set(Some(Box::new({
+            let first = first.clone();
+            let bytes = bytes.clone();
+            move |direction, peer, len| {
+                assert_eq!(direction, PacketDirection::Outbound);
+                assert_eq!(peer, &[2; 32]);
+                first.fetch_add(1, Ordering::Relaxed);
+                bytes.fetch_add(len, Ordering::Relaxed);
+            }
+        })));
+        hook.record(PacketDirection::Outbound, &[2; 32], &[0; 100]);
+        hook.record(PacketDirection::Outbound, &[2; 32], &[0; 20]);
+        assert_eq!(first.load(Ordering::Relaxed), 2);
+        assert_eq!(bytes.load(Ordering::Relaxed), 120);
+
+        let second = Arc::new(AtomicUsize::new(0));
+        hook.set(Some(Box::new({
+            let second = second.clone();
+            move |_, _, _| {
+                second.fetch_add(1, Ordering::Relaxed);
+            }
+        })));
+        hook.record(PacketDirection::Inbound, &[2; 32], &[0; 100]);
+        assert_eq!(first.load(Ordering::Relaxed), 2);
+        assert_eq!(second.load(Ordering::Relaxed), 1);
+
+        hook.set(None);
+        hook.record(PacketDirection::Inbound, &[2; 32], &[0; 100]);
+        assert_eq!(second.load(Ordering::Relaxed), 1);
+    }
+}
@@ -0,0 +1,120 @@
+//! On-demand measurement of meshnet path quality, only built with `diagnostics` feature
+
+use std::{
+    io::{self, ErrorKind},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde::Serialize;
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+
+use super::traceroute::{echo_request, wait_for_reply, ProbeReply};
+
+/// Minimal time to wait for the reply of a single probe
+const MIN_PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Round trip time and packet loss measured to the meshnet peer
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct PathQuality {
+    /// Average round trip time of the answered probes, `None` if none were answered
+    pub avg_rtt_ms: Option<f64>,
+    /// Shortest round trip time of the answered probes
+    pub min_rtt_ms: Option<f64>,
+    /// Longest round trip time of the answered probes
+    pub max_rtt_ms: Option<f64>,
+    /// Percentage of probes which were not answered in time
+    pub packet_loss_pct: f64,
+}
+
+impl PathQuality {
+    /// Summarize round trip times of the probes, `None` stands for a lost probe
+    fn from_samples(samples: &[Option<Duration>]) -> Self {
+        let rtts: Vec<f64> = samples
+            .iter()
+            .flatten()
+            .map(|rtt| rtt.as_secs_f64() * 1000.0)
+            .collect();
+        let lost = samples.len() - rtts.len();
+        Self {
+            avg_rtt_ms: (!rtts.is_empty()).then(|| rtts.iter().sum::<f64>() / rtts.len() as f64),
+            min_rtt_ms: rtts.iter().copied().reduce(f64::min),
+            max_rtt_ms: rtts.iter().copied().reduce(f64::max),
+            packet_loss_pct: if samples.is_empty() {
+                0.0
+            } else {
+                lost as f64 * 100.0 / samples.len() as f64
+            },
+        }
+    }
+}
+
+/// Measure path quality to the meshnet `target` with `samples` ICMP echo requests
+///
+/// Probes are sent `interval` apart, each waiting for its reply for at least one second, so
+/// the call blocks for up to `samples * max(interval, 1s)`. Probes are sent through a raw
+/// socket, so the process needs privileges to open one.
+pub fn measure(target: Ipv4Addr, samples: u32, interval: Duration) -> io::Result<PathQuality> {
+    if samples == 0 || samples > u32::from(u16::MAX) {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "Invalid number of samples",
+        ));
+    }
+
+    let socket = Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4))?;
+    let target = SockAddr::from(SocketAddr::new(IpAddr::V4(target), 0));
+    let identifier: u16 = rand::random();
+    let timeout = interval.max(MIN_PROBE_TIMEOUT);
+
+    let mut rtts = Vec::with_capacity(samples as usize);
+    let mut next = Instant::now();
+    for sequence in 0..samples as u16 {
+        thread::sleep(next.saturating_duration_since(Instant::now()));
+        let sent = Instant::now();
+        next = sent + interval;
+        socket.send_to(&echo_request(identifier, sequence)?, &target)?;
+        let reply = wait_for_reply(&socket, identifier, sequence, sent + timeout)?;
+        rtts.push((reply == Some(ProbeReply::Reached)).then(|| sent.elapsed()));
+    }
+    Ok(PathQuality::from_samples(&rtts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summarize_samples() {
+        let quality = PathQuality::from_samples(&[
+            Some(Duration::from_millis(12)),
+            None,
+            Some(Duration::from_millis(18)),
+            Some(Duration::from_millis(15)),
+        ]);
+        assert_eq!(
+            quality,
+            PathQuality {
+                avg_rtt_ms: Some(15.0),
+                min_rtt_ms: Some(12.0),
+                max_rtt_ms: Some(18.0),
+                packet_loss_pct: 25.0,
+            }
+        );
+    }
+
+    #[test]
+    fn summarize_lost_samples() {
+        let quality = PathQuality::from_samples(&[None, None]);
+        assert_eq!(
+            quality,
+            PathQuality {
+                avg_rtt_ms: None,
+                min_rtt_ms: None,
+                max_rtt_ms: None,
+                packet_loss_pct: 100.0,
+            }
+        );
+    }
+}
@@ -0,0 +1,77 @@
+//! Description of the platform libtelio runs on, collected for diagnostics and bug reports
+
+use std::path::Path;
+
+use serde::Serialize;
+use telio_wg::AdapterType;
+
+/// Operating system, its version and availability of the tunnel drivers
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct PlatformInfo {
+    /// Operating system, e.g. `linux` or `macos`
+    pub os: String,
+    /// Kernel release reported by `uname`, `None` if unknown
+    pub os_version: Option<String>,
+    /// CPU architecture, e.g. `x86_64` or `aarch64`
+    pub arch: String,
+    /// WireGuard kernel module is loaded, so the native Linux adapter can be used
+    pub has_kernel_wireguard: bool,
+    /// Tunnel devices can be created by the adapters
+    pub tun_available: bool,
+}
+
+impl PlatformInfo {
+    /// Collect the information about the current platform
+    pub fn collect() -> Self {
+        Self {
+            os: std::env::consts::OS.to_owned(),
+            os_version: os_version(),
+            arch: std::env::consts::ARCH.to_owned(),
+            has_kernel_wireguard: AdapterType::available()
+                .iter()
+                .any(|adapter| matches!(adapter, AdapterType::LinuxNativeWg)),
+            tun_available: tun_available(),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn os_version() -> Option<String> {
+    let mut uts = std::mem::MaybeUninit::<libc::utsname>::zeroed();
+    // SAFETY: uname only writes to the passed struct
+    if unsafe { libc::uname(uts.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    // SAFETY: struct is initialized by the successful uname call, fields are nul terminated
+    let release = unsafe { std::ffi::CStr::from_ptr(uts.assume_init_ref().release.as_ptr()) };
+    Some(release.to_string_lossy().into_owned())
+}
+
+#[cfg(not(unix))]
+fn os_version() -> Option<String> {
+    None
+}
+
+fn tun_available() -> bool {
+    if cfg!(any(target_os = "linux", target_os = "android")) {
+        Path::new("/dev/net/tun").exists()
+    } else {
+        // utun is part of the darwin kernel, Windows adapters install their drivers on demand
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn platform_info_describes_current_platform() {
+        let info = PlatformInfo::collect();
+        assert_eq!(info.os, std::env::consts::OS);
+        assert_eq!(info.arch, std::env::consts::ARCH);
+        if cfg!(unix) {
+            assert!(info.os_version.map_or(false, |version| !version.is_empty()));
+        }
+    }
+}
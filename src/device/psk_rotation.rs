@@ -0,0 +1,636 @@
+//! Periodic rotation of WireGuard pre-shared keys of meshnet peers
+//!
+//! Of each pair of peers, the one with the greater public key initiates the rotation. It sends
+//! [`PskRotateMsg`] offer with a new key encrypted for the peer, which stores the key and
+//! acknowledges it. Initiator applies the key after receiving the acknowledgement and confirms
+//! it, only then the responder applies the key as well. Offers and confirmations are sent again
+//! until answered, since relayed messages may be lost.
+//!
+//! Both peers keep the previous key until a handshake with the new one succeeds, and fall back to
+//! it if none does in [`COMMIT_TIMEOUT`]. Negotiated keys are not persisted, so a restarted peer
+//! sends a reset to each of its peers, which then use no key with it until the next rotation.
+
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryInto,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use telio_crypto::{encryption, PresharedKey, PublicKey, SecretKey};
+use telio_proto::{PskRotateKind, PskRotateMsg};
+use thiserror::Error as TError;
+
+/// Interval of sending the offers and confirmations which were not answered
+pub const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Number of times an offer or a confirmation is sent again
+pub const MAX_RETRIES: u32 = 6;
+
+/// Time to wait for a handshake with the new key before falling back to the previous one,
+/// longer than the WireGuard rekey interval
+pub const COMMIT_TIMEOUT: Duration = Duration::from_secs(180);
+
+#[derive(Debug, TError)]
+pub enum Error {
+    #[error("Pre-shared key offer from a peer which is not the initiator")]
+    UnexpectedOffer,
+    #[error("Acknowledgement of a pre-shared key which was not offered")]
+    UnexpectedAck,
+    #[error("Confirmation of a pre-shared key which was not acknowledged")]
+    UnexpectedConfirm,
+    #[error("Replayed pre-shared key message")]
+    Replayed,
+    #[error("Invalid pre-shared key length")]
+    InvalidLength,
+    #[error(transparent)]
+    Encryption(#[from] encryption::Error),
+    #[error(transparent)]
+    Codec(#[from] telio_proto::CodecError),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Change of the pre-shared key used with a peer
+#[derive(Debug, PartialEq, Eq)]
+pub enum KeyChange {
+    /// Newly negotiated key is used
+    Rotated(PresharedKey),
+    /// No handshake succeeded with the new key, the previous one, if any, is used again
+    Reverted(Option<PresharedKey>),
+    /// Peer has no negotiated key, no key is used
+    Reset,
+}
+
+/// Offer of the initiator waiting for the acknowledgement
+#[derive(Debug)]
+struct Offer {
+    sequence: u64,
+    psk: PresharedKey,
+    msg: PskRotateMsg,
+    retries: u32,
+    retry_at: Instant,
+}
+
+/// Applied key waiting for a handshake with the peer
+#[derive(Debug)]
+struct Commit {
+    sequence: u64,
+    initiator: bool,
+    previous: Option<PresharedKey>,
+    applied: Instant,
+    retries: u32,
+    retry_at: Instant,
+}
+
+/// Negotiation state of the pre-shared keys with all meshnet peers
+#[derive(Debug, Default)]
+pub struct PskRotation {
+    // Sequence of the last offer or reset sent
+    sequence: u64,
+    // Keys offered to the peers and not yet acknowledged
+    pending: HashMap<PublicKey, Offer>,
+    // Keys offered by the peers and not yet confirmed
+    staged: HashMap<PublicKey, (u64, PresharedKey)>,
+    // Applied keys without a handshake yet
+    committing: HashMap<PublicKey, Commit>,
+    // Sequence of the last offer accepted from each peer
+    accepted: HashMap<PublicKey, u64>,
+    // Sequence of the last reset received from each peer
+    resets: HashMap<PublicKey, u64>,
+    // Peers the reset was sent to since the start
+    announced: HashSet<PublicKey>,
+}
+
+/// Whether the local peer initiates the rotation of the key shared with `peer`
+pub fn is_initiator(local: &PublicKey, peer: &PublicKey) -> bool {
+    local > peer
+}
+
+impl PskRotation {
+    /// Offer new keys to the peers for which the local peer is the initiator
+    ///
+    /// Previous unacknowledged offers are forgotten. Peers with a key still waiting for a
+    /// handshake are skipped.
+    pub fn rotate(
+        &mut self,
+        local_sk: &SecretKey,
+        peers: impl IntoIterator<Item = PublicKey>,
+        now: Instant,
+    ) -> Result<Vec<(PublicKey, PskRotateMsg)>> {
+        let local_pk = local_sk.public();
+        self.pending.clear();
+
+        let mut offers = Vec::new();
+        for peer in peers {
+            if !is_initiator(&local_pk, &peer) || self.committing.contains_key(&peer) {
+                continue;
+            }
+            offers.push((peer, self.offer(local_sk, peer, now)?));
+        }
+        Ok(offers)
+    }
+
+    /// Resets of the negotiated keys for the peers which did not get one since the start
+    pub fn reset(
+        &mut self,
+        peers: impl IntoIterator<Item = PublicKey>,
+    ) -> Vec<(PublicKey, PskRotateMsg)> {
+        let mut resets = Vec::new();
+        for peer in peers {
+            if self.announced.insert(peer) {
+                resets.push((peer, PskRotateMsg::reset(self.next_sequence())));
+            }
+        }
+        resets
+    }
+
+    /// Handle message received from `sender`, with which `current` key is used
+    ///
+    /// Returns the change of the key used with the peer and the message which should be sent
+    /// back.
+    pub fn handle(
+        &mut self,
+        local_sk: &SecretKey,
+        sender: PublicKey,
+        current: Option<PresharedKey>,
+        msg: PskRotateMsg,
+        now: Instant,
+    ) -> Result<(Option<KeyChange>, Option<PskRotateMsg>)> {
+        let sequence = msg.get_sequence();
+        match msg.get_kind() {
+            PskRotateKind::Offer => {
+                if is_initiator(&local_sk.public(), &sender) {
+                    return Err(Error::UnexpectedOffer);
+                }
+                // Offer is sent again if the acknowledgement was lost
+                if matches!(self.staged.get(&sender), Some((staged, _)) if *staged == sequence) {
+                    return Ok((None, Some(PskRotateMsg::ack(sequence))));
+                }
+                if self
+                    .accepted
+                    .get(&sender)
+                    .map_or(false, |last| *last >= sequence)
+                {
+                    return Err(Error::Replayed);
+                }
+                let psk = encryption::decrypt_response(msg.get_encrypted_psk(), local_sk, &sender)?;
+                let psk = PresharedKey::new(
+                    psk.as_slice()
+                        .try_into()
+                        .map_err(|_| Error::InvalidLength)?,
+                );
+                self.staged.insert(sender, (sequence, psk));
+                Ok((None, Some(PskRotateMsg::ack(sequence))))
+            }
+            PskRotateKind::Ack => {
+                match self.pending.get(&sender) {
+                    Some(offer) if offer.sequence == sequence => {
+                        let psk = offer.psk;
+                        self.pending.remove(&sender);
+                        self.commit(sender, sequence, true, current, now);
+                        return Ok((
+                            Some(KeyChange::Rotated(psk)),
+                            Some(PskRotateMsg::confirm(sequence)),
+                        ));
+                    }
+                    _ => (),
+                }
+                // Acknowledgement of the offer sent again, the confirmation may have been lost
+                match self.committing.get(&sender) {
+                    Some(commit) if commit.initiator && commit.sequence == sequence => {
+                        Ok((None, Some(PskRotateMsg::confirm(sequence))))
+                    }
+                    _ => Err(Error::UnexpectedAck),
+                }
+            }
+            PskRotateKind::Confirm => {
+                if is_initiator(&local_sk.public(), &sender) {
+                    return Err(Error::UnexpectedConfirm);
+                }
+                match self.staged.get(&sender) {
+                    Some((staged, psk)) if *staged == sequence => {
+                        let psk = *psk;
+                        self.staged.remove(&sender);
+                        self.accepted.insert(sender, sequence);
+                        self.commit(sender, sequence, false, current, now);
+                        Ok((Some(KeyChange::Rotated(psk)), None))
+                    }
+                    _ if self.accepted.get(&sender) == Some(&sequence) => Ok((None, None)),
+                    _ => Err(Error::UnexpectedConfirm),
+                }
+            }
+            PskRotateKind::Reset => {
+                if self
+                    .resets
+                    .get(&sender)
+                    .map_or(false, |last| *last >= sequence)
+                {
+                    return Err(Error::Replayed);
+                }
+                self.resets.insert(sender, sequence);
+                self.pending.remove(&sender);
+                self.staged.remove(&sender);
+                self.committing.remove(&sender);
+
+                let change = current.map(|_| KeyChange::Reset);
+                // Restarted peer is reachable, so there is no reason to wait for the next rotation
+                let offer = if is_initiator(&local_sk.public(), &sender) {
+                    Some(self.offer(local_sk, sender, now)?)
+                } else {
+                    None
+                };
+                Ok((change, offer))
+            }
+        }
+    }
+
+    /// Time of the next call to [`PskRotation::retry`], if any is needed
+    pub fn next_retry(&self) -> Option<Instant> {
+        self.pending
+            .values()
+            .map(|offer| offer.retry_at)
+            .chain(self.committing.values().map(|commit| commit.retry_at))
+            .min()
+    }
+
+    /// Send again unanswered messages and finish commits of the applied keys
+    ///
+    /// `handshake_since` tells whether a handshake with the peer succeeded after the given time.
+    /// Returns the changes of the keys and the messages which should be sent to the peers.
+    pub fn retry(
+        &mut self,
+        now: Instant,
+        handshake_since: impl Fn(&PublicKey, Instant) -> bool,
+    ) -> Vec<(PublicKey, Option<KeyChange>, Option<PskRotateMsg>)> {
+        let mut steps = Vec::new();
+
+        self.pending.retain(|peer, offer| {
+            if offer.retry_at > now {
+                return true;
+            }
+            // Peer is unreachable, both sides keep the previous key
+            if offer.retries >= MAX_RETRIES {
+                return false;
+            }
+            offer.retries += 1;
+            offer.retry_at = now + RETRY_INTERVAL;
+            steps.push((*peer, None, Some(offer.msg.clone())));
+            true
+        });
+
+        self.committing.retain(|peer, commit| {
+            if commit.retry_at > now {
+                return true;
+            }
+            if handshake_since(peer, commit.applied) {
+                return false;
+            }
+            if now >= commit.applied + COMMIT_TIMEOUT {
+                steps.push((*peer, Some(KeyChange::Reverted(commit.previous)), None));
+                return false;
+            }
+            commit.retry_at = now + RETRY_INTERVAL;
+            if commit.initiator && commit.retries < MAX_RETRIES {
+                commit.retries += 1;
+                steps.push((*peer, None, Some(PskRotateMsg::confirm(commit.sequence))));
+            }
+            true
+        });
+
+        steps
+    }
+
+    fn offer(
+        &mut self,
+        local_sk: &SecretKey,
+        peer: PublicKey,
+        now: Instant,
+    ) -> Result<PskRotateMsg> {
+        let sequence = self.next_sequence();
+        let psk = PresharedKey::new(rand::random());
+        let encrypted =
+            encryption::encrypt_response(&psk.0, &mut rand::thread_rng(), local_sk, &peer)?;
+        let msg = PskRotateMsg::offer(sequence, &encrypted)?;
+        self.pending.insert(
+            peer,
+            Offer {
+                sequence,
+                psk,
+                msg: msg.clone(),
+                retries: 0,
+                retry_at: now + RETRY_INTERVAL,
+            },
+        );
+        Ok(msg)
+    }
+
+    fn commit(
+        &mut self,
+        peer: PublicKey,
+        sequence: u64,
+        initiator: bool,
+        previous: Option<PresharedKey>,
+        now: Instant,
+    ) {
+        self.committing.insert(
+            peer,
+            Commit {
+                sequence,
+                initiator,
+                previous,
+                applied: now,
+                retries: 0,
+                retry_at: now + RETRY_INTERVAL,
+            },
+        );
+    }
+
+    fn next_sequence(&mut self) -> u64 {
+        // Unix time keeps sequence increasing across restarts of the device
+        self.sequence = (self.sequence + 1).max(unix_millis());
+        self.sequence
+    }
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ordered_keys() -> (SecretKey, SecretKey) {
+        let a = SecretKey::gen();
+        let b = SecretKey::gen();
+        if a.public() > b.public() {
+            (a, b)
+        } else {
+            (b, a)
+        }
+    }
+
+    fn rotated(change: Option<KeyChange>) -> PresharedKey {
+        match change {
+            Some(KeyChange::Rotated(psk)) => psk,
+            change => panic!("Key not rotated: {:?}", change),
+        }
+    }
+
+    #[test]
+    fn rotation_is_triggered_only_by_initiator() {
+        let (initiator, responder) = ordered_keys();
+        let mut rotation = PskRotation::default();
+
+        let offers = rotation
+            .rotate(&initiator, vec![responder.public()], Instant::now())
+            .unwrap();
+        assert_eq!(offers.len(), 1);
+        assert_eq!(offers[0].0, responder.public());
+        assert_eq!(offers[0].1.get_kind(), PskRotateKind::Offer);
+
+        let mut rotation = PskRotation::default();
+        assert!(rotation
+            .rotate(&responder, vec![initiator.public()], Instant::now())
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn negotiate_key() {
+        let (initiator, responder) = ordered_keys();
+        let mut initiator_rotation = PskRotation::default();
+        let mut responder_rotation = PskRotation::default();
+        let now = Instant::now();
+
+        let (_, offer) = initiator_rotation
+            .rotate(&initiator, vec![responder.public()], now)
+            .unwrap()
+            .remove(0);
+        let (change, ack) = responder_rotation
+            .handle(&responder, initiator.public(), None, offer.clone(), now)
+            .unwrap();
+        // Responder keeps the previous key until the confirmation
+        assert_eq!(change, None);
+        let ack = ack.unwrap();
+        assert_eq!(ack.get_kind(), PskRotateKind::Ack);
+        assert_eq!(ack.get_sequence(), offer.get_sequence());
+
+        let (change, confirm) = initiator_rotation
+            .handle(&initiator, responder.public(), None, ack.clone(), now)
+            .unwrap();
+        let initiator_psk = rotated(change);
+        let confirm = confirm.unwrap();
+        assert_eq!(confirm.get_kind(), PskRotateKind::Confirm);
+
+        let (change, reply) = responder_rotation
+            .handle(&responder, initiator.public(), None, confirm.clone(), now)
+            .unwrap();
+        assert!(reply.is_none());
+        assert_eq!(rotated(change), initiator_psk);
+
+        // Handshake with the new key finishes the commit on both sides
+        for rotation in [&mut initiator_rotation, &mut responder_rotation] {
+            assert!(rotation.retry(now + RETRY_INTERVAL, |_, _| true).is_empty());
+            assert_eq!(rotation.next_retry(), None);
+        }
+
+        // Replayed messages do not change the key
+        assert!(matches!(
+            responder_rotation.handle(&responder, initiator.public(), None, offer, now),
+            Err(Error::Replayed)
+        ));
+        assert!(matches!(
+            responder_rotation.handle(&responder, initiator.public(), None, confirm, now),
+            Ok((None, None))
+        ));
+        assert!(matches!(
+            initiator_rotation.handle(&initiator, responder.public(), None, ack, now),
+            Err(Error::UnexpectedAck)
+        ));
+    }
+
+    #[test]
+    fn lost_ack_is_recovered_by_offering_again() {
+        let (initiator, responder) = ordered_keys();
+        let mut initiator_rotation = PskRotation::default();
+        let mut responder_rotation = PskRotation::default();
+        let previous = Some(PresharedKey::new([1; 32]));
+        let now = Instant::now();
+
+        let (_, offer) = initiator_rotation
+            .rotate(&initiator, vec![responder.public()], now)
+            .unwrap()
+            .remove(0);
+        // Acknowledgement is lost
+        let (change, _) = responder_rotation
+            .handle(&responder, initiator.public(), previous, offer.clone(), now)
+            .unwrap();
+        assert_eq!(change, None);
+
+        assert_eq!(initiator_rotation.next_retry(), Some(now + RETRY_INTERVAL));
+        let now = now + RETRY_INTERVAL;
+        let mut steps = initiator_rotation.retry(now, |_, _| false);
+        assert_eq!(steps.len(), 1);
+        let (peer, change, retried) = steps.remove(0);
+        assert_eq!(peer, responder.public());
+        assert_eq!(change, None);
+        assert_eq!(retried, Some(offer));
+
+        let (change, ack) = responder_rotation
+            .handle(
+                &responder,
+                initiator.public(),
+                previous,
+                retried.unwrap(),
+                now,
+            )
+            .unwrap();
+        assert_eq!(change, None);
+        let (change, confirm) = initiator_rotation
+            .handle(&initiator, responder.public(), previous, ack.unwrap(), now)
+            .unwrap();
+        let initiator_psk = rotated(change);
+        let (change, _) = responder_rotation
+            .handle(
+                &responder,
+                initiator.public(),
+                previous,
+                confirm.unwrap(),
+                now,
+            )
+            .unwrap();
+        assert_eq!(rotated(change), initiator_psk);
+    }
+
+    #[test]
+    fn offer_is_abandoned_after_retries() {
+        let (initiator, responder) = ordered_keys();
+        let mut rotation = PskRotation::default();
+        let mut now = Instant::now();
+
+        rotation
+            .rotate(&initiator, vec![responder.public()], now)
+            .unwrap();
+        for _ in 0..MAX_RETRIES {
+            now += RETRY_INTERVAL;
+            assert_eq!(rotation.retry(now, |_, _| false).len(), 1);
+        }
+        now += RETRY_INTERVAL;
+        assert!(rotation.retry(now, |_, _| false).is_empty());
+        assert_eq!(rotation.next_retry(), None);
+    }
+
+    #[test]
+    fn fall_back_to_previous_key_without_handshake() {
+        let (initiator, responder) = ordered_keys();
+        let mut initiator_rotation = PskRotation::default();
+        let mut responder_rotation = PskRotation::default();
+        let previous = Some(PresharedKey::new([1; 32]));
+        let start = Instant::now();
+
+        let (_, offer) = initiator_rotation
+            .rotate(&initiator, vec![responder.public()], start)
+            .unwrap()
+            .remove(0);
+        let (_, ack) = responder_rotation
+            .handle(&responder, initiator.public(), previous, offer, start)
+            .unwrap();
+        // Confirmation is lost, so the responder still uses the previous key
+        let (change, _) = initiator_rotation
+            .handle(
+                &initiator,
+                responder.public(),
+                previous,
+                ack.unwrap(),
+                start,
+            )
+            .unwrap();
+        rotated(change);
+
+        let mut now = start;
+        let mut confirms = 0;
+        let mut reverted = false;
+        while now < start + COMMIT_TIMEOUT {
+            now += RETRY_INTERVAL;
+            for (peer, change, msg) in initiator_rotation.retry(now, |_, _| false) {
+                assert_eq!(peer, responder.public());
+                match change {
+                    Some(change) => {
+                        assert_eq!(change, KeyChange::Reverted(previous));
+                        assert!(now >= start + COMMIT_TIMEOUT);
+                        reverted = true;
+                    }
+                    None => {
+                        assert_eq!(msg.unwrap().get_kind(), PskRotateKind::Confirm);
+                        confirms += 1;
+                    }
+                }
+            }
+        }
+        assert_eq!(confirms, MAX_RETRIES);
+        assert!(reverted);
+        assert_eq!(initiator_rotation.next_retry(), None);
+    }
+
+    #[test]
+    fn reset_drops_key_of_restarted_peer() {
+        let (initiator, responder) = ordered_keys();
+        let psk = Some(PresharedKey::new([1; 32]));
+        let now = Instant::now();
+
+        // Restarted initiator
+        let mut restarted = PskRotation::default();
+        let mut resets = restarted.reset(vec![responder.public()]);
+        assert!(restarted.reset(vec![responder.public()]).is_empty());
+        let (_, reset) = resets.remove(0);
+        assert_eq!(reset.get_kind(), PskRotateKind::Reset);
+
+        let mut responder_rotation = PskRotation::default();
+        assert!(matches!(
+            responder_rotation.handle(&responder, initiator.public(), psk, reset.clone(), now),
+            Ok((Some(KeyChange::Reset), None))
+        ));
+        assert!(matches!(
+            responder_rotation.handle(&responder, initiator.public(), None, reset, now),
+            Err(Error::Replayed)
+        ));
+
+        // Restarted responder gets a new offer right away
+        let (_, reset) = PskRotation::default()
+            .reset(vec![initiator.public()])
+            .remove(0);
+        let (change, offer) = PskRotation::default()
+            .handle(&initiator, responder.public(), psk, reset, now)
+            .unwrap();
+        assert_eq!(change, Some(KeyChange::Reset));
+        assert_eq!(offer.unwrap().get_kind(), PskRotateKind::Offer);
+    }
+
+    #[test]
+    fn reject_offer_from_responder() {
+        let (initiator, responder) = ordered_keys();
+        let encrypted = encryption::encrypt_response(
+            &[1; 32],
+            &mut rand::thread_rng(),
+            &responder,
+            &initiator.public(),
+        )
+        .unwrap();
+        let offer = PskRotateMsg::offer(1, &encrypted).unwrap();
+
+        assert!(matches!(
+            PskRotation::default().handle(
+                &initiator,
+                responder.public(),
+                None,
+                offer,
+                Instant::now()
+            ),
+            Err(Error::UnexpectedOffer)
+        ));
+    }
+}
@@ -0,0 +1,197 @@
+//! Traceroute of meshnet paths, only built with `diagnostics` feature
+
+use std::{
+    io::{self, ErrorKind, Read},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use pnet_packet::{
+    icmp::{
+        checksum,
+        echo_reply::EchoReplyPacket,
+        echo_request::{EchoRequestPacket, MutableEchoRequestPacket},
+        time_exceeded::TimeExceededPacket,
+        IcmpPacket, IcmpTypes,
+    },
+    ipv4::Ipv4Packet,
+    Packet,
+};
+use serde::Serialize;
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use telio_model::api_config::PathType;
+
+/// Size of the payload carried by each probe
+const PROBE_PAYLOAD_SIZE: usize = 32;
+/// Buffer large enough to hold any ICMP reply to the probe
+const RECV_BUFFER_SIZE: usize = 1500;
+/// Longest wait for the reply of a single hop, longer timeouts are cut down to it
+pub const MAX_HOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Single hop of the traced path
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TracerouteHop {
+    /// Hop number, equal to the TTL of the probe
+    pub hop: u8,
+    /// Path used by the tunnel to the traced peer
+    pub via: PathType,
+    /// Round trip time of the probe, `None` if the hop did not reply in time
+    pub rtt_ms: Option<u64>,
+}
+
+/// Result of the meshnet traceroute
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct TracerouteReport {
+    /// Probed hops, the last one is the target if it was reached
+    pub hops: Vec<TracerouteHop>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum ProbeReply {
+    TimeExceeded,
+    Reached,
+}
+
+/// Trace the path to the meshnet `target` with ICMP echo requests of increasing TTL
+///
+/// Blocks for up to `max_hops * timeout`, `timeout` is capped at `MAX_HOP_TIMEOUT`. Probes are sent through a raw socket, so the
+/// process needs privileges to open one. `via` is reported for every hop as the probes
+/// follow the tunnel to the traced peer.
+pub fn traceroute(
+    target: Ipv4Addr,
+    via: PathType,
+    max_hops: u8,
+    timeout: Duration,
+) -> io::Result<TracerouteReport> {
+    let socket = Socket::new(Domain::IPV4, Type::RAW, Some(Protocol::ICMPV4))?;
+    let target = SockAddr::from(SocketAddr::new(IpAddr::V4(target), 0));
+    let identifier: u16 = rand::random();
+    let timeout = timeout.min(MAX_HOP_TIMEOUT);
+
+    let mut report = TracerouteReport::default();
+    for hop in 1..=max_hops {
+        socket.set_ttl(u32::from(hop))?;
+        let sent = Instant::now();
+        socket.send_to(&echo_request(identifier, hop.into())?, &target)?;
+        let reply = wait_for_reply(&socket, identifier, hop.into(), sent + timeout)?;
+        report.hops.push(TracerouteHop {
+            hop,
+            via,
+            rtt_ms: reply.map(|_| sent.elapsed().as_millis() as u64),
+        });
+        if reply == Some(ProbeReply::Reached) {
+            break;
+        }
+    }
+    Ok(report)
+}
+
+pub(super) fn echo_request(identifier: u16, sequence: u16) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0u8; EchoRequestPacket::minimum_packet_size() + PROBE_PAYLOAD_SIZE];
+    let mut request = MutableEchoRequestPacket::new(&mut buf).ok_or(ErrorKind::InvalidInput)?;
+    request.set_icmp_type(IcmpTypes::EchoRequest);
+    request.set_identifier(identifier);
+    request.set_sequence_number(sequence);
+    let checksum = checksum(&IcmpPacket::new(request.packet()).ok_or(ErrorKind::InvalidInput)?);
+    request.set_checksum(checksum);
+    Ok(buf)
+}
+
+pub(super) fn wait_for_reply(
+    mut socket: &Socket,
+    identifier: u16,
+    sequence: u16,
+    deadline: Instant,
+) -> io::Result<Option<ProbeReply>> {
+    let mut buf = [0u8; RECV_BUFFER_SIZE];
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Ok(None);
+        }
+        socket.set_read_timeout(Some(remaining))?;
+        let len = match socket.read(&mut buf) {
+            Ok(len) => len,
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                return Ok(None)
+            }
+            Err(err) => return Err(err),
+        };
+        let packet = buf.get(..len).ok_or(ErrorKind::InvalidData)?;
+        if let Some(reply) = parse_reply(packet, identifier, sequence) {
+            return Ok(Some(reply));
+        }
+    }
+}
+
+/// Match received IPv4 packet against the probe, other ICMP traffic is ignored
+fn parse_reply(packet: &[u8], identifier: u16, sequence: u16) -> Option<ProbeReply> {
+    let ip = Ipv4Packet::new(packet)?;
+    let icmp = IcmpPacket::new(ip.payload())?;
+    match icmp.get_icmp_type() {
+        IcmpTypes::EchoReply => {
+            let reply = EchoReplyPacket::new(ip.payload())?;
+            (reply.get_identifier() == identifier && reply.get_sequence_number() == sequence)
+                .then_some(ProbeReply::Reached)
+        }
+        IcmpTypes::TimeExceeded => {
+            // Carries the IP header and the first 8 bytes of the expired probe
+            let exceeded = TimeExceededPacket::new(ip.payload())?;
+            let original = Ipv4Packet::new(exceeded.payload())?;
+            let request = EchoRequestPacket::new(original.payload())?;
+            (request.get_identifier() == identifier && request.get_sequence_number() == sequence)
+                .then_some(ProbeReply::TimeExceeded)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pnet_packet::{
+        icmp::{echo_reply::MutableEchoReplyPacket, time_exceeded::MutableTimeExceededPacket},
+        ip::IpNextHeaderProtocols,
+        ipv4::MutableIpv4Packet,
+    };
+
+    fn ipv4_packet(payload: &[u8]) -> Vec<u8> {
+        let mut buf = vec![0u8; MutableIpv4Packet::minimum_packet_size() + payload.len()];
+        let mut packet = MutableIpv4Packet::new(&mut buf).expect("Failed to create packet");
+        packet.set_version(4);
+        packet.set_header_length(5);
+        packet.set_total_length((MutableIpv4Packet::minimum_packet_size() + payload.len()) as u16);
+        packet.set_next_level_protocol(IpNextHeaderProtocols::Icmp);
+        packet.set_payload(payload);
+        buf
+    }
+
+    #[test]
+    fn match_echo_reply_of_probe() {
+        let mut buf = vec![0u8; EchoReplyPacket::minimum_packet_size()];
+        let mut reply = MutableEchoReplyPacket::new(&mut buf).expect("Failed to create reply");
+        reply.set_icmp_type(IcmpTypes::EchoReply);
+        reply.set_identifier(7);
+        reply.set_sequence_number(3);
+        let packet = ipv4_packet(&buf);
+
+        assert_eq!(parse_reply(&packet, 7, 3), Some(ProbeReply::Reached));
+        assert_eq!(parse_reply(&packet, 7, 2), None);
+        assert_eq!(parse_reply(&packet, 8, 3), None);
+    }
+
+    #[test]
+    fn match_time_exceeded_of_probe() {
+        let request = echo_request(7, 3).expect("Failed to create probe");
+        let original = ipv4_packet(&request[..8]);
+        let mut buf = vec![0u8; TimeExceededPacket::minimum_packet_size() + original.len()];
+        let mut exceeded =
+            MutableTimeExceededPacket::new(&mut buf).expect("Failed to create time exceeded");
+        exceeded.set_icmp_type(IcmpTypes::TimeExceeded);
+        exceeded.set_payload(&original);
+        let packet = ipv4_packet(&buf);
+
+        assert_eq!(parse_reply(&packet, 7, 3), Some(ProbeReply::TimeExceeded));
+        assert_eq!(parse_reply(&packet, 7, 4), None);
+    }
+}
@@ -0,0 +1,215 @@
+//! Sampled log of packets exchanged with peers, only built with `traffic_logging` feature
+//!
+//! Packets are recorded from the firewall callbacks of the WireGuard adapter, so only packets
+//! accepted by the firewall and the traffic shaper are logged. Payload is not logged unless
+//! explicitly requested.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::atomic::{AtomicBool, Ordering},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use telio_crypto::PublicKey;
+use telio_model::api_config::PathType;
+
+/// Maximal number of payload bytes logged per packet
+const MAX_LOGGED_PAYLOAD: usize = 64;
+
+/// Configuration of the traffic log
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct TrafficLogConfig {
+    /// Probability of logging a packet, between 0.0 and 1.0
+    pub sample_rate: f64,
+    /// Number of latest entries kept
+    pub max_entries: usize,
+    /// Log first bytes of the packet along with its metadata
+    pub include_payload_bytes: bool,
+}
+
+impl Default for TrafficLogConfig {
+    fn default() -> Self {
+        Self {
+            sample_rate: 0.01,
+            max_entries: 1000,
+            include_payload_bytes: false,
+        }
+    }
+}
+
+/// Direction of the logged packet, as seen from this device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrafficDirection {
+    /// Packet received from the peer
+    Inbound,
+    /// Packet sent to the peer
+    Outbound,
+}
+
+/// Metadata of a single logged packet
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TrafficLogEntry {
+    /// Time the packet was seen, in milliseconds since UNIX epoch
+    pub ts_ms: u64,
+    /// Direction of the packet
+    pub direction: TrafficDirection,
+    /// Size of the IP packet in bytes
+    pub size: usize,
+    /// Public key of the peer exchanging the packet
+    pub public_key: PublicKey,
+    /// Path used to reach the peer when the packet was seen
+    pub path: PathType,
+    /// Base64 encoded first bytes of the packet, only if `include_payload_bytes` is set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub payload: Option<String>,
+}
+
+#[derive(Default)]
+struct State {
+    config: TrafficLogConfig,
+    entries: VecDeque<TrafficLogEntry>,
+    paths: HashMap<PublicKey, PathType>,
+}
+
+/// Traffic log shared between the runtime and the packet callbacks
+#[derive(Default)]
+pub struct TrafficLog {
+    enabled: AtomicBool,
+    state: Mutex<State>,
+}
+
+impl TrafficLog {
+    /// Start logging with `config`, entries logged so far are kept up to `max_entries`
+    pub fn enable(&self, config: TrafficLogConfig) {
+        let mut state = self.state.lock();
+        let excess = state.entries.len().saturating_sub(config.max_entries);
+        state.entries.drain(..excess);
+        state.config = TrafficLogConfig {
+            sample_rate: config.sample_rate.clamp(0.0, 1.0),
+            ..config
+        };
+        self.enabled.store(true, Ordering::Relaxed);
+    }
+
+    /// Update the path reported for packets of the peer
+    pub fn set_path(&self, public_key: PublicKey, path: PathType) {
+        self.state.lock().paths.insert(public_key, path);
+    }
+
+    /// Log the packet if it is picked by sampling
+    pub fn record(&self, direction: TrafficDirection, peer: &[u8; 32], packet: &[u8]) {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return;
+        }
+        let mut state = self.state.lock();
+        if state.config.max_entries == 0 || rand::random::<f64>() >= state.config.sample_rate {
+            return;
+        }
+
+        let public_key = PublicKey(*peer);
+        let entry = TrafficLogEntry {
+            ts_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as u64),
+            direction,
+            size: packet.len(),
+            public_key,
+            path: state.paths.get(&public_key).copied().unwrap_or_default(),
+            payload: state
+                .config
+                .include_payload_bytes
+                .then(|| base64::encode(&packet[..packet.len().min(MAX_LOGGED_PAYLOAD)])),
+        };
+        if state.entries.len() >= state.config.max_entries {
+            state.entries.pop_front();
+        }
+        state.entries.push_back(entry);
+    }
+
+    /// Logged entries, ordered from the oldest
+    pub fn entries(&self) -> Vec<TrafficLogEntry> {
+        self.state.lock().entries.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(
+        sample_rate: f64,
+        max_entries: usize,
+        include_payload_bytes: bool,
+    ) -> TrafficLogConfig {
+        TrafficLogConfig {
+            sample_rate,
+            max_entries,
+            include_payload_bytes,
+        }
+    }
+
+    #[test]
+    fn nothing_is_logged_until_enabled() {
+        let log = TrafficLog::default();
+        log.record(TrafficDirection::Inbound, &[1; 32], &[0; 100]);
+        assert!(log.entries().is_empty());
+    }
+
+    #[test]
+    fn keep_latest_entries() {
+        let log = TrafficLog::default();
+        log.enable(config(1.0, 2, false));
+        log.set_path(PublicKey([2; 32]), PathType::Direct);
+        for size in 1..=3 {
+            log.record(TrafficDirection::Outbound, &[2; 32], &vec![0; size]);
+        }
+
+        let entries = log.entries();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].size, 2);
+        assert_eq!(entries[1].size, 3);
+        assert_eq!(entries[1].direction, TrafficDirection::Outbound);
+        assert_eq!(entries[1].public_key, PublicKey([2; 32]));
+        assert_eq!(entries[1].path, PathType::Direct);
+        assert_eq!(entries[1].payload, None);
+    }
+
+    #[test]
+    fn payload_is_logged_only_on_request() {
+        let log = TrafficLog::default();
+        log.enable(config(1.0, 10, true));
+        log.record(TrafficDirection::Inbound, &[1; 32], &[0xff; 100]);
+
+        let entries = log.entries();
+        assert_eq!(entries[0].path, PathType::Relay);
+        assert_eq!(
+            entries[0].payload,
+            Some(base64::encode([0xff; MAX_LOGGED_PAYLOAD]))
+        );
+    }
+
+    #[test]
+    fn zero_sample_rate_logs_nothing() {
+        let log = TrafficLog::default();
+        log.enable(config(0.0, 10, false));
+        log.record(TrafficDirection::Inbound, &[1; 32], &[0; 100]);
+        assert!(log.entries().is_empty());
+    }
+
+    #[test]
+    fn deserialize_config_with_defaults() {
+        let config: TrafficLogConfig = serde_json::from_str(r#"{"sample_rate":0.5}"#).unwrap();
+        assert_eq!(
+            config,
+            TrafficLogConfig {
+                sample_rate: 0.5,
+                max_entries: 1000,
+                include_payload_bytes: false,
+            }
+        );
+    }
+}
@@ -10,6 +10,7 @@ use telio_crypto::PublicKey;
 use telio_dns::DnsResolver;
 use telio_firewall::firewall::{Firewall, FILE_SEND_PORT};
 use telio_model::api_config::Features;
+use telio_model::mesh::ExitNode;
 use telio_model::EndpointMap;
 use telio_model::SocketAddr;
 use telio_proto::PeersStatesMap;
@@ -62,6 +63,7 @@ pub async fn consolidate_wg_state(
 
     consolidate_wg_private_key(requested_state, &*entities.wireguard_interface).await?;
     consolidate_wg_fwmark(requested_state, &*entities.wireguard_interface).await?;
+    consolidate_wg_listen_port(requested_state, &*entities.wireguard_interface).await?;
     consolidate_wg_peers(
         requested_state,
         &*entities.wireguard_interface,
@@ -114,6 +116,22 @@ async fn consolidate_wg_fwmark<W: WireGuard>(
     Ok(())
 }
 
+async fn consolidate_wg_listen_port<W: WireGuard>(
+    requested_state: &RequestedState,
+    wireguard_interface: &W,
+) -> Result {
+    // Without requested port the one picked by the adapter is kept
+    let requested_port = match requested_state.device_config.listen_port {
+        Some(port) => port,
+        None => return Ok(()),
+    };
+    let actual_port = wireguard_interface.get_interface().await?.listen_port;
+    if actual_port != Some(requested_port) {
+        wireguard_interface.set_listen_port(requested_port).await?;
+    }
+    Ok(())
+}
+
 #[allow(clippy::too_many_arguments)]
 async fn consolidate_wg_peers<
     W: WireGuard,
@@ -272,6 +290,20 @@ async fn consolidate_wg_peers<
     Ok(())
 }
 
+/// Allowed ips of the exit node peer, all traffic is routed through it if the node doesn't specify them
+pub fn exit_node_allowed_ips(exit_node: &ExitNode, features: &Features) -> Result<Vec<IpNetwork>> {
+    Ok(exit_node
+        .allowed_ips
+        .clone()
+        .unwrap_or(vec![
+            IpNetwork::V4("0.0.0.0/0".parse()?),
+            IpNetwork::V6("::/0".parse()?),
+        ])
+        .into_iter()
+        .filter(|network| features.ipv6 || network.is_ipv4())
+        .collect())
+}
+
 fn check_allowed_ips_correctness(peers: &BTreeMap<PublicKey, RequestedPeer>) -> Result {
     peers
         .iter()
@@ -387,16 +419,7 @@ async fn build_requested_peers_list<
 
     // Add or promote exit node peer
     if let Some(exit_node) = &requested_state.exit_node {
-        let allowed_ips: Vec<IpNetwork> = exit_node
-            .allowed_ips
-            .clone()
-            .unwrap_or(vec![
-                IpNetwork::V4("0.0.0.0/0".parse()?),
-                IpNetwork::V6("::/0".parse()?),
-            ])
-            .into_iter()
-            .filter(|network| features.ipv6 || network.is_ipv4())
-            .collect();
+        let allowed_ips = exit_node_allowed_ips(exit_node, features)?;
 
         let preshared_key = requested_state
             .postquantum_wg
@@ -407,7 +430,7 @@ async fn build_requested_peers_list<
             // Exit node is meshnet peer, so just promote already existing node to be exit node
             // with allowed ips change
             meshnet_peer.peer.allowed_ips = allowed_ips;
-            meshnet_peer.peer.preshared_key = preshared_key;
+            meshnet_peer.peer.preshared_key = preshared_key.or(meshnet_peer.peer.preshared_key);
             exit_node_exists = true;
         } else {
             // Exit node is a fresh node, therefore - insert create new peer
@@ -545,6 +568,7 @@ async fn build_requested_meshnet_peers_list<
                         endpoint,
                         persistent_keepalive_interval,
                         allowed_ips,
+                        preshared_key: requested_state.meshnet_psks.get(&public_key).copied(),
                         ..Default::default()
                     },
                     local_direct_endpoint: None,
@@ -624,7 +648,10 @@ async fn build_requested_meshnet_peers_list<
         requested_peer.peer.endpoint = selected_remote_endpoint;
         requested_peer.local_direct_endpoint = selected_local_endpoint;
 
-        // Adjust keepalive for direct and offline peers
+        // Adjust keepalive for direct and offline peers, policy set by the user takes precedence
+        let policy_keepalive = requested_state
+            .keepalive_policy
+            .secs_for(&requested_peer.peer.public_key);
         requested_peer.peer.persistent_keepalive_interval =
             if is_peer_proxying(&requested_peer.peer, proxy_endpoints) {
                 if matches!(
@@ -634,10 +661,10 @@ async fn build_requested_meshnet_peers_list<
                     // If peer is offline according to derp, we turn off keepalives.
                     None
                 } else {
-                    requested_state.keepalive_periods.proxying
+                    policy_keepalive.or(requested_state.keepalive_periods.proxying)
                 }
             } else {
-                Some(requested_state.keepalive_periods.direct)
+                Some(policy_keepalive.unwrap_or(requested_state.keepalive_periods.direct))
             };
     }
 
@@ -866,7 +893,7 @@ mod tests {
     use telio_model::api_config::{
         DEFAULT_DIRECT_PERSISTENT_KEEPALIVE_PERIOD, DEFAULT_PERSISTENT_KEEPALIVE_PERIOD,
     };
-    use telio_model::config::{Config, PeerBase, Server};
+    use telio_model::config::{Config, KeepaliveOverride, KeepalivePolicy, PeerBase, Server};
     use telio_model::mesh::ExitNode;
     use telio_proxy::MockProxy;
     use telio_traversal::cross_ping_check::MockCrossPingCheckTrait;
@@ -994,6 +1021,47 @@ mod tests {
             .unwrap();
     }
 
+    #[tokio::test]
+    async fn update_listen_port_when_requested() {
+        let mut wg_mock = MockWireGuard::new();
+
+        wg_mock.expect_get_interface().returning(|| {
+            Ok(Interface {
+                listen_port: Some(12345),
+                ..Default::default()
+            })
+        });
+
+        wg_mock
+            .expect_set_listen_port()
+            .with(predicate::eq(51820))
+            .times(1)
+            .returning(|_| Ok(()));
+
+        let requested_state = RequestedState {
+            device_config: DeviceConfig {
+                listen_port: Some(51820),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        consolidate_wg_listen_port(&requested_state, &wg_mock)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn keep_listen_port_when_not_requested() {
+        let mut wg_mock = MockWireGuard::new();
+        wg_mock.expect_get_interface().never();
+        wg_mock.expect_set_listen_port().never();
+
+        consolidate_wg_listen_port(&RequestedState::default(), &wg_mock)
+            .await
+            .unwrap();
+    }
+
     fn create_requested_state(
         input: Vec<(
             PublicKey,
@@ -1263,6 +1331,14 @@ mod tests {
                     nicknames: false,
                     boringtun_reset_connections: Default::default(),
                     flush_events_on_stop_timeout_seconds: None,
+                    proxy_dns_query_timeout_ms: None,
+                    event_history_size: None,
+                    config_expiry_policy: None,
+                    max_peers: None,
+                    event_queue_warn_threshold: None,
+                    stale_peer_threshold_secs: None,
+                    ip_conflict_resolution: None,
+                    exit_node_failover_timeout_secs: None,
                     post_quantum_vpn: Default::default(),
                     no_link_detection: None,
                 },
@@ -1553,6 +1629,43 @@ mod tests {
         f.consolidate_peers().await;
     }
 
+    #[tokio::test]
+    async fn when_keepalive_policy_set_then_override_is_used() {
+        let mut f = Fixture::new();
+
+        let pub_key = SecretKey::gen().public();
+        let allowed_ips = vec![IpAddr::from([1, 2, 3, 4])];
+        let mapped_port = 18;
+        let proxy_endpoint = SocketAddr::from(([127, 0, 0, 1], mapped_port));
+
+        let override_keepalive_time = 120;
+        f.requested_state.keepalive_periods.proxying = Some(1234);
+        f.requested_state.keepalive_policy = KeepalivePolicy {
+            default_secs: Some(25),
+            overrides: vec![KeepaliveOverride {
+                public_key: pub_key,
+                secs: override_keepalive_time,
+            }],
+        };
+
+        f.when_requested_meshnet_config(vec![(pub_key, allowed_ips.clone())]);
+        f.when_proxy_mapping(vec![(pub_key, mapped_port)]);
+        f.when_current_peers(vec![]);
+        f.when_time_since_last_rx(vec![]);
+        f.when_time_since_last_endpoint_change(vec![]);
+        f.when_cross_check_validated_endpoints(vec![]);
+        f.when_upgrade_requests(vec![]);
+
+        f.then_add_peer(vec![(
+            pub_key,
+            proxy_endpoint,
+            override_keepalive_time,
+            allowed_ips.into_iter().map(|ip| ip.into()).collect(),
+        )]);
+
+        f.consolidate_peers().await;
+    }
+
     #[tokio::test]
     async fn when_upgrade_requested_by_peer_then_upgrade() {
         let mut f = Fixture::new();
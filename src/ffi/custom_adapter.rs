@@ -0,0 +1,358 @@
+//! WireGuard adapter whose encrypted packets are carried by the application through
+//! `telio_custom_adapter`
+
+use std::{
+    convert::{TryFrom, TryInto},
+    ffi::CString,
+    io,
+    mem::MaybeUninit,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use telio_crypto::{PublicKey, KEY_SIZE};
+use telio_wg::{
+    uapi::{Cmd, Response},
+    Adapter, AdapterFactory, Error as AdapterError, FirewallCb, PacketAdapter, Transport, Tun,
+    Tunnel,
+};
+
+use super::types::{telio_adapter_factory_cb, telio_custom_adapter, telio_result};
+
+/// Transport forwarding encrypted packets to the functions provided by the application
+///
+/// The adapter of the application is stopped when the transport is dropped, i.e. after the
+/// threads of the packet adapter using it have exited.
+struct CustomTransport {
+    adapter: telio_custom_adapter,
+    stopped: AtomicBool,
+}
+
+impl CustomTransport {
+    /// Create the adapter with the factory of the application
+    fn create(factory: telio_adapter_factory_cb) -> Result<Self, AdapterError> {
+        let mut adapter = MaybeUninit::<telio_custom_adapter>::uninit();
+        // Safety: factory is given by the application, which promises to fill in the adapter
+        // when it returns TELIO_RES_OK
+        let res = unsafe { (factory.cb)(factory.ctx, adapter.as_mut_ptr()) };
+        if !matches!(res, telio_result::TELIO_RES_OK) {
+            return Err(AdapterError::InternalError("Custom adapter factory failed"));
+        }
+        Ok(Self {
+            adapter: unsafe { adapter.assume_init() },
+            stopped: AtomicBool::new(false),
+        })
+    }
+
+    fn stop(&self) {
+        if !self.stopped.swap(true, Ordering::SeqCst) {
+            unsafe { (self.adapter.stop)(self.adapter.handle) }
+        }
+    }
+}
+
+impl Drop for CustomTransport {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+impl Transport for CustomTransport {
+    fn send_packet(&self, peer: &PublicKey, packet: &[u8]) -> io::Result<()> {
+        let res = unsafe {
+            (self.adapter.send_packet)(
+                self.adapter.handle,
+                peer.0.as_ptr(),
+                packet.as_ptr(),
+                packet.len(),
+            )
+        };
+        match res {
+            telio_result::TELIO_RES_OK => Ok(()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "Custom adapter failed to send packet",
+            )),
+        }
+    }
+
+    fn recv_packet(
+        &self,
+        buf: &mut [u8],
+        timeout: Duration,
+    ) -> io::Result<Option<(PublicKey, usize)>> {
+        let mut peer = [0u8; KEY_SIZE];
+        let timeout = timeout.as_millis().try_into().unwrap_or(u32::MAX);
+        let len = unsafe {
+            (self.adapter.recv_packet)(
+                self.adapter.handle,
+                buf.as_mut_ptr(),
+                buf.len(),
+                timeout,
+                peer.as_mut_ptr(),
+            )
+        };
+        match usize::try_from(len) {
+            Ok(0) => Ok(None),
+            Ok(len) if len <= buf.len() => Ok(Some((PublicKey(peer), len))),
+            _ => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "Custom adapter failed to receive packet",
+            )),
+        }
+    }
+
+    fn set_peer(
+        &self,
+        peer: &PublicKey,
+        endpoint: Option<SocketAddr>,
+        remove: bool,
+    ) -> io::Result<()> {
+        let endpoint = endpoint
+            .map(|endpoint| CString::new(endpoint.to_string()))
+            .transpose()?;
+        let res = unsafe {
+            (self.adapter.set_peer)(
+                self.adapter.handle,
+                peer.0.as_ptr(),
+                endpoint
+                    .as_ref()
+                    .map_or(std::ptr::null(), |endpoint| endpoint.as_ptr()),
+                remove,
+            )
+        };
+        match res {
+            telio_result::TELIO_RES_OK => Ok(()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "Custom adapter failed to set peer",
+            )),
+        }
+    }
+}
+
+/// Userspace WireGuard sending encrypted packets through the adapter of the application
+pub struct CustomAdapter {
+    adapter: PacketAdapter,
+    transport: Arc<CustomTransport>,
+}
+
+impl CustomAdapter {
+    /// Create the adapter with the factory of the application, on the tunnel `name` or `tun`
+    pub fn create(
+        factory: telio_adapter_factory_cb,
+        name: &str,
+        tun: Option<Tun>,
+        firewall_process_inbound_callback: FirewallCb,
+        firewall_process_outbound_callback: FirewallCb,
+    ) -> Result<Self, AdapterError> {
+        let transport = Arc::new(CustomTransport::create(factory)?);
+        let adapter = PacketAdapter::start(
+            name,
+            tun,
+            transport.clone(),
+            firewall_process_inbound_callback,
+            firewall_process_outbound_callback,
+        )?;
+        Ok(Self { adapter, transport })
+    }
+
+    #[cfg(test)]
+    fn with_tunnel(
+        factory: telio_adapter_factory_cb,
+        tunnel: Arc<dyn Tunnel>,
+    ) -> Result<Self, AdapterError> {
+        let transport = Arc::new(CustomTransport::create(factory)?);
+        let adapter = PacketAdapter::with_tunnel(transport.clone(), tunnel, None, None)?;
+        Ok(Self { adapter, transport })
+    }
+}
+
+/// Wrap the factory of the application, so the adapter is recreated on every adapter start
+pub fn factory(factory: telio_adapter_factory_cb) -> AdapterFactory {
+    Arc::new(move |name, tun, inbound, outbound| {
+        let adapter = CustomAdapter::create(factory, name, tun, inbound, outbound)?;
+        Ok(Box::new(adapter) as Box<dyn Adapter>)
+    })
+}
+
+#[async_trait]
+impl Adapter for CustomAdapter {
+    async fn stop(&self) {
+        // Threads calling into the adapter of the application are joined first
+        self.adapter.stop().await;
+        self.transport.stop();
+    }
+
+    fn get_adapter_luid(&self) -> u64 {
+        0
+    }
+
+    async fn send_uapi_cmd(&self, cmd: &Cmd) -> Result<Response, AdapterError> {
+        self.adapter.send_uapi_cmd(cmd).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libc::c_char;
+    use std::{
+        ffi::{c_void, CStr},
+        sync::{atomic::AtomicUsize, Mutex},
+    };
+    use telio_crypto::SecretKey;
+    use telio_wg::uapi::{Interface, Peer};
+
+    /// Calls made by libtelio to the adapter of the application
+    #[derive(Default)]
+    struct Calls {
+        peers: Mutex<Vec<(PublicKey, Option<String>, bool)>>,
+        stops: AtomicUsize,
+    }
+
+    unsafe extern "C" fn send_packet(
+        _: *mut c_void,
+        _: *const u8,
+        _: *const u8,
+        _: usize,
+    ) -> telio_result {
+        telio_result::TELIO_RES_OK
+    }
+
+    unsafe extern "C" fn recv_packet(
+        _: *mut c_void,
+        _: *mut u8,
+        _: usize,
+        timeout_ms: u32,
+        _: *mut u8,
+    ) -> i32 {
+        std::thread::sleep(Duration::from_millis(timeout_ms.into()));
+        0
+    }
+
+    unsafe extern "C" fn set_peer(
+        handle: *mut c_void,
+        peer: *const u8,
+        endpoint: *const c_char,
+        remove: bool,
+    ) -> telio_result {
+        let calls = &*(handle as *const Calls);
+        let peer = PublicKey(*(peer as *const [u8; KEY_SIZE]));
+        let endpoint =
+            (!endpoint.is_null()).then(|| CStr::from_ptr(endpoint).to_str().unwrap().to_owned());
+        calls.peers.lock().unwrap().push((peer, endpoint, remove));
+        telio_result::TELIO_RES_OK
+    }
+
+    unsafe extern "C" fn stop(handle: *mut c_void) {
+        let calls = &*(handle as *const Calls);
+        calls.stops.fetch_add(1, Ordering::SeqCst);
+    }
+
+    unsafe extern "C" fn create(
+        ctx: *mut c_void,
+        adapter: *mut telio_custom_adapter,
+    ) -> telio_result {
+        adapter.write(telio_custom_adapter {
+            handle: ctx,
+            send_packet,
+            recv_packet,
+            set_peer,
+            stop,
+        });
+        telio_result::TELIO_RES_OK
+    }
+
+    unsafe extern "C" fn fail(_: *mut c_void, _: *mut telio_custom_adapter) -> telio_result {
+        telio_result::TELIO_RES_ERROR
+    }
+
+    /// Tunnel without packets
+    struct NoTunnel;
+
+    impl Tunnel for NoTunnel {
+        fn read_packet(&self, _: &mut [u8], timeout: Duration) -> io::Result<Option<usize>> {
+            std::thread::sleep(timeout);
+            Ok(None)
+        }
+
+        fn write_packet(&self, _: &[u8]) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn adapter(calls: &Calls) -> CustomAdapter {
+        CustomAdapter::with_tunnel(
+            telio_adapter_factory_cb {
+                ctx: calls as *const Calls as *mut c_void,
+                cb: create,
+            },
+            Arc::new(NoTunnel),
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn peers_are_set_on_the_application_adapter() {
+        let calls = Calls::default();
+        let adapter = adapter(&calls);
+        let peer = SecretKey::gen().public();
+
+        let interface = Interface {
+            private_key: Some(SecretKey::gen()),
+            peers: vec![(
+                peer,
+                Peer {
+                    public_key: peer,
+                    endpoint: Some(([10, 0, 0, 1], 51820).into()),
+                    ..Default::default()
+                },
+            )]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        };
+        adapter
+            .send_uapi_cmd(&Cmd::Set(interface.into()))
+            .await
+            .unwrap();
+        assert_eq!(
+            *calls.peers.lock().unwrap(),
+            vec![(peer, Some("10.0.0.1:51820".to_owned()), false)]
+        );
+
+        adapter.stop().await;
+        assert_eq!(calls.stops.load(Ordering::SeqCst), 1);
+        drop(adapter);
+        assert_eq!(calls.stops.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn dropped_adapter_is_stopped() {
+        let calls = Calls::default();
+        drop(adapter(&calls));
+        // Application adapter is stopped once the threads using it have exited
+        for _ in 0..50 {
+            if calls.stops.load(Ordering::SeqCst) == 1 {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        panic!("Custom adapter was not stopped");
+    }
+
+    #[test]
+    fn factory_failure() {
+        assert!(factory(telio_adapter_factory_cb {
+            ctx: std::ptr::null_mut(),
+            cb: fail,
+        })("tun10", None, None, None)
+        .is_err());
+    }
+}
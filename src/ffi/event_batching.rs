@@ -0,0 +1,170 @@
+//! Grouping of events into JSON arrays, enabled with `telio_enable_event_batching`
+
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::Duration,
+};
+
+/// Receiver of the JSON encoded batches flushed by the timer
+pub type BatchSink = Arc<dyn Fn(String) + Send + Sync>;
+
+/// Runner of the timer flushes, so that batches are passed to the callbacks by the same task
+/// as the events
+pub type FlushRunner = Arc<dyn Fn(Box<dyn FnOnce() + Send>) + Send + Sync>;
+
+#[derive(Default)]
+struct State {
+    /// Maximal number of events in a batch, `None` if batching is disabled
+    max_batch_size: Option<usize>,
+    pending: Vec<String>,
+    /// Bumped on every reconfiguration, so outdated flush timers stop
+    generation: u64,
+}
+
+/// Events waiting to be passed to the event callbacks as a single JSON array
+#[derive(Default)]
+pub struct EventBatcher {
+    state: Mutex<State>,
+}
+
+impl EventBatcher {
+    fn state(&self) -> MutexGuard<State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Start grouping events, a batch is flushed once it holds `max_batch_size` events or
+    /// `flush_interval` passes, whichever comes first. Events already waiting are kept.
+    ///
+    /// Timer only schedules the flushes with `run_flush`, batch is taken and passed to `sink`
+    /// by the flush itself.
+    pub fn enable(
+        self: &Arc<Self>,
+        max_batch_size: usize,
+        flush_interval: Duration,
+        run_flush: FlushRunner,
+        sink: BatchSink,
+    ) {
+        let generation = {
+            let mut state = self.state();
+            state.max_batch_size = Some(max_batch_size);
+            state.generation += 1;
+            state.generation
+        };
+
+        let batcher = Arc::downgrade(self);
+        thread::spawn(move || loop {
+            thread::sleep(flush_interval);
+            match batcher.upgrade() {
+                Some(current) if current.state().generation == generation => (),
+                _ => return,
+            }
+            let batcher = batcher.clone();
+            let sink = sink.clone();
+            run_flush(Box::new(move || {
+                let batch = batcher.upgrade().and_then(|batcher| {
+                    let mut state = batcher.state();
+                    if state.generation != generation {
+                        return None;
+                    }
+                    take_batch(&mut state)
+                });
+                if let Some(batch) = batch {
+                    sink(batch);
+                }
+            }));
+        });
+    }
+
+    /// Stop grouping events, returns the batch of events still waiting
+    pub fn disable(&self) -> Option<String> {
+        let mut state = self.state();
+        state.max_batch_size = None;
+        state.generation += 1;
+        take_batch(&mut state)
+    }
+
+    /// Queue the JSON encoded event, returns what should be passed to the callbacks now:
+    /// the event itself if batching is disabled or the whole batch once it is full
+    pub fn push(&self, event: String) -> Option<String> {
+        let mut state = self.state();
+        let max_batch_size = match state.max_batch_size {
+            Some(max_batch_size) => max_batch_size,
+            None => return Some(event),
+        };
+        state.pending.push(event);
+        if state.pending.len() >= max_batch_size {
+            take_batch(&mut state)
+        } else {
+            None
+        }
+    }
+}
+
+fn take_batch(state: &mut State) -> Option<String> {
+    if state.pending.is_empty() {
+        return None;
+    }
+    Some(format!(
+        "[{}]",
+        state.pending.drain(..).collect::<Vec<_>>().join(",")
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    fn ignore() -> BatchSink {
+        Arc::new(|_| {})
+    }
+
+    fn inline() -> FlushRunner {
+        Arc::new(|flush| flush())
+    }
+
+    #[test]
+    fn events_are_passed_through_by_default() {
+        let batcher = EventBatcher::default();
+        assert_eq!(batcher.push("{}".to_owned()), Some("{}".to_owned()));
+    }
+
+    #[test]
+    fn full_batch_is_flushed() {
+        let batcher = Arc::new(EventBatcher::default());
+        batcher.enable(2, Duration::from_secs(3600), inline(), ignore());
+
+        assert_eq!(batcher.push(r#"{"a":1}"#.to_owned()), None);
+        assert_eq!(
+            batcher.push(r#"{"b":2}"#.to_owned()),
+            Some(r#"[{"a":1},{"b":2}]"#.to_owned())
+        );
+        assert_eq!(batcher.push(r#"{"c":3}"#.to_owned()), None);
+        assert_eq!(batcher.disable(), Some(r#"[{"c":3}]"#.to_owned()));
+        assert_eq!(batcher.push("{}".to_owned()), Some("{}".to_owned()));
+    }
+
+    #[test]
+    fn batch_is_flushed_after_interval() {
+        let batcher = Arc::new(EventBatcher::default());
+        let (tx, rx) = mpsc::channel();
+        let tx = Mutex::new(tx);
+        batcher.enable(
+            10,
+            Duration::from_millis(10),
+            inline(),
+            Arc::new(move |batch| {
+                let _ = tx.lock().unwrap().send(batch);
+            }),
+        );
+
+        assert_eq!(batcher.push("{}".to_owned()), None);
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(5)).unwrap(),
+            "[{}]".to_owned()
+        );
+    }
+}
@@ -0,0 +1,168 @@
+//! Structured fields of tracing events and spans, passed to `telio_logger_v2_cb`
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use parking_lot::Mutex;
+use serde_json::{Map, Value};
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    Event,
+};
+
+/// Name of the field where tracing stores the messages passed to tracing::info! etc
+const MESSAGE_FIELD: &str = "message";
+
+thread_local! {
+    /// Spans entered on the current thread, innermost last
+    static CURRENT_SPANS: RefCell<Vec<u64>> = RefCell::new(Vec::new());
+}
+
+/// Collects fields other than the message into a JSON object
+struct FieldsVisitor<'a>(&'a mut Map<String, Value>);
+
+impl<'a> FieldsVisitor<'a> {
+    fn insert(&mut self, field: &Field, value: Value) {
+        if field.name() != MESSAGE_FIELD {
+            self.0.insert(field.name().to_owned(), value);
+        }
+    }
+}
+
+impl<'a> Visit for FieldsVisitor<'a> {
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, value.into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, value.into());
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.insert(field, format!("{:?}", value).into());
+    }
+}
+
+struct SpanData {
+    parent: Option<u64>,
+    fields: Map<String, Value>,
+    refs: usize,
+}
+
+/// Spans known to the subscriber along with their fields
+#[derive(Default)]
+pub struct SpanRegistry {
+    last_id: AtomicU64,
+    spans: Mutex<HashMap<u64, SpanData>>,
+}
+
+impl SpanRegistry {
+    pub fn new_span(&self, attrs: &Attributes<'_>) -> Id {
+        let parent = if attrs.is_contextual() {
+            current_span()
+        } else {
+            attrs.parent().map(Id::into_u64)
+        };
+        let mut fields = Map::new();
+        attrs.record(&mut FieldsVisitor(&mut fields));
+
+        // Span IDs must not be 0
+        let id = self.last_id.fetch_add(1, Ordering::Relaxed) + 1;
+        self.spans.lock().insert(
+            id,
+            SpanData {
+                parent,
+                fields,
+                refs: 1,
+            },
+        );
+        Id::from_u64(id)
+    }
+
+    pub fn record(&self, span: &Id, values: &Record<'_>) {
+        if let Some(data) = self.spans.lock().get_mut(&span.into_u64()) {
+            values.record(&mut FieldsVisitor(&mut data.fields));
+        }
+    }
+
+    pub fn enter(&self, span: &Id) {
+        CURRENT_SPANS.with(|spans| spans.borrow_mut().push(span.into_u64()));
+    }
+
+    pub fn exit(&self, span: &Id) {
+        CURRENT_SPANS.with(|spans| {
+            let mut spans = spans.borrow_mut();
+            if let Some(pos) = spans.iter().rposition(|id| *id == span.into_u64()) {
+                spans.remove(pos);
+            }
+        });
+    }
+
+    pub fn clone_span(&self, span: &Id) -> Id {
+        if let Some(data) = self.spans.lock().get_mut(&span.into_u64()) {
+            data.refs += 1;
+        }
+        span.clone()
+    }
+
+    pub fn try_close(&self, span: Id) -> bool {
+        let mut spans = self.spans.lock();
+        let closed = match spans.get_mut(&span.into_u64()) {
+            Some(data) => {
+                data.refs -= 1;
+                data.refs == 0
+            }
+            None => false,
+        };
+        if closed {
+            spans.remove(&span.into_u64());
+        }
+        closed
+    }
+
+    /// JSON object with fields of the event and its parent spans, fields of inner spans and
+    /// of the event itself take precedence
+    pub fn fields_json(&self, event: &Event<'_>) -> String {
+        let mut parent = if event.is_contextual() {
+            current_span()
+        } else {
+            event.parent().map(Id::into_u64)
+        };
+
+        let mut fields = Map::new();
+        {
+            let spans = self.spans.lock();
+            let mut chain = Vec::new();
+            while let Some(data) = parent.and_then(|id| spans.get(&id)) {
+                chain.push(&data.fields);
+                parent = data.parent;
+            }
+            for span_fields in chain.into_iter().rev() {
+                fields.extend(span_fields.clone());
+            }
+        }
+        event.record(&mut FieldsVisitor(&mut fields));
+        Value::Object(fields).to_string()
+    }
+}
+
+fn current_span() -> Option<u64> {
+    CURRENT_SPANS.with(|spans| spans.borrow().last().copied())
+}
@@ -1,3 +1,7 @@
+#[cfg(not(target_os = "windows"))]
+mod custom_adapter;
+mod event_batching;
+mod log_fields;
 pub mod types;
 
 use base64::{decode as base64decode, encode as base64encode};
@@ -5,33 +9,42 @@ use ffi_helpers::{error_handling, panic as panic_handling};
 use ipnetwork::IpNetwork;
 use libc::c_char;
 use rand::Rng;
-use telio_crypto::{PublicKey, SecretKey};
+use telio_crypto::{signature, token, PublicKey, SecretKey};
+use telio_proto::MAX_APPLICATION_PAYLOAD_SIZE;
 use telio_wg::AdapterType;
 use tracing::{error, trace, Subscriber};
 
-#[cfg(target_os = "linux")]
-use libc::c_uint;
-
-#[cfg(not(target_os = "windows"))]
-use libc::c_int;
+use libc::{c_int, c_uint};
 #[cfg(target_os = "android")]
-use telio_sockets::Protect;
+use telio_sockets::{BindNetwork, NetworkConstraints, Protect};
 use uuid::Uuid;
 
 use std::{
+    convert::TryFrom,
     ffi::{CStr, CString},
     fmt,
     net::{IpAddr, SocketAddr},
     panic,
     process::abort,
     ptr::null,
-    sync::{Mutex, Once},
+    sync::{Arc, LockResult, Mutex, MutexGuard, Once},
+    thread::{self, JoinHandle},
     time::Duration,
 };
 
+use self::event_batching::EventBatcher;
+use self::log_fields::SpanRegistry;
 use self::types::*;
-use crate::device::{Device, DeviceConfig, Result as DevResult};
-use telio_model::{api_config::Features, config::PartialConfig, event::*, mesh::ExitNode};
+use crate::device::{Device, DeviceConfig, Error as DevError, PlatformInfo, Result as DevResult};
+use telio_model::{
+    api_config::{Features, IpConflictPolicy},
+    config::{
+        diff_configs, Config, DnsFallbackConfig, DnsTtlOverride, FailoverExitNode, KeepalivePolicy,
+        PartialConfig, ProxySettings, RelaySelectionPolicy,
+    },
+    event::*,
+    mesh::{ExitNode, ManagedRoute, PeerGroup, PeerGroupPolicy},
+};
 
 // debug tools
 use telio_utils::{
@@ -41,6 +54,7 @@ use telio_utils::{
 
 const DEFAULT_PANIC_MSG: &str = "libtelio panicked";
 const MAX_CONFIG_LENGTH: usize = 16 * 1024 * 1024;
+const MAX_ACTIVE_CONNECTIONS: usize = 256;
 
 /// Check if res is ok, else return early by converting Error into telio_result
 /// and saving it to LAST_ERROR storage
@@ -74,23 +88,140 @@ macro_rules! ffi_catch_panic {
 /// Length of a public or private key
 const KEY_SIZE: usize = 32;
 
+/// Repeats of a log message passed through before they are suppressed
+const LOG_REPEATS_BEFORE_SUPPRESSION: u32 = 10;
+/// Suppressed repeats before the first report, doubled after every report
+const LOG_FIRST_REPEAT_REPORT_INTERVAL: u32 = 100;
+/// Maximal number of suppressed repeats between reports
+const LOG_MAX_REPEAT_REPORT_INTERVAL: u32 = 10_000;
+
+/// Message passed to the logger along with the level and the fields it was logged with
+#[derive(Debug, Clone, PartialEq)]
+struct LogLine {
+    message: String,
+    level: tracing::Level,
+    /// JSON object of the event and span fields, collected only for the v2 callback
+    fields: Option<String>,
+}
+
+impl LogLine {
+    /// Line with the same level and fields as this one
+    fn with_message(&self, message: String) -> Self {
+        Self {
+            message,
+            level: self.level,
+            fields: self.fields.clone(),
+        }
+    }
+}
+
 struct LogStatus {
-    string: String,
+    /// Last logged line, repeats are detected by its message
+    line: LogLine,
+    /// Repeats of `line` seen so far
     counter: u32,
+    /// Repeats suppressed since the last report
+    suppressed: u32,
+    /// Suppressed repeats which trigger the next report
+    report_interval: u32,
+}
+
+impl LogStatus {
+    fn new(line: LogLine) -> Self {
+        Self {
+            line,
+            counter: 0,
+            suppressed: 0,
+            report_interval: LOG_FIRST_REPEAT_REPORT_INTERVAL,
+        }
+    }
+
+    /// Lines to pass to the logger for `line`, reports of repeats are backed off
+    /// exponentially, so that tight loops do not flood the log
+    ///
+    /// Reports keep the level and the fields of the repeated line.
+    fn filter(&mut self, line: LogLine) -> Vec<LogLine> {
+        if self.line.message != line.message {
+            let mut lines = Vec::with_capacity(2);
+            if self.suppressed > 0 {
+                lines.push(self.line.with_message(format!(
+                    "[repeated {} more times!] {}",
+                    self.suppressed, self.line.message
+                )));
+            }
+            lines.push(line.clone());
+            *self = Self::new(line);
+            return lines;
+        }
+
+        self.counter = self.counter.saturating_add(1);
+        if self.counter <= LOG_REPEATS_BEFORE_SUPPRESSION {
+            return vec![line];
+        }
+
+        self.suppressed += 1;
+        if self.suppressed < self.report_interval {
+            return Vec::new();
+        }
+
+        let report = self.line.with_message(format!(
+            "[repeated {} times!] {}",
+            self.suppressed, self.line.message
+        ));
+        self.suppressed = 0;
+        self.report_interval = self
+            .report_interval
+            .saturating_mul(2)
+            .min(LOG_MAX_REPEAT_REPORT_INTERVAL);
+        vec![report]
+    }
 }
 
 lazy_static::lazy_static! {
-    static ref LAST_LOG_STATUS: Mutex<LogStatus> = {
-        Mutex::new(LogStatus{string: String::default(), counter: 0})
-    };
+    static ref LAST_LOG_STATUS: Mutex<LogStatus> = Mutex::new(LogStatus::new(LogLine {
+        message: String::default(),
+        level: tracing::Level::TRACE,
+        fields: None,
+    }));
 }
 
 #[allow(non_camel_case_types)]
 pub struct telio {
     inner: Mutex<Device>,
     id: usize,
+    events: EventListeners,
+    event_batcher: Arc<EventBatcher>,
+    /// Thread started by `telio_start_async`
+    starting: Mutex<Option<JoinHandle<()>>>,
+}
+
+impl telio {
+    /// Lock the device, after the startup begun by `telio_start_async` finished
+    fn device(&self) -> LockResult<MutexGuard<'_, Device>> {
+        join_starting(self);
+        self.inner.lock()
+    }
+}
+
+/// Instance passed to the thread started by `telio_start_async`
+struct StartingTelio(*const telio);
+
+// SAFETY: the instance is not freed before the starting thread is joined in `telio_destroy`
+unsafe impl Send for StartingTelio {}
+
+impl StartingTelio {
+    fn get(&self) -> &telio {
+        // SAFETY: see `Send` implementation
+        unsafe { &*self.0 }
+    }
 }
 
+/// Event callbacks with their IDs, the callback passed to `telio_new` has ID 0
+type EventListeners = Arc<Mutex<Vec<(u64, telio_event_cb)>>>;
+
+/// ID of the callback passed to `telio_new`, it cannot be removed
+const DEFAULT_EVENT_LISTENER_ID: u64 = 0;
+
 /// cbindgen:ignore
 static PANIC_HOOK: Once = Once::new();
 
@@ -117,6 +248,46 @@ pub extern "C" fn telio_new(
     }
 
     let features = ffi_try!(deserialize_features(features));
+    let logger = LoggerCallback::V1(logger);
+    let ret = telio_new_common(
+        dev,
+        &features,
+        events,
+        log_level,
+        logger,
+        #[cfg(target_os = "android")]
+        None,
+        #[cfg(target_os = "android")]
+        None,
+    );
+
+    log_entry(features, events, log_level, logger, ret, dev);
+    ret
+}
+
+#[no_mangle]
+/// Create new telio library instance with structured logging callback
+///
+/// Same as `telio_new`, except that along with the message, the logger receives a JSON object
+/// of fields of the log message and of the spans it was logged in, e.g. `{"peer":"..."}`.
+/// # Parameters
+/// - `events`:     Events callback
+/// - `features`:   JSON string of enabled features
+/// - `log_level`:  Log level
+/// - `logger_v2`:  Structured logging callback
+pub extern "C" fn telio_new_with_logger_v2(
+    dev: *mut *mut telio,
+    features: *const c_char,
+    events: telio_event_cb,
+    log_level: telio_log_level,
+    logger_v2: telio_logger_v2_cb,
+) -> telio_result {
+    unsafe {
+        fortify_source();
+    }
+
+    let features = ffi_try!(deserialize_features(features));
+    let logger = LoggerCallback::V2(logger_v2);
     let ret = telio_new_common(
         dev,
         &features,
@@ -125,12 +296,39 @@ pub extern "C" fn telio_new(
         logger,
         #[cfg(target_os = "android")]
         None,
+        #[cfg(target_os = "android")]
+        None,
     );
 
     log_entry(features, events, log_level, logger, ret, dev);
     ret
 }
 
+fn dispatch_event(listeners: &EventListeners, batcher: &EventBatcher, e: Box<Event>) {
+    let event = e
+        .to_json()
+        .unwrap_or_else(|_| String::from("event_to_json error"));
+    if let Some(json) = batcher.push(event) {
+        deliver_events(listeners, json);
+    }
+}
+
+/// Pass JSON encoded event or batch of events to all listeners
+fn deliver_events(listeners: &EventListeners, json: String) {
+    // Callbacks are called without the lock, so they may add or remove listeners
+    let listeners = match listeners.lock() {
+        Ok(listeners) => listeners.clone(),
+        Err(poisoned) => poisoned.into_inner().clone(),
+    };
+    let _ = CString::new(json)
+        .map(|s| {
+            for (_, events) in listeners {
+                unsafe { (events.cb)(events.ctx, s.as_ptr()) }
+            }
+        })
+        .map_err(|e| telio_log_warn!("Failed to create CString: {:?}", e));
+}
+
 fn char_to_str<'a>(char_ptr: *const c_char) -> Result<&'a str, telio_result> {
     if !char_ptr.is_null() {
         let cstr = unsafe { CStr::from_ptr(char_ptr) };
@@ -198,7 +396,54 @@ pub extern "C" fn telio_new_with_protect(
     protect: telio_protect_cb,
 ) -> telio_result {
     let features = ffi_try!(deserialize_features(features));
-    let ret = telio_new_common(dev, &features, events, log_level, logger, Some(protect));
+    let logger = LoggerCallback::V1(logger);
+    let ret = telio_new_common(
+        dev,
+        &features,
+        events,
+        log_level,
+        logger,
+        Some(protect),
+        None,
+    );
+    log_entry(features, events, log_level, logger, ret, dev);
+    ret
+}
+
+#[cfg(target_os = "android")]
+#[no_mangle]
+/// Create new telio library instance which binds its sockets to networks through the host
+///
+/// Same as `telio_new_with_protect`, except that sockets are bound with `bind_network` to a
+/// network satisfying the constraints set with `telio_set_network_constraints`. Apps cannot
+/// bind sockets to interfaces themselves, so without it the constraints are not applied.
+/// # Parameters
+/// - `events`:       Events callback
+/// - `features`:     JSON string of enabled features
+/// - `log_level`:    Log level
+/// - `logger`:       Logging callback
+/// - `protect`:      Callback executed after exit-node connect (for VpnService::protectFromVpn())
+/// - `bind_network`: Callback binding sockets to a network (for Network.bindSocket())
+pub extern "C" fn telio_new_with_network_binding(
+    dev: *mut *mut telio,
+    features: *const c_char,
+    events: telio_event_cb,
+    log_level: telio_log_level,
+    logger: telio_logger_cb,
+    protect: telio_protect_cb,
+    bind_network: telio_bind_network_cb,
+) -> telio_result {
+    let features = ffi_try!(deserialize_features(features));
+    let logger = LoggerCallback::V1(logger);
+    let ret = telio_new_common(
+        dev,
+        &features,
+        events,
+        log_level,
+        logger,
+        Some(protect),
+        Some(bind_network),
+    );
     log_entry(features, events, log_level, logger, ret, dev);
     ret
 }
@@ -211,7 +456,7 @@ fn log_entry(
     features: Features,
     events: telio_event_cb,
     log_level: telio_log_level,
-    logger: telio_logger_cb,
+    logger: LoggerCallback,
     ret: telio_result,
     dev: *mut *mut telio,
 ) {
@@ -231,28 +476,25 @@ fn telio_new_common(
     features: &Features,
     events: telio_event_cb,
     log_level: telio_log_level,
-    logger: telio_logger_cb,
+    logger: LoggerCallback,
     #[cfg(target_os = "android")] protect_cb: Option<telio_protect_cb>,
+    #[cfg(target_os = "android")] bind_network_cb: Option<telio_bind_network_cb>,
 ) -> telio_result {
-    let tracing_subscriber = TelioTracingSubscriber {
-        callback: logger,
-        max_level: log_level.into(),
-    };
+    let tracing_subscriber = TelioTracingSubscriber::with_callback(logger, log_level.into());
     if tracing::subscriber::set_global_default(tracing_subscriber).is_err() {
         telio_log_warn!("Could not set logger, because logger had already been set by previous libtelio instance");
     }
 
-    let event_dispatcher = move |e: Box<Event>| {
-        let _ = CString::new(
-            e.to_json()
-                .unwrap_or_else(|_| String::from("event_to_json error")),
-        )
-        .map(|s| unsafe { (events.cb)(events.ctx, s.as_ptr()) })
-        .map_err(|e| telio_log_warn!("Failed to create CString: {:?}", e));
+    let listeners: EventListeners = Arc::new(Mutex::new(vec![(DEFAULT_EVENT_LISTENER_ID, events)]));
+    let event_batcher = Arc::new(EventBatcher::default());
+    let event_dispatcher = {
+        let listeners = listeners.clone();
+        let event_batcher = event_batcher.clone();
+        move |e: Box<Event>| dispatch_event(&listeners, &event_batcher, e)
     };
 
     PANIC_HOOK.call_once(|| {
-        let events = event_dispatcher;
+        let events = event_dispatcher.clone();
         panic::set_hook(Box::new(move |info| {
             // We need it on the logs as well ...
             error!("{}", info);
@@ -298,13 +540,34 @@ fn telio_new_common(
             })),
             None => None,
         };
+        #[cfg(not(target_os = "android"))]
+        let bind_network = None;
+        #[cfg(target_os = "android")]
+        let bind_network: Option<BindNetwork> = bind_network_cb.map(|bind_network| {
+            std::sync::Arc::new(move |fd, constraints: NetworkConstraints| unsafe {
+                (bind_network.cb)(
+                    bind_network.ctx,
+                    fd,
+                    constraints.require_unmetered,
+                    constraints.require_wifi,
+                )
+            }) as BindNetwork
+        });
 
-        let device = ffi_try!(Device::new((*features).clone(), event_dispatcher, protect));
+        let device = ffi_try!(Device::new(
+            (*features).clone(),
+            event_dispatcher,
+            protect,
+            bind_network,
+        ));
 
         unsafe {
             *dev = Box::into_raw(Box::new(telio {
                 inner: Mutex::new(device),
                 id: rand::thread_rng().gen::<usize>(),
+                events: listeners,
+                event_batcher,
+                starting: Mutex::new(None),
             }))
         };
 
@@ -314,9 +577,35 @@ fn telio_new_common(
 
 #[no_mangle]
 /// Completely stop and uninit telio lib.
+///
+/// `shuttingdown` event is reported before anything is stopped, after the events reported
+/// earlier. It is the last event passed to the event callback, events reported while stopping
+/// are dropped. Waits for the callback to receive it for up to
+/// `flush_events_on_stop_timeout_seconds`, or 5 seconds if the feature is not set.
 pub extern "C" fn telio_destroy(dev: *mut telio) {
+    // Notify before anything is stopped. Device lock is not held while waiting for the
+    // callback, so it may still call into telio, e.g. to collect the final state.
+    if let Some(dev) = unsafe { dev.as_ref() } {
+        let mut device = match dev.device() {
+            Ok(device) => device,
+            Err(poisoned) => poisoned.into_inner(),
+        };
+        let delivered = device.publish_shutting_down();
+        let timeout = device.shutting_down_delivery_timeout();
+        drop(device);
+        if let Some(delivered) = delivered {
+            if let Err(std::sync::mpsc::RecvTimeoutError::Timeout) = delivered.recv_timeout(timeout)
+            {
+                telio_log_warn!("telio_destroy: ShuttingDown event was not delivered in time");
+            }
+        }
+        if let Some(batch) = dev.event_batcher.disable() {
+            deliver_events(&dev.events, batch);
+        }
+    }
+
     let dev = unsafe { Box::from_raw(dev) };
-    let mut dev = match dev.inner.lock() {
+    let mut dev = match dev.device() {
         Ok(dev) => dev,
         Err(poisoned) => {
             telio_log_debug!("main telio lock has been poisoned");
@@ -330,6 +619,9 @@ pub extern "C" fn telio_destroy(dev: *mut telio) {
 #[no_mangle]
 /// Explicitly deallocate telio object and shutdown async rt.
 pub extern "C" fn telio_destroy_hard(dev: *mut telio) -> telio_result {
+    if let Some(dev) = unsafe { dev.as_ref() } {
+        join_starting(dev);
+    }
     let dev_b = unsafe { Box::from_raw(dev) };
     let device = dev_b.inner.into_inner().unwrap_or_else(|e| e.into_inner());
 
@@ -344,12 +636,175 @@ pub extern "C" fn telio_destroy_hard(dev: *mut telio) -> telio_result {
     TELIO_RES_ERROR
 }
 
+#[no_mangle]
+/// Register additional events callback.
+///
+/// Every event is passed to all registered callbacks, in the order of registration.
+///
+/// # Parameters
+/// - `id`: Caller chosen ID of the callback, used to remove it. ID 0 is taken by the callback
+///         passed to `telio_new`.
+/// - `cb`: Events callback.
+///
+/// # Returns
+/// `TELIO_RES_ERROR` if a callback with the same ID is already registered.
+///
+pub extern "C" fn telio_add_event_listener(
+    dev: &telio,
+    id: u64,
+    cb: telio_event_cb,
+) -> telio_result {
+    telio_log_info!(
+        "telio_add_event_listener entry with instance id: {}. Listener id: {}",
+        dev.id,
+        id
+    );
+    ffi_catch_panic!({
+        let mut listeners = ffi_try!(dev.events.lock().map_err(|_| TELIO_RES_LOCK_ERROR));
+        if listeners.iter().any(|(listener_id, _)| *listener_id == id) {
+            telio_log_error!(
+                "telio_add_event_listener: listener {} already registered",
+                id
+            );
+            return TELIO_RES_ERROR;
+        }
+        listeners.push((id, cb));
+        TELIO_RES_OK
+    })
+}
+
+#[no_mangle]
+/// Unregister events callback added with `telio_add_event_listener`.
+///
+/// # Parameters
+/// - `id`: ID the callback was registered with.
+///
+/// # Returns
+/// `TELIO_RES_ERROR` if no callback with the ID is registered or the ID is 0, callback passed
+/// to `telio_new` cannot be removed.
+///
+pub extern "C" fn telio_remove_event_listener(dev: &telio, id: u64) -> telio_result {
+    telio_log_info!(
+        "telio_remove_event_listener entry with instance id: {}. Listener id: {}",
+        dev.id,
+        id
+    );
+    ffi_catch_panic!({
+        if id == DEFAULT_EVENT_LISTENER_ID {
+            telio_log_error!("telio_remove_event_listener: default listener cannot be removed");
+            return TELIO_RES_ERROR;
+        }
+        let mut listeners = ffi_try!(dev.events.lock().map_err(|_| TELIO_RES_LOCK_ERROR));
+        let count = listeners.len();
+        listeners.retain(|(listener_id, _)| *listener_id != id);
+        if listeners.len() == count {
+            telio_log_error!(
+                "telio_remove_event_listener: listener {} not registered",
+                id
+            );
+            return TELIO_RES_ERROR;
+        }
+        TELIO_RES_OK
+    })
+}
+
+#[no_mangle]
+/// Group events passed to the event callbacks into batches.
+///
+/// Instead of a single event, callbacks receive a JSON array of events. A batch is passed once
+/// it holds `max_batch_size` events or `flush_interval_ms` passes since batching was enabled or
+/// the last timer flush, whichever comes first. Calling it again changes the limits.
+///
+/// Batches are passed by the same task as the single events, so callbacks are never called
+/// concurrently.
+///
+/// # Parameters
+/// - `max_batch_size`: Maximal number of events in a batch, must not be 0.
+/// - `flush_interval_ms`: Longest time an event waits in a batch, must not be 0.
+///
+pub extern "C" fn telio_enable_event_batching(
+    dev: &telio,
+    max_batch_size: u32,
+    flush_interval_ms: u32,
+) -> telio_result {
+    telio_log_info!(
+        "telio_enable_event_batching entry with instance id: {}. Max batch size: {}, flush interval: {}ms",
+        dev.id,
+        max_batch_size,
+        flush_interval_ms
+    );
+    ffi_catch_panic!({
+        if max_batch_size == 0 || flush_interval_ms == 0 {
+            telio_log_error!("telio_enable_event_batching: limits must not be 0");
+            return TELIO_RES_BAD_CONFIG;
+        }
+        // Flushes are run by the task passing the events, so callbacks are never called
+        // concurrently
+        let run_flush =
+            ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR)).event_task_runner();
+        let listeners = dev.events.clone();
+        dev.event_batcher.enable(
+            max_batch_size as usize,
+            Duration::from_millis(flush_interval_ms.into()),
+            run_flush,
+            Arc::new(move |batch| deliver_events(&listeners, batch)),
+        );
+        TELIO_RES_OK
+    })
+}
+
+#[no_mangle]
+/// Pass every event to the event callbacks on its own again, events waiting in a batch are
+/// passed as the last batch right after the events already reported.
+pub extern "C" fn telio_disable_event_batching(dev: &telio) -> telio_result {
+    telio_log_info!(
+        "telio_disable_event_batching entry with instance id: {}",
+        dev.id
+    );
+    ffi_catch_panic!({
+        let run = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR)).event_task_runner();
+        if let Some(batch) = dev.event_batcher.disable() {
+            let listeners = dev.events.clone();
+            run(Box::new(move || deliver_events(&listeners, batch)));
+        }
+        TELIO_RES_OK
+    })
+}
+
 #[no_mangle]
 /// Get default recommended adapter type for platform.
 pub extern "C" fn telio_get_default_adapter() -> telio_adapter_type {
     AdapterType::default().into()
 }
 
+#[no_mangle]
+/// Get adapter types available on this platform.
+///
+/// # Returns
+/// JSON array of adapter type names, e.g. `["TELIO_ADAPTER_BORING_TUN"]`.
+///
+pub extern "C" fn telio_get_supported_adapters() -> *mut c_char {
+    let adapters: Vec<String> = AdapterType::available()
+        .into_iter()
+        .map(|adapter| format!("{:?}", telio_adapter_type::from(adapter)))
+        .collect();
+    serialize_to_unmanaged_string("telio_get_supported_adapters", &adapters)
+}
+
+#[no_mangle]
+/// Get information about the platform for diagnostics and bug reports.
+///
+/// Does not require a telio instance.
+///
+/// # Returns
+/// JSON string
+/// `{"os":"linux","os_version":"6.1.0","arch":"aarch64","has_kernel_wireguard":true,"tun_available":true}`,
+/// `os_version` is the kernel release reported by `uname` and is null where unavailable.
+///
+pub extern "C" fn telio_get_platform_info() -> *mut c_char {
+    serialize_to_unmanaged_string("telio_get_platform_info", &PlatformInfo::collect())
+}
+
 #[no_mangle]
 /// Start telio with specified adapter.
 ///
@@ -368,7 +823,7 @@ pub extern "C" fn telio_start(
     );
 
     ffi_catch_panic!({
-        let mut dev = ffi_try!(dev.inner.lock().map_err(|_| TELIO_RES_LOCK_ERROR));
+        let mut dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
 
         dev.start(&DeviceConfig {
             private_key,
@@ -376,11 +831,92 @@ pub extern "C" fn telio_start(
             fwmark: None,
             name: None,
             tun: None,
+            listen_port: None,
+            custom_adapter: None,
         })
         .telio_log_result("telio_start")
     })
 }
 
+#[no_mangle]
+/// Start telio with specified adapter in the background.
+///
+/// Returns without waiting for the adapter to be initialized, which takes 500ms or more on some
+/// platforms. `StartupComplete` event is reported when starting finishes, successfully or not.
+/// Other calls, including `telio_start` and `telio_stop`, wait for the startup to finish.
+///
+/// # Returns
+/// `TELIO_RES_ALREADY_STARTED` if the device is started or still starting.
+///
+pub extern "C" fn telio_start_async(
+    dev: &telio,
+    private_key: *const c_char,
+    adapter: telio_adapter_type,
+) -> telio_result {
+    let private_key = ffi_try!(char_ptr_to_type::<SecretKey>(private_key));
+    telio_log_info!(
+        "telio_start_async entry with instance id: {}. Public key: {:?}. Adapter: {:?}",
+        dev.id,
+        private_key.public(),
+        &adapter
+    );
+
+    ffi_catch_panic!({
+        let mut starting = ffi_try!(dev.starting.lock().map_err(|_| TELIO_RES_LOCK_ERROR));
+        if starting
+            .as_ref()
+            .map_or(false, |starting| !starting.is_finished())
+            || ffi_try!(dev.inner.lock().map_err(|_| TELIO_RES_LOCK_ERROR)).is_running()
+        {
+            return TELIO_RES_ALREADY_STARTED;
+        }
+
+        let config = DeviceConfig {
+            private_key,
+            adapter: adapter.into(),
+            fwmark: None,
+            name: None,
+            tun: None,
+            listen_port: None,
+            custom_adapter: None,
+        };
+        let instance = StartingTelio(dev);
+        *starting = Some(thread::spawn(move || {
+            let dev = instance.get();
+            // Lock is held until the event is queued, so the startup is reported before any
+            // event of the calls waiting for it
+            let mut device = match dev.inner.lock() {
+                Ok(device) => device,
+                Err(poisoned) => poisoned.into_inner(),
+            };
+            let result = device.start(&config).map_err(|e| {
+                telio_log_error!("telio_start_async: {}", e);
+                e.to_string()
+            });
+            device.publish_event(Box::new(Event::new::<StartupComplete>().set(
+                StartupComplete {
+                    success: result.is_ok(),
+                    error: result.err(),
+                },
+            )));
+        }));
+        TELIO_RES_OK
+    })
+}
+
+/// Wait for the thread started by `telio_start_async`
+///
+/// Lock is held while joining, so every caller returns only after the startup finished.
+fn join_starting(dev: &telio) {
+    let mut starting = match dev.starting.lock() {
+        Ok(starting) => starting,
+        Err(poisoned) => poisoned.into_inner(),
+    };
+    if let Some(thread) = starting.take() {
+        let _ = thread.join();
+    }
+}
+
 #[no_mangle]
 /// Start telio with specified adapter and name.
 ///
@@ -392,7 +928,7 @@ pub extern "C" fn telio_start_named(
     name: *const c_char,
 ) -> telio_result {
     ffi_catch_panic!({
-        let mut dev = ffi_try!(dev.inner.lock().map_err(|_| TELIO_RES_LOCK_ERROR));
+        let mut dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
 
         let private_key = ffi_try!(char_ptr_to_type::<SecretKey>(private_key));
         let name = ffi_try!(char_ptr_to_type::<String>(name));
@@ -402,30 +938,100 @@ pub extern "C" fn telio_start_named(
             fwmark: None,
             name: Some(name),
             tun: None,
+            listen_port: None,
+            custom_adapter: None,
         })
         .telio_log_result("telio_start_named")
     })
 }
 
-#[cfg(not(target_os = "windows"))]
 #[no_mangle]
-/// Start telio device with specified adapter and already open tunnel.
+#[cfg(target_os = "linux")]
+/// Start telio with specified adapter and name, marking encapsulated packets with fwmark.
 ///
-/// Telio will take ownership of tunnel , and close it on stop.
+/// Unlike `telio_start_named` followed by `telio_set_fwmark`, no packet leaves without the mark.
+/// Adapter will attempt to open its own tunnel.
 ///
 /// # Parameters
 /// - `private_key`: base64 encoded private_key.
 /// - `adapter`: Adapter type.
-/// - `tun`: A valid filedescriptor to tun device.
+/// - `name`: Name of the tunnel interface.
+/// - `fwmark`: unsigned 32-bit integer
 ///
-pub extern "C" fn telio_start_with_tun(
+pub extern "C" fn telio_start_named_with_fwmark(
     dev: &telio,
     private_key: *const c_char,
     adapter: telio_adapter_type,
-    tun: c_int,
+    name: *const c_char,
+    fwmark: c_uint,
 ) -> telio_result {
+    let private_key = ffi_try!(char_ptr_to_type::<SecretKey>(private_key));
+    let name = ffi_try!(char_ptr_to_type::<String>(name));
+    telio_log_info!(
+        "telio_start_named_with_fwmark entry with instance id: {}. Public key: {:?}. Adapter: {:?}. Name: {}. fwmark: {}",
+        dev.id,
+        private_key.public(),
+        &adapter,
+        name,
+        fwmark
+    );
+
     ffi_catch_panic!({
-        let mut dev = ffi_try!(dev.inner.lock().map_err(|_| TELIO_RES_LOCK_ERROR));
+        let mut dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+
+        dev.start(&DeviceConfig {
+            private_key,
+            adapter: adapter.into(),
+            fwmark: Some(fwmark),
+            name: Some(name),
+            tun: None,
+            listen_port: None,
+            custom_adapter: None,
+        })
+        .telio_log_result("telio_start_named_with_fwmark")
+    })
+}
+
+#[no_mangle]
+#[cfg(not(target_os = "linux"))]
+/// Packet marking is available only on Linux.
+///
+/// # Returns
+/// `TELIO_RES_UNSUPPORTED_PLATFORM`
+///
+pub extern "C" fn telio_start_named_with_fwmark(
+    dev: &telio,
+    _private_key: *const c_char,
+    _adapter: telio_adapter_type,
+    _name: *const c_char,
+    _fwmark: c_uint,
+) -> telio_result {
+    telio_log_info!(
+        "telio_start_named_with_fwmark entry with instance id: {}. Not supported on this platform",
+        dev.id
+    );
+    TELIO_RES_UNSUPPORTED_PLATFORM
+}
+
+#[cfg(not(target_os = "windows"))]
+#[no_mangle]
+/// Start telio device with specified adapter and already open tunnel.
+///
+/// Telio will take ownership of tunnel , and close it on stop.
+///
+/// # Parameters
+/// - `private_key`: base64 encoded private_key.
+/// - `adapter`: Adapter type.
+/// - `tun`: A valid filedescriptor to tun device.
+///
+pub extern "C" fn telio_start_with_tun(
+    dev: &telio,
+    private_key: *const c_char,
+    adapter: telio_adapter_type,
+    tun: c_int,
+) -> telio_result {
+    ffi_catch_panic!({
+        let mut dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
         let private_key = ffi_try!(char_ptr_to_type::<SecretKey>(private_key));
         dev.start(&DeviceConfig {
             private_key,
@@ -433,17 +1039,93 @@ pub extern "C" fn telio_start_with_tun(
             fwmark: None,
             name: None,
             tun: Some(tun),
+            listen_port: None,
+            custom_adapter: None,
         })
         .telio_log_result("telio_start_with_tun")
     })
 }
 
+#[cfg(target_os = "windows")]
+#[no_mangle]
+/// Already open tunnels are not supported on Windows.
+///
+/// # Returns
+/// `TELIO_RES_UNSUPPORTED_PLATFORM`
+///
+pub extern "C" fn telio_start_with_tun(
+    dev: &telio,
+    _private_key: *const c_char,
+    _adapter: telio_adapter_type,
+    _tun: c_int,
+) -> telio_result {
+    telio_log_info!(
+        "telio_start_with_tun entry with instance id: {}. Not supported on this platform",
+        dev.id
+    );
+    TELIO_RES_UNSUPPORTED_PLATFORM
+}
+
+#[cfg(not(target_os = "windows"))]
+#[no_mangle]
+/// Start telio device with a transport of encrypted packets implemented by the application.
+///
+/// libtelio runs WireGuard in userspace on the default tunnel interface and hands encrypted
+/// packets to the adapter created by `adapter_factory`, which carries them to the peers, e.g.
+/// over a transport other than UDP. The factory is called on every adapter start, the adapter is
+/// stopped with its `stop` function when the device stops or the adapter is dropped. Plaintext
+/// packets pass the meshnet firewall as with the built-in adapters.
+///
+/// # Parameters
+/// - `private_key`: base64 encoded private_key.
+/// - `adapter_factory`: Callback filling in `telio_custom_adapter` and returning `TELIO_RES_OK`.
+///
+pub extern "C" fn telio_start_with_custom_adapter(
+    dev: &telio,
+    private_key: *const c_char,
+    adapter_factory: telio_adapter_factory_cb,
+) -> telio_result {
+    ffi_catch_panic!({
+        let mut dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+        let private_key = ffi_try!(char_ptr_to_type::<SecretKey>(private_key));
+        dev.start(&DeviceConfig {
+            private_key,
+            adapter: AdapterType::default(),
+            fwmark: None,
+            name: None,
+            tun: None,
+            listen_port: None,
+            custom_adapter: Some(custom_adapter::factory(adapter_factory)),
+        })
+        .telio_log_result("telio_start_with_custom_adapter")
+    })
+}
+
+#[cfg(target_os = "windows")]
+#[no_mangle]
+/// Custom adapters are not supported on Windows.
+///
+/// # Returns
+/// `TELIO_RES_UNSUPPORTED_PLATFORM`
+///
+pub extern "C" fn telio_start_with_custom_adapter(
+    dev: &telio,
+    _private_key: *const c_char,
+    _adapter_factory: telio_adapter_factory_cb,
+) -> telio_result {
+    telio_log_info!(
+        "telio_start_with_custom_adapter entry with instance id: {}. Not supported on this platform",
+        dev.id
+    );
+    TELIO_RES_UNSUPPORTED_PLATFORM
+}
+
 #[no_mangle]
 /// Stop telio device.
 pub extern "C" fn telio_stop(dev: &telio) -> telio_result {
     telio_log_info!("telio_stop entry with instance id: {}.", dev.id,);
     ffi_catch_panic!({
-        let mut dev = match dev.inner.lock() {
+        let mut dev = match dev.device() {
             Ok(dev) => dev,
             Err(poisoned) => poisoned.into_inner(),
         };
@@ -455,7 +1137,7 @@ pub extern "C" fn telio_stop(dev: &telio) -> telio_result {
 #[no_mangle]
 /// get device luid.
 pub extern "C" fn telio_get_adapter_luid(dev: &telio) -> u64 {
-    match dev.inner.lock() {
+    match dev.device() {
         Ok(mut d) => d.get_adapter_luid(),
         Err(e) => {
             telio_log_error!("telio_get_adapter_luid() failed {:?}", e);
@@ -464,6 +1146,48 @@ pub extern "C" fn telio_get_adapter_luid(dev: &telio) -> u64 {
     }
 }
 
+#[cfg(not(target_os = "windows"))]
+#[no_mangle]
+/// Get the tunnel file descriptor passed to `telio_start_with_tun`.
+///
+/// The descriptor is borrowed, it stays owned by telio and must not be closed
+/// by the caller.
+///
+/// # Returns
+/// The file descriptor, or -1 if the device is not started or did not use an
+/// already open tunnel.
+pub extern "C" fn telio_get_current_tun_fd(dev: &telio) -> c_int {
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_get_current_tun_fd: dev lock: {}", err);
+            return -1;
+        }
+    };
+
+    match dev.get_current_tun_fd() {
+        Ok(tun) => tun.unwrap_or(-1),
+        Err(err) => {
+            telio_log_debug!("telio_get_current_tun_fd: {}", err);
+            -1
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+#[no_mangle]
+/// Already open tunnels are not supported on Windows.
+///
+/// # Returns
+/// -1
+pub extern "C" fn telio_get_current_tun_fd(dev: &telio) -> c_int {
+    telio_log_debug!(
+        "telio_get_current_tun_fd with instance id: {}. Not supported on this platform",
+        dev.id
+    );
+    -1
+}
+
 fn char_ptr_to_type<T: std::str::FromStr>(value: *const c_char) -> Result<T, telio_result>
 where
     <T as std::str::FromStr>::Err: std::fmt::Debug,
@@ -491,15 +1215,44 @@ pub extern "C" fn telio_set_private_key(dev: &telio, private_key: *const c_char)
         private_key.public()
     );
     ffi_catch_panic!({
-        let dev = ffi_try!(dev.inner.lock().map_err(|_| TELIO_RES_LOCK_ERROR));
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
         ffi_try!(dev.set_private_key(&private_key));
         TELIO_RES_OK
     })
 }
 
+#[no_mangle]
+/// Rotates private key of started device.
+///
+/// On success `KeyRotated` event with old and new public keys is reported through the event
+/// callback, so the new public key can be registered with the backend. As with
+/// `telio_set_private_key`, `TELIO_RES_INVALID_KEY` is returned if meshnet is on and the key
+/// differs from the one in meshnet config while `validate_keys` feature is enabled.
+///
+/// # Parameters
+/// - `new_private_key`: Base64 encoded WireGuard private key, must not be NULL.
+///
+pub extern "C" fn telio_rotate_secret_key(
+    dev: &telio,
+    new_private_key: *const c_char,
+) -> telio_result {
+    let new_private_key = ffi_try!(char_ptr_to_type::<SecretKey>(new_private_key));
+
+    telio_log_info!(
+        "telio_rotate_secret_key entry with instance id: {}. Public key: {:?}",
+        dev.id,
+        new_private_key.public()
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+        dev.rotate_secret_key(&new_private_key)
+            .telio_log_result("telio_rotate_secret_key")
+    })
+}
+
 #[no_mangle]
 pub extern "C" fn telio_get_private_key(dev: &telio) -> *mut c_char {
-    let dev = match dev.inner.lock() {
+    let dev = match dev.device() {
         Ok(dev) => dev,
         Err(err) => {
             telio_log_error!("telio_get_private_key: dev.get_private_key: {}", err);
@@ -516,6 +1269,162 @@ pub extern "C" fn telio_get_private_key(dev: &telio) -> *mut c_char {
     }
 }
 
+#[no_mangle]
+/// Get a stable identifier of the device.
+///
+/// # Returns
+/// Base64 encoded SHA-256 hash of the device public key. Stays the same across restarts
+/// as long as the same private key is used. NULL is returned on failure.
+pub extern "C" fn telio_get_device_fingerprint(dev: &telio) -> *mut c_char {
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_get_device_fingerprint: dev lock: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+
+    match dev.get_device_fingerprint() {
+        Ok(fingerprint) => bytes_to_zero_terminated_unmanaged_bytes(fingerprint.as_bytes()),
+        Err(err) => {
+            telio_log_error!("telio_get_device_fingerprint: {}", err);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+/// Get a stable UUID of the device for correlation with backend logs.
+///
+/// # Returns
+/// UUID v5 string derived from the device public key, e.g.
+/// `"3b7e8a52-1c4d-5f60-9a2b-7c8d9e0f1a2b"`. Stays the same across restarts as long as the same
+/// private key is used, while the key itself is not revealed. NULL is returned on failure.
+pub extern "C" fn telio_get_device_id(dev: &telio) -> *mut c_char {
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_get_device_id: dev lock: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+
+    match dev.get_device_id() {
+        Ok(id) => bytes_to_zero_terminated_unmanaged_bytes(id.to_string().as_bytes()),
+        Err(err) => {
+            telio_log_error!("telio_get_device_id: {}", err);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+/// Export current WireGuard interface and peer configuration.
+///
+/// Configuration is read from the adapter and formatted as a `wg(8)` config file, e.g.
+/// ```text
+/// [Interface]
+/// # PrivateKey is hidden, public key fingerprint: <fingerprint>
+/// ListenPort = 51820
+///
+/// [Peer]
+/// PublicKey = <public key>
+/// AllowedIPs = 100.64.0.2/32
+/// Endpoint = 1.2.3.4:51820
+/// PersistentKeepalive = 25
+/// ```
+/// Secrets are not exported: the private key is replaced by the fingerprint returned by
+/// `telio_get_device_fingerprint` and preshared keys are omitted.
+///
+/// # Returns
+/// Config file contents, NULL is returned on failure.
+pub extern "C" fn telio_export_wireguard_config(dev: &telio) -> *mut c_char {
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_export_wireguard_config: dev lock: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+
+    match dev.export_wireguard_config() {
+        Ok(conf) => bytes_to_zero_terminated_unmanaged_bytes(conf.as_bytes()),
+        Err(err) => {
+            telio_log_error!("telio_export_wireguard_config: {}", err);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+/// Zero rx/tx byte counters of all peers.
+///
+/// Counters reported afterwards start from zero, e.g. to count traffic of a single VPN session.
+/// Counters of the WireGuard adapter itself are not changed.
+///
+pub extern "C" fn telio_reset_statistics(dev: &telio) -> telio_result {
+    telio_log_info!("telio_reset_statistics entry with instance id: {}", dev.id);
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+
+        dev.reset_statistics(None)
+            .telio_log_result("telio_reset_statistics")
+    })
+}
+
+#[no_mangle]
+/// Zero rx/tx byte counters of a single peer.
+///
+/// # Parameters
+/// - `public_key`: Base64 encoded WireGuard public key of the peer.
+///
+/// # Returns
+/// `TELIO_RES_ERROR` if the peer is not configured.
+///
+pub extern "C" fn telio_reset_statistics_for(
+    dev: &telio,
+    public_key: *const c_char,
+) -> telio_result {
+    telio_log_info!(
+        "telio_reset_statistics_for entry with instance id: {}. Public Key: {:?}",
+        dev.id,
+        public_key
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+        let public_key = ffi_try!(char_ptr_to_type::<PublicKey>(public_key));
+
+        dev.reset_statistics(Some(public_key))
+            .telio_log_result("telio_reset_statistics_for")
+    })
+}
+
+#[no_mangle]
+/// Sets UDP port the WireGuard socket listens on.
+///
+/// Equivalent to `wg set <interface> listen-port <port>`. The port is applied on the next
+/// `telio_start*` call, so the device must be stopped. By default the adapter picks a random port.
+///
+/// # Parameters
+/// - `port`: UDP port number, 0 lets the adapter pick a random port.
+///
+/// # Returns
+/// `TELIO_RES_ALREADY_STARTED` if the device is running.
+///
+pub extern "C" fn telio_listen_on_port(dev: &telio, port: u16) -> telio_result {
+    telio_log_info!(
+        "telio_listen_on_port entry with instance id: {}. Port: {}",
+        dev.id,
+        port
+    );
+    ffi_catch_panic!({
+        let mut dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+
+        dev.set_listen_port(port)
+            .telio_log_result("telio_listen_on_port")
+    })
+}
+
 #[no_mangle]
 #[cfg(target_os = "linux")]
 /// Sets fmark for started device.
@@ -530,12 +1439,80 @@ pub extern "C" fn telio_set_fwmark(dev: &telio, fwmark: c_uint) -> telio_result
             dev.id,
             fwmark
         );
-        let dev = ffi_try!(dev.inner.lock().map_err(|_| TELIO_RES_LOCK_ERROR));
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
         ffi_try!(dev.set_fwmark(fwmark));
         TELIO_RES_OK
     })
 }
 
+#[no_mangle]
+#[cfg(not(target_os = "linux"))]
+/// Packet marking is available only on Linux.
+///
+/// # Returns
+/// `TELIO_RES_UNSUPPORTED_PLATFORM`
+///
+pub extern "C" fn telio_set_fwmark(dev: &telio, _fwmark: c_uint) -> telio_result {
+    telio_log_info!(
+        "telio_set_fwmark entry with instance id: {}. Not supported on this platform",
+        dev.id
+    );
+    TELIO_RES_UNSUPPORTED_PLATFORM
+}
+
+#[no_mangle]
+#[cfg(target_os = "android")]
+/// Restrict traffic of the started device to networks matching the constraints.
+///
+/// Sockets are bound to a matching network by the `bind_network` callback given to
+/// `telio_new_with_network_binding`. When no network matches, they keep using the default network.
+/// Returns `TELIO_RES_BAD_CONFIG` if the instance was created without the callback, as the
+/// constraints would have no effect.
+///
+/// # Parameters
+/// - `constraints`: JSON object, e.g. `{"require_unmetered":true,"require_wifi":false}`
+///
+pub extern "C" fn telio_set_network_constraints(
+    dev: &telio,
+    constraints: *const c_char,
+) -> telio_result {
+    ffi_catch_panic!({
+        if constraints.is_null() {
+            return TELIO_RES_INVALID_STRING;
+        }
+        let constraints = ffi_try!(unsafe { CStr::from_ptr(constraints) }
+            .to_str()
+            .map_err(|_| TELIO_RES_INVALID_STRING));
+        let constraints: NetworkConstraints = ffi_try!(serde_json::from_str(constraints));
+        telio_log_info!(
+            "telio_set_network_constraints entry with instance id: {}. constraints: {:?}",
+            dev.id,
+            constraints
+        );
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+        dev.set_network_constraints(constraints)
+            .telio_log_result("telio_set_network_constraints")
+    })
+}
+
+#[no_mangle]
+#[cfg(not(target_os = "android"))]
+/// Network constraints are available only on Android.
+///
+/// # Returns
+/// `TELIO_RES_UNSUPPORTED_PLATFORM`
+///
+pub extern "C" fn telio_set_network_constraints(
+    dev: &telio,
+    _constraints: *const c_char,
+) -> telio_result {
+    telio_log_info!(
+        "telio_set_network_constraints entry with instance id: {}. Not supported on this platform",
+        dev.id
+    );
+    TELIO_RES_UNSUPPORTED_PLATFORM
+}
+
 #[no_mangle]
 /// Notify telio with network state changes.
 ///
@@ -553,12 +1530,31 @@ pub extern "C" fn telio_notify_network_change(
         dev.id
     );
     ffi_catch_panic!({
-        let dev = ffi_try!(dev.inner.lock().map_err(|_| TELIO_RES_LOCK_ERROR));
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
         dev.notify_network_change()
             .telio_log_result("telio_notify_network_change")
     })
 }
 
+#[no_mangle]
+/// Searches for direct path endpoints again and pings the meshnet peers with them.
+///
+/// Use it after network changes telio cannot notice, e.g. manual NAT or firewall changes.
+/// Unlike `telio_notify_network_change`, the relay connection is kept. Known STUN endpoints are
+/// forgotten, so all the peers are probed even if the endpoints did not change. Does nothing if
+/// direct connections are not enabled.
+pub extern "C" fn telio_trigger_network_redetection(dev: &telio) -> telio_result {
+    telio_log_info!(
+        "telio_trigger_network_redetection entry with instance id: {}.",
+        dev.id
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+        dev.trigger_network_redetection()
+            .telio_log_result("telio_trigger_network_redetection")
+    })
+}
+
 #[no_mangle]
 /// Wrapper for `telio_connect_to_exit_node_with_id` that doesn't take an identifier
 pub extern "C" fn telio_connect_to_exit_node(
@@ -622,61 +1618,122 @@ pub extern "C" fn telio_connect_to_exit_node_with_id(
     endpoint: *const c_char,
 ) -> telio_result {
     ffi_catch_panic!({
-        let dev = ffi_try!(dev.inner.lock().map_err(|_| TELIO_RES_LOCK_ERROR));
-        let identifier = if !identifier.is_null() {
-            let cstr = ffi_try!(unsafe { CStr::from_ptr(identifier) }
-                .to_str()
-                .map_err(|_| TELIO_RES_INVALID_STRING));
-            cstr.to_owned()
-        } else {
-            Uuid::new_v4().to_string()
-        };
-
-        let public_key = if !public_key.is_null() {
-            ffi_try!(char_ptr_to_type::<PublicKey>(public_key))
-        } else {
-            telio_log_error!("Public Key is NULL");
-            return TELIO_RES_ERROR;
-        };
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+        let node = ffi_try!(exit_node_from_ffi(
+            identifier,
+            public_key,
+            allowed_ips,
+            endpoint
+        ));
+        dev.connect_exit_node(&node)
+            .telio_log_result("telio_connect_to_exit_node")
+    })
+}
 
-        let allowed_ips = if !allowed_ips.is_null() {
-            let cstr = ffi_try!(unsafe { CStr::from_ptr(allowed_ips) }
-                .to_str()
-                .map_err(|_| TELIO_RES_INVALID_STRING))
+#[no_mangle]
+/// Connects to an exit node like `telio_connect_to_exit_node`, retrying until the exit node
+/// is reachable.
+///
+/// Returns after the first attempt. When no handshake with the exit node succeeds within the
+/// delay, the exit node is connected again and the delay is multiplied by the backoff factor.
+/// `ExitNodeConnected` event is reported on the first handshake, `ExitNodeConnectionFailed`
+/// after the last attempt. Retrying stops when another exit node is connected or the exit
+/// node is disconnected.
+///
+/// # Parameters
+/// - `public_key`: Base64 encoded WireGuard public key for an exit node.
+/// - `allowed_ips`: Semicolon separated list of subnets which will be routed to the exit node.
+///                  Can be NULL, same as "0.0.0.0/0".
+/// - `endpoint`: An endpoint to an exit node. Can be NULL, must contain a port.
+/// - `policy_json`: JSON object, e.g.
+///                  `{"max_attempts":5,"initial_delay_ms":1000,"backoff_factor":2.0}`.
+///                  Missing fields take the values from the example.
+///
+pub extern "C" fn telio_connect_to_exit_node_with_retry(
+    dev: &telio,
+    public_key: *const c_char,
+    allowed_ips: *const c_char,
+    endpoint: *const c_char,
+    policy_json: *const c_char,
+) -> telio_result {
+    let policy_str = ffi_try!(char_to_str(policy_json));
+    let policy: crate::device::ExitNodeRetryPolicy = ffi_try!(serde_json::from_str(policy_str));
+    telio_log_info!(
+        "telio_connect_to_exit_node_with_retry entry with instance id: {}. Public Key: {:?}. Policy: {:?}",
+        dev.id,
+        public_key,
+        policy
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+        let node = ffi_try!(exit_node_from_ffi(
+            null(),
+            public_key,
+            allowed_ips,
+            endpoint
+        ));
+        dev.connect_exit_node_with_retry(&node, policy)
+            .telio_log_result("telio_connect_to_exit_node_with_retry")
+    })
+}
+
+/// Exit node from the arguments of `telio_connect_to_exit_node_with_id`
+fn exit_node_from_ffi(
+    identifier: *const c_char,
+    public_key: *const c_char,
+    allowed_ips: *const c_char,
+    endpoint: *const c_char,
+) -> Result<ExitNode, telio_result> {
+    let identifier = if !identifier.is_null() {
+        let cstr = unsafe { CStr::from_ptr(identifier) }
+            .to_str()
+            .map_err(|_| TELIO_RES_INVALID_STRING)?;
+        cstr.to_owned()
+    } else {
+        Uuid::new_v4().to_string()
+    };
+
+    let public_key = if !public_key.is_null() {
+        char_ptr_to_type::<PublicKey>(public_key)?
+    } else {
+        telio_log_error!("Public Key is NULL");
+        return Err(TELIO_RES_ERROR);
+    };
+
+    let allowed_ips = if !allowed_ips.is_null() {
+        let cstr = unsafe { CStr::from_ptr(allowed_ips) }
+            .to_str()
+            .map_err(|_| TELIO_RES_INVALID_STRING)?
             .split(';');
-            let allowed_ips: Vec<IpNetwork> = ffi_try!(cstr
-                .map(|net| net.parse())
-                .collect::<Result<Vec<_>, _>>()
-                .map_err(|_| TELIO_RES_INVALID_STRING));
-            Some(allowed_ips)
-        } else {
-            None
-        };
+        let allowed_ips: Vec<IpNetwork> = cstr
+            .map(|net| net.parse())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| TELIO_RES_INVALID_STRING)?;
+        Some(allowed_ips)
+    } else {
+        None
+    };
 
-        let endpoint = if !endpoint.is_null() {
-            let cstr = ffi_try!(unsafe { CStr::from_ptr(endpoint) }
-                .to_str()
-                .map_err(|_| TELIO_RES_INVALID_STRING));
-            match cstr {
-                "" => None,
-                _ => {
-                    let endpoint: SocketAddr =
-                        ffi_try!(cstr.parse().map_err(|_| TELIO_RES_INVALID_STRING));
-                    Some(endpoint)
-                }
+    let endpoint = if !endpoint.is_null() {
+        let cstr = unsafe { CStr::from_ptr(endpoint) }
+            .to_str()
+            .map_err(|_| TELIO_RES_INVALID_STRING)?;
+        match cstr {
+            "" => None,
+            _ => {
+                let endpoint: SocketAddr = cstr.parse().map_err(|_| TELIO_RES_INVALID_STRING)?;
+                Some(endpoint)
             }
-        } else {
-            None
-        };
+        }
+    } else {
+        None
+    };
 
-        let node = ExitNode {
-            identifier,
-            public_key,
-            allowed_ips,
-            endpoint,
-        };
-        dev.connect_exit_node(&node)
-            .telio_log_result("telio_connect_to_exit_node")
+    Ok(ExitNode {
+        identifier,
+        public_key,
+        allowed_ips,
+        endpoint,
     })
 }
 
@@ -709,12 +1766,162 @@ pub extern "C" fn telio_enable_magic_dns(
         servers
     );
     ffi_catch_panic!({
-        let dev = ffi_try!(dev.inner.lock().map_err(|_| TELIO_RES_BAD_CONFIG));
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_BAD_CONFIG));
         dev.enable_magic_dns(&servers)
             .telio_log_result("telio_enable_magic_dns")
     })
 }
 
+#[no_mangle]
+/// Replaces upstream servers of the running magic DNS.
+///
+/// Use it when the DNS servers of the system change, e.g. after switching networks. Queries
+/// keep being answered while the servers are swapped, unlike with `telio_disable_magic_dns`
+/// followed by `telio_enable_magic_dns`.
+///
+/// # Parameters
+/// - `new_servers`: JSON array of DNS servers to route the requests trough.
+///
+/// # Returns
+/// `TELIO_RES_BAD_CONFIG` if magic DNS is not enabled.
+///
+pub extern "C" fn telio_notify_dns_servers_changed(
+    dev: &telio,
+    new_servers: *const c_char,
+) -> telio_result {
+    let servers_str = ffi_try!(char_to_str(new_servers));
+    let servers: Vec<IpAddr> = ffi_try!(serde_json::from_str(servers_str));
+    telio_log_info!(
+        "telio_notify_dns_servers_changed entry with instance id: {}. DNS Server: {:?}",
+        dev.id,
+        servers
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+        match dev.notify_dns_servers_changed(&servers) {
+            Err(DevError::DnsNotEnabled) => {
+                telio_log_error!("telio_notify_dns_servers_changed: magic DNS is not enabled");
+                TELIO_RES_BAD_CONFIG
+            }
+            res => res.telio_log_result("telio_notify_dns_servers_changed"),
+        }
+    })
+}
+
+#[no_mangle]
+/// Configures servers asked by magic DNS when its forward servers fail or do not answer in time.
+///
+/// Only names outside of the meshnet are sent to the fallback servers, so meshnet hostnames
+/// never leak to them. Can be called before `telio_enable_magic_dns`, the setting is kept until
+/// replaced.
+///
+/// # Parameters
+/// - `servers_json`: JSON object `{"servers":["1.1.1.1"],"timeout_ms":2000}`, `timeout_ms` is
+///                   optional and defaults to 2000. Empty `servers` disable the fallback.
+///
+pub extern "C" fn telio_set_dns_fallback_servers(
+    dev: &telio,
+    servers_json: *const c_char,
+) -> telio_result {
+    let servers_str = ffi_try!(char_to_str(servers_json));
+    let fallback: DnsFallbackConfig = ffi_try!(serde_json::from_str(servers_str));
+    telio_log_info!(
+        "telio_set_dns_fallback_servers entry with instance id: {}. Fallback: {:?}",
+        dev.id,
+        fallback
+    );
+    if fallback.timeout_ms == 0 {
+        telio_log_error!("telio_set_dns_fallback_servers: timeout_ms must be positive");
+        return TELIO_RES_BAD_CONFIG;
+    }
+    let fallback = Some(fallback).filter(|fallback| !fallback.servers.is_empty());
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+        dev.set_dns_fallback_servers(fallback)
+            .telio_log_result("telio_set_dns_fallback_servers")
+    })
+}
+
+#[no_mangle]
+/// Clamps TTLs of the records resolved by magic DNS to the given range.
+///
+/// Records with shorter TTL are cached and reported with `min_ttl_secs`, records with longer
+/// TTL with `max_ttl_secs`. Applies to forwarded and meshnet records. Can be called before
+/// `telio_enable_magic_dns`, the setting is kept until replaced.
+///
+/// # Parameters
+/// - `min_ttl_secs`: Minimum TTL in seconds, 0 leaves it unset (default).
+/// - `max_ttl_secs`: Maximum TTL in seconds, 0 leaves it unset (default).
+///
+/// # Returns
+/// `TELIO_RES_BAD_CONFIG` if both bounds are set and `min_ttl_secs` exceeds `max_ttl_secs`.
+///
+pub extern "C" fn telio_set_dns_ttl_override(
+    dev: &telio,
+    min_ttl_secs: u32,
+    max_ttl_secs: u32,
+) -> telio_result {
+    telio_log_info!(
+        "telio_set_dns_ttl_override entry with instance id: {}. Min: {}. Max: {}",
+        dev.id,
+        min_ttl_secs,
+        max_ttl_secs
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+        dev.set_dns_ttl_override(DnsTtlOverride {
+            min_ttl_secs,
+            max_ttl_secs,
+        })
+        .telio_log_result("telio_set_dns_ttl_override")
+    })
+}
+
+#[no_mangle]
+/// Resolves names outside of the meshnet with a DNS over HTTPS server.
+///
+/// Magic DNS sends the queries in HTTPS POST requests to the server instead of sending them
+/// over UDP to the servers given to `telio_enable_magic_dns`. Connections to the server go
+/// through the proxy set with `telio_set_proxy_settings`, if any. Hostname of the server is
+/// resolved by the servers given to `telio_enable_magic_dns`, over TCP through the proxy if set.
+/// Can be called before `telio_enable_magic_dns`, the setting is kept until
+/// `telio_disable_dns_over_https`.
+///
+/// # Parameters
+/// - `doh_url`: URL of the server, e.g. `https://cloudflare-dns.com/dns-query`.
+///
+/// # Returns
+/// `TELIO_RES_BAD_CONFIG` if the URL is not an `https` URL with `/dns-query` path.
+///
+pub extern "C" fn telio_enable_dns_over_https(dev: &telio, doh_url: *const c_char) -> telio_result {
+    let doh_url = ffi_try!(char_to_str(doh_url));
+    telio_log_info!(
+        "telio_enable_dns_over_https entry with instance id: {}. Url: {}",
+        dev.id,
+        doh_url
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+        dev.enable_dns_over_https(doh_url)
+            .telio_log_result("telio_enable_dns_over_https")
+    })
+}
+
+#[no_mangle]
+/// Resolves names outside of the meshnet over UDP with the servers given to
+/// `telio_enable_magic_dns` again.
+pub extern "C" fn telio_disable_dns_over_https(dev: &telio) -> telio_result {
+    telio_log_info!(
+        "telio_disable_dns_over_https entry with instance id: {}.",
+        dev.id
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+        dev.disable_dns_over_https()
+            .telio_log_result("telio_disable_dns_over_https")
+    })
+}
+
 #[no_mangle]
 /// Disables magic DNS if it was enabled.
 pub extern "C" fn telio_disable_magic_dns(dev: &telio) -> telio_result {
@@ -723,13 +1930,143 @@ pub extern "C" fn telio_disable_magic_dns(dev: &telio) -> telio_result {
         dev.id
     );
     ffi_catch_panic!({
-        let dev = ffi_try!(dev.inner.lock().map_err(|_| TELIO_RES_BAD_CONFIG));
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_BAD_CONFIG));
 
         dev.disable_magic_dns()
             .telio_log_result("telio_disable_magic_dns")
     })
 }
 
+#[no_mangle]
+/// Resolves a name through magic DNS without routing system DNS through the tunnel.
+///
+/// Magic DNS has to be enabled. Names outside of the meshnet are forwarded to the servers given to
+/// `telio_enable_magic_dns`. Blocks until the answer arrives or `proxy_dns_query_timeout_ms`
+/// feature (5 seconds by default) elapses.
+///
+/// # Parameters
+/// - `query_name`: Domain name to resolve, e.g. `peer.nord`.
+/// - `record_type`: Type of the requested records, e.g. `A` or `AAAA`.
+///
+/// # Returns
+/// JSON string `{"name":"peer.nord.","type":"A","ttl":300,"records":["100.64.0.1"]}`.
+/// NULL is returned if the query fails or times out.
+///
+pub extern "C" fn telio_proxy_dns_query(
+    dev: &telio,
+    query_name: *const c_char,
+    record_type: *const c_char,
+) -> *mut c_char {
+    let (query_name, record_type) = match (char_to_str(query_name), char_to_str(record_type)) {
+        (Ok(query_name), Ok(record_type)) => (query_name, record_type),
+        _ => return std::ptr::null_mut(),
+    };
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_proxy_dns_query: dev lock: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+
+    match dev.proxy_dns_query(query_name, record_type) {
+        Ok(response) => serialize_to_unmanaged_string("telio_proxy_dns_query", &response),
+        Err(err) => {
+            telio_log_error!("telio_proxy_dns_query: {}", err);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+/// Resolves a meshnet hostname to its addresses through magic DNS.
+///
+/// Magic DNS has to be enabled. Both A and AAAA records are queried, each query is limited just
+/// like in `telio_proxy_dns_query`.
+///
+/// # Parameters
+/// - `hostname`: Hostname of the meshnet node, e.g. `peer.nord`.
+///
+/// # Returns
+/// JSON array of addresses, e.g. `["100.64.0.1","fd74:656c:696f::1"]`, empty if the name has
+/// no records. NULL is returned if a query fails or times out.
+///
+pub extern "C" fn telio_resolve_mesh_hostname(dev: &telio, hostname: *const c_char) -> *mut c_char {
+    let hostname = match char_to_str(hostname) {
+        Ok(hostname) => hostname,
+        Err(_) => return std::ptr::null_mut(),
+    };
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_resolve_mesh_hostname: dev lock: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+
+    match dev.resolve_mesh_hostname(hostname) {
+        Ok(addresses) => serialize_to_unmanaged_string("telio_resolve_mesh_hostname", &addresses),
+        Err(err) => {
+            telio_log_error!("telio_resolve_mesh_hostname: {}", err);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+/// Answers magic DNS queries for a name with the given addresses.
+///
+/// Injected records take precedence over meshnet records and forwarding, an existing override of
+/// the name is replaced. Magic DNS has to be enabled, overrides are dropped when it is disabled.
+///
+/// # Parameters
+/// - `name`:           Domain name, e.g. `test.mesh`.
+/// - `addresses_json`: JSON array of IPv4 and IPv6 addresses, served as A and AAAA records.
+/// - `ttl`:            TTL of the records, in seconds.
+///
+pub extern "C" fn telio_dns_override(
+    dev: &telio,
+    name: *const c_char,
+    addresses_json: *const c_char,
+    ttl: u32,
+) -> telio_result {
+    let name = ffi_try!(char_to_str(name));
+    let addresses_str = ffi_try!(char_to_str(addresses_json));
+    let addresses: Vec<IpAddr> = ffi_try!(serde_json::from_str(addresses_str));
+    telio_log_info!(
+        "telio_dns_override entry with instance id: {}. Name: {}. Addresses: {:?}. TTL: {}",
+        dev.id,
+        name,
+        addresses,
+        ttl
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+        dev.dns_override(name, addresses, ttl)
+            .telio_log_result("telio_dns_override")
+    })
+}
+
+#[no_mangle]
+/// Removes records injected with `telio_dns_override`.
+///
+/// # Parameters
+/// - `name`: Domain name passed to `telio_dns_override`.
+///
+pub extern "C" fn telio_dns_remove_override(dev: &telio, name: *const c_char) -> telio_result {
+    let name = ffi_try!(char_to_str(name));
+    telio_log_info!(
+        "telio_dns_remove_override entry with instance id: {}. Name: {}",
+        dev.id,
+        name
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+        dev.dns_remove_override(name)
+            .telio_log_result("telio_dns_remove_override")
+    })
+}
+
 #[no_mangle]
 /// Disconnects from specified exit node.
 ///
@@ -746,7 +2083,7 @@ pub extern "C" fn telio_disconnect_from_exit_node(
         public_key
     );
     ffi_catch_panic!({
-        let dev = ffi_try!(dev.inner.lock().map_err(|_| TELIO_RES_LOCK_ERROR));
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
         let public_key = if !public_key.is_null() {
             ffi_try!(char_ptr_to_type::<PublicKey>(public_key))
         } else {
@@ -767,13 +2104,88 @@ pub extern "C" fn telio_disconnect_from_exit_nodes(dev: &telio) -> telio_result
         dev.id
     );
     ffi_catch_panic!({
-        let dev = ffi_try!(dev.inner.lock().map_err(|_| TELIO_RES_LOCK_ERROR));
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
 
         dev.disconnect_exit_nodes()
             .telio_log_result("telio_disconnect_from_exit_nodes")
     })
 }
 
+#[no_mangle]
+/// Switches between exit nodes automatically.
+///
+/// If the connected exit node is not in the list, the first node is connected. When nothing
+/// is received from the connected exit node for longer than `exit_node_failover_timeout_secs`
+/// feature (30 seconds by default), the next node in the list is connected and
+/// `ExitNodeFailover` event is reported. After the last node, the list starts over from the
+/// first one. Failover pauses while another exit node is connected manually.
+///
+/// # Parameters
+/// - `failover_config_json`: JSON array of exit nodes in priority order, e.g.
+///   `[{"public_key":"...","endpoint":"1.2.3.4:51820"},{"public_key":"..."}]`.
+///   `endpoint` is set for VPN servers and omitted for meshnet exit nodes.
+///
+pub extern "C" fn telio_enable_exit_node_failover(
+    dev: &telio,
+    failover_config_json: *const c_char,
+) -> telio_result {
+    let config_str = ffi_try!(char_to_str(failover_config_json));
+    let nodes: Vec<FailoverExitNode> = ffi_try!(serde_json::from_str(config_str));
+    telio_log_info!(
+        "telio_enable_exit_node_failover entry with instance id: {}. Nodes: {:?}",
+        dev.id,
+        nodes
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+
+        dev.enable_exit_node_failover(nodes)
+            .telio_log_result("telio_enable_exit_node_failover")
+    })
+}
+
+#[no_mangle]
+/// Stops switching exit nodes automatically, the connected exit node is kept.
+pub extern "C" fn telio_disable_exit_node_failover(dev: &telio) -> telio_result {
+    telio_log_info!(
+        "telio_disable_exit_node_failover entry with instance id: {}.",
+        dev.id
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+
+        dev.disable_exit_node_failover()
+            .telio_log_result("telio_disable_exit_node_failover")
+    })
+}
+
+#[no_mangle]
+/// Get exit nodes the device is currently connected to.
+///
+/// # Returns
+/// JSON array
+/// `[{"identifier":"...","public_key":"...","allowed_ips":["0.0.0.0/0"],"endpoint":"1.2.3.4:51820"}]`,
+/// empty if no exit node is connected. `endpoint` is null for meshnet exit nodes.
+/// NULL is returned on failure.
+///
+pub extern "C" fn telio_get_connected_exit_nodes(dev: &telio) -> *mut c_char {
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_get_connected_exit_nodes: dev lock: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+
+    match dev.get_connected_exit_nodes() {
+        Ok(nodes) => serialize_to_unmanaged_string("telio_get_connected_exit_nodes", &nodes),
+        Err(err) => {
+            telio_log_error!("telio_get_connected_exit_nodes: {}", err);
+            std::ptr::null_mut()
+        }
+    }
+}
+
 #[no_mangle]
 /// Enables meshnet if it is not enabled yet.
 /// In case meshnet is enabled, this updates the peer map with the specified one.
@@ -781,9 +2193,19 @@ pub extern "C" fn telio_disconnect_from_exit_nodes(dev: &telio) -> telio_result
 /// # Parameters
 /// - `cfg`: Output of GET /v1/meshnet/machines/{machineIdentifier}/map
 ///
+/// # Returns
+/// `TELIO_RES_BAD_CONFIG` if `timestamp` of the config is older than allowed by
+/// `config_expiry_policy` feature with `refuse` action. `StaleConfig` event is reported for
+/// stale configs regardless of the action.
+///
+/// `TELIO_RES_BAD_CONFIG` if the config has more peers than allowed by `max_peers` feature.
+///
+/// `TELIO_RES_BAD_CONFIG` if the config assigns the same IP address to more than one node and
+/// the ip conflict policy is `detect_and_refuse`, see `telio_set_ip_conflict_resolution_policy`.
+///
 pub extern "C" fn telio_set_meshnet(dev: &telio, cfg: *const c_char) -> telio_result {
     ffi_catch_panic!({
-        let telio_dev = ffi_try!(dev.inner.lock().map_err(|_| TELIO_RES_LOCK_ERROR));
+        let telio_dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
 
         if cfg.is_null() {
             telio_log_debug!("Stopping meshnet due to empty config");
@@ -817,91 +2239,2389 @@ pub extern "C" fn telio_set_meshnet(dev: &telio, cfg: *const c_char) -> telio_re
                 .set_config(&Some(cfg))
                 .telio_log_result("telio_set_meshnet")
         }
-    })
+    })
+}
+
+#[no_mangle]
+/// Compares peers of two meshnet configs.
+///
+/// Peers are matched by their public keys. Device state is not used or changed.
+///
+/// # Parameters
+/// - `old_cfg`: JSON string of the old config, in the format of `telio_set_meshnet`.
+/// - `new_cfg`: JSON string of the new config.
+///
+/// # Returns
+/// JSON object with base64 encoded public keys of the peers, e.g.
+/// `{"added":["pk1"],"removed":["pk2"],"modified":["pk3"]}`, or NULL if a config is invalid.
+///
+pub extern "C" fn telio_get_config_diff(
+    _dev: &telio,
+    old_cfg: *const c_char,
+    new_cfg: *const c_char,
+) -> *mut c_char {
+    let parse = |cfg: *const c_char| -> Result<Config, telio_result> {
+        let cfg_str = char_to_str(cfg)?;
+        let cfg: PartialConfig = serde_json::from_str(cfg_str).map_err(|err| {
+            telio_log_error!("telio_get_config_diff: {}", err);
+            TELIO_RES_INVALID_STRING
+        })?;
+        Ok(cfg.to_config().0)
+    };
+    match (parse(old_cfg), parse(new_cfg)) {
+        (Ok(old_cfg), Ok(new_cfg)) => serialize_to_unmanaged_string(
+            "telio_get_config_diff",
+            &diff_configs(&old_cfg, &new_cfg),
+        ),
+        _ => std::ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+/// Disables the meshnet functionality by closing all the connections.
+pub extern "C" fn telio_set_meshnet_off(dev: &telio) -> telio_result {
+    telio_log_info!("telio_set_meshnet_off entry with instance id: {}.", dev.id);
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+
+        dev.set_config(&None)
+            .telio_log_result("telio_set_meshnet_off")
+    })
+}
+
+#[no_mangle]
+/// Get a hash of the last successfully applied meshnet config.
+///
+/// Can be compared with the previously returned one to skip pushing configs which did not change.
+///
+/// # Returns
+/// Hex encoded SHA-256 hash of the canonical JSON form of the config. String of 64 zeroes is
+/// returned if meshnet is not configured, NULL is returned on failure.
+pub extern "C" fn telio_get_meshnet_config_hash(dev: &telio) -> *mut c_char {
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_get_meshnet_config_hash: dev lock: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+
+    match dev.get_meshnet_config_hash() {
+        Ok(hash) => bytes_to_zero_terminated_unmanaged_bytes(hash.as_bytes()),
+        Err(err) => {
+            telio_log_error!("telio_get_meshnet_config_hash: {}", err);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+/// Get the hostname of this device in the meshnet.
+///
+/// # Returns
+/// Hostname from the meshnet config, e.g. `mydevice.nord`. NULL is returned if meshnet is not
+/// configured or on failure.
+pub extern "C" fn telio_get_mesh_hostname(dev: &telio) -> *mut c_char {
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_get_mesh_hostname: dev lock: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+
+    match dev.get_mesh_hostname() {
+        Ok(Some(hostname)) => bytes_to_zero_terminated_unmanaged_bytes(hostname.as_bytes()),
+        Ok(None) => std::ptr::null_mut(),
+        Err(err) => {
+            telio_log_error!("telio_get_mesh_hostname: {}", err);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+/// Get the time the meshnet config was last applied.
+///
+/// Set when `telio_set_meshnet` or `telio_set_meshnet_off` completes successfully.
+///
+/// # Returns
+/// Unix timestamp in seconds, 0 if no config was applied or on failure.
+pub extern "C" fn telio_get_last_config_apply_time(dev: &telio) -> u64 {
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_get_last_config_apply_time: dev lock: {}", err);
+            return 0;
+        }
+    };
+
+    match dev.get_last_config_apply_time() {
+        Ok(time) => time.unwrap_or(0),
+        Err(err) => {
+            telio_log_error!("telio_get_last_config_apply_time: {}", err);
+            0
+        }
+    }
+}
+
+#[no_mangle]
+/// Get IP stack of the device, derived from the addresses in the meshnet config.
+///
+/// # Returns
+/// One of "ipv4_only", "ipv6_only" or "dual_stack". NULL is returned if meshnet is not
+/// configured or on failure.
+pub extern "C" fn telio_get_ip_stack(dev: &telio) -> *mut c_char {
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_get_ip_stack: dev lock: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+
+    match dev.get_ip_stack() {
+        Ok(stack) => bytes_to_zero_terminated_unmanaged_bytes(stack.as_str().as_bytes()),
+        Err(err) => {
+            telio_log_error!("telio_get_ip_stack: {}", err);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+/// Get the routing decision for traffic to the given IP address.
+///
+/// Performs longest prefix match against allowed IPs of the WireGuard peers, similar to
+/// `ip route get` for the tunnel interface.
+///
+/// # Parameters
+/// - `ip`: IPv4 or IPv6 address, e.g. `100.64.1.5`.
+///
+/// # Returns
+/// JSON string `{"ip":"100.64.1.5","peer_pk":"...","path":"direct","is_exit":false,"interface":"nlx0"}`,
+/// `is_exit` is true when traffic goes through the exit node. NULL is returned if no route
+/// matches or on failure.
+pub extern "C" fn telio_get_peer_route(dev: &telio, ip: *const c_char) -> *mut c_char {
+    let ip = match char_ptr_to_type::<IpAddr>(ip) {
+        Ok(ip) => ip,
+        Err(_) => return std::ptr::null_mut(),
+    };
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_get_peer_route: dev lock: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+
+    match dev.get_peer_route(ip) {
+        Ok(route) => serialize_to_unmanaged_string("telio_get_peer_route", &route),
+        Err(err) => {
+            telio_log_error!("telio_get_peer_route: {}", err);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+/// Get the allowed IPs of the peer as currently configured on the WireGuard adapter.
+///
+/// Reflects what is actually routed to the peer, including changes made at runtime, e.g. by
+/// connecting to an exit node, rather than the meshnet config given to `telio_set_meshnet`.
+///
+/// # Parameters
+/// - `public_key`: Base64 encoded WireGuard public key of the peer.
+///
+/// # Returns
+/// JSON array of CIDR strings, e.g. `["100.64.1.5/32"]`, empty if the peer has no routes
+/// configured. NULL is returned on failure.
+pub extern "C" fn telio_get_peer_allowed_ips(
+    dev: &telio,
+    public_key: *const c_char,
+) -> *mut c_char {
+    let public_key = match char_ptr_to_type::<PublicKey>(public_key) {
+        Ok(public_key) => public_key,
+        Err(_) => return std::ptr::null_mut(),
+    };
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_get_peer_allowed_ips: dev lock: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+
+    match dev.get_peer_allowed_ips(public_key) {
+        Ok(allowed_ips) => {
+            serialize_to_unmanaged_string("telio_get_peer_allowed_ips", &allowed_ips)
+        }
+        Err(err) => {
+            telio_log_error!("telio_get_peer_allowed_ips: {}", err);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+/// Get the routes to the tunnel interface managed by telio, for debugging routing conflicts.
+///
+/// Routes are the allowed IPs of all peers configured on the WireGuard adapter. After
+/// `telio_stop` no routes are managed and an empty array is returned.
+///
+/// # Returns
+/// JSON array, e.g.
+/// `[{"prefix":"0.0.0.0/0","gateway":null,"interface":"nlx0","metric":null,"source":"exit_node"}]`,
+/// `source` is either `exit_node` or `meshnet`. NULL is returned on failure.
+pub extern "C" fn telio_get_route_table(dev: &telio) -> *mut c_char {
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_get_route_table: dev lock: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+
+    match dev.get_route_table() {
+        Ok(routes) => serialize_to_unmanaged_string("telio_get_route_table", &routes),
+        Err(DevError::NotStarted) => {
+            serialize_to_unmanaged_string("telio_get_route_table", &Vec::<ManagedRoute>::new())
+        }
+        Err(err) => {
+            telio_log_error!("telio_get_route_table: {}", err);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+/// Asks a sleeping meshnet peer to wake up.
+///
+/// The request is sent over the relay, so no direct connection to the peer is
+/// needed. The peer reports it with a `PeerWakeRequest` event and probes its
+/// endpoints again. Delivery is not confirmed.
+///
+/// # Parameters
+/// - `public_key`: Base64 encoded WireGuard public key of the meshnet peer.
+///
+pub extern "C" fn telio_meshnet_wake_peer(dev: &telio, public_key: *const c_char) -> telio_result {
+    telio_log_info!(
+        "telio_meshnet_wake_peer entry with instance id: {}. Public Key: {:?}",
+        dev.id,
+        public_key
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+        let public_key = ffi_try!(char_ptr_to_type::<PublicKey>(public_key));
+
+        dev.wake_peer(public_key)
+            .telio_log_result("telio_meshnet_wake_peer")
+    })
+}
+
+#[no_mangle]
+/// Verifies that a meshnet peer owns the private key of its public key.
+///
+/// A random 32 byte nonce is sent to the peer over the relay, and the peer answers
+/// with its signature of the nonce. The signature is created with XEdDSA, the Ed25519
+/// key pair derived from the WireGuard key of the peer. The result is reported with an
+/// `AuthChallengeResult` event, nothing is reported if the peer doesn't answer.
+///
+/// # Parameters
+/// - `peer_pk`: Base64 encoded WireGuard public key of the meshnet peer.
+///
+pub extern "C" fn telio_meshnet_send_auth_challenge(
+    dev: &telio,
+    peer_pk: *const c_char,
+) -> telio_result {
+    let peer_pk = ffi_try!(char_ptr_to_type::<PublicKey>(peer_pk));
+    telio_log_info!(
+        "telio_meshnet_send_auth_challenge entry with instance id: {}. Public Key: {:?}",
+        dev.id,
+        peer_pk
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+        dev.send_auth_challenge(peer_pk)
+            .telio_log_result("telio_meshnet_send_auth_challenge")
+    })
+}
+
+#[no_mangle]
+/// Sends a WireGuard keepalive to the meshnet peer right away.
+///
+/// Keepalives are otherwise sent by the persistent keepalive timer of each peer, which may lag
+/// behind after the system was asleep. The keepalive is sent regardless of the timer state.
+///
+/// # Parameters
+/// - `public_key`: Base64 encoded WireGuard public key of the meshnet peer.
+///
+pub extern "C" fn telio_meshnet_peer_keepalive_probe(
+    dev: &telio,
+    public_key: *const c_char,
+) -> telio_result {
+    let public_key = ffi_try!(char_ptr_to_type::<PublicKey>(public_key));
+    telio_log_info!(
+        "telio_meshnet_peer_keepalive_probe entry with instance id: {}. Public Key: {:?}",
+        dev.id,
+        public_key
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+
+        dev.send_keepalive(public_key)
+            .telio_log_result("telio_meshnet_peer_keepalive_probe")
+    })
+}
+
+#[no_mangle]
+/// Asks the application to request a new meshnet IP address for this device.
+///
+/// Reports `IpReassignmentRequested` event, the application should then ask the backend for a
+/// new assignment and pass the updated config to `telio_set_meshnet`. libtelio does not
+/// contact the backend itself.
+///
+/// # Returns
+/// `TELIO_RES_ERROR` if meshnet is not configured.
+///
+pub extern "C" fn telio_meshnet_request_ip_reassignment(dev: &telio) -> telio_result {
+    telio_log_info!(
+        "telio_meshnet_request_ip_reassignment entry with instance id: {}.",
+        dev.id
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+
+        dev.request_ip_reassignment()
+            .telio_log_result("telio_meshnet_request_ip_reassignment")
+    })
+}
+
+#[no_mangle]
+/// Bans a meshnet peer locally, all traffic from it is dropped.
+///
+/// The peer is kept in the WireGuard configuration, so the ban can be lifted with
+/// `telio_meshnet_unban_peer` without a meshnet config change. Bans are cleared on
+/// `telio_stop`.
+///
+/// # Parameters
+/// - `public_key`: Base64 encoded WireGuard public key of the peer.
+///
+pub extern "C" fn telio_meshnet_ban_peer(dev: &telio, public_key: *const c_char) -> telio_result {
+    telio_log_info!(
+        "telio_meshnet_ban_peer entry with instance id: {}. Public Key: {:?}",
+        dev.id,
+        public_key
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+        let public_key = ffi_try!(char_ptr_to_type::<PublicKey>(public_key));
+
+        dev.ban_peer(public_key)
+            .telio_log_result("telio_meshnet_ban_peer")
+    })
+}
+
+#[no_mangle]
+/// Lifts the ban set by `telio_meshnet_ban_peer`.
+///
+/// # Parameters
+/// - `public_key`: Base64 encoded WireGuard public key of the peer.
+///
+pub extern "C" fn telio_meshnet_unban_peer(dev: &telio, public_key: *const c_char) -> telio_result {
+    telio_log_info!(
+        "telio_meshnet_unban_peer entry with instance id: {}. Public Key: {:?}",
+        dev.id,
+        public_key
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+        let public_key = ffi_try!(char_ptr_to_type::<PublicKey>(public_key));
+
+        dev.unban_peer(public_key)
+            .telio_log_result("telio_meshnet_unban_peer")
+    })
+}
+
+#[no_mangle]
+/// Get peers banned with `telio_meshnet_ban_peer`.
+///
+/// # Returns
+/// JSON array of base64 encoded public keys, e.g. `["5Mzuv..."]`. NULL is returned on failure.
+pub extern "C" fn telio_meshnet_list_banned_peers(dev: &telio) -> *mut c_char {
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_meshnet_list_banned_peers: dev lock: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+
+    match dev.get_banned_peers() {
+        Ok(peers) => serialize_to_unmanaged_string("telio_meshnet_list_banned_peers", &peers),
+        Err(err) => {
+            telio_log_error!("telio_meshnet_list_banned_peers: {}", err);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+/// Sends an application message to a meshnet peer over the relay.
+///
+/// # Parameters
+/// - `recipient_public_key`: Base64 encoded WireGuard public key of the meshnet peer.
+/// - `payload`:              Message data, at most 4096 bytes.
+/// - `len`:                  Length of the message data.
+///
+pub extern "C" fn telio_meshnet_send_custom_message(
+    dev: &telio,
+    recipient_public_key: *const c_char,
+    payload: *const u8,
+    len: usize,
+) -> telio_result {
+    telio_log_info!(
+        "telio_meshnet_send_custom_message entry with instance id: {}. Public Key: {:?}. Length: {}",
+        dev.id,
+        recipient_public_key,
+        len
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+        let public_key = if !recipient_public_key.is_null() {
+            ffi_try!(char_ptr_to_type::<PublicKey>(recipient_public_key))
+        } else {
+            telio_log_debug!("Public Key is NULL");
+            return TELIO_RES_ERROR;
+        };
+
+        if len > MAX_APPLICATION_PAYLOAD_SIZE {
+            telio_log_debug!(
+                "Payload of {} bytes exceeds limit of {} bytes",
+                len,
+                MAX_APPLICATION_PAYLOAD_SIZE
+            );
+            return TELIO_RES_BAD_CONFIG;
+        }
+        let payload = match (payload.is_null(), len) {
+            (_, 0) => &[][..],
+            (true, _) => {
+                telio_log_debug!("Payload is NULL");
+                return TELIO_RES_ERROR;
+            }
+            (false, len) => unsafe { std::slice::from_raw_parts(payload, len) },
+        };
+
+        dev.send_custom_message(public_key, payload)
+            .telio_log_result("telio_meshnet_send_custom_message")
+    })
+}
+
+#[no_mangle]
+/// Sends an application message to all connected meshnet peers over the relay.
+///
+/// Peers receive it through the callback registered with `telio_set_custom_message_handler`.
+///
+/// # Parameters
+/// - `payload`:  Message data, at most 4096 bytes.
+/// - `len`:      Length of the message data.
+/// - `enqueued`: Set to the number of peers the message was enqueued for, may be NULL.
+///               Delivery to the peers is not confirmed.
+///
+pub extern "C" fn telio_meshnet_broadcast(
+    dev: &telio,
+    payload: *const u8,
+    len: usize,
+    enqueued: *mut usize,
+) -> telio_result {
+    telio_log_info!(
+        "telio_meshnet_broadcast entry with instance id: {}. Length: {}",
+        dev.id,
+        len
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+
+        if len > MAX_APPLICATION_PAYLOAD_SIZE {
+            telio_log_debug!(
+                "Payload of {} bytes exceeds limit of {} bytes",
+                len,
+                MAX_APPLICATION_PAYLOAD_SIZE
+            );
+            return TELIO_RES_BAD_CONFIG;
+        }
+        let payload = match (payload.is_null(), len) {
+            (_, 0) => &[][..],
+            (true, _) => {
+                telio_log_debug!("Payload is NULL");
+                return TELIO_RES_ERROR;
+            }
+            (false, len) => unsafe { std::slice::from_raw_parts(payload, len) },
+        };
+
+        dev.broadcast_custom_message(payload)
+            .map(|count| {
+                telio_log_debug!("telio_meshnet_broadcast: enqueued for {} peers", count);
+                if !enqueued.is_null() {
+                    unsafe { *enqueued = count };
+                }
+            })
+            .telio_log_result("telio_meshnet_broadcast")
+    })
+}
+
+#[no_mangle]
+/// Registers a callback for application messages received from meshnet peers.
+///
+/// Replaces previously registered callback.
+///
+/// # Parameters
+/// - `handler`: Callback receiving base64 encoded sender public key and message data.
+///
+pub extern "C" fn telio_set_custom_message_handler(
+    dev: &telio,
+    handler: telio_custom_message_cb,
+) -> telio_result {
+    telio_log_info!(
+        "telio_set_custom_message_handler entry with instance id: {}.",
+        dev.id
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+
+        let handler = move |sender: PublicKey, payload: Vec<u8>| {
+            let _ = CString::new(sender.to_string())
+                .map(|s| unsafe {
+                    (handler.cb)(handler.ctx, s.as_ptr(), payload.as_ptr(), payload.len())
+                })
+                .map_err(|e| telio_log_warn!("Failed to create CString: {:?}", e));
+        };
+        dev.set_custom_message_handler(Some(std::sync::Arc::new(handler)));
+
+        TELIO_RES_OK
+    })
+}
+
+#[no_mangle]
+/// Registers a callback detecting network changes, for hosts which don't call
+/// `telio_notify_network_change` themselves.
+///
+/// The callback is polled every 5 seconds from the telio runtime, so it must return quickly.
+/// It returns an identifier of the current state of the network interfaces, e.g. a hash of
+/// the interface addresses and the default route. A change of the identifier is handled like
+/// `telio_notify_network_change`. Replaces previously registered callback,
+/// `telio_unregister_link_state_provider` removes it.
+///
+/// # Parameters
+/// - `provider`: Callback returning the identifier of the network interfaces state.
+///
+pub extern "C" fn telio_register_link_state_provider(
+    dev: &telio,
+    provider: telio_link_state_provider_cb,
+) -> telio_result {
+    telio_log_info!(
+        "telio_register_link_state_provider entry with instance id: {}.",
+        dev.id
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+
+        let provider = move || unsafe { (provider.cb)(provider.ctx) };
+        dev.set_link_state_provider(Some(std::sync::Arc::new(provider)));
+
+        TELIO_RES_OK
+    })
+}
+
+#[no_mangle]
+/// Unregisters the callback registered with `telio_register_link_state_provider`.
+///
+/// A poll already in progress may still be running the callback when this returns.
+///
+pub extern "C" fn telio_unregister_link_state_provider(dev: &telio) -> telio_result {
+    telio_log_info!(
+        "telio_unregister_link_state_provider entry with instance id: {}.",
+        dev.id
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+        dev.set_link_state_provider(None);
+        TELIO_RES_OK
+    })
+}
+
+#[no_mangle]
+/// Encrypts application payload so that only the given meshnet peer can read it.
+///
+/// Key is derived from X25519 agreement of the device private key and `recipient_pk` and the
+/// direction from this device to the recipient, and is used only for application payloads,
+/// payload is sealed with XChaCha20-Poly1305. Peer decrypts it with `telio_decrypt_payload`.
+///
+/// # Parameters
+/// - `recipient_pk`: Base64 encoded WireGuard public key of the recipient.
+/// - `plaintext`:    Payload to encrypt.
+/// - `len`:          Length of the payload.
+/// - `out`:          Set to the encrypted payload, must be freed with `telio_free_bytes`.
+/// - `out_len`:      Set to the length of the encrypted payload.
+///
+pub extern "C" fn telio_encrypt_payload(
+    dev: &telio,
+    recipient_pk: *const c_char,
+    plaintext: *const u8,
+    len: usize,
+    out: *mut *mut u8,
+    out_len: *mut usize,
+) -> telio_result {
+    telio_log_info!(
+        "telio_encrypt_payload entry with instance id: {}. Public Key: {:?}. Length: {}",
+        dev.id,
+        recipient_pk,
+        len
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+        let (public_key, plaintext) = ffi_try!(payload_crypto_args(
+            recipient_pk,
+            plaintext,
+            len,
+            out,
+            out_len
+        ));
+
+        dev.encrypt_payload(&public_key, plaintext)
+            .map(|ciphertext| unsafe { write_unmanaged_bytes(&ciphertext, out, out_len) })
+            .telio_log_result("telio_encrypt_payload")
+    })
+}
+
+#[no_mangle]
+/// Decrypts application payload encrypted by a meshnet peer with `telio_encrypt_payload`.
+///
+/// # Parameters
+/// - `sender_pk`:  Base64 encoded WireGuard public key of the sender.
+/// - `ciphertext`: Encrypted payload.
+/// - `len`:        Length of the encrypted payload.
+/// - `out`:        Set to the decrypted payload, must be freed with `telio_free_bytes`.
+/// - `out_len`:    Set to the length of the decrypted payload.
+///
+pub extern "C" fn telio_decrypt_payload(
+    dev: &telio,
+    sender_pk: *const c_char,
+    ciphertext: *const u8,
+    len: usize,
+    out: *mut *mut u8,
+    out_len: *mut usize,
+) -> telio_result {
+    telio_log_info!(
+        "telio_decrypt_payload entry with instance id: {}. Public Key: {:?}. Length: {}",
+        dev.id,
+        sender_pk,
+        len
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+        let (public_key, ciphertext) = ffi_try!(payload_crypto_args(
+            sender_pk, ciphertext, len, out, out_len
+        ));
+
+        dev.decrypt_payload(&public_key, ciphertext)
+            .map(|plaintext| unsafe { write_unmanaged_bytes(&plaintext, out, out_len) })
+            .telio_log_result("telio_decrypt_payload")
+    })
+}
+
+#[no_mangle]
+/// Signs data with the device WireGuard private key, e.g. to attest identity to a peer.
+///
+/// Signature is created with XEdDSA over `data` prefixed with a context of its own, so the peer
+/// verifies it with the WireGuard public key of the device using `telio_verify_signature`, and
+/// it cannot be used in place of signatures libtelio creates for its own protocols.
+///
+/// # Parameters
+/// - `data`:          Data to sign.
+/// - `len`:           Length of the data.
+/// - `out_signature`: Buffer the signature is written to, at least 64 bytes.
+/// - `out_len`:       Set to the size of `out_signature` by the caller, set to the length of
+///                    the signature on return. `TELIO_RES_BAD_CONFIG` is returned and the
+///                    required size is set if the buffer is too small.
+///
+pub extern "C" fn telio_sign_data(
+    dev: &telio,
+    data: *const u8,
+    len: usize,
+    out_signature: *mut u8,
+    out_len: *mut usize,
+) -> telio_result {
+    telio_log_info!(
+        "telio_sign_data entry with instance id: {}. Length: {}",
+        dev.id,
+        len
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+        if out_signature.is_null() || out_len.is_null() {
+            telio_log_debug!("Signature output is NULL");
+            return TELIO_RES_INVALID_STRING;
+        }
+        let capacity = unsafe { *out_len };
+        if capacity < signature::SIGNATURE_SIZE {
+            telio_log_debug!(
+                "Signature buffer too small: {} < {}",
+                capacity,
+                signature::SIGNATURE_SIZE
+            );
+            unsafe { *out_len = signature::SIGNATURE_SIZE };
+            return TELIO_RES_BAD_CONFIG;
+        }
+        let data = ffi_try!(bytes_arg(data, len));
+
+        dev.sign_data(data)
+            .map(|signature| unsafe {
+                std::slice::from_raw_parts_mut(out_signature, signature.len())
+                    .copy_from_slice(&signature);
+                *out_len = signature.len();
+            })
+            .telio_log_result("telio_sign_data")
+    })
+}
+
+#[no_mangle]
+/// Verifies a signature created by a meshnet peer with `telio_sign_data`.
+///
+/// Does not require a device instance.
+///
+/// # Parameters
+/// - `public_key`: Base64 encoded WireGuard public key of the signer.
+/// - `data`:       Signed data.
+/// - `len`:        Length of the data.
+/// - `signature`:  Signature to verify.
+/// - `sig_len`:    Length of the signature.
+///
+/// # Returns
+/// `TELIO_RES_OK` if the signature is valid, `TELIO_RES_ERROR` if it is not.
+///
+pub extern "C" fn telio_verify_signature(
+    public_key: *const c_char,
+    data: *const u8,
+    len: usize,
+    signature: *const u8,
+    sig_len: usize,
+) -> telio_result {
+    telio_log_info!(
+        "telio_verify_signature entry. Public Key: {:?}. Length: {}. Signature length: {}",
+        public_key,
+        len,
+        sig_len
+    );
+    ffi_catch_panic!({
+        let public_key = ffi_try!(char_ptr_to_type::<PublicKey>(public_key));
+        let data = ffi_try!(bytes_arg(data, len));
+        let signature_bytes = ffi_try!(bytes_arg(signature, sig_len));
+
+        if crate::device::verify_signed_data(&public_key, data, signature_bytes) {
+            TELIO_RES_OK
+        } else {
+            telio_log_debug!("telio_verify_signature: invalid signature");
+            TELIO_RES_ERROR
+        }
+    })
+}
+
+#[no_mangle]
+/// Creates a short-lived token proving that the device owns its WireGuard private key.
+///
+/// Token has the JWT compact form `header.claims.signature` with base64url encoded parts. Claims
+/// are `{"pk":"<public key>","iat":<unix secs>,"exp":<unix secs>}`, the signature is created with
+/// XEdDSA over `header.claims` prefixed with a context of tokens, so signatures of
+/// `telio_sign_data` are not valid tokens.
+///
+/// # Parameters
+/// - `expiry_secs`: Validity of the token, in seconds, capped at one day.
+///
+/// # Returns
+/// Token string, NULL is returned on failure.
+///
+pub extern "C" fn telio_create_meshnet_token(dev: &telio, expiry_secs: u32) -> *mut c_char {
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_create_meshnet_token: dev lock: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+
+    match dev.create_meshnet_token(expiry_secs) {
+        Ok(token) => bytes_to_zero_terminated_unmanaged_bytes(token.as_bytes()),
+        Err(err) => {
+            telio_log_error!("telio_create_meshnet_token: {}", err);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+/// Verifies a token created with `telio_create_meshnet_token`.
+///
+/// Does not require a device instance.
+///
+/// # Parameters
+/// - `token`:       Token to verify.
+/// - `expected_pk`: Base64 encoded WireGuard public key the token has to be issued for.
+///
+/// # Returns
+/// `TELIO_RES_OK` if the token is valid, not expired and not issued in the future,
+/// `TELIO_RES_ERROR` if it is not.
+///
+pub extern "C" fn telio_verify_meshnet_token(
+    token: *const c_char,
+    expected_pk: *const c_char,
+) -> telio_result {
+    telio_log_info!(
+        "telio_verify_meshnet_token entry. Public Key: {:?}",
+        expected_pk
+    );
+    ffi_catch_panic!({
+        let token_str = ffi_try!(char_to_str(token));
+        let expected_pk = ffi_try!(char_ptr_to_type::<PublicKey>(expected_pk));
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        match token::verify(token_str, &expected_pk, now) {
+            Ok(_) => TELIO_RES_OK,
+            Err(err) => {
+                telio_log_debug!("telio_verify_meshnet_token: {}", err);
+                TELIO_RES_ERROR
+            }
+        }
+    })
+}
+
+#[no_mangle]
+/// Frees a buffer returned by `telio_encrypt_payload` or `telio_decrypt_payload`.
+///
+/// Passing NULL is a no-op.
+pub extern "C" fn telio_free_bytes(bytes: *mut u8) {
+    if !bytes.is_null() {
+        unsafe { libc::free(bytes as *mut libc::c_void) };
+    }
+}
+
+#[no_mangle]
+/// Disables direct connections between meshnet peers.
+///
+/// Stops all direct path probing (including STUN) and routes meshnet traffic through the relay.
+/// The setting is kept across `telio_set_meshnet` calls.
+pub extern "C" fn telio_disable_direct_path(dev: &telio) -> telio_result {
+    telio_log_info!(
+        "telio_disable_direct_path entry with instance id: {}.",
+        dev.id
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+
+        dev.disable_direct_path()
+            .telio_log_result("telio_disable_direct_path")
+    })
+}
+
+#[no_mangle]
+/// Re-enables direct connections disabled by `telio_disable_direct_path`.
+pub extern "C" fn telio_enable_direct_path(dev: &telio) -> telio_result {
+    telio_log_info!(
+        "telio_enable_direct_path entry with instance id: {}.",
+        dev.id
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+
+        dev.enable_direct_path()
+            .telio_log_result("telio_enable_direct_path")
+    })
+}
+
+#[no_mangle]
+/// Limits the number of meshnet peers which exchange traffic through the relay at the same time.
+///
+/// The number is unlimited by default. When the limit is reached, sessions of the most recently
+/// active peers are kept and new peers are refused until the least recently used sessions become
+/// idle. The setting is kept across `telio_set_meshnet` calls.
+///
+/// # Parameters
+/// - `max`: Maximum number of simultaneous relay sessions, the minimum allowed value is 1.
+///
+pub extern "C" fn telio_set_max_relay_connections(dev: &telio, max: u32) -> telio_result {
+    telio_log_info!(
+        "telio_set_max_relay_connections entry with instance id: {}. Max: {}",
+        dev.id,
+        max
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+
+        dev.set_max_relay_connections(max)
+            .telio_log_result("telio_set_max_relay_connections")
+    })
+}
+
+#[no_mangle]
+/// Chooses the relay servers the device connects to.
+///
+/// Servers are taken from the meshnet config, the policy is kept across `telio_set_meshnet`
+/// calls. If the connected server is not allowed by the new policy, the relay reconnects.
+///
+/// # Parameters
+/// - `policy_json`: One of:
+///     - `{"strategy":"lowest_latency"}`: try servers in the order of their weight, assigned by
+///       the API according to expected latency. This is the default.
+///     - `{"strategy":"pinned","hostname":"derp1.example.com"}`: connect only to the server with
+///       the hostname, other servers are not tried.
+///
+pub extern "C" fn telio_set_relay_server_selection_policy(
+    dev: &telio,
+    policy_json: *const c_char,
+) -> telio_result {
+    let policy_str = ffi_try!(char_to_str(policy_json));
+    let policy: RelaySelectionPolicy = ffi_try!(serde_json::from_str(policy_str));
+    telio_log_info!(
+        "telio_set_relay_server_selection_policy entry with instance id: {}. Policy: {:?}",
+        dev.id,
+        policy
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+
+        dev.set_relay_server_selection_policy(policy)
+            .telio_log_result("telio_set_relay_server_selection_policy")
+    })
+}
+
+#[no_mangle]
+/// Sets WireGuard persistent keepalive intervals of meshnet peers.
+///
+/// Peers without an override use `default_secs`, or the intervals from the
+/// `persistent_keepalive` feature if it is not set. The policy is kept across
+/// `telio_set_meshnet` calls and private key changes.
+///
+/// # Parameters
+/// - `policy_json`: JSON object, e.g.
+///   `{"default_secs":25,"overrides":[{"public_key":"...","secs":120}]}`.
+///   Intervals are in seconds, at most 65535.
+///
+pub extern "C" fn telio_set_keepalive_policy(
+    dev: &telio,
+    policy_json: *const c_char,
+) -> telio_result {
+    let policy_str = ffi_try!(char_to_str(policy_json));
+    let policy: KeepalivePolicy = ffi_try!(serde_json::from_str(policy_str));
+    telio_log_info!(
+        "telio_set_keepalive_policy entry with instance id: {}. Policy: {:?}",
+        dev.id,
+        policy
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+
+        dev.set_keepalive_policy(policy)
+            .telio_log_result("telio_set_keepalive_policy")
+    })
+}
+
+#[no_mangle]
+/// Sets proxy used for control-plane connections of libtelio.
+///
+/// HTTPS and WebSocket connections to DERP relay servers and connections to the
+/// DNS over HTTPS server of magic DNS are tunneled through the proxy. Data-plane WireGuard UDP traffic, including WireGuard packets to
+/// peers and STUN, always bypasses the proxy. The setting is kept across
+/// `telio_set_meshnet` calls. Changing it reconnects to the relay server.
+///
+/// # Parameters
+/// - `settings_json`: JSON object, e.g.
+///   `{"type":"socks5","host":"proxy.corp.com","port":1080,"username":"...","password":"..."}`.
+///   `type` is `http` (tunnel opened with `CONNECT`) or `socks5`, `username`
+///   and `password` are optional. NULL connects directly.
+///
+pub extern "C" fn telio_set_proxy_settings(
+    dev: &telio,
+    settings_json: *const c_char,
+) -> telio_result {
+    let proxy: Option<ProxySettings> = if settings_json.is_null() {
+        None
+    } else {
+        let settings_str = ffi_try!(char_to_str(settings_json));
+        Some(ffi_try!(serde_json::from_str(settings_str)))
+    };
+    telio_log_info!(
+        "telio_set_proxy_settings entry with instance id: {}. Proxy: {:?}",
+        dev.id,
+        proxy
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+
+        dev.set_proxy_settings(proxy)
+            .telio_log_result("telio_set_proxy_settings")
+    })
+}
+
+#[no_mangle]
+/// Sets handling of IP addresses assigned by the backend to more than one meshnet node.
+///
+/// Overrides `ip_conflict_resolution` feature, the policy is checked on the next
+/// `telio_set_meshnet` call.
+///
+/// # Parameters
+/// - `policy`: One of:
+///     - `detect_and_log`: log the conflict and apply the config.
+///     - `detect_and_refuse`: report `DuplicateIpDetected` event and fail `telio_set_meshnet`
+///       with `TELIO_RES_BAD_CONFIG`.
+///     - `detect_and_resolve_via_backoff`: report `DuplicateIpDetected` event and apply the
+///       config without the conflicting peers. This device keeps its own addresses, among
+///       peers the one with the lowest public key keeps the address.
+///
+///   NULL disables the check.
+///
+pub extern "C" fn telio_set_ip_conflict_resolution_policy(
+    dev: &telio,
+    policy: *const c_char,
+) -> telio_result {
+    let policy: Option<IpConflictPolicy> = if policy.is_null() {
+        None
+    } else {
+        let policy_str = ffi_try!(char_to_str(policy));
+        Some(ffi_try!(serde_json::from_value(serde_json::Value::String(
+            policy_str.to_owned()
+        ))))
+    };
+    telio_log_info!(
+        "telio_set_ip_conflict_resolution_policy entry with instance id: {}. Policy: {:?}",
+        dev.id,
+        policy
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+
+        dev.set_ip_conflict_resolution_policy(policy)
+            .telio_log_result("telio_set_ip_conflict_resolution_policy")
+    })
+}
+
+#[no_mangle]
+/// Creates or replaces a group of meshnet peers sharing the same routing policy.
+///
+/// If this device is a member of the group and the policy specifies an exit node, traffic is
+/// routed through that exit node. Groups are kept across `telio_set_meshnet` calls.
+///
+/// # Parameters
+/// - `group_id`: Identifier of the group.
+/// - `member_keys_json`: JSON array of base64 encoded public keys of the meshnet nodes,
+///                       may include the key of this device.
+/// - `policy_json`: JSON object `{"exit_node":"<public key>","mtu":1280}`, both fields are
+///                  optional. NULL means no policy.
+///
+pub extern "C" fn telio_create_peer_group(
+    dev: &telio,
+    group_id: *const c_char,
+    member_keys_json: *const c_char,
+    policy_json: *const c_char,
+) -> telio_result {
+    telio_log_info!(
+        "telio_create_peer_group entry with instance id: {}.",
+        dev.id
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+        let id = ffi_try!(char_to_str(group_id)).to_owned();
+        let members: Vec<PublicKey> = ffi_try!(serde_json::from_str(ffi_try!(char_to_str(
+            member_keys_json
+        ))));
+        let policy: PeerGroupPolicy = if !policy_json.is_null() {
+            ffi_try!(serde_json::from_str(ffi_try!(char_to_str(policy_json))))
+        } else {
+            Default::default()
+        };
+
+        dev.create_peer_group(PeerGroup {
+            id,
+            members,
+            policy,
+        })
+        .telio_log_result("telio_create_peer_group")
+    })
+}
+
+#[no_mangle]
+/// Deletes a peer group created by `telio_create_peer_group`.
+///
+/// If traffic was routed through the exit node of the group, it is disconnected.
+///
+/// # Parameters
+/// - `group_id`: Identifier of the group.
+///
+pub extern "C" fn telio_delete_peer_group(dev: &telio, group_id: *const c_char) -> telio_result {
+    telio_log_info!(
+        "telio_delete_peer_group entry with instance id: {}.",
+        dev.id
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+        let id = ffi_try!(char_to_str(group_id)).to_owned();
+
+        dev.delete_peer_group(id)
+            .telio_log_result("telio_delete_peer_group")
+    })
+}
+
+#[no_mangle]
+/// Lists peer groups created by `telio_create_peer_group`.
+///
+/// # Returns
+/// JSON array `[{"id":"office","members":["<public key>"],"policy":{"exit_node":"<public key>","mtu":1280}}]`
+/// ordered by group identifiers. NULL is returned on failure.
+///
+pub extern "C" fn telio_list_peer_groups(dev: &telio) -> *mut c_char {
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_list_peer_groups: dev lock: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+
+    match dev.list_peer_groups() {
+        Ok(groups) => serialize_to_unmanaged_string("telio_list_peer_groups", &groups),
+        Err(err) => {
+            telio_log_error!("telio_list_peer_groups: {}", err);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+/// Limits bandwidth of traffic exchanged with the peer.
+///
+/// Installs a token bucket rate limiter on the packet processing path, packets exceeding the limit
+/// are dropped. Replaces previous limits of the peer.
+///
+/// Only the boringtun adapter runs packets through the limiter. With other adapters, or an adapter
+/// given to `telio_start_with_custom_adapter`, the call fails with `TELIO_RES_BAD_CONFIG`.
+///
+/// # Parameters
+/// - `public_key`: Base64 encoded WireGuard public key of the peer.
+/// - `max_tx_kbps`: Limit of traffic sent to the peer in kilobits per second, 0 means unlimited.
+/// - `max_rx_kbps`: Limit of traffic received from the peer in kilobits per second, 0 means unlimited.
+///
+pub extern "C" fn telio_enable_traffic_shaping(
+    dev: &telio,
+    public_key: *const c_char,
+    max_tx_kbps: u32,
+    max_rx_kbps: u32,
+) -> telio_result {
+    telio_log_info!(
+        "telio_enable_traffic_shaping entry with instance id: {}. Public Key: {:?}. Max tx: {} kbps. Max rx: {} kbps",
+        dev.id,
+        public_key,
+        max_tx_kbps,
+        max_rx_kbps
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+        let public_key = ffi_try!(char_ptr_to_type::<PublicKey>(public_key));
+
+        dev.enable_traffic_shaping(public_key, max_tx_kbps, max_rx_kbps)
+            .telio_log_result("telio_enable_traffic_shaping")
+    })
+}
+
+#[no_mangle]
+/// Removes bandwidth limits set by `telio_enable_traffic_shaping`.
+///
+/// # Parameters
+/// - `public_key`: Base64 encoded WireGuard public key of the peer.
+///
+pub extern "C" fn telio_disable_traffic_shaping(
+    dev: &telio,
+    public_key: *const c_char,
+) -> telio_result {
+    telio_log_info!(
+        "telio_disable_traffic_shaping entry with instance id: {}. Public Key: {:?}",
+        dev.id,
+        public_key
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+        let public_key = ffi_try!(char_ptr_to_type::<PublicKey>(public_key));
+
+        dev.disable_traffic_shaping(public_key)
+            .telio_log_result("telio_disable_traffic_shaping")
+    })
+}
+
+#[no_mangle]
+/// Sets a nickname reported for the meshnet peer in the status map.
+///
+/// Nickname is kept until meshnet config is replaced and does not affect the connection.
+/// `TELIO_RES_BAD_CONFIG` is returned if the nickname is not valid, e.g. contains spaces or
+/// upper case letters.
+///
+/// # Parameters
+/// - `public_key`: Base64 encoded WireGuard public key of the meshnet peer.
+/// - `nickname`:   Nickname of the peer, NULL removes previously set nickname.
+///
+pub extern "C" fn telio_set_peer_nickname(
+    dev: &telio,
+    public_key: *const c_char,
+    nickname: *const c_char,
+) -> telio_result {
+    telio_log_info!(
+        "telio_set_peer_nickname entry with instance id: {}. Public Key: {:?}",
+        dev.id,
+        public_key
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+        let public_key = ffi_try!(char_ptr_to_type::<PublicKey>(public_key));
+        let nickname = if !nickname.is_null() {
+            Some(ffi_try!(char_to_str(nickname)).to_owned())
+        } else {
+            None
+        };
+
+        dev.set_peer_nickname(public_key, nickname)
+            .telio_log_result("telio_set_peer_nickname")
+    })
+}
+
+#[no_mangle]
+/// Gets a nickname reported for the meshnet peer.
+///
+/// # Parameters
+/// - `public_key`: Base64 encoded WireGuard public key of the meshnet peer.
+///
+/// # Returns
+/// Nickname set by `telio_set_peer_nickname` or the one from meshnet config. NULL is returned
+/// if the peer has no nickname or is not part of the meshnet.
+///
+pub extern "C" fn telio_get_peer_nickname(dev: &telio, public_key: *const c_char) -> *mut c_char {
+    let public_key = match char_ptr_to_type::<PublicKey>(public_key) {
+        Ok(public_key) => public_key,
+        Err(_) => return std::ptr::null_mut(),
+    };
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_get_peer_nickname: dev lock: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+
+    match dev.get_peer_nickname(public_key) {
+        Ok(Some(nickname)) => bytes_to_zero_terminated_unmanaged_bytes(nickname.as_bytes()),
+        Ok(None) => std::ptr::null_mut(),
+        Err(err) => {
+            telio_log_error!("telio_get_peer_nickname: {}", err);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+/// Gets the operating system of the meshnet peer.
+///
+/// # Parameters
+/// - `public_key`: Base64 encoded WireGuard public key of the meshnet peer.
+///
+/// # Returns
+/// Operating system as reported in the meshnet config, e.g. `linux` or `android`. NULL is
+/// returned if the config does not specify it or the peer is not part of the meshnet.
+///
+pub extern "C" fn telio_get_peer_os(dev: &telio, public_key: *const c_char) -> *mut c_char {
+    let public_key = match char_ptr_to_type::<PublicKey>(public_key) {
+        Ok(public_key) => public_key,
+        Err(_) => return std::ptr::null_mut(),
+    };
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_get_peer_os: dev lock: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+
+    match dev.get_peer_os(public_key) {
+        Ok(Some(os)) => bytes_to_zero_terminated_unmanaged_bytes(os.as_bytes()),
+        Ok(None) => std::ptr::null_mut(),
+        Err(err) => {
+            telio_log_error!("telio_get_peer_os: {}", err);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+/// Gets the telio version running on the meshnet peer.
+///
+/// Peers advertise it along with their capabilities over the relay.
+///
+/// # Parameters
+/// - `public_key`: Base64 encoded WireGuard public key of the meshnet peer.
+///
+/// # Returns
+/// Version in the format of `telio_get_version_tag`, e.g. `4.2.1`. NULL is returned if the
+/// version is not known yet, the peer runs a version which does not advertise it or the peer
+/// is not part of the meshnet.
+///
+pub extern "C" fn telio_get_peer_version(dev: &telio, public_key: *const c_char) -> *mut c_char {
+    let public_key = match char_ptr_to_type::<PublicKey>(public_key) {
+        Ok(public_key) => public_key,
+        Err(_) => return std::ptr::null_mut(),
+    };
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_get_peer_version: dev lock: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+
+    match dev.get_peer_version(public_key) {
+        Ok(Some(version)) => bytes_to_zero_terminated_unmanaged_bytes(version.as_bytes()),
+        Ok(None) => std::ptr::null_mut(),
+        Err(err) => {
+            telio_log_error!("telio_get_peer_version: {}", err);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+/// Gets the features supported by the meshnet peer.
+///
+/// Peers advertise them over the relay after connecting to it, all features are
+/// reported as unsupported until the advertisement is received.
+///
+/// # Parameters
+/// - `public_key`: Base64 encoded WireGuard public key of the meshnet peer.
+///
+/// # Returns
+/// JSON object
+/// `{"direct_path":true,"custom_messages":true,"psk_rotation":false,"version_advertisement":true}`.
+/// NULL is returned if the peer is not part of the meshnet or on failure.
+///
+pub extern "C" fn telio_meshnet_get_peer_capabilities(
+    dev: &telio,
+    public_key: *const c_char,
+) -> *mut c_char {
+    let public_key = match char_ptr_to_type::<PublicKey>(public_key) {
+        Ok(public_key) => public_key,
+        Err(_) => return std::ptr::null_mut(),
+    };
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_meshnet_get_peer_capabilities: dev lock: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+
+    match dev.get_peer_capabilities(public_key) {
+        Ok(capabilities) => {
+            serialize_to_unmanaged_string("telio_meshnet_get_peer_capabilities", &capabilities)
+        }
+        Err(err) => {
+            telio_log_error!("telio_meshnet_get_peer_capabilities: {}", err);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+/// Gets all known endpoints of the meshnet peer.
+///
+/// Unlike the endpoint in the node status, which is the one currently used, this lists the relay
+/// connection and every direct endpoint validated by the connectivity check.
+///
+/// # Parameters
+/// - `public_key`: Base64 encoded WireGuard public key of the meshnet peer.
+///
+/// # Returns
+/// JSON array
+/// `[{"endpoint":"1.2.3.4:51820","type":"stun","last_seen_secs":5},{"endpoint":"relay","type":"relay","last_seen_secs":1}]`,
+/// `type` is one of `local`, `stun`, `upnp` or `relay`, `last_seen_secs` is null if nothing
+/// was received through the endpoint yet. NULL is returned if the peer is not part of the
+/// meshnet or on failure.
+///
+pub extern "C" fn telio_meshnet_get_peer_endpoints(
+    dev: &telio,
+    public_key: *const c_char,
+) -> *mut c_char {
+    let public_key = match char_ptr_to_type::<PublicKey>(public_key) {
+        Ok(public_key) => public_key,
+        Err(_) => return std::ptr::null_mut(),
+    };
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_meshnet_get_peer_endpoints: dev lock: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+
+    match dev.get_peer_endpoints(public_key) {
+        Ok(endpoints) => {
+            serialize_to_unmanaged_string("telio_meshnet_get_peer_endpoints", &endpoints)
+        }
+        Err(err) => {
+            telio_log_error!("telio_meshnet_get_peer_endpoints: {}", err);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+/// Gets the graph of meshnet nodes and connections of this node to the peers.
+///
+/// Built from the node status and the relay latency measurements, so clients can render a
+/// network map. Every configured peer is a node, connected peers also have an edge from this
+/// node, telling if the connection is `direct` or `relay`.
+///
+/// # Returns
+/// JSON string
+/// `{"nodes":[{"pk":"...","ip":"100.64.0.1"}],"edges":[{"from":"...","to":"...","path":"relay","rtt_ms":5}]}`,
+/// the first node is this device. `rtt_ms` is measured only for relayed connections and is null
+/// otherwise. NULL is returned on failure.
+///
+pub extern "C" fn telio_meshnet_get_topology(dev: &telio) -> *mut c_char {
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_meshnet_get_topology: dev lock: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+
+    match dev.get_mesh_topology() {
+        Ok(topology) => serialize_to_unmanaged_string("telio_meshnet_get_topology", &topology),
+        Err(err) => {
+            telio_log_error!("telio_meshnet_get_topology: {}", err);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn telio_generate_secret_key(_dev: &telio) -> *mut c_char {
+    let secret_key = SecretKey::gen();
+    key_to_c_zero_terminated_string_unmanaged(secret_key.as_bytes()) //Managed by swig
+}
+
+#[no_mangle]
+pub extern "C" fn telio_generate_public_key(_dev: &telio, secret: *const c_char) -> *mut c_char {
+    if secret.is_null() {
+        return std::ptr::null_mut();
+    }
+    let secret_base64: String = unsafe { CStr::from_ptr(secret) }
+        .to_str()
+        .unwrap_or_default()
+        .parse()
+        .unwrap_or_default();
+    if secret_base64.is_empty() {
+        return std::ptr::null_mut();
+    }
+    let secret_dec = match base64decode(secret_base64.as_bytes()) {
+        Ok(x) => x,
+        Err(_) => return std::ptr::null_mut(),
+    };
+    let mut secret_bytes = [0_u8; 32];
+    secret_bytes.copy_from_slice(&secret_dec);
+
+    let secret_key = SecretKey::new(secret_bytes);
+    let public_key = secret_key.public();
+
+    key_to_c_zero_terminated_string_unmanaged(&public_key.0) //Managed by swig
+}
+
+#[no_mangle]
+/// Checks whether a string is a valid WireGuard public key.
+///
+/// Can be called without a device instance, e.g. to validate user input.
+///
+/// # Returns
+/// `TELIO_RES_OK` if `key_b64` is a base64 encoded 32 byte key,
+/// `TELIO_RES_INVALID_STRING` otherwise.
+pub extern "C" fn telio_validate_public_key(key_b64: *const c_char) -> telio_result {
+    validate_base64_key(key_b64)
+}
+
+#[no_mangle]
+/// Checks whether a string is a valid WireGuard secret key.
+///
+/// Can be called without a device instance, e.g. to validate user input.
+///
+/// # Returns
+/// `TELIO_RES_OK` if `key_b64` is a base64 encoded 32 byte key,
+/// `TELIO_RES_INVALID_STRING` otherwise.
+pub extern "C" fn telio_validate_secret_key(key_b64: *const c_char) -> telio_result {
+    validate_base64_key(key_b64)
+}
+
+fn validate_base64_key(key_b64: *const c_char) -> telio_result {
+    let key = ffi_try!(char_to_str(key_b64));
+    match base64decode(key) {
+        Ok(bytes) if bytes.len() == KEY_SIZE => TELIO_RES_OK,
+        _ => TELIO_RES_INVALID_STRING,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn telio_get_version_tag() -> *mut c_char {
+    bytes_to_zero_terminated_unmanaged_bytes(version_tag().as_bytes())
+}
+
+#[no_mangle]
+pub extern "C" fn telio_get_commit_sha() -> *mut c_char {
+    bytes_to_zero_terminated_unmanaged_bytes(commit_sha().as_bytes())
+}
+
+#[no_mangle]
+/// Get version of the C API implemented by the library.
+///
+/// Can be called before `telio_new`. Compare it with `TELIO_MIN_API_VERSION` of the header the
+/// wrapper was built with to detect mismatched library and wrapper versions.
+///
+pub extern "C" fn telio_get_api_version() -> u32 {
+    TELIO_MIN_API_VERSION
+}
+
+/// Schemas generated from the config types by the build script
+const FEATURES_SCHEMA: &str = include_str!(concat!(env!("OUT_DIR"), "/features_schema.json"));
+const MESHNET_SCHEMA: &str = include_str!(concat!(env!("OUT_DIR"), "/meshnet_schema.json"));
+
+#[no_mangle]
+/// Get the JSON schema of a config format accepted by the library.
+///
+/// Schemas are generated from the types used to parse the configs when the library is built, so
+/// they always match the running version. Can be called before `telio_new`.
+///
+/// # Parameters
+/// - `schema_type`: Config format to describe.
+///
+/// # Returns
+/// JSON schema (draft 7) of the config.
+///
+pub extern "C" fn telio_get_config_schema(schema_type: telio_schema_type) -> *mut c_char {
+    let schema = match schema_type {
+        telio_schema_type::TELIO_SCHEMA_FEATURES => FEATURES_SCHEMA,
+        telio_schema_type::TELIO_SCHEMA_MESHNET => MESHNET_SCHEMA,
+    };
+    bytes_to_zero_terminated_unmanaged_bytes(schema.as_bytes())
+}
+
+#[cfg(feature = "benchmarking")]
+#[no_mangle]
+/// Measure throughput of the WireGuard implementation of the adapter used by the device.
+///
+/// Blocks for at least `duration_ms` milliseconds, capped at 10 seconds. Only available when built
+/// with `benchmarking` feature, returns NULL if the adapter cannot be benchmarked. The device is not
+/// locked while the measurement runs.
+///
+/// The live adapter is not measured: packets go through a pair of in-memory boringtun tunnels,
+/// without the TUN device and the network sockets. Only the `boringtun` adapter is supported,
+/// NULL is returned for the other adapters.
+///
+/// # Returns
+/// JSON string `{"adapter":"boringtun","throughput_mbps":120.5,"latency_us":50}`.
+///
+pub extern "C" fn telio_benchmark_adapter(dev: &telio, duration_ms: u32) -> *mut c_char {
+    // Only the adapter type is needed, the device lock is released before the measurement
+    let adapter = match dev.device().map(|dev| dev.adapter_type()) {
+        Ok(Ok(adapter)) => adapter,
+        Ok(Err(err)) => {
+            error!("telio_benchmark_adapter: {}", err);
+            return std::ptr::null_mut();
+        }
+        Err(err) => {
+            error!("telio_benchmark_adapter: dev lock: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+    let duration = std::time::Duration::from_millis(duration_ms.into());
+    let benchmark = match telio_wg::benchmark::benchmark_adapter(adapter, duration) {
+        Ok(benchmark) => benchmark,
+        Err(err) => {
+            error!("telio_benchmark_adapter: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+    serialize_to_unmanaged_string("telio_benchmark_adapter", &benchmark)
+}
+
+#[cfg(feature = "diagnostics")]
+#[no_mangle]
+/// Trace the path to the meshnet peer with ICMP probes of increasing TTL.
+///
+/// Blocks for up to `max_hops * timeout_ms` milliseconds. Only available when built with
+/// `diagnostics` feature and requires privileges to open a raw socket.
+///
+/// # Parameters
+/// - `target_public_key`: Base64 encoded WireGuard public key of the meshnet peer.
+/// - `max_hops`: Maximum number of probed hops.
+/// - `timeout_ms`: Time to wait for the reply of a single hop, capped at 5000 milliseconds.
+///
+/// # Returns
+/// JSON string `{"hops":[{"hop":1,"via":"relay","rtt_ms":10}]}`, `rtt_ms` is null for hops
+/// which did not reply in time. NULL is returned if the peer is not part of the meshnet or on
+/// failure.
+///
+pub extern "C" fn telio_meshnet_traceroute(
+    dev: &telio,
+    target_public_key: *const c_char,
+    max_hops: u8,
+    timeout_ms: u32,
+) -> *mut c_char {
+    let target = match char_ptr_to_type::<PublicKey>(target_public_key) {
+        Ok(target) => target,
+        Err(_) => return std::ptr::null_mut(),
+    };
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_meshnet_traceroute: dev lock: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+    let report = match dev.meshnet_traceroute(
+        target,
+        max_hops,
+        std::time::Duration::from_millis(timeout_ms.into()),
+    ) {
+        Ok(report) => report,
+        Err(err) => {
+            telio_log_error!("telio_meshnet_traceroute: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+    serialize_to_unmanaged_string("telio_meshnet_traceroute", &report)
+}
+
+#[cfg(feature = "diagnostics")]
+#[no_mangle]
+/// Measure round trip time and packet loss to the meshnet peer with ICMP echo requests.
+///
+/// Blocks for up to `samples * max(interval_ms, 1000)` milliseconds. Only available when built
+/// with `diagnostics` feature and requires privileges to open a raw socket.
+///
+/// # Parameters
+/// - `public_key`: Base64 encoded WireGuard public key of the meshnet peer.
+/// - `samples`: Number of probes to send, between 1 and 65535.
+/// - `interval_ms`: Time between the probes.
+///
+/// # Returns
+/// JSON string `{"avg_rtt_ms":15.2,"min_rtt_ms":12.0,"max_rtt_ms":18.0,"packet_loss_pct":0.5}`,
+/// RTT fields are null if no probe was answered. NULL is returned if the peer is not part of
+/// the meshnet or on failure.
+///
+pub extern "C" fn telio_measure_path_quality(
+    dev: &telio,
+    public_key: *const c_char,
+    samples: u32,
+    interval_ms: u32,
+) -> *mut c_char {
+    let target = match char_ptr_to_type::<PublicKey>(public_key) {
+        Ok(target) => target,
+        Err(_) => return std::ptr::null_mut(),
+    };
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_measure_path_quality: dev lock: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+    let quality = match dev.measure_path_quality(
+        target,
+        samples,
+        std::time::Duration::from_millis(interval_ms.into()),
+    ) {
+        Ok(quality) => quality,
+        Err(err) => {
+            telio_log_error!("telio_measure_path_quality: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+    serialize_to_unmanaged_string("telio_measure_path_quality", &quality)
+}
+
+#[cfg(feature = "diagnostics")]
+#[no_mangle]
+/// Diagnose connectivity to the meshnet peer in several stages.
+///
+/// Stages are run one after another even if some of them fail: `dns` resolves the hostname of the
+/// peer through magic DNS, `relay` checks the relay server connection and the peer's presence on
+/// it, `stun` checks that STUN servers respond so endpoints for the direct path can be discovered
+/// and `ping` sends an ICMP echo request to the peer through the tunnel. Blocks for up to the magic
+/// DNS query timeout plus one second. Only available when built with `diagnostics` feature, the
+/// `ping` stage requires privileges to open a raw socket.
+///
+/// # Parameters
+/// - `target_public_key`: Base64 encoded WireGuard public key of the meshnet peer.
+///
+/// # Returns
+/// JSON string `{"tests":[{"test":"relay","passed":true,"latency_ms":12,"error":null}]}`, with
+/// the stages in the order they were run. `latency_ms` is null when not known. NULL is returned
+/// if the peer is not part of the meshnet or on failure.
+///
+pub extern "C" fn telio_check_connectivity(
+    dev: &telio,
+    target_public_key: *const c_char,
+) -> *mut c_char {
+    let target = match char_ptr_to_type::<PublicKey>(target_public_key) {
+        Ok(target) => target,
+        Err(_) => return std::ptr::null_mut(),
+    };
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_check_connectivity: dev lock: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+    let report = match dev.check_connectivity(target) {
+        Ok(report) => report,
+        Err(err) => {
+            telio_log_error!("telio_check_connectivity: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+    serialize_to_unmanaged_string("telio_check_connectivity", &report)
+}
+
+#[cfg(feature = "memory_diagnostics")]
+#[no_mangle]
+/// Get approximate memory used by long-lived structures of the started device.
+///
+/// Estimated from sizes of the collections kept by each subsystem. Only available when built
+/// with `memory_diagnostics` feature.
+///
+/// # Returns
+/// JSON string
+/// `{"peer_map_bytes":4096,"log_buffer_bytes":102400,"relay_state_bytes":8192,"total_bytes":114688}`
+/// or NULL on failure. `log_buffer_bytes` is the history of events reported to the app.
+///
+pub extern "C" fn telio_get_memory_stats(dev: &telio) -> *mut c_char {
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_get_memory_stats: dev lock: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+    let stats = match dev.get_memory_stats() {
+        Ok(stats) => stats,
+        Err(err) => {
+            telio_log_error!("telio_get_memory_stats: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+    serialize_to_unmanaged_string("telio_get_memory_stats", &stats)
+}
+
+#[cfg(feature = "audit_log")]
+#[no_mangle]
+/// Write peer changes, exit node connections and key rotations to the audit log.
+///
+/// Every record is chained with HMAC keyed by the audit key, so removed or modified records are
+/// detected by `telio_verify_audit_log`. The audit key is kept by the application and is
+/// independent of the device private key, so key rotations do not break the log. Only available
+/// when built with `audit_log` feature.
+///
+/// # Parameters
+/// - `path`: Path of the log file, records are appended to an existing log. NULL stops logging.
+/// - `audit_key`: Secret of the application keying the log, e.g. 32 random bytes encoded as
+///   base64. Ignored when `path` is NULL.
+///
+/// # Returns
+/// `TELIO_RES_INVALID_KEY` if the audit key is empty, `TELIO_RES_ERROR` if the file cannot be
+/// opened or the existing log does not verify with the audit key.
+///
+pub extern "C" fn telio_set_audit_log_path(
+    dev: &telio,
+    path: *const c_char,
+    audit_key: *const c_char,
+) -> telio_result {
+    telio_log_info!(
+        "telio_set_audit_log_path entry with instance id: {}",
+        dev.id
+    );
+    ffi_catch_panic!({
+        let (path, audit_key) = if path.is_null() {
+            (None, Vec::new())
+        } else {
+            let audit_key = ffi_try!(char_to_str(audit_key));
+            if audit_key.is_empty() {
+                return TELIO_RES_INVALID_KEY;
+            }
+            (
+                Some(std::path::PathBuf::from(ffi_try!(char_to_str(path)))),
+                audit_key.as_bytes().to_vec(),
+            )
+        };
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+
+        dev.set_audit_log_path(path, audit_key)
+            .telio_log_result("telio_set_audit_log_path")
+    })
+}
+
+#[cfg(feature = "audit_log")]
+#[no_mangle]
+/// Verify the chain of the audit log written with `audit_key`.
+///
+/// # Parameters
+/// - `path`: Path of the log file.
+/// - `audit_key`: Audit key given to `telio_set_audit_log_path`.
+///
+/// # Returns
+/// `TELIO_RES_OK` if the log is intact, `TELIO_RES_ERROR` if it was tampered with or cannot be
+/// read.
+///
+pub extern "C" fn telio_verify_audit_log(
+    path: *const c_char,
+    audit_key: *const c_char,
+) -> telio_result {
+    ffi_catch_panic!({
+        let path = ffi_try!(char_to_str(path));
+        let audit_key = ffi_try!(char_to_str(audit_key));
+        if audit_key.is_empty() {
+            return TELIO_RES_INVALID_KEY;
+        }
+
+        crate::device::verify_audit_log(std::path::Path::new(path), audit_key.as_bytes())
+            .map_err(crate::device::Error::AuditLog)
+            .telio_log_result("telio_verify_audit_log")
+    })
+}
+
+#[cfg(feature = "traffic_logging")]
+#[no_mangle]
+/// Start logging metadata of a random sample of packets exchanged with peers.
+///
+/// Logged are direction, size, public key of the peer and the path (relay or direct) of packets
+/// accepted by the firewall. Calling it again replaces the config. Only available when built with
+/// `traffic_logging` feature.
+///
+/// # Parameters
+/// - `config_json`: JSON object, e.g.
+///   `{"sample_rate":0.01,"max_entries":1000,"include_payload_bytes":false}`.
+///   `sample_rate` is the probability of logging a packet, `max_entries` latest entries are kept.
+///   First 64 bytes of the packets are logged only if `include_payload_bytes` is true. Missing
+///   fields take the values from the example.
+///
+pub extern "C" fn telio_enable_traffic_logging(
+    dev: &telio,
+    config_json: *const c_char,
+) -> telio_result {
+    let config_str = ffi_try!(char_to_str(config_json));
+    let config: crate::device::TrafficLogConfig = ffi_try!(serde_json::from_str(config_str));
+    telio_log_info!(
+        "telio_enable_traffic_logging entry with instance id: {}. Config: {:?}",
+        dev.id,
+        config
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+
+        dev.enable_traffic_logging(config)
+            .telio_log_result("telio_enable_traffic_logging")
+    })
+}
+
+#[cfg(feature = "traffic_logging")]
+#[no_mangle]
+/// Get packets logged since `telio_enable_traffic_logging`.
+///
+/// Only available when built with `traffic_logging` feature.
+///
+/// # Returns
+/// JSON array of entries ordered from the oldest, e.g.
+/// `[{"ts_ms":1700000000000,"direction":"inbound","size":84,"public_key":"...","path":"relay"}]`.
+/// Entries carry base64 encoded `payload` only if `include_payload_bytes` was set. NULL is returned
+/// on failure.
+///
+pub extern "C" fn telio_get_traffic_log(dev: &telio) -> *mut c_char {
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_get_traffic_log: dev lock: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+
+    match dev.get_traffic_log() {
+        Ok(entries) => serialize_to_unmanaged_string("telio_get_traffic_log", &entries),
+        Err(err) => {
+            telio_log_error!("telio_get_traffic_log: {}", err);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[cfg(feature = "packet_trace")]
+#[no_mangle]
+/// Start capturing the first bytes of packets exchanged with meshnet peers over the relay.
+///
+/// Packets are captured as exchanged with the peers, before encryption of outgoing and after
+/// decryption of incoming control messages. Calling it again replaces the limits, which are kept
+/// when meshnet is restarted. Only available when built with `packet_trace` feature.
+///
+/// # Parameters
+/// - `max_bytes_per_packet`: Number of bytes captured from the start of each packet.
+/// - `max_packets`: Number of the latest packets kept, 0 disables the capture.
+///
+pub extern "C" fn telio_enable_packet_trace(
+    dev: &telio,
+    max_bytes_per_packet: u32,
+    max_packets: u32,
+) -> telio_result {
+    telio_log_info!(
+        "telio_enable_packet_trace entry with instance id: {}. Max bytes per packet: {}, max packets: {}",
+        dev.id,
+        max_bytes_per_packet,
+        max_packets
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+
+        dev.enable_packet_trace(max_bytes_per_packet as usize, max_packets as usize)
+            .telio_log_result("telio_enable_packet_trace")
+    })
+}
+
+#[cfg(feature = "packet_trace")]
+#[no_mangle]
+/// Get packets captured since the previous call, the capture buffer is cleared.
+///
+/// Only available when built with `packet_trace` feature.
+///
+/// # Returns
+/// JSON array of packets ordered from the oldest, e.g.
+/// `[{"direction":"rx","peer_pk":"...","timestamp_ms":1700000000000,"hex":"aabbcc"}]`.
+/// NULL is returned on failure.
+///
+pub extern "C" fn telio_get_packet_trace(dev: &telio) -> *mut c_char {
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_get_packet_trace: dev lock: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+
+    match dev.get_packet_trace() {
+        Ok(entries) => serialize_to_unmanaged_string("telio_get_packet_trace", &entries),
+        Err(err) => {
+            telio_log_error!("telio_get_packet_trace: {}", err);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[cfg(feature = "packet_stats")]
+#[no_mangle]
+/// Registers a callback called for every packet exchanged with peers.
+///
+/// Only packets accepted by the firewall are reported. The callback is called from the packet
+/// processing threads without locks or allocations, so it must be thread safe and return
+/// quickly. Replaces previously registered callback, which may still be called by packets
+/// already in flight. Only available when built with `packet_stats` feature.
+///
+/// # Parameters
+/// - `cb`: Callback receiving direction (0 inbound, 1 outbound), pointer to the 32 byte public
+///         key of the peer, valid only during the call, and size of the IP packet.
+///
+pub extern "C" fn telio_enable_traffic_stats_hook(
+    dev: &telio,
+    cb: telio_packet_stats_cb,
+) -> telio_result {
+    telio_log_info!(
+        "telio_enable_traffic_stats_hook entry with instance id: {}.",
+        dev.id
+    );
+    ffi_catch_panic!({
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
+
+        let handler =
+            move |direction: crate::device::PacketDirection, peer: &[u8; 32], len: u32| unsafe {
+                (cb.cb)(cb.ctx, direction as u8, peer.as_ptr(), len)
+            };
+        dev.set_packet_stats_handler(Some(Box::new(handler)))
+            .telio_log_result("telio_enable_traffic_stats_hook")
+    })
+}
+
+#[no_mangle]
+/// Get the number of meshnet peers which are currently connected.
+///
+/// Returns -1 on error.
+pub extern "C" fn telio_get_connected_peers_count(dev: &telio) -> i64 {
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            error!("telio_get_connected_peers_count: dev lock: {}", err);
+            return -1;
+        }
+    };
+    match dev.get_connected_peers_count() {
+        Ok(count) => count as i64,
+        Err(err) => {
+            error!("telio_get_connected_peers_count: {}", err);
+            -1
+        }
+    }
+}
+
+#[no_mangle]
+/// Get the estimated difference of the local clock from the clock of the relay server.
+///
+/// The skew is estimated from the time reported by the server when connecting to it.
+/// `ClockSkewWarning` event is reported when it exceeds 90 seconds, as WireGuard handshakes
+/// fail when the clocks differ by more than 3 minutes.
+///
+/// # Returns
+/// Skew in milliseconds, positive if the local clock is ahead. Returns 0 if not connected to
+/// a relay server or on error.
+pub extern "C" fn telio_get_clock_skew(dev: &telio) -> i64 {
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            error!("telio_get_clock_skew: dev lock: {}", err);
+            return 0;
+        }
+    };
+    match dev.get_clock_skew() {
+        Ok(skew_ms) => skew_ms.unwrap_or(0),
+        Err(err) => {
+            error!("telio_get_clock_skew: {}", err);
+            0
+        }
+    }
+}
+
+#[no_mangle]
+/// Get the number of events waiting to be delivered to the event callback.
+///
+/// Growing depth means the callback does not keep up with the events, e.g. it is blocked.
+/// Returns 0 on error.
+pub extern "C" fn telio_get_event_queue_depth(dev: &telio) -> u32 {
+    match dev.device() {
+        Ok(dev) => dev.get_event_queue_depth() as u32,
+        Err(err) => {
+            error!("telio_get_event_queue_depth: dev lock: {}", err);
+            0
+        }
+    }
+}
+
+#[no_mangle]
+/// Get the number of threads spawned for this instance.
+///
+/// Counts threads of the async runtime and the thread monitoring them, threads spawned
+/// internally by the WireGuard adapter are not included. Returns -1 on error.
+pub extern "C" fn telio_get_thread_count(dev: &telio) -> i32 {
+    match dev.device() {
+        Ok(dev) => i32::try_from(dev.get_thread_count()).unwrap_or(-1),
+        Err(err) => {
+            error!("telio_get_thread_count: dev lock: {}", err);
+            -1
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn telio_get_status_map(dev: &telio) -> *mut c_char {
+    trace!("acquiring dev lock");
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            error!("telio_get_status_map: dev lock: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+    trace!("retrieving external nodes");
+    let nodes = match dev.external_nodes() {
+        Ok(nodes) => nodes,
+        Err(err) => {
+            error!("telio_get_status_map: external_nodes: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+    trace!("serializing");
+    let json = match serde_json::to_string(&nodes) {
+        Ok(json) => json,
+        Err(err) => {
+            error!("telio_get_status_map: to_string: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+    trace!("converting to char pointer");
+    bytes_to_zero_terminated_unmanaged_bytes(json.as_bytes())
+}
+
+#[no_mangle]
+/// Get a liveness report of the running device.
+///
+/// Each component is probed with a bounded timeout, components that do not respond in time are
+/// reported as `degraded`, components that are not enabled are reported as `disabled`.
+///
+/// # Returns
+/// JSON encoded report, e.g.
+/// `{"healthy":true,"components":{"wireguard":"ok","relay":"ok","dns":"disabled","direct_path":"ok"}}`
+/// or NULL if the runtime itself is unresponsive, the reason is available via `telio_get_last_error`.
+pub extern "C" fn telio_health_check(dev: &telio) -> *mut c_char {
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_health_check: dev lock: {}", err);
+            error_handling::update_last_error(TELIO_RES_LOCK_ERROR);
+            return std::ptr::null_mut();
+        }
+    };
+
+    match dev.health_check() {
+        Ok(report) => serialize_to_unmanaged_string("telio_health_check", &report),
+        Err(err) => {
+            telio_log_error!("telio_health_check: {}", err);
+            error_handling::update_last_error(err);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+/// Get STUN servers used for direct path discovery.
+///
+/// # Returns
+/// JSON array of servers, e.g.
+/// `[{"hostname":"derp-01","ip":"1.2.3.4","port":3478,"last_response_ms":12}]`.
+/// `last_response_ms` is null for servers which failed to respond or were not queried yet.
+/// `ip` is the IPv6 address of the server when IPv6 is enabled in the config and the server
+/// has one. Array is empty when direct connections are not enabled. NULL is returned on failure,
+/// the reason is available via `telio_get_last_error`.
+pub extern "C" fn telio_get_stun_servers(dev: &telio) -> *mut c_char {
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_get_stun_servers: dev lock: {}", err);
+            error_handling::update_last_error(TELIO_RES_LOCK_ERROR);
+            return std::ptr::null_mut();
+        }
+    };
+
+    match dev.get_stun_servers() {
+        Ok(servers) => serialize_to_unmanaged_string("telio_get_stun_servers", &servers),
+        Err(err) => {
+            telio_log_error!("telio_get_stun_servers: {}", err);
+            error_handling::update_last_error(err);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+#[no_mangle]
+/// Get network interfaces seen by direct path discovery.
+///
+/// # Returns
+/// JSON array of IPv4 interfaces other than loopback, e.g.
+/// `[{"name":"en0","ip":"192.168.1.5","role":"primary"},{"name":"utun0","ip":"100.64.0.1","role":"tunnel"}]`.
+/// Interfaces with `primary` role are probed for endpoint candidates, interfaces with `tunnel` role
+/// have an address from the meshnet range and are skipped. Array is empty when local interfaces
+/// endpoint provider is not enabled. NULL is returned on failure.
+pub extern "C" fn telio_list_interfaces(dev: &telio) -> *mut c_char {
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_list_interfaces: dev lock: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+
+    match dev.list_interfaces() {
+        Ok(interfaces) => serialize_to_unmanaged_string("telio_list_interfaces", &interfaces),
+        Err(err) => {
+            telio_log_error!("telio_list_interfaces: {}", err);
+            std::ptr::null_mut()
+        }
+    }
 }
 
 #[no_mangle]
-/// Disables the meshnet functionality by closing all the connections.
-pub extern "C" fn telio_set_meshnet_off(dev: &telio) -> telio_result {
-    telio_log_info!("telio_set_meshnet_off entry with instance id: {}.", dev.id);
-    ffi_catch_panic!({
-        let dev = ffi_try!(dev.inner.lock().map_err(|_| TELIO_RES_LOCK_ERROR));
+/// Get the latest round trip time samples over the relay.
+///
+/// Samples of a peer are taken from WireGuard handshakes going through the relay. Samples of the
+/// relay server itself are taken from DERP poll requests, which are sent only when derp polling
+/// feature is enabled. At most 64 latest samples are kept for the server and for each peer.
+///
+/// # Parameters
+/// - `peer_pk`: Base64 encoded public key of the meshnet peer, NULL for the relay server.
+/// - `n_samples`: Maximum number of samples to return.
+///
+/// # Returns
+/// JSON array of samples ordered from the oldest, e.g.
+/// `[{"ts_secs":1700000010,"rtt_ms":15},{"ts_secs":1700000020,"rtt_ms":17}]`.
+/// Array is empty when meshnet is not configured. NULL is returned on failure.
+pub extern "C" fn telio_get_relay_latency_history(
+    dev: &telio,
+    peer_pk: *const c_char,
+    n_samples: u32,
+) -> *mut c_char {
+    let peer = if !peer_pk.is_null() {
+        match char_ptr_to_type::<PublicKey>(peer_pk) {
+            Ok(public_key) => Some(public_key),
+            Err(_) => return std::ptr::null_mut(),
+        }
+    } else {
+        None
+    };
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_get_relay_latency_history: dev lock: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
 
-        dev.set_config(&None)
-            .telio_log_result("telio_set_meshnet_off")
-    })
+    match dev.get_relay_latency_history(peer, n_samples as usize) {
+        Ok(samples) => serialize_to_unmanaged_string("telio_get_relay_latency_history", &samples),
+        Err(err) => {
+            telio_log_error!("telio_get_relay_latency_history: {}", err);
+            std::ptr::null_mut()
+        }
+    }
 }
 
 #[no_mangle]
-pub extern "C" fn telio_generate_secret_key(_dev: &telio) -> *mut c_char {
-    let secret_key = SecretKey::gen();
-    key_to_c_zero_terminated_string_unmanaged(secret_key.as_bytes()) //Managed by swig
+/// Get all configured relay servers and their health.
+///
+/// Servers are listed in the order they are tried, including servers which were not tried yet.
+/// `latency_ms` is the time the latest successful connection to the server took to establish,
+/// `last_checked_secs` is the number of seconds since the latest connection attempt.
+///
+/// # Returns
+/// JSON array of servers, e.g.
+/// `[{"hostname":"derp1.example.com","port":8765,"latency_ms":15,"connected":true,"last_checked_secs":5}]`.
+/// `latency_ms` and `last_checked_secs` are null for servers which were not tried yet. Array is
+/// empty when meshnet is not configured. NULL is returned on failure.
+pub extern "C" fn telio_get_relay_server_list(dev: &telio) -> *mut c_char {
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_get_relay_server_list: dev lock: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+
+    match dev.get_relay_server_list() {
+        Ok(servers) => serialize_to_unmanaged_string("telio_get_relay_server_list", &servers),
+        Err(err) => {
+            telio_log_error!("telio_get_relay_server_list: {}", err);
+            std::ptr::null_mut()
+        }
+    }
 }
 
 #[no_mangle]
-pub extern "C" fn telio_generate_public_key(_dev: &telio, secret: *const c_char) -> *mut c_char {
-    if secret.is_null() {
-        return std::ptr::null_mut();
-    }
-    let secret_base64: String = unsafe { CStr::from_ptr(secret) }
-        .to_str()
-        .unwrap_or_default()
-        .parse()
-        .unwrap_or_default();
-    if secret_base64.is_empty() {
-        return std::ptr::null_mut();
-    }
-    let secret_dec = match base64decode(secret_base64.as_bytes()) {
-        Ok(x) => x,
+/// Get identifier of the ongoing relay session with a meshnet peer.
+///
+/// Session starts with the first packet relayed to or from the peer and ends when the connection
+/// to the relay server is closed, so the identifier changes every time the session is
+/// re-established. It is logged when the session starts and ends, to group log lines by session.
+///
+/// # Parameters
+/// - `peer_pk`: Base64 encoded public key of the meshnet peer.
+///
+/// # Returns
+/// UUID string, e.g. `67e55044-10b1-426f-9247-bb680e5fe0c8`. NULL is returned if there is no
+/// relay session with the peer or on failure.
+pub extern "C" fn telio_get_relay_session_id(dev: &telio, peer_pk: *const c_char) -> *mut c_char {
+    let peer = match char_ptr_to_type::<PublicKey>(peer_pk) {
+        Ok(public_key) => public_key,
         Err(_) => return std::ptr::null_mut(),
     };
-    let mut secret_bytes = [0_u8; 32];
-    secret_bytes.copy_from_slice(&secret_dec);
-
-    let secret_key = SecretKey::new(secret_bytes);
-    let public_key = secret_key.public();
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_get_relay_session_id: dev lock: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
 
-    key_to_c_zero_terminated_string_unmanaged(&public_key.0) //Managed by swig
+    match dev.get_relay_session_id(peer) {
+        Ok(Some(id)) => bytes_to_zero_terminated_unmanaged_bytes(id.to_string().as_bytes()),
+        Ok(None) => std::ptr::null_mut(),
+        Err(err) => {
+            telio_log_error!("telio_get_relay_session_id: {}", err);
+            std::ptr::null_mut()
+        }
+    }
 }
 
 #[no_mangle]
-pub extern "C" fn telio_get_version_tag() -> *mut c_char {
-    bytes_to_zero_terminated_unmanaged_bytes(version_tag().as_bytes())
-}
+/// Get connection events reported after the given time.
+///
+/// Allows replaying events missed while the app was not active. Peer, relay and key rotation
+/// events are kept, only the latest `event_history_size` (256 by default) of them are available.
+///
+/// # Parameters
+/// - `since_unix_secs`: Unix timestamp in seconds, only newer events are returned.
+///
+/// # Returns
+/// JSON array of events ordered from the oldest, in the same form as passed to the event
+/// callback with additional `ts_secs` field, e.g.
+/// `[{"ts_secs":1700000010,"type":"node","body":{...}}]`. NULL is returned on failure.
+pub extern "C" fn telio_get_connection_events_since(
+    dev: &telio,
+    since_unix_secs: u64,
+) -> *mut c_char {
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_get_connection_events_since: dev lock: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
 
-#[no_mangle]
-pub extern "C" fn telio_get_commit_sha() -> *mut c_char {
-    bytes_to_zero_terminated_unmanaged_bytes(commit_sha().as_bytes())
+    serialize_to_unmanaged_string(
+        "telio_get_connection_events_since",
+        &dev.get_connection_events_since(since_unix_secs),
+    )
 }
 
 #[no_mangle]
-pub extern "C" fn telio_get_status_map(dev: &telio) -> *mut c_char {
-    trace!("acquiring dev lock");
-    let dev = match dev.inner.lock() {
+/// Get connections currently tracked by the firewall.
+///
+/// # Returns
+/// JSON array of at most 256 most recently active connections, e.g.
+/// `[{"src":"192.168.100.1:1234","dst":"1.1.1.1:443","peer_pk":"...","bytes_tx":100,"bytes_rx":200}]`.
+/// `src` is the side which initiated the connection. NULL is returned on failure.
+pub extern "C" fn telio_list_active_connections(dev: &telio) -> *mut c_char {
+    let dev = match dev.device() {
         Ok(dev) => dev,
         Err(err) => {
-            error!("telio_get_status_map: dev lock: {}", err);
+            telio_log_error!("telio_list_active_connections: dev lock: {}", err);
             return std::ptr::null_mut();
         }
     };
-    trace!("retrieving external nodes");
-    let nodes = match dev.external_nodes() {
-        Ok(nodes) => nodes,
-        Err(err) => {
-            error!("telio_get_status_map: external_nodes: {}", err);
-            return std::ptr::null_mut();
+
+    match dev.get_active_connections(MAX_ACTIVE_CONNECTIONS) {
+        Ok(connections) => {
+            serialize_to_unmanaged_string("telio_list_active_connections", &connections)
         }
-    };
-    trace!("serializing");
-    let json = match serde_json::to_string(&nodes) {
-        Ok(json) => json,
         Err(err) => {
-            error!("telio_get_status_map: to_string: {}", err);
-            return std::ptr::null_mut();
+            telio_log_error!("telio_list_active_connections: {}", err);
+            std::ptr::null_mut()
         }
-    };
-    trace!("converting to char pointer");
-    bytes_to_zero_terminated_unmanaged_bytes(json.as_bytes())
+    }
 }
 
 #[no_mangle]
@@ -914,12 +4634,36 @@ pub extern "C" fn telio_get_last_error(_dev: &telio) -> *mut c_char {
     std::ptr::null_mut()
 }
 
+#[no_mangle]
+/// Get the last error of the WireGuard adapter.
+///
+/// Unlike `telio_get_last_error`, only failures of the adapter itself are reported, e.g. failed
+/// creation of the tunnel interface or a failed UAPI request. The error is kept until another
+/// adapter operation fails.
+///
+/// # Returns
+/// Error message, or NULL if no adapter operation has failed yet.
+pub extern "C" fn telio_get_adapter_error(dev: &telio) -> *mut c_char {
+    let dev = match dev.device() {
+        Ok(dev) => dev,
+        Err(err) => {
+            telio_log_error!("telio_get_adapter_error: dev lock: {}", err);
+            return std::ptr::null_mut();
+        }
+    };
+
+    match dev.get_adapter_error() {
+        Some(err) => bytes_to_zero_terminated_unmanaged_bytes(err.as_bytes()),
+        None => std::ptr::null_mut(),
+    }
+}
+
 #[allow(clippy::panic)]
 #[no_mangle]
 /// For testing only.
 pub extern "C" fn __telio_generate_stack_panic(dev: &telio) -> telio_result {
     ffi_catch_panic!({
-        let dev = ffi_try!(dev.inner.lock().map_err(|_| TELIO_RES_LOCK_ERROR));
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
 
         if dev.is_running() {
             panic!("runtime_panic_test_call_stack");
@@ -934,7 +4678,7 @@ pub extern "C" fn __telio_generate_stack_panic(dev: &telio) -> telio_result {
 /// For testing only.
 pub extern "C" fn __telio_generate_thread_panic(dev: &telio) -> telio_result {
     ffi_catch_panic!({
-        let dev = ffi_try!(dev.inner.lock().map_err(|_| TELIO_RES_LOCK_ERROR));
+        let dev = ffi_try!(dev.device().map_err(|_| TELIO_RES_LOCK_ERROR));
 
         if dev.is_running() {
             let res = dev._panic();
@@ -949,32 +4693,11 @@ pub extern "C" fn __telio_generate_thread_panic(dev: &telio) -> telio_result {
     })
 }
 
-fn filter_log_message(msg: String) -> Option<String> {
-    let mut log_status = match LAST_LOG_STATUS.lock() {
-        Ok(status) => status,
-        Err(_) => {
-            return None;
-        }
-    };
-
-    if !log_status.string.eq(&msg) {
-        log_status.string = msg.clone();
-        log_status.counter = 0;
-        return Some(msg);
-    }
-
-    if log_status.counter > 0 && log_status.counter % 100 == 0 {
-        log_status.counter += 1;
-        return Some(format!("[repeated 100 times!] {}", msg));
-    }
-
-    if log_status.counter < 10 {
-        log_status.counter += 1;
-        return Some(msg);
+fn filter_log_message(line: LogLine) -> Vec<LogLine> {
+    match LAST_LOG_STATUS.lock() {
+        Ok(mut status) => status.filter(line),
+        Err(_) => Vec::new(),
     }
-
-    log_status.counter += 1;
-    None
 }
 
 /// Visitor for `tracing` events that converts one field with name equal to `field_name`
@@ -1000,16 +4723,34 @@ impl<'a> tracing::field::Visit for TraceFieldVisitor<'a> {
     }
 }
 
+/// Logging callback given by the application
+#[derive(Clone, Copy, Debug)]
+enum LoggerCallback {
+    V1(telio_logger_cb),
+    V2(telio_logger_v2_cb),
+}
+
 pub struct TelioTracingSubscriber {
-    callback: telio_logger_cb,
+    callback: LoggerCallback,
     max_level: tracing::Level,
+    spans: SpanRegistry,
 }
 
 impl TelioTracingSubscriber {
     pub fn new(callback: telio_logger_cb, max_level: tracing::Level) -> Self {
+        Self::with_callback(LoggerCallback::V1(callback), max_level)
+    }
+
+    /// Subscriber passing fields of the events and their spans to the callback
+    pub fn new_v2(callback: telio_logger_v2_cb, max_level: tracing::Level) -> Self {
+        Self::with_callback(LoggerCallback::V2(callback), max_level)
+    }
+
+    fn with_callback(callback: LoggerCallback, max_level: tracing::Level) -> Self {
         TelioTracingSubscriber {
             callback,
             max_level,
+            spans: SpanRegistry::default(),
         }
     }
 }
@@ -1020,13 +4761,12 @@ impl Subscriber for TelioTracingSubscriber {
             && metadata.level() <= &self.max_level
     }
 
-    fn new_span(&self, _span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
-        // TODO using a placeholder for now
-        tracing::span::Id::from_u64(1337)
+    fn new_span(&self, span: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+        self.spans.new_span(span)
     }
 
-    fn record(&self, _span: &tracing::span::Id, _values: &tracing::span::Record<'_>) {
-        // TODO
+    fn record(&self, span: &tracing::span::Id, values: &tracing::span::Record<'_>) {
+        self.spans.record(span, values)
     }
 
     fn record_follows_from(&self, _span: &tracing::span::Id, _follows: &tracing::span::Id) {
@@ -1038,7 +4778,6 @@ impl Subscriber for TelioTracingSubscriber {
             return;
         }
 
-        let level = *event.metadata().level();
         let mut visitor = TraceFieldVisitor {
             // hardcoded name of the field where tracing stores the messages passed to tracing::info! etc
             field_name: "message",
@@ -1047,19 +4786,52 @@ impl Subscriber for TelioTracingSubscriber {
         };
         event.record(&mut visitor);
 
-        if let Some(filtered_msg) = filter_log_message(visitor.message) {
-            if let Ok(cstr) = CString::new(filtered_msg) {
-                unsafe { (self.callback.cb)(self.callback.ctx, level.into(), cstr.as_ptr()) };
+        let line = LogLine {
+            message: visitor.message,
+            level: *event.metadata().level(),
+            fields: match self.callback {
+                LoggerCallback::V1(_) => None,
+                LoggerCallback::V2(_) => Some(self.spans.fields_json(event)),
+            },
+        };
+        for filtered in filter_log_message(line) {
+            if let Ok(cstr) = CString::new(filtered.message) {
+                match self.callback {
+                    LoggerCallback::V1(cb) => unsafe {
+                        (cb.cb)(cb.ctx, filtered.level.into(), cstr.as_ptr())
+                    },
+                    LoggerCallback::V2(cb) => {
+                        let fields = filtered.fields.unwrap_or_else(|| String::from("{}"));
+                        if let Ok(fields) = CString::new(fields) {
+                            unsafe {
+                                (cb.cb)(
+                                    cb.ctx,
+                                    filtered.level.into(),
+                                    cstr.as_ptr(),
+                                    fields.as_ptr(),
+                                )
+                            };
+                        }
+                    }
+                }
             }
         }
     }
 
-    fn enter(&self, _span: &tracing::span::Id) {
-        // TODO
+    fn enter(&self, span: &tracing::span::Id) {
+        self.spans.enter(span)
     }
 
-    fn exit(&self, _span: &tracing::span::Id) {
-        // TODO
+    fn exit(&self, span: &tracing::span::Id) {
+        self.spans.exit(span)
+    }
+
+    fn clone_span(&self, span: &tracing::span::Id) -> tracing::span::Id {
+        self.spans.clone_span(span)
+    }
+
+    fn try_close(&self, span: tracing::span::Id) -> bool {
+        self.spans.try_close(span)
     }
 }
 
@@ -1083,6 +4855,56 @@ fn key_to_c_zero_terminated_string_unmanaged(key: &[u8; KEY_SIZE]) -> *mut c_cha
     bytes_to_zero_terminated_unmanaged_bytes(base64encode(key).as_bytes())
 }
 
+fn serialize_to_unmanaged_string<T: serde::Serialize>(caller: &str, value: &T) -> *mut c_char {
+    match serde_json::to_string(value) {
+        Ok(json) => bytes_to_zero_terminated_unmanaged_bytes(json.as_bytes()),
+        Err(err) => {
+            telio_log_error!("{}: to_string: {}", caller, err);
+            std::ptr::null_mut()
+        }
+    }
+}
+
+fn payload_crypto_args<'a>(
+    public_key: *const c_char,
+    data: *const u8,
+    len: usize,
+    out: *mut *mut u8,
+    out_len: *mut usize,
+) -> Result<(PublicKey, &'a [u8]), telio_result> {
+    if public_key.is_null() || out.is_null() || out_len.is_null() {
+        telio_log_debug!("Public key or output is NULL");
+        return Err(TELIO_RES_INVALID_STRING);
+    }
+    let public_key = char_ptr_to_type::<PublicKey>(public_key)?;
+    Ok((public_key, bytes_arg(data, len)?))
+}
+
+fn bytes_arg<'a>(data: *const u8, len: usize) -> Result<&'a [u8], telio_result> {
+    match (data.is_null(), len) {
+        (_, 0) => Ok(&[][..]),
+        (true, _) => {
+            telio_log_debug!("Payload is NULL");
+            Err(TELIO_RES_ERROR)
+        }
+        (false, len) => Ok(unsafe { std::slice::from_raw_parts(data, len) }),
+    }
+}
+
+/// # Safety
+///
+/// `out` and `out_len` must be valid for writes.
+unsafe fn write_unmanaged_bytes(bytes: &[u8], out: *mut *mut u8, out_len: *mut usize) {
+    // Allocate at least one byte, so that the caller never receives NULL on success
+    let buf = libc::malloc(bytes.len().max(1)) as *mut u8;
+    if buf.is_null() {
+        abort();
+    }
+    std::slice::from_raw_parts_mut(buf, bytes.len()).copy_from_slice(bytes);
+    *out = buf;
+    *out_len = bytes.len();
+}
+
 fn bytes_to_zero_terminated_unmanaged_bytes(bytes: &[u8]) -> *mut c_char {
     let buf = unsafe {
         let buf = libc::malloc(bytes.len() + 1) as *mut u8;
@@ -1109,6 +4931,13 @@ mod tests {
 
     unsafe extern "C" fn test_telio_event_fn(_: *mut c_void, _: *const c_char) {}
 
+    unsafe extern "C" fn test_telio_recording_event_fn(ctx: *mut c_void, event: *const c_char) {
+        let events = &*(ctx as *const Mutex<Vec<String>>);
+        if let Ok(mut events) = events.lock() {
+            events.push(CStr::from_ptr(event).to_string_lossy().into_owned());
+        }
+    }
+
     unsafe extern "C" fn test_telio_logger_fn(
         _: *mut c_void,
         _: telio_log_level,
@@ -1176,8 +5005,17 @@ mod tests {
         let features = Features::default();
         let event_cb = Box::new(|_event| {});
         let telio_dev = telio {
-            inner: Mutex::new(Device::new(features, event_cb, None)?),
+            inner: Mutex::new(Device::new(features, event_cb, None, None)?),
             id: rand::thread_rng().gen::<usize>(),
+            events: Arc::new(Mutex::new(vec![(
+                DEFAULT_EVENT_LISTENER_ID,
+                telio_event_cb {
+                    ctx: ptr::null_mut(),
+                    cb: test_telio_event_fn,
+                },
+            )])),
+            event_batcher: Default::default(),
+            starting: Default::default(),
         };
 
         let cfg = "a".repeat(MAX_CONFIG_LENGTH);
@@ -1249,6 +5087,178 @@ mod tests {
         let _ = panic::take_hook();
     }
 
+    /// Instance passing the events of the device to a listener recording them in `events`
+    fn recording_telio(events: &Mutex<Vec<String>>) -> anyhow::Result<telio> {
+        let listeners: EventListeners = Arc::new(Mutex::new(vec![(
+            DEFAULT_EVENT_LISTENER_ID,
+            telio_event_cb {
+                ctx: events as *const _ as *mut c_void,
+                cb: test_telio_recording_event_fn,
+            },
+        )]));
+        let event_batcher = Arc::new(EventBatcher::default());
+        let event_dispatcher = {
+            let listeners = listeners.clone();
+            let event_batcher = event_batcher.clone();
+            move |e: Box<Event>| dispatch_event(&listeners, &event_batcher, e)
+        };
+        Ok(telio {
+            inner: Mutex::new(Device::new(
+                Features::default(),
+                event_dispatcher,
+                None,
+                None,
+            )?),
+            id: rand::thread_rng().gen::<usize>(),
+            events: listeners,
+            event_batcher,
+            starting: Default::default(),
+        })
+    }
+
+    #[test]
+    fn test_telio_destroy_reports_shutting_down() -> anyhow::Result<()> {
+        let events = Mutex::new(Vec::<String>::new());
+        let telio_dev = Box::into_raw(Box::new(recording_telio(&events)?));
+
+        // Queued event is delivered first
+        unsafe { &*telio_dev }
+            .inner
+            .lock()
+            .unwrap()
+            .publish_event(Box::new(Event::new::<StartupComplete>().set(
+                StartupComplete {
+                    success: true,
+                    error: None,
+                },
+            )));
+        telio_destroy(telio_dev);
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 2);
+        assert!(events[0].starts_with(r#"{"type":"startupcomplete""#));
+        assert_eq!(events[1], r#"{"type":"shuttingdown","body":{}}"#);
+        Ok(())
+    }
+
+    #[test]
+    fn test_telio_start_async_reports_startup_complete() -> anyhow::Result<()> {
+        let events = Mutex::new(Vec::<String>::new());
+        let telio_dev = Box::into_raw(Box::new(recording_telio(&events)?));
+        let private_key = CString::new(SecretKey::gen().to_string())?;
+
+        assert_eq!(
+            telio_start_async(
+                unsafe { &*telio_dev },
+                private_key.as_ptr(),
+                telio_adapter_type::TELIO_ADAPTER_BORING_TUN,
+            ),
+            TELIO_RES_OK
+        );
+        // Unit tests start the runtime with the mocked adapter of `wg::tests::setup`, no TUN
+        // is created. Destroying waits for the startup, which is reported whether it succeeded.
+        telio_destroy(telio_dev);
+
+        let events = events.lock().unwrap();
+        assert!(events.first().map_or(false, |event| event
+            .starts_with(r#"{"type":"startupcomplete""#)));
+        assert_eq!(
+            events.last(),
+            Some(&String::from(r#"{"type":"shuttingdown","body":{}}"#))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_event_listeners() -> anyhow::Result<()> {
+        let default_events = Mutex::new(Vec::<String>::new());
+        let added_events = Mutex::new(Vec::<String>::new());
+        let recording_cb = |events: &Mutex<Vec<String>>| telio_event_cb {
+            ctx: events as *const _ as *mut c_void,
+            cb: test_telio_recording_event_fn,
+        };
+        let telio_dev = Box::into_raw(Box::new(telio {
+            inner: Mutex::new(Device::new(
+                Features::default(),
+                |_event: Box<Event>| {},
+                None,
+                None,
+            )?),
+            id: rand::thread_rng().gen::<usize>(),
+            events: Arc::new(Mutex::new(vec![(
+                DEFAULT_EVENT_LISTENER_ID,
+                recording_cb(&default_events),
+            )])),
+            event_batcher: Default::default(),
+            starting: Default::default(),
+        }));
+        let dev = unsafe { &*telio_dev };
+
+        assert_eq!(
+            telio_add_event_listener(dev, 1, recording_cb(&added_events)),
+            TELIO_RES_OK
+        );
+        assert_eq!(
+            telio_add_event_listener(dev, 1, recording_cb(&added_events)),
+            TELIO_RES_ERROR
+        );
+        assert_eq!(
+            telio_add_event_listener(dev, 2, recording_cb(&added_events)),
+            TELIO_RES_OK
+        );
+        assert_eq!(telio_remove_event_listener(dev, 2), TELIO_RES_OK);
+        assert_eq!(telio_remove_event_listener(dev, 2), TELIO_RES_ERROR);
+        assert_eq!(
+            telio_remove_event_listener(dev, DEFAULT_EVENT_LISTENER_ID),
+            TELIO_RES_ERROR
+        );
+
+        telio_destroy(telio_dev);
+
+        let shutting_down = vec![String::from(r#"{"type":"shuttingdown","body":{}}"#)];
+        assert_eq!(*default_events.lock().unwrap(), shutting_down);
+        assert_eq!(*added_events.lock().unwrap(), shutting_down);
+        Ok(())
+    }
+
+    #[test]
+    fn test_event_batching() -> anyhow::Result<()> {
+        let events = Mutex::new(Vec::<String>::new());
+        let telio_dev = Box::into_raw(Box::new(recording_telio(&events)?));
+        let dev = unsafe { &*telio_dev };
+        let shutting_down = || Box::new(Event::new::<ShuttingDown>().set(ShuttingDown {}));
+
+        assert_eq!(
+            telio_enable_event_batching(dev, 0, 1000),
+            TELIO_RES_BAD_CONFIG
+        );
+        assert_eq!(telio_enable_event_batching(dev, 2, 0), TELIO_RES_BAD_CONFIG);
+        assert_eq!(telio_enable_event_batching(dev, 2, 3_600_000), TELIO_RES_OK);
+        dispatch_event(&dev.events, &dev.event_batcher, shutting_down());
+        assert!(events.lock().unwrap().is_empty());
+        dispatch_event(&dev.events, &dev.event_batcher, shutting_down());
+        dispatch_event(&dev.events, &dev.event_batcher, shutting_down());
+        assert_eq!(telio_disable_event_batching(dev), TELIO_RES_OK);
+        // Last batch is passed by the event task
+        let start = std::time::Instant::now();
+        while events.lock().unwrap().len() < 2 && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        dispatch_event(&dev.events, &dev.event_batcher, shutting_down());
+
+        let event = r#"{"type":"shuttingdown","body":{}}"#;
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                format!("[{event},{event}]"),
+                format!("[{event}]"),
+                event.to_owned()
+            ]
+        );
+        telio_destroy(telio_dev);
+        Ok(())
+    }
+
     #[test]
     fn test_bytes_to_zero_terminated_unmanaged_bytes() {
         let inputs: [(&[u8], &[u8]); 3] = [(&[], &[0]), (&[0], &[0, 0]), (&[1, 2], &[1, 2, 0])];
@@ -1260,6 +5270,114 @@ mod tests {
         }
     }
 
+    fn log_line(message: &str) -> LogLine {
+        LogLine {
+            message: message.to_owned(),
+            level: tracing::Level::INFO,
+            fields: None,
+        }
+    }
+
+    #[test]
+    fn test_log_repeats_are_reported_with_backoff() {
+        let mut status = LogStatus::new(log_line(""));
+        let mut emitted = Vec::new();
+        for _ in 0..1 + 10 + 100 + 200 + 400 + 5 {
+            emitted.extend(
+                status
+                    .filter(log_line("loop"))
+                    .into_iter()
+                    .map(|l| l.message),
+            );
+        }
+        emitted.extend(
+            status
+                .filter(log_line("other"))
+                .into_iter()
+                .map(|l| l.message),
+        );
+
+        let mut expected = vec!["loop".to_owned(); 11];
+        expected.extend([
+            "[repeated 100 times!] loop".to_owned(),
+            "[repeated 200 times!] loop".to_owned(),
+            "[repeated 400 times!] loop".to_owned(),
+            "[repeated 5 more times!] loop".to_owned(),
+            "other".to_owned(),
+        ]);
+        assert_eq!(emitted, expected);
+    }
+
+    #[test]
+    fn test_log_repeat_report_interval_is_capped() {
+        let mut status = LogStatus::new(log_line("loop"));
+        let reports: Vec<_> = (0..200_000)
+            .flat_map(|_| status.filter(log_line("loop")))
+            .skip(10)
+            .collect();
+        assert_eq!(
+            reports.last().unwrap().message,
+            "[repeated 10000 times!] loop"
+        );
+        assert_eq!(status.report_interval, LOG_MAX_REPEAT_REPORT_INTERVAL);
+    }
+
+    #[test]
+    fn test_log_repeat_summary_keeps_level_and_fields() {
+        let error = LogLine {
+            message: "failed".to_owned(),
+            level: tracing::Level::ERROR,
+            fields: Some(r#"{"peer":"a"}"#.to_owned()),
+        };
+        let trace = LogLine {
+            message: "tick".to_owned(),
+            level: tracing::Level::TRACE,
+            fields: Some(r#"{"peer":"b"}"#.to_owned()),
+        };
+
+        let mut status = LogStatus::new(log_line(""));
+        for _ in 0..LOG_REPEATS_BEFORE_SUPPRESSION + 2 {
+            status.filter(error.clone());
+        }
+        assert_eq!(
+            status.filter(trace.clone()),
+            vec![
+                error.with_message("[repeated 1 more times!] failed".to_owned()),
+                trace
+            ]
+        );
+    }
+
+    #[test]
+    fn test_validate_keys() {
+        let valid = CString::new(SecretKey::gen().public().to_string()).unwrap();
+        assert_eq!(telio_validate_public_key(valid.as_ptr()), TELIO_RES_OK);
+        assert_eq!(telio_validate_secret_key(valid.as_ptr()), TELIO_RES_OK);
+
+        for invalid in [
+            "",
+            "not a key",
+            // 31 bytes
+            "AQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQEBAQ==",
+            // Hex encoded
+            "0101010101010101010101010101010101010101010101010101010101010101",
+        ] {
+            let invalid = CString::new(invalid).unwrap();
+            assert_eq!(
+                telio_validate_public_key(invalid.as_ptr()),
+                TELIO_RES_INVALID_STRING
+            );
+            assert_eq!(
+                telio_validate_secret_key(invalid.as_ptr()),
+                TELIO_RES_INVALID_STRING
+            );
+        }
+        assert_eq!(
+            telio_validate_public_key(ptr::null()),
+            TELIO_RES_INVALID_STRING
+        );
+    }
+
     #[test]
     fn test_logging_when_telio_dev_empty() -> anyhow::Result<()> {
         let telio_dev: *mut *mut telio = ptr::null_mut();
@@ -1274,8 +5392,17 @@ mod tests {
         let event_cb = Box::new(|_event| {});
         let id = rand::thread_rng().gen::<usize>();
         let telio_dev: *mut *mut telio = Box::into_raw(Box::new(Box::into_raw(Box::new(telio {
-            inner: Mutex::new(Device::new(features, event_cb, None)?),
+            inner: Mutex::new(Device::new(features, event_cb, None, None)?),
             id,
+            events: Arc::new(Mutex::new(vec![(
+                DEFAULT_EVENT_LISTENER_ID,
+                telio_event_cb {
+                    ctx: ptr::null_mut(),
+                    cb: test_telio_event_fn,
+                },
+            )])),
+            event_batcher: Default::default(),
+            starting: Default::default(),
         }))));
         let res = get_instance_id_from_ptr(telio_dev);
         assert_eq!(res, Some(id));
@@ -7,6 +7,11 @@ use std::ffi::c_void;
 
 use crate::device::{AdapterType, Error as DevError, Result as DevResult};
 
+/// Version of the C API described by this header, incremented whenever a function or a type of
+/// the API changes. Wrappers should check that `telio_get_api_version` returns at least this
+/// version before calling anything else.
+pub const TELIO_MIN_API_VERSION: u32 = 1;
+
 #[derive(Clone, Copy, Debug)]
 #[cfg_attr(test, derive(PartialEq, Eq))]
 #[allow(non_camel_case_types)]
@@ -27,12 +32,17 @@ pub enum telio_result {
     TELIO_RES_INVALID_STRING = 5,
     /// The device is already started.
     TELIO_RES_ALREADY_STARTED = 6,
+    /// Operation is not supported on this platform.
+    TELIO_RES_UNSUPPORTED_PLATFORM = 7,
 }
 impl std::error::Error for telio_result {}
 impl std::fmt::Display for telio_result {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
             TELIO_RES_ALREADY_STARTED => write!(f, "Device is already started"),
+            TELIO_RES_UNSUPPORTED_PLATFORM => {
+                write!(f, "Operation is not supported on this platform")
+            }
             TELIO_RES_INVALID_KEY => write!(f, "Cannot parse key as base64 string"),
             TELIO_RES_BAD_CONFIG => write!(f, "Cannot Parse Configuration "),
             TELIO_RES_LOCK_ERROR => write!(f, "Cannot lock a mutex"),
@@ -75,6 +85,17 @@ pub enum telio_log_level {
     TELIO_LOG_TRACE = 6,
 }
 
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+/// Config formats described by `telio_get_config_schema`.
+pub enum telio_schema_type {
+    /// Features JSON passed to `telio_new`.
+    TELIO_SCHEMA_FEATURES,
+    /// Meshnet config JSON passed to `telio_set_meshnet`.
+    TELIO_SCHEMA_MESHNET,
+}
+
 #[allow(non_camel_case_types)]
 pub type telio_event_fn = unsafe extern "C" fn(*mut c_void, *const c_char);
 
@@ -105,6 +126,124 @@ pub struct telio_logger_cb {
     pub cb: telio_logger_fn,
 }
 
+#[allow(non_camel_case_types)]
+pub type telio_logger_v2_fn =
+    unsafe extern "C" fn(*mut c_void, telio_log_level, *const c_char, *const c_char);
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+/// Logging callback receiving structured fields of the log message
+pub struct telio_logger_v2_cb {
+    /// Context to pass to callback.
+    /// User must ensure safe access of this var from multithreaded context.
+    pub ctx: *mut c_void,
+    /// Function to be called with the level, the message and a JSON object of fields of the
+    /// message and the spans it was logged in
+    pub cb: telio_logger_v2_fn,
+}
+
+#[allow(non_camel_case_types)]
+pub type telio_custom_message_fn =
+    unsafe extern "C" fn(*mut c_void, *const c_char, *const u8, usize);
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+/// Custom message callback, receives base64 encoded sender public key and message payload
+pub struct telio_custom_message_cb {
+    /// Context to pass to callback.
+    /// User must ensure safe access of this var from multithreaded context.
+    pub ctx: *mut c_void,
+    /// Function to be called
+    pub cb: telio_custom_message_fn,
+}
+
+#[allow(non_camel_case_types)]
+pub type telio_link_state_provider_fn = unsafe extern "C" fn(*mut c_void) -> u64;
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+/// Link state provider callback, returns an identifier of the current network interfaces state
+pub struct telio_link_state_provider_cb {
+    /// Context to pass to callback.
+    /// User must ensure safe access of this var from multithreaded context.
+    pub ctx: *mut c_void,
+    /// Function to be called
+    pub cb: telio_link_state_provider_fn,
+}
+
+#[cfg(feature = "packet_stats")]
+#[allow(non_camel_case_types)]
+pub type telio_packet_stats_fn = unsafe extern "C" fn(*mut c_void, u8, *const u8, u32);
+
+#[cfg(feature = "packet_stats")]
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+/// Packet statistics callback, receives direction (0 inbound, 1 outbound), 32 byte public key
+/// of the peer and size of the packet
+pub struct telio_packet_stats_cb {
+    /// Context to pass to callback.
+    /// User must ensure safe access of this var from multithreaded context.
+    pub ctx: *mut c_void,
+    /// Function to be called
+    pub cb: telio_packet_stats_fn,
+}
+
+#[allow(non_camel_case_types)]
+pub type telio_adapter_send_packet_fn =
+    unsafe extern "C" fn(*mut c_void, *const u8, *const u8, usize) -> telio_result;
+
+#[allow(non_camel_case_types)]
+pub type telio_adapter_recv_packet_fn =
+    unsafe extern "C" fn(*mut c_void, *mut u8, usize, u32, *mut u8) -> i32;
+
+#[allow(non_camel_case_types)]
+pub type telio_adapter_set_peer_fn =
+    unsafe extern "C" fn(*mut c_void, *const u8, *const c_char, bool) -> telio_result;
+
+#[allow(non_camel_case_types)]
+pub type telio_adapter_stop_fn = unsafe extern "C" fn(*mut c_void);
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+/// Transport of encrypted WireGuard packets implemented by the application
+pub struct telio_custom_adapter {
+    /// Handle of the adapter passed to its functions.
+    pub handle: *mut c_void,
+    /// Send the encrypted packet `(data, len)` to the peer with the 32 byte public key,
+    /// returns `TELIO_RES_OK` on success.
+    pub send_packet: telio_adapter_send_packet_fn,
+    /// Wait up to `timeout_ms` for an encrypted packet, copy it to `(buf, capacity)` and the
+    /// 32 byte public key of its sender to `peer`. Returns the length of the packet, 0 on timeout
+    /// and a negative value on failure.
+    pub recv_packet: telio_adapter_recv_packet_fn,
+    /// Add or update the peer with the 32 byte public key and its endpoint, e.g. `1.2.3.4:51820`,
+    /// NULL if unknown, or remove the peer if `remove` is set. Returns `TELIO_RES_OK` on success.
+    pub set_peer: telio_adapter_set_peer_fn,
+    /// Stop the adapter, the handle is not used afterwards. Called exactly once.
+    pub stop: telio_adapter_stop_fn,
+}
+
+#[allow(non_camel_case_types)]
+pub type telio_adapter_factory_fn =
+    unsafe extern "C" fn(*mut c_void, *mut telio_custom_adapter) -> telio_result;
+
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+/// Factory of custom adapters, fills in the adapter and returns `TELIO_RES_OK` on success
+pub struct telio_adapter_factory_cb {
+    /// Context to pass to callback.
+    /// User must ensure safe access of this var from multithreaded context.
+    pub ctx: *mut c_void,
+    /// Function to be called
+    pub cb: telio_adapter_factory_fn,
+}
+
 #[cfg(target_os = "android")]
 #[allow(non_camel_case_types)]
 pub type telio_protect_fn = unsafe extern "C" fn(*mut c_void, i32);
@@ -122,13 +261,38 @@ pub struct telio_protect_cb {
     pub cb: telio_protect_fn,
 }
 
+#[cfg(target_os = "android")]
+#[allow(non_camel_case_types)]
+pub type telio_bind_network_fn = unsafe extern "C" fn(*mut c_void, i32, bool, bool) -> bool;
+
+#[cfg(target_os = "android")]
+#[allow(non_camel_case_types)]
+#[repr(C)]
+#[derive(Copy, Clone)]
+/// Android callback binding the socket to a network, e.g. with `Network.bindSocket()`.
+/// Receives the socket, `require_unmetered` and `require_wifi`, returns false if no network
+/// satisfies the constraints.
+pub struct telio_bind_network_cb {
+    /// Context to pass to callback.
+    /// User must ensure safe access of this var from multithreaded context.
+    pub ctx: *mut c_void,
+    /// Function to be called
+    pub cb: telio_bind_network_fn,
+}
+
 #[no_mangle]
 pub extern "C" fn __telio_force_export(
     _: telio_result,
     _: telio_adapter_type,
+    _: telio_schema_type,
     _: telio_event_cb,
     _: telio_logger_cb,
+    _: telio_logger_v2_cb,
+    _: telio_custom_message_cb,
+    _: telio_link_state_provider_cb,
+    _: telio_adapter_factory_cb,
     #[cfg(target_os = "android")] _: telio_protect_cb,
+    #[cfg(target_os = "android")] _: telio_bind_network_cb,
 ) {
 }
 // Map library types to C Api types
@@ -175,6 +339,16 @@ impl From<DevError> for telio_result {
         match _err {
             DevError::AlreadyStarted => TELIO_RES_ALREADY_STARTED,
             DevError::BadPublicKey => TELIO_RES_INVALID_KEY,
+            DevError::StaleConfig => TELIO_RES_BAD_CONFIG,
+            DevError::TooManyPeers { .. } => TELIO_RES_BAD_CONFIG,
+            DevError::DuplicateIp { .. } => TELIO_RES_BAD_CONFIG,
+            DevError::EmptyFailoverList => TELIO_RES_BAD_CONFIG,
+            DevError::InvalidRetryPolicy => TELIO_RES_BAD_CONFIG,
+            DevError::InvalidDnsTtlOverride => TELIO_RES_BAD_CONFIG,
+            DevError::InvalidDnsOverHttpsUrl(_) => TELIO_RES_BAD_CONFIG,
+            DevError::TrafficShapingUnsupported => TELIO_RES_BAD_CONFIG,
+            DevError::NetworkBindingUnavailable => TELIO_RES_BAD_CONFIG,
+            DevError::InvalidNickname => TELIO_RES_BAD_CONFIG,
             _ => TELIO_RES_ERROR,
         }
     }
@@ -186,6 +360,16 @@ impl From<&DevError> for telio_result {
         match _err {
             DevError::AlreadyStarted => TELIO_RES_ALREADY_STARTED,
             DevError::BadPublicKey => TELIO_RES_INVALID_KEY,
+            DevError::StaleConfig => TELIO_RES_BAD_CONFIG,
+            DevError::TooManyPeers { .. } => TELIO_RES_BAD_CONFIG,
+            DevError::DuplicateIp { .. } => TELIO_RES_BAD_CONFIG,
+            DevError::EmptyFailoverList => TELIO_RES_BAD_CONFIG,
+            DevError::InvalidRetryPolicy => TELIO_RES_BAD_CONFIG,
+            DevError::InvalidDnsTtlOverride => TELIO_RES_BAD_CONFIG,
+            DevError::InvalidDnsOverHttpsUrl(_) => TELIO_RES_BAD_CONFIG,
+            DevError::TrafficShapingUnsupported => TELIO_RES_BAD_CONFIG,
+            DevError::NetworkBindingUnavailable => TELIO_RES_BAD_CONFIG,
+            DevError::InvalidNickname => TELIO_RES_BAD_CONFIG,
             _ => TELIO_RES_ERROR,
         }
     }
@@ -246,8 +430,32 @@ unsafe impl Send for telio_event_cb {}
 
 unsafe impl Sync for telio_logger_cb {}
 unsafe impl Send for telio_logger_cb {}
+unsafe impl Sync for telio_logger_v2_cb {}
+unsafe impl Send for telio_logger_v2_cb {}
+
+unsafe impl Sync for telio_custom_message_cb {}
+unsafe impl Send for telio_custom_message_cb {}
+
+unsafe impl Sync for telio_link_state_provider_cb {}
+unsafe impl Send for telio_link_state_provider_cb {}
+
+#[cfg(feature = "packet_stats")]
+unsafe impl Sync for telio_packet_stats_cb {}
+#[cfg(feature = "packet_stats")]
+unsafe impl Send for telio_packet_stats_cb {}
+
+unsafe impl Sync for telio_custom_adapter {}
+unsafe impl Send for telio_custom_adapter {}
+
+unsafe impl Sync for telio_adapter_factory_cb {}
+unsafe impl Send for telio_adapter_factory_cb {}
 
 #[cfg(target_os = "android")]
 unsafe impl Sync for telio_protect_cb {}
 #[cfg(target_os = "android")]
 unsafe impl Send for telio_protect_cb {}
+
+#[cfg(target_os = "android")]
+unsafe impl Sync for telio_bind_network_cb {}
+#[cfg(target_os = "android")]
+unsafe impl Send for telio_bind_network_cb {}
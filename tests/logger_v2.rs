@@ -0,0 +1,45 @@
+use std::{
+    ffi::CStr,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use libc::{c_char, c_void};
+use telio::TelioTracingSubscriber;
+
+#[test]
+fn test_logger_v2() {
+    let call_count = AtomicUsize::new(0);
+    unsafe extern "C" fn test_telio_logger_v2_fn(
+        ctx: *mut c_void,
+        level: telio::ffi_types::telio_log_level,
+        message: *const c_char,
+        fields: *const c_char,
+    ) {
+        assert_eq!(telio::ffi_types::telio_log_level::TELIO_LOG_INFO, level);
+        assert!(CStr::from_ptr(message)
+            .to_str()
+            .unwrap()
+            .ends_with("test message"));
+        let fields: serde_json::Value =
+            serde_json::from_str(CStr::from_ptr(fields).to_str().unwrap()).unwrap();
+        assert_eq!(
+            serde_json::json!({"operation": "connect", "peer": "inner", "attempt": 2}),
+            fields
+        );
+        let call_count: &AtomicUsize = &*(ctx as *const AtomicUsize);
+        call_count.fetch_add(1, Ordering::Relaxed);
+    }
+    let logger = telio::ffi_types::telio_logger_v2_cb {
+        ctx: &call_count as *const AtomicUsize as *mut c_void,
+        cb: test_telio_logger_v2_fn,
+    };
+    let tracing_subscriber = TelioTracingSubscriber::new_v2(logger, tracing::Level::INFO);
+    tracing::subscriber::set_global_default(tracing_subscriber).unwrap();
+
+    let outer = tracing::info_span!("outer", operation = "connect", peer = "outer");
+    let _outer = outer.enter();
+    let inner = tracing::info_span!("inner", peer = "inner");
+    let _inner = inner.enter();
+    tracing::info!(attempt = 2, "test message");
+    assert_eq!(1, call_count.load(Ordering::Relaxed));
+}